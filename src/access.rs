@@ -0,0 +1,184 @@
+use std::{
+    collections::BTreeSet,
+    io,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, warn};
+
+use crate::config::Config;
+
+/// How letitgo gets at the directories it scans and excludes in.
+///
+/// The CLI reads them directly ([`DirectAccess`]).  An app embedding the
+/// engine inside the macOS App Sandbox can only read what the user granted
+/// it: it implements this trait with security-scoped bookmarks, starting
+/// access to a granted root in [`begin`](Self::begin) and stopping it when
+/// the returned [`AccessGrant`] is dropped.  Everything below a granted root
+/// is then read and written with plain file system calls, as the CLI does.
+pub trait FileSystemAccess: Send + Sync {
+    /// Start accessing `root` and everything below it, until the grant is
+    /// dropped.  A root the embedding app holds no grant for is an error,
+    /// typically `PermissionDenied`.
+    fn begin(&self, root: &Path) -> io::Result<AccessGrant>;
+}
+
+/// Access to a root started by [`FileSystemAccess::begin`], ended on drop.
+#[must_use = "access ends when the grant is dropped"]
+pub struct AccessGrant {
+    end: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl AccessGrant {
+    /// A grant with nothing to end, for access that needs no starting.
+    pub fn unscoped() -> Self {
+        AccessGrant { end: None }
+    }
+
+    /// A grant that runs `end` when dropped, e.g. to stop accessing a
+    /// security-scoped resource.
+    pub fn ending_with(end: impl FnOnce() + Send + 'static) -> Self {
+        AccessGrant {
+            end: Some(Box::new(end)),
+        }
+    }
+}
+
+impl Drop for AccessGrant {
+    fn drop(&mut self) {
+        if let Some(end) = self.end.take() {
+            end();
+        }
+    }
+}
+
+/// Plain file system access, as a process outside any sandbox has it.
+pub struct DirectAccess;
+
+impl FileSystemAccess for DirectAccess {
+    fn begin(&self, _root: &Path) -> io::Result<AccessGrant> {
+        Ok(AccessGrant::unscoped())
+    }
+}
+
+/// The grants an operation holds; access ends when it is dropped.
+#[must_use = "access ends when the scope is dropped"]
+#[derive(Default)]
+pub struct Scope {
+    _grants: Vec<AccessGrant>,
+    denied: Vec<PathBuf>,
+}
+
+impl Scope {
+    /// Whether `path` lies under a root the scope was denied access to:
+    /// what it finds there says nothing about what is on disk.
+    pub fn denies(&self, path: &Path) -> bool {
+        self.denied.iter().any(|root| path.starts_with(root))
+    }
+
+    /// Start access to `root`, remembering it as denied on failure.
+    fn begin(&mut self, access: &dyn FileSystemAccess, root: &Path) -> io::Result<()> {
+        match access.begin(root) {
+            Ok(grant) => {
+                self._grants.push(grant);
+                Ok(())
+            }
+            Err(e) => {
+                self.denied.push(root.to_path_buf());
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Start access to everything a run with `config` reads and excludes in:
+/// `search_paths` and the locations of system artifacts and presets (for a
+/// glob, the directory it matches in).
+///
+/// A root that cannot be accessed is logged and left to fail as any
+/// unreadable directory does: discovery reports a search path it cannot
+/// read as permission denied, and an artifact location yields nothing.
+pub fn begin_run(
+    access: &dyn FileSystemAccess,
+    config: &Config,
+    search_paths: &[PathBuf],
+) -> Scope {
+    let mut scope = Scope::default();
+    for root in search_paths {
+        if let Err(e) = scope.begin(access, root) {
+            warn!("No access to search path {}: {e}", root.display());
+        }
+    }
+    for root in artifact_roots(config, search_paths) {
+        if let Err(e) = scope.begin(access, &root) {
+            debug!("No access to {}: {e}", root.display());
+        }
+    }
+    scope
+}
+
+/// Start access to the roots holding `paths`, e.g. the cached paths a
+/// command checks or un-excludes: for each path, the search path or
+/// artifact location (see [`begin_run`]) it is in, else its parent.
+///
+/// A root that cannot be accessed is logged and remembered: ask the scope
+/// whether it [`denies`](Scope::denies) a path before taking a path that
+/// seems missing for gone.
+pub fn begin_paths<'a>(
+    access: &dyn FileSystemAccess,
+    config: &Config,
+    paths: impl IntoIterator<Item = &'a Path>,
+) -> Scope {
+    let search_paths = config.resolved_search_paths();
+    let known: Vec<PathBuf> = search_paths
+        .iter()
+        .cloned()
+        .chain(artifact_roots(config, &search_paths))
+        .collect();
+    let roots: BTreeSet<PathBuf> = paths
+        .into_iter()
+        .map(|path| {
+            known
+                .iter()
+                .find(|root| path.starts_with(root))
+                .cloned()
+                .unwrap_or_else(|| path.parent().unwrap_or(path).to_path_buf())
+        })
+        .collect();
+
+    let mut scope = Scope::default();
+    // Sorted, so a root comes before the roots nested in it
+    let mut begun: Vec<&Path> = Vec::new();
+    for root in &roots {
+        if begun.iter().any(|outer| root.starts_with(outer)) {
+            continue;
+        }
+        begun.push(root);
+        if let Err(e) = scope.begin(access, root) {
+            warn!("No access to {}: {e}", root.display());
+        }
+    }
+    scope
+}
+
+/// The locations of system artifacts and presets `config` excludes (for a
+/// glob, the directory it matches in), but for those in `search_paths`.
+fn artifact_roots(config: &Config, search_paths: &[PathBuf]) -> BTreeSet<PathBuf> {
+    let mut locations = Vec::new();
+    if config.exclude_system_artifacts {
+        locations.extend(config.rooted_entries(&config.system_artifacts));
+    }
+    for preset in &config.presets {
+        locations.extend(config.rooted_entries(preset.locations()));
+    }
+    locations
+        .into_iter()
+        .map(PathBuf::from)
+        .map(|path| match path.file_name() {
+            Some(name) if name.to_string_lossy().contains(['*', '?', '[', '{']) => {
+                path.parent().map_or(path.clone(), Path::to_path_buf)
+            }
+            _ => path,
+        })
+        .filter(|root| !search_paths.iter().any(|sp| root.starts_with(sp)))
+        .collect()
+}
@@ -0,0 +1,140 @@
+use globset::{Glob, GlobMatcher};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, warn};
+
+use crate::config::expand_tilde;
+
+/// Default `system_artifacts` entries: large, reproducible VM and container
+/// images that live outside any Git repository.
+pub const DEFAULT_SYSTEM_ARTIFACTS: &[&str] = &[
+    "~/Library/Containers/com.docker.docker/Data/vms",
+    "~/.orbstack",
+    "~/Parallels/*.pvm",
+];
+
+/// Built-in sets of global package-manager caches.
+///
+/// Everything in these directories can be re-downloaded, so excluding them is
+/// always safe; the worst case is a slower first build after a restore.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    Cargo,
+    Npm,
+    Pip,
+    Gradle,
+    Homebrew,
+}
+
+impl Preset {
+    /// Cache locations covered by this preset (supports `~`).
+    pub fn locations(self) -> &'static [&'static str] {
+        match self {
+            Preset::Cargo => &["~/.cargo/registry", "~/.cargo/git"],
+            Preset::Npm => &["~/.npm"],
+            Preset::Pip => &["~/Library/Caches/pip"],
+            Preset::Gradle => &["~/.gradle/caches", "~/.gradle/wrapper/dists"],
+            Preset::Homebrew => &["~/Library/Caches/Homebrew"],
+        }
+    }
+}
+
+impl std::fmt::Display for Preset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Preset::Cargo => "cargo",
+            Preset::Npm => "npm",
+            Preset::Pip => "pip",
+            Preset::Gradle => "gradle",
+            Preset::Homebrew => "homebrew",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Resolve configured artifact locations to the paths that currently exist.
+///
+/// Each entry supports a leading `~` and glob characters in its **last**
+/// component only (e.g. `~/Parallels/*.pvm`), which keeps resolution to a
+/// single directory listing per entry.  Missing paths are skipped silently.
+pub fn resolve_artifact_paths(entries: &[String]) -> HashSet<PathBuf> {
+    let mut found = HashSet::new();
+    for entry in entries {
+        let path = expand_tilde(entry);
+        let Some(name) = path.file_name().map(|n| n.to_string_lossy().into_owned()) else {
+            continue;
+        };
+
+        if !name.contains(['*', '?', '[', '{']) {
+            if path.exists() {
+                debug!("artifact: {}", path.display());
+                found.insert(path);
+            }
+            continue;
+        }
+
+        let matcher = match Glob::new(&name) {
+            Ok(g) => g.compile_matcher(),
+            Err(e) => {
+                warn!("Invalid artifact pattern `{}`: {}", entry, e);
+                continue;
+            }
+        };
+        let parent = path.parent().unwrap_or(Path::new("/"));
+        found.extend(matching_children(parent, &matcher));
+    }
+    found
+}
+
+/// List the children of `dir` whose file name matches `matcher`.
+fn matching_children(dir: &Path, matcher: &GlobMatcher) -> Vec<PathBuf> {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    read_dir
+        .flatten()
+        .filter(|e| matcher.is_match(e.file_name()))
+        .map(|e| e.path())
+        .inspect(|p| debug!("artifact: {}", p.display()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_resolve_artifact_paths_expands_last_component_glob() {
+        let tmp = tempdir().unwrap();
+        let vms = tmp.path().join("Parallels");
+        fs::create_dir_all(vms.join("Windows 11.pvm")).unwrap();
+        fs::create_dir_all(vms.join("Ubuntu.pvm")).unwrap();
+        fs::create_dir_all(vms.join("Shared")).unwrap();
+        fs::create_dir_all(tmp.path().join("docker/vms")).unwrap();
+
+        let entries = vec![
+            format!("{}/*.pvm", vms.display()),
+            format!("{}/docker/vms", tmp.path().display()),
+            format!("{}/orbstack", tmp.path().display()),
+        ];
+        let found = resolve_artifact_paths(&entries);
+
+        let expected: HashSet<PathBuf> = [
+            vms.join("Windows 11.pvm"),
+            vms.join("Ubuntu.pvm"),
+            tmp.path().join("docker/vms"),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(found, expected);
+    }
+}
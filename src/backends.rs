@@ -0,0 +1,352 @@
+use anyhow::{Context, Result};
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tempfile::NamedTempFile;
+use tracing::debug;
+
+use crate::{config::ExcludesFormat, tmutil::ExclusionManager};
+
+/// Name of the tag file defined by the Cache Directory Tagging Specification.
+pub const CACHEDIR_TAG: &str = "CACHEDIR.TAG";
+
+/// First line every valid tag file starts with.
+const CACHEDIR_SIGNATURE: &str = "Signature: 8a477f597d28d172789f06886806bc55";
+
+/// The full tag letitgo writes; the second line marks it as ours.
+const CACHEDIR_TAG_CONTENT: &str = "Signature: 8a477f597d28d172789f06886806bc55
+# This file is a cache directory tag created by letitgo.
+# For information about cache directory tags, see:
+#\thttps://bford.info/cachedir/
+";
+
+/// Backend for backup tools that skip tagged cache directories (borg,
+/// restic and GNU tar with `--exclude-caches`): a directory is excluded by
+/// writing a `CACHEDIR.TAG` into it.
+///
+/// Only directories can be tagged; excluded files stay in backups.  Tags
+/// that letitgo did not write (Cargo tags `target/` itself) are never
+/// touched, and count as exclusions.
+#[derive(Debug, Default)]
+pub struct CachedirTagManager;
+
+impl ExclusionManager for CachedirTagManager {
+    fn add_exclusions(&self, paths: &[&Path], _fixed_path: bool) -> Result<()> {
+        for path in paths {
+            if !path.is_dir() {
+                debug!("Not a directory, cannot tag: {}", path.display());
+                continue;
+            }
+            let tag = path.join(CACHEDIR_TAG);
+            if is_tag(&tag) {
+                continue;
+            }
+            fs::write(&tag, CACHEDIR_TAG_CONTENT)
+                .with_context(|| format!("writing {}", tag.display()))?;
+        }
+        Ok(())
+    }
+
+    fn remove_exclusions(&self, paths: &[&Path], _fixed_path: bool) -> Result<()> {
+        for path in paths {
+            let tag = path.join(CACHEDIR_TAG);
+            let ours = fs::read_to_string(&tag).is_ok_and(|text| text == CACHEDIR_TAG_CONTENT);
+            if ours {
+                fs::remove_file(&tag).with_context(|| format!("removing {}", tag.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_excluded(&self, path: &Path) -> Result<bool> {
+        Ok(is_tag(&path.join(CACHEDIR_TAG)))
+    }
+}
+
+/// Whether `tag` is a tag file carrying the specification's signature.
+fn is_tag(tag: &Path) -> bool {
+    fs::read(tag).is_ok_and(|bytes| bytes.starts_with(CACHEDIR_SIGNATURE.as_bytes()))
+}
+
+/// The header line of an excludes file in `format`: a `#` comment, as
+/// restic, borg and rsync all read it, naming the format so that the lines
+/// are read back in it even after `format` is changed.
+fn excludes_file_header(format: ExcludesFormat) -> String {
+    let name = match format {
+        ExcludesFormat::Restic => "restic",
+        ExcludesFormat::Borg => "borg",
+        ExcludesFormat::Rsync => "rsync",
+    };
+    format!("# Maintained by letitgo ({name} format); edits are overwritten on the next run.")
+}
+
+/// The format named by the header line `line`, if it is one.
+fn header_format(line: &str) -> Option<ExcludesFormat> {
+    [
+        ExcludesFormat::Restic,
+        ExcludesFormat::Borg,
+        ExcludesFormat::Rsync,
+    ]
+    .into_iter()
+    .find(|&format| excludes_file_header(format) == line)
+}
+
+/// Backend for backup tools that read a list of paths to skip
+/// (`restic --exclude-file`, `borg --exclude-from`, `rsync --exclude-from`):
+/// the excluded paths are kept in `path`, sorted, one per line in `format`.
+///
+/// Each run renders the whole file again and replaces it atomically when
+/// the text differs, so a change of `format` takes effect on the next run.
+#[derive(Debug)]
+pub struct ExcludesFileManager {
+    path: PathBuf,
+    format: ExcludesFormat,
+    /// Held across each read-modify-write: a run adds and removes paths
+    /// from two threads at once.
+    update_lock: Mutex<()>,
+}
+
+impl ExcludesFileManager {
+    pub fn new(path: impl Into<PathBuf>, format: ExcludesFormat) -> Self {
+        ExcludesFileManager {
+            path: path.into(),
+            format,
+            update_lock: Mutex::new(()),
+        }
+    }
+
+    /// The paths currently listed in the file (none if it does not exist).
+    pub fn entries(&self) -> Result<BTreeSet<PathBuf>> {
+        Ok(self.parse(&self.read()?))
+    }
+
+    /// The paths listed in `text`, read in the format its header names:
+    /// `format` unless it was changed since the file was written.
+    fn parse(&self, text: &str) -> BTreeSet<PathBuf> {
+        let format = text
+            .lines()
+            .next()
+            .and_then(header_format)
+            .unwrap_or(self.format);
+        text.lines()
+            .filter_map(|line| parse_line(format, line))
+            .collect()
+    }
+
+    /// The file's text, empty if it does not exist.
+    fn read(&self) -> Result<String> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => Ok(text),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(e).with_context(|| format!("reading {}", self.path.display())),
+        }
+    }
+
+    /// Apply `edit` to the listed paths and write the file back if its text
+    /// changes.
+    fn update(&self, edit: impl FnOnce(&mut BTreeSet<PathBuf>)) -> Result<()> {
+        let _guard = self
+            .update_lock
+            .lock()
+            .expect("excludes file lock poisoned");
+        let before = self.read()?;
+        let mut entries = self.parse(&before);
+        edit(&mut entries);
+        let mut text = format!("{}\n", excludes_file_header(self.format));
+        for entry in &entries {
+            text.push_str(&render_line(self.format, entry));
+            text.push('\n');
+        }
+        if text == before {
+            return Ok(());
+        }
+
+        let parent = self.path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(parent)
+            .with_context(|| format!("creating excludes file dir: {}", parent.display()))?;
+        let mut tmp = NamedTempFile::new_in(parent)
+            .with_context(|| format!("creating temp file in {}", parent.display()))?;
+        tmp.write_all(text.as_bytes())
+            .with_context(|| format!("writing excludes temp file in {}", parent.display()))?;
+        tmp.persist(&self.path)
+            .with_context(|| format!("persisting excludes file to {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+impl ExclusionManager for ExcludesFileManager {
+    fn add_exclusions(&self, paths: &[&Path], _fixed_path: bool) -> Result<()> {
+        self.update(|entries| entries.extend(paths.iter().map(|p| p.to_path_buf())))
+    }
+
+    fn remove_exclusions(&self, paths: &[&Path], _fixed_path: bool) -> Result<()> {
+        self.update(|entries| {
+            for path in paths {
+                entries.remove(*path);
+            }
+        })
+    }
+
+    fn is_excluded(&self, path: &Path) -> Result<bool> {
+        Ok(self.entries()?.contains(path))
+    }
+
+    fn are_excluded(&self, paths: &[&Path]) -> Vec<bool> {
+        let entries = self.entries().unwrap_or_default();
+        paths.iter().map(|p| entries.contains(*p)).collect()
+    }
+}
+
+/// `path` as one line of an excludes file in `format`.  restic expands
+/// environment variables in the file, so its `$` is written as `$$`.
+fn render_line(format: ExcludesFormat, path: &Path) -> String {
+    let path = path.to_string_lossy();
+    match format {
+        ExcludesFormat::Restic => escape_glob(&path).replace('$', "$$"),
+        ExcludesFormat::Borg => format!("pp:{path}"),
+        ExcludesFormat::Rsync => format!("- {}", escape_glob(&path)),
+    }
+}
+
+/// The path on a line written by [`render_line`] in `format`; `None` for
+/// blank lines, comments and lines of no path.
+fn parse_line(format: ExcludesFormat, line: &str) -> Option<PathBuf> {
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let escaped = match format {
+        ExcludesFormat::Borg => return line.strip_prefix("pp:").map(PathBuf::from),
+        ExcludesFormat::Rsync => line.strip_prefix("- ")?,
+        ExcludesFormat::Restic => line,
+    };
+    let mut path = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => path.extend(chars.next()),
+            '$' if format == ExcludesFormat::Restic => {
+                chars.next_if_eq(&'$');
+                path.push('$');
+            }
+            c => path.push(c),
+        }
+    }
+    Some(PathBuf::from(path))
+}
+
+/// Backslash-escape the characters restic and rsync read as wildcards, so a
+/// path matches only itself.
+fn escape_glob(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        if matches!(c, '*' | '?' | '[' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_cachedir_tag_only_touches_its_own_tags() {
+        let tmp = tempdir().unwrap();
+        let (ours, cargo, file) = (
+            tmp.path().join("node_modules"),
+            tmp.path().join("target"),
+            tmp.path().join("build.log"),
+        );
+        fs::create_dir_all(&ours).unwrap();
+        fs::create_dir_all(&cargo).unwrap();
+        fs::write(&file, "").unwrap();
+        let cargo_tag = format!(
+            "{CACHEDIR_SIGNATURE}\n# This file is a cache directory tag created by cargo.\n"
+        );
+        fs::write(cargo.join(CACHEDIR_TAG), &cargo_tag).unwrap();
+
+        let manager = CachedirTagManager;
+        let paths = [ours.as_path(), cargo.as_path(), file.as_path()];
+        manager.add_exclusions(&paths, false).unwrap();
+        assert_eq!(manager.are_excluded(&paths), vec![true, true, false]);
+        assert_eq!(
+            fs::read_to_string(cargo.join(CACHEDIR_TAG)).unwrap(),
+            cargo_tag
+        );
+
+        manager.remove_exclusions(&paths, false).unwrap();
+        assert!(!ours.join(CACHEDIR_TAG).exists());
+        assert!(cargo.join(CACHEDIR_TAG).exists());
+    }
+
+    #[test]
+    fn test_excludes_file_lists_sorted_paths_in_each_format() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("letitgo/excludes.txt");
+        let manager = ExcludesFileManager::new(&path, ExcludesFormat::Restic);
+        manager
+            .add_exclusions(&[Path::new("/b/target"), Path::new("/a/dist")], false)
+            .unwrap();
+        manager
+            .remove_exclusions(&[Path::new("/b/target")], false)
+            .unwrap();
+        manager
+            .add_exclusions(&[Path::new("/c/[id]*.log"), Path::new("/d/$HOME")], false)
+            .unwrap();
+        let header = excludes_file_header(ExcludesFormat::Restic);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{header}\n/a/dist\n/c/\\[id]\\*.log\n/d/$$HOME\n")
+        );
+        assert!(manager.is_excluded(Path::new("/c/[id]*.log")).unwrap());
+        assert!(manager.is_excluded(Path::new("/d/$HOME")).unwrap());
+        assert!(!manager.is_excluded(Path::new("/b/target")).unwrap());
+
+        // Switching formats rewrites the same paths on the next update
+        for (format, lines) in [
+            (
+                ExcludesFormat::Borg,
+                "pp:/a/dist\npp:/c/[id]*.log\npp:/d/$HOME\n",
+            ),
+            (
+                ExcludesFormat::Rsync,
+                "- /a/dist\n- /c/\\[id]\\*.log\n- /d/$HOME\n",
+            ),
+            (
+                ExcludesFormat::Restic,
+                "/a/dist\n/c/\\[id]\\*.log\n/d/$$HOME\n",
+            ),
+        ] {
+            let manager = ExcludesFileManager::new(&path, format);
+            manager.add_exclusions(&[], false).unwrap();
+            assert_eq!(
+                fs::read_to_string(&path).unwrap(),
+                format!("{}\n{lines}", excludes_file_header(format))
+            );
+        }
+    }
+
+    #[test]
+    fn test_excludes_file_lines_are_read_in_its_own_format() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("excludes.txt");
+        // Paths that look like another format's lines
+        let odd = [Path::new("pp:notes"), Path::new("- draft")];
+        let restic = ExcludesFileManager::new(&path, ExcludesFormat::Restic);
+        restic.add_exclusions(&odd, false).unwrap();
+        assert_eq!(restic.are_excluded(&odd), vec![true, true]);
+
+        // Read as restic lines while migrating to borg
+        let borg = ExcludesFileManager::new(&path, ExcludesFormat::Borg);
+        borg.add_exclusions(&[], false).unwrap();
+        assert_eq!(borg.are_excluded(&odd), vec![true, true]);
+        assert!(!borg.is_excluded(Path::new("notes")).unwrap());
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::{Result, bail};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, warn};
+
+use crate::{
+    cache::nested_paths,
+    config::Config,
+    ignore_resolver::{ResolveOptions, resolve_repo},
+    scanner::{ScanOptions, discover},
+    size::{SeenInodes, disk_size_dedup, path_in_backup},
+};
+
+/// What the current rules would have kept out of one existing backup.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupAnalysis {
+    /// The backup that was walked.
+    pub backup: PathBuf,
+    /// Repositories found in the backup.
+    pub repos: usize,
+    /// Paths in the backup the current rules exclude.
+    pub paths: usize,
+    /// Their total size in the backup.
+    pub size_bytes: u64,
+    /// Repositories holding excluded paths, largest first.
+    pub by_repo: Vec<RepoWaste>,
+}
+
+/// The excluded paths of one repository inside a backup.
+#[derive(Debug, Clone, Serialize)]
+pub struct RepoWaste {
+    /// The repository, as found in the backup.
+    pub repo: PathBuf,
+    pub size_bytes: u64,
+    /// Each excluded path with its size, largest first.
+    pub paths: Vec<(PathBuf, u64)>,
+}
+
+/// Walk the backup at `backup` and measure what the current rules exclude.
+///
+/// The configured `search_paths` and `ignored_paths` are located inside the
+/// backup like [`path_in_backup`] does; when no search path is in it (e.g.
+/// a backup of another Mac), the whole backup is searched.  Each repository
+/// is resolved from the ignore files stored in the backup with `config`'s
+/// rules.  Sizes are allocated bytes, and a file hard-linked into several
+/// paths (as in older HFS+ backups) counts once.
+pub fn analyze_backup(backup: &Path, config: &Config) -> Result<BackupAnalysis> {
+    if !backup.is_dir() {
+        bail!("{} is not a mounted backup directory", backup.display());
+    }
+    let mut search_paths: Vec<PathBuf> = config
+        .resolved_search_paths()
+        .iter()
+        .filter_map(|p| path_in_backup(backup, p))
+        .collect();
+    if search_paths.is_empty() {
+        warn!(
+            "None of the search paths is in {}; searching all of it",
+            backup.display()
+        );
+        search_paths.push(backup.to_path_buf());
+    }
+    let ignored_paths: Vec<PathBuf> = config
+        .resolved_ignored_paths()
+        .iter()
+        .filter_map(|p| path_in_backup(backup, p))
+        .collect();
+    debug!("Searching {search_paths:?} in the backup");
+
+    let repos = discover(&search_paths, &ignored_paths, &ScanOptions::default()).repos;
+    let options = ResolveOptions::from_config(config)?;
+    let seen = SeenInodes::default();
+    let mut by_repo: Vec<RepoWaste> = repos
+        .par_iter()
+        .filter_map(|repo| match resolve_repo(repo, &options) {
+            Ok(paths) => Some((repo, paths)),
+            Err(e) => {
+                warn!("Error resolving {}: {e:#}", repo.display());
+                None
+            }
+        })
+        .map(|(repo, paths)| {
+            let mut paths: Vec<PathBuf> = paths.into_iter().collect();
+            let nested: HashSet<PathBuf> = nested_paths(&paths).into_iter().collect();
+            paths.retain(|p| !nested.contains(p));
+            let mut sized: Vec<(PathBuf, u64)> = paths
+                .into_iter()
+                .map(|p| {
+                    let size = disk_size_dedup(&p, &seen);
+                    (p, size)
+                })
+                .collect();
+            sized.sort_by(|(pa, sa), (pb, sb)| sb.cmp(sa).then_with(|| pa.cmp(pb)));
+            RepoWaste {
+                repo: repo.clone(),
+                size_bytes: sized.iter().map(|(_, s)| s).sum(),
+                paths: sized,
+            }
+        })
+        .filter(|waste| !waste.paths.is_empty())
+        .collect();
+    by_repo.sort_by(|a, b| {
+        b.size_bytes
+            .cmp(&a.size_bytes)
+            .then_with(|| a.repo.cmp(&b.repo))
+    });
+
+    Ok(BackupAnalysis {
+        backup: backup.to_path_buf(),
+        repos: repos.len(),
+        paths: by_repo.iter().map(|r| r.paths.len()).sum(),
+        size_bytes: by_repo.iter().map(|r| r.size_bytes).sum(),
+        by_repo,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_analyze_backup_measures_excluded_paths_in_volume_copy() {
+        let tmp = tempdir().unwrap();
+        let live = tmp.path().join("live/code");
+        let backup = tmp.path().join("backups/2026-01-01-120000.backup");
+        let code = backup
+            .join("Macintosh HD - Data")
+            .join(live.strip_prefix("/").unwrap());
+        for (repo, ignore) in [("a", "target/\ntarget/debug/\n"), ("b", "dist/\n")] {
+            let repo = code.join(repo);
+            fs::create_dir_all(repo.join(".git")).unwrap();
+            fs::create_dir_all(repo.join("target/debug")).unwrap();
+            fs::create_dir_all(repo.join("dist")).unwrap();
+            fs::write(repo.join(".gitignore"), ignore).unwrap();
+            fs::write(repo.join("target/debug/bin"), vec![1u8; 64 * 1024]).unwrap();
+            fs::write(repo.join("dist/app.js"), vec![2u8; 64 * 1024]).unwrap();
+        }
+        let config = Config {
+            search_paths: vec![live.to_string_lossy().into_owned()],
+            ignored_paths: vec![],
+            whitelist: vec![],
+            ..Config::default()
+        };
+
+        let analysis = analyze_backup(&backup, &config).unwrap();
+        assert_eq!(analysis.repos, 2);
+        // `target/debug` is counted as part of `target`, never twice
+        let paths: Vec<&Path> = analysis
+            .by_repo
+            .iter()
+            .flat_map(|r| r.paths.iter().map(|(p, _)| p.as_path()))
+            .collect();
+        assert_eq!(paths.len(), 2, "{paths:?}");
+        assert!(paths.contains(&code.join("a/target").as_path()));
+        assert!(paths.contains(&code.join("b/dist").as_path()));
+        assert!(analysis.size_bytes >= 2 * 64 * 1024);
+
+        // A backup holding none of the search paths is searched as a whole
+        let other = Config {
+            search_paths: vec!["/nonexistent/letitgo".into()],
+            ..config
+        };
+        assert_eq!(analyze_backup(&backup, &other).unwrap().paths, 2);
+        assert!(analyze_backup(&tmp.path().join("missing"), &other).is_err());
+    }
+}
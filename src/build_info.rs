@@ -0,0 +1,39 @@
+use serde::Serialize;
+
+use crate::tmutil::UPDATE_XATTR_DIRECTLY;
+
+/// Metadata about this binary, embedded at compile time by `build.rs`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    /// Short commit hash, suffixed `-dirty` for uncommitted changes, or
+    /// `unknown` outside a git checkout.
+    pub git_sha: &'static str,
+    /// UTC build date (`SOURCE_DATE_EPOCH` when set).
+    pub build_date: &'static str,
+    pub target: &'static str,
+    pub profile: &'static str,
+    pub rustc: &'static str,
+    /// Enabled cargo features.
+    pub features: Vec<&'static str>,
+    /// Whether sticky exclusions are written as xattrs directly rather than
+    /// through `tmutil`.
+    pub xattr_direct: bool,
+}
+
+/// The [`BuildInfo`] of the running binary.
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("LETITGO_GIT_SHA"),
+        build_date: env!("LETITGO_BUILD_DATE"),
+        target: env!("LETITGO_TARGET"),
+        profile: env!("LETITGO_PROFILE"),
+        rustc: env!("LETITGO_RUSTC_VERSION"),
+        features: env!("LETITGO_FEATURES")
+            .split(',')
+            .filter(|f| !f.is_empty())
+            .collect(),
+        xattr_direct: UPDATE_XATTR_DIRECTLY,
+    }
+}
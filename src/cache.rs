@@ -1,26 +1,179 @@
 use anyhow::{Context, Result};
-use chrono::{DateTime, FixedOffset};
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     fs,
+    os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
 };
 use tempfile::NamedTempFile;
 
-use crate::config::ExclusionMode;
+use crate::{
+    artifacts::Preset,
+    config::{CacheFormat, ExclusionMode},
+    encryption,
+    error::{ErrorKind, FileContext},
+    permissions,
+    provenance::Rule,
+};
+
+/// Leading bytes of a binary property list, which JSON never starts with.
+const BINARY_MAGIC: &[u8] = b"bplist00";
+
+/// Where an excluded path came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Origin {
+    /// Ignore rules (`.gitignore`, `.lignore`, …) inside a working tree.
+    #[default]
+    Repo,
+    /// A sub-path of a bare repository (`bare_repo_excludes`).
+    BareRepo,
+    /// A `system_artifacts` location outside any repo.
+    SystemArtifact,
+    /// A package-manager cache from a built-in preset.
+    Preset,
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Origin::Repo => "repo",
+            Origin::BareRepo => "bare-repo",
+            Origin::SystemArtifact => "system-artifact",
+            Origin::Preset => "preset",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The consecutive failed attempts to exclude one path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PathFailures {
+    pub count: u32,
+    pub last_attempt: DateTime<FixedOffset>,
+    /// When the path was quarantined: runs skip it until
+    /// `quarantine_reprobe_interval` after `last_attempt`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quarantined_since: Option<DateTime<FixedOffset>>,
+}
+
+/// Per-path metadata recorded alongside [`Cache::paths`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PathMeta {
+    pub origin: Origin,
+    /// The preset that produced the path, when `origin` is [`Origin::Preset`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<Preset>,
+    /// The mode the path is excluded in, when `mode_rules` picked another
+    /// one than [`Cache::exclusion_mode`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<ExclusionMode>,
+}
+
+impl PathMeta {
+    /// Metadata for a path produced by `preset`.
+    pub fn preset(preset: Preset) -> Self {
+        PathMeta {
+            origin: Origin::Preset,
+            preset: Some(preset),
+            ..PathMeta::default()
+        }
+    }
+
+    /// Metadata with the given origin and no further detail.
+    pub fn origin(origin: Origin) -> Self {
+        PathMeta {
+            origin,
+            ..PathMeta::default()
+        }
+    }
+}
+
+/// Repository roots remembered from discovery, used by `run --fast` to skip
+/// the filesystem walk.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct KnownRepos {
+    /// When the configured search paths were last walked in full.
+    pub discovered_at: Option<DateTime<FixedOffset>>,
+    pub repos: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bare_repos: Vec<PathBuf>,
+    /// The repos among `repos` that are sparse checkouts; the service runs
+    /// again as soon as one of them checks out other paths.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sparse_repos: Vec<PathBuf>,
+    /// When each of `repos` and `bare_repos` was last resolved without an
+    /// error, as `letitgo repos` shows.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub resolved_at: BTreeMap<PathBuf, DateTime<FixedOffset>>,
+}
+
+impl KnownRepos {
+    /// Record that `repos` were resolved `at`, forgetting the times of
+    /// repos no longer known.
+    pub fn mark_resolved<'a>(
+        &mut self,
+        repos: impl IntoIterator<Item = &'a PathBuf>,
+        at: DateTime<FixedOffset>,
+    ) {
+        for repo in repos {
+            self.resolved_at.insert(repo.clone(), at);
+        }
+        let known: HashSet<&PathBuf> = self.repos.iter().chain(&self.bare_repos).collect();
+        self.resolved_at.retain(|repo, _| known.contains(repo));
+    }
+}
 
 /// Persistent state written to disk between runs.
 ///
-/// Stored as pretty-printed JSON at `~/.cache/letitgo/cache.json`.
+/// Stored as pretty-printed JSON at `~/.cache/letitgo/cache.json`, or as a
+/// binary plist with `cache_format = "binary"`.
 /// Tracks which paths are currently excluded from Time Machine so that
 /// subsequent runs can compute a diff and avoid redundant `tmutil` calls.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Cache {
     pub version: u32,
     pub last_run: Option<DateTime<FixedOffset>>,
     pub exclusion_mode: ExclusionMode,
     pub paths: Vec<PathBuf>,
+    /// Metadata for paths whose origin is not the default [`Origin::Repo`].
+    /// Kept sparse so that caches of plain repo exclusions stay small.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub meta: BTreeMap<PathBuf, PathMeta>,
+    /// Directories excluded as a whole by `collapse_threshold`, with the
+    /// entries inside them that were not ignored themselves.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub collapsed: BTreeMap<PathBuf, Vec<PathBuf>>,
+    /// Repositories found by discovery; absent in caches from older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub known_repos: Option<KnownRepos>,
+    /// Paths per `tmutil` call that adaptive batching settled on, where the
+    /// next run starts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tmutil_batch_size: Option<usize>,
+    /// Excluded paths whose last exclusion attempts failed, and whether
+    /// they are quarantined (see [`crate::quarantine`]).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub failures: BTreeMap<PathBuf, PathFailures>,
+    /// When each path was first excluded; absent for paths cached by
+    /// versions that did not record it.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub added: BTreeMap<PathBuf, DateTime<FixedOffset>>,
+    /// The rule behind each path excluded by a working tree's rules; the
+    /// rule of other paths follows from their [`PathMeta`] (see
+    /// [`Cache::rule_for`]).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rules: BTreeMap<PathBuf, Rule>,
+    /// Encoding the cache was read in and is written in.
+    #[serde(skip)]
+    pub format: CacheFormat,
+    /// Whether the cache was read encrypted and is written encrypted (see
+    /// [`crate::encryption`]).
+    #[serde(skip)]
+    pub encrypted: bool,
 }
 
 impl Cache {
@@ -31,29 +184,116 @@ impl Cache {
             last_run: None,
             exclusion_mode: ExclusionMode::Sticky,
             paths: Vec::new(),
+            meta: BTreeMap::new(),
+            collapsed: BTreeMap::new(),
+            known_repos: None,
+            tmutil_batch_size: None,
+            failures: BTreeMap::new(),
+            added: BTreeMap::new(),
+            rules: BTreeMap::new(),
+            format: CacheFormat::Json,
+            encrypted: false,
         }
     }
 
+    /// Return the metadata recorded for `path` (defaults for repo paths).
+    pub fn meta_for(&self, path: &Path) -> PathMeta {
+        self.meta.get(path).cloned().unwrap_or_default()
+    }
+
+    /// Return the mode `path` is excluded in.
+    pub fn mode_for(&self, path: &Path) -> ExclusionMode {
+        self.meta
+            .get(path)
+            .and_then(|m| m.mode.clone())
+            .unwrap_or_else(|| self.exclusion_mode.clone())
+    }
+
+    /// Drop metadata for paths that are no longer in [`Cache::paths`].
+    pub fn prune_meta(&mut self) {
+        let live = self.path_set();
+        self.meta.retain(|p, _| live.contains(p));
+        self.collapsed.retain(|p, _| live.contains(p));
+        self.failures.retain(|p, _| live.contains(p));
+        self.added.retain(|p, _| live.contains(p));
+        self.rules.retain(|p, _| live.contains(p));
+    }
+
+    /// The rule that produced `path`, when this cache knows it.
+    pub fn rule_for(&self, path: &Path) -> Option<Rule> {
+        self.rules
+            .get(path)
+            .cloned()
+            .or_else(|| Rule::from_meta(&self.meta_for(path)))
+    }
+
+    /// The deepest known repository or bare repository containing `path`.
+    pub fn repo_of(&self, path: &Path) -> Option<&Path> {
+        let known = self.known_repos.as_ref()?;
+        known
+            .repos
+            .iter()
+            .chain(&known.bare_repos)
+            .filter(|r| path.starts_with(r))
+            .max_by_key(|r| r.components().count())
+            .map(PathBuf::as_path)
+    }
+
     /// Return the cached paths as a `HashSet` for O(1) membership tests.
     pub fn path_set(&self) -> HashSet<PathBuf> {
         self.paths.iter().cloned().collect()
     }
 }
 
-/// Load the cache from `path`. Returns an empty cache if the file does not exist.
+/// Load the cache from `path`, in either [`CacheFormat`], decrypting it
+/// with the user's cache key if it is encrypted. Returns an empty cache if
+/// the file does not exist.
 pub fn load_cache(path: &Path) -> Result<Cache> {
-    match fs::read_to_string(path) {
-        Ok(text) => {
-            let cache: Cache = serde_json::from_str(&text)
-                .with_context(|| format!("parsing cache: {}", path.display()))?;
-            Ok(cache)
+    match fs::read(path) {
+        Ok(bytes) if encryption::is_encrypted(&bytes) => {
+            let bytes = encryption::cache_key(false)
+                .and_then(|key| {
+                    let key = key.context(
+                        "the cache is encrypted but this user has no cache key \
+                         (delete it and run `letitgo run` to rebuild it)",
+                    )?;
+                    encryption::decrypt(&key, &bytes)
+                })
+                .with_context(|| FileContext::new(ErrorKind::Cache, path, "decrypting cache"))?;
+            let cache = parse_cache(path, &bytes)?;
+            Ok(Cache {
+                encrypted: true,
+                ..cache
+            })
         }
+        Ok(bytes) => parse_cache(path, &bytes),
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Cache::empty()),
-        Err(e) => Err(e).with_context(|| format!("reading cache: {}", path.display())),
+        Err(e) => Err(e).with_context(|| FileContext::new(ErrorKind::Cache, path, "reading cache")),
     }
 }
 
-/// Write the cache to `path` atomically.
+/// Parse the unencrypted cache `bytes` read from `path`.
+fn parse_cache(path: &Path, bytes: &[u8]) -> Result<Cache> {
+    match bytes {
+        bytes if bytes.starts_with(BINARY_MAGIC) => {
+            let cache: Cache = plist::from_bytes(bytes).with_context(|| {
+                FileContext::new(ErrorKind::Cache, path, "parsing binary cache")
+            })?;
+            Ok(Cache {
+                format: CacheFormat::Binary,
+                ..cache
+            })
+        }
+        bytes => {
+            let cache: Cache = serde_json::from_slice(bytes)
+                .with_context(|| FileContext::new(ErrorKind::Cache, path, "parsing cache"))?;
+            Ok(cache)
+        }
+    }
+}
+
+/// Write the cache to `path` atomically, encoded as [`Cache::format`] and
+/// encrypted when [`Cache::encrypted`].
 ///
 /// Serialises to a `NamedTempFile` in the same directory as `path`, then
 /// renames it into place. `rename(2)` is atomic on POSIX systems, so a
@@ -61,7 +301,7 @@ pub fn load_cache(path: &Path) -> Result<Cache> {
 /// the old complete file or the new complete file — never a partial write.
 pub fn write_cache(path: &Path, cache: &Cache) -> Result<()> {
     let parent = path.parent().unwrap_or(Path::new("."));
-    fs::create_dir_all(parent)
+    permissions::create_private_dir(parent)
         .with_context(|| format!("creating cache dir: {}", parent.display()))?;
 
     // Sort references for deterministic output (easier to diff/debug)
@@ -75,25 +315,62 @@ pub fn write_cache(path: &Path, cache: &Cache) -> Result<()> {
         last_run: Option<DateTime<FixedOffset>>,
         exclusion_mode: &'a ExclusionMode,
         paths: &'a [&'a PathBuf],
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        meta: &'a BTreeMap<PathBuf, PathMeta>,
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        collapsed: &'a BTreeMap<PathBuf, Vec<PathBuf>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        known_repos: &'a Option<KnownRepos>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tmutil_batch_size: Option<usize>,
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        failures: &'a BTreeMap<PathBuf, PathFailures>,
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        added: &'a BTreeMap<PathBuf, DateTime<FixedOffset>>,
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        rules: &'a BTreeMap<PathBuf, Rule>,
     }
     let sorted_cache = CacheRef {
         version: cache.version,
         last_run: cache.last_run,
         exclusion_mode: &cache.exclusion_mode,
         paths: &sorted_refs,
+        meta: &cache.meta,
+        collapsed: &cache.collapsed,
+        known_repos: &cache.known_repos,
+        tmutil_batch_size: cache.tmutil_batch_size,
+        failures: &cache.failures,
+        added: &cache.added,
+        rules: &cache.rules,
+    };
+    let bytes = match cache.format {
+        CacheFormat::Json => {
+            serde_json::to_vec_pretty(&sorted_cache).context("serializing cache")?
+        }
+        CacheFormat::Binary => {
+            let mut bytes = Vec::new();
+            plist::to_writer_binary(&mut bytes, &sorted_cache).context("serializing cache")?;
+            bytes
+        }
+    };
+    let bytes = if cache.encrypted {
+        let key = encryption::cache_key(true)?.context("creating cache key")?;
+        encryption::encrypt(&key, &bytes)?
+    } else {
+        bytes
     };
-    let text = serde_json::to_string_pretty(&sorted_cache).context("serializing cache")?;
 
-    // Write to a sibling temp file, fsync, then atomically rename into place.
+    // Write to a sibling temp file (created 0600), fsync, then atomically
+    // rename into place.
     let mut tmp = NamedTempFile::new_in(parent)
         .with_context(|| format!("creating temp file in {}", parent.display()))?;
-    std::io::Write::write_all(&mut tmp, text.as_bytes())
+    std::io::Write::write_all(&mut tmp, &bytes)
         .with_context(|| format!("writing cache temp file in {}", parent.display()))?;
     tmp.as_file()
         .sync_all()
         .with_context(|| format!("fsyncing cache temp file in {}", parent.display()))?;
     tmp.persist(path)
-        .with_context(|| format!("persisting cache to {}", path.display()))?;
+        .with_context(|| FileContext::new(ErrorKind::Cache, path, "persisting cache to"))?;
 
     Ok(())
 }
@@ -113,6 +390,115 @@ pub fn diff_sets<'a>(
     (to_add, to_remove)
 }
 
+/// Directory holding the snapshots of the cache at `cache_path`.
+pub fn snapshot_dir(cache_path: &Path) -> PathBuf {
+    cache_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("snapshots")
+}
+
+/// Copy the cache at `cache_path` into [`snapshot_dir`] as
+/// `cache-<timestamp>.json`, then delete all but the newest `keep` snapshots.
+///
+/// Returns the snapshot written, or `None` when there is no cache yet.
+pub fn snapshot_cache(cache_path: &Path, keep: usize) -> Result<Option<PathBuf>> {
+    if !cache_path.exists() {
+        return Ok(None);
+    }
+    let dir = snapshot_dir(cache_path);
+    permissions::create_private_dir(&dir)
+        .with_context(|| format!("creating snapshot dir: {}", dir.display()))?;
+    // Two snapshots within a millisecond get consecutive timestamps, so the
+    // names stay unique and in order.
+    let mut taken_at = Local::now();
+    let snapshot = loop {
+        let name = format!("cache-{}.json", taken_at.format("%Y%m%dT%H%M%S%.3f"));
+        let candidate = dir.join(name);
+        if !candidate.exists() {
+            break candidate;
+        }
+        taken_at += chrono::Duration::milliseconds(1);
+    };
+    // `fs::copy` copies the mode of a cache written by an older version
+    fs::copy(cache_path, &snapshot)
+        .and_then(|_| {
+            fs::set_permissions(
+                &snapshot,
+                fs::Permissions::from_mode(permissions::FILE_MODE),
+            )
+        })
+        .with_context(|| format!("writing cache snapshot: {}", snapshot.display()))?;
+
+    let snapshots = list_snapshots(cache_path)?;
+    for old in &snapshots[..snapshots.len().saturating_sub(keep)] {
+        fs::remove_file(old)
+            .with_context(|| format!("removing old snapshot: {}", old.display()))?;
+    }
+    Ok(Some(snapshot))
+}
+
+/// Return the snapshots of the cache at `cache_path`, oldest first.
+pub fn list_snapshots(cache_path: &Path) -> Result<Vec<PathBuf>> {
+    let dir = snapshot_dir(cache_path);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", dir.display())),
+    };
+    let mut snapshots: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("cache-") && n.ends_with(".json"))
+        })
+        .collect();
+    // Timestamps in the names sort chronologically.
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+/// When the snapshot at `path` was taken, from its `cache-<timestamp>.json`
+/// name (local time).
+pub fn snapshot_taken_at(path: &Path) -> Option<NaiveDateTime> {
+    let name = path.file_name()?.to_str()?;
+    let stamp = name.strip_prefix("cache-")?.strip_suffix(".json")?;
+    NaiveDateTime::parse_from_str(stamp, "%Y%m%dT%H%M%S%.3f").ok()
+}
+
+/// The paths in `paths` that lie inside another one of them.
+pub fn nested_paths(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let set: HashSet<&Path> = paths.iter().map(PathBuf::as_path).collect();
+    paths
+        .iter()
+        .filter(|p| p.ancestors().skip(1).any(|a| set.contains(a)))
+        .cloned()
+        .collect()
+}
+
+/// Find the snapshot called `name`: a file name from [`list_snapshots`],
+/// its timestamp alone (`cache-<timestamp>.json` without the decoration),
+/// or a path to a snapshot file.
+pub fn find_snapshot(cache_path: &Path, name: &str) -> Result<PathBuf> {
+    let as_path = Path::new(name);
+    if as_path.components().count() > 1 && as_path.is_file() {
+        return Ok(as_path.to_path_buf());
+    }
+    list_snapshots(cache_path)?
+        .into_iter()
+        .find(|p| {
+            let file_name = p.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            file_name == name || file_name == format!("cache-{name}.json")
+        })
+        .with_context(|| {
+            format!(
+                "no snapshot `{name}` in {} (see `letitgo cache list`)",
+                snapshot_dir(cache_path).display()
+            )
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -139,6 +525,79 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_meta_round_trip_and_legacy_cache() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("cache.json");
+
+        let mut cache = Cache::empty();
+        cache.paths = vec![pb("/repo/target"), pb("/home/.npm")];
+        cache
+            .meta
+            .insert(pb("/home/.npm"), PathMeta::preset(Preset::Npm));
+        write_cache(&path, &cache).unwrap();
+
+        let loaded = load_cache(&path).unwrap();
+        assert_eq!(
+            loaded.meta_for(Path::new("/repo/target")).origin,
+            Origin::Repo
+        );
+        assert_eq!(
+            loaded.meta_for(Path::new("/home/.npm")),
+            PathMeta::preset(Preset::Npm)
+        );
+
+        // Caches written before per-path metadata existed still load.
+        fs::write(
+            &path,
+            r#"{"version":1,"last_run":null,"exclusion_mode":"sticky","paths":["/a"]}"#,
+        )
+        .unwrap();
+        let legacy = load_cache(&path).unwrap();
+        assert!(legacy.meta.is_empty());
+        assert_eq!(legacy.path_set(), [pb("/a")].into_iter().collect());
+    }
+
+    #[test]
+    fn test_binary_format_round_trips_and_is_detected() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("cache.json");
+
+        let mut cache = Cache::empty();
+        cache.last_run = Some(Local::now().fixed_offset());
+        cache.paths = (0..1000)
+            .map(|i| pb(&format!("/Users/alice/projects/repo-{i}/target")))
+            .collect();
+        cache
+            .meta
+            .insert(pb("/home/.npm"), PathMeta::preset(Preset::Npm));
+        cache
+            .collapsed
+            .insert(pb("/repo/logs"), vec![pb("/repo/logs/keep")]);
+        cache.known_repos = Some(KnownRepos {
+            discovered_at: None,
+            repos: vec![pb("/repo")],
+            bare_repos: Vec::new(),
+            sparse_repos: vec![pb("/repo")],
+            resolved_at: BTreeMap::new(),
+        });
+        write_cache(&path, &cache).unwrap();
+        let json_len = fs::metadata(&path).unwrap().len();
+
+        cache.format = CacheFormat::Binary;
+        write_cache(&path, &cache).unwrap();
+        assert!(fs::read(&path).unwrap().starts_with(BINARY_MAGIC));
+        assert!(fs::metadata(&path).unwrap().len() < json_len);
+
+        let loaded = load_cache(&path).unwrap();
+        assert_eq!(loaded.format, CacheFormat::Binary);
+        assert_eq!(loaded.path_set(), cache.path_set());
+        assert_eq!(loaded.last_run, cache.last_run);
+        assert_eq!(loaded.meta, cache.meta);
+        assert_eq!(loaded.collapsed, cache.collapsed);
+        assert_eq!(loaded.known_repos, cache.known_repos);
+    }
+
     #[test]
     fn test_diff_sets() {
         let old: HashSet<PathBuf> = [pb("/a"), pb("/b"), pb("/c")].into_iter().collect();
@@ -200,4 +659,22 @@ mod tests {
             "old data must not survive an overwrite"
         );
     }
+
+    #[test]
+    fn test_snapshot_rotation() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("cache.json");
+        assert_eq!(snapshot_cache(&path, 2).unwrap(), None);
+
+        let mut written = Vec::new();
+        for i in 0..3 {
+            let mut cache = Cache::empty();
+            cache.paths = vec![PathBuf::from(format!("/run/{i}"))];
+            write_cache(&path, &cache).unwrap();
+            written.push(snapshot_cache(&path, 2).unwrap().unwrap());
+        }
+        assert_eq!(list_snapshots(&path).unwrap(), written[1..]);
+        let newest = load_cache(&written[2]).unwrap();
+        assert_eq!(newest.paths, vec![pb("/run/2")]);
+    }
 }
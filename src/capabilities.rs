@@ -0,0 +1,198 @@
+use serde::Deserialize;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
+};
+use tracing::debug;
+
+/// What is known of `tmutil` per macOS release (`data/tmutil.toml`).
+const PROFILES: &str = include_str!("../data/tmutil.toml");
+
+/// A dotted version such as `10.15.7` or `4.0.0`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version(Vec<u32>);
+
+impl Version {
+    /// The leading dotted number of `text` (`"14.5"`, `"tmutil version
+    /// 4.0.0 (x)"`), or `None` when it has none.
+    pub fn find(text: &str) -> Option<Version> {
+        let start = text.find(|c: char| c.is_ascii_digit())?;
+        let parts: Vec<u32> = text[start..]
+            .split(|c: char| !c.is_ascii_digit() && c != '.')
+            .next()?
+            .split('.')
+            .map_while(|part| part.parse().ok())
+            .collect();
+        (!parts.is_empty()).then_some(Version(parts))
+    }
+
+    /// Whether `self` lies in `min..=max`, each compared at its own
+    /// precision: `26` holds `26.1`.
+    fn within(&self, min: &Version, max: &Version) -> bool {
+        let at = |len: usize| Version(self.0.iter().copied().take(len).collect());
+        at(min.0.len()) >= *min && at(max.0.len()) <= *max
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(u32::to_string).collect();
+        write!(f, "{}", parts.join("."))
+    }
+}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(d)?;
+        Version::find(&text)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid version `{text}`")))
+    }
+}
+
+/// How `tmutil` behaves on a range of macOS releases.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    pub macos_min: Version,
+    pub macos_max: Version,
+    /// Exit codes of `addexclusion`/`removeexclusion` that only mean the
+    /// path is gone.
+    pub safe_exit_codes: Vec<i32>,
+}
+
+/// What applies outside every [`Profile`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct DefaultProfile {
+    pub safe_exit_codes: Vec<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Profiles {
+    default: DefaultProfile,
+    #[serde(default)]
+    profile: Vec<Profile>,
+}
+
+fn profiles() -> &'static Profiles {
+    static PARSED: OnceLock<Profiles> = OnceLock::new();
+    PARSED.get_or_init(|| toml::from_str(PROFILES).expect("data/tmutil.toml parses"))
+}
+
+/// The running system as far as `tmutil` is concerned, probed once per run
+/// by the Time Machine backend and by `doctor`.
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    /// `sw_vers -productVersion`; `None` off macOS.
+    pub macos_version: Option<Version>,
+    /// The `tmutil` probed.
+    pub tmutil: PathBuf,
+    /// `tmutil version`; `None` when it could not be run.
+    pub tmutil_version: Option<Version>,
+    /// The known profile matching `macos_version`, if any.
+    pub profile: Option<Profile>,
+    /// The safe exit codes in effect: the profile's, else the default ones.
+    pub safe_exit_codes: Vec<i32>,
+}
+
+impl Capabilities {
+    /// Ask the system for its macOS version and `tmutil` for its own.
+    pub fn probe(tmutil: &Path) -> Self {
+        let macos_version = command_version(Path::new("/usr/bin/sw_vers"), &["-productVersion"]);
+        let tmutil_version = command_version(tmutil, &["version"]);
+        let capabilities = Self::for_versions(tmutil, macos_version, tmutil_version);
+        debug!("{capabilities}");
+        capabilities
+    }
+
+    /// The capabilities of a system reporting these versions.
+    pub fn for_versions(
+        tmutil: &Path,
+        macos_version: Option<Version>,
+        tmutil_version: Option<Version>,
+    ) -> Self {
+        let profiles = profiles();
+        let profile = macos_version.as_ref().and_then(|version| {
+            profiles
+                .profile
+                .iter()
+                .find(|p| version.within(&p.macos_min, &p.macos_max))
+                .cloned()
+        });
+        let safe_exit_codes = profile.as_ref().map_or_else(
+            || profiles.default.safe_exit_codes.clone(),
+            |p| p.safe_exit_codes.clone(),
+        );
+        Capabilities {
+            macos_version,
+            tmutil: tmutil.to_path_buf(),
+            tmutil_version,
+            profile,
+            safe_exit_codes,
+        }
+    }
+
+    /// Whether letitgo knows how `tmutil` behaves here: a profile matches
+    /// and `tmutil` reported its version.
+    pub fn is_known(&self) -> bool {
+        self.profile.is_some() && self.tmutil_version.is_some()
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_unknown =
+            |v: &Option<Version>| v.as_ref().map_or("unknown".into(), Version::to_string);
+        write!(
+            f,
+            "macOS {}, tmutil {} ({}), profile: {}",
+            or_unknown(&self.macos_version),
+            or_unknown(&self.tmutil_version),
+            self.tmutil.display(),
+            self.profile
+                .as_ref()
+                .map_or("none (defaults)", |p| p.name.as_str())
+        )
+    }
+}
+
+/// The version `program args` prints, or `None` when it cannot be run.
+fn command_version(program: &Path, args: &[&str]) -> Option<Version> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        debug!(
+            "{} {} exited with {}",
+            program.display(),
+            args.join(" "),
+            output.status
+        );
+        return None;
+    }
+    Version::find(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_is_picked_by_macos_version_at_the_bounds_precision() {
+        let tmutil = Path::new("/usr/bin/tmutil");
+        let caps = |macos: &str| {
+            Capabilities::for_versions(tmutil, Version::find(macos), Version::find("4.0.0"))
+        };
+        assert!(caps("10.15.7").is_known());
+        assert!(caps("26.1").is_known());
+        assert!(!caps("10.14.6").is_known());
+        assert!(!caps("27.0").is_known());
+        assert_eq!(caps("27.0").safe_exit_codes, vec![22, 213]);
+        assert_eq!(
+            Version::find("tmutil version 4.0.0 (x)")
+                .unwrap()
+                .to_string(),
+            "4.0.0"
+        );
+        assert!(!Capabilities::for_versions(tmutil, Version::find("14.5"), None).is_known());
+    }
+}
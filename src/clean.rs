@@ -1,38 +1,239 @@
 use anyhow::Result;
-use std::path::{Path, PathBuf};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Component, Path, PathBuf},
+};
 use tracing::{info, warn};
 
-use crate::{cache, tmutil::ExclusionManager};
+use crate::{
+    cache::{self, Cache},
+    config::TrashedPaths,
+    modes,
+    tmutil::ExclusionManager,
+};
+
+/// Stale paths that belong together, for `clean --interactive`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleGroup {
+    /// The known repository holding the paths, else the unmounted volume
+    /// or the parent directory they were in.
+    pub root: PathBuf,
+    pub paths: Vec<PathBuf>,
+    /// `/Volumes/<name>` when the paths are on a volume that is not
+    /// mounted: they may come back when it is plugged in.
+    pub unmounted_volume: Option<PathBuf>,
+}
+
+/// `/Volumes/<name>` of a path on a volume that is not mounted: the
+/// directory is missing, or is a leftover directory on the same device as
+/// `/Volumes` rather than the mount point of a device of its own.
+pub fn unmounted_volume(path: &Path) -> Option<PathBuf> {
+    let mut components = path.components();
+    let (Some(Component::RootDir), Some(Component::Normal(volumes)), Some(Component::Normal(name))) =
+        (components.next(), components.next(), components.next())
+    else {
+        return None;
+    };
+    if volumes != "Volumes" {
+        return None;
+    }
+    let volume = Path::new("/").join(volumes).join(name);
+    let mounted = match (fs::metadata(&volume), fs::metadata("/Volumes")) {
+        (Ok(volume), Ok(volumes)) => volume.dev() != volumes.dev(),
+        _ => false,
+    };
+    (!mounted).then_some(volume)
+}
+
+/// Whether `path` is stale: it no longer exists on disk, and not because
+/// the volume it is on is unplugged (see [`unmounted_volume`]).
+pub fn is_stale(path: &Path) -> bool {
+    !path.exists() && unmounted_volume(path).is_none()
+}
+
+/// Whether `path` lies in a Trash: the user's `~/.Trash` or the
+/// `.Trashes` of a volume.  It exists until the Trash is emptied.
+pub fn is_trashed(path: &Path) -> bool {
+    path.components()
+        .any(|c| matches!(c, Component::Normal(name) if name == ".Trash" || name == ".Trashes"))
+}
+
+/// Group `stale` by the innermost of `repos` holding each path, else by
+/// unmounted volume, else by parent directory.
+pub fn group_stale(stale: &[PathBuf], repos: &[PathBuf]) -> Vec<StaleGroup> {
+    let mut groups: BTreeMap<PathBuf, StaleGroup> = BTreeMap::new();
+    for path in stale {
+        let volume = unmounted_volume(path);
+        let root = repos
+            .iter()
+            .filter(|repo| path.starts_with(repo))
+            .max_by_key(|repo| repo.components().count())
+            .cloned()
+            .or_else(|| volume.clone())
+            .unwrap_or_else(|| path.parent().unwrap_or(path).to_path_buf());
+        groups
+            .entry(root.clone())
+            .or_insert_with(|| StaleGroup {
+                root,
+                paths: Vec::new(),
+                unmounted_volume: volume,
+            })
+            .paths
+            .push(path.clone());
+    }
+    let mut groups: Vec<StaleGroup> = groups.into_values().collect();
+    for group in &mut groups {
+        group.paths.sort();
+    }
+    groups
+}
 
 /// Remove all exclusions for stale paths (those that no longer exist on disk).
+/// Paths on an unmounted volume are unavailable rather than stale and are
+/// left alone; paths moved to the Trash are removed too.
 ///
+/// The cache is snapshotted first, keeping `keep_snapshots` (`0` = none).
 /// Returns the number of stale paths removed.
 pub fn clean_stale(
     cache_path: &Path,
     exclusion_manager: &dyn ExclusionManager,
-    fixed_path: bool,
+    keep_snapshots: usize,
     dry_run: bool,
 ) -> Result<usize> {
-    let mut cache = cache::load_cache(cache_path)?;
-    let old_set = cache.path_set();
+    clean_selected(
+        cache_path,
+        exclusion_manager,
+        keep_snapshots,
+        dry_run,
+        false,
+        TrashedPaths::Remove,
+        |_| false,
+        |_, stale| Ok(stale.to_vec()),
+    )
+}
 
-    let (live, stale): (Vec<PathBuf>, Vec<PathBuf>) = old_set.into_iter().partition(|p| p.exists());
+/// Like [`clean_stale`], removing only the stale paths `select` returns
+/// (given the cache and all stale paths, sorted); the others stay
+/// excluded and cached.  With `include_unmounted`, paths on an unmounted
+/// volume count as stale too.  Paths in the Trash (see [`is_trashed`])
+/// count as stale unless `trashed` keeps them.  Missing paths `denied`
+/// says letitgo has no access to (see [`crate::access::Scope::denies`])
+/// may well exist and are left alone.
+#[allow(clippy::too_many_arguments)]
+pub fn clean_selected(
+    cache_path: &Path,
+    exclusion_manager: &dyn ExclusionManager,
+    keep_snapshots: usize,
+    dry_run: bool,
+    include_unmounted: bool,
+    trashed: TrashedPaths,
+    denied: impl Fn(&Path) -> bool,
+    select: impl FnOnce(&Cache, &[PathBuf]) -> Result<Vec<PathBuf>>,
+) -> Result<usize> {
+    let mut cache = cache::load_cache(cache_path)?;
+    let (unreachable, missing): (Vec<&PathBuf>, Vec<&PathBuf>) = cache
+        .paths
+        .iter()
+        .filter(|p| !p.exists())
+        .partition(|p| denied(p));
+    let (mut stale, unavailable): (Vec<PathBuf>, Vec<PathBuf>) = missing
+        .into_iter()
+        .cloned()
+        .partition(|p| include_unmounted || unmounted_volume(p).is_none());
+    let in_trash = cache.paths.iter().filter(|p| p.exists() && is_trashed(p));
+    match trashed {
+        TrashedPaths::Remove => stale.extend(in_trash.cloned()),
+        TrashedPaths::Keep => {
+            let kept = in_trash.count();
+            if kept > 0 {
+                info!("clean: kept {kept} path(s) in the Trash (trashed_paths = \"keep\")");
+            }
+        }
+    }
+    stale.sort();
+    if !unreachable.is_empty() {
+        info!(
+            "clean: left {} path(s) letitgo has no access to alone",
+            unreachable.len()
+        );
+    }
+    if !unavailable.is_empty() {
+        info!(
+            "clean: left {} path(s) on unmounted volumes alone (--include-unmounted removes them)",
+            unavailable.len()
+        );
+    }
 
     if stale.is_empty() {
         info!("clean: no stale paths found");
         return Ok(0);
     }
+    let stale = select(&cache, &stale)?;
+    if stale.is_empty() {
+        return Ok(0);
+    }
 
     for path in &stale {
-        warn!("Stale path removed from exclusions: {}", path.display());
+        if is_trashed(path) && path.exists() {
+            warn!("Trashed path removed from exclusions: {}", path.display());
+        } else {
+            warn!("Stale path removed from exclusions: {}", path.display());
+        }
     }
 
     if !dry_run {
         let stale_refs: Vec<&Path> = stale.iter().map(|p| p.as_path()).collect();
-        exclusion_manager.remove_exclusions(&stale_refs, fixed_path)?;
-        cache.paths = live;
+        // Each in the mode it was added in
+        modes::remove_by_mode(exclusion_manager, &stale_refs, |p| cache.mode_for(p))?;
+        let removed: HashSet<&PathBuf> = stale.iter().collect();
+        cache.paths.retain(|p| !removed.contains(p));
+        cache.prune_meta();
+        if keep_snapshots > 0 {
+            cache::snapshot_cache(cache_path, keep_snapshots)?;
+        }
         cache::write_cache(cache_path, &cache)?;
     }
 
     Ok(stale.len())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale_paths_group_by_innermost_repo_then_unmounted_volume() {
+        let repos = [
+            PathBuf::from("/code/app"),
+            PathBuf::from("/code/app/vendor/lib"),
+        ];
+        let stale = [
+            "/code/app/target",
+            "/code/app/vendor/lib/target",
+            "/Volumes/letitgo-unplugged/work/node_modules",
+            "/Volumes/letitgo-unplugged/other/build",
+            "/tmp/gone/build",
+        ]
+        .map(PathBuf::from);
+        let groups = group_stale(&stale, &repos);
+        let roots: Vec<&Path> = groups.iter().map(|g| g.root.as_path()).collect();
+        assert_eq!(
+            roots,
+            [
+                "/Volumes/letitgo-unplugged",
+                "/code/app",
+                "/code/app/vendor/lib",
+                "/tmp/gone"
+            ]
+            .map(Path::new)
+        );
+        assert_eq!(groups[0].paths.len(), 2);
+        assert_eq!(
+            groups[0].unmounted_volume.as_deref(),
+            Some(Path::new("/Volumes/letitgo-unplugged"))
+        );
+        assert_eq!(groups[1].unmounted_volume, None);
+    }
+}
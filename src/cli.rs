@@ -1,9 +1,21 @@
 use clap::{Args, Parser, Subcommand};
-use std::path::PathBuf;
+use std::{ffi::OsString, path::PathBuf, time::Duration};
+
+use crate::config::parse_duration;
+use crate::export::ExportFormat;
+use crate::list::{ListFormat, ListSort};
+use crate::schema::SchemaName;
+use crate::test_glob::PatternKind;
 
 /// letitgo — keep Time Machine backups lean by excluding gitignored paths.
 #[derive(Debug, Parser)]
-#[command(name = "letitgo", version, about, long_about = None)]
+#[command(
+    name = "letitgo",
+    version,
+    about,
+    long_about = None,
+    after_help = "Any other COMMAND runs `letitgo-COMMAND` from PATH."
+)]
 pub struct Cli {
     /// Path to config file
     #[arg(short, long, global = true, value_name = "PATH")]
@@ -21,26 +33,168 @@ pub struct Cli {
     #[arg(long, global = true)]
     pub dry_run: bool,
 
+    /// Don't take the instance lock (unsafe with concurrent runs; see `no_lock`)
+    #[arg(long, global = true)]
+    pub no_lock: bool,
+
+    /// Answer yes to every confirmation prompt (see `assume_yes`)
+    #[arg(short = 'y', long, global = true)]
+    pub yes: bool,
+
+    /// Never prompt: every confirmation is answered no (see `non_interactive`)
+    #[arg(long, global = true)]
+    pub non_interactive: bool,
+
+    /// Plain output for screen readers and log scrapers: no color or
+    /// symbols, one record per line starting with its type (ITEM, FIELD, …)
+    #[arg(long, global = true)]
+    pub plain: bool,
+
+    /// On failure, print the error on stderr as one JSON object (kind,
+    /// message, causes, path, suggestion) instead of text
+    #[arg(long, global = true)]
+    pub json_errors: bool,
+
+    /// Treat DIR as the filesystem root: default config, cache and lock paths,
+    /// search paths and exclusions all live under it
+    #[arg(long, global = true, value_name = "DIR")]
+    pub root: Option<PathBuf>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
+impl Cli {
+    /// The global flags given, as arguments for an external subcommand.
+    pub fn global_args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = Vec::new();
+        if let Some(config) = &self.config {
+            args.extend(["--config".into(), config.into()]);
+        }
+        if let Some(root) = &self.root {
+            args.extend(["--root".into(), root.into()]);
+        }
+        args.extend((0..self.verbose).map(|_| "--verbose".into()));
+        let switches = [
+            (self.quiet, "--quiet"),
+            (self.dry_run, "--dry-run"),
+            (self.no_lock, "--no-lock"),
+            (self.yes, "--yes"),
+            (self.non_interactive, "--non-interactive"),
+            (self.plain, "--plain"),
+            (self.json_errors, "--json-errors"),
+        ];
+        args.extend(switches.iter().filter(|(on, _)| *on).map(|(_, f)| f.into()));
+        args
+    }
+}
+
 #[derive(Debug, Subcommand)]
 pub enum Commands {
     /// Scan, compute exclusions, and update Time Machine
     Run(RunArgs),
 
+    /// Compute what `run` would change, without changing anything, as a
+    /// plan file for `apply`
+    Plan(PlanArgs),
+
+    /// Make the changes of a plan written by `plan`
+    Apply(ApplyArgs),
+
     /// Show currently excluded paths (from cache)
     List(ListArgs),
 
+    /// Show the cached paths matching a filter expression, e.g.
+    /// `size > 1GB and repo ~ "work/*"`
+    Query(QueryArgs),
+
+    /// Write the excluded paths as configuration for another backup tool
+    Export(ExportArgs),
+
     /// Remove all exclusions made by letitgo and clear the cache
-    Reset(ResetArgs),
+    Reset,
 
     /// Validate cached paths and remove stale exclusions
-    Clean,
+    Clean(CleanArgs),
 
     /// Create a default config file with inline comments
     Init(InitArgs),
+
+    /// Explain why a path is or isn't excluded
+    Explain(ExplainArgs),
+
+    /// Report which step keeps a path from being excluded
+    WhyNot(ExplainArgs),
+
+    /// List the repositories discovery found, or tell why the one
+    /// containing a path was not found
+    Repos(ReposArgs),
+
+    /// Test a whitelist glob or ignore pattern against sample paths (or the
+    /// cached exclusions) without a scan
+    TestGlob(TestGlobArgs),
+
+    /// Check the `.lignore` files under the search paths for problems
+    Lint(JsonArgs),
+
+    /// Check that this system's tmutil is one letitgo knows how to drive,
+    /// and that letitgo's files are private to you
+    Doctor(DoctorArgs),
+
+    /// Estimate how much space the exclusions save, on disk and in the
+    /// latest Time Machine backup
+    Stats(StatsArgs),
+
+    /// Measure what the current rules would have kept out of existing
+    /// (mounted) Time Machine backups
+    AnalyzeBackup(AnalyzeBackupArgs),
+
+    /// Delete the paths the current rules exclude from existing Time Machine
+    /// backups (`tmutil delete -p`; needs sudo)
+    PurgeBackups(PurgeBackupsArgs),
+
+    /// Compare the cache with its previous snapshot, or with a fresh scan
+    Diff(DiffArgs),
+
+    /// Show how the exclusions would differ from the cache under another
+    /// config, without changing anything
+    Simulate(SimulateArgs),
+
+    /// List, take and restore cache snapshots; compact the cache
+    #[command(subcommand)]
+    Cache(CacheCommand),
+
+    /// Show or refresh the managed policy set by `policy_url`
+    #[command(subcommand)]
+    Policy(PolicyCommand),
+
+    /// Edit a repository's `.lignore`
+    #[command(subcommand)]
+    Lignore(LignoreCommand),
+
+    /// Time Machine snapshot and destination helpers
+    #[command(subcommand)]
+    Tm(TmCommand),
+
+    /// Run letitgo as a long-lived process under a supervisor
+    #[command(subcommand)]
+    Service(ServiceCommand),
+
+    /// Install a root daemon that applies fixed-path exclusions for
+    /// user-level runs (`fixed_path_backend = "helper"`)
+    #[command(subcommand)]
+    PrivilegedHelper(HelperCommand),
+
+    /// Print the version; with --verbose, the embedded build metadata
+    Version(JsonArgs),
+
+    /// Print the JSON Schema of a file or output format letitgo writes
+    Schema(SchemaArgs),
+
+    /// Any other name runs `letitgo-<name>` from PATH, with the global flags
+    /// and the config and cache paths (see `external`)
+    #[command(external_subcommand)]
+    External(Vec<OsString>),
 }
 
 #[derive(Debug, Args)]
@@ -48,6 +202,52 @@ pub struct RunArgs {
     /// Override configured search paths (repeatable)
     #[arg(long, value_name = "DIR", action = clap::ArgAction::Append)]
     pub search_path: Vec<PathBuf>,
+
+    /// Only run if the last run is older than DURATION (e.g. 30m, 12h, 7d)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub if_stale: Option<Duration>,
+
+    /// Re-resolve the repos found by the last full scan instead of walking
+    /// the search paths (plus any --search-path roots)
+    #[arg(long)]
+    pub fast: bool,
+
+    /// Allow exclusions shallower than `min_exclusion_depth`
+    #[arg(long)]
+    pub force: bool,
+
+    /// Exclude paths above `large_exclusion_threshold` without asking
+    #[arg(long)]
+    pub yes_large: bool,
+
+    /// Take over the lock if the process recorded in it has exited
+    #[arg(long)]
+    pub steal_stale_lock: bool,
+
+    /// Fail the run if a directory cannot be scanned (permission denied)
+    #[arg(long)]
+    pub strict_permissions: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct PlanArgs {
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    /// Write the plan to PATH instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ApplyArgs {
+    /// Plan file written by `letitgo plan`
+    #[arg(value_name = "PLAN")]
+    pub plan: PathBuf,
+
+    /// Apply the plan only if its hash (logged by `letitgo plan`) is this one
+    #[arg(long, value_name = "HEX")]
+    pub sha256: Option<String>,
 }
 
 #[derive(Debug, Args)]
@@ -57,15 +257,76 @@ pub struct ListArgs {
     pub json: bool,
 
     /// Show only paths that no longer exist on disk
-    #[arg(long)]
+    #[arg(long, conflicts_with = "quarantined")]
     pub stale: bool,
+
+    /// Show only the paths skipped after repeated failures, with their
+    /// failure counts
+    #[arg(long, conflicts_with_all = ["format", "sort"])]
+    pub quarantined: bool,
+
+    /// `table`: aligned columns with each path's repo, origin, size and
+    /// the date it was added
+    #[arg(long, value_enum, default_value = "plain", conflicts_with = "json")]
+    pub format: ListFormat,
+
+    /// Order of the paths (default: as cached)
+    #[arg(long, value_enum)]
+    pub sort: Option<ListSort>,
+
+    /// Replace the directories of each path with a short hash
+    /// (`3f2a9c1b/node_modules`), for sharing output from a private machine
+    #[arg(long)]
+    pub redact: bool,
 }
 
 #[derive(Debug, Args)]
-pub struct ResetArgs {
-    /// Skip confirmation prompt
+pub struct QueryArgs {
+    /// The filter: comparisons such as `size > 1GB`, `origin == preset` or
+    /// `path ~ "*/node_modules"`, combined with `and`, `or`, `not` and
+    /// parentheses. Fields: path, repo, origin, preset, mode, size,
+    /// failures, stale, trashed, quarantined, collapsed
+    #[arg(value_name = "EXPR")]
+    pub expr: String,
+
+    /// Output as JSON
     #[arg(long)]
-    pub yes: bool,
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct CleanArgs {
+    /// List the stale paths by repository and ask before removing each
+    /// group (keep e.g. the paths of an unplugged volume)
+    #[arg(short, long)]
+    pub interactive: bool,
+    /// Also remove the paths on volumes that are not mounted, instead of
+    /// keeping them until the volume is plugged in again
+    #[arg(long)]
+    pub include_unmounted: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Configuration format, or inventory, to write
+    #[arg(long, value_enum)]
+    pub format: ExportFormat,
+
+    /// Write to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// Show each directory of an inventory as a short hash, as `list
+    /// --redact` does
+    #[arg(long)]
+    pub redact: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct SchemaArgs {
+    /// cache, plan, report (the `report_path` file) or list (`list --json`)
+    #[arg(value_enum)]
+    pub name: SchemaName,
 }
 
 #[derive(Debug, Args)]
@@ -74,3 +335,248 @@ pub struct InitArgs {
     #[arg(long)]
     pub force: bool,
 }
+
+#[derive(Debug, Args)]
+pub struct ExplainArgs {
+    /// Path to explain
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct TestGlobArgs {
+    /// Whitelist glob, or `.gitignore` / `.lignore` line with --kind gitignore
+    pub pattern: String,
+
+    /// Paths to test; the cached exclusions if none
+    pub paths: Vec<PathBuf>,
+
+    /// How to read the pattern
+    #[arg(long, value_enum, default_value = "whitelist")]
+    pub kind: PatternKind,
+
+    /// Directory an ignore pattern is relative to (default: the repository
+    /// holding each path)
+    #[arg(long, value_name = "DIR")]
+    pub base: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ReposArgs {
+    /// Show only the repository containing this path
+    #[arg(value_name = "PATH")]
+    pub path: Option<PathBuf>,
+
+    /// Output as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct StatsArgs {
+    /// Output as JSON
+    #[arg(long)]
+    pub json: bool,
+
+    /// Do not look up the latest Time Machine backup
+    #[arg(long)]
+    pub no_backup: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct AnalyzeBackupArgs {
+    /// Backups to analyze, e.g. `/Volumes/<disk>/<date>.backup`
+    #[arg(required = true, value_name = "BACKUP")]
+    pub backups: Vec<PathBuf>,
+
+    /// Repositories to list per backup
+    #[arg(long, default_value_t = 10)]
+    pub top: usize,
+
+    /// Output as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct PurgeBackupsArgs {
+    /// Backups to purge (default: every backup but the latest)
+    #[arg(value_name = "BACKUP")]
+    pub backups: Vec<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    /// Compare the cache with a dry-run resolution instead of the previous
+    /// snapshot
+    #[arg(long)]
+    pub fresh: bool,
+
+    /// Output as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CacheCommand {
+    /// List the cache snapshots, oldest first
+    List(JsonArgs),
+
+    /// Snapshot the current cache now
+    Snapshot,
+
+    /// Restore the exclusions recorded in a snapshot
+    Restore(RestoreArgs),
+
+    /// Compact the cache and prune old or duplicate snapshots
+    Gc(GcArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct RestoreArgs {
+    /// Snapshot name from `cache list` (or its timestamp, or a file path)
+    pub snapshot: String,
+}
+
+#[derive(Debug, Args)]
+pub struct GcArgs {
+    /// Delete snapshots older than DURATION (e.g. 12h, 30d); the newest is kept
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "30d")]
+    pub retention: Duration,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PolicyCommand {
+    /// Show the cached policy, when it was fetched and what it sets
+    Show(JsonArgs),
+
+    /// Fetch the policy now, even if the cached copy is fresh
+    Refresh,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ServiceCommand {
+    /// Repeat `run` every `service_interval` until SIGTERM or SIGINT
+    Run(RunArgs),
+
+    /// Ask the running service for a run soon (requests are coalesced)
+    Trigger,
+
+    /// Show the running service's last run and next run; exits non-zero
+    /// when it is stopped, failing or overdue
+    Status(JsonArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum HelperCommand {
+    /// Install and load the helper daemon (run with sudo)
+    Install(HelperInstallArgs),
+
+    /// Unload the helper daemon and delete its files (run with sudo)
+    Uninstall,
+
+    /// Check that the helper answers and accepts this user
+    Status,
+
+    /// Serve helper requests (what the installed daemon runs)
+    #[command(hide = true)]
+    Serve(HelperServeArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct HelperInstallArgs {
+    /// User allowed to use the helper [default: the user who ran sudo]
+    #[arg(long)]
+    pub uid: Option<u32>,
+}
+
+#[derive(Debug, Args)]
+pub struct HelperServeArgs {
+    /// User allowed to use the helper (repeatable)
+    #[arg(long = "allow-uid", required = true)]
+    pub allow_uids: Vec<u32>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TmCommand {
+    /// Thin local APFS snapshots to reclaim space (`tmutil thinlocalsnapshots`)
+    ThinLocalSnapshots(ThinArgs),
+
+    /// Show the configured backup destinations (`tmutil destinationinfo`)
+    ListDestinations(JsonArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ThinArgs {
+    /// Volume whose snapshots are thinned
+    #[arg(default_value = "/")]
+    pub mount_point: PathBuf,
+
+    /// Bytes to try to reclaim (default: as many as Time Machine allows)
+    #[arg(long, value_name = "BYTES")]
+    pub purge_amount: Option<u64>,
+
+    /// Urgency from 1 (low) to 4 (high); requires --purge-amount
+    #[arg(long, requires = "purge_amount", value_parser = clap::value_parser!(u8).range(1..=4))]
+    pub urgency: Option<u8>,
+
+    /// Output as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct SimulateArgs {
+    /// Config file to simulate instead of the current one (`--config`)
+    #[arg(value_name = "CONFIG")]
+    pub alt_config: Option<PathBuf>,
+
+    /// Override a setting, as in the config file (repeatable), e.g.
+    /// `--set 'whitelist=[]'` or `--set max_exclusions=100`
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+
+    /// Output as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct JsonArgs {
+    /// Output as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DoctorArgs {
+    /// Output as JSON
+    #[arg(long)]
+    pub json: bool,
+
+    /// Make the config, the cache and letitgo's other files private to you
+    /// (0600, directories 0700) before checking
+    #[arg(long)]
+    pub fix_permissions: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum LignoreCommand {
+    /// Exclude paths matching PATTERN even if they are not gitignored
+    Add(LignoreArgs),
+
+    /// Keep the gitignored path PATTERN in backups (`!PATTERN`)
+    Negate(LignoreArgs),
+
+    /// Delete the line PATTERN (e.g. `data/` or `!target/`)
+    Remove(LignoreArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct LignoreArgs {
+    /// The pattern, relative to the repository root
+    pub pattern: String,
+
+    /// Repository to edit (default: the one containing the current directory)
+    #[arg(long, value_name = "DIR")]
+    pub repo: Option<PathBuf>,
+}
@@ -1,11 +1,38 @@
 use anyhow::{Context, Result};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    artifacts::{DEFAULT_SYSTEM_ARTIFACTS, Preset},
+    error::{ErrorKind, FileContext},
+    scanner::ScanOptions,
+    service::RunWindow,
+    throttle::NiceMode,
+    walk::{WalkBackend, Walker},
+};
 
 /// The default config file contents, written by `letitgo init`.
 pub const DEFAULT_CONFIG: &str = r#"# letitgo configuration
 # Location: ~/.config/letitgo/config.toml
 
+# Other config files to merge beneath this one, e.g. a team-wide base.
+# Lists append (rule_order is replaced), other values set here override
+# the included ones. Paths support `~` and are relative to this file.
+# include = ["~/.config/letitgo/work.toml"]
+
+# Managed policy published by your organization: a TOML fragment that may
+# only set whitelist, ignored_paths and protected_globs. It is fetched with
+# curl, cached next to the cache file, refetched after
+# policy_refresh_interval and merged beneath everything above. Pin it with
+# its SHA-256 to refuse any other content. See `letitgo policy show`.
+# policy_url = "https://it.example.com/letitgo/policy.toml"
+# policy_sha256 = "<hex digest>"
+# policy_refresh_interval = "1d"
+
 # Directories to scan for Git repos
 search_paths = ["~"]
 
@@ -20,6 +47,33 @@ ignored_paths = [
     "~/Pictures",
 ]
 
+# Follow symlinks at the top level of each search path: a search path that is
+# itself a symlink, and directory symlinks directly inside one (a "symlink
+# farm" like ~/projects/foo -> /Volumes/Work/foo) are scanned at their
+# targets. Links deeper down and inside repos are never followed; targets
+# that overlap another search root are skipped.
+follow_symlinked_search_paths = false
+
+# How directory trees are walked. Every walk yields hidden entries, follows
+# no symlink below its root and treats ignore files as data; the backend
+# only changes how the repo scan walks each search path:
+#   "ignore"  — in parallel, on all cores (default)
+#   "walkdir" — on one thread
+walk_backend = "ignore"
+# Do not descend into other file systems mounted inside a search path or a
+# repo (e.g. a disk image or network share mounted under ~/src).
+walk_same_file_system = false
+
+# Hold back when the machine is busy or hot, so a scan does not get in the
+# way. The load average (and, on macOS, the thermal pressure) is checked
+# every second while repos are resolved:
+#   "off"        — use every core regardless (default)
+#   "polite"     — when every core is busy, resolve repos on half the cores;
+#                  at heavy thermal pressure, one at a time
+#   "aggressive" — from half the cores busy, one repo at a time; at moderate
+#                  thermal pressure, pause between repos (up to 5 minutes)
+nice_mode = "off"
+
 # Glob patterns for paths to always include in backups (whitelist).
 # Paths matching these globs will NOT be excluded from Time Machine,
 # even if they are matched by .gitignore.
@@ -27,7 +81,49 @@ whitelist = [
     "**/application.yml",
 ]
 
-# Exclusion mode: "sticky" (default) or "fixed-path"
+# When a whitelist glob matches a path that a `.lignore` explicitly added,
+# which one wins: "whitelist" (keep it in backups, default) or "lignore".
+# Either way the conflict is reported during `run` and `explain`.
+lignore_vs_whitelist = "whitelist"
+
+# Order in which rule layers are applied; later layers override earlier ones.
+#   gitignore — .gitignore, .git/info/exclude, core.excludesFile (adds paths)
+#   lignore   — .lignore additions and `!` negations
+#   manual    — exclude_git_dir, extra_repo_relative_excludes and
+#               exclude_extensions (adds paths)
+#   whitelist — the whitelist above (removes paths)
+# Leave a layer out to disable it.
+rule_order = ["gitignore", "lignore", "manual", "whitelist"]
+
+# Where exclusions are recorded:
+#   "time-machine"  — Time Machine, as set by exclusion_mode below (macOS
+#                     only; the default there)
+#   "cachedir-tag"  — a CACHEDIR.TAG file in each excluded directory, skipped
+#                     by `borg`/`restic`/`tar --exclude-caches` (the default
+#                     elsewhere). Files cannot be tagged and stay backed up;
+#                     tags letitgo did not write are left alone.
+#   "excludes-file" — the excluded paths, one per line, in excludes_file,
+#                     for `restic --exclude-file`, `borg --exclude-from` or
+#                     `rsync --exclude-from`
+# backend = "time-machine"
+excludes_file = "~/.config/letitgo/excludes.txt"
+
+# Syntax of excludes_file, for the tool that reads it:
+#   "restic" — one path per line, `$` doubled (`restic backup --exclude-file`)
+#   "borg"   — `pp:/path` patterns (`borg create --exclude-from`)
+#   "rsync"  — `- /path` rules anchored at the transfer root, so sync from
+#              `/` (`rsync --exclude-from`)
+# The whole file is rewritten (atomically) whenever its content would change.
+excludes_file_format = "restic"
+
+# Also stop excluded paths from syncing, so "don't back up" means "don't
+# sync" too (opt-in). Paths inside a Syncthing folder (one with a
+# .stfolder marker) are listed in a managed block of that folder's
+# .stignore; paths inside a Dropbox folder get the com.dropbox.ignored
+# attribute. Removing an exclusion removes the entry or attribute again.
+sync_ignore = false
+
+# Exclusion mode (time-machine backend): "sticky" (default) or "fixed-path"
 #
 # sticky:     Sets an extended attribute on each item. No sudo needed.
 #             Exclusion follows the file if moved, but is lost if the
@@ -37,16 +133,290 @@ whitelist = [
 #             sudo (run as root). Survives deletion; re-applies when a
 #             new item appears at the same path.
 #
-# IMPORTANT: run `letitgo reset` before switching modes.
+# Switching modes migrates the exclusions on the next `run`: each one is
+# added in the new mode before it is removed in the old one.
 exclusion_mode = "sticky"
+
+# Exclusion mode per path class: the first rule whose glob matches an
+# excluded path picks its mode; other paths use `exclusion_mode`. E.g.
+# fixed-path for directories that build tools delete and recreate, sticky
+# for everything else. Editing the rules migrates paths like a mode switch.
+mode_rules = [
+#   { glob = "**/{target,node_modules}", mode = "fixed-path" },
+]
+
+# Sticky mode: threads used to set/remove exclusion xattrs in parallel.
+# 0 (default) uses one per CPU; lower it to reduce load on slow or network
+# volumes. Throughput is logged with -v.
+xattr_concurrency = 0
+
+# Resolve repos while discovery is still walking the search paths instead of
+# after it finishes, overlapping disk IO with matching. Can cut wall time on
+# large home directories; the result is identical either way.
+pipeline_discovery = false
+
+# A repo whose index tracks at least this many files (a monorepo) has its
+# .gitignore rules resolved by a walk on all cores rather than on one; the
+# result is identical either way. 0 walks every repo on one core.
+parallel_walk_min_files = 100000
+
+# Fixed-path mode only: "tmutil" (default) spawns `tmutil -p` per path;
+# "plist" edits SkipPaths in /Library/Preferences/com.apple.TimeMachine.plist
+# through `defaults import` — one locked write per run instead of thousands
+# of subprocesses. Before each edit the plist is copied to
+# `com.apple.TimeMachine.plist.letitgo-backup-<time>` (the newest 10 kept).
+# Both require sudo like fixed-path mode itself.
+# "helper" sends the changes to the daemon set up once by
+# `sudo letitgo privileged-helper install`, so runs (and the service) need
+# no sudo; it only changes paths owned by the installing user.
+fixed_path_backend = "tmutil"
+
+# The `tmutil` to run for fixed-path exclusions and `isexcluded` checks
+# instead of /usr/bin/tmutil, e.g. a fake script implementing its CLI to
+# test letitgo on a host without Time Machine. The LETITGO_TMUTIL
+# environment variable overrides it (and also applies to `tm` commands).
+# tmutil_binary_path = "/usr/bin/tmutil"
+
+# How fixed-path changes are passed to `tmutil`: tmutil_batch_size paths
+# per subprocess, killed after tmutil_batch_timeout. The paths of a batch
+# that timed out are retried one by one with tmutil_retry_timeout; those
+# that time out again are skipped with a warning.
+#
+# With tmutil_adaptive_batching the size starts at tmutil_batch_size (or
+# the size the last run settled on, kept in the cache), doubles after each
+# full batch that took under a quarter of tmutil_batch_timeout, up to
+# tmutil_max_batch_size, and halves after a timeout.
+tmutil_batch_size = 1
+tmutil_adaptive_batching = true
+tmutil_max_batch_size = 64
+tmutil_batch_timeout = "10s"
+tmutil_retry_timeout = "5s"
+
+# A path whose exclusion fails or times out on quarantine_after runs in a
+# row (e.g. on a FUSE mount) is quarantined: runs skip it, except for one
+# attempt every quarantine_reprobe_interval, until an attempt succeeds.
+# List them with `letitgo list --quarantined`. 0 never quarantines.
+quarantine_after = 3
+quarantine_reprobe_interval = "7d"
+
+# Cached paths that now live in the Trash (~/.Trash, a volume's .Trashes)
+# still exist, yet vanish when the Trash is emptied. `letitgo clean`:
+#   "remove" — removes their exclusions like those of deleted paths (default)
+#   "keep"   — keeps them excluded, tagged [trashed] in `list`, until they
+#              are gone
+trashed_paths = "remove"
+
+# Sticky mode only: on every run, check that each cached path that still
+# exists carries the exclusion xattr and re-set it if not. A directory that
+# was deleted and recreated (e.g. `target/` after `cargo clean`) otherwise
+# stays backed up until its ignore rules change. Costs one xattr read per
+# cached path; set to false to trust the cache.
+reassert = true
+
+# Also look for bare repositories (`foo.git/` with HEAD, objects/, refs/)
+# and exclude the listed sub-paths of each one (relative to the bare repo).
+detect_bare_repos = false
+bare_repo_excludes = ["objects"]
+
+# Exclude each repository's own `.git` directory:
+#   "never" (default), "if-remote-exists" (only when a remote is configured,
+#   so the history can be re-cloned), or "always".
+exclude_git_dir = "never"
+
+# Repos whose ignored data might be the only copy — no remote configured, or
+# (with require_pushed_head = true) HEAD not yet pushed to any remote, or
+# tracked files modified since they were last staged:
+#   "normal"       — treat like any other repo (default)
+#   "conservative" — exclude ignored directories only; keep ignored files and .git
+#   "skip"         — exclude nothing inside such repos
+unbacked_repo_policy = "normal"
+require_pushed_head = false
+
+# Extra paths, relative to each repo root, to exclude when they exist —
+# typically reproducible object stores. Entries under `.git/` resolve to the
+# shared gitdir, so worktrees use the main repository's store.
+# Example: [".git/lfs/objects"]
+extra_repo_relative_excludes = []
+
+# Exclude files with these extensions (case-insensitive, without the dot)
+# inside repos, e.g. ["iso", "dmg", "mp4"], without editing each repo's
+# ignore files. exclude_extensions_scope picks the candidates:
+#   "untracked" — only files Git does not track: ignored ones, and new ones
+#                 no `.gitignore` names yet (default)
+#   "all"       — every matching file, tracked ones included
+# Part of the `manual` rule layer; costs an extra walk of each repo.
+exclude_extensions = []
+exclude_extensions_scope = "untracked"
+
+# Opt-in safety scan: never exclude an ignored directory (or file) that
+# contains a file whose name matches one of `protected_globs`. The blocking
+# file is reported in the log. Costs an extra walk of each ignored directory.
+safety_scan = false
+protected_globs = ["*.key", "*.pem", "*.sqlite", "*.sqlite3", "*.pst", "*.kdbx"]
+
+# Safety rails: a search root, the home directory and any repo root (or a
+# directory above one) are never excluded. Repo paths with fewer components
+# than this — e.g. `/Users/alice/Documents` has 3 — are only excluded with
+# `letitgo run --force`, guarding against rules like `/*` in a repo at `~`.
+# Presets and system_artifacts are exempt. 0 disables the depth check.
+min_exclusion_depth = 4
+
+# A single new exclusion at least this large may be data you actually want
+# backed up. Such paths are listed prominently; `run` asks before excluding
+# them (or needs `--yes-large` when not interactive) and skips them otherwise.
+# Units: KB, MB, GB, TB (or KiB … TiB). 0 disables the check.
+large_exclusion_threshold = "50GB"
+
+# Upper bound on the number of exclusions per run (0 = unlimited). When more
+# paths qualify, the largest ones (by size on disk) are kept and the rest are
+# logged and left in backups — protects huge monorepos from creating hundreds
+# of thousands of xattrs.
+max_exclusions = 0
+
+# Resolve, diff and apply one repository (with the repos nested in it) at a
+# time instead of building one exclusion set for the whole machine, bounding
+# memory when file-level patterns in huge monorepos yield millions of paths.
+# Ignored while max_exclusions is set; replaces pipeline_discovery, and the
+# large-exclusion prompt comes once per repository.
+streaming_apply = false
+
+# Opt-in: when at least this percentage of a directory's entries is excluded
+# (e.g. thousands of `*.log` files), exclude the directory itself instead of
+# each entry. Below 100 the remaining entries stop being backed up too; they
+# are listed in the cache under `collapsed`. A directory is never collapsed
+# when that would exclude a whitelisted path, a protected file or a nested
+# repo. 0 (default) disables collapsing.
+collapse_threshold = 0
+
+# Files a .gitignore or exclude file matches that are smaller than this many
+# kilobytes (1 KB = 1000 bytes) stay in backups: excluding them saves next to
+# nothing, and each is one more exclusion to write and keep. Directories,
+# and paths .lignore files or this config add, are excluded whatever their
+# size. Pairs with large_exclusion_threshold, which asks before excluding
+# large directories. 0 (default) excludes every matched file.
+min_file_size_kb = 0
+
+# Opt-in: a directory a repo ignores (e.g. `vendor/`) may hold checkouts of
+# other repos. Such a directory is never excluded whole, so by default it
+# stays in backups. With this on, everything in it is excluded except the
+# nested repos, which are resolved by their own ignore rules.
+descend_into_excluded = false
+
+# Opt-in: exclude such a directory whole, nested repos and all, without
+# resolving them (a vendored checkout's own rules are not read). Takes
+# precedence over descend_into_excluded. A repo inside a preset or system
+# artifact location is never resolved, whatever this says.
+skip_covered_repos = false
+
+# Opt-in: also exclude large, reproducible VM/container images outside repos.
+# RISK: anything stored *inside* these images is no longer backed up — e.g.
+# Docker volumes holding local databases, or files saved inside a VM. Only
+# enable this if that data is disposable or backed up another way.
+# Entries support `~` and a glob in the last component.
+exclude_system_artifacts = false
+system_artifacts = [
+    "~/Library/Containers/com.docker.docker/Data/vms",
+    "~/.orbstack",
+    "~/Parallels/*.pvm",
+]
+
+# Built-in package-manager cache presets to exclude. Available:
+# "cargo", "npm", "pip", "gradle", "homebrew"
+presets = []
+
+# `letitgo run --fast` re-resolves the repos found by the last full scan
+# instead of walking search_paths again. After this long, --fast does a full
+# discovery anyway so new repos are picked up. Units: s, m, h, d, w.
+full_rediscovery_interval = "7d"
+
+# `letitgo service run` runs every service_interval, plus a random delay of
+# up to service_jitter (also before the first run). After consecutive
+# failures the pause doubles each time, up to a day.
+service_interval = "1h"
+service_jitter = "5m"
+
+# `letitgo service trigger` (e.g. from a git hook) asks the service for an
+# earlier run. Requests within service_debounce of the first one, or made
+# while a run is in progress, are served by a single run. A `letitgo run`
+# that finds the lock held also queues one follow-up run with the service.
+service_debounce = "30s"
+
+# When service_windows lists any, `letitgo service run` only starts runs of
+# its own inside them, deferring scheduled and triggered runs to the next
+# opening: "<days> HH:MM-HH:MM", the days being daily, weekdays, weekends or
+# day names, ranges and lists (Mon-Fri, Sat,Sun). A window may run past
+# midnight ("daily 22:00-06:00"). Runs in progress are never cut short.
+# A top-level key like the other service_* settings, not a [schedule] table.
+# service_windows = ["Mon-Fri 12:00-14:00", "daily 02:00-05:00"]
+service_windows = []
+
+# Serve the service's health as JSON on http://<addr>/health while
+# `service run` is up: 200 when healthy, 503 when the last run failed or a
+# run is overdue. Loopback addresses only. `letitgo service status` reports
+# the same from the status file, without the endpoint.
+# service_health_addr = "127.0.0.1:9184"
+
+# Answer companion tools (a menu bar app, say) on service.sock next to the
+# cache while `service run` is up: one JSON command per line — {"op":
+# "status"}, {"op": "list"}, {"op": "plan"} or {"op": "run"} — each answered
+# with a line {"result": ...} or {"error": "..."}. Runs and plans take turns
+# with the scheduled ones. Only your user may connect.
+service_socket = false
+
+# Number of earlier caches to keep (in `snapshots/` next to the cache file).
+# A snapshot is taken before the exclusion set changes (run, clean, reset,
+# cache restore), so `letitgo diff` can show what the last change did and
+# `letitgo cache restore` can undo it. 0 disables snapshots.
+cache_snapshots = 10
+
+# On-disk encoding of the cache: "json" (default, readable) or "binary" (a
+# binary plist — smaller and much faster to load once the cache holds
+# hundreds of thousands of paths). The format is detected when reading, so
+# switching needs no migration; the file keeps its name.
+cache_format = "json"
+
+# Encrypt the cache and its snapshots, which otherwise list every project
+# path on the machine in plain text. The key is kept in the login keychain
+# on macOS (in a private file under ~/.local/share/letitgo elsewhere), so
+# other users and backups of the cache see only ciphertext. Turning it on or
+# off takes effect at the next write; `letitgo list --redact` hides paths in
+# output instead.
+cache_encryption = false
+
+# Skip the lock that keeps two letitgo instances from changing exclusions
+# at once — only for containers, tests or network home directories where
+# flock on the cache directory misbehaves. Every locking command then logs a
+# warning. Same as the global `--no-lock` flag.
+no_lock = false
+
+# Answer yes to every confirmation prompt: `reset`, `purge-backups` and
+# large exclusions in `run`. Same as `--yes`.
+assume_yes = false
+
+# Never wait for an answer: every prompt is answered no, as it already is
+# when stdin is not a terminal (pipes, cron, the service). `assume_yes` wins.
+# Same as `--non-interactive`.
+non_interactive = false
+
+# Write a JSON summary of every run (start/end time, durations, counts,
+# version, errors) to this file, e.g. for an MDM agent to collect. It is only
+# written locally; letitgo sends nothing over the network. Supports `~`.
+# report_path = "~/Library/Logs/letitgo/last-run.json"
+
+# Language of prompts, `list` and run summaries ("en", "ja", ...). Unset =
+# from LC_ALL / LC_MESSAGES / LANG. A catalog in
+# ~/.config/letitgo/locales/<language>.toml (next to this file) overrides
+# the built-in messages or adds a language; the keys are in locales/en.toml
+# of the letitgo source.
+# language = "ja"
 "#;
 
 /// How Time Machine exclusions are applied to the filesystem.
 ///
 /// The two modes differ in where the exclusion metadata is stored and whether
-/// `sudo` is required.  Run `letitgo reset` before switching modes to clear
-/// exclusions set with the previous method.
-#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+/// `sudo` is required.  After a switch, `run` migrates the cached exclusions
+/// to the new method.  `mode_rules` can pick another mode per path.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub enum ExclusionMode {
     /// Sets an extended attribute (`com.apple.metadata:com_apple_backup_excludeItem`)
@@ -71,6 +441,14 @@ impl ExclusionMode {
     }
 }
 
+/// A `mode_rules` entry: paths matching `glob` are excluded in `mode`
+/// (see [`ModeSelector`](crate::modes::ModeSelector)).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ModeRule {
+    pub glob: String,
+    pub mode: ExclusionMode,
+}
+
 impl std::fmt::Display for ExclusionMode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -80,6 +458,197 @@ impl std::fmt::Display for ExclusionMode {
     }
 }
 
+/// Whether a repository's own `.git` directory is excluded from backups.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExcludeGitDir {
+    /// Always back up `.git` (the default).
+    #[default]
+    Never,
+    /// Exclude `.git` only when at least one remote with a URL is configured,
+    /// so the history can be recovered by cloning again.
+    IfRemoteExists,
+    /// Exclude every `.git` directory unconditionally.
+    Always,
+}
+
+/// How to treat repositories that are not backed by a remote.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnbackedRepoPolicy {
+    /// Apply every exclusion as for any other repository (the default).
+    #[default]
+    Normal,
+    /// Only exclude ignored directories (build output, dependency trees);
+    /// ignored files and the `.git` directory stay in backups.
+    Conservative,
+    /// Exclude nothing inside the repository.
+    Skip,
+}
+
+impl std::fmt::Display for ExcludeGitDir {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExcludeGitDir::Never => write!(f, "never"),
+            ExcludeGitDir::IfRemoteExists => write!(f, "if-remote-exists"),
+            ExcludeGitDir::Always => write!(f, "always"),
+        }
+    }
+}
+
+/// The files of a repository that `exclude_extensions` applies to.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExtensionScope {
+    /// Files the index does not track (the default): tracked files are part
+    /// of the project and stay in backups.
+    #[default]
+    Untracked,
+    /// Every file, tracked or not.
+    All,
+}
+
+/// A layer of exclusion rules; see [`Config::rule_order`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuleLayer {
+    /// `.gitignore`, `info/exclude` and `core.excludesFile` matches.
+    Gitignore,
+    /// `.lignore` additions and negations.
+    Lignore,
+    /// The config `whitelist` (removes paths).
+    Whitelist,
+    /// Paths declared in config: `exclude_git_dir`, `extra_repo_relative_excludes`,
+    /// `exclude_extensions`.
+    Manual,
+}
+
+impl std::fmt::Display for RuleLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleLayer::Gitignore => write!(f, "gitignore"),
+            RuleLayer::Lignore => write!(f, "lignore"),
+            RuleLayer::Whitelist => write!(f, "whitelist"),
+            RuleLayer::Manual => write!(f, "manual"),
+        }
+    }
+}
+
+/// What `clean` does with cached paths that were moved to the Trash.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TrashedPaths {
+    /// Remove their exclusions like those of deleted paths (the default).
+    #[default]
+    Remove,
+    /// Keep them excluded until they disappear with the emptied Trash.
+    Keep,
+}
+
+/// Which layer wins when a whitelist glob matches a path a `.lignore` added.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictWinner {
+    /// Keep the path in backups (the default, and the safer choice).
+    #[default]
+    Whitelist,
+    /// Exclude the path as the `.lignore` asked.
+    Lignore,
+}
+
+impl std::fmt::Display for ConflictWinner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConflictWinner::Whitelist => write!(f, "whitelist"),
+            ConflictWinner::Lignore => write!(f, "lignore"),
+        }
+    }
+}
+
+/// On-disk encoding of the exclusion cache.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CacheFormat {
+    /// Pretty-printed JSON.
+    #[default]
+    Json,
+    /// Binary property list, for caches too large to parse quickly as JSON.
+    Binary,
+}
+
+/// Where exclusions are recorded.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// Time Machine, as set by `exclusion_mode` (macOS only).
+    TimeMachine,
+    /// A `CACHEDIR.TAG` in each excluded directory, for backup tools run
+    /// with `--exclude-caches`.
+    CachedirTag,
+    /// The paths listed in `excludes_file`, for backup tools that read an
+    /// exclude list.
+    ExcludesFile,
+}
+
+impl Default for Backend {
+    /// Time Machine on macOS, cache directory tags elsewhere.
+    fn default() -> Self {
+        if cfg!(target_os = "macos") {
+            Backend::TimeMachine
+        } else {
+            Backend::CachedirTag
+        }
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::TimeMachine => write!(f, "time-machine"),
+            Backend::CachedirTag => write!(f, "cachedir-tag"),
+            Backend::ExcludesFile => write!(f, "excludes-file"),
+        }
+    }
+}
+
+/// Syntax of the file written by the `excludes-file` backend.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExcludesFormat {
+    /// One path per line, glob characters escaped (`restic --exclude-file`).
+    #[default]
+    Restic,
+    /// `pp:` path-prefix patterns (`borg create --exclude-from`).
+    Borg,
+    /// `- ` exclude rules, glob characters escaped (`rsync --exclude-from`).
+    Rsync,
+}
+
+/// How fixed-path exclusions are registered.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FixedPathBackend {
+    /// One `tmutil addexclusion -p` / `removeexclusion -p` per path.
+    #[default]
+    Tmutil,
+    /// Edit `SkipPaths` in `/Library/Preferences/com.apple.TimeMachine.plist`
+    /// directly, in a single locked write per run.
+    Plist,
+    /// Ask the privileged helper daemon (`letitgo privileged-helper
+    /// install`) to run `tmutil -p`, so runs need no sudo.
+    Helper,
+}
+
+impl std::fmt::Display for FixedPathBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FixedPathBackend::Tmutil => write!(f, "tmutil"),
+            FixedPathBackend::Plist => write!(f, "plist"),
+            FixedPathBackend::Helper => write!(f, "helper"),
+        }
+    }
+}
+
 /// Runtime configuration loaded from `~/.config/letitgo/config.toml`.
 ///
 /// All fields have compile-time defaults that match [`DEFAULT_CONFIG`], so the
@@ -96,14 +665,327 @@ pub struct Config {
     #[serde(default = "default_ignored_paths")]
     pub ignored_paths: Vec<String>,
 
+    /// Follow symlinks at the top level of search paths (never inside repos).
+    #[serde(default)]
+    pub follow_symlinked_search_paths: bool,
+
+    /// The library the repo scan walks search paths with.
+    #[serde(default)]
+    pub walk_backend: WalkBackend,
+
+    /// Keep every walk on the file system it starts on.
+    #[serde(default)]
+    pub walk_same_file_system: bool,
+
+    /// How much a run holds back when the machine is busy or hot.
+    #[serde(default)]
+    pub nice_mode: NiceMode,
+
     /// Glob patterns for paths that should never be excluded from Time Machine,
     /// even if they are matched by `.gitignore` (e.g. `**/.env`).
     #[serde(default = "default_whitelist")]
     pub whitelist: Vec<String>,
 
+    /// Which layer wins when the whitelist matches a `.lignore` addition.
+    #[serde(default)]
+    pub lignore_vs_whitelist: ConflictWinner,
+
+    /// Rule layers in the order they are applied; later layers override
+    /// earlier ones and omitted layers are disabled.
+    #[serde(default = "default_rule_order")]
+    pub rule_order: Vec<RuleLayer>,
+
+    /// Where exclusions are recorded — see [`Backend`].
+    #[serde(default)]
+    pub backend: Backend,
+
+    /// The file the `excludes-file` backend maintains (supports `~`).
+    #[serde(default = "default_excludes_file")]
+    pub excludes_file: String,
+
+    /// Syntax of `excludes_file` — see [`ExcludesFormat`].
+    #[serde(default)]
+    pub excludes_file_format: ExcludesFormat,
+
+    /// Also keep excluded paths out of Syncthing and Dropbox folders that
+    /// contain them — see [`SyncIgnoreManager`](crate::sync_ignore::SyncIgnoreManager).
+    #[serde(default)]
+    pub sync_ignore: bool,
+
     /// How to register exclusions with Time Machine — see [`ExclusionMode`].
     #[serde(default)]
     pub exclusion_mode: ExclusionMode,
+
+    /// Per-path overrides of `exclusion_mode` — see [`ModeRule`].
+    #[serde(default)]
+    pub mode_rules: Vec<ModeRule>,
+
+    /// Threads used to set/remove sticky-mode xattrs (`0` = one per CPU).
+    #[serde(default)]
+    pub xattr_concurrency: usize,
+
+    /// Stream discovered repos into resolver workers instead of resolving
+    /// after discovery completes.
+    #[serde(default)]
+    pub pipeline_discovery: bool,
+
+    /// Tracked files from which a repo's gitignore layer is walked on all
+    /// cores (`0` = never).
+    #[serde(default = "default_parallel_walk_min_files")]
+    pub parallel_walk_min_files: u64,
+
+    /// How fixed-path exclusions are applied — see [`FixedPathBackend`].
+    #[serde(default)]
+    pub fixed_path_backend: FixedPathBackend,
+
+    /// The `tmutil` to run instead of `/usr/bin/tmutil` (`~` expanded);
+    /// `$LETITGO_TMUTIL` overrides it.
+    #[serde(default)]
+    pub tmutil_binary_path: Option<String>,
+
+    /// Paths per `tmutil` subprocess (fixed-path mode), or where adaptive
+    /// batching starts; `0` counts as 1.
+    #[serde(default = "default_tmutil_batch_size")]
+    pub tmutil_batch_size: usize,
+
+    /// Grow the batch size while `tmutil` is fast and halve it on timeouts,
+    /// remembering it in the cache.
+    #[serde(default = "default_tmutil_adaptive_batching")]
+    pub tmutil_adaptive_batching: bool,
+
+    /// Largest batch size adaptive batching grows to.
+    #[serde(default = "default_tmutil_max_batch_size")]
+    pub tmutil_max_batch_size: usize,
+
+    /// How long one `tmutil` subprocess may take before it is killed.
+    #[serde(
+        default = "default_tmutil_batch_timeout",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub tmutil_batch_timeout: Duration,
+
+    /// Timeout for each path of a timed-out batch, retried on its own.
+    #[serde(
+        default = "default_tmutil_retry_timeout",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub tmutil_retry_timeout: Duration,
+
+    /// Consecutive failed runs after which a path is quarantined; `0`
+    /// disables the quarantine.
+    #[serde(default = "default_quarantine_after")]
+    pub quarantine_after: u32,
+
+    /// How often a quarantined path is tried again.
+    #[serde(
+        default = "default_quarantine_reprobe_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub quarantine_reprobe_interval: Duration,
+
+    /// What `clean` does with cached paths moved to the Trash.
+    #[serde(default)]
+    pub trashed_paths: TrashedPaths,
+
+    /// Re-check cached sticky-mode exclusions on every run and re-apply the
+    /// ones whose xattr went missing.
+    #[serde(default = "default_reassert")]
+    pub reassert: bool,
+
+    /// Discover bare repositories during the scan (opt-in).
+    #[serde(default)]
+    pub detect_bare_repos: bool,
+
+    /// Paths relative to each bare repository that are excluded when
+    /// `detect_bare_repos` is on (e.g. `objects` holds all packs).
+    #[serde(default = "default_bare_repo_excludes")]
+    pub bare_repo_excludes: Vec<String>,
+
+    /// Policy for excluding each repository's `.git` directory.
+    #[serde(default)]
+    pub exclude_git_dir: ExcludeGitDir,
+
+    /// Policy for repositories with no remote (see [`UnbackedRepoPolicy`]).
+    #[serde(default)]
+    pub unbacked_repo_policy: UnbackedRepoPolicy,
+
+    /// Also treat repositories whose `HEAD` is not on any remote-tracking
+    /// branch, or whose tracked files were modified since staged, as
+    /// unbacked.
+    #[serde(default)]
+    pub require_pushed_head: bool,
+
+    /// Extra repo-relative paths to exclude when present (e.g.
+    /// `.git/lfs/objects`).  `.git/…` entries resolve to the shared gitdir.
+    #[serde(default)]
+    pub extra_repo_relative_excludes: Vec<String>,
+
+    /// File extensions to exclude inside repositories, without the dot.
+    #[serde(default)]
+    pub exclude_extensions: Vec<String>,
+
+    /// Which files `exclude_extensions` applies to.
+    #[serde(default)]
+    pub exclude_extensions_scope: ExtensionScope,
+
+    /// Refuse to exclude paths containing files that match `protected_globs`.
+    #[serde(default)]
+    pub safety_scan: bool,
+
+    /// File-name globs that block an exclusion when `safety_scan` is on.
+    #[serde(default = "default_protected_globs")]
+    pub protected_globs: Vec<String>,
+
+    /// Exclusions with fewer path components than this need `run --force`
+    /// (`0` = no limit).
+    #[serde(default = "default_min_exclusion_depth")]
+    pub min_exclusion_depth: usize,
+
+    /// New exclusions at least this large (bytes) need confirmation or
+    /// `run --yes-large` (`0` = no limit).
+    #[serde(
+        default = "default_large_exclusion_threshold",
+        deserialize_with = "deserialize_size"
+    )]
+    pub large_exclusion_threshold: u64,
+
+    /// Upper bound on the number of exclusions (`0` = unlimited); the largest
+    /// paths are kept.
+    #[serde(default)]
+    pub max_exclusions: usize,
+
+    /// Diff and apply exclusions per repo group instead of as one set.
+    #[serde(default)]
+    pub streaming_apply: bool,
+
+    /// Exclude a whole directory once this percentage of its entries is
+    /// excluded (`0` = off).
+    #[serde(default)]
+    pub collapse_threshold: u8,
+
+    /// Leave files ignore rules match in backups below this many kilobytes
+    /// (`0` = off).
+    #[serde(default)]
+    pub min_file_size_kb: u64,
+
+    /// Exclude the contents of an excluded directory around the repos nested
+    /// in it, instead of leaving it in backups.
+    #[serde(default)]
+    pub descend_into_excluded: bool,
+
+    /// Exclude a directory a repo ignores whole, leaving the repos nested in
+    /// it unresolved.
+    #[serde(default)]
+    pub skip_covered_repos: bool,
+
+    /// Exclude the `system_artifacts` locations (opt-in).
+    #[serde(default)]
+    pub exclude_system_artifacts: bool,
+
+    /// VM/container image locations outside repos (supports `~` and a glob in
+    /// the last component).
+    #[serde(default = "default_system_artifacts")]
+    pub system_artifacts: Vec<String>,
+
+    /// Package-manager cache presets to exclude (see [`Preset`]).
+    #[serde(default)]
+    pub presets: Vec<Preset>,
+
+    /// How long `run --fast` may reuse the cached repo list before doing a
+    /// full discovery again (e.g. `"7d"`).
+    #[serde(
+        default = "default_full_rediscovery_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub full_rediscovery_interval: Duration,
+
+    /// Pause between runs of `service run` (e.g. `"1h"`).
+    #[serde(
+        default = "default_service_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub service_interval: Duration,
+
+    /// Upper bound of the random delay `service run` adds to each pause.
+    #[serde(
+        default = "default_service_jitter",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub service_jitter: Duration,
+
+    /// How long `service run` collects run requests before acting on them.
+    #[serde(
+        default = "default_service_debounce",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub service_debounce: Duration,
+
+    /// When `service run` may start runs of its own (empty = any time).
+    #[serde(default)]
+    pub service_windows: Vec<RunWindow>,
+
+    /// Loopback `host:port` where `service run` serves `GET /health`
+    /// (unset = no endpoint).
+    #[serde(default)]
+    pub service_health_addr: Option<String>,
+
+    /// Answer [`service::Command`](crate::service::Command)s on the service
+    /// socket while `service run` is up.
+    #[serde(default)]
+    pub service_socket: bool,
+
+    /// Number of cache snapshots to keep (`0` = none).
+    #[serde(default = "default_cache_snapshots")]
+    pub cache_snapshots: usize,
+
+    /// How the cache is encoded on disk — see [`CacheFormat`].
+    #[serde(default)]
+    pub cache_format: CacheFormat,
+
+    /// Encrypt the cache with the user's cache key (see
+    /// [`crate::encryption`]).
+    #[serde(default)]
+    pub cache_encryption: bool,
+
+    /// Run mutating commands without taking the instance lock.
+    #[serde(default)]
+    pub no_lock: bool,
+
+    /// Answer yes to every confirmation prompt.
+    #[serde(default)]
+    pub assume_yes: bool,
+
+    /// Answer no to every confirmation prompt without reading stdin.
+    #[serde(default)]
+    pub non_interactive: bool,
+
+    /// Directory the whole run is confined to (the global `--root`); never
+    /// read from the config file.
+    #[serde(skip)]
+    pub root: Option<PathBuf>,
+
+    /// Where to write the post-run report (supports `~`; unset = none).
+    #[serde(default)]
+    pub report_path: Option<String>,
+
+    /// URL of a managed policy fragment merged beneath this config (opt-in).
+    #[serde(default)]
+    pub policy_url: Option<String>,
+
+    /// Hex SHA-256 the fetched policy must have.
+    #[serde(default)]
+    pub policy_sha256: Option<String>,
+
+    /// How long the cached policy is used before it is fetched again.
+    #[serde(
+        default = "default_policy_refresh_interval",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub policy_refresh_interval: Duration,
+
+    /// Language of user-facing messages (unset = from the locale).
+    #[serde(default)]
+    pub language: Option<String>,
 }
 
 impl Default for Config {
@@ -111,12 +993,97 @@ impl Default for Config {
         Config {
             search_paths: default_search_paths(),
             ignored_paths: default_ignored_paths(),
+            follow_symlinked_search_paths: false,
+            walk_backend: WalkBackend::Ignore,
+            walk_same_file_system: false,
+            nice_mode: NiceMode::Off,
             whitelist: default_whitelist(),
+            lignore_vs_whitelist: ConflictWinner::Whitelist,
+            rule_order: default_rule_order(),
+            backend: Backend::default(),
+            excludes_file: default_excludes_file(),
+            excludes_file_format: ExcludesFormat::Restic,
+            sync_ignore: false,
             exclusion_mode: ExclusionMode::Sticky,
+            mode_rules: Vec::new(),
+            xattr_concurrency: 0,
+            pipeline_discovery: false,
+            parallel_walk_min_files: default_parallel_walk_min_files(),
+            fixed_path_backend: FixedPathBackend::Tmutil,
+            tmutil_binary_path: None,
+            tmutil_batch_size: default_tmutil_batch_size(),
+            tmutil_adaptive_batching: true,
+            tmutil_max_batch_size: default_tmutil_max_batch_size(),
+            tmutil_batch_timeout: default_tmutil_batch_timeout(),
+            tmutil_retry_timeout: default_tmutil_retry_timeout(),
+            quarantine_after: default_quarantine_after(),
+            quarantine_reprobe_interval: default_quarantine_reprobe_interval(),
+            trashed_paths: TrashedPaths::Remove,
+            reassert: true,
+            detect_bare_repos: false,
+            bare_repo_excludes: default_bare_repo_excludes(),
+            exclude_git_dir: ExcludeGitDir::Never,
+            unbacked_repo_policy: UnbackedRepoPolicy::Normal,
+            require_pushed_head: false,
+            extra_repo_relative_excludes: Vec::new(),
+            exclude_extensions: Vec::new(),
+            exclude_extensions_scope: ExtensionScope::Untracked,
+            safety_scan: false,
+            protected_globs: default_protected_globs(),
+            min_exclusion_depth: default_min_exclusion_depth(),
+            large_exclusion_threshold: default_large_exclusion_threshold(),
+            max_exclusions: 0,
+            streaming_apply: false,
+            collapse_threshold: 0,
+            min_file_size_kb: 0,
+            descend_into_excluded: false,
+            skip_covered_repos: false,
+            exclude_system_artifacts: false,
+            system_artifacts: default_system_artifacts(),
+            presets: Vec::new(),
+            full_rediscovery_interval: default_full_rediscovery_interval(),
+            service_interval: default_service_interval(),
+            service_jitter: default_service_jitter(),
+            service_debounce: default_service_debounce(),
+            service_windows: Vec::new(),
+            service_health_addr: None,
+            service_socket: false,
+            cache_snapshots: default_cache_snapshots(),
+            cache_format: CacheFormat::Json,
+            cache_encryption: false,
+            no_lock: false,
+            assume_yes: false,
+            non_interactive: false,
+            root: None,
+            report_path: None,
+            policy_url: None,
+            policy_sha256: None,
+            policy_refresh_interval: default_policy_refresh_interval(),
+            language: None,
         }
     }
 }
 
+fn default_excludes_file() -> String {
+    "~/.config/letitgo/excludes.txt".to_string()
+}
+
+fn default_reassert() -> bool {
+    true
+}
+
+fn default_parallel_walk_min_files() -> u64 {
+    100_000
+}
+
+fn default_min_exclusion_depth() -> usize {
+    4
+}
+
+fn default_large_exclusion_threshold() -> u64 {
+    50_000_000_000
+}
+
 fn default_search_paths() -> Vec<String> {
     vec!["~".to_string()]
 }
@@ -140,32 +1107,373 @@ fn default_whitelist() -> Vec<String> {
     ]
 }
 
+fn default_rule_order() -> Vec<RuleLayer> {
+    vec![
+        RuleLayer::Gitignore,
+        RuleLayer::Lignore,
+        RuleLayer::Manual,
+        RuleLayer::Whitelist,
+    ]
+}
+
+fn default_bare_repo_excludes() -> Vec<String> {
+    vec!["objects".to_string()]
+}
+
+fn default_protected_globs() -> Vec<String> {
+    ["*.key", "*.pem", "*.sqlite", "*.sqlite3", "*.pst", "*.kdbx"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+fn default_full_rediscovery_interval() -> Duration {
+    Duration::from_secs(7 * 24 * 60 * 60)
+}
+
+fn default_tmutil_batch_size() -> usize {
+    1
+}
+
+fn default_tmutil_adaptive_batching() -> bool {
+    true
+}
+
+fn default_tmutil_max_batch_size() -> usize {
+    64
+}
+
+fn default_tmutil_batch_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_tmutil_retry_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_quarantine_after() -> u32 {
+    3
+}
+
+fn default_quarantine_reprobe_interval() -> Duration {
+    Duration::from_secs(7 * 24 * 60 * 60)
+}
+
+fn default_service_interval() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+fn default_service_jitter() -> Duration {
+    Duration::from_secs(5 * 60)
+}
+
+fn default_service_debounce() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_cache_snapshots() -> usize {
+    10
+}
+
+fn default_policy_refresh_interval() -> Duration {
+    Duration::from_secs(24 * 60 * 60)
+}
+
+fn deserialize_duration<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+    let s = String::deserialize(d)?;
+    parse_duration(&s).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_size<'de, D: serde::Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    match Size::deserialize(d)? {
+        Size::Bytes(n) => Ok(n),
+        Size::Text(s) => parse_size(&s).map_err(serde::de::Error::custom),
+    }
+}
+
+fn default_system_artifacts() -> Vec<String> {
+    DEFAULT_SYSTEM_ARTIFACTS
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
 impl Config {
     /// Load config from `path`. Returns `(Config, found)` — if the file does
     /// not exist, returns the default config and `found = false`.
+    ///
+    /// Files listed in `include` are merged beneath the file that includes
+    /// them (see [`merge_tables`]).
     pub fn load(path: &Path) -> Result<(Self, bool)> {
-        match std::fs::read_to_string(path) {
-            Ok(text) => {
-                let config: Config = toml::from_str(&text)
-                    .with_context(|| format!("parsing config file: {}", path.display()))?;
-                Ok((config, true))
-            }
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok((Config::default(), false)),
-            Err(e) => Err(e).with_context(|| format!("reading config file: {}", path.display())),
+        Self::load_over(path, None)
+    }
+
+    /// Like [`Config::load`], with `base` (a managed policy fragment) merged
+    /// beneath the file and its includes.
+    pub fn load_over(path: &Path, base: Option<toml::Table>) -> Result<(Self, bool)> {
+        if !path.exists() {
+            return Ok((Config::default(), false));
         }
+        Ok((Self::load_overridden(path, base, toml::Table::new())?, true))
     }
 
-    /// Expand `~` in every entry of `search_paths` and return absolute `PathBuf`s.
+    /// Like [`Config::load_over`], with `overrides` (see [`parse_overrides`])
+    /// replacing the settings of the file.  A missing file is an empty one.
+    pub fn load_overridden(
+        path: &Path,
+        base: Option<toml::Table>,
+        overrides: toml::Table,
+    ) -> Result<Self> {
+        let mut table = base.unwrap_or_default();
+        if path.exists() {
+            merge_tables(&mut table, load_table(path, &mut Vec::new())?);
+        }
+        override_table(&mut table, overrides);
+        toml::Value::Table(table)
+            .try_into()
+            .with_context(|| FileContext::new(ErrorKind::Config, path, "parsing config file"))
+    }
+
+    /// Whether `exclusion_mode` or any of `mode_rules` is fixed-path.
+    pub fn uses_fixed_path(&self) -> bool {
+        self.exclusion_mode.is_fixed_path()
+            || self.mode_rules.iter().any(|r| r.mode.is_fixed_path())
+    }
+
+    /// Expand `~` in every entry of `search_paths` and return absolute `PathBuf`s
+    /// (under [`root`](Self::root), if set).
     pub fn resolved_search_paths(&self) -> Vec<PathBuf> {
-        self.search_paths.iter().map(|p| expand_tilde(p)).collect()
+        self.rooted_entries(&self.search_paths)
+            .iter()
+            .map(PathBuf::from)
+            .collect()
     }
 
-    /// Expand `~` in every entry of `ignored_paths` and return absolute `PathBuf`s.
+    /// Expand `~` in every entry of `ignored_paths` and return absolute `PathBuf`s
+    /// (under [`root`](Self::root), if set).
     pub fn resolved_ignored_paths(&self) -> Vec<PathBuf> {
-        self.ignored_paths.iter().map(|p| expand_tilde(p)).collect()
+        self.rooted_entries(&self.ignored_paths)
+            .iter()
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    /// Expand `~` in `report_path` (under [`root`](Self::root), if set).
+    pub fn resolved_report_path(&self) -> Option<PathBuf> {
+        self.report_path
+            .as_deref()
+            .map(|p| self.rooted(&expand_tilde(p)))
+    }
+
+    /// `path` moved under [`root`](Self::root), if set.
+    pub fn rooted(&self, path: &Path) -> PathBuf {
+        match &self.root {
+            Some(root) => rebase(root, path),
+            None => path.to_path_buf(),
+        }
+    }
+
+    /// `entries` (paths that may start with `~`) expanded and moved under
+    /// [`root`](Self::root), e.g. for `system_artifacts` and presets.
+    pub fn rooted_entries(&self, entries: &[impl AsRef<str>]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|e| {
+                self.rooted(&expand_tilde(e.as_ref()))
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    /// Scanner options derived from this config.
+    pub fn scan_options(&self) -> ScanOptions {
+        ScanOptions {
+            detect_bare_repos: self.detect_bare_repos,
+            follow_symlinked_search_paths: self.follow_symlinked_search_paths,
+            walker: self.walker(),
+        }
+    }
+
+    /// How this config walks directory trees.
+    pub fn walker(&self) -> Walker {
+        Walker {
+            backend: self.walk_backend,
+            same_file_system: self.walk_same_file_system,
+            threads: 0,
+        }
+    }
+}
+
+/// Lists that keep their order: an including file replaces them instead of
+/// appending to them.
+const REPLACED_LISTS: &[&str] = &["rule_order"];
+
+/// Read the TOML table at `path` with the files it `include`s merged beneath
+/// it, in order.  `stack` holds the files being loaded, to reject cycles.
+///
+/// Include paths support `~` and are relative to the including file.
+fn load_table(path: &Path, stack: &mut Vec<PathBuf>) -> Result<toml::Table> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| FileContext::new(ErrorKind::Config, path, "reading config file"))?;
+    let mut table: toml::Table = toml::from_str(&text)
+        .with_context(|| FileContext::new(ErrorKind::Config, path, "parsing config file"))?;
+    let includes = match table.remove("include") {
+        None => return Ok(table),
+        Some(toml::Value::Array(includes)) => includes,
+        Some(_) => anyhow::bail!("{}: `include` must be a list of paths", path.display()),
+    };
+
+    let canonical = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    if stack.contains(&canonical) {
+        anyhow::bail!("config include cycle through {}", path.display());
+    }
+    stack.push(canonical);
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut merged = toml::Table::new();
+    for include in includes {
+        let Some(include) = include.as_str() else {
+            anyhow::bail!("{}: `include` must be a list of paths", path.display());
+        };
+        let included = load_table(&dir.join(expand_tilde(include)), stack)
+            .with_context(|| format!("included from {}", path.display()))?;
+        merge_tables(&mut merged, included);
+    }
+    stack.pop();
+    merge_tables(&mut merged, table);
+    Ok(merged)
+}
+
+/// Merge `over` into `base`: lists append (skipping values already present,
+/// except [`REPLACED_LISTS`]), tables merge key by key, and any other value
+/// in `over` replaces the one in `base`.
+fn merge_tables(base: &mut toml::Table, over: toml::Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Array(list)), toml::Value::Array(more))
+                if !REPLACED_LISTS.contains(&key.as_str()) =>
+            {
+                for v in more {
+                    if !list.contains(&v) {
+                        list.push(v);
+                    }
+                }
+            }
+            (Some(toml::Value::Table(table)), toml::Value::Table(more)) => {
+                merge_tables(table, more)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Replace the values of `base` with those of `over`, descending into tables
+/// both have; unlike [`merge_tables`], lists are replaced too.
+fn override_table(base: &mut toml::Table, over: toml::Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(table)), toml::Value::Table(more)) => {
+                override_table(table, more)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
     }
 }
 
+/// Parse `KEY=VALUE` setting overrides (`simulate --set`) into a table.  The
+/// value is TOML (`whitelist=[]`, `max_exclusions=100`); one that is not
+/// valid TOML is taken as a string, so `exclusion_mode=fixed-path` needs no
+/// quotes.  A key that [`DEFAULT_CONFIG`] does not document is an error,
+/// since the config would silently ignore it.
+pub fn parse_overrides(entries: &[String]) -> Result<toml::Table> {
+    let mut table = toml::Table::new();
+    for entry in entries {
+        let Some((key, value)) = entry.split_once('=') else {
+            anyhow::bail!("invalid override `{entry}`: expected KEY=VALUE");
+        };
+        let (key, value) = (key.trim(), value.trim());
+        let known = DEFAULT_CONFIG.lines().any(|line| {
+            let line = line.trim_start_matches("# ");
+            line.split_once(" = ").is_some_and(|(k, _)| k == key)
+        });
+        if !known {
+            anyhow::bail!("invalid override `{entry}`: unknown setting `{key}`");
+        }
+        let parsed: toml::Table = toml::from_str(&format!("{key} = {value}"))
+            .or_else(|_| toml::from_str(&format!("{key} = {}", toml::Value::String(value.into()))))
+            .with_context(|| format!("invalid override `{entry}`"))?;
+        override_table(&mut table, parsed);
+    }
+    Ok(table)
+}
+
+/// Parse a human-friendly duration such as `90s`, `30m`, `12h`, `7d` or `2w`.
+///
+/// A bare number is taken as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, unit) = s.split_at(split);
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid duration `{s}`: expected e.g. 30m, 12h, 7d"))?;
+    let scale = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        other => {
+            return Err(format!(
+                "invalid duration unit `{other}` (use s, m, h, d or w)"
+            ));
+        }
+    };
+    Ok(Duration::from_secs(value * scale))
+}
+
+/// Parse a human-friendly size such as `500MB`, `50GB` or `1.5TiB` into bytes.
+///
+/// Decimal (`KB`, `MB`, `GB`, `TB`) and binary (`KiB` … `TiB`) units are
+/// accepted, case-insensitively; a bare number is taken as bytes.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let value: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size `{s}`: expected e.g. 500MB, 50GB"))?;
+    let scale: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" | "k" => 1_000,
+        "mb" | "m" => 1_000_000,
+        "gb" | "g" => 1_000_000_000,
+        "tb" | "t" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        other => {
+            return Err(format!(
+                "invalid size unit `{other}` (use B, KB, MB, GB, TB or KiB … TiB)"
+            ));
+        }
+    };
+    Ok((value * scale as f64) as u64)
+}
+
 /// Expand a leading `~` to the user's home directory.
 pub fn expand_tilde(path: &str) -> PathBuf {
     if let Some(rest) = path.strip_prefix("~/") {
@@ -180,7 +1488,16 @@ pub fn expand_tilde(path: &str) -> PathBuf {
     PathBuf::from(path)
 }
 
-fn dirs_home() -> Option<PathBuf> {
+/// The absolute `path` as seen under `root`; paths already under `root` and
+/// relative paths are returned as they are.
+pub fn rebase(root: &Path, path: &Path) -> PathBuf {
+    if path.starts_with(root) || !path.is_absolute() {
+        return path.to_path_buf();
+    }
+    root.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+pub(crate) fn dirs_home() -> Option<PathBuf> {
     directories::UserDirs::new().map(|u| u.home_dir().to_path_buf())
 }
 
@@ -188,6 +1505,91 @@ fn dirs_home() -> Option<PathBuf> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("50GB"), Ok(50_000_000_000));
+        assert_eq!(parse_size("1.5 KiB"), Ok(1536));
+        assert_eq!(parse_size("512"), Ok(512));
+        assert!(parse_size("5 parsecs").is_err());
+        let config: Config = toml::from_str("large_exclusion_threshold = 0").unwrap();
+        assert_eq!(config.large_exclusion_threshold, 0);
+    }
+
+    #[test]
+    fn test_load_merges_includes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = tmp.path().join("team/base.toml");
+        std::fs::create_dir_all(base.parent().unwrap()).unwrap();
+        std::fs::write(
+            &base,
+            r#"
+whitelist = ["**/.env"]
+ignored_paths = ["~/Library"]
+exclusion_mode = "fixed-path"
+rule_order = ["gitignore", "whitelist"]
+"#,
+        )
+        .unwrap();
+        let main = tmp.path().join("config.toml");
+        std::fs::write(
+            &main,
+            r#"
+include = ["team/base.toml"]
+whitelist = ["**/.env", "**/secrets.yml"]
+exclusion_mode = "sticky"
+rule_order = ["lignore", "gitignore"]
+"#,
+        )
+        .unwrap();
+
+        let (config, found) = Config::load(&main).unwrap();
+        assert!(found);
+        assert_eq!(config.whitelist, vec!["**/.env", "**/secrets.yml"]);
+        assert_eq!(config.ignored_paths, vec!["~/Library"]);
+        assert_eq!(config.exclusion_mode, ExclusionMode::Sticky);
+        assert_eq!(
+            config.rule_order,
+            vec![RuleLayer::Lignore, RuleLayer::Gitignore]
+        );
+
+        std::fs::write(&base, "include = [\"../config.toml\"]\n").unwrap();
+        let err = Config::load(&main).unwrap_err();
+        assert!(format!("{err:#}").contains("include cycle"), "{err:#}");
+    }
+
+    #[test]
+    fn test_overrides_replace_lists_and_take_bare_strings() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("config.toml");
+        std::fs::write(&path, "whitelist = [\"**/.env\", \"**/*.key\"]\n").unwrap();
+        let overrides = parse_overrides(&[
+            r#"whitelist = ["**/*.key"]"#.to_string(),
+            "exclusion_mode=fixed-path".to_string(),
+            "max_exclusions=100".to_string(),
+        ])
+        .unwrap();
+
+        let config = Config::load_overridden(&path, None, overrides).unwrap();
+        assert_eq!(config.whitelist, vec!["**/*.key"]);
+        assert_eq!(config.exclusion_mode, ExclusionMode::FixedPath);
+        assert_eq!(config.max_exclusions, 100);
+
+        // Settings only documented in comments are known too
+        assert!(parse_overrides(&["report_path=/tmp/r.json".to_string()]).is_ok());
+        for bad in ["whitelst=[]", "whitelist"] {
+            assert!(parse_overrides(&[bad.to_string()]).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(7 * 86_400)));
+        assert!(parse_duration("12y").is_err());
+        assert!(parse_duration("h").is_err());
+    }
+
     #[test]
     fn test_resolved_search_paths_expands_tilde() {
         if let Some(home) = dirs_home() {
@@ -0,0 +1,137 @@
+use serde::Serialize;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    capabilities::Capabilities,
+    config::{Backend, Config},
+    permissions::{self, Loose},
+};
+
+/// The outcome of one `doctor` check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    /// Works, but not in a way letitgo is known to handle.
+    Warn,
+    /// Exclusions will fail until this is fixed.
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        })
+    }
+}
+
+/// One line of the `doctor` report.
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+/// Every check of `doctor` for `config`, whose Time Machine backend would
+/// run `tmutil`, and letitgo's files `state` (see
+/// [`permissions::state_paths`]).
+pub fn checks(config: &Config, tmutil: &Path, state: &[PathBuf]) -> Vec<Check> {
+    let mut checks = Vec::new();
+    if config.backend == Backend::TimeMachine {
+        checks.push(capability_check(&Capabilities::probe(tmutil)));
+    }
+    checks.push(permission_check(&permissions::loose(state)));
+    checks
+}
+
+/// Whether letitgo's files are private to the user.  Files others can read
+/// are a warning: they give away every project on the machine, but
+/// exclusions still work.
+pub fn permission_check(loose: &[Loose]) -> Check {
+    const SHOWN: usize = 3;
+    let (status, detail) = if loose.is_empty() {
+        (
+            Status::Ok,
+            "config, cache and their files are private to you".to_string(),
+        )
+    } else {
+        let mut listed: Vec<String> = loose
+            .iter()
+            .take(SHOWN)
+            .map(|l| format!("{} ({:o})", l.path.display(), l.mode))
+            .collect();
+        if loose.len() > SHOWN {
+            listed.push(format!("{} more", loose.len() - SHOWN));
+        }
+        (
+            Status::Warn,
+            format!(
+                "{} path(s) other users can access: {}; run `letitgo doctor --fix-permissions`",
+                loose.len(),
+                listed.join(", ")
+            ),
+        )
+    };
+    Check {
+        name: "permissions",
+        status,
+        detail,
+    }
+}
+
+/// Whether `tmutil` on this system is one whose quirks `data/tmutil.toml`
+/// records.  An unknown macOS release is a warning (the default exit codes
+/// are used); a `tmutil` that does not run is a failure.
+pub fn capability_check(tmutil: &Capabilities) -> Check {
+    let codes = format!("safe exit codes {:?}", tmutil.safe_exit_codes);
+    let (status, detail) = match (&tmutil.macos_version, &tmutil.tmutil_version) {
+        (None, _) => (
+            Status::Warn,
+            "not macOS (no sw_vers); Time Machine exclusions need macOS".to_string(),
+        ),
+        (Some(_), None) => (
+            Status::Fail,
+            format!("{} did not report its version", tmutil.tmutil.display()),
+        ),
+        (Some(_), Some(_)) if tmutil.is_known() => (Status::Ok, format!("{tmutil}; {codes}")),
+        (Some(_), Some(_)) => (
+            Status::Warn,
+            format!("{tmutil}: untested combination, using the default {codes}"),
+        ),
+    };
+    Check {
+        name: "tmutil",
+        status,
+        detail,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capabilities::Version;
+
+    #[test]
+    fn test_untested_macos_warns_and_a_silent_tmutil_fails() {
+        let tmutil = Path::new("/usr/bin/tmutil");
+        let check = |macos: &str, version: Option<&str>| {
+            capability_check(&Capabilities::for_versions(
+                tmutil,
+                Version::find(macos),
+                version.and_then(Version::find),
+            ))
+            .status
+        };
+        assert_eq!(check("15.2", Some("4.0.0")), Status::Ok);
+        assert_eq!(check("27.0", Some("5.0.0")), Status::Warn);
+        assert_eq!(check("15.2", None), Status::Fail);
+        assert_eq!(check("", None), Status::Warn);
+    }
+}
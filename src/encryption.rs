@@ -0,0 +1,283 @@
+use anyhow::{Context, Result, bail};
+use chacha20poly1305::{
+    ChaCha20Poly1305, Key, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng, Payload},
+};
+use directories::BaseDirs;
+use std::{
+    env, fs,
+    io::Write,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use crate::{config::expand_tilde, permissions};
+
+/// Leading bytes of an encrypted cache, which neither JSON nor a binary
+/// plist starts with.  They are authenticated along with the contents.
+const MAGIC: &[u8] = b"letitgo-encrypted-v1\n";
+
+/// Length of the random nonce stored after [`MAGIC`].
+const NONCE_LEN: usize = 12;
+
+/// Overrides where the cache key is kept: a file holding it, created on
+/// first use.  For machines without a keychain, and for tests.
+pub const KEY_FILE_ENV: &str = "LETITGO_CACHE_KEY_FILE";
+
+/// Keychain item holding the cache key on macOS.
+#[cfg(target_os = "macos")]
+const KEYCHAIN_SERVICE: &str = "letitgo";
+#[cfg(target_os = "macos")]
+const KEYCHAIN_ACCOUNT: &str = "cache-key";
+
+/// The key encrypted caches are sealed with, one per user.
+#[derive(Clone)]
+pub struct CacheKey(Key);
+
+impl CacheKey {
+    fn generate() -> Self {
+        CacheKey(ChaCha20Poly1305::generate_key(&mut OsRng))
+    }
+
+    fn to_hex(&self) -> String {
+        self.0.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    fn from_hex(text: &str) -> Result<Self> {
+        let text = text.trim();
+        if text.len() != 64 || !text.is_ascii() {
+            bail!("a cache key is 64 hex digits");
+        }
+        let bytes = (0..32)
+            .map(|i| u8::from_str_radix(&text[2 * i..2 * i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .context("a cache key is 64 hex digits")?;
+        Ok(CacheKey(*Key::from_slice(&bytes)))
+    }
+}
+
+/// Whether `bytes` are a cache written by [`encrypt`].
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Seal `plaintext` with `key` under a fresh random nonce.
+pub fn encrypt(key: &CacheKey, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let sealed = ChaCha20Poly1305::new(&key.0)
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: MAGIC,
+            },
+        )
+        .map_err(|_| anyhow::anyhow!("encrypting cache"))?;
+    let mut bytes = Vec::with_capacity(MAGIC.len() + NONCE_LEN + sealed.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&nonce);
+    bytes.extend_from_slice(&sealed);
+    Ok(bytes)
+}
+
+/// Open `bytes` written by [`encrypt`], failing if they were sealed with
+/// another key or changed since.
+pub fn decrypt(key: &CacheKey, bytes: &[u8]) -> Result<Vec<u8>> {
+    let Some(rest) = bytes.strip_prefix(MAGIC) else {
+        bail!("not an encrypted cache");
+    };
+    if rest.len() < NONCE_LEN {
+        bail!("encrypted cache is truncated");
+    }
+    let (nonce, sealed) = rest.split_at(NONCE_LEN);
+    ChaCha20Poly1305::new(&key.0)
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: sealed,
+                aad: MAGIC,
+            },
+        )
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "cannot decrypt the cache: it was encrypted with another key or has been \
+                 modified (delete it and run `letitgo run` to rebuild it)"
+            )
+        })
+}
+
+/// The user's cache key, created when missing and `create` is set.
+///
+/// It is kept in the file named by [`KEY_FILE_ENV`] when that is set, else
+/// in the login keychain on macOS and in a `0600` file under the user's
+/// data directory elsewhere.  The key is looked up once per process.
+pub fn cache_key(create: bool) -> Result<Option<CacheKey>> {
+    static KEY: OnceLock<CacheKey> = OnceLock::new();
+    if let Some(key) = KEY.get() {
+        return Ok(Some(key.clone()));
+    }
+    let key = match key_file_path() {
+        Some(path) => key_file(&path, create)?,
+        None => keychain_key(create)?,
+    };
+    if let Some(key) = &key {
+        let _ = KEY.set(key.clone());
+    }
+    Ok(key)
+}
+
+/// The file the cache key is kept in; `None` when it is in the keychain.
+pub fn key_file_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(KEY_FILE_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    if cfg!(target_os = "macos") {
+        return None;
+    }
+    Some(match BaseDirs::new() {
+        Some(base) => base.data_local_dir().join("letitgo/cache.key"),
+        None => expand_tilde("~/.local/share/letitgo/cache.key"),
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn keychain_key(create: bool) -> Result<Option<CacheKey>> {
+    use std::process::{Command, Stdio};
+
+    let output = Command::new("security")
+        .args(["find-generic-password", "-s", KEYCHAIN_SERVICE])
+        .args(["-a", KEYCHAIN_ACCOUNT, "-w"])
+        .output()
+        .context("running `security` to read the cache key")?;
+    if output.status.success() {
+        return CacheKey::from_hex(&String::from_utf8_lossy(&output.stdout))
+            .map(Some)
+            .context("reading the cache key from the keychain");
+    }
+    // 44 is errSecItemNotFound
+    if output.status.code() != Some(44) {
+        bail!(
+            "reading the cache key from the keychain: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    if !create {
+        return Ok(None);
+    }
+
+    // Through `security -i` on stdin, so the key never shows in `ps`
+    let key = CacheKey::generate();
+    let mut child = Command::new("security")
+        .arg("-i")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("running `security` to store the cache key")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        writeln!(
+            stdin,
+            "add-generic-password -s {KEYCHAIN_SERVICE} -a {KEYCHAIN_ACCOUNT} -w {}",
+            key.to_hex()
+        )
+        .context("storing the cache key in the keychain")?;
+    }
+    let output = child
+        .wait_with_output()
+        .context("storing the cache key in the keychain")?;
+    if !output.status.success() {
+        bail!(
+            "storing the cache key in the keychain: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(Some(key))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn keychain_key(_create: bool) -> Result<Option<CacheKey>> {
+    unreachable!("the key is kept in a file without a keychain")
+}
+
+/// The key in `path`, or a new one written there (private, see
+/// [`permissions`]) when missing and `create` is set.
+fn key_file(path: &Path, create: bool) -> Result<Option<CacheKey>> {
+    match fs::read_to_string(path) {
+        Ok(text) => {
+            return CacheKey::from_hex(&text)
+                .map(Some)
+                .with_context(|| format!("reading cache key: {}", path.display()));
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e).with_context(|| format!("reading cache key: {}", path.display()));
+        }
+    }
+    if !create {
+        return Ok(None);
+    }
+    if let Some(dir) = path.parent() {
+        permissions::create_private_dir(dir)
+            .with_context(|| format!("creating key dir: {}", dir.display()))?;
+    }
+    let key = CacheKey::generate();
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(permissions::FILE_MODE)
+        .open(path)
+        .and_then(|file| {
+            file.set_permissions(fs::Permissions::from_mode(permissions::FILE_MODE))?;
+            Ok(file)
+        })
+        .with_context(|| format!("creating cache key: {}", path.display()))?;
+    writeln!(file, "{}", key.to_hex())
+        .with_context(|| format!("writing cache key: {}", path.display()))?;
+    Ok(Some(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypted_cache_round_trips_and_rejects_tampering() {
+        let key = CacheKey::generate();
+        let plaintext = br#"{"paths":["/Users/me/secret-project/target"]}"#;
+        let sealed = encrypt(&key, plaintext).unwrap();
+
+        assert!(is_encrypted(&sealed));
+        assert!(
+            !sealed.windows(14).any(|w| w == b"secret-project"),
+            "paths must not show in the file"
+        );
+        assert_eq!(decrypt(&key, &sealed).unwrap(), plaintext);
+        // A fresh nonce each time
+        assert_ne!(encrypt(&key, plaintext).unwrap(), sealed);
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decrypt(&key, &tampered).is_err());
+        assert!(decrypt(&CacheKey::generate(), &sealed).is_err());
+        assert!(decrypt(&key, &sealed[..MAGIC.len() + 4]).is_err());
+    }
+
+    #[test]
+    fn test_key_file_is_created_private_and_reused() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("letitgo/cache.key");
+
+        assert!(key_file(&path, false).unwrap().is_none());
+        assert!(!path.exists(), "looking up must not create the key");
+        let key = key_file(&path, true).unwrap().unwrap();
+        let mode = |p: &Path| fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(path.parent().unwrap()), 0o700);
+
+        let again = key_file(&path, true).unwrap().unwrap();
+        assert_eq!(again.to_hex(), key.to_hex());
+        fs::write(&path, "not a key").unwrap();
+        assert!(key_file(&path, false).is_err());
+    }
+}
@@ -1,8 +1,256 @@
-/// Returns true if a tmutil exit code is a "safe" non-fatal error.
+use serde::Serialize;
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use crate::capabilities::Capabilities;
+
+/// Returns true if a tmutil exit code is a "safe" non-fatal error on this
+/// system, per the profile `data/tmutil.toml` gives its macOS version.
 ///
 /// Known safe codes:
 /// - 22: cannot change exclusion setting (path no longer exists on disk)
 /// - 213: path not found (item was already removed / never existed)
-pub fn is_tmutil_safe_error(exit_code: i32) -> bool {
-    matches!(exit_code, 22 | 213)
+pub fn is_tmutil_safe_error(exit_code: i32, tmutil: &Capabilities) -> bool {
+    tmutil.safe_exit_codes.contains(&exit_code)
+}
+
+/// What a failed `tmutil` call means for the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TmutilErrorClass {
+    /// The path is gone or was never there: nothing to exclude.
+    Ignorable,
+    /// A transient condition; the same call may succeed when repeated.
+    Retryable,
+    /// Every further call will fail the same way (missing privileges, an
+    /// unknown error): the run stops.
+    Fatal,
+}
+
+/// `tmutil` stderr texts and what they mean, checked before the exit code:
+/// `tmutil` exits 1 for most errors and only says in its message which one
+/// it hit.
+const STDERR_CLASSES: &[(&str, TmutilErrorClass)] = &[
+    // Full Disk Access missing, or a `-p` call without root
+    ("Operation not permitted", TmutilErrorClass::Fatal),
+    ("requires root", TmutilErrorClass::Fatal),
+    ("Resource busy", TmutilErrorClass::Retryable),
+    (
+        "Resource temporarily unavailable",
+        TmutilErrorClass::Retryable,
+    ),
+    ("Interrupted system call", TmutilErrorClass::Retryable),
+    ("timed out", TmutilErrorClass::Retryable),
+    // EINVAL: the path vanished between the scan and the call
+    ("Invalid argument", TmutilErrorClass::Ignorable),
+    ("No such file or directory", TmutilErrorClass::Ignorable),
+];
+
+/// A `tmutil` call that exited with an error, classified by its stderr and
+/// exit code.
+#[derive(Debug, Clone)]
+pub struct TmutilError {
+    pub verb: String,
+    pub code: i32,
+    pub stderr: String,
+    pub class: TmutilErrorClass,
+}
+
+impl TmutilError {
+    /// Classify the failure of `tmutil <verb>`: the first [`STDERR_CLASSES`]
+    /// text in `stderr` decides, else an exit code the profile lists as
+    /// safe is ignorable and any other is fatal.
+    pub fn classify(verb: &str, code: i32, stderr: &str, tmutil: &Capabilities) -> Self {
+        let class = STDERR_CLASSES
+            .iter()
+            .find(|(text, _)| stderr.contains(text))
+            .map(|&(_, class)| class)
+            .unwrap_or(if is_tmutil_safe_error(code, tmutil) {
+                TmutilErrorClass::Ignorable
+            } else {
+                TmutilErrorClass::Fatal
+            });
+        TmutilError {
+            verb: verb.to_string(),
+            code,
+            stderr: stderr.trim().to_string(),
+            class,
+        }
+    }
+}
+
+impl fmt::Display for TmutilError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tmutil {} failed (exit {}): {}",
+            self.verb, self.code, self.stderr
+        )
+    }
+}
+
+impl std::error::Error for TmutilError {}
+
+/// What a command failed on, as `--json-errors` reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    /// The config file or one it includes.
+    Config,
+    /// The cache file.
+    Cache,
+    /// The instance lockfile.
+    Lock,
+    /// A `tmutil` call (see [`TmutilError`]).
+    Tmutil,
+    /// A file or directory letitgo may not read or write.
+    PermissionDenied,
+    /// A path that does not exist.
+    NotFound,
+    /// Any other I/O error.
+    Io,
+    Other,
+}
+
+/// An error context naming the file an operation failed on, so that
+/// [`ErrorReport`] can report the file without parsing messages.  It reads
+/// as `<action>: <path>`.
+#[derive(Debug, Clone)]
+pub struct FileContext {
+    pub kind: ErrorKind,
+    pub action: &'static str,
+    pub path: PathBuf,
+}
+
+impl FileContext {
+    pub fn new(kind: ErrorKind, path: &Path, action: &'static str) -> Self {
+        FileContext {
+            kind,
+            action,
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl fmt::Display for FileContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.action, self.path.display())
+    }
+}
+
+/// The structured form of a failed command's error, printed on stderr as
+/// JSON with `--json-errors`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    pub kind: ErrorKind,
+    /// The outermost message, as `Error:` would print it.
+    pub message: String,
+    /// The messages of the errors underneath, outermost first.
+    pub causes: Vec<String>,
+    /// The file the outermost [`FileContext`] names.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+}
+
+impl ErrorReport {
+    /// Classify `error` by what its chain holds: a [`TmutilError`], an I/O
+    /// permission or not-found error, else the kind of the outermost
+    /// [`FileContext`].
+    pub fn new(error: &anyhow::Error) -> Self {
+        let file = error.downcast_ref::<FileContext>();
+        let tmutil = error.chain().find_map(|e| e.downcast_ref::<TmutilError>());
+        let io = error
+            .chain()
+            .find_map(|e| e.downcast_ref::<io::Error>())
+            .map(io::Error::kind);
+        let kind = match (tmutil, io, file) {
+            (Some(_), _, _) => ErrorKind::Tmutil,
+            (_, Some(io::ErrorKind::PermissionDenied), _) => ErrorKind::PermissionDenied,
+            (_, _, Some(file)) => file.kind,
+            (_, Some(io::ErrorKind::NotFound), _) => ErrorKind::NotFound,
+            (_, Some(_), _) => ErrorKind::Io,
+            _ => ErrorKind::Other,
+        };
+        let suggestion = match kind {
+            ErrorKind::Tmutil if tmutil.is_some_and(|e| e.stderr.contains("not permitted")) => {
+                "Grant Full Disk Access to the app running letitgo, and run fixed-path \
+                 changes as root (or use fixed_path_backend = \"helper\")"
+            }
+            ErrorKind::Tmutil => "Run `letitgo doctor` to check this system's tmutil",
+            ErrorKind::PermissionDenied => {
+                "Check the file's permissions; reading other apps' data needs Full Disk \
+                 Access for the app running letitgo"
+            }
+            ErrorKind::Config => {
+                "Fix the config file; `letitgo init` writes one documenting every setting"
+            }
+            ErrorKind::Cache => {
+                "Restore a snapshot with `letitgo cache restore`, or delete the cache and \
+                 run again"
+            }
+            ErrorKind::Lock => "Check that the cache directory is writable",
+            ErrorKind::NotFound => "Check that the path exists",
+            ErrorKind::Io | ErrorKind::Other => "",
+        };
+        ErrorReport {
+            kind,
+            message: error.to_string(),
+            causes: error.chain().skip(1).map(ToString::to_string).collect(),
+            path: file.map(|f| f.path.clone()),
+            suggestion: (!suggestion.is_empty()).then(|| suggestion.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capabilities::Version;
+    use std::path::Path;
+
+    #[test]
+    fn test_stderr_text_decides_before_the_exit_code() {
+        let tmutil = Capabilities::for_versions(Path::new("tmutil"), Version::find("15.2"), None);
+        let class =
+            |code, stderr| TmutilError::classify("addexclusion", code, stderr, &tmutil).class;
+        assert_eq!(class(213, ""), TmutilErrorClass::Ignorable);
+        assert_eq!(
+            class(1, "/a: Invalid argument (error 22)"),
+            TmutilErrorClass::Ignorable
+        );
+        assert_eq!(class(16, "/a: Resource busy"), TmutilErrorClass::Retryable);
+        assert_eq!(
+            class(213, "/a: Operation not permitted"),
+            TmutilErrorClass::Fatal
+        );
+        assert_eq!(class(1, "boom"), TmutilErrorClass::Fatal);
+    }
+
+    #[test]
+    fn test_error_report_names_the_file_and_classifies_io_errors() {
+        use anyhow::Context;
+
+        let missing = Path::new("/nonexistent/cache.json");
+        let error = std::fs::read(missing)
+            .with_context(|| FileContext::new(ErrorKind::Cache, missing, "reading cache"))
+            .context("loading state")
+            .unwrap_err();
+        let report = ErrorReport::new(&error);
+        assert_eq!(report.kind, ErrorKind::Cache);
+        assert_eq!(report.path.as_deref(), Some(missing));
+        assert_eq!(report.message, "loading state");
+        assert_eq!(report.causes[0], "reading cache: /nonexistent/cache.json");
+
+        let denied = anyhow::Error::new(io::Error::from(io::ErrorKind::PermissionDenied)).context(
+            FileContext::new(ErrorKind::Config, missing, "reading config file"),
+        );
+        assert_eq!(ErrorReport::new(&denied).kind, ErrorKind::PermissionDenied);
+        assert_eq!(
+            ErrorReport::new(&anyhow::anyhow!("boom")).kind,
+            ErrorKind::Other
+        );
+    }
 }
@@ -0,0 +1,321 @@
+use anyhow::Result;
+use path_clean::PathClean;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    config::{Config, ConflictWinner},
+    git,
+    guard::{SafetyRails, Violation},
+    ignore_resolver::{
+        ResolveOptions, RuleMatch, collapse_file_exclusions, gitignore_rule_for, keep_marker,
+        lignore_rules_for, protected_file_in, resolve_repo, small_file_size,
+    },
+};
+
+/// Everything `letitgo explain` knows about why a path is or isn't excluded.
+#[derive(Debug, Clone)]
+pub struct Explanation {
+    /// The absolute, cleaned path being explained.
+    pub path: PathBuf,
+    /// The working tree containing `path`, if any.
+    pub repo: Option<PathBuf>,
+    /// The marker file keeping everything in `repo` in backups.
+    pub keep_marker: Option<PathBuf>,
+    /// The ignore rule that decided the Git-level outcome.
+    pub gitignore: Option<RuleMatch>,
+    /// `.lignore` rules that add or re-include `path` or an ancestor.
+    pub lignore: Vec<RuleMatch>,
+    /// Config whitelist globs matching `path` or a matched ancestor.
+    pub whitelist: Vec<String>,
+    /// A protected file that blocked the exclusion (safety scan).
+    pub protected_file: Option<PathBuf>,
+    /// The resolved exclusion covering `path` (itself or an ancestor).
+    pub covered_by: Option<PathBuf>,
+    /// Set when a whitelist glob matches a path a `.lignore` added: the layer
+    /// that wins under the current `rule_order` / `lignore_vs_whitelist`.
+    pub whitelist_conflict: Option<ConflictWinner>,
+}
+
+impl Explanation {
+    /// `true` if a `run` would exclude `path` (directly or via an ancestor).
+    pub fn is_excluded(&self) -> bool {
+        self.covered_by.is_some()
+    }
+}
+
+/// Explain how the current `config` treats `path`.
+///
+/// Re-resolves the containing repository in full so the verdict matches what
+/// `run` would compute, then looks up the individual rules for the details.
+/// Under [`Config::root`] the path is moved there, like the search paths.
+pub fn explain(path: &Path, config: &Config) -> Result<Explanation> {
+    let path = if path.is_absolute() {
+        path.clean()
+    } else {
+        std::env::current_dir()?.join(path).clean()
+    };
+    let path = config.rooted(&path);
+
+    let mut explanation = Explanation {
+        path: path.clone(),
+        repo: git::find_repo(&path),
+        keep_marker: None,
+        gitignore: None,
+        lignore: Vec::new(),
+        whitelist: Vec::new(),
+        protected_file: None,
+        covered_by: None,
+        whitelist_conflict: None,
+    };
+    let Some(repo) = explanation.repo.clone() else {
+        return Ok(explanation);
+    };
+    explanation.keep_marker = keep_marker(&repo);
+
+    let options = ResolveOptions::from_config(config)?;
+    let mut excluded = resolve_repo(&repo, &options)?;
+    collapse_file_exclusions(&repo, &mut excluded, &options);
+    explanation.covered_by = path
+        .ancestors()
+        .take_while(|a| a.starts_with(&repo))
+        .find(|a| excluded.contains(*a))
+        .map(Path::to_path_buf);
+
+    explanation.gitignore = gitignore_rule_for(&repo, &path)?;
+    explanation.lignore = lignore_rules_for(&repo, &path);
+
+    let mut targets: Vec<&Path> = vec![&path];
+    targets.extend(explanation.gitignore.iter().map(|r| r.target.as_path()));
+    targets.extend(explanation.lignore.iter().map(|r| r.target.as_path()));
+    let mut whitelist: Vec<String> = targets
+        .iter()
+        .flat_map(|t| options.matching_whitelist(t))
+        .map(str::to_string)
+        .collect();
+    whitelist.sort();
+    whitelist.dedup();
+    explanation.whitelist = whitelist;
+    if !explanation.whitelist.is_empty() && explanation.lignore.iter().any(|r| r.ignored) {
+        explanation.whitelist_conflict = options.conflict_winner();
+    }
+
+    if explanation.covered_by.is_none()
+        && let Some(rule) = explanation.gitignore.as_ref().filter(|r| r.ignored)
+    {
+        explanation.protected_file = protected_file_in(&rule.target, &options);
+    }
+
+    Ok(explanation)
+}
+
+/// The step of a `run` that keeps a path out of the exclusion set, as
+/// reported by `letitgo why-not`.  Checked in pipeline order; the first one
+/// that applies wins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotExcluded {
+    /// The path is not below any configured search path.
+    OutsideSearchPaths,
+    /// The path is below an `ignored_paths` entry, so the scan never enters it.
+    IgnoredPath(PathBuf),
+    /// No Git working tree contains the path.
+    NoRepo,
+    /// A marker file at the root of its repo keeps the whole repo in backups.
+    KeepMarker(PathBuf),
+    /// An ancestor is excluded instead, which already covers the path.
+    AncestorExcluded(PathBuf),
+    /// The would-be exclusion is refused by the safety rails.
+    SafetyRail(PathBuf, Violation),
+    /// An ignore rule matches the file, but it is smaller than
+    /// `min_file_size_kb`; holds its size in bytes.
+    TooSmall(u64),
+    /// Config whitelist globs keep the path in backups.
+    Whitelisted(Vec<String>),
+    /// A `.lignore` negation re-includes the path or an ancestor.
+    LignoreNegation(RuleMatch),
+    /// A protected file inside the ignored directory blocked it.
+    ProtectedFile(PathBuf),
+    /// No ignore rule matches the path.
+    NoMatchingRule,
+}
+
+/// Report why `run` would not exclude `path`, or `None` if it would.
+///
+/// Builds on [`explain`] and adds the steps before resolution (search paths,
+/// `ignored_paths`) and after it (safety rails).  The `max_exclusions` budget
+/// and `large_exclusion_threshold` prompt depend on the whole run and are not
+/// considered.
+pub fn why_not(path: &Path, config: &Config) -> Result<Option<NotExcluded>> {
+    let explanation = explain(path, config)?;
+    let path = &explanation.path;
+
+    let search_paths = config.resolved_search_paths();
+    if !search_paths.iter().any(|sp| path.starts_with(sp)) {
+        return Ok(Some(NotExcluded::OutsideSearchPaths));
+    }
+    if let Some(ignored) = config
+        .resolved_ignored_paths()
+        .into_iter()
+        .find(|ig| path.starts_with(ig))
+    {
+        return Ok(Some(NotExcluded::IgnoredPath(ignored)));
+    }
+    let Some(repo) = &explanation.repo else {
+        return Ok(Some(NotExcluded::NoRepo));
+    };
+    if let Some(marker) = explanation.keep_marker {
+        return Ok(Some(NotExcluded::KeepMarker(marker)));
+    }
+
+    if let Some(covered) = &explanation.covered_by {
+        let rails = SafetyRails::new(&search_paths, [repo], config.min_exclusion_depth);
+        if let Some(violation) = rails.check(covered) {
+            return Ok(Some(NotExcluded::SafetyRail(covered.clone(), violation)));
+        }
+        if covered == path {
+            return Ok(None);
+        }
+        return Ok(Some(NotExcluded::AncestorExcluded(covered.clone())));
+    }
+
+    if let Some(rule) = &explanation.gitignore
+        && rule.ignored
+        && rule.target == *path
+        && let Some(size) = small_file_size(path, config.min_file_size_kb.saturating_mul(1000))
+    {
+        return Ok(Some(NotExcluded::TooSmall(size)));
+    }
+    if !explanation.whitelist.is_empty() {
+        return Ok(Some(NotExcluded::Whitelisted(explanation.whitelist)));
+    }
+    if let Some(rule) = explanation.lignore.into_iter().find(|r| !r.ignored) {
+        return Ok(Some(NotExcluded::LignoreNegation(rule)));
+    }
+    if let Some(file) = explanation.protected_file {
+        return Ok(Some(NotExcluded::ProtectedFile(file)));
+    }
+    Ok(Some(NotExcluded::NoMatchingRule))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_explain_reports_rules_and_verdict() {
+        let tmp = tempdir().unwrap();
+        let repo = tmp.path().join("repo");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        fs::create_dir_all(repo.join("target/debug")).unwrap();
+        fs::create_dir_all(repo.join("build")).unwrap();
+        fs::write(repo.join(".gitignore"), "# build output\ntarget/\nbuild/\n").unwrap();
+        fs::write(repo.join(".lignore"), "!build/\n").unwrap();
+        let config = Config {
+            whitelist: vec![],
+            ..Config::default()
+        };
+
+        let target = explain(&repo.join("target/debug"), &config).unwrap();
+        assert_eq!(target.repo.as_deref(), Some(repo.as_path()));
+        assert_eq!(target.covered_by, Some(repo.join("target")));
+        let rule = target.gitignore.unwrap();
+        assert!(rule.ignored);
+        assert_eq!(rule.target, repo.join("target"));
+        assert_eq!(rule.source.file, repo.join(".gitignore"));
+        assert_eq!(rule.source.line, Some(2));
+        assert_eq!(rule.source.pattern, "target/");
+
+        let build = explain(&repo.join("build"), &config).unwrap();
+        assert!(!build.is_excluded());
+        assert_eq!(build.lignore.len(), 1);
+        assert!(!build.lignore[0].ignored);
+        assert_eq!(build.lignore[0].source.line, Some(1));
+
+        let wl_config = Config {
+            whitelist: vec!["**/build".to_string()],
+            lignore_vs_whitelist: ConflictWinner::Lignore,
+            ..Config::default()
+        };
+        fs::write(repo.join(".gitignore"), "target/\n").unwrap();
+        fs::write(repo.join(".lignore"), "build/\n").unwrap();
+        let conflict = explain(&repo.join("build"), &wl_config).unwrap();
+        assert_eq!(conflict.whitelist_conflict, Some(ConflictWinner::Lignore));
+        assert!(conflict.is_excluded());
+
+        let outside = explain(tmp.path(), &config).unwrap();
+        assert!(outside.repo.is_none());
+        assert!(!outside.is_excluded());
+    }
+
+    #[test]
+    fn test_why_not_reports_first_filter() {
+        let tmp = tempdir().unwrap();
+        let root = tmp.path().join("a/b/c");
+        let repo = root.join("repo");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        for dir in ["target/debug", "build", "src", "vendor"] {
+            fs::create_dir_all(repo.join(dir)).unwrap();
+        }
+        fs::create_dir_all(root.join("skipped/repo/.git")).unwrap();
+        fs::write(repo.join(".gitignore"), "target/\nbuild/\nvendor/\n").unwrap();
+        fs::write(repo.join(".lignore"), "!build/\n").unwrap();
+        let config = Config {
+            search_paths: vec![root.to_string_lossy().into_owned()],
+            ignored_paths: vec![root.join("skipped").to_string_lossy().into_owned()],
+            whitelist: vec!["**/vendor".to_string()],
+            min_exclusion_depth: 0,
+            ..Config::default()
+        };
+        let why = |rel: &str| why_not(&root.join(rel), &config).unwrap();
+
+        assert_eq!(why("repo/target"), None);
+        assert_eq!(
+            why("repo/target/debug"),
+            Some(NotExcluded::AncestorExcluded(repo.join("target")))
+        );
+        assert_eq!(
+            why("repo/vendor"),
+            Some(NotExcluded::Whitelisted(vec!["**/vendor".to_string()]))
+        );
+        assert!(matches!(
+            why("repo/build"),
+            Some(NotExcluded::LignoreNegation(r)) if r.source.pattern == "!build/"
+        ));
+        assert_eq!(why("repo/src"), Some(NotExcluded::NoMatchingRule));
+        fs::write(repo.join("debug.log"), "tiny").unwrap();
+        fs::write(repo.join(".gitignore"), "target/\nbuild/\nvendor/\n*.log\n").unwrap();
+        let small = Config {
+            min_file_size_kb: 1,
+            ..config.clone()
+        };
+        assert_eq!(
+            why_not(&repo.join("debug.log"), &small).unwrap(),
+            Some(NotExcluded::TooSmall(4))
+        );
+        assert_eq!(why("repo/debug.log"), None);
+        assert_eq!(
+            why("skipped/repo"),
+            Some(NotExcluded::IgnoredPath(root.join("skipped")))
+        );
+        assert_eq!(why("."), Some(NotExcluded::NoRepo));
+        assert_eq!(
+            why_not(tmp.path(), &config).unwrap(),
+            Some(NotExcluded::OutsideSearchPaths)
+        );
+
+        let deep = Config {
+            min_exclusion_depth: 64,
+            ..config.clone()
+        };
+        assert!(matches!(
+            why_not(&repo.join("target"), &deep).unwrap(),
+            Some(NotExcluded::SafetyRail(p, Violation::TooShallow(_))) if p == repo.join("target")
+        ));
+        fs::write(repo.join(".letitgo-keep"), "").unwrap();
+        assert_eq!(
+            why("repo/target"),
+            Some(NotExcluded::KeepMarker(repo.join(".letitgo-keep")))
+        );
+    }
+}
@@ -0,0 +1,365 @@
+use chrono::{DateTime, FixedOffset, Local};
+use clap::ValueEnum;
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cache::{Cache, Origin},
+    list::{self, Row},
+    provenance::Rule,
+    size::format_size,
+};
+
+/// What `letitgo export` renders the exclusion set as: configuration
+/// fragments for backup tools that letitgo does not drive itself, or an
+/// inventory for people to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// Arq: a JSON object whose `excludedItemPaths` lists every path.
+    Arq,
+    /// Backblaze: `excludefname_rule` elements for `bzexcluderules_editable.xml`.
+    Backblaze,
+    /// A Markdown inventory: the paths by repository, with sizes and rules.
+    Markdown,
+    /// The inventory as a standalone HTML page.
+    Html,
+}
+
+impl ExportFormat {
+    /// Whether this is an inventory, whose sizes are measured on disk.
+    pub fn is_inventory(self) -> bool {
+        matches!(self, ExportFormat::Markdown | ExportFormat::Html)
+    }
+}
+
+/// Render the cached paths in `format`, sorted.  `redact` hides directory
+/// names in an inventory as `list --redact` does.
+pub fn render(format: ExportFormat, cache: &Cache, redact: bool) -> String {
+    let mut paths = cache.paths.clone();
+    paths.sort();
+    match format {
+        ExportFormat::Arq => render_arq(&paths),
+        ExportFormat::Backblaze => render_backblaze(&paths),
+        ExportFormat::Markdown => render_markdown(&Inventory::new(cache, redact)),
+        ExportFormat::Html => render_html(&Inventory::new(cache, redact)),
+    }
+}
+
+/// The exclusion inventory: every cached path with its size on disk and
+/// the rule behind it, grouped by the repository holding it.  Groups come
+/// largest first, paths outside any repository last; so do the paths of a
+/// group.
+#[derive(Debug, Clone)]
+pub struct Inventory {
+    pub generated_at: DateTime<FixedOffset>,
+    pub last_run: Option<DateTime<FixedOffset>>,
+    pub exclusion_mode: String,
+    pub groups: Vec<Group>,
+}
+
+/// The paths of one repository, or (`repo` being `None`) of none.
+#[derive(Debug, Clone)]
+pub struct Group {
+    pub repo: Option<PathBuf>,
+    /// The sizes of its paths that exist.
+    pub size: u64,
+    pub items: Vec<Item>,
+}
+
+/// A path of the inventory.
+#[derive(Debug, Clone)]
+pub struct Item {
+    /// Relative to the group's repository, else absolute.
+    pub path: PathBuf,
+    pub origin: Origin,
+    pub rule: Option<Rule>,
+    /// `None` for a path that no longer exists.
+    pub size: Option<u64>,
+    pub added: Option<DateTime<FixedOffset>>,
+}
+
+impl Inventory {
+    /// The inventory of `cache`, measuring every path (in parallel).
+    pub fn new(cache: &Cache, redact: bool) -> Self {
+        let paths: Vec<&Path> = cache.paths.iter().map(PathBuf::as_path).collect();
+        let mut by_repo: BTreeMap<Option<PathBuf>, Vec<Row>> = BTreeMap::new();
+        for row in list::rows(cache, &paths, true) {
+            by_repo.entry(row.repo.clone()).or_default().push(row);
+        }
+        let mut groups: Vec<Group> = by_repo
+            .into_iter()
+            .map(|(repo, rows)| {
+                let mut items: Vec<Item> = rows
+                    .into_iter()
+                    .map(|row| Item {
+                        rule: cache.rule_for(&row.path),
+                        path: match &repo {
+                            Some(repo) => row.path.strip_prefix(repo).unwrap_or(&row.path).into(),
+                            None if redact => list::redact(&row.path),
+                            None => row.path,
+                        },
+                        origin: row.origin,
+                        size: row.size,
+                        added: row.added,
+                    })
+                    .collect();
+                items.sort_by(|a, b| (Reverse(a.size), &a.path).cmp(&(Reverse(b.size), &b.path)));
+                Group {
+                    size: items.iter().filter_map(|i| i.size).sum(),
+                    repo: repo.map(|r| if redact { list::redact_dir(&r) } else { r }),
+                    items,
+                }
+            })
+            .collect();
+        groups.sort_by(|a, b| {
+            (a.repo.is_none(), Reverse(a.size), &a.repo).cmp(&(
+                b.repo.is_none(),
+                Reverse(b.size),
+                &b.repo,
+            ))
+        });
+        Inventory {
+            generated_at: Local::now().fixed_offset(),
+            last_run: cache.last_run,
+            exclusion_mode: cache.exclusion_mode.to_string(),
+            groups,
+        }
+    }
+
+    fn path_count(&self) -> usize {
+        self.groups.iter().map(|g| g.items.len()).sum()
+    }
+
+    fn size(&self) -> u64 {
+        self.groups.iter().map(|g| g.size).sum()
+    }
+
+    /// The line under the title: when, how many and how much.
+    fn summary(&self) -> String {
+        let repos = self.groups.iter().filter(|g| g.repo.is_some()).count();
+        let outside: usize = self
+            .groups
+            .iter()
+            .filter(|g| g.repo.is_none())
+            .map(|g| g.items.len())
+            .sum();
+        let outside = match outside {
+            0 => String::new(),
+            n => format!(" and {n} outside them"),
+        };
+        format!(
+            "{} path(s) in {repos} repo(s){outside}, {} on disk, kept out of backups \
+             (exclusion mode `{}`; last run {}; generated {}).",
+            self.path_count(),
+            format_size(self.size()),
+            self.exclusion_mode,
+            self.last_run.map_or("never".to_string(), |t| t
+                .format("%Y-%m-%d %H:%M")
+                .to_string()),
+            self.generated_at.format("%Y-%m-%d %H:%M"),
+        )
+    }
+}
+
+impl Group {
+    fn title(&self) -> String {
+        match &self.repo {
+            Some(repo) => repo.display().to_string(),
+            None => "Outside repositories".to_string(),
+        }
+    }
+}
+
+impl Item {
+    /// The cells of its table row: path, size, rule, origin, date added.
+    fn cells(&self) -> [String; 5] {
+        [
+            self.path.display().to_string(),
+            self.size.map_or("-".to_string(), format_size),
+            self.rule.as_ref().map_or("-".to_string(), Rule::to_string),
+            self.origin.to_string(),
+            self.added
+                .map_or("-".to_string(), |t| t.format("%Y-%m-%d").to_string()),
+        ]
+    }
+}
+
+const HEADERS: [&str; 5] = ["Path", "Size", "Rule", "Origin", "Added"];
+
+fn render_markdown(inventory: &Inventory) -> String {
+    let mut text = String::from("# Time Machine exclusions\n\n");
+    let _ = writeln!(text, "{}\n", inventory.summary());
+    text.push_str("| Repository | Paths | Size |\n|---|--:|--:|\n");
+    for group in &inventory.groups {
+        let _ = writeln!(
+            text,
+            "| {} | {} | {} |",
+            md_cell(&group.title()),
+            group.items.len(),
+            format_size(group.size)
+        );
+    }
+    for group in &inventory.groups {
+        let _ = write!(
+            text,
+            "\n## {} ({})\n\n| {} |\n|---|--:|---|---|---|\n",
+            group.title(),
+            format_size(group.size),
+            HEADERS.join(" | ")
+        );
+        for item in &group.items {
+            let [path, size, rule, origin, added] = item.cells();
+            let _ = writeln!(
+                text,
+                "| {} | {size} | {} | {origin} | {added} |",
+                md_code(&path),
+                md_cell(&rule)
+            );
+        }
+    }
+    text
+}
+
+/// `text` as a Markdown code span inside a table cell: pipes escaped, and
+/// a double-backtick span around text holding a backtick.
+fn md_code(text: &str) -> String {
+    let text = md_cell(text);
+    if text.contains('`') {
+        format!("`` {text} ``")
+    } else {
+        format!("`{text}`")
+    }
+}
+
+/// `text` in a Markdown table cell: pipes escaped.
+fn md_cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+fn render_html(inventory: &Inventory) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Time Machine exclusions</title>\n<style>\n\
+         body { font-family: -apple-system, sans-serif; margin: 2em; }\n\
+         table { border-collapse: collapse; margin-bottom: 2em; }\n\
+         th, td { border: 1px solid #ccc; padding: 0.25em 0.5em; text-align: left; }\n\
+         td.size { text-align: right; white-space: nowrap; }\n\
+         </style>\n</head>\n<body>\n<h1>Time Machine exclusions</h1>\n",
+    );
+    let _ = writeln!(html, "<p>{}</p>", xml_escape(&inventory.summary()));
+    html.push_str("<table>\n<tr><th>Repository</th><th>Paths</th><th>Size</th></tr>\n");
+    for group in &inventory.groups {
+        let _ = writeln!(
+            html,
+            "<tr><td><code>{}</code></td><td class=\"size\">{}</td><td class=\"size\">{}</td></tr>",
+            xml_escape(&group.title()),
+            group.items.len(),
+            format_size(group.size)
+        );
+    }
+    html.push_str("</table>\n");
+    for group in &inventory.groups {
+        let _ = writeln!(
+            html,
+            "<h2>{} ({})</h2>\n<table>\n<tr>{}</tr>",
+            xml_escape(&group.title()),
+            format_size(group.size),
+            HEADERS.map(|h| format!("<th>{h}</th>")).concat()
+        );
+        for item in &group.items {
+            let [path, size, rule, origin, added] = item.cells().map(|c| xml_escape(&c));
+            let _ = writeln!(
+                html,
+                "<tr><td><code>{path}</code></td><td class=\"size\">{size}</td>\
+                 <td>{rule}</td><td>{origin}</td><td>{added}</td></tr>"
+            );
+        }
+        html.push_str("</table>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn render_arq(paths: &[PathBuf]) -> String {
+    let fragment = serde_json::json!({ "excludedItemPaths": paths });
+    let mut text = serde_json::to_string_pretty(&fragment).expect("serializing paths");
+    text.push('\n');
+    text
+}
+
+/// Backblaze matches rules against the lowercased path without its first
+/// character (the leading `/`); directories end in `/` so that only their
+/// contents match, not siblings sharing the prefix.
+fn render_backblaze(paths: &[PathBuf]) -> String {
+    let mut text = String::from("<bzexclusions>\n");
+    for path in paths {
+        let mut prefix = path.to_string_lossy().to_lowercase();
+        if path.is_dir() && !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        let prefix = prefix.strip_prefix('/').unwrap_or(&prefix);
+        text.push_str(&format!(
+            "  <excludefname_rule plat=\"mac\" osVers=\"*\" ruleIsOptional=\"t\" \
+             skipFirstCharThenStartsWith=\"{}\" contains_1=\"*\" contains_2=\"*\" \
+             doesNotContain=\"*\" endsWith=\"*\" hasFileExtension=\"*\" />\n",
+            xml_escape(prefix)
+        ));
+    }
+    text.push_str("</bzexclusions>\n");
+    text
+}
+
+/// Escape `text` for use in an XML (or HTML) attribute value or text.
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_exports_list_every_path() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path().join("Code/R&D/node_modules");
+        fs::create_dir_all(&dir).unwrap();
+        let file = tmp.path().join("Code/app.log");
+        let cache = Cache {
+            paths: vec![file.clone(), dir.clone()],
+            ..Cache::empty()
+        };
+
+        let arq: serde_json::Value =
+            serde_json::from_str(&render(ExportFormat::Arq, &cache, false)).unwrap();
+        assert_eq!(arq["excludedItemPaths"][0], dir.to_string_lossy().as_ref());
+
+        let backblaze = render(ExportFormat::Backblaze, &cache, false);
+        let prefix = |p: &PathBuf| p.to_string_lossy().to_lowercase()[1..].to_string();
+        assert!(backblaze.contains(&format!(
+            "skipFirstCharThenStartsWith=\"{}/\"",
+            prefix(&dir).replace('&', "&amp;")
+        )));
+        assert!(backblaze.contains(&format!(
+            "skipFirstCharThenStartsWith=\"{}\"",
+            prefix(&file)
+        )));
+        assert_eq!(backblaze.matches("<excludefname_rule").count(), 2);
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+/// Prefix of external subcommand executables: `letitgo foo` runs
+/// `letitgo-foo` from `PATH`, as `cargo foo` runs `cargo-foo`.
+pub const EXTERNAL_PREFIX: &str = "letitgo-";
+
+// An external subcommand is started with these set, so that it finds the
+// files letitgo would use without parsing the global flags.
+
+/// The config file, after `--config` and `--root`.
+pub const CONFIG_ENV: &str = "LETITGO_CONFIG";
+/// The cache file, after `--root`.
+pub const CACHE_ENV: &str = "LETITGO_CACHE";
+/// The instance lockfile, after `--root`.
+pub const LOCK_ENV: &str = "LETITGO_LOCK";
+/// The `--root` directory; unset without it.
+pub const ROOT_ENV: &str = "LETITGO_ROOT";
+/// The `letitgo` binary that started the subcommand, for calling back into it.
+pub const EXE_ENV: &str = "LETITGO_EXE";
+/// The version of that binary.
+pub const VERSION_ENV: &str = "LETITGO_VERSION";
+
+/// The directories of `PATH`.
+pub fn search_path() -> Vec<PathBuf> {
+    env::var_os("PATH")
+        .map(|path| env::split_paths(&path).collect())
+        .unwrap_or_default()
+}
+
+/// The executable implementing subcommand `name`: the first
+/// `letitgo-<name>` in `dirs` that is an executable file.
+pub fn find(name: &str, dirs: &[PathBuf]) -> Option<PathBuf> {
+    dirs.iter()
+        .map(|dir| dir.join(format!("{EXTERNAL_PREFIX}{name}")))
+        .find(|path| is_executable(path))
+}
+
+/// Every external subcommand in `dirs` by name, each with the executable
+/// [`find`] picks for it.
+pub fn list(dirs: &[PathBuf]) -> BTreeMap<String, PathBuf> {
+    let mut found = BTreeMap::new();
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Some(name) = entry
+                .file_name()
+                .to_str()
+                .and_then(|n| n.strip_prefix(EXTERNAL_PREFIX))
+                .map(str::to_string)
+            else {
+                continue;
+            };
+            if !name.is_empty() && !found.contains_key(&name) && is_executable(&path) {
+                found.insert(name, path);
+            }
+        }
+    }
+    found
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+/// The name and arguments clap collected for an external subcommand.
+pub fn split_args(args: &[OsString]) -> Option<(String, &[OsString])> {
+    let (name, rest) = args.split_first()?;
+    Some((name.to_str()?.to_string(), rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_executable_on_path_wins() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (first, second) = (tmp.path().join("first"), tmp.path().join("second"));
+        let script = |dir: &Path, name: &str, mode: u32| {
+            fs::create_dir_all(dir).unwrap();
+            let path = dir.join(name);
+            fs::write(&path, "#!/bin/sh\n").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+            path
+        };
+        script(&first, "letitgo-gui", 0o644);
+        let gui = script(&second, "letitgo-gui", 0o755);
+        let export = script(&first, "letitgo-export-md", 0o755);
+        script(&first, "cargo-gui", 0o755);
+        let dirs = [first, second, tmp.path().join("missing")];
+
+        // Not executable, so the later directory's copy is used
+        assert_eq!(find("gui", &dirs), Some(gui.clone()));
+        assert_eq!(find("missing", &dirs), None);
+        assert_eq!(
+            list(&dirs),
+            BTreeMap::from([("export-md".to_string(), export), ("gui".to_string(), gui)])
+        );
+    }
+}
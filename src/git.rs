@@ -0,0 +1,747 @@
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    fs,
+    io::{self, Read},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+};
+use tracing::warn;
+use walkdir::WalkDir;
+
+use crate::config::dirs_home;
+
+/// Where a working tree keeps its Git metadata.
+///
+/// For a regular checkout both fields point at `<repo>/.git`.  For a linked
+/// worktree (`git worktree add`) or a submodule, `<repo>/.git` is a *file*
+/// containing `gitdir: <path>`; `git_dir` is that target, and `common_dir`
+/// is the directory shared by all worktrees of the same repository (it holds
+/// `config`, `info/exclude`, `objects/`, …).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitLayout {
+    /// Per-worktree Git directory (`HEAD`, `index`, …).
+    pub git_dir: PathBuf,
+    /// Shared Git directory (`config`, `info/exclude`, `objects/`, …).
+    pub common_dir: PathBuf,
+}
+
+impl GitLayout {
+    /// Path to the repository-wide `info/exclude` file.
+    pub fn info_exclude(&self) -> PathBuf {
+        self.common_dir.join("info/exclude")
+    }
+
+    /// Path to the repository `config` file.
+    pub fn config_file(&self) -> PathBuf {
+        self.common_dir.join("config")
+    }
+
+    /// Whether this is a linked worktree, with a Git directory of its own
+    /// under `<common_dir>/worktrees/`.
+    pub fn is_linked_worktree(&self) -> bool {
+        self.git_dir != self.common_dir
+    }
+
+    /// Whether this is a submodule checkout: its Git directory lives in the
+    /// `modules/` directory of another repository's Git directory.
+    pub fn is_submodule(&self) -> bool {
+        self.common_dir.ancestors().skip(1).any(|dir| {
+            dir.file_name() == Some(OsStr::new("modules"))
+                && dir
+                    .parent()
+                    .is_some_and(|git_dir| git_dir.join("HEAD").is_file())
+        })
+    }
+
+    /// The working trees added to this repository with `git worktree add`,
+    /// sorted, as recorded in `<common_dir>/worktrees/*/gitdir`.  Those
+    /// whose `.git` file is gone (what `git worktree prune` drops) are left
+    /// out.
+    pub fn linked_worktrees(&self) -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir(self.common_dir.join("worktrees")) else {
+            return Vec::new();
+        };
+        let mut worktrees: Vec<PathBuf> = entries
+            .flatten()
+            .filter_map(|entry| fs::read_to_string(entry.path().join("gitdir")).ok())
+            .map(|text| PathBuf::from(text.trim()))
+            .filter(|dot_git| dot_git.is_file())
+            .filter_map(|dot_git| {
+                dot_git
+                    .parent()
+                    .map(|dir| clean_existing(dir.to_path_buf()))
+            })
+            .collect();
+        worktrees.sort();
+        worktrees
+    }
+}
+
+/// Return the nearest ancestor of `path` (inclusive) that has a `.git` entry.
+pub fn find_repo(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .find(|a| a.join(".git").exists())
+        .map(Path::to_path_buf)
+}
+
+/// Resolve the Git directory layout for the working tree rooted at `repo_root`.
+///
+/// Returns `None` if `repo_root/.git` does not exist or is a malformed
+/// `gitdir:` file.
+pub fn resolve_layout(repo_root: &Path) -> Option<GitLayout> {
+    let dot_git = repo_root.join(".git");
+    let meta = fs::metadata(&dot_git).ok()?;
+
+    let git_dir = if meta.is_dir() {
+        dot_git
+    } else {
+        let text = fs::read_to_string(&dot_git).ok()?;
+        let Some(target) = parse_gitdir_file(&text) else {
+            warn!("Malformed .git file: {}", dot_git.display());
+            return None;
+        };
+        let target = PathBuf::from(target);
+        if target.is_absolute() {
+            target
+        } else {
+            repo_root.join(target)
+        }
+    };
+
+    // Linked worktrees have a `commondir` file pointing (usually relatively)
+    // at the main repository's `.git` directory.
+    let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(text) => {
+            let target = PathBuf::from(text.trim());
+            if target.is_absolute() {
+                target
+            } else {
+                git_dir.join(target)
+            }
+        }
+        Err(_) => git_dir.clone(),
+    };
+
+    Some(GitLayout {
+        git_dir: clean_existing(git_dir),
+        common_dir: clean_existing(common_dir),
+    })
+}
+
+/// Extract the target of a `gitdir: <path>` line from a `.git` file.
+fn parse_gitdir_file(text: &str) -> Option<&str> {
+    text.lines()
+        .find_map(|line| line.trim().strip_prefix("gitdir:"))
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+}
+
+/// Canonicalize `path` if it exists, otherwise normalize it lexically.
+fn clean_existing(path: PathBuf) -> PathBuf {
+    use path_clean::PathClean;
+    fs::canonicalize(&path).unwrap_or_else(|_| path.clean())
+}
+
+/// One `key = value` line of a Git config file, with its enclosing section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigEntry {
+    /// Lower-cased section name (`core`, `remote`, …).
+    pub section: String,
+    /// Subsection as written, e.g. `origin` in `[remote "origin"]`.
+    pub subsection: Option<String>,
+    /// Lower-cased key name.
+    pub key: String,
+    pub value: String,
+}
+
+/// Parse the entries of a Git config file.
+///
+/// Handles `[section]` and `[section "subsection"]` headers with `key = value`
+/// lines, which is all we need here; includes and multi-line values are not
+/// supported.  Section and key names are case-insensitive, as in Git.
+pub fn read_config(config_path: &Path) -> Vec<ConfigEntry> {
+    let Ok(text) = fs::read_to_string(config_path) else {
+        return Vec::new();
+    };
+    let mut entries = Vec::new();
+    let mut section = String::new();
+    let mut subsection = None;
+    for raw_line in text.lines() {
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let (name, sub) = match header.split_once(char::is_whitespace) {
+                Some((name, sub)) => (name, Some(sub.trim().trim_matches('"').to_string())),
+                None => (header.trim(), None),
+            };
+            section = name.to_ascii_lowercase();
+            subsection = sub;
+            continue;
+        }
+        if let Some((k, v)) = line.split_once('=') {
+            entries.push(ConfigEntry {
+                section: section.clone(),
+                subsection: subsection.clone(),
+                key: k.trim().to_ascii_lowercase(),
+                value: v.trim().trim_matches('"').to_string(),
+            });
+        }
+    }
+    entries
+}
+
+/// Read a single `section.key` value (outside any subsection) from a Git
+/// config file.  The last occurrence wins.
+pub fn read_config_value(config_path: &Path, section: &str, key: &str) -> Option<String> {
+    read_config(config_path)
+        .into_iter()
+        .rev()
+        .find(|e| {
+            e.subsection.is_none()
+                && e.section.eq_ignore_ascii_case(section)
+                && e.key.eq_ignore_ascii_case(key)
+        })
+        .map(|e| e.value)
+}
+
+/// Return the names of remotes that have a `url` configured, sorted and
+/// each once (a remote may have several `url`s or sections).
+pub fn remote_names(layout: &GitLayout) -> Vec<String> {
+    let mut names: Vec<String> = read_config(&layout.config_file())
+        .into_iter()
+        .filter(|e| e.section == "remote" && e.key == "url")
+        .filter_map(|e| e.subsection)
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+/// Return `true` if any remote has a `url` or `pushurl` to push to.
+pub fn has_pushable_remote(layout: &GitLayout) -> bool {
+    read_config(&layout.config_file())
+        .iter()
+        .any(|e| e.section == "remote" && (e.key == "url" || e.key == "pushurl"))
+}
+
+/// Whether a repository's work can be recovered from somewhere else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackingStatus {
+    /// At least one remote with a `url` or `pushurl` is configured.
+    pub has_remote: bool,
+    /// The commit checked out at `HEAD` is the tip of some remote-tracking
+    /// branch, i.e. there are no unpushed commits on the current branch.
+    pub head_pushed: bool,
+}
+
+/// Inspect remotes and refs to decide how well-backed a repository is.
+///
+/// Works purely from files in the gitdir (`config`, `HEAD`, loose refs and
+/// `packed-refs`) so no `git` binary is needed.
+pub fn backing_status(layout: &GitLayout) -> BackingStatus {
+    let has_remote = has_pushable_remote(layout);
+    let head_pushed = has_remote
+        && head_commit(layout).is_some_and(|head| remote_tracking_commits(layout).contains(&head));
+    BackingStatus {
+        has_remote,
+        head_pushed,
+    }
+}
+
+/// Whether no tracked file under the working tree `root` was modified or
+/// deleted since it was last staged, judged like `git status` does before
+/// hashing: by the size and modification time its index entry recorded.
+/// Staged but uncommitted changes and untracked files are not seen.  An
+/// unreadable index counts as modified.
+pub fn worktree_clean(layout: &GitLayout, root: &Path) -> bool {
+    let data = match fs::read(layout.git_dir.join("index")) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return true,
+        Err(_) => return false,
+    };
+    let Some(entries) = index_entries(&data, hash_len(layout)) else {
+        return false;
+    };
+    entries.iter().all(|entry| {
+        // Sparse directories, submodules and skip-worktree entries have no
+        // file of their own to compare
+        if entry.skip_worktree || matches!(entry.mode & 0o170000, 0o040000 | 0o160000) {
+            return true;
+        }
+        root.join(&entry.path)
+            .symlink_metadata()
+            .is_ok_and(|meta| entry.matches(&meta))
+    })
+}
+
+/// Resolve `HEAD` to a commit id, following one level of symbolic ref.
+pub fn head_commit(layout: &GitLayout) -> Option<String> {
+    let head = fs::read_to_string(layout.git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref:") {
+        Some(ref_name) => resolve_ref(layout, ref_name.trim()),
+        None => Some(head.to_string()),
+    }
+}
+
+/// Look up `ref_name` (e.g. `refs/heads/main`) as a loose ref, then in
+/// `packed-refs`.
+fn resolve_ref(layout: &GitLayout, ref_name: &str) -> Option<String> {
+    if let Ok(id) = fs::read_to_string(layout.common_dir.join(ref_name)) {
+        return Some(id.trim().to_string());
+    }
+    packed_refs(layout)
+        .into_iter()
+        .find(|(name, _)| name == ref_name)
+        .map(|(_, id)| id)
+}
+
+/// Parse `packed-refs` into `(ref name, commit id)` pairs.
+fn packed_refs(layout: &GitLayout) -> Vec<(String, String)> {
+    let Ok(text) = fs::read_to_string(layout.common_dir.join("packed-refs")) else {
+        return Vec::new();
+    };
+    text.lines()
+        .filter(|l| !l.starts_with('#') && !l.starts_with('^'))
+        .filter_map(|l| l.split_once(' '))
+        .map(|(id, name)| (name.trim().to_string(), id.to_string()))
+        .collect()
+}
+
+/// Collect the commit ids of every remote-tracking branch.
+fn remote_tracking_commits(layout: &GitLayout) -> HashSet<String> {
+    let mut commits: HashSet<String> = packed_refs(layout)
+        .into_iter()
+        .filter(|(name, _)| name.starts_with("refs/remotes/"))
+        .map(|(_, id)| id)
+        .collect();
+    let remotes_dir = layout.common_dir.join("refs/remotes");
+    for entry in WalkDir::new(&remotes_dir).into_iter().flatten() {
+        // `refs/remotes/<remote>/HEAD` is symbolic; skip anything that is not
+        // a plain commit id.
+        if entry.file_type().is_file()
+            && let Ok(id) = fs::read_to_string(entry.path())
+            && !id.starts_with("ref:")
+        {
+            commits.insert(id.trim().to_string());
+        }
+    }
+    commits
+}
+
+/// Return the `core.excludesFile` Git uses for the repository, with a
+/// leading `~` expanded: the first one set in the repository's config,
+/// `~/.gitconfig` and `$XDG_CONFIG_HOME/git/config`, else Git's default
+/// `$XDG_CONFIG_HOME/git/ignore` (`$XDG_CONFIG_HOME` defaulting to
+/// `~/.config`).
+pub fn core_excludes_file(layout: &GitLayout) -> Option<PathBuf> {
+    let xdg_config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from);
+    excludes_file_for(layout, dirs_home().as_deref(), xdg_config_home.as_deref())
+}
+
+/// [`core_excludes_file`] for the given home directory and
+/// `$XDG_CONFIG_HOME`.
+fn excludes_file_for(
+    layout: &GitLayout,
+    home: Option<&Path>,
+    xdg_config_home: Option<&Path>,
+) -> Option<PathBuf> {
+    let xdg_git = xdg_config_home
+        .map(Path::to_path_buf)
+        .or_else(|| home.map(|home| home.join(".config")))
+        .map(|dir| dir.join("git"));
+    let configs = [
+        Some(layout.config_file()),
+        home.map(|home| home.join(".gitconfig")),
+        xdg_git.as_ref().map(|dir| dir.join("config")),
+    ];
+    let configured = configs
+        .into_iter()
+        .flatten()
+        .find_map(|config| read_config_value(&config, "core", "excludesfile"));
+    match configured {
+        Some(value) => match (value.strip_prefix("~/"), home) {
+            (Some(rest), Some(home)) => Some(home.join(rest)),
+            _ => Some(PathBuf::from(value)),
+        },
+        None => xdg_git.map(|dir| dir.join("ignore")),
+    }
+}
+
+/// Whether the working tree is a sparse checkout: `core.sparseCheckout` is
+/// set — in the worktree's `config.worktree`, where `git sparse-checkout`
+/// puts it, or the shared config — and `info/sparse-checkout` exists.
+pub fn is_sparse_checkout(layout: &GitLayout) -> bool {
+    let enabled = [layout.git_dir.join("config.worktree"), layout.config_file()]
+        .iter()
+        .find_map(|config| read_config_value(config, "core", "sparsecheckout"))
+        .is_some_and(|v| matches!(v.to_ascii_lowercase().as_str(), "true" | "yes" | "on" | "1"));
+    enabled && layout.git_dir.join("info/sparse-checkout").is_file()
+}
+
+/// The files that change when a sparse checkout materializes other paths:
+/// its pattern file, and `HEAD`, rewritten by a switch to another tree.
+pub fn sparse_checkout_files(layout: &GitLayout) -> [PathBuf; 2] {
+    [
+        layout.git_dir.join("info/sparse-checkout"),
+        layout.git_dir.join("HEAD"),
+    ]
+}
+
+/// The paths the index of the working tree tracks, relative to its root,
+/// or `None` when the index cannot be read.  A repository without an index
+/// (nothing added yet) tracks nothing.  A sparse index lists a directory
+/// outside the sparse-checkout cone as a single entry.
+pub fn tracked_paths(layout: &GitLayout) -> Option<HashSet<PathBuf>> {
+    let data = match fs::read(layout.git_dir.join("index")) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Some(HashSet::new()),
+        Err(e) => {
+            warn!("Cannot read the index of {}: {e}", layout.git_dir.display());
+            return None;
+        }
+    };
+    let paths = parse_index(&data, hash_len(layout));
+    if paths.is_none() {
+        warn!("Unsupported index format in {}", layout.git_dir.display());
+    }
+    paths
+}
+
+/// The length in bytes of the repository's object names.
+fn hash_len(layout: &GitLayout) -> usize {
+    match read_config_value(&layout.config_file(), "extensions", "objectformat") {
+        Some(format) if format.eq_ignore_ascii_case("sha256") => 32,
+        _ => 20,
+    }
+}
+
+/// How many entries the index of the working tree lists, read from its
+/// header alone; `None` without a readable index.
+pub fn index_entry_count(layout: &GitLayout) -> Option<u32> {
+    let mut header = [0; 12];
+    fs::File::open(layout.git_dir.join("index"))
+        .and_then(|mut file| file.read_exact(&mut header))
+        .ok()?;
+    (header[..4] == *b"DIRC")
+        .then(|| u32::from_be_bytes([header[8], header[9], header[10], header[11]]))
+}
+
+/// The entry paths of a version 2–4 index file (`gitformat-index(5)`),
+/// whose object names are `hash_len` bytes long.
+fn parse_index(data: &[u8], hash_len: usize) -> Option<HashSet<PathBuf>> {
+    Some(
+        index_entries(data, hash_len)?
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect(),
+    )
+}
+
+/// An entry of the index: a path and the stat data it was staged with.
+#[derive(Debug)]
+struct IndexEntry {
+    path: PathBuf,
+    mtime: (u32, u32),
+    mode: u32,
+    size: u32,
+    skip_worktree: bool,
+}
+
+impl IndexEntry {
+    /// Whether `meta` still looks like the file this entry was staged from.
+    /// The index keeps the low 32 bits of the size and seconds, and no
+    /// nanoseconds where git was built without them.
+    fn matches(&self, meta: &fs::Metadata) -> bool {
+        meta.size() as u32 == self.size
+            && meta.mtime() as u32 == self.mtime.0
+            && (self.mtime.1 == 0 || meta.mtime_nsec() as u32 == self.mtime.1)
+    }
+}
+
+/// The entries of a version 2–4 index file, whose object names are
+/// `hash_len` bytes long.
+fn index_entries(data: &[u8], hash_len: usize) -> Option<Vec<IndexEntry>> {
+    let u32_at = |at: usize| Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?));
+    if data.get(..4)? != b"DIRC" {
+        return None;
+    }
+    let version = u32_at(4)?;
+    if !(2..=4).contains(&version) {
+        return None;
+    }
+    let count = u32_at(8)? as usize;
+    let mut entries = Vec::with_capacity(count);
+    let mut previous: Vec<u8> = Vec::new();
+    let mut at = 12;
+    for _ in 0..count {
+        let start = at;
+        // 40 bytes of stat data, the object name, then 16 bits of flags
+        let flags_at = start + 40 + hash_len;
+        let flags = u16::from_be_bytes(data.get(flags_at..flags_at + 2)?.try_into().ok()?);
+        at = flags_at + 2;
+        let mut skip_worktree = false;
+        if version >= 3 && flags & 0x4000 != 0 {
+            skip_worktree = data.get(at)? & 0x40 != 0;
+            at += 2;
+        }
+        let path = if version == 4 {
+            // Prefix-compressed: drop the end of the previous path, append
+            let (strip, len) = offset_varint(data.get(at..)?)?;
+            at += len;
+            let end = at + data.get(at..)?.iter().position(|&b| b == 0)?;
+            let keep = previous.len().checked_sub(strip)?;
+            let mut path = previous[..keep].to_vec();
+            path.extend_from_slice(&data[at..end]);
+            at = end + 1;
+            path
+        } else {
+            let end = at + data.get(at..)?.iter().position(|&b| b == 0)?;
+            let path = data[at..end].to_vec();
+            // NUL-padded to a multiple of 8 bytes
+            at = start + (end - start + 8) / 8 * 8;
+            path
+        };
+        entries.push(IndexEntry {
+            path: PathBuf::from(OsStr::from_bytes(&path)),
+            mtime: (u32_at(start + 8)?, u32_at(start + 12)?),
+            mode: u32_at(start + 24)?,
+            size: u32_at(start + 36)?,
+            skip_worktree,
+        });
+        previous = path;
+    }
+    Some(entries)
+}
+
+/// Git's "offset" varint: each continuation adds one before shifting, so no
+/// value has two encodings.  Returns the value and its length in bytes.
+fn offset_varint(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut value = (*bytes.first()? & 0x7f) as usize;
+    let mut len = 1;
+    while bytes[len - 1] & 0x80 != 0 {
+        let byte = *bytes.get(len)?;
+        value = ((value + 1) << 7) | (byte & 0x7f) as usize;
+        len += 1;
+    }
+    Some((value, len))
+}
+
+/// An index file of `version` listing `names` (zeroed stat data and object
+/// names; for version 4, each name starts with its prefix varint).
+#[cfg(test)]
+pub(crate) fn test_index(version: u32, names: &[&[u8]]) -> Vec<u8> {
+    let mut data = b"DIRC".to_vec();
+    data.extend(version.to_be_bytes());
+    data.extend((names.len() as u32).to_be_bytes());
+    for name in names {
+        let start = data.len();
+        data.extend([0; 60]);
+        data.extend((name.len() as u16).to_be_bytes());
+        data.extend(*name);
+        data.push(0);
+        if version < 4 {
+            data.resize(start + (data.len() - start).div_ceil(8) * 8, 0);
+        }
+    }
+    data
+}
+
+/// A version 2 index staging `names` under `root` as they are now (only
+/// the stat data [`worktree_clean`] compares is filled in).
+#[cfg(test)]
+pub(crate) fn test_index_of(root: &Path, names: &[&str]) -> Vec<u8> {
+    let mut data = test_index(2, &names.iter().map(|n| n.as_bytes()).collect::<Vec<_>>());
+    let mut at = 12;
+    for name in names {
+        let meta = root.join(name).symlink_metadata().unwrap();
+        data[at + 8..at + 12].copy_from_slice(&(meta.mtime() as u32).to_be_bytes());
+        data[at + 12..at + 16].copy_from_slice(&(meta.mtime_nsec() as u32).to_be_bytes());
+        data[at + 24..at + 28].copy_from_slice(&meta.mode().to_be_bytes());
+        data[at + 36..at + 40].copy_from_slice(&(meta.size() as u32).to_be_bytes());
+        at += (62 + name.len() + 8) / 8 * 8;
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_resolve_layout_linked_worktree() {
+        let tmp = tempdir().unwrap();
+        let main = tmp.path().join("main");
+        let wt_git = main.join(".git/worktrees/feature");
+        fs::create_dir_all(&wt_git).unwrap();
+        fs::write(wt_git.join("commondir"), "../..\n").unwrap();
+
+        let worktree = tmp.path().join("feature");
+        fs::create_dir_all(&worktree).unwrap();
+        fs::write(
+            worktree.join(".git"),
+            format!("gitdir: {}\n", wt_git.display()),
+        )
+        .unwrap();
+
+        let layout = resolve_layout(&worktree).unwrap();
+        assert_eq!(layout.git_dir, fs::canonicalize(&wt_git).unwrap());
+        assert_eq!(
+            layout.common_dir,
+            fs::canonicalize(main.join(".git")).unwrap()
+        );
+        assert_eq!(
+            layout.info_exclude(),
+            fs::canonicalize(main.join(".git"))
+                .unwrap()
+                .join("info/exclude")
+        );
+    }
+
+    #[test]
+    fn test_read_config_value_section_scoping() {
+        let tmp = tempdir().unwrap();
+        let config = tmp.path().join("config");
+        fs::write(
+            &config,
+            "[user]\n\texcludesFile = wrong\n[core]\n\tbare = false\n\texcludesFile = \"~/.gitignore_global\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            read_config_value(&config, "core", "excludesfile").as_deref(),
+            Some("~/.gitignore_global")
+        );
+        assert_eq!(read_config_value(&config, "core", "missing"), None);
+    }
+
+    #[test]
+    fn test_core_excludes_file_follows_git_lookup_order() {
+        let tmp = tempdir().unwrap();
+        let home = tmp.path().join("home");
+        let xdg = tmp.path().join("xdg");
+        fs::create_dir_all(tmp.path().join("repo/.git")).unwrap();
+        fs::create_dir_all(xdg.join("git")).unwrap();
+        fs::create_dir_all(&home).unwrap();
+        let layout = resolve_layout(&tmp.path().join("repo")).unwrap();
+        let lookup = |xdg: Option<&Path>| excludes_file_for(&layout, Some(&home), xdg);
+
+        // Git's default, under $XDG_CONFIG_HOME or else ~/.config
+        assert_eq!(lookup(Some(&xdg)), Some(xdg.join("git/ignore")));
+        assert_eq!(lookup(None), Some(home.join(".config/git/ignore")));
+
+        fs::write(
+            xdg.join("git/config"),
+            "[core]\n\texcludesFile = /xdg/ignore\n",
+        )
+        .unwrap();
+        assert_eq!(lookup(Some(&xdg)), Some(PathBuf::from("/xdg/ignore")));
+        fs::write(
+            home.join(".gitconfig"),
+            "[core]\n\texcludesFile = ~/.gitignore_global\n",
+        )
+        .unwrap();
+        assert_eq!(lookup(Some(&xdg)), Some(home.join(".gitignore_global")));
+        fs::write(
+            layout.config_file(),
+            "[core]\n\texcludesFile = /repo/ignore\n",
+        )
+        .unwrap();
+        assert_eq!(lookup(Some(&xdg)), Some(PathBuf::from("/repo/ignore")));
+    }
+
+    #[test]
+    fn test_sparse_checkout_needs_the_setting_and_the_pattern_file() {
+        let tmp = tempdir().unwrap();
+        let git_dir = tmp.path().join(".git");
+        fs::create_dir_all(git_dir.join("info")).unwrap();
+        let layout = resolve_layout(tmp.path()).unwrap();
+        fs::write(git_dir.join("info/sparse-checkout"), "/*\n!/*/\n/a/\n").unwrap();
+        assert!(!is_sparse_checkout(&layout));
+
+        // `git sparse-checkout set` writes the setting per worktree
+        fs::write(
+            git_dir.join("config.worktree"),
+            "[core]\n\tsparseCheckout = true\n",
+        )
+        .unwrap();
+        assert!(is_sparse_checkout(&layout));
+        fs::remove_file(git_dir.join("info/sparse-checkout")).unwrap();
+        assert!(!is_sparse_checkout(&layout));
+    }
+
+    #[test]
+    fn test_remote_names_requires_url() {
+        let tmp = tempdir().unwrap();
+        let git_dir = tmp.path().join(".git");
+        fs::create_dir_all(&git_dir).unwrap();
+        fs::write(
+            git_dir.join("config"),
+            "[core]\n\tbare = false\n[remote \"origin\"]\n\turl = git@example.com:a/b.git\n\tfetch = +refs/heads/*:refs/remotes/origin/*\n[remote \"stub\"]\n\tfetch = +refs/heads/*:refs/remotes/stub/*\n[remote \"backup\"]\n\turl = /mnt/b.git\n[remote \"origin\"]\n\turl = https://example.com/a/b.git\n",
+        )
+        .unwrap();
+
+        let layout = resolve_layout(tmp.path()).unwrap();
+        assert_eq!(
+            remote_names(&layout),
+            vec!["backup".to_string(), "origin".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_backing_status_compares_head_with_remote_refs() {
+        let tmp = tempdir().unwrap();
+        let git_dir = tmp.path().join(".git");
+        fs::create_dir_all(git_dir.join("refs/heads")).unwrap();
+        fs::create_dir_all(git_dir.join("refs/remotes/origin")).unwrap();
+        fs::write(
+            git_dir.join("config"),
+            "[remote \"origin\"]\n\turl = https://example.com/r.git\n",
+        )
+        .unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(git_dir.join("refs/heads/main"), "aaaa\n").unwrap();
+        // origin/main lives in packed-refs; origin/HEAD is symbolic
+        fs::write(
+            git_dir.join("packed-refs"),
+            "# pack-refs with: peeled fully-peeled sorted\nbbbb refs/remotes/origin/main\n",
+        )
+        .unwrap();
+        fs::write(
+            git_dir.join("refs/remotes/origin/HEAD"),
+            "ref: refs/remotes/origin/main\n",
+        )
+        .unwrap();
+        let layout = resolve_layout(tmp.path()).unwrap();
+
+        // Local main is ahead of origin/main
+        let status = backing_status(&layout);
+        assert!(status.has_remote);
+        assert!(!status.head_pushed);
+
+        // After a push, origin/main matches the loose local ref
+        fs::write(git_dir.join("refs/remotes/origin/main"), "aaaa\n").unwrap();
+        assert!(backing_status(&layout).head_pushed);
+    }
+
+    #[test]
+    fn test_parse_index_reads_padded_and_prefix_compressed_paths() {
+        let paths = |names: &[&str]| names.iter().map(PathBuf::from).collect::<HashSet<_>>();
+
+        let v2 = test_index(2, &[b"a/bb/x.mp4", b"top"]);
+        assert_eq!(parse_index(&v2, 20), Some(paths(&["a/bb/x.mp4", "top"])));
+        // `a/y` keeps `a/` of `a/bb/x.mp4` (strips 8 bytes), `c.iso` nothing
+        let v4 = test_index(4, &[b"\x00a/bb/x.mp4", b"\x08y", b"\x03c.iso"]);
+        assert_eq!(
+            parse_index(&v4, 20),
+            Some(paths(&["a/bb/x.mp4", "a/y", "c.iso"]))
+        );
+        assert_eq!(parse_index(b"DIRC\0\0\0\x02\0\0\0\x01", 20), None);
+    }
+}
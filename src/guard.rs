@@ -0,0 +1,112 @@
+use directories::BaseDirs;
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+/// Why a path must not be excluded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// The path is a search root, the home directory, or a repository root
+    /// or one of its ancestors.  Never excluded.
+    Protected(&'static str),
+    /// The path has fewer than `min_exclusion_depth` components.  Excluded
+    /// only with `--force`.
+    TooShallow(usize),
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::Protected(what) => write!(f, "it is {what}"),
+            Violation::TooShallow(depth) => write!(f, "it is only {depth} level(s) deep"),
+        }
+    }
+}
+
+/// Hard limits on what a run may exclude, guarding against ignore rules
+/// like `/*` in a repository at `~`.
+#[derive(Debug, Clone)]
+pub struct SafetyRails {
+    protected: HashMap<PathBuf, &'static str>,
+    min_depth: usize,
+}
+
+impl SafetyRails {
+    /// Protect the home directory, every search root, and every repository
+    /// root together with its ancestors.  Paths with fewer than `min_depth`
+    /// normal components are flagged as too shallow (`0` disables that check).
+    pub fn new<'a>(
+        search_roots: impl IntoIterator<Item = &'a PathBuf>,
+        repo_roots: impl IntoIterator<Item = &'a PathBuf>,
+        min_depth: usize,
+    ) -> Self {
+        let mut protected = HashMap::new();
+        for repo in repo_roots {
+            for ancestor in repo.ancestors() {
+                protected.insert(
+                    ancestor.to_path_buf(),
+                    "a repository root or one of its ancestors",
+                );
+            }
+        }
+        for root in search_roots {
+            protected.insert(root.clone(), "a search root");
+        }
+        if let Some(dirs) = BaseDirs::new() {
+            protected.insert(dirs.home_dir().to_path_buf(), "the home directory");
+        }
+        SafetyRails {
+            protected,
+            min_depth,
+        }
+    }
+
+    /// Return why `path` must not be excluded, if anything.
+    pub fn check(&self, path: &Path) -> Option<Violation> {
+        if let Some(what) = self.protected.get(path) {
+            return Some(Violation::Protected(what));
+        }
+        let depth = path
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .count();
+        (depth < self.min_depth).then_some(Violation::TooShallow(depth))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safety_rails() {
+        let root = PathBuf::from("/Users/alice/projects");
+        let repo = PathBuf::from("/Users/alice/projects/web/app");
+        let rails = SafetyRails::new([&root], [&repo], 4);
+
+        assert_eq!(
+            rails.check(&root),
+            Some(Violation::Protected("a search root"))
+        );
+        for path in [
+            "/Users/alice/projects/web/app",
+            "/Users/alice/projects/web",
+            "/",
+        ] {
+            assert!(matches!(
+                rails.check(Path::new(path)),
+                Some(Violation::Protected(_))
+            ));
+        }
+        assert_eq!(
+            rails.check(Path::new("/Users/alice/Documents")),
+            Some(Violation::TooShallow(3))
+        );
+        assert_eq!(
+            rails.check(Path::new("/Users/alice/projects/web/app/target")),
+            None
+        );
+        assert_eq!(rails.check(Path::new("/Users/alice/projects/other")), None);
+    }
+}
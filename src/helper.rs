@@ -0,0 +1,705 @@
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::CString,
+    fs,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{MetadataExt, PermissionsExt},
+        io::{AsRawFd, FromRawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Component, Path, PathBuf},
+    process::Command,
+    sync::{Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+
+use crate::{tmutil::ExclusionManager, warnings::Warning};
+
+/// launchd label of the helper daemon.
+pub const HELPER_LABEL: &str = "io.github.ifsheldon.letitgo.helper";
+
+/// The launchd job `privileged-helper install` writes.
+pub const HELPER_PLIST: &str = "/Library/LaunchDaemons/io.github.ifsheldon.letitgo.helper.plist";
+
+/// Where the helper binary is installed: a root-owned copy, so that no user
+/// can replace what launchd runs as root.
+pub const HELPER_BINARY: &str = "/Library/PrivilegedHelperTools/io.github.ifsheldon.letitgo.helper";
+
+/// The socket the helper listens on.
+pub const HELPER_SOCKET: &str = "/var/run/io.github.ifsheldon.letitgo.helper.sock";
+
+/// How long the helper waits for a connected client to send its whole
+/// request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The longest request the helper reads, newline included; a run's paths
+/// fit many times over.
+const MAX_REQUEST_BYTES: u64 = 4 << 20;
+
+/// How many connections the helper answers at once; more wait to be
+/// accepted.
+const MAX_CONNECTIONS: usize = 8;
+
+/// One request to the helper, sent as a line of JSON.  Only fixed-path
+/// exclusions can be changed: sticky ones need no privileges.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+pub enum Request {
+    /// Check that the helper is up and the caller is allowed.
+    Ping,
+    Add {
+        paths: Vec<PathBuf>,
+    },
+    Remove {
+        paths: Vec<PathBuf>,
+    },
+}
+
+/// The helper's answer to a [`Request`], a line of JSON.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Response {
+    /// Why the request was refused or failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Paths the helper worked around (see [`ExclusionManager::take_warnings`]).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<Warning>,
+}
+
+// ─── Server (runs as root) ───────────────────────────────────────────────────
+
+/// Bind the helper socket at `path`, replacing a stale one.  Anyone may
+/// connect: [`serve`] checks who is asking.
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    match fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("removing {}", path.display())),
+    }
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("binding {}", path.display()))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o666))
+        .with_context(|| format!("setting permissions of {}", path.display()))?;
+    Ok(listener)
+}
+
+/// Answer the requests on `listener` with `manager`, each connection on a
+/// thread of its own and at most [`MAX_CONNECTIONS`] at once: only from
+/// root and the users in `allowed_uids`, and only for paths the requesting
+/// user owns (see [`authorize`]).  Runs until the listener fails.
+pub fn serve(
+    listener: UnixListener,
+    allowed_uids: &[u32],
+    manager: &dyn ExclusionManager,
+) -> Result<()> {
+    let (active, done) = (Mutex::new(0), Condvar::new());
+    thread::scope(|s| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Accepting a helper connection failed: {e}");
+                    continue;
+                }
+            };
+            let mut count = done
+                .wait_while(active.lock().expect("helper lock poisoned"), |n| {
+                    *n >= MAX_CONNECTIONS
+                })
+                .expect("helper lock poisoned");
+            *count += 1;
+            drop(count);
+            let (active, done) = (&active, &done);
+            s.spawn(move || {
+                if let Err(e) = answer(stream, allowed_uids, manager) {
+                    debug!("Helper request failed: {e:#}");
+                }
+                *active.lock().expect("helper lock poisoned") -= 1;
+                done.notify_one();
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Read one request from `stream` and write the response.
+fn answer(stream: UnixStream, allowed_uids: &[u32], manager: &dyn ExclusionManager) -> Result<()> {
+    let uid = peer_uid(&stream).context("reading the client's uid")?;
+    answer_uid(stream, uid, allowed_uids, manager)
+}
+
+/// [`answer`] the client `uid`.  Users who may not use the helper are
+/// refused before anything is read from them.
+fn answer_uid(
+    stream: UnixStream,
+    uid: u32,
+    allowed_uids: &[u32],
+    manager: &dyn ExclusionManager,
+) -> Result<()> {
+    let result = check_caller(uid, allowed_uids)
+        .and_then(|()| read_request(&stream))
+        .and_then(|line| handle(&line, uid, allowed_uids, manager));
+    let response = match result {
+        Ok(warnings) => Response {
+            error: None,
+            warnings,
+        },
+        Err(e) => {
+            warn!("Request from uid {uid} failed: {e:#}");
+            Response {
+                error: Some(format!("{e:#}")),
+                warnings: Vec::new(),
+            }
+        }
+    };
+    let mut stream = stream;
+    writeln!(stream, "{}", serde_json::to_string(&response)?)?;
+    Ok(())
+}
+
+/// Fail unless `uid` is root or one of `allowed_uids`.
+fn check_caller(uid: u32, allowed_uids: &[u32]) -> Result<()> {
+    if uid != 0 && !allowed_uids.contains(&uid) {
+        bail!("uid {uid} may not use the privileged helper");
+    }
+    Ok(())
+}
+
+/// The first line of `stream`, without its newline: at most
+/// [`MAX_REQUEST_BYTES`], sent within [`REQUEST_TIMEOUT`] all told.
+fn read_request(stream: &UnixStream) -> Result<String> {
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    let mut reader = stream.take(MAX_REQUEST_BYTES);
+    let mut request = Vec::new();
+    let mut chunk = [0; 8192];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            bail!(
+                "no complete request within {}s",
+                REQUEST_TIMEOUT.as_secs_f64()
+            );
+        }
+        stream.set_read_timeout(Some(left))?;
+        let read = match reader.read(&mut chunk) {
+            Ok(read) => read,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e).context("reading the request"),
+        };
+        let start = request.len();
+        request.extend_from_slice(&chunk[..read]);
+        if let Some(end) = request[start..].iter().position(|&b| b == b'\n') {
+            request.truncate(start + end);
+            break;
+        }
+        if read == 0 {
+            if reader.limit() == 0 {
+                bail!("request longer than {MAX_REQUEST_BYTES} bytes");
+            }
+            break;
+        }
+    }
+    String::from_utf8(request).context("reading the request")
+}
+
+fn handle(
+    line: &str,
+    uid: u32,
+    allowed_uids: &[u32],
+    manager: &dyn ExclusionManager,
+) -> Result<Vec<Warning>> {
+    check_caller(uid, allowed_uids)?;
+    let (add, paths) = match serde_json::from_str(line).context("parsing the request")? {
+        Request::Ping => return Ok(Vec::new()),
+        Request::Add { paths } => (true, paths),
+        Request::Remove { paths } => (false, paths),
+    };
+    let grants = paths
+        .iter()
+        .map(|path| authorize(uid, path))
+        .collect::<Result<Vec<_>>>()?;
+    // Last look before acting: the checked ancestors are still in place
+    for grant in &grants {
+        grant.recheck()?;
+    }
+    let refs: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+    if add {
+        info!(
+            "Adding {} fixed-path exclusion(s) for uid {uid}",
+            refs.len()
+        );
+        manager.add_exclusions(&refs, true)?;
+    } else {
+        info!(
+            "Removing {} fixed-path exclusion(s) for uid {uid}",
+            refs.len()
+        );
+        manager.remove_exclusions(&refs, true)?;
+    }
+    Ok(manager.take_warnings())
+}
+
+/// Check that `uid` may change the fixed-path exclusion of `path`: it must
+/// be absolute and normalized, and its nearest existing ancestor (itself,
+/// when it exists) must be owned by `uid` and reached without symlinks.
+/// Root may change any path.
+///
+/// The ancestor is opened one component at a time with `O_NOFOLLOW`, and
+/// its owner read from that descriptor, which the returned [`Grant`] keeps
+/// open: swapping a component for a symlink cannot redirect the check.
+pub fn authorize(uid: u32, path: &Path) -> Result<Grant> {
+    let normalized = path
+        .components()
+        .all(|c| !matches!(c, Component::ParentDir | Component::CurDir));
+    if !path.is_absolute() || !normalized {
+        bail!("{} is not an absolute, normalized path", path.display());
+    }
+    if uid == 0 {
+        return Ok(Grant { checked: None });
+    }
+    let (existing, file) = open_existing_ancestor(path)?;
+    let owner = file
+        .metadata()
+        .with_context(|| format!("reading {}", existing.display()))?
+        .uid();
+    if owner != uid {
+        bail!("{} is not owned by uid {uid}", existing.display());
+    }
+    Ok(Grant {
+        checked: Some((existing, file)),
+    })
+}
+
+/// A path [`authorize`] accepted, holding open the ancestor it checked.
+#[derive(Debug)]
+pub struct Grant {
+    checked: Option<(PathBuf, fs::File)>,
+}
+
+impl Grant {
+    /// Fail if the checked ancestor's path no longer leads to the file that
+    /// was checked, e.g. it was renamed away and replaced.
+    pub fn recheck(&self) -> Result<()> {
+        let Some((path, file)) = &self.checked else {
+            return Ok(());
+        };
+        let held = file.metadata()?;
+        let now = path
+            .symlink_metadata()
+            .with_context(|| format!("reading {}", path.display()))?;
+        if (held.dev(), held.ino()) != (now.dev(), now.ino()) {
+            bail!("{} was replaced while being checked", path.display());
+        }
+        Ok(())
+    }
+}
+
+/// Open the nearest existing ancestor of the absolute, normalized `path`
+/// (itself, when it exists) from `/` down, refusing symlinks on the way.
+fn open_existing_ancestor(path: &Path) -> Result<(PathBuf, fs::File)> {
+    let mut reached = PathBuf::from("/");
+    let mut file = fs::File::open(&reached).context("opening /")?;
+    for component in path.components() {
+        let Component::Normal(name) = component else {
+            continue;
+        };
+        let c_name = CString::new(name.as_bytes())
+            .with_context(|| format!("{} contains a NUL byte", path.display()))?;
+        let flags = libc::O_RDONLY | libc::O_NOFOLLOW | libc::O_NONBLOCK | libc::O_CLOEXEC;
+        // SAFETY: `file` is an open descriptor and `c_name` NUL-terminated
+        let fd = unsafe { libc::openat(file.as_raw_fd(), c_name.as_ptr(), flags) };
+        if fd < 0 {
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                Some(libc::ENOENT) => break,
+                Some(libc::ELOOP) => bail!("{} goes through a symlink", path.display()),
+                _ => {
+                    return Err(err)
+                        .with_context(|| format!("opening {}", reached.join(name).display()));
+                }
+            }
+        }
+        // SAFETY: `fd` was just opened and nothing else owns it
+        file = unsafe { fs::File::from_raw_fd(fd) };
+        reached.push(name);
+    }
+    Ok((reached, file))
+}
+
+/// The uid of the process at the other end of `stream`.
+#[cfg(target_os = "linux")]
+pub fn peer_uid(stream: &UnixStream) -> std::io::Result<u32> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: `cred` and `len` are valid for writes of the sizes passed
+    let rc = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&mut cred as *mut libc::ucred).cast(),
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+/// The uid of the process at the other end of `stream`.
+#[cfg(not(target_os = "linux"))]
+pub fn peer_uid(stream: &UnixStream) -> std::io::Result<u32> {
+    let (mut uid, mut gid) = (0, 0);
+    // SAFETY: `uid` and `gid` are valid for writes
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(uid)
+}
+
+// ─── Client ──────────────────────────────────────────────────────────────────
+
+/// Send `request` to the helper listening on `socket` and wait for its
+/// answer, returning the warnings it reported.
+pub fn send(socket: &Path, request: &Request) -> Result<Vec<Warning>> {
+    let mut stream = UnixStream::connect(socket).with_context(|| {
+        format!(
+            "connecting to the privileged helper at {} (see `letitgo privileged-helper install`)",
+            socket.display()
+        )
+    })?;
+    writeln!(stream, "{}", serde_json::to_string(request)?)?;
+    let mut line = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut line)
+        .context("reading the privileged helper's answer")?;
+    let response: Response =
+        serde_json::from_str(&line).context("parsing the privileged helper's answer")?;
+    match response.error {
+        Some(error) => bail!("privileged helper: {error}"),
+        None => Ok(response.warnings),
+    }
+}
+
+/// Fixed-path backend (`fixed_path_backend = "helper"`) that asks the
+/// privileged helper to change fixed-path exclusions, so that runs need no
+/// sudo.  Sticky-mode calls and queries go to `sticky`.
+pub struct HelperManager {
+    socket: PathBuf,
+    sticky: Box<dyn ExclusionManager>,
+    warnings: Mutex<Vec<Warning>>,
+}
+
+impl HelperManager {
+    pub fn new(socket: impl Into<PathBuf>, sticky: Box<dyn ExclusionManager>) -> Self {
+        HelperManager {
+            socket: socket.into(),
+            sticky,
+            warnings: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn send(&self, request: Request) -> Result<()> {
+        let warnings = send(&self.socket, &request)?;
+        self.warnings
+            .lock()
+            .expect("warnings lock poisoned")
+            .extend(warnings);
+        Ok(())
+    }
+}
+
+impl ExclusionManager for HelperManager {
+    fn add_exclusions(&self, paths: &[&Path], fixed_path: bool) -> Result<()> {
+        if !fixed_path {
+            return self.sticky.add_exclusions(paths, fixed_path);
+        }
+        if paths.is_empty() {
+            return Ok(());
+        }
+        self.send(Request::Add {
+            paths: paths.iter().map(|p| p.to_path_buf()).collect(),
+        })
+    }
+
+    fn remove_exclusions(&self, paths: &[&Path], fixed_path: bool) -> Result<()> {
+        if !fixed_path {
+            return self.sticky.remove_exclusions(paths, fixed_path);
+        }
+        if paths.is_empty() {
+            return Ok(());
+        }
+        self.send(Request::Remove {
+            paths: paths.iter().map(|p| p.to_path_buf()).collect(),
+        })
+    }
+
+    fn is_excluded(&self, path: &Path) -> Result<bool> {
+        self.sticky.is_excluded(path)
+    }
+
+    fn are_excluded(&self, paths: &[&Path]) -> Vec<bool> {
+        self.sticky.are_excluded(paths)
+    }
+
+    fn take_warnings(&self) -> Vec<Warning> {
+        let mut warnings =
+            std::mem::take(&mut *self.warnings.lock().expect("warnings lock poisoned"));
+        warnings.extend(self.sticky.take_warnings());
+        warnings
+    }
+}
+
+// ─── Installation ────────────────────────────────────────────────────────────
+
+/// Fail unless running as root.
+pub fn require_root(what: &str) -> Result<()> {
+    // SAFETY: geteuid has no preconditions
+    if unsafe { libc::geteuid() } != 0 {
+        bail!("{what} needs root: run it with sudo");
+    }
+    Ok(())
+}
+
+/// The launchd job that keeps `HELPER_BINARY privileged-helper serve`
+/// running as root for the users in `allowed_uids`.
+fn launchd_job(allowed_uids: &[u32]) -> plist::Value {
+    let mut args = vec![
+        HELPER_BINARY.to_string(),
+        "privileged-helper".to_string(),
+        "serve".to_string(),
+    ];
+    for uid in allowed_uids {
+        args.push(format!("--allow-uid={uid}"));
+    }
+    let mut job = plist::Dictionary::new();
+    job.insert("Label".into(), HELPER_LABEL.into());
+    job.insert(
+        "ProgramArguments".into(),
+        plist::Value::Array(args.into_iter().map(plist::Value::String).collect()),
+    );
+    job.insert("RunAtLoad".into(), true.into());
+    job.insert("KeepAlive".into(), true.into());
+    job.insert(
+        "StandardErrorPath".into(),
+        "/var/log/letitgo-helper.log".into(),
+    );
+    plist::Value::Dictionary(job)
+}
+
+/// Install the helper for `uid`: copy the running binary to
+/// [`HELPER_BINARY`], write the launchd job to [`HELPER_PLIST`] and
+/// (re)load it.  Needs root.
+pub fn install(uid: u32, dry_run: bool) -> Result<()> {
+    let exe = std::env::current_exe().context("locating the letitgo binary")?;
+    if dry_run {
+        info!("[dry-run] would copy {} to {HELPER_BINARY}", exe.display());
+        info!("[dry-run] would write {HELPER_PLIST} serving uid {uid} and load it");
+        return Ok(());
+    }
+    require_root("privileged-helper install")?;
+    let binary = Path::new(HELPER_BINARY);
+    if let Some(dir) = binary.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+    // Copy beside it, then rename: the running helper keeps its old inode
+    let staged = binary.with_extension("new");
+    fs::copy(&exe, &staged)
+        .with_context(|| format!("copying {} to {}", exe.display(), staged.display()))?;
+    fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("setting permissions of {}", staged.display()))?;
+    fs::rename(&staged, binary).with_context(|| format!("installing {HELPER_BINARY}"))?;
+
+    launchd_job(&[uid])
+        .to_file_xml(HELPER_PLIST)
+        .with_context(|| format!("writing {HELPER_PLIST}"))?;
+    fs::set_permissions(HELPER_PLIST, fs::Permissions::from_mode(0o644))
+        .with_context(|| format!("setting permissions of {HELPER_PLIST}"))?;
+    // Not loaded yet on a first install
+    let _ = launchctl(&["bootout", &format!("system/{HELPER_LABEL}")]);
+    launchctl(&["bootstrap", "system", HELPER_PLIST])?;
+    info!("Installed the privileged helper for uid {uid}");
+    Ok(())
+}
+
+/// Unload the helper and delete its launchd job, binary and socket.  Needs
+/// root.
+pub fn uninstall(dry_run: bool) -> Result<()> {
+    if dry_run {
+        info!("[dry-run] would unload {HELPER_LABEL} and delete {HELPER_PLIST}, {HELPER_BINARY}");
+        return Ok(());
+    }
+    require_root("privileged-helper uninstall")?;
+    if let Err(e) = launchctl(&["bootout", &format!("system/{HELPER_LABEL}")]) {
+        debug!("Unloading the helper: {e:#}");
+    }
+    for path in [HELPER_PLIST, HELPER_BINARY, HELPER_SOCKET] {
+        match fs::remove_file(path) {
+            Ok(()) => info!("Deleted {path}"),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("deleting {path}")),
+        }
+    }
+    Ok(())
+}
+
+fn launchctl(args: &[&str]) -> Result<()> {
+    let output = Command::new("/bin/launchctl")
+        .args(args)
+        .output()
+        .context("spawning launchctl")?;
+    if !output.status.success() {
+        bail!(
+            "launchctl {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tmutil::mock::{MockExclusionManager, MockOp};
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    fn current_uid() -> u32 {
+        // SAFETY: getuid has no preconditions
+        unsafe { libc::getuid() }
+    }
+
+    /// Serve on a socket in `dir` for `allowed_uids` with a mock.
+    fn start(dir: &Path, allowed_uids: Vec<u32>) -> (PathBuf, Arc<MockExclusionManager>) {
+        let socket = dir.join("helper.sock");
+        let listener = bind(&socket).unwrap();
+        let mock = Arc::new(MockExclusionManager::new());
+        let manager = Arc::clone(&mock);
+        thread::spawn(move || serve(listener, &allowed_uids, &manager));
+        (socket, mock)
+    }
+
+    #[test]
+    fn test_helper_applies_fixed_path_changes_only_for_allowed_owners() {
+        let tmp = tempdir().unwrap();
+        let root = fs::canonicalize(tmp.path()).unwrap();
+        let target = root.join("repo/target");
+        fs::create_dir_all(&target).unwrap();
+        let (socket, served) = start(&root, vec![current_uid()]);
+
+        // Fixed-path calls go through the helper, sticky ones stay local
+        let sticky = Arc::new(MockExclusionManager::new());
+        let manager = HelperManager::new(&socket, Box::new(Arc::clone(&sticky)));
+        let gone = root.join("repo/node_modules");
+        manager.add_exclusions(&[&target, &gone], true).unwrap();
+        manager.remove_exclusions(&[&gone], true).unwrap();
+        manager.add_exclusions(&[&target], false).unwrap();
+        let calls: Vec<(MockOp, bool)> = served
+            .calls()
+            .into_iter()
+            .map(|c| (c.op, c.fixed_path))
+            .collect();
+        assert_eq!(calls, vec![(MockOp::Add, true), (MockOp::Remove, true)]);
+        assert_eq!(sticky.added_paths(), vec![target.clone()]);
+
+        // Paths reached through `..`, or another user's, are refused
+        let dotted = root.join("repo/../repo/target");
+        let err = manager.add_exclusions(&[&dotted], true).unwrap_err();
+        assert!(format!("{err:#}").contains("normalized"));
+        let stranger = current_uid() + 1;
+        let err = authorize(stranger, &target).unwrap_err();
+        assert!(format!("{err:#}").contains("not owned"));
+        assert!(authorize(0, &target).is_ok());
+
+        // Users not allowed at install time are refused outright
+        let add = format!(r#"{{"op":"add","paths":["{}"]}}"#, target.display());
+        let err = handle(&add, stranger, &[current_uid()], served.as_ref()).unwrap_err();
+        assert!(format!("{err:#}").contains("may not use"));
+        assert_eq!(served.calls().len(), 2);
+    }
+
+    #[test]
+    fn test_helper_answers_while_another_client_stalls() {
+        let tmp = tempdir().unwrap();
+        let root = fs::canonicalize(tmp.path()).unwrap();
+        let (socket, _served) = start(&root, vec![current_uid()]);
+
+        let _stalled = UnixStream::connect(&socket).unwrap();
+        let start = Instant::now();
+        send(&socket, &Request::Ping).unwrap();
+        assert!(start.elapsed() < REQUEST_TIMEOUT);
+    }
+
+    #[test]
+    fn test_helper_refuses_strangers_unread_and_oversized_requests() {
+        let mock = MockExclusionManager::new();
+        let response = |client: &mut UnixStream| -> Response {
+            let mut line = String::new();
+            BufReader::new(&*client).read_line(&mut line).unwrap();
+            serde_json::from_str(&line).unwrap()
+        };
+
+        // A stranger is answered at once, though it never sends a request
+        let (server, mut client) = UnixStream::pair().unwrap();
+        let start = Instant::now();
+        answer_uid(server, current_uid() + 1, &[current_uid()], &mock).unwrap();
+        assert!(start.elapsed() < REQUEST_TIMEOUT);
+        let error = response(&mut client).error.unwrap();
+        assert!(error.contains("may not use"), "{error}");
+
+        // An allowed user's request is read up to the limit only
+        let (server, mut client) = UnixStream::pair().unwrap();
+        let uid = current_uid();
+        thread::scope(|s| {
+            s.spawn(|| answer_uid(server, uid, &[uid], &mock).unwrap());
+            let padding = vec![b' '; MAX_REQUEST_BYTES as usize];
+            client.write_all(&padding).unwrap();
+            let error = response(&mut client).error.unwrap();
+            assert!(error.contains("longer than"), "{error}");
+        });
+        assert!(mock.calls().is_empty());
+    }
+
+    #[test]
+    fn test_authorize_checks_what_it_opened() {
+        let tmp = tempdir().unwrap();
+        let root = fs::canonicalize(tmp.path()).unwrap();
+        let repo = root.join("repo");
+        fs::create_dir_all(repo.join("target")).unwrap();
+        // Root may change any path: check as a user owning the tree
+        let uid = match current_uid() {
+            0 => {
+                for dir in [&repo, &repo.join("target")] {
+                    std::os::unix::fs::chown(dir, Some(4321), None).unwrap();
+                }
+                4321
+            }
+            uid => uid,
+        };
+
+        // A symlink anywhere on the way is refused, even to one's own tree
+        std::os::unix::fs::symlink(&repo, root.join("link")).unwrap();
+        let err = authorize(uid, &root.join("link/target")).unwrap_err();
+        assert!(format!("{err:#}").contains("symlink"));
+
+        // The checked directory swapped for another after the check
+        let grant = authorize(uid, &repo.join("target/new")).unwrap();
+        grant.recheck().unwrap();
+        fs::rename(repo.join("target"), root.join("moved")).unwrap();
+        fs::create_dir(repo.join("target")).unwrap();
+        let err = grant.recheck().unwrap_err();
+        assert!(format!("{err:#}").contains("replaced"));
+    }
+}
@@ -0,0 +1,208 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{collections::HashMap, fmt::Display, fs, path::Path, sync::OnceLock};
+use tracing::warn;
+
+/// Built-in catalogs by language; `en` has every key and is the fallback.
+const BUILTIN: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.toml")),
+    ("ja", include_str!("../locales/ja.toml")),
+];
+
+/// One message of a catalog: a plain string, or a form per count.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Message {
+    Text(String),
+    Plural { one: String, other: String },
+}
+
+impl Message {
+    fn form(&self, n: Option<u64>) -> &str {
+        match self {
+            Message::Text(text) => text,
+            Message::Plural { one, .. } if n == Some(1) => one,
+            Message::Plural { other, .. } => other,
+        }
+    }
+}
+
+type Messages = HashMap<String, Message>;
+
+/// The messages of one language, backed by the built-in English ones.
+#[derive(Debug)]
+pub struct Catalog {
+    /// Searched in order: a user catalog, the built-in one, English.
+    layers: Vec<Messages>,
+}
+
+impl Catalog {
+    /// The built-in English messages.
+    pub fn english() -> Self {
+        Catalog {
+            layers: vec![builtin("en").expect("built-in en catalog")],
+        }
+    }
+
+    /// The catalog for `language` (e.g. `ja`): `<dir>/<language>.toml`
+    /// when `dir` holds one, over the built-in catalog, over English.
+    ///
+    /// A user catalog that cannot be read is skipped with a warning.
+    pub fn load(language: &str, dir: Option<&Path>) -> Self {
+        let mut catalog = Catalog::english();
+        if language != "en"
+            && let Some(messages) = builtin(language)
+        {
+            catalog.layers.insert(0, messages);
+        }
+        if let Some(path) = dir.map(|dir| dir.join(format!("{language}.toml")))
+            && path.exists()
+        {
+            match read_catalog(&path) {
+                Ok(messages) => catalog.layers.insert(0, messages),
+                Err(e) => warn!("Ignoring message catalog: {e:#}"),
+            }
+        }
+        catalog
+    }
+
+    /// Whether any messages beyond the built-in English ones were found.
+    pub fn is_translated(&self) -> bool {
+        self.layers.len() > 1
+    }
+
+    /// The message `key` with each `{name}` replaced by its argument; the
+    /// `one` or `other` form is picked by the argument `n`.  An unknown key
+    /// is returned as is.
+    pub fn message(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        let Some(message) = self.layers.iter().find_map(|layer| layer.get(key)) else {
+            return key.to_string();
+        };
+        let n = args
+            .iter()
+            .find(|(name, _)| *name == "n")
+            .and_then(|(_, value)| value.to_string().parse().ok());
+        let mut text = message.form(n).to_string();
+        for (name, value) in args {
+            text = text.replace(&format!("{{{name}}}"), &value.to_string());
+        }
+        text
+    }
+}
+
+fn builtin(language: &str) -> Option<Messages> {
+    let (_, text) = BUILTIN.iter().find(|(lang, _)| *lang == language)?;
+    Some(toml::from_str(text).expect("built-in catalogs parse"))
+}
+
+fn read_catalog(path: &Path) -> Result<Messages> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("reading message catalog {}", path.display()))?;
+    toml::from_str(&text).with_context(|| format!("parsing message catalog {}", path.display()))
+}
+
+/// The language of the locale environment (`LC_ALL`, `LC_MESSAGES`, then
+/// `LANG`), e.g. `ja` for `ja_JP.UTF-8`; `en` when unset or `C`/`POSIX`.
+pub fn language_from_env() -> String {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    let language = locale
+        .split(['_', '-', '.', '@'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    match language.as_str() {
+        "" | "c" | "posix" => "en".to_string(),
+        _ => language,
+    }
+}
+
+static CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+/// Use `catalog` for every later message; only the first call has effect.
+/// Until then (and in tests) messages are English.
+pub fn init(catalog: Catalog) {
+    let _ = CATALOG.set(catalog);
+}
+
+/// [`Catalog::message`] of the catalog set with [`init`].
+pub fn message(key: &str, args: &[(&str, &dyn Display)]) -> String {
+    CATALOG.get_or_init(Catalog::english).message(key, args)
+}
+
+/// A user-facing message from the catalog: `tr!("list-header", n = count)`.
+#[macro_export]
+macro_rules! tr {
+    ($key:literal $(, $name:ident = $value:expr)* $(,)?) => {
+        $crate::i18n::message($key, &[$((stringify!($name), &$value as &dyn std::fmt::Display)),*])
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use tempfile::tempdir;
+
+    /// The `{name}` placeholders of every form of `message`.
+    fn placeholders(message: &Message) -> BTreeSet<String> {
+        let forms = match message {
+            Message::Text(text) => vec![text],
+            Message::Plural { one, other } => vec![one, other],
+        };
+        forms
+            .into_iter()
+            .flat_map(|text| text.split('{').skip(1))
+            .filter_map(|rest| rest.split_once('}').map(|(name, _)| name.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_builtin_catalogs_translate_english_keys_with_the_same_arguments() {
+        let english = builtin("en").unwrap();
+        for (language, _) in BUILTIN {
+            for (key, message) in builtin(language).unwrap() {
+                let original = english
+                    .get(&key)
+                    .unwrap_or_else(|| panic!("{language}: `{key}` is not in en.toml"));
+                assert_eq!(
+                    placeholders(&message),
+                    placeholders(original),
+                    "{language}: `{key}`"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_user_catalog_overrides_builtin_and_falls_back_to_english() {
+        let tmp = tempdir().unwrap();
+        fs::write(
+            tmp.path().join("ja.toml"),
+            "list-empty = \"なし\"\nwarning-timed-out = { one = \"1 件\", other = \"{n} 件\" }\n",
+        )
+        .unwrap();
+        fs::write(tmp.path().join("de.toml"), "list-empty = \"Nichts\"\n").unwrap();
+
+        let ja = Catalog::load("ja", Some(tmp.path()));
+        assert_eq!(ja.message("list-empty", &[]), "なし");
+        assert_eq!(ja.message("warning-timed-out", &[("n", &3)]), "3 件");
+        assert_eq!(ja.message("warning-timed-out", &[("n", &1)]), "1 件");
+        assert_eq!(
+            ja.message("list-header", &[("n", &2)]),
+            "Time Machine から除外されているパス: 2 件"
+        );
+
+        // A language letitgo does not ship, from its user catalog alone
+        let de = Catalog::load("de", Some(tmp.path()));
+        assert_eq!(de.message("list-empty", &[]), "Nichts");
+        assert_eq!(
+            de.message("warning-repo-skipped", &[("n", &1)]),
+            "1 repo skipped"
+        );
+        assert_eq!(de.message("no-such-key", &[]), "no-such-key");
+    }
+}
@@ -3,27 +3,683 @@ use globset::{Glob, GlobSet, GlobSetBuilder};
 use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use path_clean::PathClean;
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
 };
-use tracing::{debug, warn};
-use walkdir::WalkDir;
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::{
+        Config, ConflictWinner, ExcludeGitDir, ExtensionScope, RuleLayer, UnbackedRepoPolicy,
+    },
+    git,
+    pattern_cache::PatternCache,
+    throttle::Throttle,
+    walk::{Visit, Walker},
+    warnings::{WarningKind, Warnings},
+};
+
+/// Settings shared by every repository in a run, derived from [`Config`].
+#[derive(Debug, Clone)]
+pub struct ResolveOptions {
+    /// Compiled config whitelist.
+    pub whitelist_globs: GlobSet,
+    /// The whitelist globs as written, indexed like `whitelist_globs`.
+    pub whitelist_patterns: Vec<String>,
+    /// Which layer wins when the whitelist matches a `.lignore` addition.
+    pub lignore_vs_whitelist: ConflictWinner,
+    /// Rule layers in the order they are applied; later layers win.
+    pub rule_order: Vec<RuleLayer>,
+    /// Whether to exclude each repository's `.git` directory.
+    pub exclude_git_dir: ExcludeGitDir,
+    /// What to do with repositories that are not backed by a remote.
+    pub unbacked_repo_policy: UnbackedRepoPolicy,
+    /// Count repositories with unpushed `HEAD` commits as unbacked.
+    pub require_pushed_head: bool,
+    /// Extra repo-relative paths to exclude when they exist.
+    pub extra_repo_relative_excludes: Vec<String>,
+    /// File extensions to exclude, without the dot.
+    pub exclude_extensions: Vec<String>,
+    /// Which files `exclude_extensions` applies to.
+    pub exclude_extensions_scope: ExtensionScope,
+    /// Compiled `protected_globs`, present only when `safety_scan` is on.
+    pub protected_globs: Option<GlobSet>,
+    /// Percentage of excluded entries at which a directory is excluded as a
+    /// whole (see [`collapse_file_exclusions`]); `None` disables collapsing.
+    pub collapse_threshold: Option<u8>,
+    /// Parsed ignore-file patterns shared across repositories (empty unless
+    /// the caller loads a persisted one).
+    pub patterns: Arc<PatternCache>,
+    /// Problems worked around while resolving, for the caller to report.
+    pub warnings: Arc<Warnings>,
+    /// How repositories are walked.
+    pub walker: Walker,
+    /// Tracked files from which a repository's gitignore layer is walked on
+    /// all cores; `0` never does.
+    pub parallel_walk_min_files: u64,
+    /// Bytes below which a file ignore rules match is left out (`0` = off).
+    pub min_file_size: u64,
+    /// Holds back resolution while the machine is busy (`nice_mode`).
+    pub throttle: Arc<Throttle>,
+}
+
+impl ResolveOptions {
+    /// Build the options for a run from `config`.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Ok(ResolveOptions {
+            whitelist_globs: build_whitelist_globset(&config.whitelist)?,
+            whitelist_patterns: config.whitelist.clone(),
+            lignore_vs_whitelist: config.lignore_vs_whitelist,
+            rule_order: validate_rule_order(&config.rule_order)?,
+            exclude_git_dir: config.exclude_git_dir,
+            unbacked_repo_policy: config.unbacked_repo_policy,
+            require_pushed_head: config.require_pushed_head,
+            extra_repo_relative_excludes: config.extra_repo_relative_excludes.clone(),
+            exclude_extensions: config
+                .exclude_extensions
+                .iter()
+                .map(|ext| ext.trim_start_matches('.').to_string())
+                .collect(),
+            exclude_extensions_scope: config.exclude_extensions_scope,
+            protected_globs: config
+                .safety_scan
+                .then(|| build_protected_globset(&config.protected_globs))
+                .transpose()?,
+            collapse_threshold: match config.collapse_threshold {
+                0 => None,
+                pct @ 1..=100 => Some(pct),
+                pct => anyhow::bail!("collapse_threshold must be 0-100, got {pct}"),
+            },
+            patterns: Arc::default(),
+            warnings: Arc::default(),
+            walker: Walker {
+                threads: config.nice_mode.walk_threads(),
+                ..config.walker()
+            },
+            parallel_walk_min_files: config.parallel_walk_min_files,
+            min_file_size: config.min_file_size_kb.saturating_mul(1000),
+            throttle: Arc::new(Throttle::new(config.nice_mode)),
+        })
+    }
+
+    /// Which layer decides a whitelist vs `.lignore` conflict, or `None` if
+    /// the two cannot conflict because one of the layers is disabled.
+    ///
+    /// When the whitelist runs first, `.lignore` additions are applied after
+    /// it and always win; otherwise `lignore_vs_whitelist` decides.
+    pub fn conflict_winner(&self) -> Option<ConflictWinner> {
+        let position = |layer| self.rule_order.iter().position(|l| *l == layer);
+        let lignore = position(RuleLayer::Lignore)?;
+        let whitelist = position(RuleLayer::Whitelist)?;
+        if whitelist < lignore {
+            Some(ConflictWinner::Lignore)
+        } else {
+            Some(self.lignore_vs_whitelist)
+        }
+    }
+
+    /// The whitelist globs (as written) that match `path`.
+    pub fn matching_whitelist(&self, path: &Path) -> Vec<&str> {
+        self.whitelist_globs
+            .matches(path)
+            .into_iter()
+            .filter_map(|i| self.whitelist_patterns.get(i).map(String::as_str))
+            .collect()
+    }
+}
+
+/// Check that `rule_order` names each layer at most once.
+///
+/// Layers left out of the list are disabled.
+fn validate_rule_order(rule_order: &[RuleLayer]) -> Result<Vec<RuleLayer>> {
+    for (i, layer) in rule_order.iter().enumerate() {
+        if rule_order[..i].contains(layer) {
+            anyhow::bail!("rule_order lists `{layer}` more than once");
+        }
+    }
+    Ok(rule_order.to_vec())
+}
+
+/// Resolve everything that should be excluded for the working tree at
+/// `repo_root` by applying the rule layers in `options.rule_order`: ignore
+/// files, `.lignore` overrides, the config whitelist and config-declared
+/// ("manual") paths.  Later layers override earlier ones.
+pub fn resolve_repo(repo_root: &Path, options: &ResolveOptions) -> Result<HashSet<PathBuf>> {
+    Ok(resolve_repo_sources(repo_root, options)?
+        .into_keys()
+        .collect())
+}
+
+/// Like [`resolve_repo`], mapping each excluded path to the rule that
+/// excluded it.
+pub fn resolve_repo_sources(
+    repo_root: &Path,
+    options: &ResolveOptions,
+) -> Result<HashMap<PathBuf, MatchSource>> {
+    if let Some(marker) = keep_marker(repo_root) {
+        info!(
+            "Keeping all of {} in backups ({} found)",
+            repo_root.display(),
+            marker.display()
+        );
+        return Ok(HashMap::new());
+    }
+
+    let policy = if options.unbacked_repo_policy != UnbackedRepoPolicy::Normal
+        && !is_backed(repo_root, options.require_pushed_head)
+    {
+        options.unbacked_repo_policy
+    } else {
+        UnbackedRepoPolicy::Normal
+    };
+
+    if policy == UnbackedRepoPolicy::Skip {
+        info!(
+            "Skipping {} — not backed by a remote (unbacked_repo_policy = \"skip\")",
+            repo_root.display()
+        );
+        return Ok(HashMap::new());
+    }
+
+    let conservative = policy == UnbackedRepoPolicy::Conservative;
+    let mut excluded: HashMap<PathBuf, MatchSource> = HashMap::new();
+    let mut lines = SourceLines::default();
+    for layer in &options.rule_order {
+        match layer {
+            RuleLayer::Gitignore => {
+                apply_gitignore_layer(
+                    repo_root,
+                    &mut excluded,
+                    &mut lines,
+                    &options.patterns,
+                    &options.warnings,
+                    options.walker,
+                    walks_in_parallel(repo_root, options),
+                )?;
+                drop_small_files(&mut excluded, options.min_file_size);
+            }
+            RuleLayer::Lignore => apply_lignore_overrides(
+                repo_root,
+                &mut excluded,
+                &mut lines,
+                &options.patterns,
+                &options.warnings,
+                options.walker,
+            )?,
+            RuleLayer::Whitelist => apply_whitelist_layer(&mut excluded, options),
+            // Config-declared paths are never excluded for unbacked repos
+            // under the conservative policy.
+            RuleLayer::Manual if conservative => {}
+            RuleLayer::Manual => apply_manual_layer(repo_root, &mut excluded, options),
+        }
+    }
+
+    if conservative {
+        debug!(
+            "Conservative exclusions for unbacked repo {}",
+            repo_root.display()
+        );
+        excluded.retain(|p, _| p.is_dir());
+    }
+    apply_safety_scan(&mut excluded, options);
+    Ok(excluded)
+}
+
+/// Drop the files ignore rules matched that are smaller than `min_size`
+/// bytes (`min_file_size_kb`): excluding them saves next to nothing, and
+/// each one is an exclusion to write and keep.  Directories, and paths
+/// `.lignore` files or the config added, stay.
+fn drop_small_files(excluded: &mut HashMap<PathBuf, MatchSource>, min_size: u64) {
+    if min_size == 0 {
+        return;
+    }
+    let before = excluded.len();
+    excluded.retain(|path, source| {
+        source.is_lignore() || source.is_config() || small_file_size(path, min_size).is_none()
+    });
+    if excluded.len() < before {
+        debug!(
+            "Kept {} file(s) under {min_size} bytes in backups",
+            before - excluded.len()
+        );
+    }
+}
+
+/// The size of `path` if it is a file (a symlink not followed) of fewer
+/// than `min_size` bytes.
+pub fn small_file_size(path: &Path, min_size: u64) -> Option<u64> {
+    let meta = fs::symlink_metadata(path).ok()?;
+    (meta.is_file() && meta.len() < min_size).then_some(meta.len())
+}
+
+/// Whether the gitignore layer walks `repo_root` on all cores: its index
+/// lists at least `parallel_walk_min_files` files.
+fn walks_in_parallel(repo_root: &Path, options: &ResolveOptions) -> bool {
+    if options.parallel_walk_min_files == 0 {
+        return false;
+    }
+    let Some(files) = git::resolve_layout(repo_root).and_then(|l| git::index_entry_count(&l))
+    else {
+        return false;
+    };
+    let parallel = u64::from(files) >= options.parallel_walk_min_files;
+    if parallel {
+        debug!(
+            "Walking {} on all cores ({files} tracked files)",
+            repo_root.display()
+        );
+    }
+    parallel
+}
+
+/// Manual layer: add the paths declared in config (`exclude_git_dir`,
+/// `extra_repo_relative_excludes`, `exclude_extensions`) for this repository.
+fn apply_manual_layer(
+    repo_root: &Path,
+    excluded: &mut HashMap<PathBuf, MatchSource>,
+    options: &ResolveOptions,
+) {
+    if let Some(git_dir) = git_dir_exclusion(repo_root, options.exclude_git_dir) {
+        debug!("git dir exclusion: {}", git_dir.display());
+        let source = MatchSource::config("exclude_git_dir", &options.exclude_git_dir.to_string());
+        excluded.insert(git_dir, source);
+    }
+
+    for rel in &options.extra_repo_relative_excludes {
+        if let Some(path) = repo_relative_path(repo_root, rel)
+            && path.exists()
+        {
+            debug!("extra repo-relative exclusion: {}", path.display());
+            excluded.insert(
+                path,
+                MatchSource::config("extra_repo_relative_excludes", rel),
+            );
+        }
+    }
+
+    if !options.exclude_extensions.is_empty() {
+        apply_extension_excludes(repo_root, excluded, options);
+    }
+}
+
+/// Add the files of `repo_root` whose extension is listed in
+/// `exclude_extensions` (ignoring case), skipping tracked files unless the
+/// scope is `all`.  Excluded directories and nested repositories are not
+/// descended into.
+fn apply_extension_excludes(
+    repo_root: &Path,
+    excluded: &mut HashMap<PathBuf, MatchSource>,
+    options: &ResolveOptions,
+) {
+    let tracked = match options.exclude_extensions_scope {
+        ExtensionScope::All => None,
+        ExtensionScope::Untracked => {
+            let Some(tracked) =
+                git::resolve_layout(repo_root).and_then(|layout| git::tracked_paths(&layout))
+            else {
+                // Which files are tracked is unknown: exclude none of them
+                debug!("exclude_extensions skipped for {}", repo_root.display());
+                return;
+            };
+            Some(tracked)
+        }
+    };
+
+    let mut walker = options.walker.walk(repo_root).min_depth(1);
+    while let Some(entry) = walker.next() {
+        // Walk errors were reported by the gitignore layer
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let path = entry.path();
+        if entry.file_type().is_dir() {
+            if path.file_name().is_some_and(|n| n == ".git")
+                || path.join(".git").exists()
+                || excluded.contains_key(path)
+            {
+                walker.skip_current_dir();
+            }
+            continue;
+        }
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let Some(ext) = path.extension().and_then(|e| e.to_str()) else {
+            continue;
+        };
+        let Some(rule) = options
+            .exclude_extensions
+            .iter()
+            .find(|rule| rule.eq_ignore_ascii_case(ext))
+        else {
+            continue;
+        };
+        if let (Some(tracked), Ok(rel)) = (&tracked, path.strip_prefix(repo_root))
+            && tracked.contains(rel)
+        {
+            continue;
+        }
+        debug!("extension exclusion: {}", path.display());
+        excluded
+            .entry(path.to_path_buf())
+            .or_insert_with(|| MatchSource::config("exclude_extensions", rule));
+    }
+}
+
+/// Whitelist layer: drop every excluded path matching the config whitelist.
+///
+/// A whitelist hit on a path that a `.lignore` explicitly added is a
+/// conflict: it is reported with both rules, and `lignore_vs_whitelist`
+/// decides whether the path is excluded.
+fn apply_whitelist_layer(excluded: &mut HashMap<PathBuf, MatchSource>, options: &ResolveOptions) {
+    if options.whitelist_globs.is_empty() {
+        return;
+    }
+    excluded.retain(|path, source| {
+        if !options.whitelist_globs.is_match(path) {
+            return true;
+        }
+        if !source.is_lignore() {
+            debug!("whitelist retains: {}", path.display());
+            return false;
+        }
+        let winner = options.lignore_vs_whitelist;
+        warn!(
+            "Conflict for {}: .lignore rule `{}` ({}) excludes it, whitelist `{}` keeps it \
+             — {} wins (lignore_vs_whitelist)",
+            path.display(),
+            source.pattern,
+            source.location(),
+            options.matching_whitelist(path).join("`, `"),
+            winner,
+        );
+        winner == ConflictWinner::Lignore
+    });
+}
+
+/// Drop every path in `excluded` that is, or contains, a file whose name
+/// matches the `protected_globs` of `options`, logging the file that blocked the exclusion.
+fn apply_safety_scan(excluded: &mut HashMap<PathBuf, MatchSource>, options: &ResolveOptions) {
+    let Some(protected) = &options.protected_globs else {
+        return;
+    };
+    excluded.retain(
+        |path, _| match find_protected_file(path, protected, options.walker) {
+            Some(file) => {
+                warn!(
+                    "Not excluding {} — contains protected file {}",
+                    path.display(),
+                    file.display()
+                );
+                false
+            }
+            None => true,
+        },
+    );
+}
+
+/// Replace per-entry exclusions with a single exclusion of their directory
+/// when at least `options.collapse_threshold` percent of the directory's
+/// entries are excluded.  Collapsing cascades upwards but never reaches
+/// `repo_root`.
+///
+/// A directory is not collapsed if any entry that would newly lose its
+/// backup is (or contains) a whitelisted path, a protected file or a nested
+/// repository.  Returns, for each collapsed directory, the entries that were
+/// not ignored themselves but are now excluded with it.
+pub fn collapse_file_exclusions(
+    repo_root: &Path,
+    excluded: &mut HashSet<PathBuf>,
+    options: &ResolveOptions,
+) -> BTreeMap<PathBuf, Vec<PathBuf>> {
+    let mut collapsed: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    let Some(threshold) = options.collapse_threshold else {
+        return collapsed;
+    };
+    let below_root = |d: &Path| d != repo_root && d.starts_with(repo_root);
+
+    // Deepest directories first so that collapses can cascade upwards.
+    let mut dirs: Vec<PathBuf> = excluded
+        .iter()
+        .filter_map(|p| p.parent())
+        .filter(|d| below_root(d))
+        .map(Path::to_path_buf)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+
+    let mut i = 0;
+    while let Some(dir) = dirs.get(i).cloned() {
+        i += 1;
+        if excluded.contains(&dir)
+            || dir.join(".git").exists()
+            || options.whitelist_globs.is_match(&dir)
+        {
+            continue;
+        }
+        let Ok(read_dir) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let entries: Vec<PathBuf> = read_dir.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        let kept: Vec<PathBuf> = entries
+            .iter()
+            .filter(|e| !excluded.contains(*e))
+            .cloned()
+            .collect();
+        if entries.is_empty()
+            || (entries.len() - kept.len()) * 100 < threshold as usize * entries.len()
+        {
+            continue;
+        }
+        if let Some(blocker) = kept.iter().find_map(|e| collapse_blocker(e, options)) {
+            debug!(
+                "Not collapsing {} — would exclude {}",
+                dir.display(),
+                blocker.display()
+            );
+            continue;
+        }
+
+        excluded.retain(|p| !p.starts_with(&dir));
+        excluded.insert(dir.clone());
+        let mut swallowed = kept;
+        collapsed.retain(|d, entries| {
+            let inner = d.starts_with(&dir);
+            if inner {
+                swallowed.append(entries);
+            }
+            !inner
+        });
+        swallowed.sort();
+        debug!(
+            "Collapsed exclusions into {} ({} non-ignored entr(ies) included)",
+            dir.display(),
+            swallowed.len()
+        );
+        collapsed.insert(dir.clone(), swallowed);
+        if let Some(parent) = dir.parent().filter(|d| below_root(d)) {
+            dirs.push(parent.to_path_buf());
+        }
+    }
+    collapsed
+}
+
+/// Return the first path at or below `entry` that must stay backed up: a
+/// whitelisted path, a protected file, or a nested repository.
+fn collapse_blocker(entry: &Path, options: &ResolveOptions) -> Option<PathBuf> {
+    options
+        .walker
+        .entries(entry)
+        .find(|e| {
+            e.file_name() == ".git"
+                || options.whitelist_globs.is_match(e.path())
+                || options
+                    .protected_globs
+                    .as_ref()
+                    .is_some_and(|g| !e.file_type().is_dir() && g.is_match(e.file_name()))
+        })
+        .map(|e| e.into_path())
+}
+
+/// Return the first file at or below `path` whose name matches `protected`.
+fn find_protected_file(path: &Path, protected: &GlobSet, walker: Walker) -> Option<PathBuf> {
+    walker
+        .entries(path)
+        .find(|e| !e.file_type().is_dir() && protected.is_match(e.file_name()))
+        .map(|e| e.into_path())
+}
+
+/// Resolve a configured repo-relative path.
+///
+/// `.git/<rest>` maps to `<common gitdir>/<rest>` so that linked worktrees
+/// share the main repository's LFS store and similar.  Paths that would
+/// escape the repository (via `..`) are rejected.
+fn repo_relative_path(repo_root: &Path, rel: &str) -> Option<PathBuf> {
+    let rel = Path::new(rel);
+    if let Ok(rest) = rel.strip_prefix(".git") {
+        let layout = git::resolve_layout(repo_root)?;
+        let path = layout.common_dir.join(rest).clean();
+        return path.starts_with(&layout.common_dir).then_some(path);
+    }
+    let path = repo_root.join(rel).clean();
+    path.starts_with(repo_root).then_some(path)
+}
+
+/// Files that, at the root of a working tree, keep everything in it in
+/// backups: for repos whose ignored data is precious (a research dataset
+/// in a gitignored directory).
+pub const KEEP_MARKERS: [&str; 2] = [".letitgo-keep", ".nolignore"];
+
+/// The [`KEEP_MARKERS`] file at `repo_root`, if there is one.  Its contents
+/// do not matter.
+pub fn keep_marker(repo_root: &Path) -> Option<PathBuf> {
+    KEEP_MARKERS
+        .iter()
+        .map(|name| repo_root.join(name))
+        .find(|marker| marker.symlink_metadata().is_ok())
+}
+
+/// Return `true` if the repository at `repo_root` has a pushable remote and,
+/// when `require_pushed_head` is set, its `HEAD` commit is on a remote branch
+/// and no tracked file was modified since it was staged.
+fn is_backed(repo_root: &Path, require_pushed_head: bool) -> bool {
+    let Some(layout) = git::resolve_layout(repo_root) else {
+        return false;
+    };
+    let status = git::backing_status(&layout);
+    status.has_remote
+        && (!require_pushed_head || status.head_pushed && git::worktree_clean(&layout, repo_root))
+}
+
+/// Return `repo_root/.git` if `policy` says it should be excluded.
+///
+/// Only real `.git` directories qualify: for worktrees and submodules `.git`
+/// is a small pointer file and the object store lives elsewhere.
+fn git_dir_exclusion(repo_root: &Path, policy: ExcludeGitDir) -> Option<PathBuf> {
+    let git_dir = repo_root.join(".git");
+    if policy == ExcludeGitDir::Never || !git_dir.is_dir() {
+        return None;
+    }
+    if policy == ExcludeGitDir::IfRemoteExists {
+        let layout = git::resolve_layout(repo_root)?;
+        if git::remote_names(&layout).is_empty() {
+            debug!("No remote configured, keeping {}", git_dir.display());
+            return None;
+        }
+    }
+    Some(git_dir)
+}
 
 /// Resolve the set of paths that should be excluded from Time Machine backups
 /// for a single Git repository, applying .gitignore and .lignore rules.
 ///
+/// The repository's `info/exclude` and `core.excludesFile` are honoured as
+/// well; for linked worktrees they are read from the shared gitdir.
+///
 /// Uses a single-pass algorithm: `.gitignore` files are discovered incrementally
 /// during the walk, and a per-directory matcher is built for each one.
 /// Ignored directories are physically pruned via `skip_current_dir()`, so large
 /// trees like `node_modules/` are never traversed.
 ///
-/// Returns a set of absolute `PathBuf`s.
+/// Returns a set of absolute `PathBuf`s; see [`resolve_with_provenance`] for
+/// the rule behind each one.
 pub fn resolve_excluded_paths(
     repo_root: &Path,
     whitelist_globs: &GlobSet,
 ) -> Result<HashSet<PathBuf>> {
-    let mut excluded: HashSet<PathBuf> = HashSet::new();
+    Ok(resolve_with_provenance(repo_root, whitelist_globs)?
+        .into_keys()
+        .collect())
+}
+
+/// The paths the `.gitignore` and exclude files of `repo_root` exclude, each
+/// mapped to its rule, before `.lignore` files and the whitelist apply.
+pub fn resolve_gitignore_layer(
+    repo_root: &Path,
+    walker: Walker,
+) -> Result<HashMap<PathBuf, MatchSource>> {
+    let mut excluded = HashMap::new();
+    apply_gitignore_layer(
+        repo_root,
+        &mut excluded,
+        &mut SourceLines::default(),
+        &PatternCache::default(),
+        &Warnings::default(),
+        walker,
+        false,
+    )?;
+    Ok(excluded)
+}
+
+/// Like [`resolve_excluded_paths`], but maps each excluded path to the
+/// `.gitignore`, exclude-file or `.lignore` rule that matched it.
+pub fn resolve_with_provenance(
+    repo_root: &Path,
+    whitelist_globs: &GlobSet,
+) -> Result<HashMap<PathBuf, MatchSource>> {
+    let mut excluded = HashMap::new();
+    let mut lines = SourceLines::default();
+    let (patterns, warnings) = (PatternCache::default(), Warnings::default());
+    let walker = Walker::default();
+    apply_gitignore_layer(
+        repo_root,
+        &mut excluded,
+        &mut lines,
+        &patterns,
+        &warnings,
+        walker,
+        false,
+    )?;
+    apply_lignore_overrides(
+        repo_root,
+        &mut excluded,
+        &mut lines,
+        &patterns,
+        &warnings,
+        walker,
+    )?;
+    if !whitelist_globs.is_empty() {
+        excluded.retain(|path, _| !is_whitelisted(path, whitelist_globs));
+    }
+    Ok(excluded)
+}
 
+/// Gitignore layer: walk `repo_root` and add every path matched by
+/// `core.excludesFile`, `info/exclude` or a `.gitignore`.
+///
+/// Paths already in `excluded` (from earlier layers) are not descended into.
+fn apply_gitignore_layer(
+    repo_root: &Path,
+    excluded: &mut HashMap<PathBuf, MatchSource>,
+    lines: &mut SourceLines,
+    patterns: &PatternCache,
+    warnings: &Warnings,
+    walker: Walker,
+    parallel: bool,
+) -> Result<()> {
     // ---- Single-pass: walk + incremental .gitignore discovery ----
 
     // Each `.gitignore` gets its own Gitignore matcher rooted at the directory
@@ -31,26 +687,56 @@ pub fn resolve_excluded_paths(
     // cannot correctly scope anchored patterns (e.g. `/target/`) from nested
     // `.gitignore` files — the `ignore` crate requires the builder root to
     // match the file's parent for anchored patterns to resolve.
-    let mut matchers: Vec<(PathBuf, Gitignore)> = Vec::new();
+    let mut matchers: Vec<DirMatcher> = Vec::new();
+
+    // Repository-wide exclude files come first so that any `.gitignore` takes
+    // precedence over them, mirroring Git: core.excludesFile < info/exclude
+    // < .gitignore.  For linked worktrees these live in the shared gitdir.
+    if let Some(layout) = git::resolve_layout(repo_root) {
+        let exclude_files = git::core_excludes_file(&layout)
+            .into_iter()
+            .chain(std::iter::once(layout.info_exclude()));
+        for exclude_file in exclude_files {
+            if exclude_file.is_file() {
+                matchers.push(build_gitignore(
+                    repo_root,
+                    &exclude_file,
+                    patterns,
+                    warnings,
+                )?);
+            }
+        }
+    }
 
     // Pre-load the root .gitignore (if any) so its rules apply to first-level entries.
     let root_gi = repo_root.join(".gitignore");
     if root_gi.exists() {
-        let m = build_gitignore(repo_root, &root_gi)?;
-        matchers.push((repo_root.to_path_buf(), m));
+        matchers.push(build_gitignore(repo_root, &root_gi, patterns, warnings)?);
+    }
+
+    if parallel {
+        let mut found =
+            gitignore_matches_parallel(repo_root, &matchers, excluded, patterns, warnings, walker)?;
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        for (path, file, glob) in found {
+            debug!("gitignore match: {}", path.display());
+            excluded.insert(path, glob_source(&file, &glob, lines));
+        }
+        return Ok(());
     }
 
     // Use a while-let loop so we can call skip_current_dir() for physical pruning.
-    let mut walker = WalkDir::new(repo_root)
-        .follow_links(false)
-        .min_depth(1)
-        .into_iter();
+    let mut walk = walker.walk(repo_root).min_depth(1);
 
-    while let Some(entry_result) = walker.next() {
+    while let Some(entry_result) = walk.next() {
         let entry = match entry_result {
             Ok(e) => e,
             Err(e) => {
-                warn!("Permission error walking {}: {}", repo_root.display(), e);
+                warnings.push(
+                    WarningKind::PermissionDenied,
+                    e.path(),
+                    format!("Permission error walking {}: {}", repo_root.display(), e),
+                );
                 continue;
             }
         };
@@ -60,21 +746,21 @@ pub fn resolve_excluded_paths(
 
         // Skip .git directories (don't descend)
         if is_dir && path.file_name().is_some_and(|n| n == ".git") {
-            walker.skip_current_dir();
+            walk.skip_current_dir();
             continue;
         }
 
         // Skip if any parent is already in excluded set (logical pruning)
         let mut ancestor_excluded = false;
         for ancestor in path.ancestors().skip(1) {
-            if excluded.contains(ancestor) {
+            if excluded.contains_key(ancestor) {
                 ancestor_excluded = true;
                 break;
             }
         }
         if ancestor_excluded {
             if is_dir {
-                walker.skip_current_dir();
+                walk.skip_current_dir();
             }
             continue;
         }
@@ -85,10 +771,9 @@ pub fn resolve_excluded_paths(
         if is_dir {
             let gi_path = path.join(".gitignore");
             if gi_path.exists() {
-                let already = matchers.iter().any(|(d, _)| d == path);
+                let already = matchers.iter().any(|m| m.dir == path);
                 if !already {
-                    let m = build_gitignore(path, &gi_path)?;
-                    matchers.push((path.to_path_buf(), m));
+                    matchers.push(build_gitignore(path, &gi_path, patterns, warnings)?);
                 }
             }
         }
@@ -97,11 +782,11 @@ pub fn resolve_excluded_paths(
         // A deeper .gitignore takes precedence: Ignore → excluded,
         // Whitelist (negation) → not excluded, None → fall through to parent.
         match match_against_all(path, is_dir, &matchers) {
-            ignore::Match::Ignore(_) => {
+            ignore::Match::Ignore((file, glob)) => {
                 debug!("gitignore match: {}", path.display());
-                excluded.insert(path.to_path_buf());
+                excluded.insert(path.to_path_buf(), glob_source(file, glob, lines));
                 if is_dir {
-                    walker.skip_current_dir(); // physical pruning
+                    walk.skip_current_dir(); // physical pruning
                 }
             }
             ignore::Match::Whitelist(_) | ignore::Match::None => {
@@ -110,44 +795,372 @@ pub fn resolve_excluded_paths(
         }
     }
 
-    // ---- Apply .lignore overrides ----
-    apply_lignore_overrides(repo_root, &mut excluded)?;
+    Ok(())
+}
 
-    // ---- Apply config whitelist ----
-    apply_whitelist(&mut excluded, whitelist_globs);
+/// Where an ignore rule was written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchSource {
+    /// The ignore file containing the rule.
+    pub file: PathBuf,
+    /// 1-based line of the rule within `file`, when it could be located.
+    pub line: Option<usize>,
+    /// The rule as written (negations keep their leading `!`).
+    pub pattern: String,
+}
 
-    Ok(excluded)
+impl MatchSource {
+    /// Source for a path declared by config key `key`.
+    pub fn config(key: &str, value: &str) -> Self {
+        MatchSource {
+            file: PathBuf::from("config"),
+            line: None,
+            pattern: format!("{key} = {value}"),
+        }
+    }
+
+    /// `true` if the rule came from a `.lignore` file.
+    pub fn is_lignore(&self) -> bool {
+        self.file.file_name().is_some_and(|n| n == ".lignore")
+    }
+
+    /// `true` if the path was declared by config (see [`config`](Self::config)).
+    pub fn is_config(&self) -> bool {
+        self.file == Path::new("config")
+    }
+
+    /// Format the rule location as `file:line` (or just `file`).
+    pub fn location(&self) -> String {
+        match self.line {
+            Some(line) => format!("{}:{}", self.file.display(), line),
+            None => self.file.display().to_string(),
+        }
+    }
+}
+
+/// A single rule that matched `target` (the path or one of its ancestors).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleMatch {
+    pub target: PathBuf,
+    /// `true` for an ignore/addition rule, `false` for a negation.
+    pub ignored: bool,
+    pub source: MatchSource,
 }
 
-/// Build a single `Gitignore` matcher for one `.gitignore` file, rooted at
-/// the directory containing that file.
-fn build_gitignore(dir: &Path, gitignore_path: &Path) -> Result<Gitignore> {
-    let mut builder = GitignoreBuilder::new(dir);
-    if let Some(err) = builder.add(gitignore_path) {
-        warn!("Error reading {}: {}", gitignore_path.display(), err);
+/// The walk of [`apply_gitignore_layer`] on all cores (through
+/// [`Walker::walk_parallel`]), for repositories too large to walk on one:
+/// each path it excludes, with the ignore file and glob that matched.
+///
+/// The semantics are the sequential walk's.  A directory is visited before
+/// anything in it is read, so the matcher of its `.gitignore` is in place
+/// before its entries are matched; each entry is matched against its
+/// ancestors' matchers only, deepest first, after `base` (the exclude files
+/// and the root `.gitignore`).  Ignored directories and those `earlier`
+/// layers excluded are not descended into.
+fn gitignore_matches_parallel(
+    repo_root: &Path,
+    base: &[DirMatcher],
+    earlier: &HashMap<PathBuf, MatchSource>,
+    patterns: &PatternCache,
+    warnings: &Warnings,
+    walker: Walker,
+) -> Result<Vec<(PathBuf, PathBuf, ignore::gitignore::Glob)>> {
+    let nested: RwLock<HashMap<PathBuf, Arc<DirMatcher>>> = RwLock::default();
+    let found = Mutex::new(Vec::new());
+    let failed = Mutex::new(None);
+    walker.walk_parallel(repo_root, || {
+        Box::new(|entry| {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => {
+                    warnings.push(
+                        WarningKind::PermissionDenied,
+                        e.path(),
+                        format!("Permission error walking {}: {}", repo_root.display(), e),
+                    );
+                    return Visit::Continue;
+                }
+            };
+            let (path, is_dir) = (entry.path(), entry.is_dir());
+            if entry.depth() == 0 {
+                return Visit::Continue;
+            }
+            if is_dir && entry.file_name() == ".git" {
+                return Visit::Skip;
+            }
+            if path.ancestors().skip(1).any(|a| earlier.contains_key(a)) {
+                return Visit::Skip;
+            }
+            if is_dir {
+                let gi_path = path.join(".gitignore");
+                if gi_path.exists() {
+                    match build_gitignore(path, &gi_path, patterns, warnings) {
+                        Ok(m) => {
+                            nested
+                                .write()
+                                .unwrap()
+                                .insert(path.to_path_buf(), Arc::new(m));
+                        }
+                        Err(e) => {
+                            failed.lock().unwrap().get_or_insert(e);
+                            return Visit::Skip;
+                        }
+                    }
+                }
+            }
+
+            // Shallowest first, as the sequential walk stacks them
+            let ancestors: Vec<Arc<DirMatcher>> = {
+                let nested = nested.read().unwrap();
+                let mut found: Vec<_> = path
+                    .ancestors()
+                    .take_while(|a| *a != repo_root)
+                    .filter_map(|a| nested.get(a).cloned())
+                    .collect();
+                found.reverse();
+                found
+            };
+            let matchers = base.iter().chain(ancestors.iter().map(Arc::as_ref));
+            match match_against_all(path, is_dir, matchers) {
+                ignore::Match::Ignore((file, glob)) => {
+                    found.lock().unwrap().push((
+                        path.to_path_buf(),
+                        file.to_path_buf(),
+                        glob.clone(),
+                    ));
+                    Visit::Skip
+                }
+                ignore::Match::Whitelist(_) | ignore::Match::None => Visit::Continue,
+            }
+        })
+    });
+    match failed.into_inner().unwrap() {
+        Some(e) => Err(e),
+        None => Ok(found.into_inner().unwrap()),
     }
-    builder.build().context("building gitignore matcher")
 }
 
-/// Check `path` against a stack of per-directory matchers.
+/// Find the ignore rule (`core.excludesFile`, `info/exclude` or `.gitignore`)
+/// that decides whether `path` inside `repo_root` is ignored.
+///
+/// Mirrors the walk in [`resolve_excluded_paths`]: each ancestor of `path`
+/// is checked from the repo root down, and the first ignored ancestor wins
+/// because the walk never descends into it.  A negation only counts when no
+/// ancestor is ignored.
+pub fn gitignore_rule_for(repo_root: &Path, path: &Path) -> Result<Option<RuleMatch>> {
+    let Ok(rel) = path.strip_prefix(repo_root) else {
+        return Ok(None);
+    };
+
+    let (patterns, warnings) = (PatternCache::default(), Warnings::default());
+    let mut matchers: Vec<DirMatcher> = Vec::new();
+    if let Some(layout) = git::resolve_layout(repo_root) {
+        let exclude_files = git::core_excludes_file(&layout)
+            .into_iter()
+            .chain(std::iter::once(layout.info_exclude()));
+        for exclude_file in exclude_files {
+            if exclude_file.is_file() {
+                matchers.push(build_gitignore(
+                    repo_root,
+                    &exclude_file,
+                    &patterns,
+                    &warnings,
+                )?);
+            }
+        }
+    }
+
+    let mut lines = SourceLines::default();
+    let mut decided = None;
+    let mut dir = repo_root.to_path_buf();
+    for component in rel.components() {
+        let gi_path = dir.join(".gitignore");
+        if gi_path.is_file() {
+            matchers.push(build_gitignore(&dir, &gi_path, &patterns, &warnings)?);
+        }
+        let target = dir.join(component);
+        match match_against_all(&target, target.is_dir(), &matchers) {
+            ignore::Match::Ignore((file, glob)) => {
+                return Ok(Some(RuleMatch {
+                    ignored: true,
+                    source: glob_source(file, glob, &mut lines),
+                    target,
+                }));
+            }
+            ignore::Match::Whitelist((file, glob)) => {
+                decided = Some(RuleMatch {
+                    ignored: false,
+                    source: glob_source(file, glob, &mut lines),
+                    target: target.clone(),
+                });
+            }
+            ignore::Match::None => {}
+        }
+        dir = target;
+    }
+    Ok(decided)
+}
+
+/// Find every `.lignore` rule that applies to `path` or one of its ancestors.
+pub fn lignore_rules_for(repo_root: &Path, path: &Path) -> Vec<RuleMatch> {
+    let mut rules = Vec::new();
+    for dir in path.ancestors().skip(1) {
+        if !dir.starts_with(repo_root) {
+            break;
+        }
+        let lignore_path = dir.join(".lignore");
+        let Ok(content) = std::fs::read_to_string(&lignore_path) else {
+            continue;
+        };
+        for (idx, raw_line) in content.lines().enumerate() {
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let source = MatchSource {
+                file: lignore_path.clone(),
+                line: Some(idx + 1),
+                pattern: line.to_string(),
+            };
+            if let Some(neg) = line.strip_prefix('!') {
+                let candidate = dir.join(neg.trim_end_matches('/')).clean();
+                if path.starts_with(&candidate) {
+                    rules.push(RuleMatch {
+                        target: candidate,
+                        ignored: false,
+                        source,
+                    });
+                }
+                continue;
+            }
+            let mut builder = GitignoreBuilder::new(dir);
+            if builder.add_line(None, line).is_err() {
+                continue;
+            }
+            let Ok(matcher) = builder.build() else {
+                continue;
+            };
+            let matched = path
+                .ancestors()
+                .take_while(|p| p.starts_with(dir) && *p != dir)
+                .find(|p| {
+                    let rel = p.strip_prefix(dir).unwrap_or(p);
+                    matcher.matched(rel, p.is_dir()).is_ignore()
+                });
+            if let Some(target) = matched {
+                rules.push(RuleMatch {
+                    target: target.to_path_buf(),
+                    ignored: true,
+                    source,
+                });
+            }
+        }
+    }
+    rules
+}
+
+/// Describe the compiled gitignore glob `glob` of the ignore file `file`.
+///
+/// The `ignore` crate records the pattern but not the line, so the line is
+/// looked up in `lines`, which caches each file's contents for the duration
+/// of one resolution.  `file` is passed in rather than taken from the glob
+/// because matchers are shared between files with the same content.
+fn glob_source(
+    file: &Path,
+    glob: &ignore::gitignore::Glob,
+    lines: &mut SourceLines,
+) -> MatchSource {
+    let file = file.to_path_buf();
+    let pattern = glob.original().to_string();
+    let line = lines
+        .0
+        .entry(file.clone())
+        .or_insert_with(|| {
+            std::fs::read_to_string(&file)
+                .map(|c| c.lines().map(|l| l.trim().to_string()).collect())
+                .unwrap_or_default()
+        })
+        .iter()
+        .position(|l| *l == pattern)
+        .map(|i| i + 1);
+    MatchSource {
+        file,
+        line,
+        pattern,
+    }
+}
+
+/// Trimmed lines of the ignore files seen so far, keyed by path.
+#[derive(Default)]
+struct SourceLines(HashMap<PathBuf, Vec<String>>);
+
+/// Return the first file at or below `path` whose name matches the
+/// `protected_globs` in `options`, if the safety scan is enabled.
+pub fn protected_file_in(path: &Path, options: &ResolveOptions) -> Option<PathBuf> {
+    find_protected_file(path, options.protected_globs.as_ref()?, options.walker)
+}
+
+/// A compiled ignore file and the directory its patterns are relative to.
+struct DirMatcher {
+    dir: PathBuf,
+    /// The ignore file, reported as the source of its matches.
+    file: PathBuf,
+    /// Possibly shared with other files of the same content, so it is only
+    /// ever given paths relative to `dir`.
+    matcher: Arc<Gitignore>,
+}
+
+/// Build the matcher for one ignore file rooted at `dir` (the directory
+/// containing a `.gitignore`, or the repo root for exclude files), reusing
+/// the compiled matcher of any identical file in `patterns`.
+fn build_gitignore(
+    dir: &Path,
+    gitignore_path: &Path,
+    patterns: &PatternCache,
+    warnings: &Warnings,
+) -> Result<DirMatcher> {
+    let matcher = match patterns.matcher(dir, gitignore_path) {
+        Ok(matcher) => matcher,
+        Err(err) => {
+            warnings.push(
+                WarningKind::UnreadableIgnoreFile,
+                Some(gitignore_path),
+                format!("Error reading {}: {:#}", gitignore_path.display(), err),
+            );
+            Arc::new(Gitignore::empty())
+        }
+    };
+    Ok(DirMatcher {
+        dir: dir.to_path_buf(),
+        file: gitignore_path.to_path_buf(),
+        matcher,
+    })
+}
+
+/// Check `path` against a stack of per-directory matchers, returning the
+/// deciding glob with the file it came from.
 ///
 /// Matchers are checked from deepest (last) to shallowest (first).
 /// A deeper `.gitignore` takes precedence: `Ignore` → excluded,
 /// `Whitelist` (negation pattern) → not excluded, `None` → fall through.
-fn match_against_all<'a>(
+fn match_against_all<'a, I>(
     path: &Path,
     is_dir: bool,
-    matchers: &'a [(PathBuf, Gitignore)],
-) -> ignore::Match<&'a ignore::gitignore::Glob> {
-    for (dir, matcher) in matchers.iter().rev() {
+    matchers: I,
+) -> ignore::Match<(&'a Path, &'a ignore::gitignore::Glob)>
+where
+    I: IntoIterator<Item = &'a DirMatcher>,
+    I::IntoIter: DoubleEndedIterator,
+{
+    for m in matchers.into_iter().rev() {
         // Only check matchers whose directory is an ancestor of this path
-        let rel = match path.strip_prefix(dir) {
+        let rel = match path.strip_prefix(&m.dir) {
             Ok(r) => r,
             Err(_) => continue,
         };
-        let m = matcher.matched(rel, is_dir);
-        match m {
-            ignore::Match::Ignore(_) | ignore::Match::Whitelist(_) => return m,
+        match m.matcher.matched(rel, is_dir) {
+            ignore::Match::Ignore(glob) => return ignore::Match::Ignore((&m.file, glob)),
+            ignore::Match::Whitelist(glob) => return ignore::Match::Whitelist((&m.file, glob)),
             ignore::Match::None => continue,
         }
     }
@@ -157,15 +1170,26 @@ fn match_against_all<'a>(
 /// Apply `.lignore` override files:
 /// - Plain patterns → add to exclusion set
 /// - Negated patterns (`!pattern`) → remove from exclusion set (exact match only)
-fn apply_lignore_overrides(repo_root: &Path, excluded: &mut HashSet<PathBuf>) -> Result<()> {
+fn apply_lignore_overrides(
+    repo_root: &Path,
+    excluded: &mut HashMap<PathBuf, MatchSource>,
+    lines: &mut SourceLines,
+    patterns: &PatternCache,
+    warnings: &Warnings,
+    walker: Walker,
+) -> Result<()> {
     // Find all .lignore files, skipping .git and already-excluded directories.
-    let mut walker = WalkDir::new(repo_root).follow_links(false).into_iter();
+    let mut walk = walker.walk(repo_root);
 
-    while let Some(entry_result) = walker.next() {
+    while let Some(entry_result) = walk.next() {
         let entry = match entry_result {
             Ok(e) => e,
             Err(e) => {
-                warn!("Walk error discovering .lignore files: {}", e);
+                warnings.push(
+                    WarningKind::PermissionDenied,
+                    e.path(),
+                    format!("Walk error discovering .lignore files: {e}"),
+                );
                 continue;
             }
         };
@@ -173,8 +1197,9 @@ fn apply_lignore_overrides(repo_root: &Path, excluded: &mut HashSet<PathBuf>) ->
         let path = entry.path();
         let is_dir = entry.file_type().is_dir();
 
-        if is_dir && (path.file_name().is_some_and(|n| n == ".git") || excluded.contains(path)) {
-            walker.skip_current_dir();
+        if is_dir && (path.file_name().is_some_and(|n| n == ".git") || excluded.contains_key(path))
+        {
+            walk.skip_current_dir();
             continue;
         }
 
@@ -184,7 +1209,15 @@ fn apply_lignore_overrides(repo_root: &Path, excluded: &mut HashSet<PathBuf>) ->
                 Some(d) => d,
                 None => continue,
             };
-            process_lignore_file(lignore_path, lignore_dir, excluded)?;
+            process_lignore_file(
+                lignore_path,
+                lignore_dir,
+                excluded,
+                lines,
+                patterns,
+                warnings,
+                walker,
+            )?;
         }
     }
     Ok(())
@@ -203,15 +1236,24 @@ fn apply_lignore_overrides(repo_root: &Path, excluded: &mut HashSet<PathBuf>) ->
 fn process_lignore_file(
     lignore_path: &Path,
     lignore_dir: &Path,
-    excluded: &mut HashSet<PathBuf>,
+    excluded: &mut HashMap<PathBuf, MatchSource>,
+    lines: &mut SourceLines,
+    patterns: &PatternCache,
+    warnings: &Warnings,
+    walker: Walker,
 ) -> Result<()> {
     let content = match std::fs::read_to_string(lignore_path) {
         Ok(c) => c,
         Err(e) => {
-            warn!("Cannot read {}: {}", lignore_path.display(), e);
+            warnings.push(
+                WarningKind::UnreadableIgnoreFile,
+                Some(lignore_path),
+                format!("Cannot read {}: {}", lignore_path.display(), e),
+            );
             return Ok(());
         }
     };
+    patterns.note_read(lignore_path, &content);
 
     // We'll build two gitignore matchers: one for additions, one we parse
     // manually for negations. The `ignore` gitignore builder strips the `!`
@@ -230,28 +1272,31 @@ fn process_lignore_file(
             negation_patterns.push(neg.to_string());
         } else {
             // Plain addition pattern
-            addition_builder.add_line(None, line).with_context(|| {
-                format!(
-                    "adding lignore line '{line}' from {}",
-                    lignore_path.display()
-                )
-            })?;
+            addition_builder
+                .add_line(Some(lignore_path.to_path_buf()), line)
+                .with_context(|| {
+                    format!(
+                        "adding lignore line '{line}' from {}",
+                        lignore_path.display()
+                    )
+                })?;
         }
     }
 
     // Apply additions
     let addition_matcher = addition_builder.build()?;
     // Walk lignore_dir to find newly matched paths, skipping .git and excluded dirs.
-    let mut add_walker = WalkDir::new(lignore_dir)
-        .follow_links(false)
-        .min_depth(1)
-        .into_iter();
+    let mut add_walker = walker.walk(lignore_dir).min_depth(1);
 
     while let Some(entry_result) = add_walker.next() {
         let entry = match entry_result {
             Ok(e) => e,
             Err(e) => {
-                warn!("Walk error in .lignore addition scan: {}", e);
+                warnings.push(
+                    WarningKind::PermissionDenied,
+                    e.path(),
+                    format!("Walk error in .lignore addition scan: {e}"),
+                );
                 continue;
             }
         };
@@ -259,7 +1304,8 @@ fn process_lignore_file(
         let path = entry.path();
         let is_dir = entry.file_type().is_dir();
 
-        if is_dir && (path.file_name().is_some_and(|n| n == ".git") || excluded.contains(path)) {
+        if is_dir && (path.file_name().is_some_and(|n| n == ".git") || excluded.contains_key(path))
+        {
             add_walker.skip_current_dir();
             continue;
         }
@@ -268,9 +1314,9 @@ fn process_lignore_file(
             Ok(r) => r,
             Err(_) => continue,
         };
-        if let ignore::Match::Ignore(_) = addition_matcher.matched(rel, is_dir) {
+        if let ignore::Match::Ignore(glob) = addition_matcher.matched(rel, is_dir) {
             debug!("lignore addition: {}", path.display());
-            excluded.insert(path.to_path_buf());
+            excluded.insert(path.to_path_buf(), glob_source(lignore_path, glob, lines));
             if is_dir {
                 add_walker.skip_current_dir();
             }
@@ -283,24 +1329,33 @@ fn process_lignore_file(
         // `..` or `.` components so the path matches exclusion-set entries.
         let candidate = lignore_dir.join(neg_pattern.trim_end_matches('/')).clean();
 
-        if excluded.contains(&candidate) {
+        if let Some(source) = excluded.remove(&candidate) {
             // Direct entry — remove it
-            debug!("lignore negation removes: {}", candidate.display());
-            excluded.remove(&candidate);
-        } else if let Some(parent) = excluded
+            debug!(
+                "lignore negation removes: {} (was `{}` in {})",
+                candidate.display(),
+                source.pattern,
+                source.file.display()
+            );
+        } else if let Some((parent, source)) = excluded
             .iter()
-            .find(|excl| candidate.starts_with(excl.as_path()))
+            .find(|(excl, _)| candidate.starts_with(excl.as_path()))
         {
             // The negated path is a sub-path of an excluded directory
-            warn!(
-                ".lignore negation `!{}` targets a sub-path of excluded directory `{}`. \
+            let message = format!(
+                ".lignore negation `!{}` in {} targets a sub-path of excluded directory `{}` \
+                 (matched by `{}` in {}). \
                  Sub-path negation is not yet supported — `{}` remains fully excluded. \
                  Workaround: use `!{}` to fully un-exclude the directory.",
                 neg_pattern,
+                lignore_path.display(),
                 parent.display(),
+                source.pattern,
+                source.location(),
                 parent.display(),
                 parent.file_name().unwrap_or_default().to_string_lossy(),
             );
+            warnings.push(WarningKind::RuleNotApplied, Some(lignore_path), message);
         }
         // Else: the pattern simply doesn't match anything — silently ignore
     }
@@ -313,14 +1368,35 @@ fn apply_whitelist(excluded: &mut HashSet<PathBuf>, whitelist_globs: &GlobSet) {
     if whitelist_globs.is_empty() {
         return;
     }
-    excluded.retain(|path| {
-        if whitelist_globs.is_match(path) {
-            debug!("whitelist retains: {}", path.display());
-            false // remove from exclusion set
-        } else {
-            true
-        }
-    });
+    excluded.retain(|path| !is_whitelisted(path, whitelist_globs));
+}
+
+/// Return `true` (and log it) if `path` matches the config whitelist.
+fn is_whitelisted(path: &Path, whitelist_globs: &GlobSet) -> bool {
+    let hit = whitelist_globs.is_match(path);
+    if hit {
+        debug!("whitelist retains: {}", path.display());
+    }
+    hit
+}
+
+/// Resolve the exclusions for a bare repository at `bare_root`.
+///
+/// Each entry of `rel_excludes` is joined onto `bare_root`; entries that do
+/// not exist are skipped.  The config whitelist still applies.
+pub fn resolve_bare_repo_excludes(
+    bare_root: &Path,
+    rel_excludes: &[String],
+    whitelist_globs: &GlobSet,
+) -> HashSet<PathBuf> {
+    let mut excluded: HashSet<PathBuf> = rel_excludes
+        .iter()
+        .map(|rel| bare_root.join(rel).clean())
+        .filter(|p| p.starts_with(bare_root) && p.exists())
+        .inspect(|p| debug!("bare repo exclusion: {}", p.display()))
+        .collect();
+    apply_whitelist(&mut excluded, whitelist_globs);
+    excluded
 }
 
 /// Build a `GlobSet` from a list of glob pattern strings.
@@ -334,9 +1410,21 @@ pub fn build_whitelist_globset(patterns: &[String]) -> Result<GlobSet> {
     builder.build().context("building whitelist globset")
 }
 
+/// Build a `GlobSet` of file-name patterns for the safety scan.
+fn build_protected_globset(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob =
+            Glob::new(pattern).with_context(|| format!("invalid protected glob: {pattern}"))?;
+        builder.add(glob);
+    }
+    builder.build().context("building protected globset")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::walk::WalkBackend;
     use std::fs;
     use tempfile::tempdir;
 
@@ -355,6 +1443,13 @@ mod tests {
         build_whitelist_globset(&[]).unwrap()
     }
 
+    fn default_options() -> ResolveOptions {
+        ResolveOptions {
+            whitelist_globs: empty_whitelist(),
+            ..ResolveOptions::from_config(&Config::default()).unwrap()
+        }
+    }
+
     #[test]
     fn test_basic_gitignore_exclusion() {
         let tmp = tempdir().unwrap();
@@ -603,6 +1698,466 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_resolve_repo_excludes_git_dir_only_with_remote() {
+        let tmp = tempdir().unwrap();
+        let with_remote = make_repo(&tmp.path().join("a"));
+        fs::write(
+            with_remote.join(".git/config"),
+            "[remote \"origin\"]\n\turl = https://example.com/a.git\n",
+        )
+        .unwrap();
+        let local_only = make_repo(&tmp.path().join("b"));
+
+        let options = ResolveOptions {
+            exclude_git_dir: ExcludeGitDir::IfRemoteExists,
+            ..default_options()
+        };
+
+        let excluded = resolve_repo(&with_remote, &options).unwrap();
+        assert!(excluded.contains(&with_remote.join(".git")));
+        assert!(excluded.contains(&with_remote.join("target")));
+
+        let excluded = resolve_repo(&local_only, &options).unwrap();
+        assert!(!excluded.contains(&local_only.join(".git")));
+    }
+
+    #[test]
+    fn test_resolve_with_provenance_reports_rule_sources() {
+        let tmp = tempdir().unwrap();
+        let repo = make_repo(tmp.path());
+        fs::create_dir_all(repo.join("data")).unwrap();
+        fs::create_dir_all(repo.join(".git/info")).unwrap();
+        fs::write(repo.join(".git/info/exclude"), "# local\nnode_modules/\n").unwrap();
+        fs::write(repo.join(".gitignore"), "\ntarget/\n").unwrap();
+        fs::write(repo.join(".lignore"), "data/\n").unwrap();
+
+        let excluded = resolve_with_provenance(&repo, &empty_whitelist()).unwrap();
+
+        let target = &excluded[&repo.join("target")];
+        assert_eq!(target.file, repo.join(".gitignore"));
+        assert_eq!(target.line, Some(2));
+        assert_eq!(target.pattern, "target/");
+
+        let node_modules = &excluded[&repo.join("node_modules")];
+        // The gitdir is canonicalized, so only compare the tail (macOS /private/var)
+        assert!(node_modules.file.ends_with(".git/info/exclude"));
+        assert_eq!(
+            node_modules.location(),
+            format!("{}:2", node_modules.file.display())
+        );
+
+        let data = &excluded[&repo.join("data")];
+        assert_eq!(data.file, repo.join(".lignore"));
+        assert_eq!(data.line, Some(1));
+    }
+
+    #[test]
+    fn test_shared_matcher_reports_its_own_file() {
+        let tmp = tempdir().unwrap();
+        let repo = make_repo(tmp.path());
+        fs::create_dir_all(repo.join("app/cache")).unwrap();
+        fs::write(repo.join(".gitignore"), "cache/\n").unwrap();
+        fs::write(repo.join("app/.gitignore"), "cache/\n").unwrap();
+
+        // Both files compile to one matcher; the match names the nested file
+        let excluded = resolve_with_provenance(&repo, &empty_whitelist()).unwrap();
+        assert_eq!(
+            excluded[&repo.join("app/cache")].file,
+            repo.join("app/.gitignore")
+        );
+    }
+
+    #[test]
+    fn test_lignore_vs_whitelist_conflict() {
+        let tmp = tempdir().unwrap();
+        let repo = make_repo(tmp.path());
+        fs::write(repo.join(".env"), "SECRET=1").unwrap();
+        fs::write(repo.join(".lignore"), ".env\n").unwrap();
+
+        let config = Config {
+            whitelist: vec!["**/.env".to_string()],
+            ..Config::default()
+        };
+        let options = ResolveOptions::from_config(&config).unwrap();
+        assert_eq!(options.matching_whitelist(&repo.join(".env")), ["**/.env"]);
+        let excluded = resolve_repo(&repo, &options).unwrap();
+        assert!(!excluded.contains(&repo.join(".env")));
+        assert!(excluded.contains(&repo.join("target")));
+
+        let lignore_wins = ResolveOptions {
+            lignore_vs_whitelist: ConflictWinner::Lignore,
+            ..options
+        };
+        let excluded = resolve_repo(&repo, &lignore_wins).unwrap();
+        assert!(excluded.contains(&repo.join(".env")));
+    }
+
+    #[test]
+    fn test_rule_order_controls_layer_precedence() {
+        let tmp = tempdir().unwrap();
+        let repo = make_repo(tmp.path());
+        fs::write(repo.join(".lignore"), "!target/\n").unwrap();
+        let with_order = |rule_order: Vec<RuleLayer>| ResolveOptions {
+            rule_order,
+            extra_repo_relative_excludes: vec!["src".to_string()],
+            whitelist_globs: build_whitelist_globset(&["**/src".to_string()]).unwrap(),
+            ..default_options()
+        };
+
+        // Default: the .lignore negation beats .gitignore, whitelist beats manual
+        let excluded = resolve_repo(&repo, &default_options()).unwrap();
+        assert!(!excluded.contains(&repo.join("target")));
+        let options = with_order(Config::default().rule_order);
+        let excluded = resolve_repo(&repo, &options).unwrap();
+        assert!(!excluded.contains(&repo.join("src")));
+
+        // Reversed: .gitignore is applied last and re-adds target/, and the
+        // manual entry is applied after the whitelist
+        use RuleLayer::*;
+        let options = with_order(vec![Lignore, Whitelist, Gitignore, Manual]);
+        let excluded = resolve_repo(&repo, &options).unwrap();
+        assert!(excluded.contains(&repo.join("target")));
+        assert!(excluded.contains(&repo.join("src")));
+
+        // Omitted layers are disabled
+        let options = with_order(vec![Lignore]);
+        assert!(resolve_repo(&repo, &options).unwrap().is_empty());
+
+        let config = Config {
+            rule_order: vec![Gitignore, Gitignore],
+            ..Config::default()
+        };
+        assert!(ResolveOptions::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_safety_scan_blocks_dirs_with_protected_files() {
+        let tmp = tempdir().unwrap();
+        let repo = make_repo(tmp.path());
+        fs::write(repo.join("target/debug/local.sqlite"), "").unwrap();
+        fs::write(repo.join("server.key"), "").unwrap();
+        fs::write(repo.join(".gitignore"), "target/\nnode_modules/\n*.key\n").unwrap();
+
+        let unguarded = resolve_repo(&repo, &default_options()).unwrap();
+        assert!(unguarded.contains(&repo.join("target")));
+        assert!(unguarded.contains(&repo.join("server.key")));
+
+        let config = Config {
+            safety_scan: true,
+            ..Config::default()
+        };
+        let guarded = ResolveOptions {
+            whitelist_globs: empty_whitelist(),
+            ..ResolveOptions::from_config(&config).unwrap()
+        };
+        let excluded = resolve_repo(&repo, &guarded).unwrap();
+        assert!(!excluded.contains(&repo.join("target")));
+        assert!(!excluded.contains(&repo.join("server.key")));
+        assert!(excluded.contains(&repo.join("node_modules")));
+    }
+
+    #[test]
+    fn test_unbacked_repo_policies() {
+        let tmp = tempdir().unwrap();
+        let repo = make_repo(tmp.path());
+        fs::write(repo.join("notes.db"), "").unwrap();
+        fs::write(repo.join(".gitignore"), "target/\nnode_modules/\n*.db\n").unwrap();
+
+        let conservative = ResolveOptions {
+            exclude_git_dir: ExcludeGitDir::Always,
+            unbacked_repo_policy: UnbackedRepoPolicy::Conservative,
+            ..default_options()
+        };
+        let excluded = resolve_repo(&repo, &conservative).unwrap();
+        // Directories are still excluded; the ignored file and .git are kept
+        assert!(excluded.contains(&repo.join("target")));
+        assert!(!excluded.contains(&repo.join("notes.db")));
+        assert!(!excluded.contains(&repo.join(".git")));
+
+        let skip = ResolveOptions {
+            unbacked_repo_policy: UnbackedRepoPolicy::Skip,
+            ..default_options()
+        };
+        assert!(resolve_repo(&repo, &skip).unwrap().is_empty());
+
+        // Once a remote exists the repo is treated normally again
+        fs::write(
+            repo.join(".git/config"),
+            "[remote \"origin\"]\n\turl = https://example.com/r.git\n",
+        )
+        .unwrap();
+        let excluded = resolve_repo(&repo, &conservative).unwrap();
+        assert!(excluded.contains(&repo.join("notes.db")));
+        assert!(excluded.contains(&repo.join(".git")));
+    }
+
+    #[test]
+    fn test_dirty_pushed_repo_counts_as_unbacked() {
+        let tmp = tempdir().unwrap();
+        let repo = make_repo(tmp.path());
+        fs::write(repo.join("notes.db"), "").unwrap();
+        fs::write(repo.join(".gitignore"), "target/\nnode_modules/\n*.db\n").unwrap();
+        fs::write(repo.join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::create_dir_all(repo.join(".git/refs/heads")).unwrap();
+        fs::create_dir_all(repo.join(".git/refs/remotes/origin")).unwrap();
+        fs::write(
+            repo.join(".git/config"),
+            "[remote \"origin\"]\n\turl = https://example.com/r.git\n",
+        )
+        .unwrap();
+        fs::write(repo.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(repo.join(".git/refs/heads/main"), "aaaa\n").unwrap();
+        fs::write(repo.join(".git/refs/remotes/origin/main"), "aaaa\n").unwrap();
+        fs::write(
+            repo.join(".git/index"),
+            git::test_index_of(&repo, &[".gitignore", "src/main.rs"]),
+        )
+        .unwrap();
+        let strict = ResolveOptions {
+            unbacked_repo_policy: UnbackedRepoPolicy::Conservative,
+            require_pushed_head: true,
+            ..default_options()
+        };
+
+        // Pushed and clean: treated normally
+        assert!(
+            resolve_repo(&repo, &strict)
+                .unwrap()
+                .contains(&repo.join("notes.db"))
+        );
+
+        // An edit not yet committed may exist nowhere else
+        fs::write(repo.join("src/main.rs"), "fn main() { todo!() }\n").unwrap();
+        let excluded = resolve_repo(&repo, &strict).unwrap();
+        assert!(excluded.contains(&repo.join("target")));
+        assert!(!excluded.contains(&repo.join("notes.db")));
+
+        // A deleted tracked file is an uncommitted change too
+        fs::write(
+            repo.join(".git/index"),
+            git::test_index_of(&repo, &[".gitignore", "src/main.rs"]),
+        )
+        .unwrap();
+        assert!(
+            resolve_repo(&repo, &strict)
+                .unwrap()
+                .contains(&repo.join("notes.db"))
+        );
+        fs::remove_file(repo.join("src/main.rs")).unwrap();
+        assert!(
+            !resolve_repo(&repo, &strict)
+                .unwrap()
+                .contains(&repo.join("notes.db"))
+        );
+    }
+
+    #[test]
+    fn test_exclude_extensions_skip_tracked_files_unless_scope_is_all() {
+        let tmp = tempdir().unwrap();
+        let repo = make_repo(tmp.path());
+        fs::create_dir_all(repo.join("media")).unwrap();
+        for file in [
+            "media/demo.MP4",
+            "media/logo.iso",
+            "media/notes.txt",
+            "target/out.iso",
+        ] {
+            fs::write(repo.join(file), "").unwrap();
+        }
+        fs::write(
+            repo.join(".git/index"),
+            git::test_index(2, &[b".gitignore", b"media/logo.iso"]),
+        )
+        .unwrap();
+
+        let options = ResolveOptions::from_config(&Config {
+            exclude_extensions: vec!["mp4".to_string(), ".iso".to_string()],
+            ..Config::default()
+        })
+        .unwrap();
+        let excluded = resolve_repo(&repo, &options).unwrap();
+        assert!(excluded.contains(&repo.join("media/demo.MP4")));
+        assert!(!excluded.contains(&repo.join("media/logo.iso")));
+        assert!(!excluded.contains(&repo.join("media/notes.txt")));
+        // Inside an excluded directory: covered by it
+        assert!(!excluded.contains(&repo.join("target/out.iso")));
+
+        let all = ResolveOptions {
+            exclude_extensions_scope: ExtensionScope::All,
+            ..options
+        };
+        let excluded = resolve_repo(&repo, &all).unwrap();
+        assert!(excluded.contains(&repo.join("media/logo.iso")));
+    }
+
+    #[test]
+    fn test_extra_repo_relative_excludes_follow_worktree_gitdir() {
+        let tmp = tempdir().unwrap();
+        let main = tmp.path().join("main");
+        fs::create_dir_all(main.join(".git/lfs/objects")).unwrap();
+        let wt_git = main.join(".git/worktrees/feature");
+        fs::create_dir_all(&wt_git).unwrap();
+        fs::write(wt_git.join("commondir"), "../..\n").unwrap();
+        let worktree = tmp.path().join("feature");
+        fs::create_dir_all(worktree.join("assets/cache")).unwrap();
+        fs::write(
+            worktree.join(".git"),
+            format!("gitdir: {}\n", wt_git.display()),
+        )
+        .unwrap();
+
+        let options = ResolveOptions {
+            extra_repo_relative_excludes: vec![
+                ".git/lfs/objects".to_string(),
+                "assets/cache".to_string(),
+                "../outside".to_string(),
+                "missing/dir".to_string(),
+            ],
+            ..default_options()
+        };
+        let excluded = resolve_repo(&worktree, &options).unwrap();
+
+        let lfs = fs::canonicalize(main.join(".git/lfs/objects")).unwrap();
+        let expected: HashSet<PathBuf> = [lfs, worktree.join("assets/cache")].into_iter().collect();
+        assert_eq!(excluded, expected);
+    }
+
+    #[test]
+    fn test_small_ignored_files_stay_unless_named_explicitly() {
+        let tmp = tempdir().unwrap();
+        let repo = make_repo(tmp.path());
+        fs::write(repo.join(".gitignore"), "*.log\n*.txt\nlogs/\n").unwrap();
+        fs::write(repo.join(".lignore"), "notes.txt\n").unwrap();
+        fs::create_dir_all(repo.join("logs")).unwrap();
+        fs::write(repo.join("logs/tiny.log"), "x").unwrap();
+        fs::write(repo.join("tiny.log"), "x").unwrap();
+        fs::write(repo.join("big.log"), vec![b'x'; 3000]).unwrap();
+        fs::write(repo.join("notes.txt"), "x").unwrap();
+        fs::write(repo.join("scratch.txt"), "x").unwrap();
+
+        let options = ResolveOptions {
+            min_file_size: 2000,
+            ..default_options()
+        };
+        let excluded = resolve_repo_sources(&repo, &options).unwrap();
+        let mut names: Vec<_> = excluded
+            .keys()
+            .map(|p| {
+                p.strip_prefix(&repo)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        names.sort();
+        // The directory goes whatever it holds; `.lignore` names notes.txt
+        assert_eq!(names, ["big.log", "logs", "notes.txt"]);
+        assert!(excluded[&repo.join("notes.txt")].is_lignore());
+    }
+
+    #[test]
+    fn test_parallel_gitignore_walk_matches_the_sequential_one() {
+        let tmp = tempdir().unwrap();
+        let repo = make_repo(tmp.path());
+        for dir in ["app/build/x", "app/sub/build", "lib/logs", ".git/info"] {
+            fs::create_dir_all(repo.join(dir)).unwrap();
+        }
+        fs::write(repo.join(".gitignore"), "target/\nnode_modules/\n*.log\n").unwrap();
+        // Anchored to app/, and overriding the root's `*.log` below it
+        fs::write(repo.join("app/.gitignore"), "/build/\n!keep.log\n").unwrap();
+        fs::write(repo.join("lib/.gitignore"), "logs/\n").unwrap();
+        fs::write(repo.join(".git/info/exclude"), "*.tmp\n").unwrap();
+        for file in [
+            "app/keep.log",
+            "app/sub/drop.log",
+            "app/sub/keep.log",
+            "lib/logs/a.log",
+            "lib/x.tmp",
+        ] {
+            fs::write(repo.join(file), "").unwrap();
+        }
+        fs::write(
+            repo.join(".git/index"),
+            git::test_index(2, &[b".gitignore", b"app/.gitignore"]),
+        )
+        .unwrap();
+
+        let sequential = ResolveOptions {
+            parallel_walk_min_files: 0,
+            ..default_options()
+        };
+        assert!(!walks_in_parallel(&repo, &sequential));
+        let expected = resolve_repo_sources(&repo, &sequential).unwrap();
+        let mut paths: Vec<_> = expected.keys().collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                "app/build",
+                "app/sub/drop.log",
+                "lib/logs",
+                "lib/x.tmp",
+                "node_modules",
+                "target"
+            ]
+            .map(|p| repo.join(p))
+            .iter()
+            .collect::<Vec<_>>()
+        );
+
+        for backend in [WalkBackend::Ignore, WalkBackend::Walkdir] {
+            let parallel = ResolveOptions {
+                parallel_walk_min_files: 2,
+                walker: Walker {
+                    backend,
+                    ..Walker::default()
+                },
+                ..default_options()
+            };
+            assert!(walks_in_parallel(&repo, &parallel));
+            let found = resolve_repo_sources(&repo, &parallel).unwrap();
+            assert_eq!(found, expected, "{backend}");
+        }
+        let small = ResolveOptions {
+            parallel_walk_min_files: 3,
+            ..default_options()
+        };
+        assert!(!walks_in_parallel(&repo, &small));
+    }
+
+    #[test]
+    fn test_worktree_uses_shared_info_exclude() {
+        let tmp = tempdir().unwrap();
+
+        // Main repo whose info/exclude ignores `scratch/` and `keep/`
+        let main = tmp.path().join("main");
+        fs::create_dir_all(main.join(".git/info")).unwrap();
+        fs::write(main.join(".git/info/exclude"), "scratch/\nkeep/\n").unwrap();
+        let wt_git = main.join(".git/worktrees/feature");
+        fs::create_dir_all(&wt_git).unwrap();
+        fs::write(wt_git.join("commondir"), "../..\n").unwrap();
+
+        // Linked worktree with its own .gitignore; `.git` is a gitdir file
+        let worktree = tmp.path().join("feature");
+        fs::create_dir_all(worktree.join("scratch")).unwrap();
+        fs::create_dir_all(worktree.join("target")).unwrap();
+        fs::create_dir_all(worktree.join("keep")).unwrap();
+        fs::write(
+            worktree.join(".git"),
+            format!("gitdir: {}\n", wt_git.display()),
+        )
+        .unwrap();
+        // A worktree .gitignore negation must override info/exclude
+        fs::write(worktree.join(".gitignore"), "target/\n!keep/\n").unwrap();
+
+        let excluded = resolve_excluded_paths(&worktree, &empty_whitelist()).unwrap();
+
+        assert!(excluded.contains(&worktree.join("scratch")));
+        assert!(excluded.contains(&worktree.join("target")));
+        assert!(!excluded.contains(&worktree.join("keep")));
+    }
+
     #[cfg(unix)]
     #[test]
     fn test_symlinks_not_followed_inside_repo() {
@@ -623,4 +2178,67 @@ mod tests {
         // Contents of the external dir must not be reachable via the symlink
         assert!(!excluded.contains(&external.join("secret.txt")));
     }
+
+    #[test]
+    fn test_collapse_file_exclusions() {
+        let tmp = tempdir().unwrap();
+        let root = tmp.path().join("repo");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join("logs/old")).unwrap();
+        for f in [
+            "a.log",
+            "b.log",
+            "c.log",
+            "README",
+            "old/x.log",
+            "old/y.log",
+        ] {
+            fs::write(root.join("logs").join(f), "").unwrap();
+        }
+        fs::write(root.join("top.log"), "").unwrap();
+        fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+        let config = Config {
+            whitelist: vec![],
+            collapse_threshold: 75,
+            ..Config::default()
+        };
+        let options = ResolveOptions::from_config(&config).unwrap();
+
+        // logs/old is fully excluded, which makes 4 of the 5 entries of logs/
+        // excluded; the repo root itself is never collapsed.
+        let mut excluded = resolve_repo(&root, &options).unwrap();
+        let collapsed = collapse_file_exclusions(&root, &mut excluded, &options);
+        assert_eq!(
+            excluded,
+            [root.join("logs"), root.join("top.log")]
+                .into_iter()
+                .collect()
+        );
+        assert_eq!(
+            collapsed.into_iter().collect::<Vec<_>>(),
+            vec![(root.join("logs"), vec![root.join("logs/README")])]
+        );
+
+        // Above the threshold only logs/old collapses
+        let options = ResolveOptions::from_config(&Config {
+            collapse_threshold: 90,
+            ..config.clone()
+        })
+        .unwrap();
+        let mut excluded = resolve_repo(&root, &options).unwrap();
+        collapse_file_exclusions(&root, &mut excluded, &options);
+        assert!(excluded.contains(&root.join("logs/old")));
+        assert!(excluded.contains(&root.join("logs/a.log")));
+
+        // A whitelisted entry blocks the collapse that would swallow it
+        let options = ResolveOptions::from_config(&Config {
+            whitelist: vec!["**/README".to_string()],
+            ..config
+        })
+        .unwrap();
+        let mut excluded = resolve_repo(&root, &options).unwrap();
+        collapse_file_exclusions(&root, &mut excluded, &options);
+        assert!(!excluded.contains(&root.join("logs")));
+        assert!(excluded.contains(&root.join("logs/old")));
+    }
 }
@@ -1,32 +1,111 @@
+pub mod access;
+pub mod artifacts;
+pub mod backends;
+pub mod backup;
+pub mod build_info;
 pub mod cache;
+pub mod capabilities;
 pub mod clean;
 pub mod cli;
 pub mod config;
+pub mod doctor;
+pub mod encryption;
 pub mod error;
+pub mod explain;
+pub mod export;
+pub mod external;
+pub mod git;
+pub mod guard;
+pub mod helper;
+pub mod i18n;
 pub mod ignore_resolver;
+pub mod lignore;
+pub mod lint;
+pub mod list;
+pub mod lock;
+pub mod modes;
+pub mod output;
+pub mod pattern_cache;
+pub mod permissions;
+pub mod plan;
+pub mod policy;
+pub mod provenance;
+pub mod quarantine;
+pub mod query;
+pub mod report;
+pub mod repos;
 pub mod scanner;
+pub mod schema;
+pub mod service;
+pub mod size;
+pub mod sync_ignore;
+pub mod test_glob;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+pub mod throttle;
+pub mod tm;
+#[cfg(target_os = "macos")]
+pub mod tmplist;
 pub mod tmutil;
+pub mod walk;
+pub mod warnings;
 
 use anyhow::{Context, Result};
-use chrono::Local;
+use chrono::{DateTime, FixedOffset, Local};
+use crossbeam_channel::Sender;
 use directories::BaseDirs;
-use fd_lock::RwLock as FdRwLock;
-use owo_colors::OwoColorize;
+use path_clean::PathClean;
 use rayon::prelude::*;
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::OsString,
     fs,
     io::{self, IsTerminal, Write},
     path::{Path, PathBuf},
-    time::Instant,
+    process::{Command, ExitStatus},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 use tracing::{debug, info, warn};
 
-use cache::{Cache, diff_sets, load_cache, write_cache};
-use config::{Config, expand_tilde};
-use ignore_resolver::{build_whitelist_globset, resolve_excluded_paths};
-use scanner::discover_repos;
-use tmutil::{ExclusionManager, TmutilManager};
+use access::{DirectAccess, FileSystemAccess};
+use artifacts::resolve_artifact_paths;
+use backends::{CachedirTagManager, ExcludesFileManager};
+use backup::analyze_backup;
+use cache::{
+    Cache, KnownRepos, Origin, PathMeta, diff_sets, find_snapshot, list_snapshots, load_cache,
+    nested_paths, snapshot_cache, snapshot_dir, snapshot_taken_at, write_cache,
+};
+use config::{Backend, Config, ExclusionMode, FixedPathBackend, expand_tilde, rebase};
+use export::ExportFormat;
+use guard::{SafetyRails, Violation};
+use ignore_resolver::{
+    ResolveOptions, collapse_file_exclusions, keep_marker, resolve_bare_repo_excludes,
+    resolve_repo, resolve_repo_sources,
+};
+use lignore::{LignoreEdit, edit_lignore, lignore_line};
+use list::{LIST_SCHEMA_VERSION, ListFormat, ListOutput, ListSort, PathEntry};
+use lock::InstanceLock;
+use modes::ModeSelector;
+use output::Output;
+use pattern_cache::{PatternCache, pattern_cache_path};
+use plan::{PLAN_VERSION, Plan, config_hash, read_plan, write_plan};
+use provenance::{PathRule, Rule, RuleKind, group_by_rule};
+use quarantine::Quarantine;
+use report::{RunReport, SKIP_LOCK_HELD, write_report};
+use repos::{REPOS_SCHEMA_VERSION, ReposOutput};
+use scanner::{Discovery, discover, discover_streaming};
+use schema::SchemaName;
+use service::{Schedule, ServiceState, ServiceStatus, Wake};
+use size::{disk_size, estimate_impact, format_size, keep_largest};
+use sync_ignore::{SyncIgnoreManager, dropbox_roots};
+use test_glob::{PatternKind, PatternTester, Verdict};
+#[cfg(target_os = "macos")]
+use tmplist::PlistManager;
+#[cfg(target_os = "macos")]
+use tmutil::TmutilManager;
+use tmutil::{ExclusionManager, RootedManager};
+use warnings::{Warning, WarningKind};
 
 // ─── AppContext ───────────────────────────────────────────────────────────────
 
@@ -42,8 +121,14 @@ pub struct AppContext {
     pub cache_path: PathBuf,
     /// Path to the advisory lock file used to prevent concurrent runs.
     pub lock_path: PathBuf,
-    /// Abstraction over `tmutil` — real in production, mocked in tests.
+    /// The exclusion backend — `tmutil` or another [`config::Backend`] in
+    /// production, mocked in tests.
     pub exclusion_manager: Box<dyn ExclusionManager>,
+    /// How the directories a run reads are reached: directly for the CLI,
+    /// through security-scoped bookmarks in a sandboxed app.
+    pub fs_access: Box<dyn FileSystemAccess>,
+    /// How human-readable output is printed (`--plain`, color).
+    pub output: Output,
 }
 
 impl AppContext {
@@ -59,9 +144,107 @@ impl AppContext {
             config_path,
             cache_path,
             lock_path,
-            exclusion_manager: Box::new(TmutilManager),
+            exclusion_manager: default_exclusion_manager(),
+            fs_access: Box::new(DirectAccess),
+            output: Output::default(),
+        }
+    }
+
+    /// Move the config, cache and lock paths under `root` (the global
+    /// `--root`).
+    pub fn rebase_under(&mut self, root: &Path) {
+        for path in [
+            &mut self.config_path,
+            &mut self.cache_path,
+            &mut self.lock_path,
+        ] {
+            *path = rebase(root, path);
         }
     }
+
+    /// Replace the exclusion manager with the backend selected by `config`
+    /// (`backend`; for Time Machine also `xattr_concurrency` and
+    /// `fixed_path_backend`).
+    ///
+    /// Under [`Config::root`] the manager refuses paths outside it.
+    pub fn use_backend_from(&mut self, config: &Config) -> Result<()> {
+        let manager: Box<dyn ExclusionManager> = match config.backend {
+            Backend::TimeMachine => time_machine_manager(config)?,
+            Backend::CachedirTag => Box::new(CachedirTagManager),
+            Backend::ExcludesFile => Box::new(ExcludesFileManager::new(
+                config.rooted(&expand_tilde(&config.excludes_file)),
+                config.excludes_file_format,
+            )),
+        };
+        let manager: Box<dyn ExclusionManager> = if config.sync_ignore {
+            let dropbox_info = config.rooted(&expand_tilde("~/.dropbox/info.json"));
+            Box::new(SyncIgnoreManager::new(
+                manager,
+                dropbox_roots(&dropbox_info),
+            ))
+        } else {
+            manager
+        };
+        self.exclusion_manager = match &config.root {
+            Some(root) => Box::new(RootedManager::new(root, manager)),
+            None => manager,
+        };
+        Ok(())
+    }
+}
+
+/// The backend of [`AppContext::production`]: Time Machine on macOS.
+#[cfg(target_os = "macos")]
+fn default_exclusion_manager() -> Box<dyn ExclusionManager> {
+    Box::new(TmutilManager::default())
+}
+
+/// The backend of [`AppContext::production`]: cache directory tags where
+/// there is no Time Machine.
+#[cfg(not(target_os = "macos"))]
+fn default_exclusion_manager() -> Box<dyn ExclusionManager> {
+    Box::new(CachedirTagManager)
+}
+
+/// The Time Machine backend for `config`.
+///
+/// Under [`Config::root`], fixed-path mode always edits the plist inside the
+/// root: `tmutil -p` would change the running system's settings.
+#[cfg(target_os = "macos")]
+fn time_machine_manager(config: &Config) -> Result<Box<dyn ExclusionManager>> {
+    let binary = config.tmutil_binary_path.as_deref().map(expand_tilde);
+    let tmutil = TmutilManager::new(config.xattr_concurrency)
+        .with_binary(tmutil::tmutil_binary(binary.as_deref()))
+        .with_batching(tmutil::Batching {
+            size: config.tmutil_batch_size,
+            adaptive: config.tmutil_adaptive_batching,
+            max_size: config.tmutil_max_batch_size,
+            timeout: config.tmutil_batch_timeout,
+            retry_timeout: config.tmutil_retry_timeout,
+        });
+    if !config.uses_fixed_path() {
+        return Ok(Box::new(tmutil));
+    }
+    Ok(match &config.root {
+        Some(root) => {
+            let plist = rebase(root, Path::new(tmplist::TIME_MACHINE_PLIST));
+            Box::new(PlistManager::new(plist).with_sticky(tmutil))
+        }
+        None if config.fixed_path_backend == FixedPathBackend::Plist => {
+            Box::new(PlistManager::system().with_sticky(tmutil))
+        }
+        None if config.fixed_path_backend == FixedPathBackend::Helper => Box::new(
+            helper::HelperManager::new(helper::HELPER_SOCKET, Box::new(tmutil)),
+        ),
+        None => Box::new(tmutil),
+    })
+}
+
+#[cfg(not(target_os = "macos"))]
+fn time_machine_manager(_config: &Config) -> Result<Box<dyn ExclusionManager>> {
+    anyhow::bail!(
+        "backend = \"time-machine\" needs macOS; use \"cachedir-tag\" or \"excludes-file\""
+    )
 }
 
 fn default_config_path() -> PathBuf {
@@ -78,6 +261,30 @@ fn default_cache_path() -> PathBuf {
 
 // ─── `run` command ────────────────────────────────────────────────────────────
 
+/// Options for the `run` command beyond the config file.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Search paths to use instead of the configured ones.
+    pub search_path_overrides: Vec<PathBuf>,
+    /// Print what would change but make no system calls.
+    pub dry_run: bool,
+    /// Skip the run unless the cache's `last_run` is older than this.
+    pub if_stale: Option<Duration>,
+    /// Re-resolve the cached repo list instead of walking the search paths.
+    pub fast: bool,
+    /// Allow exclusions shallower than `min_exclusion_depth`.
+    pub force: bool,
+    /// Approve new exclusions above `large_exclusion_threshold` without asking.
+    pub yes_large: bool,
+    /// Take over the lock if the process recorded in it is no longer alive.
+    pub steal_stale_lock: bool,
+    /// Fail instead of warning when a directory cannot be scanned.
+    pub strict_permissions: bool,
+    /// When another instance holds the lock, ask a running service for one
+    /// follow-up run instead of only skipping.
+    pub queue_if_locked: bool,
+}
+
 /// Execute the `run` command: scan repos, compute exclusions, apply the diff.
 ///
 /// Steps: acquire lock → load cache → discover repos → resolve ignored paths
@@ -88,341 +295,3991 @@ pub fn cmd_run(
     config: &Config,
     search_path_overrides: &[PathBuf],
     dry_run: bool,
+) -> Result<()> {
+    let options = RunOptions {
+        search_path_overrides: search_path_overrides.to_vec(),
+        dry_run,
+        ..RunOptions::default()
+    };
+    cmd_run_with(ctx, config, &options)
+}
+
+/// Execute the `run` command with the full set of [`RunOptions`].
+///
+/// With `report_path` set, a [`RunReport`] is written there after every run
+/// that is not a dry run, whether it succeeded, was skipped or failed.
+pub fn cmd_run_with(ctx: &AppContext, config: &Config, options: &RunOptions) -> Result<()> {
+    run_once(ctx, config, options).1
+}
+
+/// [`cmd_run_with`], also returning the run's report.
+fn run_once(ctx: &AppContext, config: &Config, options: &RunOptions) -> (RunReport, Result<()>) {
+    let mut report = RunReport::start(&config.exclusion_mode);
+    let result = run_reported(ctx, config, options, &mut report);
+    report.finish(&result);
+    if let Some(summary) = warnings::summary(&report.warnings) {
+        warn!("{summary}");
+    }
+    if let Some(path) = config.resolved_report_path().filter(|_| !options.dry_run)
+        && let Err(e) = write_report(&path, &report)
+    {
+        warn!("Could not write the run report: {e:#}");
+    }
+    (report, result)
+}
+
+/// The body of [`cmd_run_with`], filling in `report` as it goes.
+fn run_reported(
+    ctx: &AppContext,
+    config: &Config,
+    options: &RunOptions,
+    report: &mut RunReport,
 ) -> Result<()> {
     let start = Instant::now();
+    let dry_run = options.dry_run;
+
+    // Cheap early exit for opportunistic callers (shell hooks, cron): only
+    // the cache is read, no lock is taken and nothing is scanned.
+    if let Some(max_age) = options.if_stale
+        && let Some(age) = last_run_age(&ctx.cache_path)?
+        && age < max_age
+    {
+        debug!(
+            "Last run was {}s ago (< {}s); skipping.",
+            age.as_secs(),
+            max_age.as_secs()
+        );
+        report.skip("the last run is more recent than --if-stale");
+        return Ok(());
+    }
 
     // Acquire lockfile — skip if already held by another instance
-    let mut lock = open_lock_file(&ctx.lock_path)?;
-    let Ok(_guard) = lock.try_write() else {
-        warn!("Another letitgo instance is running. Skipping.");
+    let mut lock = open_lock(ctx, config)?;
+    let Some(_guard) = lock.try_acquire(options.steal_stale_lock)? else {
+        report.skip(SKIP_LOCK_HELD);
+        if options.queue_if_locked && !dry_run {
+            queue_service_run(ctx);
+        }
         return Ok(());
     };
 
-    // Determine effective search paths
-    let search_paths: Vec<PathBuf> = if search_path_overrides.is_empty() {
-        config.resolved_search_paths()
-    } else {
-        search_path_overrides.to_vec()
-    };
+    let search_paths = run_search_paths(config, options);
     let ignored_paths = config.resolved_ignored_paths();
+    let _access = access::begin_run(ctx.fs_access.as_ref(), config, &search_paths);
 
-    debug!("Scanning {} search path(s)…", search_paths.len());
-    for sp in &search_paths {
-        debug!("  {}", sp.display());
+    // A changed exclusion mode moves the cached exclusions over first
+    let modes = ModeSelector::new(config)?;
+    let old_cache = migrate_modes(ctx, config, &modes, load_cache(&ctx.cache_path)?, dry_run)?;
+    if config.streaming_apply {
+        if config.max_exclusions == 0 {
+            return run_streaming(
+                ctx,
+                config,
+                options,
+                &search_paths,
+                &ignored_paths,
+                old_cache,
+                report,
+            );
+        }
+        warn!("Ignoring `streaming_apply`: `max_exclusions` needs the whole exclusion set");
+    }
+    let patterns_path = pattern_cache_path(&ctx.cache_path);
+    let patterns = Arc::new(PatternCache::load(&patterns_path));
+    let (plan, large) = build_plan(
+        ctx,
+        config,
+        options,
+        &search_paths,
+        &ignored_paths,
+        old_cache,
+        Arc::clone(&patterns),
+        report,
+    )?;
+    if !dry_run && let Err(e) = patterns.save(&patterns_path) {
+        warn!("Could not save the ignore pattern cache: {e:#}");
     }
+    report.resolve_ms = report::millis(start.elapsed());
 
-    // Detect mode switch — block the run unless the user resets first
-    let old_cache = load_cache(&ctx.cache_path)?;
-    if old_cache.exclusion_mode != config.exclusion_mode && !old_cache.paths.is_empty() {
+    apply_locked(ctx, config, plan, dry_run, report)?;
+    report.apply_ms = report::millis(start.elapsed()).saturating_sub(report.resolve_ms);
+    log_run_summary(start, report, &large);
+    Ok(())
+}
+
+/// Move each cached exclusion whose mode is not the one now configured for
+/// it (`exclusion_mode` changed, or `mode_rules` pick another) in place:
+/// the moved paths that still exist are excluded in their new mode before
+/// all of them are removed in their old one, so nothing is backed up in
+/// between.  The cache is rewritten (with the new modes, without vanished
+/// moved paths) only once both steps succeeded; after a failure it still
+/// records the old modes and the next run retries.  A dry run only changes
+/// the copy.
+fn migrate_modes(
+    ctx: &AppContext,
+    config: &Config,
+    modes: &ModeSelector,
+    mut cache: Cache,
+    dry_run: bool,
+) -> Result<Cache> {
+    let moved: HashSet<PathBuf> = modes
+        .moved(&cache)
+        .into_iter()
+        .map(Path::to_path_buf)
+        .collect();
+    if moved.is_empty() && cache.exclusion_mode == config.exclusion_mode {
+        return Ok(cache);
+    }
+    if !moved.is_empty() {
+        let kept: Vec<&Path> = moved
+            .iter()
+            .map(PathBuf::as_path)
+            .filter(|p| p.exists())
+            .collect();
         if dry_run {
             info!(
-                "[dry-run] Exclusion mode changed from `{}` to `{}`. \
-                 Run `letitgo reset` before switching modes.",
-                old_cache.exclusion_mode, config.exclusion_mode
+                "[dry-run] Would move {} exclusion(s) to the mode now configured for them",
+                kept.len()
             );
-            return Ok(());
-        }
-        if !io::stdin().is_terminal() {
-            warn!(
-                "Exclusion mode changed from `{}` to `{}`. \
-                 Run `letitgo reset` first to clear old exclusions. Skipping.",
-                old_cache.exclusion_mode, config.exclusion_mode
+        } else {
+            info!(
+                "Moving {} exclusion(s) to the mode now configured for them (`exclusion_mode` is `{}`)…",
+                kept.len(),
+                config.exclusion_mode
             );
-            return Ok(());
+            modes::add_by_mode(ctx.exclusion_manager.as_ref(), &kept, |p| {
+                modes.mode_for(p).clone()
+            })
+            .context("adding exclusions in their new mode")?;
+            let old: Vec<&Path> = moved.iter().map(PathBuf::as_path).collect();
+            modes::remove_by_mode(ctx.exclusion_manager.as_ref(), &old, |p| cache.mode_for(p))
+                .context("removing exclusions in their old mode")?;
         }
+    }
 
-        eprint!(
-            "Exclusion mode changed from `{}` to `{}`. \
-             Do you want to reset now?\n\
-             This will remove {} exclusion(s) previously set with `{}` mode \
-             and clear the cache, then continue with the scan. [y/N] ",
-            old_cache.exclusion_mode,
-            config.exclusion_mode,
-            old_cache.paths.len(),
-            old_cache.exclusion_mode,
-        );
-        io::stderr().flush()?;
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        if !matches!(input.trim().to_lowercase().as_str(), "y" | "yes") {
-            info!("Aborted. Run `letitgo reset` manually before switching modes.");
-            return Ok(());
+    cache.paths.retain(|p| !moved.contains(p) || p.exists());
+    cache.exclusion_mode = config.exclusion_mode.clone();
+    modes.record(&cache.paths, &mut cache.meta);
+    cache.prune_meta();
+    if !dry_run && !moved.is_empty() {
+        if config.cache_snapshots > 0 {
+            snapshot_cache(&ctx.cache_path, config.cache_snapshots)?;
         }
+        write_cache(&ctx.cache_path, &cache)?;
+    }
+    Ok(cache)
+}
 
-        // Perform reset using the OLD mode flag so the correct tmutil verb is used
-        let old_fixed_path = old_cache.exclusion_mode.is_fixed_path();
-        let path_refs: Vec<&Path> = old_cache.paths.iter().map(|p| p.as_path()).collect();
-        ctx.exclusion_manager
-            .remove_exclusions(&path_refs, old_fixed_path)?;
-        if ctx.cache_path.exists() {
-            fs::remove_file(&ctx.cache_path).with_context(|| {
-                format!("removing cache during reset: {}", ctx.cache_path.display())
-            })?;
-        }
-        info!(
-            "Reset complete. {} exclusion(s) removed. Continuing with scan…",
-            old_cache.paths.len()
-        );
+/// The search paths of a run: the `--search-path` overrides, or the
+/// configured ones.
+fn run_search_paths(config: &Config, options: &RunOptions) -> Vec<PathBuf> {
+    let search_paths = if options.search_path_overrides.is_empty() {
+        config.resolved_search_paths()
+    } else {
+        options.search_path_overrides.clone()
+    };
+    debug!("Scanning {} search path(s)…", search_paths.len());
+    for sp in &search_paths {
+        debug!("  {}", sp.display());
     }
+    search_paths
+}
 
-    // Reload cache after potential reset (may now be empty)
-    let old_set = load_cache(&ctx.cache_path)?.path_set();
+/// Steps 1–4 of `run`: discover repos, resolve the exclusion set and diff
+/// it against `old_cache`, without changing anything.  Also returns the
+/// large exclusions that were approved.
+#[allow(clippy::too_many_arguments)]
+fn build_plan(
+    ctx: &AppContext,
+    config: &Config,
+    options: &RunOptions,
+    search_paths: &[PathBuf],
+    ignored_paths: &[PathBuf],
+    old_cache: Cache,
+    patterns: Arc<PatternCache>,
+    report: &mut RunReport,
+) -> Result<(Plan, Vec<(PathBuf, u64)>)> {
+    let old_set = old_cache.path_set();
+    let Resolved {
+        paths: mut new_set,
+        mut meta,
+        mut collapsed,
+        mut rules,
+        known_repos,
+        repos,
+        covered,
+        permission_denied,
+        errors,
+        warnings,
+    } = resolve_exclusions(
+        config,
+        options,
+        search_paths,
+        ignored_paths,
+        old_cache.known_repos,
+        Arc::clone(&patterns),
+    )?;
+    report.repos = repos;
+    report.covered_repos = covered;
+    report.permission_denied.clone_from(&permission_denied);
+    report.errors = errors;
+    report.warnings = warnings;
+    check_permissions(options, &permission_denied)?;
 
-    // 1) Discover repos
-    let repos = discover_repos(&search_paths, &ignored_paths);
-    debug!("Found {} Git repo(s)", repos.len());
+    // Large new exclusions may hold data the user wants backed up despite
+    // being ignored: list them and ask before excluding them.
+    let large = gate_large_exclusions(&mut new_set, &old_set, config, options)?;
+    meta.retain(|p, _| new_set.contains(p));
+    ModeSelector::new(config)?.record(&new_set, &mut meta);
+    collapsed.retain(|p, _| new_set.contains(p));
 
-    // 2) Build whitelist globset
-    let whitelist_globs = build_whitelist_globset(&config.whitelist)?;
+    let sorted = |paths: Vec<&Path>| {
+        let mut paths: Vec<PathBuf> = paths.into_iter().map(Path::to_path_buf).collect();
+        paths.sort();
+        paths
+    };
+    let (add, reassert, remove) = diff_changes(ctx, config, &old_set, &new_set)?;
+    let (add, mut reassert, remove) = (sorted(add), sorted(reassert), sorted(remove));
+    let mut failures = old_cache.failures;
+    failures.retain(|p, _| new_set.contains(p));
+    let mut added = old_cache.added;
+    added.retain(|p, _| new_set.contains(p));
+    let cache_rules: BTreeMap<PathBuf, Rule> = rules
+        .iter()
+        .filter(|(p, r)| r.repo.is_some() && new_set.contains(*p))
+        .map(|(p, r)| (p.clone(), r.rule.clone()))
+        .collect();
+    // Paths that failed last time are tried again (quarantined ones only
+    // when due, see `apply_changes`)
+    let retry: Vec<PathBuf> = failures
+        .keys()
+        .filter(|p| old_set.contains(*p) && reassert.binary_search(p).is_err())
+        .cloned()
+        .collect();
+    reassert.extend(retry);
+    reassert.sort();
+    rules.retain(|p, _| add.binary_search(p).is_ok() || reassert.binary_search(p).is_ok());
+    let mut paths: Vec<PathBuf> = new_set.into_iter().collect();
+    paths.sort();
+    let mut plan = Plan {
+        plan_version: PLAN_VERSION,
+        letitgo_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: Local::now().fixed_offset(),
+        exclusion_mode: config.exclusion_mode.clone(),
+        base_last_run: old_cache.last_run,
+        add,
+        reassert,
+        remove,
+        rules,
+        cache: Cache {
+            version: 1,
+            last_run: old_cache.last_run,
+            exclusion_mode: config.exclusion_mode.clone(),
+            paths,
+            meta,
+            collapsed,
+            known_repos,
+            tmutil_batch_size: old_cache.tmutil_batch_size,
+            failures,
+            added,
+            rules: cache_rules,
+            format: config.cache_format,
+            encrypted: config.cache_encryption,
+        },
+        config_sha256: config_hash(config),
+        rule_files: patterns.read_files(),
+        plan_sha256: String::new(),
+    };
+    plan.seal();
+    Ok((plan, large))
+}
 
-    // 3) Resolve excluded paths for each repo in parallel
-    let new_set: HashSet<PathBuf> = repos
-        .par_iter()
-        .map(|repo| resolve_excluded_paths(repo, &whitelist_globs))
-        .filter_map(|result| {
-            result
-                .map_err(|e| warn!("Error resolving paths: {}", e))
-                .ok()
-        })
-        .reduce(HashSet::new, |mut acc, set| {
-            acc.extend(set);
-            acc
-        });
+/// Step 5–6 of `run`: make the changes of `plan` — or, in a dry run, log
+/// them — and write its cache.  The caller holds the lock.
+fn apply_locked(
+    ctx: &AppContext,
+    config: &Config,
+    mut plan: Plan,
+    dry_run: bool,
+    report: &mut RunReport,
+) -> Result<()> {
+    let mut quarantine = Quarantine::new(config, std::mem::take(&mut plan.cache.failures));
+    let add: Vec<&Path> = plan
+        .add
+        .iter()
+        .chain(&plan.reassert)
+        .map(PathBuf::as_path)
+        .collect();
+    let remove: Vec<&Path> = plan.remove.iter().map(PathBuf::as_path).collect();
+    report.excluded = plan.cache.paths.len();
+    if let Some(size) = plan.cache.tmutil_batch_size {
+        ctx.exclusion_manager.resume_batch_size(size);
+    }
+    apply_changes(
+        ctx,
+        config,
+        (&add, &plan.rules),
+        &remove,
+        dry_run,
+        &mut quarantine,
+        report,
+    )?;
+    if dry_run {
+        return Ok(());
+    }
+    // Keep the state this run replaces, for `letitgo diff`.
+    let set_changed = !plan.add.is_empty() || !plan.remove.is_empty();
+    let now = Local::now().fixed_offset();
+    for p in &plan.add {
+        plan.cache.added.entry(p.clone()).or_insert(now);
+    }
+    let new_cache = Cache {
+        last_run: Some(now),
+        tmutil_batch_size: ctx
+            .exclusion_manager
+            .batch_size()
+            .or(plan.cache.tmutil_batch_size),
+        failures: quarantine.failures,
+        format: config.cache_format,
+        encrypted: config.cache_encryption,
+        ..plan.cache
+    };
+    if set_changed && config.cache_snapshots > 0 {
+        snapshot_cache(&ctx.cache_path, config.cache_snapshots)?;
+    }
+    write_cache(&ctx.cache_path, &new_cache)?;
+    report.added_paths = plan
+        .add
+        .iter()
+        .map(|p| PathEntry::new(&new_cache, p))
+        .collect();
+    Ok(())
+}
 
-    debug!("Total excluded paths computed: {}", new_set.len());
+/// `run` with `streaming_apply`: each group of repos nested in one another
+/// is resolved, diffed against its slice of the cache and applied before the
+/// next group is resolved, so only one group's exclusion set is held next to
+/// the cache.  Exclusions outside every repo (bare repos, system artifacts,
+/// presets) and cached paths outside every repo form a last group.
+fn run_streaming(
+    ctx: &AppContext,
+    config: &Config,
+    options: &RunOptions,
+    search_paths: &[PathBuf],
+    ignored_paths: &[PathBuf],
+    old_cache: Cache,
+    report: &mut RunReport,
+) -> Result<()> {
+    let start = Instant::now();
+    let dry_run = options.dry_run;
+    let Cache {
+        paths: old_paths,
+        known_repos: known,
+        tmutil_batch_size,
+        failures,
+        mut added,
+        ..
+    } = old_cache;
+    let mut quarantine = Quarantine::new(config, failures);
+    if let Some(size) = tmutil_batch_size {
+        ctx.exclusion_manager.resume_batch_size(size);
+    }
 
-    // 4) Diff
-    let (to_add, to_remove) = diff_sets(&old_set, &new_set);
-    let add_count = to_add.len();
-    let remove_count = to_remove.len();
-    debug!(
-        "{} path(s) to add, {} path(s) to remove",
-        add_count, remove_count
-    );
+    let patterns_path = pattern_cache_path(&ctx.cache_path);
+    let resolve_options = ResolveOptions {
+        patterns: Arc::new(PatternCache::load(&patterns_path)),
+        ..ResolveOptions::from_config(config)?
+    };
+    let (discovery, mut known_repos) =
+        discover_for_run(config, options, search_paths, ignored_paths, known, None);
+    debug!("Found {} Git repo(s)", discovery.repos.len());
+    report
+        .permission_denied
+        .clone_from(&discovery.permission_denied);
+    push_permission_denied(&resolve_options.warnings, &discovery.permission_denied);
+    check_permissions(options, &discovery.permission_denied)?;
+    let search_roots: Vec<PathBuf> = search_paths
+        .iter()
+        .chain(&config.resolved_search_paths())
+        .chain(&config.root)
+        .cloned()
+        .collect();
+    let cover_rails = SafetyRails::new(&search_roots, [], config.min_exclusion_depth);
+    let locations = resolve_locations(config, &resolve_options);
+    let extras = resolve_extras(config, &discovery.bare_repos, locations, &resolve_options);
+    let covering = covering_paths(extras.keys(), &cover_rails);
 
-    // 5) Apply exclusions
-    let fixed_path = config.exclusion_mode.is_fixed_path();
+    // Nested repos produce overlapping exclusions, so they are resolved
+    // together with the outermost one; different groups cover disjoint trees.
+    let mut sorted = discovery.repos.clone();
+    sorted.sort();
+    let mut groups: Vec<Vec<PathBuf>> = Vec::new();
+    for repo in sorted {
+        match groups.last_mut() {
+            Some(group) if repo.starts_with(&group[0]) => group.push(repo),
+            _ => groups.push(vec![repo]),
+        }
+    }
+    let roots: HashMap<PathBuf, usize> = groups
+        .iter()
+        .enumerate()
+        .map(|(i, group)| (group[0].clone(), i))
+        .collect();
+    let outside = groups.len();
+    groups.push(Vec::new());
+    let group_of = |p: &Path| {
+        p.ancestors()
+            .find_map(|a| roots.get(a).copied())
+            .unwrap_or(outside)
+    };
+    let mut old_slices: Vec<Vec<PathBuf>> = vec![Vec::new(); groups.len()];
+    for p in old_paths {
+        old_slices[group_of(&p)].push(p);
+    }
+    let mut extra_slices: Vec<BTreeMap<PathBuf, PathMeta>> = vec![BTreeMap::new(); groups.len()];
+    for (p, path_meta) in extras {
+        extra_slices[group_of(&p)].insert(p, path_meta);
+    }
+    let mut resolve_time = start.elapsed();
 
-    if dry_run {
-        for p in &to_add {
-            info!("[dry-run] would add exclusion: {}", p.display());
+    let modes = ModeSelector::new(config)?;
+    let mut paths = Vec::new();
+    let mut meta = BTreeMap::new();
+    let mut collapsed = BTreeMap::new();
+    let mut rules = BTreeMap::new();
+    let mut added_paths = Vec::new();
+    let mut large = Vec::new();
+    let mut set_changed = false;
+    // A group's exclusions all lie in its outermost repo, so the rails need
+    // only its own repos; the last group's may hold any resolved repo
+    let mut resolved_repos: Vec<PathBuf> = Vec::new();
+    let mut resolved_ok = discovery.bare_repos.clone();
+    for (i, ((repos, old_slice), extras)) in
+        groups.iter().zip(old_slices).zip(extra_slices).enumerate()
+    {
+        let group_start = Instant::now();
+        let Uncovered {
+            repos,
+            results,
+            covered,
+        } = resolve_uncovered(
+            repos,
+            &covering,
+            &cover_rails,
+            config,
+            options,
+            &resolve_options,
+            HashMap::new(),
+        );
+        let repos = &repos;
+        report.covered_repos.extend(covered);
+        resolved_repos.extend(repos.iter().cloned());
+        let protected = if i == outside { &resolved_repos } else { repos };
+        let rails = SafetyRails::new(
+            &search_roots,
+            protected.iter().chain(&discovery.bare_repos),
+            config.min_exclusion_depth,
+        );
+        let mut new_set: HashSet<PathBuf> = HashSet::new();
+        let mut group_collapsed = BTreeMap::new();
+        let mut group_rules = HashMap::new();
+        for (repo, result) in repos.iter().zip(results) {
+            match result {
+                Ok((repo_rules, repo_collapsed)) => {
+                    resolved_ok.push(repo.clone());
+                    new_set.extend(repo_rules.keys().cloned());
+                    group_rules.extend(repo_rules);
+                    group_collapsed.extend(repo_collapsed);
+                }
+                Err(e) => {
+                    resolve_options.warnings.push(
+                        WarningKind::RepoSkipped,
+                        None,
+                        format!("Error resolving paths: {e:#}"),
+                    );
+                    report.errors.push(format!("resolving paths: {e:#}"));
+                }
+            }
         }
-        for p in &to_remove {
-            info!("[dry-run] would remove exclusion: {}", p.display());
+        if config.descend_into_excluded {
+            for (dir, entries) in split_around_repos(&mut new_set, repos) {
+                if let Some(rule) = group_rules.remove(&dir) {
+                    group_rules.extend(entries.into_iter().map(|e| (e, rule.clone())));
+                }
+            }
         }
-    } else {
-        // Run add and remove in parallel (they're independent)
-        let (add_res, remove_res) = std::thread::scope(|s| {
-            let add_handle = s.spawn(|| ctx.exclusion_manager.add_exclusions(&to_add, fixed_path));
-            let remove_res = ctx
-                .exclusion_manager
-                .remove_exclusions(&to_remove, fixed_path);
-            let add_res = add_handle.join().expect("add thread panicked");
-            (add_res, remove_res)
-        });
-
-        add_res?;
-        remove_res?;
+        let mut group_meta = BTreeMap::new();
+        for (p, path_meta) in extras {
+            if new_set.insert(p.clone()) {
+                group_meta.insert(p, path_meta);
+            }
+        }
+        enforce_rails(&mut new_set, &group_meta, &rails, config, options);
+        let old_set: HashSet<PathBuf> = old_slice.into_iter().collect();
+        large.extend(gate_large_exclusions(
+            &mut new_set,
+            &old_set,
+            config,
+            options,
+        )?);
+        group_meta.retain(|p, _| new_set.contains(p));
+        modes.record(&new_set, &mut group_meta);
+        group_collapsed.retain(|p, _| new_set.contains(p));
+        group_rules.retain(|p, _| new_set.contains(p));
+        resolve_time += group_start.elapsed();
+        report.warnings.extend(resolve_options.warnings.take());
 
-        // Release borrows on old_set/new_set so we can consume new_set.
-        drop(to_add);
-        drop(to_remove);
+        report.excluded += new_set.len();
+        let dry_run_rules = if dry_run {
+            path_rules(&group_rules, &group_meta, repos)
+        } else {
+            BTreeMap::new()
+        };
+        apply_diff(
+            ctx,
+            config,
+            &old_set,
+            (&new_set, &dry_run_rules),
+            dry_run,
+            &mut quarantine,
+            report,
+        )?;
+        set_changed |= old_set != new_set;
+        let now = Local::now().fixed_offset();
+        for p in old_set.difference(&new_set) {
+            added.remove(p);
+        }
+        for p in new_set.difference(&old_set) {
+            added.insert(p.clone(), now);
+            added_paths.push(p.clone());
+        }
+        meta.extend(group_meta);
+        collapsed.extend(group_collapsed);
+        rules.extend(group_rules);
+        paths.extend(new_set);
+    }
+    report.repos = resolved_repos.len();
+    if let Some(known) = &mut known_repos {
+        known.mark_resolved(&resolved_ok, Local::now().fixed_offset());
+    }
+    report.resolve_ms = report::millis(resolve_time);
+    debug!(
+        "Applied {} repo group(s); compiled {} distinct ignore matcher(s)",
+        groups.len(),
+        resolve_options.patterns.matcher_count()
+    );
 
-        // 6) Write updated cache
+    if !dry_run {
+        if let Err(e) = resolve_options.patterns.save(&patterns_path) {
+            warn!("Could not save the ignore pattern cache: {e:#}");
+        }
         let new_cache = Cache {
             version: 1,
             last_run: Some(Local::now().fixed_offset()),
             exclusion_mode: config.exclusion_mode.clone(),
-            paths: new_set.into_iter().collect(),
+            paths,
+            meta,
+            collapsed,
+            known_repos,
+            tmutil_batch_size: ctx.exclusion_manager.batch_size().or(tmutil_batch_size),
+            failures: quarantine.failures,
+            added,
+            rules,
+            format: config.cache_format,
+            encrypted: config.cache_encryption,
         };
+        if set_changed && config.cache_snapshots > 0 {
+            snapshot_cache(&ctx.cache_path, config.cache_snapshots)?;
+        }
         write_cache(&ctx.cache_path, &new_cache)?;
+        added_paths.sort();
+        report.added_paths = added_paths
+            .iter()
+            .map(|p| PathEntry::new(&new_cache, p))
+            .collect();
+    }
+    report.apply_ms = report::millis(start.elapsed()).saturating_sub(report.resolve_ms);
+    log_run_summary(start, report, &large);
+    Ok(())
+}
+
+/// With `--strict-permissions`, fail when discovery could not read some
+/// directories.
+fn check_permissions(options: &RunOptions, permission_denied: &[PathBuf]) -> Result<()> {
+    if options.strict_permissions && !permission_denied.is_empty() {
+        let list: Vec<String> = permission_denied
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect();
+        anyhow::bail!(
+            "{} director(ies) could not be scanned (permission denied): {}",
+            list.len(),
+            list.join(", ")
+        );
     }
+    Ok(())
+}
 
-    let elapsed = start.elapsed();
+/// Log the outcome of a run that started at `start`, the large exclusions
+/// it approved and the directories it could not read.
+fn log_run_summary(start: Instant, report: &RunReport, large: &[(PathBuf, u64)]) {
     info!(
-        "Done in {:.2}s — added {}, removed {}",
-        elapsed.as_secs_f64(),
-        add_count,
-        remove_count,
+        "{}",
+        tr!(
+            "run-done",
+            seconds = format!("{:.2}", start.elapsed().as_secs_f64()),
+            added = report.added,
+            removed = report.removed,
+        )
     );
+    for (path, size) in large {
+        info!(
+            "{}",
+            tr!(
+                "run-large-approved",
+                path = path.display(),
+                size = format_size(*size),
+            )
+        );
+    }
+    report_permission_denied(&report.permission_denied);
+}
 
-    Ok(())
+/// The rule of each path a streaming group excludes, as [`resolve_exclusions`] records
+/// it for the whole set: the repo rule in `rules`, with the innermost of
+/// `repos` holding the path, else the rule its origin in `meta` implies.
+fn path_rules(
+    rules: &HashMap<PathBuf, Rule>,
+    meta: &BTreeMap<PathBuf, PathMeta>,
+    repos: &[PathBuf],
+) -> BTreeMap<PathBuf, PathRule> {
+    let repo_of = |p: &Path| {
+        repos
+            .iter()
+            .filter(|repo| p.starts_with(repo))
+            .max_by_key(|repo| repo.as_os_str().len())
+            .cloned()
+    };
+    let repo_rules = rules.iter().map(|(p, rule)| {
+        let path_rule = PathRule {
+            rule: rule.clone(),
+            repo: repo_of(p),
+        };
+        (p.clone(), path_rule)
+    });
+    let other_rules = meta.iter().filter_map(|(p, path_meta)| {
+        let rule = Rule::from_meta(path_meta)?;
+        Some((p.clone(), PathRule { rule, repo: None }))
+    });
+    other_rules.chain(repo_rules).collect()
 }
 
-// ─── `list` command ───────────────────────────────────────────────────────────
+/// Diff `new_set` against `old_set` and apply the difference — or, in a dry
+/// run, log it, grouped by the rules recorded for `new_set` — counting the
+/// paths added and removed in `report`.
+fn apply_diff(
+    ctx: &AppContext,
+    config: &Config,
+    old_set: &HashSet<PathBuf>,
+    (new_set, rules): (&HashSet<PathBuf>, &BTreeMap<PathBuf, PathRule>),
+    dry_run: bool,
+    quarantine: &mut Quarantine,
+    report: &mut RunReport,
+) -> Result<()> {
+    let (mut to_add, reassert, to_remove) = diff_changes(ctx, config, old_set, new_set)?;
+    let retry: Vec<PathBuf> = quarantine
+        .failures
+        .keys()
+        .filter(|p| old_set.contains(*p) && new_set.contains(*p))
+        .filter(|p| !reassert.contains(&p.as_path()))
+        .cloned()
+        .collect();
+    to_add.extend(reassert);
+    to_add.extend(retry.iter().map(PathBuf::as_path));
+    apply_changes(
+        ctx,
+        config,
+        (&to_add, rules),
+        &to_remove,
+        dry_run,
+        quarantine,
+        report,
+    )
+}
 
-/// Execute the `list` command: display paths currently recorded in the cache.
+/// Diff `new_set` against `old_set`: the paths to add, the cached paths to
+/// add again and the paths to remove.
 ///
-/// When `json` is `true`, prints machine-readable JSON on stdout.
-/// When `stale_only` is `true`, limits output to paths that no longer exist on disk.
-pub fn cmd_list(ctx: &AppContext, json: bool, stale_only: bool) -> Result<()> {
-    let cache = load_cache(&ctx.cache_path)?;
-    let use_color = io::stdout().is_terminal();
-
-    if json {
-        // Machine-readable JSON on stdout
-        let paths_for_output: Vec<&PathBuf> = if stale_only {
-            cache.paths.iter().filter(|p| !p.exists()).collect()
-        } else {
-            cache.paths.iter().collect()
-        };
+/// Cached paths can lose their xattr when the directory is deleted and
+/// recreated; with `reassert`, those still wanted are re-applied instead of
+/// trusting the cache.
+#[allow(clippy::type_complexity)]
+fn diff_changes<'a>(
+    ctx: &AppContext,
+    config: &Config,
+    old_set: &'a HashSet<PathBuf>,
+    new_set: &'a HashSet<PathBuf>,
+) -> Result<(Vec<&'a Path>, Vec<&'a Path>, Vec<&'a Path>)> {
+    let (to_add, to_remove) = diff_sets(old_set, new_set);
+    let mut lost = Vec::new();
+    // Fixed-path exclusions cannot be lost this way
+    let kept: Vec<&Path> = if config.reassert {
+        let modes = ModeSelector::new(config)?;
+        old_set
+            .intersection(new_set)
+            .map(|p| p.as_path())
+            .filter(|p| !modes.mode_for(p).is_fixed_path() && p.exists())
+            .collect()
+    } else {
+        Vec::new()
+    };
+    if !kept.is_empty() {
+        lost = kept
+            .iter()
+            .zip(ctx.exclusion_manager.are_excluded(&kept))
+            .filter(|(_, excluded)| !excluded)
+            .map(|(p, _)| *p)
+            .collect();
+        for p in &lost {
+            info!("Exclusion missing, re-applying: {}", p.display());
+        }
+    }
+    Ok((to_add, lost, to_remove))
+}
 
-        let output = serde_json::json!({
-            "count": paths_for_output.len(),
-            "last_run": cache.last_run,
-            "exclusion_mode": cache.exclusion_mode,
-            "paths": paths_for_output,
-        });
-        println!("{}", serde_json::to_string_pretty(&output)?);
-        return Ok(());
+/// Add `to_add` and remove `to_remove` — or, in a dry run, log them, the
+/// added paths grouped by the rule `rules` records for them — counting the
+/// paths in `report`.  Each path is changed in the mode
+/// `mode_rules` pick for it, which the cache records as well once
+/// [`migrate_modes`] has run.  Quarantined paths are not added, and the
+/// outcome of each add is recorded in `quarantine`.
+fn apply_changes(
+    ctx: &AppContext,
+    config: &Config,
+    (to_add, rules): (&[&Path], &BTreeMap<PathBuf, PathRule>),
+    to_remove: &[&Path],
+    dry_run: bool,
+    quarantine: &mut Quarantine,
+    report: &mut RunReport,
+) -> Result<()> {
+    let (to_add, held) = quarantine.hold_back(to_add);
+    let to_add = to_add.as_slice();
+    if !held.is_empty() {
+        info!(
+            "Skipping {} quarantined path(s) (see `letitgo list --quarantined`)",
+            held.len()
+        );
     }
+    report.quarantined += held.len();
+    debug!(
+        "{} path(s) to add, {} path(s) to remove",
+        to_add.len(),
+        to_remove.len()
+    );
+    report.added += to_add.len();
+    report.removed += to_remove.len();
 
-    // Plain-text output on stdout
-    if stale_only {
-        let stale: Vec<&PathBuf> = cache.paths.iter().filter(|p| !p.exists()).collect();
-        if stale.is_empty() {
-            let msg = "No stale paths found.";
-            if use_color {
-                println!("{}", msg.dimmed());
-            } else {
-                println!("{msg}");
+    if dry_run {
+        if rules.is_empty() {
+            for p in to_add {
+                info!("[dry-run] would add exclusion: {}", p.display());
             }
         } else {
-            let header = format!("{} stale path(s) (no longer exist on disk):", stale.len());
-            if use_color {
-                println!("{}", header.bold());
-            } else {
-                println!("{header}");
-            }
-            println!();
-            for p in stale {
-                let tag = " [deleted]";
-                if use_color {
-                    println!("  {}{}", p.display(), tag.yellow());
-                } else {
-                    println!("  {}{}", p.display(), tag);
+            for group in group_by_rule(to_add.iter().copied(), rules) {
+                info!("[dry-run] would add {group}:");
+                for p in &group.paths {
+                    info!("[dry-run]   {}", p.display());
                 }
             }
         }
+        for p in to_remove {
+            info!("[dry-run] would remove exclusion: {}", p.display());
+        }
     } else {
-        let paths = &cache.paths;
-        if paths.is_empty() {
-            let msg = "No paths excluded from Time Machine.";
-            if use_color {
-                println!("{}", msg.dimmed());
-            } else {
-                println!("{msg}");
-            }
-        } else {
-            let header = format!("{} path(s) excluded from Time Machine:", paths.len());
-            if use_color {
-                println!("{}", header.bold());
-            } else {
-                println!("{header}");
-            }
-            println!();
-            for p in paths {
-                println!("  {}", p.display());
-            }
+        // Run add and remove in parallel (they're independent)
+        let modes = ModeSelector::new(config)?;
+        let mode_of = |p: &Path| modes.mode_for(p).clone();
+        let manager = ctx.exclusion_manager.as_ref();
+        let (add_res, remove_res) = std::thread::scope(|s| {
+            let add_handle = s.spawn(|| modes::add_by_mode(manager, to_add, mode_of));
+            let remove_res = modes::remove_by_mode(manager, to_remove, mode_of);
+            let add_res = add_handle.join().expect("add thread panicked");
+            (add_res, remove_res)
+        });
+        let first = report.warnings.len();
+        report
+            .warnings
+            .extend(ctx.exclusion_manager.take_warnings());
+        quarantine.record(to_add, &report.warnings[first..]);
+        for p in to_remove {
+            quarantine.failures.remove(*p);
         }
+        add_res?;
+        remove_res?;
     }
-
     Ok(())
 }
 
-// ─── `reset` command ──────────────────────────────────────────────────────────
-
-/// Execute the `reset` command: remove all managed exclusions and delete the cache.
-///
-/// Prompts for confirmation unless `yes` is `true`.
-/// When `dry_run` is `true`, prints what would be removed but makes no changes.
-pub fn cmd_reset(ctx: &AppContext, config: &Config, yes: bool, dry_run: bool) -> Result<()> {
-    // Pre-load to show the count in the confirmation prompt.
-    let preview_cache = load_cache(&ctx.cache_path)?;
+/// Open the instance lock for a mutating command; with `no_lock`, a
+/// disabled one that every acquisition succeeds on.
+fn open_lock(ctx: &AppContext, config: &Config) -> Result<InstanceLock> {
+    if config.no_lock {
+        warn!(
+            "Instance locking is disabled (`no_lock`): a concurrent letitgo run \
+             can apply conflicting exclusions or lose cache updates"
+        );
+        return Ok(InstanceLock::disabled(&ctx.lock_path));
+    }
+    InstanceLock::open(&ctx.lock_path)
+}
 
-    if preview_cache.paths.is_empty() {
-        info!("Nothing to reset — cache is empty.");
-        return Ok(());
+/// Log how to deal with the directories discovery could not read; they
+/// are listed with the run's warnings.
+fn report_permission_denied(denied: &[PathBuf]) {
+    if denied.is_empty() {
+        return;
     }
+    warn!("{}", tr!("run-permission-denied", n = denied.len()));
+}
 
-    if !yes {
-        eprint!(
-            "This will remove {} exclusion(s). Continue? [y/N] ",
-            preview_cache.paths.len()
+/// Record each directory discovery could not read as a warning.
+fn push_permission_denied(warnings: &warnings::Warnings, denied: &[PathBuf]) {
+    for path in denied {
+        warnings.push(
+            WarningKind::PermissionDenied,
+            Some(path),
+            format!(
+                "Could not scan {} (permission denied); repos inside it were skipped",
+                path.display()
+            ),
         );
-        io::stderr().flush()?;
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        if !matches!(input.trim().to_lowercase().as_str(), "y" | "yes") {
-            info!("Aborted.");
-            return Ok(());
-        }
     }
+}
 
-    // Acquire lock after confirmation prompt (before any mutations)
-    let mut lock = open_lock_file(&ctx.lock_path)?;
-    let Ok(_guard) = lock.try_write() else {
-        warn!("Another letitgo instance is running. Skipping.");
-        return Ok(());
-    };
+/// The exclusion set computed by a run, before it is diffed against the cache.
+struct Resolved {
+    paths: HashSet<PathBuf>,
+    meta: BTreeMap<PathBuf, PathMeta>,
+    collapsed: BTreeMap<PathBuf, Vec<PathBuf>>,
+    /// The rule behind each path, where known.
+    rules: BTreeMap<PathBuf, PathRule>,
+    known_repos: Option<KnownRepos>,
+    /// Number of working-tree repositories resolved.
+    repos: usize,
+    /// Working trees left unresolved inside an excluded path.
+    covered: Vec<PathBuf>,
+    /// Directories discovery could not read.
+    permission_denied: Vec<PathBuf>,
+    /// Repositories that could not be resolved, as logged.
+    errors: Vec<String>,
+    /// Everything worked around while resolving.
+    warnings: Vec<Warning>,
+}
 
-    // Re-load cache under lock to avoid TOCTOU race with concurrent runs.
-    let cache = load_cache(&ctx.cache_path)?;
-    if cache.paths.is_empty() {
-        info!("Nothing to reset — cache is empty.");
-        return Ok(());
-    }
+/// Discover repositories and compute the exclusion set: repo rules, bare
+/// repos, system artifacts and presets, then the safety rails and the
+/// `max_exclusions` budget.  Makes no changes; `known` is the cached repo
+/// list used by `--fast`; `patterns` caches parsed ignore files.
+fn resolve_exclusions(
+    config: &Config,
+    options: &RunOptions,
+    search_paths: &[PathBuf],
+    ignored_paths: &[PathBuf],
+    known: Option<KnownRepos>,
+    patterns: Arc<PatternCache>,
+) -> Result<Resolved> {
+    // 1) Build whitelist globset and per-repo policies
+    let resolve_options = ResolveOptions {
+        patterns,
+        ..ResolveOptions::from_config(config)?
+    };
 
-    let fixed_path = config.exclusion_mode.is_fixed_path();
+    // 2) Discover repos — or, with --fast, reuse the cached list — and
+    //    resolve excluded paths for each repo in parallel, but for those
+    //    inside a path excluded anyway
+    let cover_rails = SafetyRails::new(
+        search_paths
+            .iter()
+            .chain(&config.resolved_search_paths())
+            .chain(&config.root),
+        [],
+        config.min_exclusion_depth,
+    );
+    let locations = resolve_locations(config, &resolve_options);
+    let (discovery, mut known_repos, resolved) = if config.pipeline_discovery {
+        let covering = covering_paths(locations.keys(), &cover_rails);
+        discover_and_resolve(
+            config,
+            options,
+            search_paths,
+            ignored_paths,
+            known,
+            &resolve_options,
+            &covering,
+        )
+    } else {
+        let (discovery, known_repos) =
+            discover_for_run(config, options, search_paths, ignored_paths, known, None);
+        (discovery, known_repos, HashMap::new())
+    };
+    debug!("Found {} Git repo(s)", discovery.repos.len());
+    push_permission_denied(&resolve_options.warnings, &discovery.permission_denied);
+    if !discovery.bare_repos.is_empty() {
+        debug!("Found {} bare repo(s)", discovery.bare_repos.len());
+    }
+    let extras = resolve_extras(config, &discovery.bare_repos, locations, &resolve_options);
+    let Uncovered {
+        repos,
+        results,
+        covered,
+    } = resolve_uncovered(
+        &discovery.repos,
+        &covering_paths(extras.keys(), &cover_rails),
+        &cover_rails,
+        config,
+        options,
+        &resolve_options,
+        resolved,
+    );
+    let repos = &repos;
+
+    let mut new_set: HashSet<PathBuf> = HashSet::new();
+    let mut collapsed: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    let mut rules: BTreeMap<PathBuf, PathRule> = BTreeMap::new();
+    let mut errors = Vec::new();
+    let mut resolved_ok = discovery.bare_repos.clone();
+    for (repo, result) in repos.iter().zip(results) {
+        match result {
+            Ok((repo_rules, repo_collapsed)) => {
+                resolved_ok.push(repo.clone());
+                for (p, rule) in repo_rules {
+                    new_set.insert(p.clone());
+                    rules.insert(
+                        p,
+                        PathRule {
+                            rule,
+                            repo: Some(repo.clone()),
+                        },
+                    );
+                }
+                collapsed.extend(repo_collapsed);
+            }
+            Err(e) => {
+                resolve_options.warnings.push(
+                    WarningKind::RepoSkipped,
+                    None,
+                    format!("Error resolving paths: {e:#}"),
+                );
+                errors.push(format!("resolving paths: {e:#}"));
+            }
+        }
+    }
+    if let Some(known) = &mut known_repos {
+        known.mark_resolved(&resolved_ok, Local::now().fixed_offset());
+    }
+    debug!(
+        "Compiled {} distinct ignore matcher(s)",
+        resolve_options.patterns.matcher_count()
+    );
+    if !collapsed.is_empty() {
+        info!(
+            "Collapsed exclusions into {} director(ies)",
+            collapsed.len()
+        );
+    }
+    if config.descend_into_excluded {
+        for (dir, entries) in split_around_repos(&mut new_set, repos) {
+            if let Some(rule) = rules.remove(&dir) {
+                rules.extend(entries.into_iter().map(|e| (e, rule.clone())));
+            }
+        }
+    }
+
+    // Paths from outside working trees carry their origin into the cache.
+    // Repo paths win when a path is produced by both.
+    let mut meta = BTreeMap::new();
+    for (p, path_meta) in extras {
+        if new_set.insert(p.clone()) {
+            if let Some(rule) = Rule::from_meta(&path_meta) {
+                rules.insert(p.clone(), PathRule { rule, repo: None });
+            }
+            meta.insert(p, path_meta);
+        }
+    }
+
+    debug!("Total excluded paths computed: {}", new_set.len());
+
+    let rails = SafetyRails::new(
+        search_paths
+            .iter()
+            .chain(&config.resolved_search_paths())
+            .chain(&config.root),
+        repos.iter().chain(&discovery.bare_repos),
+        config.min_exclusion_depth,
+    );
+    enforce_rails(&mut new_set, &meta, &rails, config, options);
+    meta.retain(|p, _| new_set.contains(p));
+    collapsed.retain(|p, _| new_set.contains(p));
+    rules.retain(|p, _| new_set.contains(p));
+
+    // Over budget: keep the exclusions that save the most space.
+    if config.max_exclusions > 0 && new_set.len() > config.max_exclusions {
+        let total = new_set.len();
+        let dropped = keep_largest(&mut new_set, config.max_exclusions);
+        warn!(
+            "{} exclusion(s) exceed max_exclusions = {}; dropping the {} smallest",
+            total,
+            config.max_exclusions,
+            dropped.len()
+        );
+        for (path, size) in &dropped {
+            info!(
+                "Over budget, not excluded: {} ({} bytes)",
+                path.display(),
+                size
+            );
+        }
+        meta.retain(|p, _| new_set.contains(p));
+        collapsed.retain(|p, _| new_set.contains(p));
+        rules.retain(|p, _| new_set.contains(p));
+    }
+
+    Ok(Resolved {
+        paths: new_set,
+        meta,
+        collapsed,
+        rules,
+        known_repos,
+        repos: repos.len(),
+        covered,
+        permission_denied: discovery.permission_denied,
+        errors,
+        warnings: resolve_options.warnings.take(),
+    })
+}
+
+/// Exclusions from outside working trees — bare repos, then the `locations`
+/// of [`resolve_locations`] — each with its origin.  A path produced by
+/// several keeps the first origin, in that order.
+fn resolve_extras(
+    config: &Config,
+    bare_repos: &[PathBuf],
+    locations: BTreeMap<PathBuf, PathMeta>,
+    resolve_options: &ResolveOptions,
+) -> BTreeMap<PathBuf, PathMeta> {
+    let mut extras = BTreeMap::new();
+    for bare in bare_repos {
+        let paths = resolve_bare_repo_excludes(
+            bare,
+            &config.bare_repo_excludes,
+            &resolve_options.whitelist_globs,
+        );
+        for p in paths {
+            extras
+                .entry(p)
+                .or_insert_with(|| PathMeta::origin(Origin::BareRepo));
+        }
+    }
+    for (p, path_meta) in locations {
+        extras.entry(p).or_insert(path_meta);
+    }
+    extras
+}
+
+/// The system artifacts and preset locations to exclude, minus whitelisted
+/// paths, each with its origin.  A path produced by several keeps the first
+/// origin, in that order.  Known before discovery, unlike bare repos.
+fn resolve_locations(
+    config: &Config,
+    resolve_options: &ResolveOptions,
+) -> BTreeMap<PathBuf, PathMeta> {
+    let mut extras = BTreeMap::new();
+    let mut extend_with = |paths: HashSet<PathBuf>, path_meta: PathMeta| {
+        for p in paths {
+            extras.entry(p).or_insert_with(|| path_meta.clone());
+        }
+    };
+    if config.exclude_system_artifacts {
+        let mut artifacts =
+            resolve_artifact_paths(&config.rooted_entries(&config.system_artifacts));
+        artifacts.retain(|p| !resolve_options.whitelist_globs.is_match(p));
+        debug!("{} system artifact path(s)", artifacts.len());
+        extend_with(artifacts, PathMeta::origin(Origin::SystemArtifact));
+    }
+    for &preset in &config.presets {
+        let mut paths = resolve_artifact_paths(&config.rooted_entries(preset.locations()));
+        paths.retain(|p| !resolve_options.whitelist_globs.is_match(p));
+        debug!("preset `{}`: {} path(s)", preset, paths.len());
+        extend_with(paths, PathMeta::preset(preset));
+    }
+    extras
+}
+
+/// Safety rails: never exclude a search root, the home directory or a repo
+/// root (or above); shallow repo paths need --force.  Paths from presets
+/// and system artifacts are listed explicitly in the config and exempt
+/// from the depth check.
+/// With `descend_into_excluded`, replace each excluded directory holding
+/// one of `repos` — which the safety rails would refuse — by its entries
+/// outside them, level by level, so that the nested repos are left to their
+/// own rules.  Returns each directory split with the entries excluded
+/// instead.
+fn split_around_repos(
+    new_set: &mut HashSet<PathBuf>,
+    repos: &[PathBuf],
+) -> Vec<(PathBuf, Vec<PathBuf>)> {
+    let repo_set: HashSet<&Path> = repos.iter().map(PathBuf::as_path).collect();
+    let holders: HashSet<&Path> = repos.iter().flat_map(|r| r.ancestors().skip(1)).collect();
+    let split: Vec<PathBuf> = new_set
+        .iter()
+        .filter(|p| holders.contains(p.as_path()))
+        .cloned()
+        .collect();
+    let mut splits = Vec::new();
+    for dir in split {
+        new_set.remove(&dir);
+        let mut entries = Vec::new();
+        let mut pending = vec![dir.clone()];
+        while let Some(holder) = pending.pop() {
+            let Ok(read_dir) = fs::read_dir(&holder) else {
+                continue;
+            };
+            for entry in read_dir.filter_map(|e| e.ok()).map(|e| e.path()) {
+                if holders.contains(entry.as_path()) {
+                    pending.push(entry);
+                } else if !repo_set.contains(entry.as_path()) {
+                    entries.push(entry);
+                }
+            }
+        }
+        entries.sort();
+        info!(
+            "Excluding {} entr(ies) of {} around the repositories nested in it",
+            entries.len(),
+            dir.display()
+        );
+        new_set.extend(entries.iter().cloned());
+        splits.push((dir, entries));
+    }
+    splits
+}
+
+fn enforce_rails(
+    new_set: &mut HashSet<PathBuf>,
+    meta: &BTreeMap<PathBuf, PathMeta>,
+    rails: &SafetyRails,
+    config: &Config,
+    options: &RunOptions,
+) {
+    new_set.retain(|p| match rails.check(p) {
+        None => true,
+        Some(Violation::TooShallow(_))
+            if meta
+                .get(p)
+                .is_some_and(|m| matches!(m.origin, Origin::Preset | Origin::SystemArtifact)) =>
+        {
+            true
+        }
+        Some(v @ Violation::TooShallow(_)) if options.force => {
+            info!("Excluding {} although {} (--force)", p.display(), v);
+            true
+        }
+        Some(v @ Violation::TooShallow(_)) => {
+            warn!(
+                "Not excluding {} — {} (min_exclusion_depth = {}); use --force to allow",
+                p.display(),
+                v,
+                config.min_exclusion_depth
+            );
+            false
+        }
+        Some(v) => {
+            warn!("Refusing to exclude {} — {}", p.display(), v);
+            false
+        }
+    });
+}
+
+/// Find new exclusions of at least `large_exclusion_threshold` bytes and
+/// confirm them, returning the approved ones with their sizes.
+///
+/// They are approved by `--yes-large` or `assume_yes`, by an interactive
+/// "y", or implicitly in a dry run (which only reports them).  Otherwise they are removed from
+/// `new_set` so they stay in backups.
+fn gate_large_exclusions(
+    new_set: &mut HashSet<PathBuf>,
+    old_set: &HashSet<PathBuf>,
+    config: &Config,
+    options: &RunOptions,
+) -> Result<Vec<(PathBuf, u64)>> {
+    let threshold = config.large_exclusion_threshold;
+    if threshold == 0 {
+        return Ok(Vec::new());
+    }
+    let mut large: Vec<(PathBuf, u64)> = new_set
+        .difference(old_set)
+        .cloned()
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|p| {
+            let size = disk_size(&p);
+            (p, size)
+        })
+        .filter(|(_, size)| *size >= threshold)
+        .collect();
+    if large.is_empty() {
+        return Ok(large);
+    }
+    large.sort_by(|(pa, sa), (pb, sb)| sb.cmp(sa).then_with(|| pa.cmp(pb)));
+    for (path, size) in &large {
+        warn!(
+            "Large exclusion: {} ({}, threshold {})",
+            path.display(),
+            format_size(*size),
+            format_size(threshold)
+        );
+    }
+
+    if options.yes_large || options.dry_run || config.assume_yes {
+        return Ok(large);
+    }
+    if interactive(config) && ask(&tr!("prompt-large", n = large.len()))? {
+        return Ok(large);
+    }
+    for (path, _) in &large {
+        new_set.remove(path);
+    }
+    warn!("{}", tr!("run-large-kept", n = large.len()));
+    Ok(Vec::new())
+}
+
+/// The excluded paths of one repo with the rule behind each, and the
+/// directories its file exclusions were collapsed into.
+type RepoResolution = (HashMap<PathBuf, Rule>, BTreeMap<PathBuf, Vec<PathBuf>>);
+
+/// Resolve the excluded paths of the working tree at `repo`.
+fn resolve_one(repo: &Path, resolve_options: &ResolveOptions) -> Result<RepoResolution> {
+    let _slot = resolve_options.throttle.enter();
+    let sources = resolve_repo_sources(repo, resolve_options)?;
+    let mut set: HashSet<PathBuf> = sources.keys().cloned().collect();
+    let collapsed = collapse_file_exclusions(repo, &mut set, resolve_options);
+    let rules = set
+        .into_iter()
+        .map(|p| {
+            let rule = match sources.get(&p) {
+                Some(source) => Rule::from(source),
+                None => Rule {
+                    kind: RuleKind::Config,
+                    pattern: format!(
+                        "collapse_threshold = {}",
+                        resolve_options.collapse_threshold.unwrap_or_default()
+                    ),
+                },
+            };
+            (p, rule)
+        })
+        .collect();
+    Ok((rules, collapsed))
+}
+
+/// The working trees a run resolved, in the order given, with their
+/// results, and those it left out.
+struct Uncovered {
+    repos: Vec<PathBuf>,
+    results: Vec<Result<RepoResolution>>,
+    covered: Vec<PathBuf>,
+}
+
+/// Those of `paths` whose exclusion holds a repo whole: everything but what
+/// the safety rails protect for another reason than the repos below it.
+fn covering_paths<'a>(
+    paths: impl IntoIterator<Item = &'a PathBuf>,
+    rails: &SafetyRails,
+) -> HashSet<PathBuf> {
+    paths
+        .into_iter()
+        .filter(|p| !matches!(rails.check(p), Some(Violation::Protected(_))))
+        .cloned()
+        .collect()
+}
+
+/// Resolve `repos` in parallel, leaving out each one inside a `covering`
+/// path — or, with `skip_covered_repos`, inside a path a repo enclosing it
+/// excludes: that exclusion holds the repo whole, so walking it would be
+/// wasted.  Enclosing repos are then resolved first, one nesting level at a
+/// time, and their paths cover only where `rails` (built without repos)
+/// would let them through.  A repo with a keep marker is never covered: it
+/// resolves to nothing, and the rails refuse any path holding it.
+///
+/// `resolved` holds results computed already (by the pipeline); those of
+/// covered repos are dropped.
+#[allow(clippy::too_many_arguments)]
+fn resolve_uncovered(
+    repos: &[PathBuf],
+    covering: &HashSet<PathBuf>,
+    rails: &SafetyRails,
+    config: &Config,
+    options: &RunOptions,
+    resolve_options: &ResolveOptions,
+    mut resolved: HashMap<PathBuf, Result<RepoResolution>>,
+) -> Uncovered {
+    let all: HashSet<&Path> = repos.iter().map(PathBuf::as_path).collect();
+    let mut levels: BTreeMap<usize, Vec<&PathBuf>> = BTreeMap::new();
+    for repo in repos {
+        let level = if config.skip_covered_repos {
+            repo.ancestors().skip(1).filter(|a| all.contains(a)).count()
+        } else {
+            0
+        };
+        levels.entry(level).or_default().push(repo);
+    }
+
+    let mut excluded = covering.clone();
+    let mut results: HashMap<&Path, Result<RepoResolution>> = HashMap::new();
+    let mut covered = Vec::new();
+    for level in levels.into_values() {
+        let mut todo = Vec::new();
+        for repo in level {
+            let by = repo.ancestors().find(|a| excluded.contains(*a));
+            match by.filter(|_| keep_marker(repo).is_none()) {
+                Some(by) => {
+                    debug!("{} is inside excluded {}", repo.display(), by.display());
+                    covered.push(repo.clone());
+                }
+                None => todo.push((repo.as_path(), resolved.remove(repo))),
+            }
+        }
+        let level_results: Vec<(&Path, Result<RepoResolution>)> = todo
+            .into_par_iter()
+            .map(|(repo, done)| {
+                (
+                    repo,
+                    done.unwrap_or_else(|| resolve_one(repo, resolve_options)),
+                )
+            })
+            .collect();
+        for (repo, result) in level_results {
+            if config.skip_covered_repos
+                && let Ok((rules, _)) = &result
+            {
+                excluded.extend(
+                    rules
+                        .keys()
+                        .filter(|p| match rails.check(p) {
+                            None => true,
+                            Some(Violation::TooShallow(_)) => options.force,
+                            Some(Violation::Protected(_)) => false,
+                        })
+                        .cloned(),
+                );
+            }
+            results.insert(repo, result);
+        }
+    }
+    if !covered.is_empty() {
+        info!("Skipped {} repo(s) inside excluded paths", covered.len());
+    }
+
+    let mut uncovered = Uncovered {
+        repos: Vec::new(),
+        results: Vec::new(),
+        covered,
+    };
+    for repo in repos {
+        if let Some(result) = results.remove(repo.as_path()) {
+            uncovered.repos.push(repo.clone());
+            uncovered.results.push(result);
+        }
+    }
+    uncovered
+}
+
+/// Repos discovered but not yet resolved when `pipeline_discovery` is on;
+/// a full channel pauses the walk until a resolver catches up.
+const PIPELINE_CAPACITY: usize = 256;
+
+/// [`discover_for_run`] with resolution overlapped: each working tree is
+/// sent through a bounded channel to one resolver thread per rayon thread as
+/// soon as the walk finds it.  A tree inside a `covering` path is not
+/// resolved.
+///
+/// Returns the results computed so far, for [`resolve_uncovered`] to
+/// complete: a repo the workers did not see is resolved there, so the
+/// outcome matches resolving after discovery.
+fn discover_and_resolve(
+    config: &Config,
+    options: &RunOptions,
+    search_paths: &[PathBuf],
+    ignored_paths: &[PathBuf],
+    known: Option<KnownRepos>,
+    resolve_options: &ResolveOptions,
+    covering: &HashSet<PathBuf>,
+) -> (
+    Discovery,
+    Option<KnownRepos>,
+    HashMap<PathBuf, Result<RepoResolution>>,
+) {
+    let (tx, rx) = crossbeam_channel::bounded::<PathBuf>(PIPELINE_CAPACITY);
+    let resolved: Mutex<HashMap<PathBuf, Result<RepoResolution>>> = Mutex::default();
+    let (discovery, known_repos) = std::thread::scope(|s| {
+        for _ in 0..rayon::current_num_threads() {
+            let (rx, resolved) = (rx.clone(), &resolved);
+            s.spawn(move || {
+                for repo in rx {
+                    if (repo.ancestors().any(|a| covering.contains(a))
+                        && keep_marker(&repo).is_none())
+                        || resolved
+                            .lock()
+                            .expect("pipeline poisoned")
+                            .contains_key(&repo)
+                    {
+                        continue;
+                    }
+                    let result = resolve_one(&repo, resolve_options);
+                    resolved
+                        .lock()
+                        .expect("pipeline poisoned")
+                        .insert(repo, result);
+                }
+            });
+        }
+        drop(rx);
+        // Dropping the last sender ends the workers once the channel drains
+        let out = discover_for_run(
+            config,
+            options,
+            search_paths,
+            ignored_paths,
+            known,
+            Some(&tx),
+        );
+        drop(tx);
+        out
+    });
+
+    let resolved = resolved.into_inner().expect("pipeline poisoned");
+    debug!(
+        "Pipeline resolved {} repo(s) during discovery",
+        resolved.len()
+    );
+    (discovery, known_repos, resolved)
+}
+
+/// Find the repositories for a run, returning them together with the
+/// [`KnownRepos`] to store in the new cache.
+///
+/// A normal run walks `search_paths`; only a walk of the *configured* search
+/// paths refreshes the stored list.  A `--fast` run re-resolves the cached
+/// repos that still exist plus anything under `--search-path`, unless the
+/// last full discovery is older than `full_rediscovery_interval`.
+///
+/// With a `sink`, every working tree is also sent to it as it is found (the
+/// cached ones of a `--fast` run up front).
+fn discover_for_run(
+    config: &Config,
+    options: &RunOptions,
+    search_paths: &[PathBuf],
+    ignored_paths: &[PathBuf],
+    known: Option<KnownRepos>,
+    sink: Option<&Sender<PathBuf>>,
+) -> (Discovery, Option<KnownRepos>) {
+    let mut scan_options = config.scan_options();
+    scan_options.walker.threads = config.nice_mode.walk_threads();
+    let scan = |paths: &[PathBuf]| match sink {
+        Some(sink) => discover_streaming(paths, ignored_paths, &scan_options, sink),
+        None => discover(paths, ignored_paths, &scan_options),
+    };
+    let now = Local::now().fixed_offset();
+    let fresh = known
+        .as_ref()
+        .and_then(|k| k.discovered_at)
+        .is_some_and(|at| {
+            (now - at)
+                .to_std()
+                .is_ok_and(|age| age < config.full_rediscovery_interval)
+        });
+
+    if options.fast
+        && fresh
+        && let Some(known) = known.clone()
+    {
+        let mut discovery = Discovery {
+            repos: known.repos.clone(),
+            bare_repos: known.bare_repos.clone(),
+            ..Discovery::default()
+        };
+        discovery.retain_existing();
+        if let Some(sink) = sink {
+            for repo in &discovery.repos {
+                let _ = sink.send(repo.clone());
+            }
+        }
+        info!(
+            "Fast run: re-resolving {} known repo(s)",
+            discovery.repos.len() + discovery.bare_repos.len()
+        );
+        if !options.search_path_overrides.is_empty() {
+            discovery.merge(scan(&options.search_path_overrides));
+        }
+        let known = known_repos(known.discovered_at, &discovery, Some(&known));
+        return (discovery, Some(known));
+    }
+    if options.fast {
+        info!("Full rediscovery due — scanning search paths despite --fast");
+    }
+
+    // A `--fast` run falling back to a full scan uses the configured paths;
+    // its overrides are folded in so they are still covered.
+    let discovery = if options.fast {
+        let mut discovery = scan(&config.resolved_search_paths());
+        discovery.merge(scan(&options.search_path_overrides));
+        discovery
+    } else {
+        scan(search_paths)
+    };
+
+    if options.search_path_overrides.is_empty() || options.fast {
+        let known = known_repos(Some(now), &discovery, known.as_ref());
+        (discovery, Some(known))
+    } else {
+        (discovery, known)
+    }
+}
+
+/// The [`KnownRepos`] to cache for `discovery`, noting which working trees
+/// are sparse checkouts and keeping the resolution times of `previous`.
+fn known_repos(
+    discovered_at: Option<DateTime<FixedOffset>>,
+    discovery: &Discovery,
+    previous: Option<&KnownRepos>,
+) -> KnownRepos {
+    let sparse_repos: Vec<PathBuf> = discovery
+        .repos
+        .iter()
+        .filter(|repo| git::resolve_layout(repo).is_some_and(|l| git::is_sparse_checkout(&l)))
+        .cloned()
+        .collect();
+    if !sparse_repos.is_empty() {
+        debug!("Found {} sparse checkout(s)", sparse_repos.len());
+    }
+    KnownRepos {
+        discovered_at,
+        repos: discovery.repos.clone(),
+        bare_repos: discovery.bare_repos.clone(),
+        sparse_repos,
+        resolved_at: previous.map(|k| k.resolved_at.clone()).unwrap_or_default(),
+    }
+}
+
+/// Ask a running service for one more run, so that a run skipped because
+/// of the lock is done once the holder finishes; no-op without a service.
+fn queue_service_run(ctx: &AppContext) {
+    let serving = service::read_status(&service::status_path(&ctx.cache_path))
+        .ok()
+        .flatten()
+        .is_some_and(|status| status.state != ServiceState::Stopped);
+    if !serving {
+        return;
+    }
+    match service::request_run(&service::trigger_path(&ctx.cache_path)) {
+        Ok(()) => info!("Queued a follow-up run with the running service"),
+        Err(e) => warn!("Could not queue a follow-up run: {e:#}"),
+    }
+}
+
+/// Time elapsed since the cache's `last_run`, or `None` if it never ran.
+///
+/// A `last_run` in the future (clock change) counts as zero age.
+fn last_run_age(cache_path: &Path) -> Result<Option<Duration>> {
+    let Some(last_run) = load_cache(cache_path)?.last_run else {
+        return Ok(None);
+    };
+    let age = Local::now().fixed_offset() - last_run;
+    Ok(Some(age.to_std().unwrap_or_default()))
+}
+
+// ─── `plan` / `apply` commands ───────────────────────────────────────────────
+
+/// Compute what `run` with `options` would change — discovery, resolution
+/// and diff — without taking the lock or changing anything, for review
+/// before [`apply_plan`].
+///
+/// Unlike `run`, cached exclusions in another mode than the one now
+/// configured for them are an error rather than migrated.
+pub fn plan_run(ctx: &AppContext, config: &Config, options: &RunOptions) -> Result<Plan> {
+    let old_cache = load_cache(&ctx.cache_path)?;
+    let moved = ModeSelector::new(config)?.moved(&old_cache).len();
+    if moved > 0 {
+        anyhow::bail!(
+            "{moved} cached exclusion(s) use another mode than `exclusion_mode`/`mode_rules` \
+             now give them; run `letitgo run` to migrate them before planning"
+        );
+    }
+    let patterns = Arc::new(PatternCache::load(&pattern_cache_path(&ctx.cache_path)));
+    let mut report = RunReport::start(&config.exclusion_mode);
+    let search_paths = run_search_paths(config, options);
+    let _access = access::begin_run(ctx.fs_access.as_ref(), config, &search_paths);
+    let (plan, _large) = build_plan(
+        ctx,
+        config,
+        options,
+        &search_paths,
+        &config.resolved_ignored_paths(),
+        old_cache,
+        patterns,
+        &mut report,
+    )?;
+    Ok(plan)
+}
+
+/// Make the changes of a plan from [`plan_run`] under the instance lock and
+/// write its cache — or, in a dry run, log them.
+///
+/// The plan is rejected when it no longer describes the current state: the
+/// cache changed since it was computed (a run or another plan was applied
+/// meanwhile), the `exclusion_mode` differs, or [`Plan::verify`] fails —
+/// the plan was edited, its hash is not `expected_sha256`, or the
+/// configuration or an ignore file it read changed.
+pub fn apply_plan(
+    ctx: &AppContext,
+    config: &Config,
+    plan: Plan,
+    expected_sha256: Option<&str>,
+    dry_run: bool,
+) -> Result<RunReport> {
+    let mut lock = open_lock(ctx, config)?;
+    let Some(_guard) = lock.try_acquire(false)? else {
+        anyhow::bail!("Another letitgo instance holds the lock; apply the plan once it is done");
+    };
+    if plan.exclusion_mode != config.exclusion_mode {
+        anyhow::bail!(
+            "The plan was made for exclusion mode `{}`, but it is now `{}`",
+            plan.exclusion_mode,
+            config.exclusion_mode
+        );
+    }
+    let cache = load_cache(&ctx.cache_path)?;
+    if cache.last_run != plan.base_last_run {
+        anyhow::bail!(
+            "The cache changed since the plan was made (last run {}, plan based on {}); \
+             run `letitgo plan` again",
+            describe_last_run(cache.last_run),
+            describe_last_run(plan.base_last_run)
+        );
+    }
+    plan.verify(config, expected_sha256)?;
+    let _access = access::begin_run(
+        ctx.fs_access.as_ref(),
+        config,
+        &config.resolved_search_paths(),
+    );
+    let mut report = RunReport::start(&config.exclusion_mode);
+    apply_locked(ctx, config, plan, dry_run, &mut report)?;
+    Ok(report)
+}
+
+fn describe_last_run(last_run: Option<DateTime<FixedOffset>>) -> String {
+    last_run.map_or("never".into(), |t| {
+        t.format("%Y-%m-%d %H:%M:%S").to_string()
+    })
+}
+
+/// Execute the `plan` command: write the plan of a `run` with `options` to
+/// `output`, or print it, and log a summary.
+pub fn cmd_plan(
+    ctx: &AppContext,
+    config: &Config,
+    options: &RunOptions,
+    output: Option<&Path>,
+) -> Result<()> {
+    let plan = plan_run(ctx, config, options)?;
+    for group in group_by_rule(plan.add.iter().map(PathBuf::as_path), &plan.rules) {
+        info!("+ {group}");
+        for p in &group.paths {
+            info!("+   {}", p.display());
+        }
+    }
+    for p in &plan.reassert {
+        info!("+ {} (re-apply)", p.display());
+    }
+    for p in &plan.remove {
+        info!("- {}", p.display());
+    }
+    info!(
+        "Plan: {} to add, {} to re-apply, {} to remove",
+        plan.add.len(),
+        plan.reassert.len(),
+        plan.remove.len()
+    );
+    match output {
+        Some(path) => {
+            write_plan(path, &plan)?;
+            info!(
+                "Wrote {} (sha256 {}); apply it with `letitgo apply {}`",
+                path.display(),
+                plan.plan_sha256,
+                path.display()
+            );
+        }
+        None => println!("{}", serde_json::to_string_pretty(&plan)?),
+    }
+    Ok(())
+}
+
+/// Execute the `apply` command: apply the plan file at `path`.
+pub fn cmd_apply(
+    ctx: &AppContext,
+    config: &Config,
+    path: &Path,
+    expected_sha256: Option<&str>,
+    dry_run: bool,
+) -> Result<()> {
+    let plan = read_plan(path)?;
+    let report = apply_plan(ctx, config, plan, expected_sha256, dry_run)?;
+    info!(
+        "{}Applied {} — added {}, removed {}",
+        if dry_run { "[dry-run] " } else { "" },
+        path.display(),
+        report.added,
+        report.removed
+    );
+    Ok(())
+}
+
+// ─── `service run` command ───────────────────────────────────────────────────
+
+/// Execute `service run`: repeat [`cmd_run_with`] with `options` until
+/// `SIGTERM` or `SIGINT`, for supervisors that keep one process alive
+/// (`brew services`, systemd) rather than launching one per run.
+///
+/// Runs are `service_interval` apart plus up to `service_jitter`; each
+/// consecutive failure doubles the pause (see [`Schedule::delay`]).  A
+/// failed run is logged, never fatal.  A stop request ends the pause at once
+/// but lets a run in progress finish, so its cache is written.
+///
+/// `service trigger` (and a `run` that finds the lock held) requests an
+/// earlier run through the trigger file: the service waits
+/// `service_debounce` for more requests, then does one run for all of them.
+/// A run that finds the lock held is retried once the holder is done,
+/// rather than dropped.  A change to the patterns or `HEAD` of a cached
+/// sparse checkout, which may have materialized paths to exclude, starts an
+/// earlier run the same way.  Outside the `service_windows`, runs and
+/// requests alike wait for the next one to open.
+///
+/// Progress is published as a [`ServiceStatus`] in the status file next to
+/// the cache (read by `service status`) and, with `service_health_addr`, on
+/// a localhost health endpoint.
+pub fn cmd_service_run(ctx: &AppContext, config: &Config, options: &RunOptions) -> Result<()> {
+    service::install_stop_handler()?;
+    let schedule = Schedule {
+        interval: config.service_interval,
+        jitter: config.service_jitter,
+    };
+    // The service retries lock conflicts itself instead of queuing to itself
+    let options = RunOptions {
+        queue_if_locked: false,
+        ..options.clone()
+    };
+    // Nobody answers prompts in the background, even from a terminal
+    let config = &Config {
+        non_interactive: true,
+        ..config.clone()
+    };
+    let shared = service::Shared::new(&ctx.cache_path);
+    if let Some(addr) = &config.service_health_addr {
+        service::serve_health(addr, Arc::clone(&shared.status))?;
+    }
+    let socket = if config.service_socket {
+        let path = service::socket_path(&ctx.cache_path);
+        let listener = service::bind_socket(&path)?;
+        info!("Answering commands on {}", path.display());
+        Some((path, listener))
+    } else {
+        None
+    };
+    info!(
+        "Service started: running every {}s (+ up to {}s jitter)",
+        schedule.interval.as_secs(),
+        schedule.jitter.as_secs()
+    );
+
+    std::thread::scope(|s| {
+        if let Some((path, listener)) = socket {
+            let (options, shared) = (&options, &shared);
+            s.spawn(move || {
+                let handle =
+                    |command| answer_service_command(ctx, config, options, shared, command);
+                if let Err(e) = service::serve_commands(listener, &handle) {
+                    warn!("The service socket failed: {e:#}");
+                }
+                let _ = fs::remove_file(path);
+            });
+        }
+        service_loop(ctx, config, &options, schedule, &shared);
+    });
+    Ok(())
+}
+
+/// Answer `command`, sent on the socket of a service running with `config`
+/// and `options`.  Runs and plans take turns with the scheduled runs (the
+/// instance lock still keeps out other processes' runs), and a run's
+/// outcome is recorded in the status like theirs.
+pub fn answer_service_command(
+    ctx: &AppContext,
+    config: &Config,
+    options: &RunOptions,
+    shared: &service::Shared,
+    command: service::Command,
+) -> Result<serde_json::Value> {
+    let value = match command {
+        service::Command::Status => shared.status().report(Local::now().fixed_offset())?,
+        service::Command::List => {
+            let cache = load_cache(&ctx.cache_path)?;
+            let paths: Vec<&Path> = cache.paths.iter().map(PathBuf::as_path).collect();
+            serde_json::to_value(list_output(&cache, &paths, false))?
+        }
+        service::Command::Plan => {
+            let _turn = shared.take_turn();
+            let mut plan = plan_run(ctx, config, options)?;
+            plan.seal();
+            serde_json::to_value(plan)?
+        }
+        service::Command::Run { dry_run } => {
+            let options = RunOptions {
+                dry_run: options.dry_run || dry_run,
+                ..options.clone()
+            };
+            let _turn = shared.take_turn();
+            info!("Run requested on the service socket");
+            let (report, result) = run_once(ctx, config, &options);
+            if !(result.is_ok() && report.skip_reason.as_deref() == Some(SKIP_LOCK_HELD)) {
+                let failures = shared.record(&result);
+                if let Err(e) = &result {
+                    warn!("Run failed ({failures} in a row): {e:#}");
+                }
+            }
+            result?;
+            serde_json::to_value(report)?
+        }
+    };
+    Ok(value)
+}
+
+/// The schedule of `service run`: a run per `schedule` (or trigger), each
+/// taking its turn, until a stop is requested.  The status is published
+/// after every step.
+fn service_loop(
+    ctx: &AppContext,
+    config: &Config,
+    options: &RunOptions,
+    schedule: Schedule,
+    shared: &service::Shared,
+) {
+    let trigger = &service::trigger_path(&ctx.cache_path);
+    let update = |edit: &dyn Fn(&mut ServiceStatus)| shared.update(edit);
+    let mut delay = schedule.initial_delay();
+    'runs: loop {
+        update(&|s| {
+            s.state = ServiceState::Waiting;
+            s.next_run = Some(Local::now().fixed_offset() + delay);
+        });
+        match service::wait(delay, trigger, &sparse_checkout_files(ctx)) {
+            Wake::Stopped => break,
+            wake @ (Wake::Triggered | Wake::Changed) => {
+                if wake == Wake::Changed {
+                    info!("A sparse checkout changed; re-resolving");
+                }
+                debug!(
+                    "Run requested; collecting requests for {}s",
+                    config.service_debounce.as_secs()
+                );
+                if !service::sleep(config.service_debounce) {
+                    break;
+                }
+            }
+            Wake::Elapsed => {}
+        }
+        // Outside the run windows, the run (and any request) waits for one
+        while let Some(opens) = service::next_window(&config.service_windows, Local::now()) {
+            info!(
+                "Outside the run windows; the next run waits until {}",
+                opens.format("%a %H:%M")
+            );
+            update(&|s| {
+                s.state = ServiceState::Waiting;
+                s.next_run = Some(opens.fixed_offset());
+            });
+            let left = (opens - Local::now()).to_std().unwrap_or_default();
+            if !service::sleep(left) {
+                break 'runs;
+            }
+        }
+        // This run answers every request made so far
+        service::take_trigger(trigger);
+
+        let turn = shared.take_turn();
+        update(&|s| {
+            s.state = ServiceState::Running;
+            s.next_run = None;
+        });
+        let (report, result) = run_once(ctx, config, options);
+        drop(turn);
+        if result.is_ok() && report.skip_reason.as_deref() == Some(SKIP_LOCK_HELD) {
+            delay = config.service_debounce.max(service::LOCK_RETRY);
+            info!("Lock held by another run; retrying in {}s", delay.as_secs());
+            continue;
+        }
+        let failures = shared.record(&result);
+        if let Err(e) = &result {
+            warn!("Run failed ({failures} in a row): {e:#}");
+        }
+        if service::stop_requested() {
+            break;
+        }
+        delay = schedule.delay(failures);
+        info!("Next run in {}s", delay.as_secs());
+    }
+    update(&|s| {
+        s.state = ServiceState::Stopped;
+        s.next_run = None;
+    });
+    info!("Stop requested; service exiting");
+}
+
+/// The files of the cached sparse checkouts that change when they check out
+/// other paths, for the service to watch.
+fn sparse_checkout_files(ctx: &AppContext) -> Vec<PathBuf> {
+    let sparse_repos = load_cache(&ctx.cache_path)
+        .ok()
+        .and_then(|cache| cache.known_repos)
+        .map(|known| known.sparse_repos)
+        .unwrap_or_default();
+    sparse_repos
+        .iter()
+        .filter_map(|repo| git::resolve_layout(repo))
+        .flat_map(|layout| git::sparse_checkout_files(&layout))
+        .collect()
+}
+
+/// Execute `service trigger`: ask the running service for a run soon.
+/// Requests made within `service_debounce` of each other, or while a run is
+/// in progress, collapse into one run.
+pub fn cmd_service_trigger(ctx: &AppContext) -> Result<()> {
+    service::request_run(&service::trigger_path(&ctx.cache_path))?;
+    let serving = service::read_status(&service::status_path(&ctx.cache_path))?
+        .is_some_and(|status| status.state != ServiceState::Stopped);
+    if serving {
+        info!("Run requested");
+    } else {
+        warn!("No service is running; the request is kept until `letitgo service run` starts");
+    }
+    Ok(())
+}
+
+/// Execute `service status`: print what the service last published and
+/// fail (exit code 1) when it is unhealthy — stopped, failing, or overdue —
+/// so monitoring tools can alert on the exit code alone.
+pub fn cmd_service_status(ctx: &AppContext, json: bool) -> Result<()> {
+    let path = service::status_path(&ctx.cache_path);
+    let Some(status) = service::read_status(&path)? else {
+        anyhow::bail!(
+            "No service status at {}; `letitgo service run` has not run",
+            path.display()
+        );
+    };
+    let now = Local::now().fixed_offset();
+    let problem = status.problem(now);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status.report(now)?)?);
+    } else {
+        let out = &ctx.output;
+        let time = |t: DateTime<FixedOffset>| t.format("%Y-%m-%d %H:%M:%S").to_string();
+        out.field(
+            "State:       ",
+            format!("{} (pid {})", status.state, status.pid),
+        );
+        out.field("Started:     ", time(status.started_at));
+        match &status.last_run {
+            Some(run) if run.ok => {
+                out.field("Last run:    ", format!("{} (ok)", time(run.finished_at)))
+            }
+            Some(run) => out.field(
+                "Last run:    ",
+                format!("{} (failed)", time(run.finished_at)),
+            ),
+            None => out.field("Last run:    ", "never"),
+        }
+        if let Some(next_run) = status.next_run {
+            out.field("Next run:    ", time(next_run));
+        }
+    }
+    match problem {
+        Some(problem) => anyhow::bail!("Service unhealthy: {problem}"),
+        None => Ok(()),
+    }
+}
+
+// ─── `privileged-helper` commands ───────────────────────────────────────────
+
+/// Execute `privileged-helper install`: install the root daemon that applies
+/// fixed-path exclusions for `uid` (default: the user who ran sudo).
+pub fn cmd_helper_install(out: &Output, uid: Option<u32>, dry_run: bool) -> Result<()> {
+    if !cfg!(target_os = "macos") {
+        anyhow::bail!("privileged-helper needs macOS");
+    }
+    let uid = match uid {
+        Some(uid) => uid,
+        None => std::env::var("SUDO_UID")
+            .ok()
+            .and_then(|uid| uid.parse().ok())
+            .context("pass --uid, or run the command with sudo")?,
+    };
+    helper::install(uid, dry_run)?;
+    if !dry_run {
+        out.note(format!(
+            "Set fixed_path_backend = \"helper\" in the config of uid {uid} to use it"
+        ));
+    }
+    Ok(())
+}
+
+/// Execute `privileged-helper uninstall`.
+pub fn cmd_helper_uninstall(dry_run: bool) -> Result<()> {
+    if !cfg!(target_os = "macos") {
+        anyhow::bail!("privileged-helper needs macOS");
+    }
+    helper::uninstall(dry_run)
+}
+
+/// Execute `privileged-helper status`: fail unless the helper answers and
+/// accepts the current user.
+pub fn cmd_helper_status(out: &Output) -> Result<()> {
+    out.field("Socket:  ", helper::HELPER_SOCKET);
+    helper::send(Path::new(helper::HELPER_SOCKET), &helper::Request::Ping)?;
+    out.field("Status:  ", "running");
+    Ok(())
+}
+
+/// Execute `privileged-helper serve`, the installed daemon: apply the
+/// fixed-path requests of `allowed_uids` with `tmutil -p` until killed.
+pub fn cmd_helper_serve(config: &Config, allowed_uids: &[u32]) -> Result<()> {
+    helper::require_root("privileged-helper serve")?;
+    let manager = time_machine_manager(&Config {
+        exclusion_mode: ExclusionMode::FixedPath,
+        mode_rules: Vec::new(),
+        fixed_path_backend: FixedPathBackend::Tmutil,
+        root: None,
+        ..config.clone()
+    })?;
+    let listener = helper::bind(Path::new(helper::HELPER_SOCKET))?;
+    info!(
+        "Privileged helper listening on {} for uid(s) {allowed_uids:?}",
+        helper::HELPER_SOCKET
+    );
+    helper::serve(listener, allowed_uids, manager.as_ref())
+}
+
+// ─── `diff` command ──────────────────────────────────────────────────────────
+
+/// Execute the `diff` command: compare the cache with the newest snapshot
+/// (what the last change did), or with `fresh` a dry-run resolution against
+/// the cache (what the next run would do).
+pub fn cmd_diff(ctx: &AppContext, config: &Config, fresh: bool, json: bool) -> Result<()> {
+    let cache = load_cache(&ctx.cache_path)?;
+    let mut diff_warnings = Vec::new();
+    let mut rules = BTreeMap::new();
+    let (from_label, from, to_label, to) = if fresh {
+        let options = RunOptions {
+            dry_run: true,
+            ..RunOptions::default()
+        };
+        let search_paths = config.resolved_search_paths();
+        let _access = access::begin_run(ctx.fs_access.as_ref(), config, &search_paths);
+        let resolved = resolve_exclusions(
+            config,
+            &options,
+            &search_paths,
+            &config.resolved_ignored_paths(),
+            None,
+            Arc::new(PatternCache::load(&pattern_cache_path(&ctx.cache_path))),
+        )?;
+        diff_warnings = resolved.warnings;
+        rules = resolved.rules;
+        if let Some(summary) = warnings::summary(&diff_warnings) {
+            warn!("{summary}");
+        }
+        (
+            "cache".to_string(),
+            cache.path_set(),
+            "fresh resolution".to_string(),
+            resolved.paths,
+        )
+    } else {
+        let Some(snapshot) = list_snapshots(&ctx.cache_path)?.pop() else {
+            info!("No cache snapshot yet; use `letitgo diff --fresh` to compare with a new scan.");
+            return Ok(());
+        };
+        let previous = load_cache(&snapshot)?;
+        (
+            snapshot.display().to_string(),
+            previous.path_set(),
+            "cache".to_string(),
+            cache.path_set(),
+        )
+    };
+
+    print_diff(
+        &ctx.output,
+        (&from_label, &from),
+        (&to_label, &to, &rules),
+        &diff_warnings,
+        json,
+    )
+}
+
+/// Print the paths `to` adds to and removes from `from`, each given with its
+/// label, as `diff` does: a summary line and the changes, or JSON listing the
+/// unchanged paths and `warnings` as well.  Added paths are grouped by the
+/// rule `rules` records for them, when it records any.
+fn print_diff(
+    out: &Output,
+    (from_label, from): (&str, &HashSet<PathBuf>),
+    (to_label, to, rules): (&str, &HashSet<PathBuf>, &BTreeMap<PathBuf, PathRule>),
+    warnings: &[Warning],
+    json: bool,
+) -> Result<()> {
+    let (mut added, mut removed) = diff_sets(from, to);
+    let mut unchanged: Vec<&PathBuf> = from.intersection(to).collect();
+    added.sort();
+    removed.sort();
+    unchanged.sort();
+    let groups = if rules.is_empty() {
+        Vec::new()
+    } else {
+        group_by_rule(added.iter().copied(), rules)
+    };
+
+    if json {
+        let mut output = serde_json::json!({
+            "from": from_label,
+            "to": to_label,
+            "added": added,
+            "removed": removed,
+            "unchanged": unchanged,
+            "warnings": warnings,
+        });
+        if !groups.is_empty() {
+            output["added_by_rule"] = serde_json::to_value(&groups)?;
+        }
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    out.title(format!(
+        "{} {} {}: {} added, {} removed, {} unchanged",
+        from_label,
+        out.arrow(),
+        to_label,
+        added.len(),
+        removed.len(),
+        unchanged.len()
+    ));
+    if groups.is_empty() {
+        for p in &added {
+            out.added(p.display());
+        }
+    }
+    for group in &groups {
+        out.note(group);
+        for p in &group.paths {
+            out.added(p.display());
+        }
+    }
+    for p in &removed {
+        out.removed(p.display());
+    }
+    Ok(())
+}
+
+// ─── `simulate` command ──────────────────────────────────────────────────────
+
+/// Execute the `simulate` command: resolve the exclusions another config
+/// would make — `alt_config` (default: the current config file) with the
+/// `KEY=VALUE` `overrides` applied, and the managed policy it names merged
+/// beneath — and print how they differ from the cache.  Like `diff --fresh`,
+/// nothing is excluded, cached or locked.
+pub fn cmd_simulate(
+    ctx: &AppContext,
+    config: &Config,
+    alt_config: Option<&Path>,
+    overrides: &[String],
+    json: bool,
+) -> Result<()> {
+    let path = alt_config.unwrap_or(&ctx.config_path);
+    let overrides = config::parse_overrides(overrides)?;
+    let load = |base| {
+        Config::load_overridden(path, base, overrides.clone())
+            .with_context(|| format!("loading config from {}", path.display()))
+    };
+    let mut simulated = load(None)?;
+    if let Some(policy) = policy::current_policy(&simulated, &ctx.cache_path) {
+        simulated = load(Some(policy.table()?))?;
+    }
+    simulated.root = config.root.clone();
+
+    let options = RunOptions {
+        dry_run: true,
+        ..RunOptions::default()
+    };
+    let search_paths = simulated.resolved_search_paths();
+    let _access = access::begin_run(ctx.fs_access.as_ref(), &simulated, &search_paths);
+    let resolved = resolve_exclusions(
+        &simulated,
+        &options,
+        &search_paths,
+        &simulated.resolved_ignored_paths(),
+        None,
+        Arc::new(PatternCache::load(&pattern_cache_path(&ctx.cache_path))),
+    )?;
+    if let Some(summary) = warnings::summary(&resolved.warnings) {
+        warn!("{summary}");
+    }
+
+    let mut label = path.display().to_string();
+    if !overrides.is_empty() {
+        label = format!("{label} with {} override(s)", overrides.len());
+    }
+    print_diff(
+        &ctx.output,
+        ("cache", &load_cache(&ctx.cache_path)?.path_set()),
+        (&label, &resolved.paths, &resolved.rules),
+        &resolved.warnings,
+        json,
+    )
+}
+
+// ─── `cache` commands ────────────────────────────────────────────────────────
+
+/// Execute `cache list`: print the cache snapshots, oldest first, with the
+/// run that wrote each one and its number of paths.
+pub fn cmd_cache_list(ctx: &AppContext, json: bool) -> Result<()> {
+    let mut entries = Vec::new();
+    for snapshot in list_snapshots(&ctx.cache_path)? {
+        let cache = load_cache(&snapshot)?;
+        entries.push((snapshot, cache));
+    }
+
+    if json {
+        let output: Vec<_> = entries
+            .iter()
+            .map(|(snapshot, cache)| {
+                serde_json::json!({
+                    "name": snapshot.file_name().map(|n| n.to_string_lossy()),
+                    "path": snapshot,
+                    "last_run": cache.last_run,
+                    "exclusion_mode": cache.exclusion_mode,
+                    "count": cache.paths.len(),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        info!(
+            "No cache snapshots in {}",
+            snapshot_dir(&ctx.cache_path).display()
+        );
+        return Ok(());
+    }
+    for (snapshot, cache) in &entries {
+        let last_run = cache
+            .last_run
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| "never".to_string());
+        ctx.output.row(format_args!(
+            "{}  {} path(s), {} mode, last run {}",
+            snapshot.file_name().unwrap_or_default().to_string_lossy(),
+            cache.paths.len(),
+            cache.exclusion_mode,
+            last_run
+        ));
+    }
+    Ok(())
+}
+
+/// Execute `cache snapshot`: snapshot the current cache now.
+pub fn cmd_cache_snapshot(ctx: &AppContext, config: &Config, dry_run: bool) -> Result<()> {
+    if dry_run {
+        info!("[dry-run] would snapshot {}", ctx.cache_path.display());
+        return Ok(());
+    }
+    match snapshot_cache(&ctx.cache_path, config.cache_snapshots.max(1))? {
+        Some(snapshot) => info!("Saved {}", snapshot.display()),
+        None => info!("Nothing to snapshot — no cache yet."),
+    }
+    Ok(())
+}
+
+/// Execute `cache restore <snapshot>`: make the exclusions match the
+/// snapshot again and make it the cache.
+///
+/// Paths only in the current cache are un-excluded and paths only in the
+/// snapshot are excluded again through the exclusion manager; in sticky mode
+/// snapshot paths that no longer exist are dropped.  The current cache is
+/// snapshotted first, so a restore can itself be undone.
+pub fn cmd_cache_restore(
+    ctx: &AppContext,
+    config: &Config,
+    snapshot: &str,
+    dry_run: bool,
+) -> Result<()> {
+    let snapshot = find_snapshot(&ctx.cache_path, snapshot)?;
+
+    let mut lock = open_lock(ctx, config)?;
+    let Some(_guard) = lock.try_acquire(false)? else {
+        return Ok(());
+    };
+
+    let mut restored = load_cache(&snapshot)?;
+    let current = load_cache(&ctx.cache_path)?;
+    let _access = access::begin_paths(
+        ctx.fs_access.as_ref(),
+        config,
+        current
+            .paths
+            .iter()
+            .chain(&restored.paths)
+            .map(PathBuf::as_path),
+    );
+    if restored.exclusion_mode != config.exclusion_mode {
+        anyhow::bail!(
+            "{} was taken in `{}` mode but the config uses `{}`",
+            snapshot.display(),
+            restored.exclusion_mode,
+            config.exclusion_mode
+        );
+    }
+    // A fixed-path exclusion applies again when the path reappears
+    let fixed: HashSet<PathBuf> = restored
+        .paths
+        .iter()
+        .filter(|p| restored.mode_for(p).is_fixed_path())
+        .cloned()
+        .collect();
+    restored.paths.retain(|p| {
+        let keep = fixed.contains(p) || p.exists();
+        if !keep {
+            warn!("Not restoring {} — it no longer exists", p.display());
+        }
+        keep
+    });
+    restored.prune_meta();
+
+    let old_set = current.path_set();
+    let new_set = restored.path_set();
+    let (to_add, to_remove) = diff_sets(&old_set, &new_set);
+    if dry_run {
+        for p in &to_add {
+            info!("[dry-run] would add exclusion: {}", p.display());
+        }
+        for p in &to_remove {
+            info!("[dry-run] would remove exclusion: {}", p.display());
+        }
+        return Ok(());
+    }
+
+    if config.cache_snapshots > 0 && old_set != new_set {
+        snapshot_cache(&ctx.cache_path, config.cache_snapshots)?;
+    }
+    let manager = ctx.exclusion_manager.as_ref();
+    modes::remove_by_mode(manager, &to_remove, |p| current.mode_for(p))?;
+    modes::add_by_mode(manager, &to_add, |p| restored.mode_for(p))?;
+
+    restored.last_run = Some(Local::now().fixed_offset());
+    write_cache(&ctx.cache_path, &restored)?;
+    info!(
+        "Restored {} — added {}, removed {}",
+        snapshot.display(),
+        to_add.len(),
+        to_remove.len()
+    );
+    Ok(())
+}
+
+/// Execute `cache gc`: compact the cache and its snapshots, printing their
+/// sizes before and after.
+///
+/// Duplicate cache entries are merged.  Paths inside another cached path
+/// are un-excluded and dropped — the outer exclusion already covers them,
+/// and `run` adds them back should it go away.  Metadata of paths no longer
+/// cached and known repos that no longer exist are dropped.  Snapshots older
+/// than `retention` (except the newest) and snapshots recording the same
+/// exclusions as the next one are deleted; the rest are rewritten without
+/// duplicates in `cache_format`.
+pub fn cmd_cache_gc(
+    ctx: &AppContext,
+    config: &Config,
+    retention: Duration,
+    dry_run: bool,
+) -> Result<()> {
+    let mut lock = open_lock(ctx, config)?;
+    let Some(_guard) = lock.try_acquire(false)? else {
+        return Ok(());
+    };
+
+    let (cache_before, snapshots_before) = cache_storage(&ctx.cache_path)?;
+    let mut cache = load_cache(&ctx.cache_path)?;
+    let entries_before = cache.paths.len();
+    let meta_before = cache.meta.len() + cache.collapsed.len();
+    cache.paths.sort();
+    cache.paths.dedup();
+    let duplicates = entries_before - cache.paths.len();
+    let nested = nested_paths(&cache.paths);
+    let nested_set: HashSet<&PathBuf> = nested.iter().collect();
+    let nested_fixed: HashSet<PathBuf> = nested
+        .iter()
+        .filter(|p| cache.mode_for(p).is_fixed_path())
+        .cloned()
+        .collect();
+    cache.paths.retain(|p| !nested_set.contains(p));
+    cache.prune_meta();
+    let stale_meta = meta_before - cache.meta.len() - cache.collapsed.len();
+    let mut missing_repos = 0;
+    if let Some(known) = &mut cache.known_repos {
+        let count = known.repos.len() + known.bare_repos.len();
+        known.repos.retain(|r| r.exists());
+        known.bare_repos.retain(|r| r.exists());
+        known.sparse_repos.retain(|r| r.exists());
+        known.resolved_at.retain(|r, _| r.exists());
+        missing_repos = count - known.repos.len() - known.bare_repos.len();
+    }
+
+    // Snapshots older than the retention window go, but the newest stays so
+    // that `diff` and `cache restore` can still undo the last change.
+    let snapshots = list_snapshots(&ctx.cache_path)?;
+    let cutoff = Local::now().naive_local() - retention;
+    let mut expired = Vec::new();
+    let mut duplicate_snapshots = Vec::new();
+    let mut kept = Vec::new();
+    let mut newer: Option<Cache> = None;
+    for (i, snapshot) in snapshots.iter().enumerate().rev() {
+        let newest = i + 1 == snapshots.len();
+        if !newest && snapshot_taken_at(snapshot).is_some_and(|t| t < cutoff) {
+            expired.push(snapshot);
+            continue;
+        }
+        let mut loaded = load_cache(snapshot)?;
+        loaded.paths.sort();
+        loaded.paths.dedup();
+        let same = newer
+            .as_ref()
+            .is_some_and(|n| n.paths == loaded.paths && n.exclusion_mode == loaded.exclusion_mode);
+        if same {
+            duplicate_snapshots.push(snapshot);
+        } else {
+            kept.push((snapshot, loaded.clone()));
+            newer = Some(loaded);
+        }
+    }
+
+    if dry_run {
+        info!(
+            "[dry-run] would merge {} duplicate path(s), drop {} nested path(s), \
+             {} stale metadata entr(ies) and {} missing known repo(s)",
+            duplicates,
+            nested.len(),
+            stale_meta,
+            missing_repos
+        );
+        for p in &nested {
+            info!("[dry-run] would remove nested exclusion: {}", p.display());
+        }
+        for snapshot in expired.iter().chain(&duplicate_snapshots) {
+            info!("[dry-run] would delete snapshot {}", snapshot.display());
+        }
+        return Ok(());
+    }
+
+    if !nested.is_empty() {
+        let refs: Vec<&Path> = nested
+            .iter()
+            .map(|p| p.as_path())
+            .filter(|p| nested_fixed.contains(*p) || p.exists())
+            .collect();
+        modes::remove_by_mode(ctx.exclusion_manager.as_ref(), &refs, |p| {
+            if nested_fixed.contains(p) {
+                ExclusionMode::FixedPath
+            } else {
+                ExclusionMode::Sticky
+            }
+        })?;
+    }
+    for snapshot in expired.iter().chain(&duplicate_snapshots) {
+        fs::remove_file(snapshot)
+            .with_context(|| format!("removing snapshot: {}", snapshot.display()))?;
+    }
+    for (snapshot, mut loaded) in kept {
+        loaded.prune_meta();
+        loaded.format = config.cache_format;
+        loaded.encrypted = config.cache_encryption;
+        write_cache(snapshot, &loaded)?;
+    }
+    if ctx.cache_path.exists() {
+        if !nested.is_empty() && config.cache_snapshots > 0 {
+            snapshot_cache(&ctx.cache_path, config.cache_snapshots)?;
+        }
+        cache.format = config.cache_format;
+        cache.encrypted = config.cache_encryption;
+        write_cache(&ctx.cache_path, &cache)?;
+    }
+
+    let (cache_after, snapshots_after) = cache_storage(&ctx.cache_path)?;
+    let out = &ctx.output;
+    let arrow = out.arrow();
+    out.field(
+        "Cache: ",
+        format!(
+            "{} {arrow} {}, {} {arrow} {} entr(ies)",
+            format_size(cache_before),
+            format_size(cache_after),
+            entries_before,
+            cache.paths.len()
+        ),
+    );
+    out.item(format!(
+        "merged {} duplicate(s); dropped {} nested path(s), {} stale metadata entr(ies), \
+         {} missing known repo(s)",
+        duplicates,
+        nested.len(),
+        stale_meta,
+        missing_repos
+    ));
+    out.field(
+        "Snapshots: ",
+        format!(
+            "{} ({}) {arrow} {} ({}); deleted {} expired, {} duplicate",
+            snapshots_before.0,
+            format_size(snapshots_before.1),
+            snapshots_after.0,
+            format_size(snapshots_after.1),
+            expired.len(),
+            duplicate_snapshots.len()
+        ),
+    );
+    Ok(())
+}
+
+/// Size of the cache file at `cache_path`, and the number and total size of
+/// its snapshots.
+fn cache_storage(cache_path: &Path) -> Result<(u64, (usize, u64))> {
+    let size = |p: &Path| fs::metadata(p).map_or(0, |m| m.len());
+    let snapshots = list_snapshots(cache_path)?;
+    let total = snapshots.iter().map(|p| size(p)).sum();
+    Ok((size(cache_path), (snapshots.len(), total)))
+}
+
+// ─── `policy` commands ───────────────────────────────────────────────────────
+
+/// Merge the managed policy selected by `policy_url` beneath `config`,
+/// fetching it when the cached copy is stale (see [`policy::current_policy`]).
+pub fn with_policy(ctx: &AppContext, config: Config) -> Result<Config> {
+    let Some(policy) = policy::current_policy(&config, &ctx.cache_path) else {
+        return Ok(config);
+    };
+    let (config, _) = Config::load_over(&ctx.config_path, Some(policy.table()?))
+        .with_context(|| format!("applying policy from {}", policy.meta.url))?;
+    Ok(config)
+}
+
+/// Execute `policy show`: print the cached policy, where and when it was
+/// fetched, and whether it is the one the config asks for.
+pub fn cmd_policy_show(ctx: &AppContext, config: &Config, json: bool) -> Result<()> {
+    let cached = policy::load_cached(&ctx.cache_path)?;
+    let current = cached.as_ref().is_some_and(|p| {
+        config.policy_url.as_deref() == Some(p.meta.url.as_str())
+            && config
+                .policy_sha256
+                .as_deref()
+                .is_none_or(|pin| policy::pin_matches(pin, &p.meta.sha256))
+    });
+
+    if json {
+        let output = serde_json::json!({
+            "policy_url": config.policy_url,
+            "policy_sha256": config.policy_sha256,
+            "cached": cached.as_ref().map(|p| serde_json::json!({
+                "url": p.meta.url,
+                "sha256": p.meta.sha256,
+                "fetched_at": p.meta.fetched_at,
+                "current": current,
+                "text": p.text,
+            })),
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    let out = &ctx.output;
+    match &config.policy_url {
+        Some(url) => out.field("policy_url: ", url),
+        None => out.field("policy_url: ", "(not set)"),
+    }
+    let Some(policy) = cached else {
+        out.note("No cached policy.");
+        return Ok(());
+    };
+    out.field(
+        "Cached from ",
+        format!(
+            "{} at {}",
+            policy.meta.url,
+            policy.meta.fetched_at.format("%Y-%m-%d %H:%M:%S")
+        ),
+    );
+    out.field(
+        "SHA-256 ",
+        format!(
+            "{}{}",
+            policy.meta.sha256,
+            if current {
+                ""
+            } else {
+                " (not applied: does not match policy_url/policy_sha256)"
+            }
+        ),
+    );
+    if out.is_plain() {
+        for line in policy.text.lines() {
+            out.item(line);
+        }
+    } else {
+        println!();
+        print!("{}", policy.text);
+    }
+    Ok(())
+}
+
+/// Execute `policy refresh`: fetch the policy now, replacing the cached copy
+/// only if it passes the `policy_sha256` pin and parses.
+pub fn cmd_policy_refresh(ctx: &AppContext, config: &Config) -> Result<()> {
+    let Some(url) = config.policy_url.as_deref() else {
+        anyhow::bail!("no policy_url is configured");
+    };
+    let policy = policy::refresh(&ctx.cache_path, url, config.policy_sha256.as_deref())?;
+    info!("Fetched policy from {url} (SHA-256 {})", policy.meta.sha256);
+    Ok(())
+}
+
+// ─── `list` command ───────────────────────────────────────────────────────────
+
+/// Execute the `list` command: display paths currently recorded in the cache.
+///
+/// When `json` is `true`, prints machine-readable JSON on stdout.
+/// When `stale_only` is `true`, limits output to paths that no longer exist on disk
+/// (see [`clean::is_stale`]: paths on an unmounted volume are not stale).
+/// `format` picks a bare path list or an aligned table (see [`list::table`]),
+/// `sort` the order; without it paths are listed in cache order.
+/// With `redact`, paths are printed [`list::redact`]ed.
+pub fn cmd_list(
+    ctx: &AppContext,
+    json: bool,
+    stale_only: bool,
+    quarantined: bool,
+    format: ListFormat,
+    sort: Option<ListSort>,
+    redact: bool,
+) -> Result<()> {
+    let cache = load_cache(&ctx.cache_path)?;
+    if quarantined {
+        return list_quarantined(ctx, &cache, json, redact);
+    }
+    let show = |p: &Path| {
+        if redact {
+            list::redact(p)
+        } else {
+            p.to_path_buf()
+        }
+    };
+    let mut paths: Vec<&Path> = cache
+        .paths
+        .iter()
+        .map(PathBuf::as_path)
+        .filter(|p| !stale_only || clean::is_stale(p))
+        .collect();
+    let table = format == ListFormat::Table && !json;
+    let mut rows = Vec::new();
+    if table || sort.is_some() {
+        let sizes = table || sort == Some(ListSort::Size);
+        rows = list::rows(&cache, &paths, sizes);
+        if let Some(sort) = sort {
+            list::sort(&mut rows, sort);
+        }
+        paths = rows.iter().map(|r| r.path.as_path()).collect();
+    }
+
+    if json {
+        // Machine-readable JSON on stdout
+        let output = list_output(&cache, &paths, redact);
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    // Human-readable output on stdout
+    let out = &ctx.output;
+    if paths.is_empty() {
+        out.note(if stale_only {
+            tr!("list-stale-empty")
+        } else {
+            tr!("list-empty")
+        });
+        return Ok(());
+    }
+    if stale_only {
+        out.heading(tr!("list-stale-header", n = paths.len()));
+    } else {
+        out.heading(tr!("list-header", n = paths.len()));
+    }
+    if table {
+        for line in list::table(&rows, redact) {
+            out.row(line);
+        }
+        return Ok(());
+    }
+    let tag = if stale_only {
+        tr!("list-deleted-tag")
+    } else {
+        tr!("list-trashed-tag")
+    };
+    for p in paths {
+        if stale_only || clean::is_trashed(p) {
+            out.tagged_item(show(p).display(), &tag);
+        } else {
+            out.item(show(p).display());
+        }
+    }
+    Ok(())
+}
+
+/// What `list --json` prints for `paths` of `cache`.
+fn list_output(cache: &Cache, paths: &[&Path], redact: bool) -> ListOutput {
+    ListOutput {
+        schema_version: LIST_SCHEMA_VERSION,
+        count: paths.len(),
+        last_run: cache.last_run,
+        exclusion_mode: cache.exclusion_mode.clone(),
+        paths: paths
+            .iter()
+            .map(|p| {
+                let entry = PathEntry::new(cache, p);
+                if redact { entry.redacted() } else { entry }
+            })
+            .collect(),
+    }
+}
+
+/// `list --quarantined`: the quarantined paths with their failure counts.
+fn list_quarantined(ctx: &AppContext, cache: &Cache, json: bool, redact: bool) -> Result<()> {
+    let entries: Vec<_> = quarantine::quarantined(&cache.failures)
+        .map(|(path, f)| {
+            let path = if redact {
+                list::redact(path)
+            } else {
+                path.to_path_buf()
+            };
+            (path, f)
+        })
+        .collect();
+    if json {
+        let paths: Vec<_> = entries
+            .iter()
+            .map(|(path, f)| {
+                serde_json::json!({
+                    "path": path,
+                    "failures": f.count,
+                    "last_attempt": f.last_attempt,
+                    "quarantined_since": f.quarantined_since,
+                })
+            })
+            .collect();
+        let output = serde_json::json!({
+            "count": paths.len(),
+            "paths": paths,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    let out = &ctx.output;
+    if entries.is_empty() {
+        out.note(tr!("list-quarantined-empty"));
+        return Ok(());
+    }
+    out.heading(tr!("list-quarantined-header", n = entries.len()));
+    for (path, f) in entries {
+        let tag = tr!(
+            "list-quarantined-tag",
+            n = f.count,
+            last = f.last_attempt.format("%Y-%m-%d %H:%M"),
+        );
+        out.tagged_item(path.display(), &tag);
+    }
+    Ok(())
+}
+
+// ─── `query` command ──────────────────────────────────────────────────────────
+
+/// Execute the `query` command: list the cached paths matching `expr` (see
+/// [`query::Query`]), with their origin and — when the query tests it —
+/// their size.
+pub fn cmd_query(ctx: &AppContext, config: &Config, expr: &str, json: bool) -> Result<()> {
+    let query = query::Query::parse(expr)?;
+    let cache = load_cache(&ctx.cache_path)?;
+    let _access = access::begin_paths(
+        ctx.fs_access.as_ref(),
+        config,
+        cache.paths.iter().map(PathBuf::as_path),
+    );
+    let entries: Vec<query::Entry> = cache
+        .paths
+        .iter()
+        .map(|p| {
+            let mut entry = query::Entry::new(p, cache.meta_for(p), cache.mode_for(p));
+            entry.repo = cache.repo_of(p);
+            entry.failures = cache.failures.get(p).map_or(0, |f| f.count);
+            entry.quarantined = cache
+                .failures
+                .get(p)
+                .is_some_and(|f| f.quarantined_since.is_some());
+            entry.collapsed = cache.collapsed.contains_key(p);
+            entry
+        })
+        .filter(|entry| query.matches(entry))
+        .collect();
+
+    if json {
+        // The objects of `list --json`, with what the query can test besides
+        let mut paths = Vec::new();
+        for e in &entries {
+            let mut path = serde_json::to_value(PathEntry::new(&cache, e.path))?;
+            path["preset"] = serde_json::json!(e.meta.preset);
+            path["mode"] = serde_json::json!(e.mode);
+            path["size_bytes"] = serde_json::json!(e.measured_size());
+            paths.push(path);
+        }
+        let output = serde_json::json!({
+            "schema_version": LIST_SCHEMA_VERSION,
+            "count": paths.len(),
+            "paths": paths,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    let out = &ctx.output;
+    if entries.is_empty() {
+        out.note(tr!("query-empty"));
+        return Ok(());
+    }
+    out.heading(tr!("query-header", n = entries.len()));
+    for e in &entries {
+        let tag = match e.measured_size() {
+            Some(size) => format!(" [{}, {}]", e.meta.origin, format_size(size)),
+            None => format!(" [{}]", e.meta.origin),
+        };
+        out.tagged_item(e.path.display(), &tag);
+    }
+    Ok(())
+}
+
+// ─── `export` command ─────────────────────────────────────────────────────────
+
+/// Execute the `export` command: render the cached paths in `format` and
+/// write them to `output` (replaced atomically) or stdout.  `redact` hides
+/// directory names in an inventory (`markdown`, `html`).
+pub fn cmd_export(
+    ctx: &AppContext,
+    config: &Config,
+    format: ExportFormat,
+    output: Option<&Path>,
+    redact: bool,
+) -> Result<()> {
+    if redact && !format.is_inventory() {
+        anyhow::bail!("--redact applies to the markdown and html inventories only");
+    }
+    let cache = load_cache(&ctx.cache_path)?;
+    let paths = &cache.paths;
+    let _access = access::begin_paths(
+        ctx.fs_access.as_ref(),
+        config,
+        paths.iter().map(PathBuf::as_path),
+    );
+    let text = export::render(format, &cache, redact);
+    let Some(output) = output else {
+        print!("{text}");
+        return Ok(());
+    };
+    let parent = output.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(parent)
+        .with_context(|| format!("creating export dir: {}", parent.display()))?;
+    let mut tmp = tempfile::NamedTempFile::new_in(parent)
+        .with_context(|| format!("creating temp file in {}", parent.display()))?;
+    tmp.write_all(text.as_bytes())
+        .with_context(|| format!("writing export temp file in {}", parent.display()))?;
+    tmp.persist(output)
+        .with_context(|| format!("persisting export to {}", output.display()))?;
+    info!("Exported {} path(s) to {}", paths.len(), output.display());
+    Ok(())
+}
+
+// ─── `reset` command ──────────────────────────────────────────────────────────
+
+/// Execute the `reset` command: remove all managed exclusions and delete the cache.
+///
+/// Asks for confirmation (see [`confirm`]).
+/// When `dry_run` is `true`, prints what would be removed but makes no changes.
+pub fn cmd_reset(ctx: &AppContext, config: &Config, dry_run: bool) -> Result<()> {
+    // Pre-load to show the count in the confirmation prompt.
+    let preview_cache = load_cache(&ctx.cache_path)?;
+
+    if preview_cache.paths.is_empty() {
+        info!("{}", tr!("reset-empty"));
+        return Ok(());
+    }
+
+    if !confirm(config, &tr!("reset-confirm", n = preview_cache.paths.len()))? {
+        info!("{}", tr!("prompt-aborted"));
+        return Ok(());
+    }
+
+    // Acquire lock after confirmation prompt (before any mutations)
+    let mut lock = open_lock(ctx, config)?;
+    let Some(_guard) = lock.try_acquire(false)? else {
+        return Ok(());
+    };
+
+    // Re-load cache under lock to avoid TOCTOU race with concurrent runs.
+    let cache = load_cache(&ctx.cache_path)?;
+    if cache.paths.is_empty() {
+        info!("{}", tr!("reset-empty"));
+        return Ok(());
+    }
+    let _access = access::begin_paths(
+        ctx.fs_access.as_ref(),
+        config,
+        cache.paths.iter().map(PathBuf::as_path),
+    );
+
+    if dry_run {
+        for p in &cache.paths {
+            info!("[dry-run] would remove exclusion: {}", p.display());
+        }
+    } else {
+        let path_refs: Vec<&Path> = cache.paths.iter().map(|p| p.as_path()).collect();
+        modes::remove_by_mode(ctx.exclusion_manager.as_ref(), &path_refs, |p| {
+            cache.mode_for(p)
+        })?;
+        // Keep the cache restorable with `cache restore`, then delete it
+        if config.cache_snapshots > 0 {
+            snapshot_cache(&ctx.cache_path, config.cache_snapshots)?;
+        }
+        if ctx.cache_path.exists() {
+            fs::remove_file(&ctx.cache_path)
+                .with_context(|| format!("removing cache: {}", ctx.cache_path.display()))?;
+        }
+        info!("{}", tr!("reset-done", n = cache.paths.len()));
+    }
+
+    Ok(())
+}
+
+/// Whether a prompt may wait for an answer on stdin: not with
+/// `non_interactive` (`--non-interactive`), nor when stdin is not a terminal.
+fn interactive(config: &Config) -> bool {
+    !config.non_interactive && io::stdin().is_terminal()
+}
+
+/// Print `prompt` on stderr and read a yes/no answer (default no).
+fn ask(prompt: &str) -> Result<bool> {
+    eprint!("{prompt}");
+    io::stderr().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(matches!(input.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Confirm a destructive step described by `question`: yes without asking
+/// under `assume_yes` (`--yes`), no with a warning when not [`interactive`],
+/// else the user's answer.
+fn confirm(config: &Config, question: &str) -> Result<bool> {
+    if config.assume_yes {
+        return Ok(true);
+    }
+    if !interactive(config) {
+        warn!("{}", tr!("prompt-not-interactive", question = question));
+        return Ok(false);
+    }
+    ask(&tr!("prompt-continue", question = question))
+}
+
+// ─── `clean` command ──────────────────────────────────────────────────────────
+
+/// Execute the `clean` command: remove exclusions for paths that no longer exist on disk.
+///
+/// Delegates to [`clean::clean_selected`].  Paths on a volume that is not
+/// mounted are kept unless `include_unmounted`; paths moved to the Trash
+/// are removed or kept per `trashed_paths`.  With `interactive`, the
+/// stale paths are listed by repository and each group is only removed when
+/// confirmed (see [`confirm`]).  When `dry_run` is `true`, reports the count of stale
+/// paths but does not modify the cache or call `tmutil`.
+pub fn cmd_clean(
+    ctx: &AppContext,
+    config: &Config,
+    interactive: bool,
+    include_unmounted: bool,
+    dry_run: bool,
+) -> Result<()> {
+    // Acquire lock — clean mutates the cache
+    let mut lock = open_lock(ctx, config)?;
+    let Some(_guard) = lock.try_acquire(false)? else {
+        return Ok(());
+    };
+
+    // A cached path letitgo cannot reach looks missing without being gone
+    let cached = load_cache(&ctx.cache_path)?.paths;
+    let access = access::begin_paths(
+        ctx.fs_access.as_ref(),
+        config,
+        cached.iter().map(PathBuf::as_path),
+    );
+    let mut kept = 0;
+    let removed = clean::clean_selected(
+        &ctx.cache_path,
+        ctx.exclusion_manager.as_ref(),
+        config.cache_snapshots,
+        dry_run,
+        include_unmounted,
+        config.trashed_paths,
+        |p| access.denies(p),
+        |cache, stale| {
+            if !interactive {
+                return Ok(stale.to_vec());
+            }
+            let repos = cache.known_repos.as_ref().map_or(&[][..], |k| &k.repos);
+            let out = &ctx.output;
+            let mut remove = Vec::new();
+            for group in clean::group_stale(stale, repos) {
+                out.title(tr!(
+                    "clean-group",
+                    root = group.root.display(),
+                    n = group.paths.len()
+                ));
+                for p in &group.paths {
+                    out.item(p.display());
+                }
+                if let Some(volume) = &group.unmounted_volume {
+                    out.note(tr!("clean-volume-unmounted", volume = volume.display()));
+                }
+                if confirm(config, &tr!("clean-confirm-group", n = group.paths.len()))? {
+                    remove.extend(group.paths);
+                } else {
+                    kept += group.paths.len();
+                }
+            }
+            Ok(remove)
+        },
+    )?;
+    if removed > 0 {
+        info!("{}", tr!("clean-done", n = removed));
+    } else if kept == 0 {
+        info!("{}", tr!("clean-none"));
+    }
+    if kept > 0 {
+        info!("{}", tr!("clean-kept", n = kept));
+    }
+    Ok(())
+}
+
+// ─── `explain` command ───────────────────────────────────────────────────────
+
+/// Execute the `explain` command: describe why `path` is or isn't excluded.
+///
+/// Combines the rule provenance from [`explain::explain`] with what the cache
+/// recorded and what the exclusion manager reports for the path on disk.
+pub fn cmd_explain(ctx: &AppContext, config: &Config, path: &Path) -> Result<()> {
+    let _access = access::begin_paths(ctx.fs_access.as_ref(), config, [path]);
+    let explanation = explain::explain(path, config)?;
+    let out = &ctx.output;
+    let path = &explanation.path;
+
+    out.title(path.display());
+
+    match &explanation.repo {
+        Some(repo) => out.field("  repo:       ", repo.display()),
+        None => out.field("  repo:       ", "(not inside a Git repository)"),
+    }
+    if let Some(marker) = &explanation.keep_marker {
+        out.field(
+            "  keep:       ",
+            format!(
+                "everything in the repo kept in backups by {}",
+                marker.display()
+            ),
+        );
+    }
+    if let Some(rule) = &explanation.gitignore {
+        let verb = if rule.ignored {
+            "ignored"
+        } else {
+            "re-included"
+        };
+        out.field(
+            "  gitignore:  ",
+            format!(
+                "{} {} by `{}` ({})",
+                rule.target.display(),
+                verb,
+                rule.source.pattern,
+                rule.source.location(),
+            ),
+        );
+    } else if explanation.repo.is_some() {
+        out.field("  gitignore:  ", "no matching rule");
+    }
+    for rule in &explanation.lignore {
+        let verb = if rule.ignored { "added" } else { "re-included" };
+        out.field(
+            "  .lignore:   ",
+            format!(
+                "{} {} by `{}` ({})",
+                rule.target.display(),
+                verb,
+                rule.source.pattern,
+                rule.source.location(),
+            ),
+        );
+    }
+    for glob in &explanation.whitelist {
+        out.field("  whitelist:  ", format!("kept in backups by `{glob}`"));
+    }
+    if let Some(winner) = explanation.whitelist_conflict {
+        out.field(
+            "  conflict:   ",
+            format!(".lignore adds it but the whitelist keeps it — {winner} wins"),
+        );
+    }
+    if let Some(file) = &explanation.protected_file {
+        out.field(
+            "  safety:     ",
+            format!("blocked by protected file {}", file.display()),
+        );
+    }
+
+    let verdict = match &explanation.covered_by {
+        Some(p) if p == path => "excluded".to_string(),
+        Some(p) => format!("excluded (via {})", p.display()),
+        None => "not excluded".to_string(),
+    };
+    out.strong_field("  decision:   ", verdict);
+
+    let cache = load_cache(&ctx.cache_path)?;
+    let cached = cache.path_set();
+    match path.ancestors().find(|a| cached.contains(*a)) {
+        Some(p) => out.field(
+            "  cache:      ",
+            format!(
+                "recorded as {} (origin: {})",
+                p.display(),
+                cache.meta_for(p).origin
+            ),
+        ),
+        None => out.field("  cache:      ", "not recorded"),
+    }
 
-    if dry_run {
-        for p in &cache.paths {
-            info!("[dry-run] would remove exclusion: {}", p.display());
+    match ctx.exclusion_manager.is_excluded(path) {
+        Ok(true) => out.field("  on disk:    ", "excluded"),
+        Ok(false) => out.field("  on disk:    ", "not excluded"),
+        Err(e) => out.field("  on disk:    ", format!("unknown ({e})")),
+    }
+    Ok(())
+}
+
+// ─── `why-not` command ───────────────────────────────────────────────────────
+
+/// Execute `why-not <path>`: print the first step of a run that keeps `path`
+/// out of the exclusion set.
+pub fn cmd_why_not(ctx: &AppContext, config: &Config, path: &Path) -> Result<()> {
+    use explain::NotExcluded;
+
+    let out = &ctx.output;
+    let _access = access::begin_paths(ctx.fs_access.as_ref(), config, [path]);
+    let reason = explain::why_not(path, config)?;
+    let path = path.display();
+    let Some(reason) = reason else {
+        out.result(format_args!("{path} is excluded"));
+        return Ok(());
+    };
+    let detail = match reason {
+        NotExcluded::OutsideSearchPaths => "it is not under any search path".to_string(),
+        NotExcluded::IgnoredPath(ig) => {
+            format!("the scan skips it (ignored_paths: {})", ig.display())
+        }
+        NotExcluded::NoRepo => "it is not inside a Git repository".to_string(),
+        NotExcluded::KeepMarker(marker) => {
+            format!("{} keeps its whole repository", marker.display())
+        }
+        NotExcluded::AncestorExcluded(p) => {
+            format!("its ancestor {} is excluded instead", p.display())
+        }
+        NotExcluded::SafetyRail(p, violation) => {
+            let hint = match violation {
+                Violation::TooShallow(_) => " (allowed with `run --force`)",
+                Violation::Protected(_) => "",
+            };
+            format!("{} is not excluded because {violation}{hint}", p.display())
+        }
+        NotExcluded::TooSmall(size) => format!(
+            "it is a {} file, under min_file_size_kb = {}",
+            format_size(size),
+            config.min_file_size_kb
+        ),
+        NotExcluded::Whitelisted(globs) => {
+            format!("the whitelist keeps it (`{}`)", globs.join("`, `"))
+        }
+        NotExcluded::LignoreNegation(rule) => format!(
+            "`{}` re-includes {} ({})",
+            rule.source.pattern,
+            rule.target.display(),
+            rule.source.location()
+        ),
+        NotExcluded::ProtectedFile(file) => {
+            format!("the safety scan found protected file {}", file.display())
+        }
+        NotExcluded::NoMatchingRule => "no ignore rule matches it".to_string(),
+    };
+    out.result(format_args!("{path} is not excluded: {detail}"));
+    Ok(())
+}
+
+// ─── `repos` command ─────────────────────────────────────────────────────────
+
+/// Execute `repos`: list the repositories of the cached repo index, or with
+/// `path` the one containing it — or why discovery has not found one.
+pub fn cmd_repos(ctx: &AppContext, config: &Config, path: Option<&Path>, json: bool) -> Result<()> {
+    let cache = load_cache(&ctx.cache_path)?;
+    let discovered_at = cache.known_repos.as_ref().and_then(|k| k.discovered_at);
+    let (entries, not_found) = match path {
+        None => (repos::entries(&cache), None),
+        Some(path) => match repos::lookup(path, config, &cache) {
+            Ok(entry) => (vec![entry], None),
+            Err(reason) => (Vec::new(), Some(reason)),
+        },
+    };
+
+    if json {
+        let output = ReposOutput {
+            schema_version: REPOS_SCHEMA_VERSION,
+            discovered_at,
+            count: entries.len(),
+            repos: entries,
+            not_found,
+        };
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    let out = &ctx.output;
+    let time = |t: DateTime<FixedOffset>| t.format("%Y-%m-%d %H:%M:%S").to_string();
+    if let (Some(path), Some(reason)) = (path, &not_found) {
+        out.result(format_args!(
+            "{} is not in a known repository: {}",
+            path.display(),
+            not_found_detail(reason)
+        ));
+        return Ok(());
+    }
+    if entries.is_empty() {
+        out.note(tr!("repos-empty"));
+        return Ok(());
+    }
+    if path.is_none() {
+        let discovered = discovered_at.map_or_else(|| "never".to_string(), time);
+        out.heading(tr!(
+            "repos-header",
+            n = entries.len(),
+            discovered = discovered
+        ));
+    }
+    for entry in &entries {
+        let mut tags = vec![match &entry.parent {
+            Some(parent) => format!("{} of {}", entry.kind, parent.display()),
+            None => entry.kind.to_string(),
+        }];
+        if entry.sparse {
+            tags.push("sparse".to_string());
+        }
+        if let Some(marker) = entry.keep_marker.as_deref().and_then(Path::file_name) {
+            tags.push(format!("kept by {}", marker.to_string_lossy()));
+        }
+        tags.push(format!("{} exclusion(s)", entry.exclusions));
+        tags.push(match entry.last_resolved {
+            Some(at) => format!("resolved {}", time(at)),
+            None => "never resolved".to_string(),
+        });
+        out.tagged_item(entry.path.display(), &format!(" [{}]", tags.join(", ")));
+        for worktree in &entry.worktrees {
+            let note = match repos::lookup(&worktree.path, config, &cache) {
+                Err(reason) if !worktree.discovered => {
+                    format!(" (not found: {})", not_found_detail(&reason))
+                }
+                _ => String::new(),
+            };
+            out.field(
+                "      worktree: ",
+                format_args!("{}{note}", worktree.path.display()),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Why discovery has not found a repository, for `repos`.
+fn not_found_detail(reason: &repos::NotFound) -> String {
+    use repos::NotFound;
+
+    match reason {
+        NotFound::NoRepo => "it is not inside a Git repository".to_string(),
+        NotFound::OutsideSearchPaths { repo } => {
+            format!("{} is not under any search path", repo.display())
         }
+        NotFound::IgnoredPath { repo, ignored } => format!(
+            "discovery skips {} (ignored_paths: {})",
+            repo.display(),
+            ignored.display()
+        ),
+        NotFound::BareNotDetected { repo } => format!(
+            "{} is a bare repository and detect_bare_repos is off",
+            repo.display()
+        ),
+        NotFound::NotYetDiscovered {
+            repo,
+            discovered_at,
+        } => {
+            let since = match discovered_at {
+                Some(at) => format!("since {}", at.format("%Y-%m-%d %H:%M:%S")),
+                None => "yet".to_string(),
+            };
+            format!(
+                "the search paths have not been walked {since}; `letitgo run` will find {}",
+                repo.display()
+            )
+        }
+    }
+}
+
+// ─── `test-glob` command ─────────────────────────────────────────────────────
+
+/// Execute `test-glob`: print what `pattern`, read as `kind`, does to each
+/// of `paths` — or, with none given, list the cached exclusions it matches.
+///
+/// Relative paths are taken from the current directory; nothing is scanned.
+pub fn cmd_test_glob(
+    ctx: &AppContext,
+    kind: PatternKind,
+    pattern: &str,
+    paths: &[PathBuf],
+    base: Option<&Path>,
+) -> Result<()> {
+    let cwd = std::env::current_dir().context("reading the current directory")?;
+    let base = base.map(|b| cwd.join(b).clean());
+    let tester = PatternTester::new(kind, pattern, base.as_deref())?;
+    let from_cache = paths.is_empty();
+    let paths: Vec<PathBuf> = if from_cache {
+        load_cache(&ctx.cache_path)?.paths
     } else {
-        let path_refs: Vec<&Path> = cache.paths.iter().map(|p| p.as_path()).collect();
-        ctx.exclusion_manager
-            .remove_exclusions(&path_refs, fixed_path)?;
-        // Delete the cache file
-        if ctx.cache_path.exists() {
-            fs::remove_file(&ctx.cache_path)
-                .with_context(|| format!("removing cache: {}", ctx.cache_path.display()))?;
+        paths.iter().map(|p| cwd.join(p).clean()).collect()
+    };
+
+    let mut matched = 0;
+    for path in &paths {
+        let verdict = tester.test(path)?;
+        if verdict != Verdict::NoMatch {
+            matched += 1;
         }
-        info!(
-            "Reset complete. {} exclusion(s) removed.",
-            cache.paths.len()
+        if !from_cache || verdict != Verdict::NoMatch {
+            ctx.output
+                .field(&format!("{verdict:<10}  "), path.display());
+        }
+    }
+    info!(
+        "`{pattern}` matches {matched} of {} {}",
+        paths.len(),
+        if from_cache { "cached paths" } else { "paths" }
+    );
+    Ok(())
+}
+
+// ─── `stats` command ─────────────────────────────────────────────────────────
+
+/// Execute the `stats` command: report how much the cached exclusions weigh
+/// on disk and — with `check_backup` — inside the latest Time Machine backup,
+/// which is roughly what future backups no longer copy.
+pub fn cmd_stats(ctx: &AppContext, json: bool, check_backup: bool) -> Result<()> {
+    let cache = load_cache(&ctx.cache_path)?;
+    let latest = if check_backup {
+        tmutil::latest_backup().unwrap_or_else(|e| {
+            warn!("Could not look up the latest backup: {e:#}");
+            None
+        })
+    } else {
+        None
+    };
+    let impact = estimate_impact(&cache.paths, latest.as_deref());
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&impact)?);
+        return Ok(());
+    }
+
+    let out = &ctx.output;
+    out.title(format!(
+        "{} path(s) excluded from Time Machine",
+        impact.paths
+    ));
+    out.field(
+        "  on disk:        ",
+        format!(
+            "{} in {} existing path(s)",
+            format_size(impact.size_bytes),
+            impact.existing
+        ),
+    );
+    match &impact.latest_backup {
+        Some(backup) => {
+            out.field("  latest backup:  ", backup.backup.display());
+            out.field(
+                "  in that backup: ",
+                format!(
+                    "{} in {} path(s) — not copied by future backups",
+                    format_size(backup.size_bytes),
+                    backup.paths
+                ),
+            );
+        }
+        None if check_backup => out.field("  latest backup:  ", "none found"),
+        None => {}
+    }
+    Ok(())
+}
+
+// ─── `analyze-backup` command ────────────────────────────────────────────────
+
+/// Execute the `analyze-backup` command: for each of `backups`, report how
+/// much of it the current rules would have excluded, listing the `top`
+/// repositories that hold the most.  Comparing backups shows which ones are
+/// worth deleting (`tmutil delete -p`).
+pub fn cmd_analyze_backup(
+    out: &Output,
+    config: &Config,
+    backups: &[PathBuf],
+    top: usize,
+    json: bool,
+) -> Result<()> {
+    let analyses = backups
+        .iter()
+        .map(|backup| {
+            analyze_backup(backup, config)
+                .with_context(|| format!("analyzing {}", backup.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&analyses)?);
+        return Ok(());
+    }
+
+    for analysis in &analyses {
+        out.title(format!(
+            "{}: {} in {} excluded path(s) across {} of {} repo(s)",
+            analysis.backup.display(),
+            format_size(analysis.size_bytes),
+            analysis.paths,
+            analysis.by_repo.len(),
+            analysis.repos
+        ));
+        for waste in analysis.by_repo.iter().take(top) {
+            out.field(
+                &format!("  {:>10}  ", format_size(waste.size_bytes)),
+                waste.repo.display(),
+            );
+            for (path, size) in waste.paths.iter().take(3) {
+                let rel = path.strip_prefix(&waste.repo).unwrap_or(path);
+                out.field(&format!("  {:>10}    ", format_size(*size)), rel.display());
+            }
+        }
+        if analysis.by_repo.len() > top {
+            out.note(format!(
+                "  {}and {} more repo(s)",
+                out.ellipsis(),
+                analysis.by_repo.len() - top
+            ));
+        }
+    }
+    Ok(())
+}
+
+// ─── `purge-backups` command ─────────────────────────────────────────────────
+
+/// Execute the `purge-backups` command: delete the paths the current rules
+/// exclude from existing backups with `tmutil delete -p`, after showing
+/// per-backup estimates and asking for confirmation (see [`confirm`]).
+///
+/// Without `backups`, every backup but the latest one is purged.  Failed
+/// deletions are logged and the rest continue; the command fails if any did.
+pub fn cmd_purge_backups(
+    out: &Output,
+    config: &Config,
+    backups: &[PathBuf],
+    dry_run: bool,
+) -> Result<()> {
+    let backups = if backups.is_empty() {
+        let mut all = tm::list_backups()?;
+        all.pop();
+        all
+    } else {
+        backups.to_vec()
+    };
+    if backups.is_empty() {
+        info!("No backups to purge (the latest one is kept).");
+        return Ok(());
+    }
+
+    let mut analyses = Vec::new();
+    for backup in &backups {
+        let analysis = analyze_backup(backup, config)
+            .with_context(|| format!("analyzing {}", backup.display()))?;
+        out.field(
+            &format!("{}: ", backup.display()),
+            format!(
+                "{} in {} path(s)",
+                format_size(analysis.size_bytes),
+                analysis.paths
+            ),
         );
+        analyses.push(analysis);
+    }
+    let total_paths: usize = analyses.iter().map(|a| a.paths).sum();
+    let total_bytes: u64 = analyses.iter().map(|a| a.size_bytes).sum();
+    if total_paths == 0 {
+        info!("Nothing to purge.");
+        return Ok(());
+    }
+    let paths = analyses
+        .iter()
+        .flat_map(|a| a.by_repo.iter())
+        .flat_map(|waste| waste.paths.iter());
+
+    if dry_run {
+        for (path, size) in paths {
+            info!(
+                "[dry-run] would delete from backup: {} ({})",
+                path.display(),
+                format_size(*size)
+            );
+        }
+        return Ok(());
+    }
+    if !confirm(
+        config,
+        &tr!(
+            "purge-confirm",
+            n = total_paths,
+            size = format_size(total_bytes),
+            backups = analyses.len(),
+        ),
+    )? {
+        info!("{}", tr!("prompt-aborted"));
+        return Ok(());
     }
 
+    let (mut deleted, mut freed, mut failed) = (0, 0, 0);
+    for (path, size) in paths {
+        match tm::delete_backup_path(path) {
+            Ok(()) => {
+                deleted += 1;
+                freed += size;
+            }
+            Err(e) => {
+                warn!("Could not delete {}: {e:#}", path.display());
+                failed += 1;
+            }
+        }
+    }
+    info!(
+        "Deleted {deleted} path(s) from backups, about {} reclaimed.",
+        format_size(freed)
+    );
+    if failed > 0 {
+        anyhow::bail!("{failed} path(s) could not be deleted from backups");
+    }
     Ok(())
 }
 
-// ─── `clean` command ──────────────────────────────────────────────────────────
+// ─── `lignore` commands ──────────────────────────────────────────────────────
 
-/// Execute the `clean` command: remove exclusions for paths that no longer exist on disk.
+/// Execute `lignore add|negate|remove`: edit the `.lignore` at the root of
+/// `repo` (default: the repository containing the current directory), then
+/// re-resolve that repository and print how its exclusions changed.
 ///
-/// Delegates to [`clean::clean_stale`].  When `dry_run` is `true`, reports the
-/// count of stale paths but does not modify the cache or call `tmutil`.
-pub fn cmd_clean(ctx: &AppContext, config: &Config, dry_run: bool) -> Result<()> {
-    // Acquire lock — clean mutates the cache
-    let mut lock = open_lock_file(&ctx.lock_path)?;
-    let Ok(_guard) = lock.try_write() else {
-        warn!("Another letitgo instance is running. Skipping.");
+/// Time Machine is not touched; the next `run` applies the change.
+pub fn cmd_lignore(
+    ctx: &AppContext,
+    config: &Config,
+    edit: LignoreEdit,
+    pattern: &str,
+    repo: Option<&Path>,
+    dry_run: bool,
+) -> Result<()> {
+    let start = match repo {
+        Some(repo) => repo.to_path_buf(),
+        None => std::env::current_dir().context("reading current directory")?,
+    };
+    let out = &ctx.output;
+    let _access = access::begin_paths(ctx.fs_access.as_ref(), config, [start.as_path()]);
+    let Some(repo) = git::find_repo(&start) else {
+        anyhow::bail!("{} is not inside a Git repository", start.display());
+    };
+    let line = lignore_line(edit, pattern)?;
+    let options = ResolveOptions::from_config(config)?;
+    let before = resolve_repo(&repo, &options)?;
+
+    let lignore_path = repo.join(".lignore");
+    if !edit_lignore(&repo, edit, &line, dry_run)? {
+        match edit {
+            LignoreEdit::Remove => out.result(format_args!(
+                "`{line}` is not in {}",
+                lignore_path.display()
+            )),
+            _ => out.result(format_args!(
+                "`{line}` is already in {}",
+                lignore_path.display()
+            )),
+        }
         return Ok(());
+    }
+    let verb = match edit {
+        LignoreEdit::Remove => "Removed",
+        _ => "Added",
     };
+    if dry_run {
+        info!(
+            "[dry-run] would update {}: {} `{}`",
+            lignore_path.display(),
+            verb.to_lowercase(),
+            line
+        );
+        return Ok(());
+    }
+    out.result(format_args!(
+        "{verb} `{line}` in {}",
+        lignore_path.display()
+    ));
+
+    let after = resolve_repo(&repo, &options)?;
+    let mut added: Vec<&PathBuf> = after.difference(&before).collect();
+    let mut removed: Vec<&PathBuf> = before.difference(&after).collect();
+    added.sort();
+    removed.sort();
+    if added.is_empty() && removed.is_empty() {
+        out.note(format_args!(
+            "No change to the exclusions of {}",
+            repo.display()
+        ));
+    }
+    for p in added {
+        out.added(p.display());
+    }
+    for p in removed {
+        out.removed(p.display());
+    }
+    out.note("Run `letitgo run` to apply.");
+    Ok(())
+}
+
+// ─── `lint` command ──────────────────────────────────────────────────────────
+
+/// Execute `lint`: check the `.lignore` files of every repository under the
+/// search paths and print one `file:line` diagnostic per problem (or, with
+/// `json`, the list as JSON).
+///
+/// Fails when any line is one the engine cannot honor (see
+/// [`lint::LintKind::is_error`]); warnings alone do not.
+pub fn cmd_lint(out: &Output, config: &Config, json: bool) -> Result<()> {
+    let discovery = discover(
+        &config.resolved_search_paths(),
+        &config.resolved_ignored_paths(),
+        &config.scan_options(),
+    );
+    let mut diagnostics = Vec::new();
+    for repo in &discovery.repos {
+        diagnostics.extend(
+            lint::lint_repo(repo, config.walker())
+                .with_context(|| format!("linting {}", repo.display()))?,
+        );
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diagnostics)?);
+    } else {
+        for d in &diagnostics {
+            out.row(format_args!(
+                "{}:{}: {} `{}`: {}",
+                d.file.display(),
+                d.line,
+                d.kind,
+                d.pattern,
+                d.message
+            ));
+        }
+    }
+    let errors = diagnostics.iter().filter(|d| d.kind.is_error()).count();
+    info!(
+        "Checked {} repositories: {} errors, {} warnings",
+        discovery.repos.len(),
+        errors,
+        diagnostics.len() - errors
+    );
+    if errors > 0 {
+        anyhow::bail!("{errors} .lignore lines cannot be applied as written");
+    }
+    Ok(())
+}
 
-    let fixed_path = config.exclusion_mode.is_fixed_path();
-    let removed = clean::clean_stale(
+// ─── `doctor` command ────────────────────────────────────────────────────────
+
+/// Execute the `doctor` command: report each [`doctor::Check`] and fail when
+/// any of them fails.  Untested macOS/`tmutil` combinations and files other
+/// users can access only warn; with `fix_permissions` those files are first
+/// made private (see [`permissions`]).
+pub fn cmd_doctor(
+    ctx: &AppContext,
+    config: &Config,
+    json: bool,
+    fix_permissions: bool,
+) -> Result<()> {
+    let out = &ctx.output;
+    let state = permissions::state_paths(
+        &ctx.config_path,
         &ctx.cache_path,
-        ctx.exclusion_manager.as_ref(),
-        fixed_path,
-        dry_run,
-    )?;
-    if removed > 0 {
-        info!("Removed {} stale exclusion(s).", removed);
+        encryption::key_file_path().as_deref(),
+    );
+    if fix_permissions {
+        for loose in permissions::loose(&state) {
+            loose
+                .restrict()
+                .with_context(|| format!("restricting permissions of {}", loose.path.display()))?;
+            info!(
+                "Restricted {} from {:o} to {:o}",
+                loose.path.display(),
+                loose.mode,
+                loose.wanted()
+            );
+        }
+    }
+    let binary = config.tmutil_binary_path.as_deref().map(expand_tilde);
+    let checks = doctor::checks(config, &tmutil::tmutil_binary(binary.as_deref()), &state);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&checks)?);
     } else {
-        info!("No stale paths found.");
+        for check in &checks {
+            out.field(
+                &format!("{:<13}", format!("{}:", check.name)),
+                format_args!("[{}] {}", check.status, check.detail),
+            );
+        }
+    }
+    let failed = checks
+        .iter()
+        .filter(|c| c.status == doctor::Status::Fail)
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{failed} doctor check(s) failed");
+    }
+    Ok(())
+}
+
+// ─── `tm` commands ────────────────────────────────────────────────────────────
+
+/// Execute `tm thin-local-snapshots`: thin the local snapshots of
+/// `mount_point` and print the ones removed.  With `dry_run`, list the
+/// existing snapshots instead.
+pub fn cmd_tm_thin(
+    out: &Output,
+    mount_point: &Path,
+    purge_amount: Option<u64>,
+    urgency: Option<u8>,
+    json: bool,
+    dry_run: bool,
+) -> Result<()> {
+    if dry_run {
+        let snapshots = tm::list_local_snapshots(mount_point)?;
+        info!(
+            "[dry-run] would thin local snapshots of {} ({} present)",
+            mount_point.display(),
+            snapshots.len()
+        );
+        return print_snapshots(out, "Local snapshots", mount_point, &snapshots, json);
+    }
+    let thinned = tm::thin_local_snapshots(mount_point, purge_amount, urgency)?;
+    print_snapshots(out, "Thinned snapshots", mount_point, &thinned, json)
+}
+
+fn print_snapshots(
+    out: &Output,
+    label: &str,
+    mount_point: &Path,
+    snapshots: &[String],
+    json: bool,
+) -> Result<()> {
+    if json {
+        let output = serde_json::json!({
+            "mount_point": mount_point,
+            "count": snapshots.len(),
+            "snapshots": snapshots,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+    out.title(format!(
+        "{label} of {}: {}",
+        mount_point.display(),
+        snapshots.len()
+    ));
+    for snapshot in snapshots {
+        out.item(snapshot);
+    }
+    Ok(())
+}
+
+/// Execute `tm list-destinations`: print the configured backup destinations.
+pub fn cmd_tm_destinations(out: &Output, json: bool) -> Result<()> {
+    let destinations = tm::list_destinations()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&destinations)?);
+        return Ok(());
+    }
+    if destinations.is_empty() {
+        out.note("No backup destinations configured.");
+        return Ok(());
+    }
+    for dest in &destinations {
+        out.title(dest.name.as_deref().unwrap_or("(unnamed)"));
+        let fields = [
+            ("kind", &dest.kind),
+            ("mounted at", &dest.mount_point),
+            ("url", &dest.url),
+            ("id", &dest.id),
+        ];
+        for (label, value) in fields {
+            if let Some(value) = value {
+                out.field(&format!("  {label:<11} "), value);
+            }
+        }
+    }
+    Ok(())
+}
+
+// ─── external subcommands ────────────────────────────────────────────────────
+
+/// Run an external subcommand: `args` is its name and arguments, as clap
+/// collected them.  `letitgo-<name>` is looked up on `PATH` and started
+/// with the `global_args` given to letitgo before its own arguments, and
+/// with the config, cache and lock paths in the environment (see
+/// [`external`]).  Returns its exit status.
+pub fn cmd_external(
+    ctx: &AppContext,
+    config: &Config,
+    global_args: &[OsString],
+    args: &[OsString],
+) -> Result<ExitStatus> {
+    let (name, rest) = external::split_args(args).context("subcommand name is not UTF-8")?;
+    let dirs = external::search_path();
+    let Some(exe) = external::find(&name, &dirs) else {
+        let installed: Vec<String> = external::list(&dirs).into_keys().collect();
+        anyhow::bail!(
+            "no such command: `{name}` (and no `{}{name}` on PATH); external commands: {}",
+            external::EXTERNAL_PREFIX,
+            if installed.is_empty() {
+                "none".to_string()
+            } else {
+                installed.join(", ")
+            }
+        );
+    };
+    debug!("Running external subcommand {}", exe.display());
+    let mut command = Command::new(&exe);
+    command
+        .args(global_args)
+        .args(rest)
+        .env(external::CONFIG_ENV, &ctx.config_path)
+        .env(external::CACHE_ENV, &ctx.cache_path)
+        .env(external::LOCK_ENV, &ctx.lock_path)
+        .env(external::VERSION_ENV, env!("CARGO_PKG_VERSION"))
+        .env_remove(external::ROOT_ENV);
+    if let Some(root) = &config.root {
+        command.env(external::ROOT_ENV, root);
+    }
+    if let Ok(current) = std::env::current_exe() {
+        command.env(external::EXE_ENV, current);
+    }
+    command
+        .status()
+        .with_context(|| format!("running {}", exe.display()))
+}
+
+// ─── `schema` command ────────────────────────────────────────────────────────
+
+/// Execute `schema`: print the JSON Schema of `name` (see [`schema::schema`]).
+pub fn cmd_schema(name: SchemaName) -> Result<()> {
+    print!("{}", schema::render(name));
+    Ok(())
+}
+
+// ─── `version` command ───────────────────────────────────────────────────────
+
+/// Execute `version`: print the version, and with `verbose` the build
+/// metadata embedded by `build.rs` (see [`build_info::BuildInfo`]).
+pub fn cmd_version(out: &Output, verbose: bool, json: bool) -> Result<()> {
+    let info = build_info::build_info();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+    out.result(format_args!("letitgo {}", info.version));
+    if !verbose {
+        return Ok(());
     }
+    let features = if info.features.is_empty() {
+        "(none)".to_string()
+    } else {
+        info.features.join(", ")
+    };
+    out.field("commit:        ", info.git_sha);
+    out.field("built:         ", info.build_date);
+    out.field("target:        ", info.target);
+    out.field("profile:       ", info.profile);
+    out.field("rustc:         ", info.rustc);
+    out.field("features:      ", features);
+    out.field(
+        "xattr-direct:  ",
+        if info.xattr_direct {
+            "yes"
+        } else {
+            "no (tmutil)"
+        },
+    );
     Ok(())
 }
 
@@ -441,11 +4298,11 @@ pub fn cmd_init(ctx: &AppContext, force: bool) -> Result<()> {
     }
 
     if let Some(parent) = ctx.config_path.parent() {
-        fs::create_dir_all(parent)
+        permissions::create_private_dir(parent)
             .with_context(|| format!("creating config dir: {}", parent.display()))?;
     }
 
-    fs::write(&ctx.config_path, config::DEFAULT_CONFIG)
+    permissions::write_private(&ctx.config_path, config::DEFAULT_CONFIG)
         .with_context(|| format!("writing config: {}", ctx.config_path.display()))?;
 
     info!("Config written to {}", ctx.config_path.display());
@@ -453,29 +4310,3 @@ pub fn cmd_init(ctx: &AppContext, force: bool) -> Result<()> {
 }
 
 // ─── Lockfile ─────────────────────────────────────────────────────────────────
-
-/// Open (or create) the lockfile and return it wrapped in an `RwLock`.
-///
-/// The caller should call [`try_write()`](FdRwLock::try_write) on the returned
-/// value and keep the resulting guard alive for the duration of the exclusive
-/// section.  Dropping the guard releases the lock; the OS also releases it
-/// automatically on process exit (even on `SIGKILL`) because `flock(2)` locks
-/// are per-open-file-description.
-fn open_lock_file(lock_path: &Path) -> Result<FdRwLock<fs::File>> {
-    use std::fs::OpenOptions;
-
-    // Ensure the parent directory exists (lockfile lives next to cache).
-    if let Some(parent) = lock_path.parent() {
-        fs::create_dir_all(parent)
-            .with_context(|| format!("creating lockfile dir: {}", parent.display()))?;
-    }
-
-    let file = OpenOptions::new()
-        .create(true)
-        .truncate(false)
-        .write(true)
-        .open(lock_path)
-        .with_context(|| format!("opening lockfile: {}", lock_path.display()))?;
-
-    Ok(FdRwLock::new(file))
-}
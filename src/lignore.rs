@@ -0,0 +1,118 @@
+use anyhow::{Context, Result, bail};
+use ignore::gitignore::GitignoreBuilder;
+use std::{fs, path::Path};
+
+/// How a `.lignore` line is edited by `letitgo lignore`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LignoreEdit {
+    /// Append an addition pattern (exclude even if not gitignored).
+    Add,
+    /// Append a `!path` negation (keep a gitignored path in backups).
+    Negate,
+    /// Delete the line.
+    Remove,
+}
+
+/// Turn `pattern` into the `.lignore` line for `edit`, checking its syntax.
+///
+/// Additions use gitignore syntax.  Negations are literal paths relative to
+/// the `.lignore` directory (see the README), so glob characters are
+/// rejected.  For [`LignoreEdit::Remove`] the pattern is used as written.
+pub fn lignore_line(edit: LignoreEdit, pattern: &str) -> Result<String> {
+    let pattern = pattern.trim();
+    if pattern.is_empty() || pattern.contains('\n') {
+        bail!("a .lignore pattern must be a single non-empty line");
+    }
+    if pattern.starts_with('#') {
+        bail!("`{pattern}` would be a comment");
+    }
+    match edit {
+        LignoreEdit::Remove => Ok(pattern.to_string()),
+        LignoreEdit::Add => {
+            if pattern.starts_with('!') {
+                bail!("use `letitgo lignore negate` for `!` patterns");
+            }
+            GitignoreBuilder::new("/")
+                .add_line(None, pattern)
+                .with_context(|| format!("invalid pattern `{pattern}`"))?;
+            Ok(pattern.to_string())
+        }
+        LignoreEdit::Negate => {
+            let path = pattern.trim_start_matches('!');
+            if path.contains(['*', '?', '[']) {
+                bail!("negations are literal paths, not globs: `{path}`");
+            }
+            if Path::new(path).is_absolute() || path.split('/').any(|c| c == "..") {
+                bail!("negations must be relative paths inside the repository: `{path}`");
+            }
+            Ok(format!("!{path}"))
+        }
+    }
+}
+
+/// Apply `edit` with `line` to the `.lignore` at the root of `repo`.
+///
+/// Returns `false` when nothing changed (the line was already present, or
+/// absent for a removal).  With `dry_run` the file is not written.
+pub fn edit_lignore(repo: &Path, edit: LignoreEdit, line: &str, dry_run: bool) -> Result<bool> {
+    let path = repo.join(".lignore");
+    let content = match fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    let present = content.lines().any(|l| l.trim() == line);
+    let new_content = match edit {
+        LignoreEdit::Add | LignoreEdit::Negate if present => return Ok(false),
+        LignoreEdit::Remove if !present => return Ok(false),
+        LignoreEdit::Add | LignoreEdit::Negate => {
+            let mut c = content;
+            if !c.is_empty() && !c.ends_with('\n') {
+                c.push('\n');
+            }
+            c.push_str(line);
+            c.push('\n');
+            c
+        }
+        LignoreEdit::Remove => content
+            .lines()
+            .filter(|l| l.trim() != line)
+            .map(|l| format!("{l}\n"))
+            .collect(),
+    };
+    if !dry_run {
+        fs::write(&path, new_content).with_context(|| format!("writing {}", path.display()))?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_lignore_line_validation_and_edit() {
+        assert_eq!(lignore_line(LignoreEdit::Add, " data/ ").unwrap(), "data/");
+        assert_eq!(
+            lignore_line(LignoreEdit::Negate, "target/").unwrap(),
+            "!target/"
+        );
+        assert!(lignore_line(LignoreEdit::Add, "!target/").is_err());
+        assert!(lignore_line(LignoreEdit::Add, "# note").is_err());
+        assert!(lignore_line(LignoreEdit::Negate, "*.log").is_err());
+        assert!(lignore_line(LignoreEdit::Negate, "../other").is_err());
+
+        let tmp = tempdir().unwrap();
+        fs::write(tmp.path().join(".lignore"), "# keep\nscratch/").unwrap();
+        assert!(edit_lignore(tmp.path(), LignoreEdit::Add, "data/", false).unwrap());
+        assert!(!edit_lignore(tmp.path(), LignoreEdit::Add, "data/", false).unwrap());
+        assert!(edit_lignore(tmp.path(), LignoreEdit::Remove, "scratch/", false).unwrap());
+        assert!(!edit_lignore(tmp.path(), LignoreEdit::Remove, "scratch/", false).unwrap());
+        assert!(edit_lignore(tmp.path(), LignoreEdit::Negate, "!target/", true).unwrap());
+        assert_eq!(
+            fs::read_to_string(tmp.path().join(".lignore")).unwrap(),
+            "# keep\ndata/\n"
+        );
+    }
+}
@@ -0,0 +1,289 @@
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use path_clean::PathClean;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::warn;
+
+use crate::{
+    ignore_resolver::{MatchSource, resolve_gitignore_layer},
+    lignore::{LignoreEdit, lignore_line},
+    walk::Walker,
+};
+
+/// What is wrong with a `.lignore` line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LintKind {
+    /// The line cannot be parsed, or is a negation that is not a literal
+    /// relative path.
+    Syntax,
+    /// A negation inside an excluded directory: the engine keeps the whole
+    /// directory excluded.
+    SubPathNegation,
+    /// An addition that matches no path, or a negation of a path that is
+    /// not excluded.
+    NoMatch,
+    /// An addition whose every match `.gitignore` already excludes.
+    Redundant,
+}
+
+impl LintKind {
+    /// Errors are lines the engine cannot honor; the rest are warnings.
+    pub fn is_error(self) -> bool {
+        matches!(self, LintKind::Syntax | LintKind::SubPathNegation)
+    }
+}
+
+impl std::fmt::Display for LintKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LintKind::Syntax => "syntax",
+            LintKind::SubPathNegation => "sub-path-negation",
+            LintKind::NoMatch => "no-match",
+            LintKind::Redundant => "redundant",
+        })
+    }
+}
+
+/// One problem found by [`lint_repo`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub file: PathBuf,
+    /// 1-based line of the rule.
+    pub line: usize,
+    pub pattern: String,
+    pub kind: LintKind,
+    pub message: String,
+}
+
+/// An addition line of one `.lignore`, compiled on its own.
+struct Addition {
+    line: usize,
+    pattern: String,
+    matcher: Gitignore,
+    /// Paths it adds outside the directories `.gitignore` excludes.
+    added: Vec<PathBuf>,
+    /// An excluded path it matches, with the rule that already excludes it.
+    covered: Option<(PathBuf, MatchSource)>,
+}
+
+/// Check every `.lignore` the engine reads in `repo_root` — those outside
+/// `.git` and the directories `.gitignore` excludes.
+///
+/// Each line is checked the way a run applies it: additions are matched
+/// against the tree (not descending into excluded directories), negations
+/// against the paths excluded by `.gitignore` and the file's additions.
+pub fn lint_repo(repo_root: &Path, walker: Walker) -> Result<Vec<Diagnostic>> {
+    let gitignored = resolve_gitignore_layer(repo_root, walker)?;
+    let mut diagnostics = Vec::new();
+    let mut walk = walker.walk(repo_root);
+    while let Some(entry) = walk.next() {
+        let entry = match entry {
+            Ok(e) => e,
+            Err(e) => {
+                warn!("Walk error discovering .lignore files: {e}");
+                continue;
+            }
+        };
+        let path = entry.path();
+        if entry.file_type().is_dir()
+            && (entry.file_name() == ".git" || gitignored.contains_key(path))
+        {
+            walk.skip_current_dir();
+            continue;
+        }
+        if entry.file_type().is_file() && entry.file_name() == ".lignore" {
+            lint_file(path, &gitignored, walker, &mut diagnostics)?;
+        }
+    }
+    diagnostics.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+    Ok(diagnostics)
+}
+
+fn lint_file(
+    file: &Path,
+    gitignored: &HashMap<PathBuf, MatchSource>,
+    walker: Walker,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<()> {
+    let dir = file.parent().unwrap_or(Path::new("/"));
+    let text = fs::read_to_string(file).with_context(|| format!("reading {}", file.display()))?;
+    let mut report = |line: usize, pattern: &str, kind: LintKind, message: String| {
+        diagnostics.push(Diagnostic {
+            file: file.to_path_buf(),
+            line,
+            pattern: pattern.to_string(),
+            kind,
+            message,
+        });
+    };
+
+    let mut additions = Vec::new();
+    let mut negations = Vec::new();
+    for (i, raw) in text.lines().enumerate() {
+        let (line, pattern) = (i + 1, raw.trim());
+        if pattern.is_empty() || pattern.starts_with('#') {
+            continue;
+        }
+        let edit = if pattern.starts_with('!') {
+            LignoreEdit::Negate
+        } else {
+            LignoreEdit::Add
+        };
+        if let Err(e) = lignore_line(edit, pattern) {
+            report(line, pattern, LintKind::Syntax, format!("{e:#}"));
+            continue;
+        }
+        if edit == LignoreEdit::Negate {
+            negations.push((line, pattern));
+            continue;
+        }
+        let mut builder = GitignoreBuilder::new(dir);
+        builder.add_line(Some(file.to_path_buf()), pattern)?;
+        additions.push(Addition {
+            line,
+            pattern: pattern.to_string(),
+            matcher: builder.build().context("building .lignore matcher")?,
+            added: Vec::new(),
+            covered: None,
+        });
+    }
+
+    // One walk for all additions, pruned like the engine's addition pass —
+    // except that excluded directories are searched while some addition has
+    // not matched anything yet, to tell redundant lines from dead ones.
+    let mut walk = walker.walk(dir).min_depth(1);
+    while let Some(entry) = walk.next() {
+        let Ok(entry) = entry else { continue };
+        let path = entry.path();
+        let is_dir = entry.file_type().is_dir();
+        if is_dir && entry.file_name() == ".git" {
+            walk.skip_current_dir();
+            continue;
+        }
+        let Ok(rel) = path.strip_prefix(dir) else {
+            continue;
+        };
+        let covering = path
+            .ancestors()
+            .find_map(|a| gitignored.get(a).map(|source| (a, source)));
+        let mut matched_dir = false;
+        for addition in &mut additions {
+            if !addition.matcher.matched(rel, is_dir).is_ignore() {
+                continue;
+            }
+            match covering {
+                Some((_, source)) => {
+                    addition
+                        .covered
+                        .get_or_insert((path.to_path_buf(), source.clone()));
+                }
+                None => {
+                    addition.added.push(path.to_path_buf());
+                    matched_dir |= is_dir;
+                }
+            }
+        }
+        let pending = additions
+            .iter()
+            .any(|a| a.added.is_empty() && a.covered.is_none());
+        if is_dir && (matched_dir || (covering.is_some() && !pending)) {
+            walk.skip_current_dir();
+        }
+    }
+
+    for addition in &additions {
+        match (&addition.added[..], &addition.covered) {
+            ([], Some((path, source))) => report(
+                addition.line,
+                &addition.pattern,
+                LintKind::Redundant,
+                format!(
+                    "{} is already excluded by `{}` ({})",
+                    path.display(),
+                    source.pattern,
+                    source.location()
+                ),
+            ),
+            ([], None) => report(
+                addition.line,
+                &addition.pattern,
+                LintKind::NoMatch,
+                "matches nothing".to_string(),
+            ),
+            _ => {}
+        }
+    }
+
+    for (line, pattern) in negations {
+        let target = dir.join(pattern[1..].trim_end_matches('/')).clean();
+        let excluded = |p: &Path| {
+            gitignored.contains_key(p) || additions.iter().any(|a| a.added.iter().any(|x| x == p))
+        };
+        if excluded(&target) {
+            continue;
+        }
+        match target.ancestors().skip(1).find(|a| excluded(a)) {
+            Some(parent) => report(
+                line,
+                pattern,
+                LintKind::SubPathNegation,
+                format!(
+                    "{} stays excluded as part of {}; negate `!{}` instead",
+                    target.display(),
+                    parent.display(),
+                    parent.strip_prefix(dir).unwrap_or(parent).display()
+                ),
+            ),
+            None => report(
+                line,
+                pattern,
+                LintKind::NoMatch,
+                format!("{} is not excluded by any rule", target.display()),
+            ),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_lint_reports_each_kind_with_its_line() {
+        let tmp = tempdir().unwrap();
+        let repo = tmp.path().join("app");
+        for dir in [".git", "target/release", "data", "src"] {
+            fs::create_dir_all(repo.join(dir)).unwrap();
+        }
+        fs::write(repo.join(".gitignore"), "target/\n").unwrap();
+        fs::write(
+            repo.join(".lignore"),
+            "# scratch\ndata/\ntarget/release/\n*.bak\n!target/release\n!data\n!src\n!*.log\n",
+        )
+        .unwrap();
+
+        let found: Vec<(usize, LintKind)> = lint_repo(&repo, Walker::default())
+            .unwrap()
+            .iter()
+            .map(|d| (d.line, d.kind))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (3, LintKind::Redundant),
+                (4, LintKind::NoMatch),
+                (5, LintKind::SubPathNegation),
+                (7, LintKind::NoMatch),
+                (8, LintKind::Syntax),
+            ]
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use chrono::{DateTime, FixedOffset};
+use clap::ValueEnum;
+use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::{
+    cmp::Reverse,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cache::{Cache, Origin},
+    config::ExclusionMode,
+    policy::sha256_hex,
+    provenance::Rule,
+    size::{disk_size, format_size},
+};
+
+/// Version of the `list --json` format.  Version 2 lists each path as a
+/// [`PathEntry`]; the unversioned format before it listed bare paths.
+pub const LIST_SCHEMA_VERSION: u32 = 2;
+
+/// A cached path as `list --json` and the run report describe it.
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct PathEntry {
+    pub path: PathBuf,
+    pub origin: Origin,
+    /// The known repository containing the path.
+    pub repo: Option<PathBuf>,
+    /// The rule that produced the path, when the cache records it.
+    pub rule: Option<Rule>,
+    /// When a run first excluded the path.
+    pub first_seen: Option<DateTime<FixedOffset>>,
+}
+
+/// The output of `list --json`.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ListOutput {
+    /// Version of this format, bumped when a field changes meaning or goes
+    /// away.
+    pub schema_version: u32,
+    pub count: usize,
+    pub last_run: Option<DateTime<FixedOffset>>,
+    pub exclusion_mode: ExclusionMode,
+    pub paths: Vec<PathEntry>,
+}
+
+impl PathEntry {
+    pub fn new(cache: &Cache, path: &Path) -> Self {
+        PathEntry {
+            path: path.to_path_buf(),
+            origin: cache.meta_for(path).origin,
+            repo: cache.repo_of(path).map(Path::to_path_buf),
+            rule: cache.rule_for(path),
+            first_seen: cache.added.get(path).copied(),
+        }
+    }
+
+    /// This entry with its paths [`redact`]ed.
+    pub fn redacted(self) -> Self {
+        PathEntry {
+            path: redact(&self.path),
+            repo: self.repo.as_deref().map(redact_dir),
+            ..self
+        }
+    }
+}
+
+/// `path` as `list --redact` prints it: its last component under the
+/// [`redact_dir`] of the directory holding it, `3f2a9c1b/node_modules`.
+pub fn redact(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => redact_dir(parent).join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// A directory (a repo, say) as `list --redact` prints it: a short hash of
+/// its path, with nothing of its name.  The hash is stable, so paths in the
+/// same directory, and that directory as a repo, still show as such.
+pub fn redact_dir(dir: &Path) -> PathBuf {
+    PathBuf::from(&sha256_hex(dir.as_os_str().as_bytes())[..8])
+}
+
+/// How `letitgo list` prints the cached paths.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ListFormat {
+    /// One path per line.
+    #[default]
+    Plain,
+    /// Aligned columns: path, repo, origin, size and date added.
+    Table,
+}
+
+/// The order `letitgo list` prints the cached paths in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ListSort {
+    /// By path.
+    Path,
+    /// Largest first.
+    Size,
+    /// By containing repository, then path; paths outside repos last.
+    Repo,
+    /// Most recently added first; paths cached before dates were recorded last.
+    Added,
+}
+
+/// A cached path with the columns of the table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub path: PathBuf,
+    pub repo: Option<PathBuf>,
+    pub origin: Origin,
+    /// `None` for paths that no longer exist, or when sizes were not asked for.
+    pub size: Option<u64>,
+    pub added: Option<DateTime<FixedOffset>>,
+}
+
+/// The rows of `paths`, measuring their sizes (in parallel) when `sizes`.
+pub fn rows(cache: &Cache, paths: &[&Path], sizes: bool) -> Vec<Row> {
+    paths
+        .par_iter()
+        .map(|path| Row {
+            path: path.to_path_buf(),
+            repo: cache.repo_of(path).map(Path::to_path_buf),
+            origin: cache.meta_for(path).origin,
+            size: (sizes && path.exists()).then(|| disk_size(path)),
+            added: cache.added.get(*path).copied(),
+        })
+        .collect()
+}
+
+pub fn sort(rows: &mut [Row], by: ListSort) {
+    match by {
+        ListSort::Path => rows.sort_by(|a, b| a.path.cmp(&b.path)),
+        ListSort::Size => rows.sort_by_key(|r| (Reverse(r.size), r.path.clone())),
+        ListSort::Repo => {
+            rows.sort_by_key(|r| (r.repo.is_none(), r.repo.clone(), r.path.clone()));
+        }
+        ListSort::Added => rows.sort_by_key(|r| (Reverse(r.added), r.path.clone())),
+    }
+}
+
+/// The table of `rows`: a header line, then one line per row, each column
+/// padded to its widest cell (sizes right-aligned).  A path inside its repo
+/// is shown relative to it; with `redacted`, absolute paths are
+/// [`redact`]ed.
+pub fn table(rows: &[Row], redacted: bool) -> Vec<String> {
+    let show = |path: &Path| {
+        if redacted && path.is_absolute() {
+            redact(path).display().to_string()
+        } else {
+            path.display().to_string()
+        }
+    };
+    let cells: Vec<[String; 5]> = rows
+        .iter()
+        .map(|r| {
+            let path = r
+                .repo
+                .as_deref()
+                .and_then(|repo| r.path.strip_prefix(repo).ok())
+                .filter(|rel| !rel.as_os_str().is_empty())
+                .unwrap_or(&r.path);
+            [
+                show(path),
+                r.repo.as_deref().map_or("-".to_string(), |repo| {
+                    if redacted {
+                        redact_dir(repo).display().to_string()
+                    } else {
+                        repo.display().to_string()
+                    }
+                }),
+                r.origin.to_string(),
+                r.size.map_or("-".to_string(), format_size),
+                r.added
+                    .map_or("-".to_string(), |t| t.format("%Y-%m-%d").to_string()),
+            ]
+        })
+        .collect();
+    let header = ["PATH", "REPO", "ORIGIN", "SIZE", "ADDED"].map(str::to_string);
+    let mut widths = [0; 5];
+    for row in std::iter::once(&header).chain(&cells) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    std::iter::once(&header)
+        .chain(&cells)
+        .map(|[path, repo, origin, size, added]| {
+            format!(
+                "{path:<w0$}  {repo:<w1$}  {origin:<w2$}  {size:>w3$}  {added}",
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+                w3 = widths[3],
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn row(path: &str, repo: Option<&str>, size: Option<u64>, day: Option<u32>) -> Row {
+        Row {
+            path: PathBuf::from(path),
+            repo: repo.map(PathBuf::from),
+            origin: if repo.is_some() {
+                Origin::Repo
+            } else {
+                Origin::Preset
+            },
+            size,
+            added: day.map(|d| {
+                FixedOffset::east_opt(0)
+                    .unwrap()
+                    .with_ymd_and_hms(2026, 3, d, 12, 0, 0)
+                    .unwrap()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_sort_orders_missing_values_last() {
+        let mut rows = vec![
+            row("/u/.npm", None, Some(5_000), None),
+            row("/u/b/target", Some("/u/b"), None, Some(2)),
+            row("/u/a/target", Some("/u/a"), Some(9_000), Some(1)),
+        ];
+        let paths = |rows: &[Row]| -> Vec<String> {
+            rows.iter().map(|r| r.path.display().to_string()).collect()
+        };
+
+        sort(&mut rows, ListSort::Size);
+        assert_eq!(paths(&rows), ["/u/a/target", "/u/.npm", "/u/b/target"]);
+        sort(&mut rows, ListSort::Repo);
+        assert_eq!(paths(&rows), ["/u/a/target", "/u/b/target", "/u/.npm"]);
+        sort(&mut rows, ListSort::Added);
+        assert_eq!(paths(&rows), ["/u/b/target", "/u/a/target", "/u/.npm"]);
+        sort(&mut rows, ListSort::Path);
+        assert_eq!(paths(&rows), ["/u/.npm", "/u/a/target", "/u/b/target"]);
+    }
+
+    #[test]
+    fn test_table_aligns_columns() {
+        let rows = [
+            row("/u/app/node_modules", Some("/u/app"), Some(1536), Some(4)),
+            row("/u/.npm", None, None, None),
+        ];
+        assert_eq!(
+            table(&rows, false),
+            [
+                "PATH          REPO    ORIGIN     SIZE  ADDED",
+                "node_modules  /u/app  repo    1.5 KiB  2026-03-04",
+                "/u/.npm       -       preset        -  -",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_redacted_paths_hide_their_directories() {
+        let dir = redact(Path::new("/Users/me/acme-secret/target"));
+        assert_eq!(dir.file_name().unwrap(), "target");
+        assert!(!dir.to_string_lossy().contains("acme"));
+        assert_eq!(
+            dir.parent(),
+            redact(Path::new("/Users/me/acme-secret/node_modules")).parent(),
+            "siblings share the hash"
+        );
+
+        let rows = [row(
+            "/Users/me/acme-secret/target",
+            Some("/Users/me/acme-secret"),
+            None,
+            None,
+        )];
+        let lines = table(&rows, true);
+        assert!(lines[1].starts_with("target  "), "{}", lines[1]);
+        assert!(lines.iter().all(|l| !l.contains("acme")));
+        // The repo shows as the directory part of the paths in it
+        let repo = redact_dir(Path::new("/Users/me/acme-secret"));
+        assert!(lines[1].contains(&format!("  {}  ", repo.display())));
+    }
+}
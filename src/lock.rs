@@ -0,0 +1,210 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, Local};
+use fd_lock::{RwLock as FdRwLock, RwLockWriteGuard};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{Seek, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+use tracing::{debug, warn};
+
+use crate::{
+    error::{ErrorKind, FileContext},
+    permissions,
+};
+
+/// Who holds the lock, written into the lockfile when it is acquired.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockInfo {
+    pub pid: u32,
+    pub argv: Vec<String>,
+    pub started_at: DateTime<FixedOffset>,
+}
+
+impl LockInfo {
+    /// Describe the current process.
+    pub fn current() -> Self {
+        LockInfo {
+            pid: std::process::id(),
+            argv: std::env::args().collect(),
+            started_at: Local::now().fixed_offset(),
+        }
+    }
+}
+
+impl std::fmt::Display for LockInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "pid {}, `{}`, since {}",
+            self.pid,
+            self.argv.join(" "),
+            self.started_at.format("%Y-%m-%d %H:%M:%S")
+        )
+    }
+}
+
+/// The advisory lock that keeps mutating commands from running concurrently.
+///
+/// Wraps an `flock(2)` on the lockfile: the OS releases it when the holder
+/// exits, even on `SIGKILL`, because `flock` locks belong to the open file
+/// description.  The holder's [`LockInfo`] is stored in the file so a blocked
+/// instance can say who it is waiting for.
+///
+/// A [`disabled`](Self::disabled) lock (`no_lock`) never touches the file.
+pub struct InstanceLock {
+    path: PathBuf,
+    lock: Option<FdRwLock<fs::File>>,
+}
+
+/// Proof of holding an [`InstanceLock`]; the lock is released on drop.
+pub struct LockGuard<'a> {
+    _guard: Option<RwLockWriteGuard<'a, fs::File>>,
+}
+
+impl InstanceLock {
+    /// Open (or create) the lockfile at `path` without locking it.
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(InstanceLock {
+            path: path.to_path_buf(),
+            lock: Some(FdRwLock::new(open_lock_file(path)?)),
+        })
+    }
+
+    /// A lock that is acquired without locking anything, for setups where
+    /// `flock` on the cache directory misbehaves.
+    pub fn disabled(path: &Path) -> Self {
+        InstanceLock {
+            path: path.to_path_buf(),
+            lock: None,
+        }
+    }
+
+    /// Take the lock and record the current process in the lockfile.
+    ///
+    /// Returns `None`, after logging who holds it, when another instance
+    /// does.  With `steal_stale`, a lock whose recorded owner is no longer
+    /// alive (e.g. the descriptor was inherited by a stray child) is taken
+    /// over by replacing the lockfile.  Keep the guard alive for the duration
+    /// of the exclusive section.
+    pub fn try_acquire(&mut self, steal_stale: bool) -> Result<Option<LockGuard<'_>>> {
+        let Some(lock) = &mut self.lock else {
+            return Ok(Some(LockGuard { _guard: None }));
+        };
+        if lock.try_write().is_err() {
+            let owner = read_lock_info(&self.path);
+            let dead_owner = owner.as_ref().filter(|o| !pid_alive(o.pid));
+            match dead_owner {
+                Some(owner) if steal_stale => {
+                    warn!(
+                        "Stealing lock {} from dead owner ({})",
+                        self.path.display(),
+                        owner
+                    );
+                    fs::remove_file(&self.path)
+                        .with_context(|| format!("removing lockfile: {}", self.path.display()))?;
+                    *lock = FdRwLock::new(open_lock_file(&self.path)?);
+                }
+                _ => {
+                    warn_held(owner.as_ref(), dead_owner.is_some());
+                    return Ok(None);
+                }
+            }
+        }
+
+        let Ok(mut guard) = lock.try_write() else {
+            warn_held(read_lock_info(&self.path).as_ref(), false);
+            return Ok(None);
+        };
+        let text = serde_json::to_string(&LockInfo::current()).context("serializing lock info")?;
+        let file: &mut fs::File = &mut guard;
+        file.set_len(0)
+            .and_then(|_| file.rewind())
+            .and_then(|_| file.write_all(text.as_bytes()))
+            .with_context(|| format!("writing lockfile: {}", self.path.display()))?;
+        Ok(Some(LockGuard {
+            _guard: Some(guard),
+        }))
+    }
+}
+
+/// Read the [`LockInfo`] stored in the lockfile at `path`, if any.
+///
+/// Lockfiles written before the metadata existed are empty and yield `None`.
+pub fn read_lock_info(path: &Path) -> Option<LockInfo> {
+    let text = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&text)
+        .map_err(|e| debug!("Unreadable lock info in {}: {}", path.display(), e))
+        .ok()
+}
+
+fn warn_held(owner: Option<&LockInfo>, dead: bool) {
+    match owner {
+        Some(owner) if dead => warn!(
+            "Another letitgo instance holds the lock ({owner}), but that process \
+             is gone. Skipping; use `run --steal-stale-lock` to take over."
+        ),
+        Some(owner) => warn!("Another letitgo instance is running ({owner}). Skipping."),
+        None => warn!("Another letitgo instance is running. Skipping."),
+    }
+}
+
+/// Open (or create) the lockfile, creating its directory (next to the cache).
+fn open_lock_file(lock_path: &Path) -> Result<fs::File> {
+    if let Some(parent) = lock_path.parent() {
+        permissions::create_private_dir(parent)
+            .with_context(|| FileContext::new(ErrorKind::Lock, parent, "creating lockfile dir"))?;
+    }
+    permissions::open_private(lock_path, false)
+        .with_context(|| FileContext::new(ErrorKind::Lock, lock_path, "opening lockfile"))
+}
+
+/// Whether process `pid` exists, via `kill -0`.  A process owned by another
+/// user counts as alive; when `kill` cannot be run we assume it is.
+pub fn pid_alive(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return true;
+    }
+    match Command::new("kill").args(["-0", &pid.to_string()]).output() {
+        Ok(out) => {
+            out.status.success() || String::from_utf8_lossy(&out.stderr).contains("not permitted")
+        }
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_lock_records_owner_and_steals_from_dead_pid() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("letitgo.lock");
+
+        let mut first = InstanceLock::open(&path).unwrap();
+        let guard = first.try_acquire(false).unwrap();
+        assert!(guard.is_some());
+        assert_eq!(read_lock_info(&path).unwrap().pid, std::process::id());
+
+        // A live owner is never stolen from
+        let mut second = InstanceLock::open(&path).unwrap();
+        assert!(second.try_acquire(true).unwrap().is_none());
+
+        // Pretend the holder was a process that has since exited
+        let mut child = Command::new("true").spawn().unwrap();
+        let dead_pid = child.id();
+        child.wait().unwrap();
+        let dead = LockInfo {
+            pid: dead_pid,
+            ..LockInfo::current()
+        };
+        fs::write(&path, serde_json::to_string(&dead).unwrap()).unwrap();
+        assert!(second.try_acquire(false).unwrap().is_none());
+        assert!(second.try_acquire(true).unwrap().is_some());
+        assert_eq!(read_lock_info(&path).unwrap().pid, std::process::id());
+    }
+}
@@ -1,25 +1,74 @@
 use anyhow::{Context, Result};
 use clap::Parser;
-use tracing::warn;
+use tracing::{Event, Subscriber, warn};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, format};
+use tracing_subscriber::registry::LookupSpan;
 
-use letitgo::cli::{Cli, Commands};
+use letitgo::cli::{
+    CacheCommand, Cli, Commands, HelperCommand, LignoreCommand, PolicyCommand, RunArgs,
+    ServiceCommand, TmCommand,
+};
 use letitgo::config::Config;
-use letitgo::{AppContext, cmd_clean, cmd_init, cmd_list, cmd_reset, cmd_run};
+use letitgo::error::ErrorReport;
+use letitgo::i18n;
+use letitgo::lignore::LignoreEdit;
+use letitgo::output::Output;
+use letitgo::{
+    AppContext, RunOptions, cmd_analyze_backup, cmd_apply, cmd_cache_gc, cmd_cache_list,
+    cmd_cache_restore, cmd_cache_snapshot, cmd_clean, cmd_diff, cmd_doctor, cmd_explain,
+    cmd_export, cmd_external, cmd_helper_install, cmd_helper_serve, cmd_helper_status,
+    cmd_helper_uninstall, cmd_init, cmd_lignore, cmd_lint, cmd_list, cmd_plan, cmd_policy_refresh,
+    cmd_policy_show, cmd_purge_backups, cmd_query, cmd_repos, cmd_reset, cmd_run_with, cmd_schema,
+    cmd_service_run, cmd_service_status, cmd_service_trigger, cmd_simulate, cmd_stats,
+    cmd_test_glob, cmd_tm_destinations, cmd_tm_thin, cmd_version, cmd_why_not, with_policy,
+};
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
     // Initialize tracing subscriber based on verbosity flags
-    init_tracing(cli.verbose, cli.quiet);
+    init_tracing(cli.verbose, cli.quiet, cli.plain);
+    let json_errors = cli.json_errors;
+    let result = run(cli);
+    if json_errors && let Err(e) = &result {
+        eprintln!("{}", serde_json::to_string(&ErrorReport::new(e))?);
+        std::process::exit(1);
+    }
+    result
+}
+
+/// Everything after argument parsing: load the config and dispatch.
+fn run(cli: Cli) -> Result<()> {
+    let output = Output::detect(cli.plain);
+
+    // Needs no config, so a broken config file cannot hide the version
+    if let Commands::Version(args) = &cli.command {
+        return cmd_version(&output, cli.verbose > 0, args.json);
+    }
+    if let Commands::Schema(args) = &cli.command {
+        return cmd_schema(args.name);
+    }
 
     // Build AppContext — use CLI-override config path if provided
     let mut ctx = AppContext::production();
+    ctx.output = output;
+    let root = cli
+        .root
+        .as_deref()
+        .map(|root| {
+            root.canonicalize()
+                .with_context(|| format!("resolving --root {}", root.display()))
+        })
+        .transpose()?;
+    if let Some(root) = &root {
+        ctx.rebase_under(root);
+    }
     if let Some(config_path) = &cli.config {
         ctx.config_path = config_path.clone();
     }
 
     // Load config (warn on first run if missing)
-    let (config, config_found) = Config::load(&ctx.config_path)
+    let (mut config, config_found) = Config::load(&ctx.config_path)
         .with_context(|| format!("loading config from {}", ctx.config_path.display()))?;
     if !config_found && !matches!(&cli.command, Commands::Init(_)) {
         warn!(
@@ -28,14 +77,171 @@ fn main() -> Result<()> {
         );
     }
 
+    if !matches!(&cli.command, Commands::Policy(_)) {
+        config = with_policy(&ctx, config)?;
+    }
+    config.root = root;
+
+    // A locale without a catalog silently stays English; a configured
+    // language does not
+    let language = config
+        .language
+        .clone()
+        .unwrap_or_else(i18n::language_from_env);
+    let locales = ctx.config_path.parent().map(|dir| dir.join("locales"));
+    let catalog = i18n::Catalog::load(&language, locales.as_deref());
+    if config.language.is_some() && language != "en" && !catalog.is_translated() {
+        warn!("No messages for language `{language}`; using English");
+    }
+    i18n::init(catalog);
+
+    // External subcommands bring their own backend, if any
+    if let Commands::External(args) = &cli.command {
+        let status = cmd_external(&ctx, &config, &cli.global_args(), args)?;
+        if !status.success() {
+            std::process::exit(status.code().unwrap_or(1));
+        }
+        return Ok(());
+    }
+
+    // `doctor` diagnoses the backend, so it must run when it cannot start
+    if !matches!(&cli.command, Commands::Doctor(_)) {
+        ctx.use_backend_from(&config)?;
+    }
+    config.no_lock |= cli.no_lock;
+    config.assume_yes |= cli.yes;
+    config.non_interactive |= cli.non_interactive;
+
     let dry_run = cli.dry_run;
 
     match cli.command {
-        Commands::Run(args) => cmd_run(&ctx, &config, &args.search_path, dry_run),
-        Commands::List(args) => cmd_list(&ctx, args.json, args.stale),
-        Commands::Reset(args) => cmd_reset(&ctx, &config, args.yes, dry_run),
-        Commands::Clean => cmd_clean(&ctx, &config, dry_run),
+        Commands::Run(args) => cmd_run_with(&ctx, &config, &run_options(&config, &args, dry_run)),
+        Commands::Plan(args) => cmd_plan(
+            &ctx,
+            &config,
+            &run_options(&config, &args.run, dry_run),
+            args.output.as_deref(),
+        ),
+        Commands::Apply(args) => {
+            cmd_apply(&ctx, &config, &args.plan, args.sha256.as_deref(), dry_run)
+        }
+        Commands::Service(ServiceCommand::Run(args)) => {
+            cmd_service_run(&ctx, &config, &run_options(&config, &args, dry_run))
+        }
+        Commands::Service(ServiceCommand::Status(args)) => cmd_service_status(&ctx, args.json),
+        Commands::Service(ServiceCommand::Trigger) => cmd_service_trigger(&ctx),
+        Commands::PrivilegedHelper(HelperCommand::Install(args)) => {
+            cmd_helper_install(&output, args.uid, dry_run)
+        }
+        Commands::PrivilegedHelper(HelperCommand::Uninstall) => cmd_helper_uninstall(dry_run),
+        Commands::PrivilegedHelper(HelperCommand::Status) => cmd_helper_status(&output),
+        Commands::PrivilegedHelper(HelperCommand::Serve(args)) => {
+            cmd_helper_serve(&config, &args.allow_uids)
+        }
+        Commands::Export(args) => cmd_export(
+            &ctx,
+            &config,
+            args.format,
+            args.output.as_deref(),
+            args.redact,
+        ),
+        Commands::List(args) => cmd_list(
+            &ctx,
+            args.json,
+            args.stale,
+            args.quarantined,
+            args.format,
+            args.sort,
+            args.redact,
+        ),
+        Commands::Query(args) => cmd_query(&ctx, &config, &args.expr, args.json),
+        Commands::Reset => cmd_reset(&ctx, &config, dry_run),
+        Commands::Clean(args) => cmd_clean(
+            &ctx,
+            &config,
+            args.interactive,
+            args.include_unmounted,
+            dry_run,
+        ),
         Commands::Init(args) => cmd_init(&ctx, args.force),
+        Commands::Explain(args) => cmd_explain(&ctx, &config, &args.path),
+        Commands::WhyNot(args) => cmd_why_not(&ctx, &config, &args.path),
+        Commands::Repos(args) => cmd_repos(&ctx, &config, args.path.as_deref(), args.json),
+        Commands::Lint(args) => cmd_lint(&output, &config, args.json),
+        Commands::Doctor(args) => cmd_doctor(&ctx, &config, args.json, args.fix_permissions),
+        Commands::TestGlob(args) => cmd_test_glob(
+            &ctx,
+            args.kind,
+            &args.pattern,
+            &args.paths,
+            args.base.as_deref(),
+        ),
+        Commands::Stats(args) => cmd_stats(&ctx, args.json, !args.no_backup),
+        Commands::AnalyzeBackup(args) => {
+            cmd_analyze_backup(&output, &config, &args.backups, args.top, args.json)
+        }
+        Commands::PurgeBackups(args) => cmd_purge_backups(&output, &config, &args.backups, dry_run),
+        Commands::Diff(args) => cmd_diff(&ctx, &config, args.fresh, args.json),
+        Commands::Simulate(args) => cmd_simulate(
+            &ctx,
+            &config,
+            args.alt_config.as_deref(),
+            &args.overrides,
+            args.json,
+        ),
+        Commands::Cache(CacheCommand::List(args)) => cmd_cache_list(&ctx, args.json),
+        Commands::Cache(CacheCommand::Snapshot) => cmd_cache_snapshot(&ctx, &config, dry_run),
+        Commands::Cache(CacheCommand::Restore(args)) => {
+            cmd_cache_restore(&ctx, &config, &args.snapshot, dry_run)
+        }
+        Commands::Cache(CacheCommand::Gc(args)) => {
+            cmd_cache_gc(&ctx, &config, args.retention, dry_run)
+        }
+        Commands::Policy(PolicyCommand::Show(args)) => cmd_policy_show(&ctx, &config, args.json),
+        Commands::Policy(PolicyCommand::Refresh) => cmd_policy_refresh(&ctx, &config),
+        Commands::Lignore(command) => {
+            let (edit, args) = match command {
+                LignoreCommand::Add(args) => (LignoreEdit::Add, args),
+                LignoreCommand::Negate(args) => (LignoreEdit::Negate, args),
+                LignoreCommand::Remove(args) => (LignoreEdit::Remove, args),
+            };
+            cmd_lignore(
+                &ctx,
+                &config,
+                edit,
+                &args.pattern,
+                args.repo.as_deref(),
+                dry_run,
+            )
+        }
+        Commands::Tm(TmCommand::ThinLocalSnapshots(args)) => cmd_tm_thin(
+            &output,
+            &args.mount_point,
+            args.purge_amount,
+            args.urgency,
+            args.json,
+            dry_run,
+        ),
+        Commands::Tm(TmCommand::ListDestinations(args)) => cmd_tm_destinations(&output, args.json),
+        Commands::Version(_) | Commands::Schema(_) => {
+            unreachable!("handled before loading the config")
+        }
+        Commands::External(_) => unreachable!("handled before starting the backend"),
+    }
+}
+
+/// The [`RunOptions`] for `run` and `service run`.
+fn run_options(config: &Config, args: &RunArgs, dry_run: bool) -> RunOptions {
+    RunOptions {
+        search_path_overrides: args.search_path.iter().map(|p| config.rooted(p)).collect(),
+        dry_run,
+        if_stale: args.if_stale,
+        fast: args.fast,
+        force: args.force,
+        yes_large: args.yes_large,
+        steal_stale_lock: args.steal_stale_lock,
+        strict_permissions: args.strict_permissions,
+        queue_if_locked: true,
     }
 }
 
@@ -51,7 +257,7 @@ fn main() -> Result<()> {
 /// | `-vv`          | `TRACE`         |
 ///
 /// The `RUST_LOG` environment variable takes precedence over all flags.
-fn init_tracing(verbose: u8, quiet: bool) {
+fn init_tracing(verbose: u8, quiet: bool, plain: bool) {
     use tracing_subscriber::{EnvFilter, fmt};
 
     let level = if quiet {
@@ -67,9 +273,34 @@ fn init_tracing(verbose: u8, quiet: bool) {
     let filter = EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| EnvFilter::new(format!("letitgo={level}")));
 
-    fmt::Subscriber::builder()
+    let builder = fmt::Subscriber::builder()
         .with_env_filter(filter)
         .with_writer(std::io::stderr)
-        .with_target(false)
-        .init();
+        .with_target(false);
+    if plain {
+        builder.with_ansi(false).event_format(PlainLogFormat).init();
+    } else {
+        builder.init();
+    }
+}
+
+/// Log lines under `--plain`: the level as the record token, then the
+/// message — no timestamp, padding or color.
+struct PlainLogFormat;
+
+impl<S, N> FormatEvent<S, N> for PlainLogFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: format::Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        write!(writer, "{} ", event.metadata().level())?;
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
 }
@@ -0,0 +1,164 @@
+use anyhow::{Context, Result};
+use globset::{Glob, GlobMatcher};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cache::{Cache, PathMeta},
+    config::{Config, ExclusionMode},
+    tmutil::ExclusionManager,
+};
+
+/// Picks the [`ExclusionMode`] of each path: the first of the config's
+/// `mode_rules` whose glob matches it, else `exclusion_mode`.
+#[derive(Debug, Clone)]
+pub struct ModeSelector {
+    rules: Vec<(GlobMatcher, ExclusionMode)>,
+    default: ExclusionMode,
+}
+
+impl ModeSelector {
+    pub fn new(config: &Config) -> Result<Self> {
+        let rules = config
+            .mode_rules
+            .iter()
+            .map(|rule| {
+                let glob = Glob::new(&rule.glob)
+                    .with_context(|| format!("invalid mode_rules glob: {}", rule.glob))?;
+                Ok((glob.compile_matcher(), rule.mode.clone()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(ModeSelector {
+            rules,
+            default: config.exclusion_mode.clone(),
+        })
+    }
+
+    pub fn mode_for(&self, path: &Path) -> &ExclusionMode {
+        self.rules
+            .iter()
+            .find(|(glob, _)| glob.is_match(path))
+            .map_or(&self.default, |(_, mode)| mode)
+    }
+
+    /// Record in `meta` the mode of each of `paths` that differs from the
+    /// default, and forget any other recorded mode (see [`Cache::mode_for`]).
+    pub fn record<'a>(
+        &self,
+        paths: impl IntoIterator<Item = &'a PathBuf>,
+        meta: &mut BTreeMap<PathBuf, PathMeta>,
+    ) {
+        for path_meta in meta.values_mut() {
+            path_meta.mode = None;
+        }
+        for path in paths {
+            let mode = self.mode_for(path);
+            if *mode != self.default {
+                meta.entry(path.clone()).or_default().mode = Some(mode.clone());
+            }
+        }
+        meta.retain(|_, path_meta| *path_meta != PathMeta::default());
+    }
+
+    /// The cached paths whose recorded mode is not the one picked now.
+    pub fn moved<'a>(&self, cache: &'a Cache) -> Vec<&'a Path> {
+        cache
+            .paths
+            .iter()
+            .map(PathBuf::as_path)
+            .filter(|p| cache.mode_for(p) != *self.mode_for(p))
+            .collect()
+    }
+}
+
+/// `paths` split by the mode `mode_of` gives them, sticky first; modes
+/// without paths are left out.
+pub fn by_mode<'a>(
+    paths: &[&'a Path],
+    mode_of: impl Fn(&Path) -> ExclusionMode,
+) -> Vec<(ExclusionMode, Vec<&'a Path>)> {
+    let (fixed, sticky): (Vec<&Path>, Vec<&Path>) =
+        paths.iter().partition(|p| mode_of(p).is_fixed_path());
+    [
+        (ExclusionMode::Sticky, sticky),
+        (ExclusionMode::FixedPath, fixed),
+    ]
+    .into_iter()
+    .filter(|(_, group)| !group.is_empty())
+    .collect()
+}
+
+/// Add exclusions for `paths`, one call per mode `mode_of` gives them.
+pub fn add_by_mode(
+    manager: &dyn ExclusionManager,
+    paths: &[&Path],
+    mode_of: impl Fn(&Path) -> ExclusionMode,
+) -> Result<()> {
+    for (mode, group) in by_mode(paths, mode_of) {
+        manager.add_exclusions(&group, mode.is_fixed_path())?;
+    }
+    Ok(())
+}
+
+/// Remove the exclusions of `paths`, one call per mode `mode_of` says they
+/// were added in.
+pub fn remove_by_mode(
+    manager: &dyn ExclusionManager,
+    paths: &[&Path],
+    mode_of: impl Fn(&Path) -> ExclusionMode,
+) -> Result<()> {
+    for (mode, group) in by_mode(paths, mode_of) {
+        manager.remove_exclusions(&group, mode.is_fixed_path())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ModeRule;
+
+    #[test]
+    fn test_first_matching_rule_picks_the_mode_and_is_recorded_sparsely() {
+        let config = Config {
+            mode_rules: vec![
+                ModeRule {
+                    glob: "**/target/doc".to_string(),
+                    mode: ExclusionMode::Sticky,
+                },
+                ModeRule {
+                    glob: "**/{target,node_modules}".to_string(),
+                    mode: ExclusionMode::FixedPath,
+                },
+            ],
+            ..Config::default()
+        };
+        let modes = ModeSelector::new(&config).unwrap();
+        let target = PathBuf::from("/code/app/target");
+        let doc = PathBuf::from("/code/app/target/doc");
+        let log = PathBuf::from("/code/app/debug.log");
+        assert_eq!(*modes.mode_for(&target), ExclusionMode::FixedPath);
+        assert_eq!(*modes.mode_for(&doc), ExclusionMode::Sticky);
+        assert_eq!(*modes.mode_for(&log), ExclusionMode::Sticky);
+
+        let mut cache = Cache::empty();
+        cache.paths = vec![target.clone(), doc.clone(), log.clone()];
+        assert_eq!(modes.moved(&cache), vec![target.as_path()]);
+        modes.record(&cache.paths, &mut cache.meta);
+        assert_eq!(cache.meta.keys().collect::<Vec<_>>(), vec![&target]);
+        assert!(modes.moved(&cache).is_empty());
+
+        let groups = by_mode(&[log.as_path(), target.as_path(), doc.as_path()], |p| {
+            cache.mode_for(p)
+        });
+        assert_eq!(
+            groups,
+            vec![
+                (ExclusionMode::Sticky, vec![log.as_path(), doc.as_path()]),
+                (ExclusionMode::FixedPath, vec![target.as_path()]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use owo_colors::OwoColorize;
+use std::{
+    fmt::Display,
+    io::{self, IsTerminal},
+};
+
+/// How commands print human-readable output on stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputStyle {
+    /// Bold headings and colored additions and removals (stdout is a
+    /// terminal).
+    Color,
+    /// The same layout without escape codes.
+    #[default]
+    NoColor,
+    /// `--plain`: no color, no symbols, one record per line, each starting
+    /// with its [`Record`] token — for screen readers and log scrapers.
+    Plain,
+}
+
+/// The type of one line of output, its first word in plain mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Record {
+    /// A title or count introducing the lines after it.
+    Heading,
+    /// One entry of a list under a heading, e.g. an excluded path.
+    Item,
+    /// One entry of a list with no heading, e.g. a cache snapshot.
+    Row,
+    /// A path or line that is added.
+    Added,
+    /// A path or line that is removed.
+    Removed,
+    /// A `label: value` pair.
+    Field,
+    /// An aside, such as an empty result or a hint.
+    Note,
+    /// The answer of a one-line command.
+    Result,
+}
+
+impl Record {
+    pub fn token(self) -> &'static str {
+        match self {
+            Record::Heading => "HEADING",
+            Record::Item => "ITEM",
+            Record::Row => "ROW",
+            Record::Added => "ADDED",
+            Record::Removed => "REMOVED",
+            Record::Field => "FIELD",
+            Record::Note => "NOTE",
+            Record::Result => "RESULT",
+        }
+    }
+}
+
+/// Prints the human-readable output of a command in its [`OutputStyle`].
+///
+/// Lines are written as the styled layout (indentation, `+`/`-` markers,
+/// color); in plain mode as `TOKEN text` with the layout dropped.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Output {
+    style: OutputStyle,
+}
+
+impl Output {
+    pub fn new(style: OutputStyle) -> Self {
+        Output { style }
+    }
+
+    /// Plain with `--plain`, else colored when stdout is a terminal.
+    pub fn detect(plain: bool) -> Self {
+        Output::new(if plain {
+            OutputStyle::Plain
+        } else if io::stdout().is_terminal() {
+            OutputStyle::Color
+        } else {
+            OutputStyle::NoColor
+        })
+    }
+
+    pub fn is_plain(&self) -> bool {
+        self.style == OutputStyle::Plain
+    }
+
+    /// `…` before a count of lines left out, or nothing in plain mode.
+    pub fn ellipsis(&self) -> &'static str {
+        if self.is_plain() { "" } else { "… " }
+    }
+
+    /// `→` between two names, or `to` in plain mode.
+    pub fn arrow(&self) -> &'static str {
+        if self.is_plain() { "to" } else { "→" }
+    }
+
+    /// The line `record` of `text`, as [`print`](Self::print) writes it.
+    pub fn render(&self, record: Record, text: impl Display) -> String {
+        let text = text.to_string();
+        match self.style {
+            // Leading spaces are layout; a newline in a path must not
+            // start a new record
+            OutputStyle::Plain => format!(
+                "{} {}",
+                record.token(),
+                text.trim_start().replace('\n', "\\n")
+            ),
+            OutputStyle::NoColor => styled_layout(record, &text),
+            OutputStyle::Color => {
+                let line = styled_layout(record, &text);
+                match record {
+                    Record::Heading => line.bold().to_string(),
+                    Record::Added => line.green().to_string(),
+                    Record::Removed => line.red().to_string(),
+                    Record::Note => line.dimmed().to_string(),
+                    _ => line,
+                }
+            }
+        }
+    }
+
+    /// Print one line of `record`.
+    pub fn print(&self, record: Record, text: impl Display) {
+        println!("{}", self.render(record, text));
+    }
+
+    /// A heading followed, outside plain mode, by a blank line.
+    pub fn heading(&self, text: impl Display) {
+        self.print(Record::Heading, text);
+        if !self.is_plain() {
+            println!();
+        }
+    }
+
+    /// A heading with no blank line after it.
+    pub fn title(&self, text: impl Display) {
+        self.print(Record::Heading, text);
+    }
+
+    pub fn item(&self, text: impl Display) {
+        self.print(Record::Item, text);
+    }
+
+    /// An item followed by a tag, e.g. ` [deleted]`, highlighted in color.
+    pub fn tagged_item(&self, text: impl Display, tag: &str) {
+        if self.style == OutputStyle::Color {
+            println!("  {text}{}", tag.yellow());
+        } else {
+            self.print(Record::Item, format!("{text}{tag}"));
+        }
+    }
+
+    pub fn row(&self, text: impl Display) {
+        self.print(Record::Row, text);
+    }
+
+    pub fn added(&self, text: impl Display) {
+        self.print(Record::Added, text);
+    }
+
+    pub fn removed(&self, text: impl Display) {
+        self.print(Record::Removed, text);
+    }
+
+    /// A field: `label` (padded as the styled layout wants it) then
+    /// `value`.  Plain mode drops the padding.
+    pub fn field(&self, label: &str, value: impl Display) {
+        match self.style {
+            OutputStyle::Plain => self.print(Record::Field, format!("{} {value}", label.trim())),
+            _ => println!("{label}{value}"),
+        }
+    }
+
+    /// Like [`field`](Self::field), with the value in bold on a terminal.
+    pub fn strong_field(&self, label: &str, value: impl Display) {
+        match self.style {
+            OutputStyle::Color => println!("{label}{}", value.to_string().bold()),
+            _ => self.field(label, value),
+        }
+    }
+
+    pub fn note(&self, text: impl Display) {
+        self.print(Record::Note, text);
+    }
+
+    pub fn result(&self, text: impl Display) {
+        self.print(Record::Result, text);
+    }
+}
+
+/// `text` in the layout of the styled modes.
+fn styled_layout(record: Record, text: &str) -> String {
+    match record {
+        Record::Item => format!("  {text}"),
+        Record::Added => format!("  + {text}"),
+        Record::Removed => format!("  - {text}"),
+        _ => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_lines_start_with_their_record_token() {
+        let plain = Output::new(OutputStyle::Plain);
+        let styled = Output::new(OutputStyle::NoColor);
+        let path = "/Users/me/code/app/target";
+
+        assert_eq!(styled.render(Record::Added, path), format!("  + {path}"));
+        assert_eq!(plain.render(Record::Added, path), format!("ADDED {path}"));
+        assert_eq!(
+            plain.render(Record::Item, "/tmp/odd\nname"),
+            "ITEM /tmp/odd\\nname"
+        );
+        let color = Output::new(OutputStyle::Color).render(Record::Removed, path);
+        assert!(color.contains("\u{1b}[") && color.contains(&format!("  - {path}")));
+    }
+}
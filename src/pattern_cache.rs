@@ -0,0 +1,309 @@
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::UNIX_EPOCH,
+};
+use tempfile::NamedTempFile;
+use tracing::{debug, warn};
+
+use crate::permissions;
+
+/// Version of the on-disk pattern cache; other versions are discarded.
+const PATTERN_CACHE_VERSION: u32 = 2;
+
+/// The pattern lines of one ignore file, as of its `mtime_ns` and `size`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    mtime_ns: u64,
+    size: u64,
+    /// SHA-256 of `patterns`, identifying identical ignore files.
+    hash: String,
+    patterns: Arc<Vec<String>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PatternCacheFile {
+    version: u32,
+    files: HashMap<PathBuf, Entry>,
+}
+
+/// Parsed pattern lists of ignore files, keyed by path and checked against
+/// the file's mtime and size, so that large generated `.gitignore` files are
+/// read and split once instead of on every matcher rebuild.
+///
+/// Compiled matchers are shared by content: the many repositories created
+/// from the same `.gitignore` template compile it once per run.
+///
+/// Shared by all repositories of a run; the pattern lists are persisted
+/// next to the cache between runs (see [`PatternCache::load`] /
+/// [`PatternCache::save`]), the matchers are not.
+///
+/// Every file read through the cache is noted with its content hash (see
+/// [`PatternCache::read_files`]), so a plan can be pinned to the ignore
+/// files it was computed from.
+#[derive(Debug, Default)]
+pub struct PatternCache {
+    files: Mutex<HashMap<PathBuf, Entry>>,
+    dirty: AtomicBool,
+    matchers: Mutex<HashMap<String, Arc<Gitignore>>>,
+    read: Mutex<BTreeMap<PathBuf, String>>,
+}
+
+/// The pattern cache file next to the exclusion cache at `cache_path`.
+pub fn pattern_cache_path(cache_path: &Path) -> PathBuf {
+    cache_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("gitignore-patterns.json")
+}
+
+impl PatternCache {
+    /// Load the cache persisted at `path`.  A missing, unreadable or
+    /// outdated file yields an empty cache: it is only an optimisation.
+    pub fn load(path: &Path) -> Self {
+        let file = fs::read_to_string(path)
+            .ok()
+            .and_then(|text| {
+                serde_json::from_str::<PatternCacheFile>(&text)
+                    .map_err(|e| debug!("Discarding pattern cache {}: {}", path.display(), e))
+                    .ok()
+            })
+            .filter(|file| file.version == PATTERN_CACHE_VERSION)
+            .unwrap_or_default();
+        PatternCache {
+            files: Mutex::new(file.files),
+            ..PatternCache::default()
+        }
+    }
+
+    /// Write the cache to `path` if anything changed since it was loaded,
+    /// dropping entries for files that no longer exist.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if !self.dirty.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let mut files = self.files.lock().expect("pattern cache poisoned").clone();
+        files.retain(|file, _| file.exists());
+        let text = serde_json::to_string(&PatternCacheFile {
+            version: PATTERN_CACHE_VERSION,
+            files,
+        })
+        .context("serializing pattern cache")?;
+
+        let parent = path.parent().unwrap_or(Path::new("."));
+        permissions::create_private_dir(parent)
+            .with_context(|| format!("creating cache dir: {}", parent.display()))?;
+        let mut tmp = NamedTempFile::new_in(parent)
+            .with_context(|| format!("creating temp file in {}", parent.display()))?;
+        std::io::Write::write_all(&mut tmp, text.as_bytes())
+            .with_context(|| format!("writing pattern cache temp file in {}", parent.display()))?;
+        tmp.persist(path)
+            .with_context(|| format!("persisting pattern cache to {}", path.display()))?;
+        self.dirty.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The pattern lines of the ignore file at `path` (comments and blank
+    /// lines removed), read again only when its mtime or size changed.
+    pub fn patterns(&self, path: &Path) -> Result<Arc<Vec<String>>> {
+        Ok(self.entry(path)?.patterns)
+    }
+
+    /// A matcher for the ignore file at `path`, shared with every file whose
+    /// patterns are identical.
+    ///
+    /// The matcher must be given paths relative to the file's directory; the
+    /// globs it returns may name another file with the same content as
+    /// their source.
+    pub fn matcher(&self, dir: &Path, path: &Path) -> Result<Arc<Gitignore>> {
+        let entry = self.entry(path)?;
+        if let Some(matcher) = self
+            .matchers
+            .lock()
+            .expect("pattern cache poisoned")
+            .get(&entry.hash)
+        {
+            return Ok(Arc::clone(matcher));
+        }
+
+        let mut builder = GitignoreBuilder::new(dir);
+        for line in entry.patterns.iter() {
+            if let Err(err) = builder.add_line(Some(path.to_path_buf()), line) {
+                warn!("Error reading {}: {}", path.display(), err);
+            }
+        }
+        let matcher = Arc::new(builder.build().context("building gitignore matcher")?);
+        Ok(Arc::clone(
+            self.matchers
+                .lock()
+                .expect("pattern cache poisoned")
+                .entry(entry.hash)
+                .or_insert(matcher),
+        ))
+    }
+
+    /// Note that the ignore file at `path`, with content `text`, was read
+    /// outside the cache (`.lignore` files are parsed by the resolver).
+    pub fn note_read(&self, path: &Path, text: &str) {
+        self.read
+            .lock()
+            .expect("pattern cache poisoned")
+            .insert(path.to_path_buf(), content_hash(&pattern_lines(text)));
+    }
+
+    /// Every ignore file read so far, with the hash of its pattern lines as
+    /// returned by [`file_hash`].
+    pub fn read_files(&self) -> BTreeMap<PathBuf, String> {
+        self.read.lock().expect("pattern cache poisoned").clone()
+    }
+
+    /// Number of distinct matchers compiled so far.
+    pub fn matcher_count(&self) -> usize {
+        self.matchers.lock().expect("pattern cache poisoned").len()
+    }
+
+    /// The cached entry for `path`, refreshed when the file changed.
+    fn entry(&self, path: &Path) -> Result<Entry> {
+        let entry = self.lookup(path)?;
+        self.read
+            .lock()
+            .expect("pattern cache poisoned")
+            .insert(path.to_path_buf(), entry.hash.clone());
+        Ok(entry)
+    }
+
+    fn lookup(&self, path: &Path) -> Result<Entry> {
+        let meta = fs::metadata(path).with_context(|| format!("reading {}", path.display()))?;
+        let mtime_ns = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| u64::try_from(d.as_nanos()).unwrap_or(u64::MAX));
+        let size = meta.len();
+
+        if let Some(entry) = self.files.lock().expect("pattern cache poisoned").get(path)
+            && entry.mtime_ns == mtime_ns
+            && entry.size == size
+        {
+            return Ok(entry.clone());
+        }
+
+        let text =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let patterns = pattern_lines(&text);
+        let entry = Entry {
+            mtime_ns,
+            size,
+            hash: content_hash(&patterns),
+            patterns: Arc::new(patterns),
+        };
+        self.files
+            .lock()
+            .expect("pattern cache poisoned")
+            .insert(path.to_path_buf(), entry.clone());
+        self.dirty.store(true, Ordering::Relaxed);
+        Ok(entry)
+    }
+}
+
+/// Hash of the pattern lines of the ignore file at `path`, read afresh
+/// (comments and blank lines do not change it).
+pub fn file_hash(path: &Path) -> Result<String> {
+    let text = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    Ok(content_hash(&pattern_lines(&text)))
+}
+
+/// The lines of an ignore file that can hold a pattern: not blank and not a
+/// `#` comment (an escaped `\#` is a pattern).  A leading BOM is dropped.
+fn pattern_lines(text: &str) -> Vec<String> {
+    text.trim_start_matches('\u{feff}')
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Hex SHA-256 of the pattern lines, one per line.
+fn content_hash(patterns: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    for line in patterns {
+        hasher.update(line.as_bytes());
+        hasher.update(b"\n");
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_pattern_cache_reuses_until_file_changes_and_persists() {
+        let tmp = tempdir().unwrap();
+        let gitignore = tmp.path().join(".gitignore");
+        fs::write(&gitignore, "\u{feff}# build output\ntarget/\n\n\\#notes\n").unwrap();
+        let cache_file = tmp.path().join("cache/gitignore-patterns.json");
+
+        let cache = PatternCache::load(&cache_file);
+        let first = cache.patterns(&gitignore).unwrap();
+        assert_eq!(*first, vec!["target/", "\\#notes"]);
+        assert!(Arc::ptr_eq(&first, &cache.patterns(&gitignore).unwrap()));
+        cache.save(&cache_file).unwrap();
+
+        // A new run reuses the persisted list without re-reading the file
+        let reloaded = PatternCache::load(&cache_file);
+        assert!(!reloaded.dirty.load(Ordering::Relaxed));
+        assert_eq!(*reloaded.patterns(&gitignore).unwrap(), *first);
+        assert!(!reloaded.dirty.load(Ordering::Relaxed));
+
+        // Any change in size invalidates the entry
+        fs::write(&gitignore, "target/\nnode_modules/\n").unwrap();
+        assert_eq!(
+            *reloaded.patterns(&gitignore).unwrap(),
+            vec!["target/", "node_modules/"]
+        );
+        assert!(reloaded.dirty.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_identical_ignore_files_share_one_matcher() {
+        let tmp = tempdir().unwrap();
+        for (repo, content) in [
+            ("a", "target/\n"),
+            ("b", "# same\ntarget/\n"),
+            ("c", "dist/\n"),
+        ] {
+            fs::create_dir_all(tmp.path().join(repo)).unwrap();
+            fs::write(tmp.path().join(repo).join(".gitignore"), content).unwrap();
+        }
+        let cache = PatternCache::default();
+        let matcher = |repo: &str| {
+            cache.matcher(
+                &tmp.path().join(repo),
+                &tmp.path().join(repo).join(".gitignore"),
+            )
+        };
+
+        let a = matcher("a").unwrap();
+        assert!(Arc::ptr_eq(&a, &matcher("b").unwrap()));
+        assert!(!Arc::ptr_eq(&a, &matcher("c").unwrap()));
+        assert_eq!(cache.matcher_count(), 2);
+        // Shared matchers work for any repo given repo-relative paths
+        assert!(a.matched(Path::new("target"), true).is_ignore());
+        assert!(!a.matched(Path::new("dist"), true).is_ignore());
+    }
+}
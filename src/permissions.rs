@@ -0,0 +1,169 @@
+use std::{
+    fs, io,
+    io::Write,
+    os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+/// Mode of every file letitgo writes: the cache lists the user's projects,
+/// so only its owner may read it.  Set explicitly, whatever the umask.
+pub const FILE_MODE: u32 = 0o600;
+/// Mode of every directory letitgo creates.
+pub const DIR_MODE: u32 = 0o700;
+
+/// Create `dir` with its missing parents, each one created [`DIR_MODE`].
+pub fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let missing: Vec<&Path> = dir
+        .ancestors()
+        .take_while(|d| !d.as_os_str().is_empty() && !d.exists())
+        .collect();
+    fs::create_dir_all(dir)?;
+    for created in missing {
+        fs::set_permissions(created, fs::Permissions::from_mode(DIR_MODE))?;
+    }
+    Ok(())
+}
+
+/// Open `path` for writing as [`FILE_MODE`], creating it if missing and
+/// restricting it if it exists.  With `truncate`, its contents go.
+pub fn open_private(path: &Path, truncate: bool) -> io::Result<fs::File> {
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(truncate)
+        .mode(FILE_MODE)
+        .open(path)?;
+    file.set_permissions(fs::Permissions::from_mode(FILE_MODE))?;
+    Ok(file)
+}
+
+/// Replace the contents of `path` with `contents`, as [`FILE_MODE`].
+pub fn write_private(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    open_private(path, true)?.write_all(contents.as_ref())
+}
+
+/// A file or directory of letitgo's that other users may read or write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loose {
+    pub path: PathBuf,
+    /// Its permission bits.
+    pub mode: u32,
+    pub dir: bool,
+}
+
+impl Loose {
+    /// The mode it should have.
+    pub fn wanted(&self) -> u32 {
+        if self.dir { DIR_MODE } else { FILE_MODE }
+    }
+
+    /// Take the group and other permissions away.
+    pub fn restrict(&self) -> io::Result<()> {
+        fs::set_permissions(&self.path, fs::Permissions::from_mode(self.wanted()))
+    }
+}
+
+/// The files letitgo keeps for the user: the config file, the directory
+/// of the cache with everything in it (snapshots, lockfile, policy, service
+/// state) and the cache key, if it is a file.  The key's directory may be
+/// one the user chose, so it is left alone.
+pub fn state_paths(config_path: &Path, cache_path: &Path, key_path: Option<&Path>) -> Vec<PathBuf> {
+    let mut paths = vec![config_path.to_path_buf()];
+    if let Some(dir) = cache_path.parent() {
+        paths.extend(
+            WalkDir::new(dir)
+                .sort_by_file_name()
+                .into_iter()
+                .flatten()
+                .map(|entry| entry.into_path()),
+        );
+    }
+    paths.extend(key_path.map(Path::to_path_buf));
+    paths
+}
+
+/// Those of `paths` owned by this user that its group or other users can
+/// access.  Missing paths and symlinks are skipped, as are files of other
+/// users — a config managed by an administrator may well be shared.
+pub fn loose(paths: &[PathBuf]) -> Vec<Loose> {
+    // SAFETY: getuid has no preconditions
+    let uid = unsafe { libc::getuid() };
+    paths
+        .iter()
+        .filter_map(|path| {
+            let meta = fs::symlink_metadata(path).ok()?;
+            let mode = meta.permissions().mode() & 0o777;
+            (meta.uid() == uid && !meta.file_type().is_symlink() && mode & 0o077 != 0).then(|| {
+                Loose {
+                    path: path.clone(),
+                    mode,
+                    dir: meta.is_dir(),
+                }
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn test_created_files_are_private_whatever_their_history() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("cache/letitgo");
+        create_private_dir(&dir).unwrap();
+        assert_eq!(mode(&dir), DIR_MODE);
+        assert_eq!(mode(dir.parent().unwrap()), DIR_MODE);
+
+        // A file that exists with a wider mode is restricted when written
+        let file = dir.join("policy.toml");
+        fs::write(&file, "old").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+        write_private(&file, "new").unwrap();
+        assert_eq!(mode(&file), FILE_MODE);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "new");
+    }
+
+    #[test]
+    fn test_loose_finds_and_restricts_open_state() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = tmp.path().join("config.toml");
+        let cache = tmp.path().join("cache/cache.json");
+        create_private_dir(&cache.parent().unwrap().join("snapshots")).unwrap();
+        write_private(&config, "").unwrap();
+        write_private(&cache, "{}").unwrap();
+        let snapshot = cache.parent().unwrap().join("snapshots/cache-1.json");
+        fs::write(&snapshot, "{}").unwrap();
+        fs::set_permissions(&snapshot, fs::Permissions::from_mode(0o644)).unwrap();
+        fs::set_permissions(cache.parent().unwrap(), fs::Permissions::from_mode(0o755)).unwrap();
+
+        let paths = state_paths(&config, &cache, Some(&tmp.path().join("no/key")));
+        let found = loose(&paths);
+        assert_eq!(
+            found,
+            [
+                Loose {
+                    path: cache.parent().unwrap().to_path_buf(),
+                    mode: 0o755,
+                    dir: true,
+                },
+                Loose {
+                    path: snapshot.clone(),
+                    mode: 0o644,
+                    dir: false,
+                },
+            ]
+        );
+        for l in &found {
+            l.restrict().unwrap();
+        }
+        assert!(loose(&paths).is_empty());
+        assert_eq!(mode(&snapshot), FILE_MODE);
+    }
+}
@@ -0,0 +1,150 @@
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, FixedOffset};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tempfile::NamedTempFile;
+
+use crate::{
+    cache::Cache,
+    config::{Config, ExclusionMode},
+    pattern_cache::file_hash,
+    policy::{pin_matches, sha256_hex},
+    provenance::PathRule,
+};
+
+/// Version of the [`Plan`] file format; `apply` refuses any other.
+pub const PLAN_VERSION: u32 = 2;
+
+/// The changes a `run` would make, computed without a lock or any change
+/// (`letitgo plan`) so they can be reviewed before `letitgo apply` makes
+/// them.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Plan {
+    pub plan_version: u32,
+    pub letitgo_version: String,
+    pub created_at: DateTime<FixedOffset>,
+    pub exclusion_mode: ExclusionMode,
+    /// `last_run` of the cache the plan was computed against; `apply`
+    /// rejects the plan once another run has changed the cache.
+    pub base_last_run: Option<DateTime<FixedOffset>>,
+    /// Paths to exclude.
+    pub add: Vec<PathBuf>,
+    /// Cached paths to exclude again because they lost their exclusion
+    /// (`reassert`).
+    #[serde(default)]
+    pub reassert: Vec<PathBuf>,
+    /// Paths to stop excluding.
+    pub remove: Vec<PathBuf>,
+    /// The rule behind each path to add or re-apply, where known, so that
+    /// the plan can be reviewed by rule.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rules: BTreeMap<PathBuf, PathRule>,
+    /// The cache written once the plan is applied.
+    pub cache: Cache,
+    /// Hash of the configuration the plan was computed with (see
+    /// [`config_hash`]).
+    pub config_sha256: String,
+    /// Every ignore file (`.gitignore`, exclude file, `.lignore`) read to
+    /// compute the plan, with the hash of its pattern lines.
+    pub rule_files: BTreeMap<PathBuf, String>,
+    /// Hash of the rest of the plan (see [`Plan::seal`]), so that an edited
+    /// plan file is rejected and a reviewed plan can be pinned by it
+    /// (`apply --sha256`).
+    pub plan_sha256: String,
+}
+
+impl Plan {
+    /// Whether applying the plan changes nothing.
+    pub fn is_empty(&self) -> bool {
+        self.add.is_empty() && self.reassert.is_empty() && self.remove.is_empty()
+    }
+
+    /// Hash of the plan without its `plan_sha256`.
+    pub fn content_hash(&self) -> String {
+        let unsealed = Plan {
+            plan_sha256: String::new(),
+            ..self.clone()
+        };
+        sha256_hex(
+            serde_json::to_string(&unsealed)
+                .expect("serializing plan")
+                .as_bytes(),
+        )
+    }
+
+    /// Set `plan_sha256`; done last, once nothing else changes.
+    pub fn seal(&mut self) {
+        self.plan_sha256 = self.content_hash();
+    }
+
+    /// Check that the plan still describes what a run would do with
+    /// `config`: it is unedited (and, with `expected`, the one reviewed),
+    /// the configuration is the same and no ignore file it read changed
+    /// since.  Ignore files created since are not noticed.
+    pub fn verify(&self, config: &Config, expected: Option<&str>) -> Result<()> {
+        let actual = self.content_hash();
+        if actual != self.plan_sha256 {
+            bail!("The plan was edited after it was made (its hash is {actual})");
+        }
+        if let Some(expected) = expected
+            && !pin_matches(expected, &actual)
+        {
+            bail!("The plan's hash is {actual}, not the expected {expected}");
+        }
+        if config_hash(config) != self.config_sha256 {
+            bail!("The configuration changed since the plan was made; run `letitgo plan` again");
+        }
+        for (path, hash) in &self.rule_files {
+            if file_hash(path).ok().as_ref() != Some(hash) {
+                bail!(
+                    "{} changed since the plan was made; run `letitgo plan` again",
+                    path.display()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Hash identifying `config`: any setting that can change the exclusion set
+/// changes it.
+pub fn config_hash(config: &Config) -> String {
+    sha256_hex(format!("{config:?}").as_bytes())
+}
+
+/// Write `plan` as pretty JSON to `path`, replacing it atomically.
+pub fn write_plan(path: &Path, plan: &Plan) -> Result<()> {
+    let parent = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(parent)
+        .with_context(|| format!("creating plan dir: {}", parent.display()))?;
+    let text = serde_json::to_string_pretty(plan).context("serializing plan")?;
+    let mut tmp = NamedTempFile::new_in(parent)
+        .with_context(|| format!("creating temp file in {}", parent.display()))?;
+    tmp.write_all(text.as_bytes())
+        .with_context(|| format!("writing plan temp file in {}", parent.display()))?;
+    tmp.persist(path)
+        .with_context(|| format!("persisting plan to {}", path.display()))?;
+    Ok(())
+}
+
+/// Read a plan written by [`write_plan`].
+pub fn read_plan(path: &Path) -> Result<Plan> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("reading plan {}", path.display()))?;
+    let plan: Plan =
+        serde_json::from_str(&text).with_context(|| format!("parsing plan {}", path.display()))?;
+    if plan.plan_version != PLAN_VERSION {
+        bail!(
+            "{} is a version {} plan; this letitgo applies version {PLAN_VERSION}",
+            path.display(),
+            plan.plan_version
+        );
+    }
+    Ok(plan)
+}
@@ -0,0 +1,255 @@
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, FixedOffset, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use tracing::{debug, info, warn};
+
+use crate::{config::Config, permissions};
+
+/// Config keys a policy fragment may set.  Each one can only keep paths in
+/// backups, so a policy never causes anything to be excluded.
+pub const POLICY_KEYS: &[&str] = &["whitelist", "ignored_paths", "protected_globs"];
+
+/// How long `curl` may take to fetch the policy.
+const FETCH_TIMEOUT_SECS: &str = "30";
+
+/// Where and when the cached policy was fetched, stored next to it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyMeta {
+    pub url: String,
+    /// Hex SHA-256 of the fetched fragment.
+    pub sha256: String,
+    pub fetched_at: DateTime<FixedOffset>,
+}
+
+/// A managed policy fragment and its [`PolicyMeta`].
+#[derive(Debug, Clone)]
+pub struct Policy {
+    pub meta: PolicyMeta,
+    /// The fragment as published.
+    pub text: String,
+}
+
+impl Policy {
+    /// The table to merge beneath the user's config: the fragment's
+    /// [`POLICY_KEYS`], each list following the built-in default so that a
+    /// policy extends the defaults instead of replacing them.
+    pub fn table(&self) -> Result<toml::Table> {
+        let fragment = parse_fragment(&self.text)?;
+        let defaults = Config::default();
+        let mut table = toml::Table::new();
+        for (key, value) in fragment {
+            let default = match key.as_str() {
+                "whitelist" => &defaults.whitelist,
+                "ignored_paths" => &defaults.ignored_paths,
+                _ => &defaults.protected_globs,
+            };
+            let mut list: Vec<toml::Value> = default.iter().cloned().map(Into::into).collect();
+            for v in value.as_array().into_iter().flatten() {
+                if !list.contains(v) {
+                    list.push(v.clone());
+                }
+            }
+            table.insert(key, toml::Value::Array(list));
+        }
+        Ok(table)
+    }
+}
+
+/// The cached fragment and its metadata, next to the cache at `cache_path`.
+pub fn policy_paths(cache_path: &Path) -> (PathBuf, PathBuf) {
+    let dir = cache_path.parent().unwrap_or(Path::new("."));
+    (dir.join("policy.toml"), dir.join("policy.json"))
+}
+
+/// Read the cached policy, if one was fetched.
+pub fn load_cached(cache_path: &Path) -> Result<Option<Policy>> {
+    let (text_path, meta_path) = policy_paths(cache_path);
+    if !text_path.exists() || !meta_path.exists() {
+        return Ok(None);
+    }
+    let meta_text = fs::read_to_string(&meta_path)
+        .with_context(|| format!("reading {}", meta_path.display()))?;
+    let meta: PolicyMeta = serde_json::from_str(&meta_text)
+        .with_context(|| format!("parsing {}", meta_path.display()))?;
+    let text = fs::read_to_string(&text_path)
+        .with_context(|| format!("reading {}", text_path.display()))?;
+    Ok(Some(Policy { meta, text }))
+}
+
+/// Fetch the policy at `url`, check it against `pin` and its syntax, and
+/// replace the cached copy with it.
+///
+/// On any failure the cached copy is left untouched.
+pub fn refresh(cache_path: &Path, url: &str, pin: Option<&str>) -> Result<Policy> {
+    let bytes = fetch(url)?;
+    let sha256 = sha256_hex(&bytes);
+    if let Some(pin) = pin.filter(|pin| !pin_matches(pin, &sha256)) {
+        bail!("policy from {url} has SHA-256 {sha256}, but policy_sha256 pins {pin}");
+    }
+    let text = String::from_utf8(bytes).with_context(|| format!("policy from {url}"))?;
+    parse_fragment(&text).with_context(|| format!("policy from {url}"))?;
+
+    let policy = Policy {
+        meta: PolicyMeta {
+            url: url.to_string(),
+            sha256,
+            fetched_at: Local::now().fixed_offset(),
+        },
+        text,
+    };
+    let (text_path, meta_path) = policy_paths(cache_path);
+    if let Some(dir) = text_path.parent() {
+        permissions::create_private_dir(dir)
+            .with_context(|| format!("creating {}", dir.display()))?;
+    }
+    permissions::write_private(&text_path, &policy.text)
+        .with_context(|| format!("writing {}", text_path.display()))?;
+    let meta = serde_json::to_string_pretty(&policy.meta).context("serializing policy meta")?;
+    permissions::write_private(&meta_path, meta)
+        .with_context(|| format!("writing {}", meta_path.display()))?;
+    Ok(policy)
+}
+
+/// The policy to apply for `config`, refreshing the cached copy when it is
+/// older than `policy_refresh_interval`, was fetched from another URL, or no
+/// longer matches `policy_sha256`.
+///
+/// Failures are logged rather than returned so that an unreachable policy
+/// server never stops a run: a cached copy from the same URL (and matching
+/// the pin) is used until a refresh succeeds.
+pub fn current_policy(config: &Config, cache_path: &Path) -> Option<Policy> {
+    let url = config.policy_url.as_deref()?;
+    let pin = config.policy_sha256.as_deref();
+    let cached = load_cached(cache_path)
+        .map_err(|e| warn!("Ignoring cached policy: {e:#}"))
+        .ok()
+        .flatten()
+        .filter(|p| p.meta.url == url)
+        .filter(|p| pin.is_none_or(|pin| pin_matches(pin, &p.meta.sha256)));
+
+    let fresh = cached.as_ref().is_some_and(|p| {
+        let age = Local::now().signed_duration_since(p.meta.fetched_at);
+        age.to_std()
+            .is_ok_and(|age| age < config.policy_refresh_interval)
+    });
+    if fresh {
+        debug!("Using cached policy from {url}");
+        return cached;
+    }
+    match refresh(cache_path, url, pin) {
+        Ok(policy) => {
+            info!("Fetched policy from {url}");
+            Some(policy)
+        }
+        Err(e) if cached.is_some() => {
+            warn!("Could not refresh the policy, using the cached copy: {e:#}");
+            cached
+        }
+        Err(e) => {
+            warn!("Could not fetch the policy; continuing without it: {e:#}");
+            None
+        }
+    }
+}
+
+/// Download `url` with `curl`, failing on HTTP errors.
+fn fetch(url: &str) -> Result<Vec<u8>> {
+    debug!("curl {url}");
+    let output = Command::new("curl")
+        .args(["-fsSL", "--max-time", FETCH_TIMEOUT_SECS, "--", url])
+        .output()
+        .context("spawning curl")?;
+    if !output.status.success() {
+        bail!(
+            "fetching {url} failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Whether the configured `pin` is the digest `sha256` (case-insensitive).
+pub fn pin_matches(pin: &str, sha256: &str) -> bool {
+    pin.trim().eq_ignore_ascii_case(sha256)
+}
+
+/// Lowercase hex SHA-256 of `bytes`.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Parse a policy fragment, keeping only [`POLICY_KEYS`] (other keys are
+/// logged and dropped).  Each kept key must be a list of strings.
+fn parse_fragment(text: &str) -> Result<toml::Table> {
+    let mut table: toml::Table = toml::from_str(text).context("parsing policy")?;
+    table.retain(|key, _| {
+        let allowed = POLICY_KEYS.contains(&key);
+        if !allowed {
+            warn!("Ignoring `{key}` in the policy: only {POLICY_KEYS:?} may be set");
+        }
+        allowed
+    });
+    for (key, value) in &table {
+        let strings = value
+            .as_array()
+            .is_some_and(|list| list.iter().all(|v| v.is_str()));
+        if !strings {
+            bail!("policy `{key}` must be a list of strings");
+        }
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_policy_refresh_pins_and_merges_beneath_config() {
+        let tmp = tempdir().unwrap();
+        let cache_path = tmp.path().join("cache/cache.json");
+        let published = tmp.path().join("policy.toml");
+        let fragment = "whitelist = [\"**/secrets/**\"]\nsearch_paths = [\"/\"]\n";
+        fs::write(&published, fragment).unwrap();
+        let url = format!("file://{}", published.display());
+
+        // A wrong pin is refused and nothing is cached
+        let err = refresh(&cache_path, &url, Some("00")).unwrap_err();
+        assert!(format!("{err:#}").contains("pins 00"), "{err:#}");
+        assert!(load_cached(&cache_path).unwrap().is_none());
+
+        let sha = sha256_hex(fragment.as_bytes());
+        let config = Config {
+            policy_url: Some(url.clone()),
+            policy_sha256: Some(sha.to_uppercase()),
+            ..Config::default()
+        };
+        let policy = current_policy(&config, &cache_path).unwrap();
+        assert_eq!(policy.meta.sha256, sha);
+        assert_eq!(load_cached(&cache_path).unwrap().unwrap().text, fragment);
+
+        // Fresh cached copies are used without fetching
+        fs::remove_file(&published).unwrap();
+        assert!(current_policy(&config, &cache_path).is_some());
+
+        // Only the allowed keys reach the config, beneath the user's values
+        let config_path = tmp.path().join("config.toml");
+        fs::write(&config_path, "whitelist = [\"**/keep.txt\"]\n").unwrap();
+        let (merged, _) = Config::load_over(&config_path, Some(policy.table().unwrap())).unwrap();
+        assert_eq!(merged.search_paths, Config::default().search_paths);
+        assert!(merged.whitelist.contains(&"**/.env".to_string()));
+        assert!(merged.whitelist.contains(&"**/secrets/**".to_string()));
+        assert_eq!(merged.whitelist.last().unwrap(), "**/keep.txt");
+    }
+}
@@ -0,0 +1,200 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cache::{Origin, PathMeta},
+    ignore_resolver::MatchSource,
+};
+
+/// The kind of rule that produced an exclusion.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuleKind {
+    /// A `.gitignore`, `info/exclude` or `core.excludesFile` pattern.
+    Gitignore,
+    /// A `.lignore` addition.
+    Lignore,
+    /// A config setting (`exclude_extensions`, `collapse_threshold`, …).
+    Config,
+    BareRepo,
+    SystemArtifact,
+    Preset,
+}
+
+/// The rule behind an exclusion, as planned changes are grouped by: the
+/// same pattern in two repositories is one rule.
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema,
+)]
+pub struct Rule {
+    pub kind: RuleKind,
+    /// The pattern as written (`target/`), the config setting
+    /// (`exclude_extensions = mp4`), or the preset's name.
+    pub pattern: String,
+}
+
+impl Rule {
+    /// The rule of an exclusion from outside working trees, per its
+    /// metadata; `None` for repo paths, whose rule the metadata lacks.
+    pub fn from_meta(meta: &PathMeta) -> Option<Self> {
+        let (kind, pattern) = match meta.origin {
+            Origin::Repo => return None,
+            Origin::BareRepo => (RuleKind::BareRepo, "bare_repo_excludes".to_string()),
+            Origin::SystemArtifact => (RuleKind::SystemArtifact, "system_artifacts".to_string()),
+            Origin::Preset => (
+                RuleKind::Preset,
+                meta.preset.map(|p| p.to_string()).unwrap_or_default(),
+            ),
+        };
+        Some(Rule { kind, pattern })
+    }
+}
+
+impl From<&MatchSource> for Rule {
+    fn from(source: &MatchSource) -> Self {
+        let kind = if source.is_lignore() {
+            RuleKind::Lignore
+        } else if source.is_config() {
+            RuleKind::Config
+        } else {
+            RuleKind::Gitignore
+        };
+        Rule {
+            kind,
+            pattern: source.pattern.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            RuleKind::Gitignore => write!(f, "`{}` patterns", self.pattern),
+            RuleKind::Lignore => write!(f, ".lignore `{}`", self.pattern),
+            RuleKind::Config => write!(f, "config `{}`", self.pattern),
+            RuleKind::BareRepo | RuleKind::SystemArtifact => write!(f, "{}", self.pattern),
+            RuleKind::Preset => write!(f, "preset `{}`", self.pattern),
+        }
+    }
+}
+
+/// The rule that produced an excluded path, and the working tree whose
+/// rules it is (none for bare repos, system artifacts and presets).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PathRule {
+    pub rule: Rule,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo: Option<PathBuf>,
+}
+
+/// Paths a change set adds because of one rule.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RuleGroup {
+    /// `None` for paths whose rule is not known.
+    #[serde(flatten)]
+    pub rule: Option<Rule>,
+    /// The number of repositories the paths are in.
+    pub repos: usize,
+    pub paths: Vec<PathBuf>,
+}
+
+impl fmt::Display for RuleGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} path(s)", self.paths.len())?;
+        match &self.rule {
+            Some(rule) => write!(f, " from {rule}")?,
+            None => write!(f, " from other rules")?,
+        }
+        if self.repos > 0 {
+            write!(f, " across {} repo(s)", self.repos)?;
+        }
+        Ok(())
+    }
+}
+
+/// Group `paths` by the rule `rules` records for each, largest group first
+/// (then by rule); paths without one form a last group.
+pub fn group_by_rule<'a>(
+    paths: impl IntoIterator<Item = &'a Path>,
+    rules: &BTreeMap<PathBuf, PathRule>,
+) -> Vec<RuleGroup> {
+    let mut groups: BTreeMap<Option<&Rule>, (BTreeSet<&Path>, Vec<PathBuf>)> = BTreeMap::new();
+    for path in paths {
+        let path_rule = rules.get(path);
+        let (repos, group) = groups.entry(path_rule.map(|r| &r.rule)).or_default();
+        if let Some(repo) = path_rule.and_then(|r| r.repo.as_deref()) {
+            repos.insert(repo);
+        }
+        group.push(path.to_path_buf());
+    }
+    let mut groups: Vec<RuleGroup> = groups
+        .into_iter()
+        .map(|(rule, (repos, mut paths))| {
+            paths.sort();
+            RuleGroup {
+                rule: rule.cloned(),
+                repos: repos.len(),
+                paths,
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        (a.rule.is_none(), std::cmp::Reverse(a.paths.len()), &a.rule).cmp(&(
+            b.rule.is_none(),
+            std::cmp::Reverse(b.paths.len()),
+            &b.rule,
+        ))
+    });
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths_group_by_pattern_across_repos_largest_first() {
+        let rule = |kind, pattern: &str| Rule {
+            kind,
+            pattern: pattern.to_string(),
+        };
+        let target = rule(RuleKind::Gitignore, "target/");
+        let mut rules = BTreeMap::new();
+        for repo in ["/code/a", "/code/b"] {
+            rules.insert(
+                Path::new(repo).join("target"),
+                PathRule {
+                    rule: target.clone(),
+                    repo: Some(PathBuf::from(repo)),
+                },
+            );
+        }
+        rules.insert(
+            PathBuf::from("/code/a/dist"),
+            PathRule {
+                rule: rule(RuleKind::Lignore, "dist/"),
+                repo: Some(PathBuf::from("/code/a")),
+            },
+        );
+        let mut paths: Vec<PathBuf> = rules.keys().cloned().collect();
+        paths.push(PathBuf::from("/code/c/out"));
+
+        let groups = group_by_rule(paths.iter().map(PathBuf::as_path), &rules);
+        let summary: Vec<String> = groups.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            summary,
+            [
+                "2 path(s) from `target/` patterns across 2 repo(s)",
+                "1 path(s) from .lignore `dist/` across 1 repo(s)",
+                "1 path(s) from other rules",
+            ]
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use chrono::{DateTime, FixedOffset, Local};
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::{info, warn};
+
+use crate::{
+    cache::PathFailures,
+    config::Config,
+    warnings::{Warning, WarningKind},
+};
+
+/// Paths that keep failing to be excluded (a FUSE mount that times out, a
+/// path `tmutil` always rejects) are quarantined after `quarantine_after`
+/// consecutive failures: runs skip them, except for one attempt every
+/// `quarantine_reprobe_interval`, until an attempt succeeds.
+#[derive(Debug, Clone)]
+pub struct Quarantine {
+    /// Failures that quarantine a path; `0` never does.
+    after: u32,
+    reprobe: Duration,
+    now: DateTime<FixedOffset>,
+    /// The failure records of the cache, updated by [`record`](Self::record).
+    pub failures: BTreeMap<PathBuf, PathFailures>,
+}
+
+impl Quarantine {
+    pub fn new(config: &Config, failures: BTreeMap<PathBuf, PathFailures>) -> Self {
+        Quarantine {
+            after: config.quarantine_after,
+            reprobe: config.quarantine_reprobe_interval,
+            now: Local::now().fixed_offset(),
+            failures,
+        }
+    }
+
+    /// Whether `path` is quarantined and not due for its re-probe.
+    pub fn holds(&self, path: &Path) -> bool {
+        self.failures.get(path).is_some_and(|f| {
+            f.quarantined_since.is_some()
+                && (self.now - f.last_attempt)
+                    .to_std()
+                    .is_ok_and(|age| age < self.reprobe)
+        })
+    }
+
+    /// `paths` split into those to attempt and those held back.
+    pub fn hold_back<'a>(&self, paths: &[&'a Path]) -> (Vec<&'a Path>, Vec<&'a Path>) {
+        paths.iter().partition(|p| !self.holds(p))
+    }
+
+    /// Count a failure for each of `attempted` that one of `warnings` was
+    /// about (it failed or timed out), quarantining the ones that reach the
+    /// limit, and forget the failures of the others.
+    pub fn record(&mut self, attempted: &[&Path], warnings: &[Warning]) {
+        let failed: HashSet<&Path> = warnings
+            .iter()
+            .filter(|w| matches!(w.kind, WarningKind::PathFailed | WarningKind::TimedOut))
+            .filter_map(|w| w.path.as_deref())
+            .collect();
+        for &path in attempted {
+            if !failed.contains(path) {
+                if let Some(f) = self.failures.remove(path)
+                    && f.quarantined_since.is_some()
+                {
+                    info!("Released from quarantine: {}", path.display());
+                }
+                continue;
+            }
+            let f = self
+                .failures
+                .entry(path.to_path_buf())
+                .or_insert(PathFailures {
+                    count: 0,
+                    last_attempt: self.now,
+                    quarantined_since: None,
+                });
+            f.count += 1;
+            f.last_attempt = self.now;
+            if self.after > 0 && f.count >= self.after && f.quarantined_since.is_none() {
+                f.quarantined_since = Some(self.now);
+                warn!(
+                    "Quarantined {} after {} consecutive failures; it is retried once \
+                     per quarantine_reprobe_interval",
+                    path.display(),
+                    f.count
+                );
+            }
+        }
+    }
+}
+
+/// The quarantined paths of a cache's `failures`, by path.
+pub fn quarantined(
+    failures: &BTreeMap<PathBuf, PathFailures>,
+) -> impl Iterator<Item = (&PathBuf, &PathFailures)> {
+    failures
+        .iter()
+        .filter(|(_, f)| f.quarantined_since.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths_are_quarantined_after_repeated_failures_and_reprobed() {
+        let config = Config {
+            quarantine_after: 2,
+            quarantine_reprobe_interval: Duration::from_secs(3600),
+            ..Config::default()
+        };
+        let (fuse, ok) = (Path::new("/mnt/fuse/target"), Path::new("/code/target"));
+        let failed = vec![Warning {
+            kind: WarningKind::TimedOut,
+            path: Some(fuse.to_path_buf()),
+            message: String::new(),
+        }];
+        let mut quarantine = Quarantine::new(&config, BTreeMap::new());
+        quarantine.record(&[fuse, ok], &failed);
+        assert!(!quarantine.holds(fuse));
+        quarantine.record(&[fuse, ok], &failed);
+        assert!(quarantine.holds(fuse));
+        assert_eq!(quarantine.hold_back(&[fuse, ok]), (vec![ok], vec![fuse]));
+        assert!(!quarantine.failures.contains_key(ok));
+
+        // Due for a re-probe once the interval has passed since the last try
+        quarantine.now += chrono::Duration::hours(2);
+        assert!(!quarantine.holds(fuse));
+        quarantine.record(&[fuse], &[]);
+        assert!(quarantine.failures.is_empty());
+    }
+}
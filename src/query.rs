@@ -0,0 +1,501 @@
+use anyhow::{Result, bail};
+use globset::{Glob, GlobMatcher};
+use std::{cell::OnceCell, fmt, path::Path};
+
+use crate::{
+    cache::{Origin, PathMeta},
+    clean,
+    config::{ExclusionMode, expand_tilde, parse_size},
+    size::disk_size,
+};
+
+/// A property of a cached path that a query can test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Field {
+    Path,
+    /// The known repository containing the path (empty outside any).
+    Repo,
+    Origin,
+    Preset,
+    Mode,
+    /// Size on disk in bytes, measured only when a query needs it.
+    Size,
+    Failures,
+    Stale,
+    Trashed,
+    Quarantined,
+    Collapsed,
+}
+
+/// What values a [`Field`] takes, and so which operators apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Text,
+    Number,
+    Flag,
+}
+
+impl Field {
+    pub const ALL: [Field; 11] = [
+        Field::Path,
+        Field::Repo,
+        Field::Origin,
+        Field::Preset,
+        Field::Mode,
+        Field::Size,
+        Field::Failures,
+        Field::Stale,
+        Field::Trashed,
+        Field::Quarantined,
+        Field::Collapsed,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Path => "path",
+            Field::Repo => "repo",
+            Field::Origin => "origin",
+            Field::Preset => "preset",
+            Field::Mode => "mode",
+            Field::Size => "size",
+            Field::Failures => "failures",
+            Field::Stale => "stale",
+            Field::Trashed => "trashed",
+            Field::Quarantined => "quarantined",
+            Field::Collapsed => "collapsed",
+        }
+    }
+
+    fn kind(self) -> Kind {
+        match self {
+            Field::Path | Field::Repo | Field::Origin | Field::Preset | Field::Mode => Kind::Text,
+            Field::Size | Field::Failures => Kind::Number,
+            Field::Stale | Field::Trashed | Field::Quarantined | Field::Collapsed => Kind::Flag,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Field> {
+        Field::ALL.into_iter().find(|f| f.name() == name)
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A cached path with the properties a query tests.
+pub struct Entry<'a> {
+    pub path: &'a Path,
+    pub repo: Option<&'a Path>,
+    pub meta: PathMeta,
+    pub mode: ExclusionMode,
+    pub failures: u32,
+    pub quarantined: bool,
+    pub collapsed: bool,
+    size: OnceCell<u64>,
+}
+
+impl<'a> Entry<'a> {
+    pub fn new(path: &'a Path, meta: PathMeta, mode: ExclusionMode) -> Self {
+        Entry {
+            path,
+            repo: None,
+            meta,
+            mode,
+            failures: 0,
+            quarantined: false,
+            collapsed: false,
+            size: OnceCell::new(),
+        }
+    }
+
+    /// The size on disk, measured on first use.
+    pub fn size(&self) -> u64 {
+        *self.size.get_or_init(|| disk_size(self.path))
+    }
+
+    /// The size on disk, if it was measured.
+    pub fn measured_size(&self) -> Option<u64> {
+        self.size.get().copied()
+    }
+
+    fn text(&self, field: Field) -> String {
+        match field {
+            Field::Path => self.path.display().to_string(),
+            Field::Repo => self
+                .repo
+                .map(|r| r.display().to_string())
+                .unwrap_or_default(),
+            Field::Origin => self.meta.origin.to_string(),
+            Field::Preset => self.meta.preset.map(|p| p.to_string()).unwrap_or_default(),
+            Field::Mode => self.mode.to_string(),
+            _ => unreachable!("{field} is not text"),
+        }
+    }
+
+    fn number(&self, field: Field) -> u64 {
+        match field {
+            Field::Size => self.size(),
+            Field::Failures => u64::from(self.failures),
+            _ => unreachable!("{field} is not a number"),
+        }
+    }
+
+    fn flag(&self, field: Field) -> bool {
+        match field {
+            Field::Stale => clean::is_stale(self.path),
+            Field::Trashed => clean::is_trashed(self.path),
+            Field::Quarantined => self.quarantined,
+            Field::Collapsed => self.collapsed,
+            _ => unreachable!("{field} is not a flag"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Glob,
+    NotGlob,
+}
+
+impl Op {
+    fn from_token(token: &str) -> Option<Op> {
+        Some(match token {
+            "==" | "=" => Op::Eq,
+            "!=" => Op::Ne,
+            "<" => Op::Lt,
+            "<=" => Op::Le,
+            ">" => Op::Gt,
+            ">=" => Op::Ge,
+            "~" => Op::Glob,
+            "!~" => Op::NotGlob,
+            _ => return None,
+        })
+    }
+
+    fn holds<T: PartialOrd>(self, a: T, b: T) -> bool {
+        match self {
+            Op::Eq => a == b,
+            Op::Ne => a != b,
+            Op::Lt => a < b,
+            Op::Le => a <= b,
+            Op::Gt => a > b,
+            Op::Ge => a >= b,
+            Op::Glob | Op::NotGlob => unreachable!("glob operators compare text"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Text(String),
+    Number(u64),
+    Flag(bool),
+    Glob(GlobMatcher),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    /// A flag field on its own: `stale`.
+    Is(Field),
+    Compare(Field, Op, Value),
+}
+
+impl Expr {
+    fn eval(&self, entry: &Entry) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(entry) && b.eval(entry),
+            Expr::Or(a, b) => a.eval(entry) || b.eval(entry),
+            Expr::Not(e) => !e.eval(entry),
+            Expr::Is(field) => entry.flag(*field),
+            Expr::Compare(field, op, value) => match value {
+                Value::Text(text) => op.holds(entry.text(*field).as_str(), text.as_str()),
+                Value::Number(n) => op.holds(entry.number(*field), *n),
+                Value::Flag(b) => op.holds(entry.flag(*field), *b),
+                Value::Glob(glob) => {
+                    let text = entry.text(*field);
+                    glob.is_match(text.as_str()) == (*op == Op::Glob)
+                }
+            },
+        }
+    }
+
+    fn fields(&self, out: &mut Vec<Field>) {
+        match self {
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                a.fields(out);
+                b.fields(out);
+            }
+            Expr::Not(e) => e.fields(out),
+            Expr::Is(field) | Expr::Compare(field, ..) => out.push(*field),
+        }
+    }
+}
+
+/// A filter over the cached paths, parsed from an expression such as
+/// `size > 1GB and repo ~ "work/*" and origin == repo`.
+///
+/// Comparisons are `FIELD OP VALUE`, combined with `and`, `or`, `not` and
+/// parentheses; a flag field alone (`stale`) tests it.  Text fields take
+/// `==`, `!=`, `~` and `!~` (glob match; a pattern not starting with `/`
+/// or `~` matches at any depth); numbers take `==` … `>=`, `size` with a
+/// unit (`500MB`, `1GiB`).
+#[derive(Debug, Clone)]
+pub struct Query {
+    expr: Expr,
+}
+
+impl Query {
+    pub fn parse(source: &str) -> Result<Query> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            bail!("unexpected `{token}` in query");
+        }
+        Ok(Query { expr })
+    }
+
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.expr.eval(entry)
+    }
+
+    /// Whether the query tests `field`.
+    pub fn uses(&self, field: Field) -> bool {
+        let mut fields = Vec::new();
+        self.expr.fields(&mut fields);
+        fields.contains(&field)
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' || c == ')' {
+            tokens.push(c.to_string());
+            chars.next();
+        } else if "=!<>~".contains(c) {
+            chars.next();
+            let mut op = c.to_string();
+            if let Some(&next) = chars.peek()
+                && "=~".contains(next)
+                && c != '~'
+            {
+                op.push(next);
+                chars.next();
+            }
+            tokens.push(op);
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::from('"');
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => text.extend(chars.next()),
+                    Some(c) => text.push(c),
+                    None => bail!("unterminated string in query"),
+                }
+            }
+            tokens.push(text);
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || "()=!<>~\"".contains(c) {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(word);
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn keyword(&mut self, word: &str) -> bool {
+        let found = self.peek().is_some_and(|t| t.eq_ignore_ascii_case(word));
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.not()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Expr> {
+        let Some(token) = self.next() else {
+            bail!("query ends where a comparison was expected");
+        };
+        if token == "(" {
+            let expr = self.or()?;
+            if self.next().as_deref() != Some(")") {
+                bail!("missing `)` in query");
+            }
+            return Ok(expr);
+        }
+        let Some(field) = Field::from_name(&token.to_ascii_lowercase()) else {
+            let names: Vec<&str> = Field::ALL.iter().map(|f| f.name()).collect();
+            bail!(
+                "unknown field `{token}` in query (fields: {})",
+                names.join(", ")
+            );
+        };
+        let Some(op) = self.peek().and_then(Op::from_token) else {
+            if field.kind() == Kind::Flag {
+                return Ok(Expr::Is(field));
+            }
+            bail!("expected an operator after `{field}`");
+        };
+        self.pos += 1;
+        let Some(raw) = self.next() else {
+            bail!("expected a value after `{field}`");
+        };
+        let quoted = raw.starts_with('"');
+        let text = raw.strip_prefix('"').unwrap_or(&raw).to_string();
+        let value = value(field, op, text, quoted)?;
+        Ok(Expr::Compare(field, op, value))
+    }
+}
+
+/// The value `text` compared with `field` by `op`, checked against the
+/// field's kind.
+fn value(field: Field, op: Op, text: String, quoted: bool) -> Result<Value> {
+    let glob = matches!(op, Op::Glob | Op::NotGlob);
+    match field.kind() {
+        Kind::Text if glob => {
+            let pattern = if text.starts_with('/') || text.starts_with('~') {
+                expand_tilde(&text).display().to_string()
+            } else {
+                format!("**/{text}")
+            };
+            match Glob::new(&pattern) {
+                Ok(g) => Ok(Value::Glob(g.compile_matcher())),
+                Err(e) => bail!("invalid pattern `{text}` for `{field}`: {e}"),
+            }
+        }
+        Kind::Text if matches!(op, Op::Eq | Op::Ne) => Ok(Value::Text(match field {
+            Field::Path | Field::Repo => expand_tilde(&text).display().to_string(),
+            // A repo's ignore rules are what most users call gitignore
+            Field::Origin if text == "gitignore" => Origin::Repo.to_string(),
+            _ => text,
+        })),
+        Kind::Text => bail!("`{field}` is text: compare it with ==, !=, ~ or !~"),
+        _ if glob => bail!("`{field}` is not text: `~` and `!~` do not apply"),
+        Kind::Number if quoted => bail!("`{field}` is a number, not a string"),
+        Kind::Number if field == Field::Size => parse_size(&text)
+            .map(Value::Number)
+            .map_err(|e| anyhow::anyhow!("{e} for `size`")),
+        Kind::Number => match text.parse() {
+            Ok(n) => Ok(Value::Number(n)),
+            Err(_) => bail!("`{field}` is a number, got `{text}`"),
+        },
+        Kind::Flag if matches!(op, Op::Eq | Op::Ne) => match text.as_str() {
+            "true" => Ok(Value::Flag(true)),
+            "false" => Ok(Value::Flag(false)),
+            _ => bail!("`{field}` is true or false, got `{text}`"),
+        },
+        Kind::Flag => bail!("`{field}` is true or false: compare it with == or !="),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::artifacts::Preset;
+    use std::fs;
+
+    #[test]
+    fn test_query_combines_size_glob_and_origin_tests() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work = tmp.path().join("work/app");
+        let target = work.join("target");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("big.bin"), vec![1u8; 64 * 1024]).unwrap();
+        let npm = tmp.path().join(".npm");
+
+        let mut build = Entry::new(&target, PathMeta::default(), ExclusionMode::Sticky);
+        build.repo = Some(&work);
+        let cache = Entry::new(&npm, PathMeta::preset(Preset::Npm), ExclusionMode::Sticky);
+
+        let query =
+            Query::parse(r#"origin == gitignore and repo ~ "work/*" and size > 10KB"#).unwrap();
+        assert!(query.uses(Field::Size) && !query.uses(Field::Stale));
+        assert!(query.matches(&build));
+        assert!(build.measured_size().is_some());
+        // `and` stops at the first failing test: no size is measured
+        assert!(!query.matches(&cache));
+        assert_eq!(cache.measured_size(), None);
+
+        let query = Query::parse("(preset == npm or size >= 1MB) and not stale").unwrap();
+        assert!(!query.matches(&build));
+        assert!(!query.matches(&cache), "the npm cache does not exist");
+        assert!(
+            Query::parse("stale and mode != fixed-path")
+                .unwrap()
+                .matches(&cache)
+        );
+    }
+
+    #[test]
+    fn test_query_errors_name_the_problem() {
+        let error = |source: &str| Query::parse(source).unwrap_err().to_string();
+        assert!(error("szie > 1GB").contains("unknown field `szie`"));
+        assert!(error("size ~ 1GB").contains("`~` and `!~` do not apply"));
+        assert!(error("size > lots").contains("invalid size"));
+        assert!(error("origin > repo").contains("compare it with =="));
+        assert!(error("(stale").contains("missing `)`"));
+        assert!(error("stale stale").contains("unexpected `stale`"));
+        assert!(error(r#"path == "open"#).contains("unterminated"));
+    }
+}
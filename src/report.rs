@@ -0,0 +1,140 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, Local};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tempfile::NamedTempFile;
+
+use crate::{list::PathEntry, warnings::Warning};
+
+/// Version of the [`RunReport`] format, bumped on incompatible changes.
+pub const REPORT_VERSION: u32 = 1;
+
+/// [`RunReport::skip_reason`] of a run that found another instance holding
+/// the lock.
+pub const SKIP_LOCK_HELD: &str = "another instance holds the lock";
+
+/// How a run ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunOutcome {
+    /// The exclusions were brought up to date.
+    Ok,
+    /// Nothing was done (see [`RunReport::skip_reason`]).
+    Skipped,
+    /// The run stopped with an error.
+    Failed,
+}
+
+/// Machine-readable summary of a `run`, written to `report_path` for fleet
+/// management tools.  It is only ever written locally.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct RunReport {
+    pub report_version: u32,
+    pub letitgo_version: &'static str,
+    pub started_at: DateTime<FixedOffset>,
+    pub finished_at: DateTime<FixedOffset>,
+    pub duration_ms: u64,
+    /// Time spent discovering repos and resolving the exclusion set.
+    pub resolve_ms: u64,
+    /// Time spent applying the changes and writing the cache.
+    pub apply_ms: u64,
+    pub outcome: RunOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip_reason: Option<String>,
+    pub exclusion_mode: String,
+    /// Repositories resolved (working trees, not bare repos).
+    pub repos: usize,
+    /// Repositories not resolved because a path the run excludes holds them
+    /// whole (a preset location, or with `skip_covered_repos` a directory
+    /// an enclosing repo ignores).
+    pub covered_repos: Vec<PathBuf>,
+    /// Paths excluded after the run.
+    pub excluded: usize,
+    pub added: usize,
+    pub removed: usize,
+    /// The paths this run added, described as `list --json` does.
+    pub added_paths: Vec<PathEntry>,
+    /// Paths not added because they are quarantined.
+    pub quarantined: usize,
+    /// Directories discovery could not read.
+    pub permission_denied: Vec<PathBuf>,
+    /// Errors that were logged and skipped (e.g. one unreadable repo), and
+    /// the error that ended a failed run, last.
+    pub errors: Vec<String>,
+    /// Problems the run worked around (see [`Warning`]).
+    pub warnings: Vec<Warning>,
+    #[serde(skip)]
+    start: Option<Instant>,
+}
+
+impl RunReport {
+    /// Start the report of a run beginning now.
+    pub fn start(exclusion_mode: impl ToString) -> Self {
+        let now = Local::now().fixed_offset();
+        RunReport {
+            report_version: REPORT_VERSION,
+            letitgo_version: env!("CARGO_PKG_VERSION"),
+            started_at: now,
+            finished_at: now,
+            duration_ms: 0,
+            resolve_ms: 0,
+            apply_ms: 0,
+            outcome: RunOutcome::Ok,
+            skip_reason: None,
+            exclusion_mode: exclusion_mode.to_string(),
+            repos: 0,
+            covered_repos: Vec::new(),
+            excluded: 0,
+            added: 0,
+            removed: 0,
+            added_paths: Vec::new(),
+            quarantined: 0,
+            permission_denied: Vec::new(),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            start: Some(Instant::now()),
+        }
+    }
+
+    /// Record that the run did nothing, and why.
+    pub fn skip(&mut self, reason: impl Into<String>) {
+        self.outcome = RunOutcome::Skipped;
+        self.skip_reason = Some(reason.into());
+    }
+
+    /// Stamp the end time and record `result`'s error, if any.
+    pub fn finish<T>(&mut self, result: &Result<T>) {
+        self.finished_at = Local::now().fixed_offset();
+        self.duration_ms = self.start.map_or(0, |s| millis(s.elapsed()));
+        if let Err(e) = result {
+            self.outcome = RunOutcome::Failed;
+            self.errors.push(format!("{e:#}"));
+        }
+    }
+}
+
+/// Whole milliseconds in `d`.
+pub fn millis(d: Duration) -> u64 {
+    d.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+/// Write `report` as JSON to `path`, replacing it atomically so a watcher
+/// never reads a partial file.
+pub fn write_report(path: &Path, report: &RunReport) -> Result<()> {
+    let parent = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(parent)
+        .with_context(|| format!("creating report dir: {}", parent.display()))?;
+    let text = serde_json::to_string_pretty(report).context("serializing run report")?;
+    let mut tmp = NamedTempFile::new_in(parent)
+        .with_context(|| format!("creating temp file in {}", parent.display()))?;
+    std::io::Write::write_all(&mut tmp, text.as_bytes())
+        .with_context(|| format!("writing report temp file in {}", parent.display()))?;
+    tmp.persist(path)
+        .with_context(|| format!("persisting report to {}", path.display()))?;
+    Ok(())
+}
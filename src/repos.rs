@@ -0,0 +1,384 @@
+use chrono::{DateTime, FixedOffset};
+use path_clean::PathClean;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cache::{Cache, KnownRepos},
+    config::Config,
+    git::{self, GitLayout},
+    ignore_resolver::keep_marker,
+    scanner::is_bare_repo,
+};
+
+/// Version of the `repos --json` format.
+pub const REPOS_SCHEMA_VERSION: u32 = 1;
+
+/// What a known repository is, from its Git directory layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RepoKind {
+    /// A working tree whose `.git` is its repository.
+    Main,
+    /// A working tree added with `git worktree add`.
+    Worktree,
+    /// A submodule checkout.
+    Submodule,
+    /// A bare repository (`detect_bare_repos`).
+    Bare,
+    /// No longer a repository on disk; `clean` forgets it.
+    Missing,
+}
+
+impl fmt::Display for RepoKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepoKind::Main => write!(f, "main"),
+            RepoKind::Worktree => write!(f, "worktree"),
+            RepoKind::Submodule => write!(f, "submodule"),
+            RepoKind::Bare => write!(f, "bare"),
+            RepoKind::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+/// A working tree added to a repository, as the repository records it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LinkedWorktree {
+    pub path: PathBuf,
+    /// Whether discovery found it; a worktree outside the search paths is
+    /// never resolved.
+    pub discovered: bool,
+}
+
+/// A repository of the persisted repo index, as `letitgo repos` lists it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RepoEntry {
+    pub path: PathBuf,
+    pub kind: RepoKind,
+    /// For a worktree, the working tree (or bare repository) it was added
+    /// to; for a submodule, the working tree of its superproject.
+    pub parent: Option<PathBuf>,
+    /// The working trees added to this repository with `git worktree add`.
+    pub worktrees: Vec<LinkedWorktree>,
+    pub sparse: bool,
+    /// The marker file keeping the whole repo in backups.
+    pub keep_marker: Option<PathBuf>,
+    /// When a run last resolved the repo without an error.
+    pub last_resolved: Option<DateTime<FixedOffset>>,
+    /// Cached exclusions inside the repo, not counting nested known repos.
+    pub exclusions: usize,
+}
+
+/// The output of `repos --json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReposOutput {
+    /// Version of this format, bumped when a field changes meaning or goes
+    /// away.
+    pub schema_version: u32,
+    /// When the configured search paths were last walked in full.
+    pub discovered_at: Option<DateTime<FixedOffset>>,
+    pub count: usize,
+    pub repos: Vec<RepoEntry>,
+    /// With a path given and no known repository containing it, why
+    /// discovery has not found one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_found: Option<NotFound>,
+}
+
+/// Why no known repository contains a path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum NotFound {
+    /// Neither a working tree nor a bare repository contains the path.
+    NoRepo,
+    /// The repository is not below any configured search path.
+    OutsideSearchPaths { repo: PathBuf },
+    /// The repository is below an `ignored_paths` entry, which discovery
+    /// does not enter.
+    IgnoredPath { repo: PathBuf, ignored: PathBuf },
+    /// A bare repository, and `detect_bare_repos` is off.
+    BareNotDetected { repo: PathBuf },
+    /// Discovery would find the repository, but has not walked the search
+    /// paths since it appeared (or ever, with no `discovered_at`).
+    NotYetDiscovered {
+        repo: PathBuf,
+        discovered_at: Option<DateTime<FixedOffset>>,
+    },
+}
+
+/// The entries of every repository in `cache`'s repo index, working trees
+/// then bare repositories, each sorted by path.
+pub fn entries(cache: &Cache) -> Vec<RepoEntry> {
+    let Some(known) = &cache.known_repos else {
+        return Vec::new();
+    };
+    let mut exclusions: HashMap<&Path, usize> = HashMap::new();
+    for path in &cache.paths {
+        if let Some(repo) = cache.repo_of(path) {
+            *exclusions.entry(repo).or_default() += 1;
+        }
+    }
+    let discovered: HashSet<PathBuf> = known
+        .repos
+        .iter()
+        .chain(&known.bare_repos)
+        .map(|repo| canonical(repo))
+        .collect();
+
+    let mut repos: Vec<&PathBuf> = known.repos.iter().collect();
+    repos.sort();
+    let mut bare: Vec<&PathBuf> = known.bare_repos.iter().collect();
+    bare.sort();
+    repos
+        .into_iter()
+        .map(|repo| (repo, false))
+        .chain(bare.into_iter().map(|repo| (repo, true)))
+        .map(|(repo, bare)| {
+            let mut entry = entry(repo, bare, known, &discovered);
+            entry.exclusions = exclusions.get(repo.as_path()).copied().unwrap_or(0);
+            entry
+        })
+        .collect()
+}
+
+/// The entry of the known repository containing `path`, or why there is
+/// none.  A repository nested in a known one but not known itself is not
+/// found, as discovery would have to find it.
+pub fn lookup(path: &Path, config: &Config, cache: &Cache) -> Result<RepoEntry, NotFound> {
+    let path = canonical(path);
+    let working_tree = git::find_repo(&path);
+    let bare = path.ancestors().find(|dir| is_bare_repo(dir));
+    let repo = match (working_tree, bare) {
+        (Some(tree), Some(bare)) if bare.starts_with(&tree) => bare.to_path_buf(),
+        (Some(tree), _) => tree,
+        (None, Some(bare)) => bare.to_path_buf(),
+        (None, None) => return Err(NotFound::NoRepo),
+    };
+    let is_bare = is_bare_repo(&repo);
+
+    if let Some(entry) = entries(cache)
+        .into_iter()
+        .find(|entry| canonical(&entry.path) == repo)
+    {
+        return Ok(entry);
+    }
+    if !config
+        .resolved_search_paths()
+        .iter()
+        .any(|sp| repo.starts_with(canonical(sp)))
+    {
+        return Err(NotFound::OutsideSearchPaths { repo });
+    }
+    if let Some(ignored) = config
+        .resolved_ignored_paths()
+        .into_iter()
+        .find(|ig| repo.starts_with(canonical(ig)))
+    {
+        return Err(NotFound::IgnoredPath { repo, ignored });
+    }
+    if is_bare && !config.detect_bare_repos {
+        return Err(NotFound::BareNotDetected { repo });
+    }
+    Err(NotFound::NotYetDiscovered {
+        repo,
+        discovered_at: cache.known_repos.as_ref().and_then(|k| k.discovered_at),
+    })
+}
+
+/// The entry of `repo` without its exclusion count.
+fn entry(repo: &Path, bare: bool, known: &KnownRepos, discovered: &HashSet<PathBuf>) -> RepoEntry {
+    let layout = if bare {
+        is_bare_repo(repo).then(|| GitLayout {
+            git_dir: repo.to_path_buf(),
+            common_dir: repo.to_path_buf(),
+        })
+    } else {
+        git::resolve_layout(repo)
+    };
+    let (kind, parent) = match &layout {
+        None => (RepoKind::Missing, None),
+        Some(_) if bare => (RepoKind::Bare, None),
+        Some(layout) if layout.is_linked_worktree() => {
+            // A `.git` common dir belongs to a working tree; any other is bare
+            let main = match layout.common_dir.file_name() {
+                Some(name) if name == ".git" => layout.common_dir.parent(),
+                _ => Some(layout.common_dir.as_path()),
+            };
+            (RepoKind::Worktree, main.map(Path::to_path_buf))
+        }
+        Some(layout) if layout.is_submodule() => {
+            (RepoKind::Submodule, repo.parent().and_then(git::find_repo))
+        }
+        Some(_) => (RepoKind::Main, None),
+    };
+    let worktrees = match &layout {
+        Some(layout) if !layout.is_linked_worktree() => layout
+            .linked_worktrees()
+            .into_iter()
+            .map(|path| LinkedWorktree {
+                discovered: discovered.contains(&path),
+                path,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    RepoEntry {
+        path: repo.to_path_buf(),
+        kind,
+        parent,
+        worktrees,
+        sparse: known.sparse_repos.iter().any(|r| r == repo),
+        keep_marker: if bare { None } else { keep_marker(repo) },
+        last_resolved: known.resolved_at.get(repo).copied(),
+        exclusions: 0,
+    }
+}
+
+/// `path` canonicalized if it exists, else made absolute and cleaned, so
+/// that two spellings of a repository compare equal.
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| std::path::absolute(path).unwrap_or_default().clean())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git_dir(dir: &Path) {
+        for sub in ["objects", "refs"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        fs::write(dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+    }
+
+    /// `dir` as a working tree whose `.git` file points at `git_dir`.
+    fn git_file(dir: &Path, git_dir: &Path) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join(".git"), format!("gitdir: {}\n", git_dir.display())).unwrap();
+    }
+
+    /// Register `worktree` as the linked worktree `name` of `common_dir`.
+    fn add_worktree(common_dir: &Path, name: &str, worktree: &Path) {
+        let admin = common_dir.join("worktrees").join(name);
+        fs::create_dir_all(&admin).unwrap();
+        fs::write(admin.join("commondir"), "../..\n").unwrap();
+        fs::write(admin.join("HEAD"), "ref: refs/heads/feature\n").unwrap();
+        fs::write(
+            admin.join("gitdir"),
+            format!("{}\n", worktree.join(".git").display()),
+        )
+        .unwrap();
+        git_file(worktree, &admin);
+    }
+
+    #[test]
+    fn test_entries_classify_worktrees_submodules_and_bare_repos() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(tmp.path()).unwrap();
+        // app with a submodule, a discovered worktree and one elsewhere
+        let app = root.join("code/app");
+        git_dir(&app.join(".git"));
+        git_dir(&app.join(".git/modules/vendor/lib"));
+        git_file(
+            &app.join("vendor/lib"),
+            &app.join(".git/modules/vendor/lib"),
+        );
+        add_worktree(&app.join(".git"), "feature", &root.join("code/app-feature"));
+        add_worktree(&app.join(".git"), "hotfix", &root.join("elsewhere/hotfix"));
+        // A bare repo whose worktrees live next to it
+        let bare = root.join("code/tool.git");
+        git_dir(&bare);
+        add_worktree(&bare, "main", &root.join("code/tool"));
+        fs::write(root.join("code/tool/.letitgo-keep"), "").unwrap();
+
+        let mut cache = Cache::empty();
+        let now = chrono::Local::now().fixed_offset();
+        cache.known_repos = Some(KnownRepos {
+            discovered_at: Some(now),
+            repos: [
+                "code/app",
+                "code/app/vendor/lib",
+                "code/app-feature",
+                "code/tool",
+                "code/gone",
+            ]
+            .map(|r| root.join(r))
+            .to_vec(),
+            bare_repos: vec![bare.clone()],
+            sparse_repos: Vec::new(),
+            resolved_at: [(app.clone(), now)].into(),
+        });
+        cache.paths = [
+            "code/app/target",
+            "code/app/vendor/lib/build",
+            "code/app/dist",
+        ]
+        .map(|p| root.join(p))
+        .to_vec();
+
+        let entries = entries(&cache);
+        let summary: Vec<(String, RepoKind, Option<PathBuf>, usize)> = entries
+            .iter()
+            .map(|e| {
+                (
+                    e.path.strip_prefix(&root).unwrap().display().to_string(),
+                    e.kind,
+                    e.parent.clone(),
+                    e.exclusions,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("code/app".into(), RepoKind::Main, None, 2),
+                (
+                    "code/app/vendor/lib".into(),
+                    RepoKind::Submodule,
+                    Some(app.clone()),
+                    1
+                ),
+                (
+                    "code/app-feature".into(),
+                    RepoKind::Worktree,
+                    Some(app.clone()),
+                    0
+                ),
+                ("code/gone".into(), RepoKind::Missing, None, 0),
+                (
+                    "code/tool".into(),
+                    RepoKind::Worktree,
+                    Some(bare.clone()),
+                    0
+                ),
+                ("code/tool.git".into(), RepoKind::Bare, None, 0),
+            ]
+        );
+        assert_eq!(
+            entries[0].worktrees,
+            [
+                LinkedWorktree {
+                    path: root.join("code/app-feature"),
+                    discovered: true,
+                },
+                LinkedWorktree {
+                    path: root.join("elsewhere/hotfix"),
+                    discovered: false,
+                },
+            ]
+        );
+        assert_eq!(entries[0].last_resolved, Some(now));
+        assert_eq!(entries[1].last_resolved, None);
+        assert_eq!(
+            entries[4].keep_marker,
+            Some(root.join("code/tool/.letitgo-keep"))
+        );
+        assert_eq!(entries[5].worktrees.len(), 1);
+        assert!(entries[5].worktrees[0].discovered);
+    }
+}
@@ -1,21 +1,79 @@
-use ignore::{WalkBuilder, WalkState};
-use std::{path::PathBuf, sync::Mutex};
+use crossbeam_channel::Sender;
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 use tracing::{debug, warn};
 
-/// Thread-local buffer that merges into a shared `Mutex<Vec<PathBuf>>` on drop.
+use crate::walk::{Visit, Walker};
+
+/// Knobs that change what [`discover`] looks for.
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    /// Also report bare repositories (`foo.git/` containing `HEAD`, `objects/`
+    /// and `refs/`).  Off by default because it costs extra `stat` calls for
+    /// every directory visited.
+    pub detect_bare_repos: bool,
+    /// Scan the targets of symlinked search paths and of directory symlinks
+    /// directly inside a search path; links deeper down are never followed.
+    pub follow_symlinked_search_paths: bool,
+    /// How each search path is walked.
+    pub walker: Walker,
+}
+
+/// Result of a discovery walk.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Discovery {
+    /// Roots of working trees (directories containing a `.git` entry).
+    pub repos: Vec<PathBuf>,
+    /// Bare repository directories; only populated when
+    /// [`ScanOptions::detect_bare_repos`] is set.
+    pub bare_repos: Vec<PathBuf>,
+    /// Directories the walk could not read (permission denied), e.g. folders
+    /// protected by macOS privacy controls.  Repos below them are missed.
+    pub permission_denied: Vec<PathBuf>,
+}
+
+impl Discovery {
+    /// Add the repos from `other`, dropping duplicates.
+    pub fn merge(&mut self, mut other: Discovery) {
+        self.repos.append(&mut other.repos);
+        self.bare_repos.append(&mut other.bare_repos);
+        self.permission_denied.append(&mut other.permission_denied);
+        dedup_paths(&mut self.repos);
+        dedup_paths(&mut self.bare_repos);
+        self.permission_denied.sort();
+        self.permission_denied.dedup();
+    }
+
+    /// Drop entries that are no longer repositories on disk.
+    pub fn retain_existing(&mut self) {
+        self.repos.retain(|r| r.join(".git").exists());
+        self.bare_repos.retain(|r| is_bare_repo(r));
+    }
+}
+
+/// Thread-local buffer that merges into a shared `Mutex<Discovery>` on drop.
 ///
 /// Each parallel-walker thread accumulates discovered repos locally, avoiding
 /// per-repo lock contention. The merge happens once per thread when the walker
 /// callback is dropped.
 struct RepoCollector<'a> {
-    local: Vec<PathBuf>,
-    global: &'a Mutex<Vec<PathBuf>>,
+    local: Discovery,
+    global: &'a Mutex<Discovery>,
 }
 
 impl Drop for RepoCollector<'_> {
     fn drop(&mut self) {
-        if !self.local.is_empty() {
-            self.global.lock().unwrap().append(&mut self.local);
+        if self.local != Discovery::default() {
+            let mut global = self.global.lock().unwrap();
+            global.repos.append(&mut self.local.repos);
+            global.bare_repos.append(&mut self.local.bare_repos);
+            global
+                .permission_denied
+                .append(&mut self.local.permission_denied);
         }
     }
 }
@@ -23,38 +81,79 @@ impl Drop for RepoCollector<'_> {
 /// Scan `search_paths` for Git repository roots in parallel using the `ignore`
 /// crate's parallel walker. Directories listed in `ignored_paths` are skipped.
 ///
+/// Linked worktrees (whose `.git` is a `gitdir:` file) are discovered like
+/// regular repos; their shared ignore rules are picked up during resolution.
+///
 /// Returns a deduplicated list of repo root `PathBuf`s.
 pub fn discover_repos(search_paths: &[PathBuf], ignored_paths: &[PathBuf]) -> Vec<PathBuf> {
-    let repos: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+    discover(search_paths, ignored_paths, &ScanOptions::default()).repos
+}
+
+/// Like [`discover_repos`], but honours `options` and also returns bare
+/// repositories when requested.
+pub fn discover(
+    search_paths: &[PathBuf],
+    ignored_paths: &[PathBuf],
+    options: &ScanOptions,
+) -> Discovery {
+    discover_into(search_paths, ignored_paths, options, None)
+}
+
+/// Like [`discover`], but also sends each working tree to `sink` as soon as
+/// it is found, so it can be resolved while the walk goes on.
+///
+/// A repo reachable through overlapping search paths may be sent more than
+/// once; the returned [`Discovery`] is deduplicated as usual.  Sending
+/// blocks while a bounded `sink` is full.
+pub fn discover_streaming(
+    search_paths: &[PathBuf],
+    ignored_paths: &[PathBuf],
+    options: &ScanOptions,
+    sink: &Sender<PathBuf>,
+) -> Discovery {
+    discover_into(search_paths, ignored_paths, options, Some(sink))
+}
 
+fn discover_into(
+    search_paths: &[PathBuf],
+    ignored_paths: &[PathBuf],
+    options: &ScanOptions,
+    sink: Option<&Sender<PathBuf>>,
+) -> Discovery {
+    let found: Mutex<Discovery> = Mutex::new(Discovery::default());
+
+    let roots;
+    let search_paths = if options.follow_symlinked_search_paths {
+        roots = symlinked_roots(search_paths);
+        &roots[..]
+    } else {
+        search_paths
+    };
     for search_root in search_paths {
         if !search_root.exists() {
             warn!("Search path does not exist: {}", search_root.display());
             continue;
         }
 
-        let mut builder = WalkBuilder::new(search_root);
-        builder
-            .hidden(false) // visit hidden dirs (e.g. .git)
-            .follow_links(false) // never follow symlinks
-            .git_ignore(false) // we handle ignore logic ourselves
-            .git_global(false)
-            .git_exclude(false);
-
-        let walker = builder.build_parallel();
-
-        walker.run(|| {
+        options.walker.walk_parallel(search_root, || {
             let mut collector = RepoCollector {
-                local: Vec::new(),
-                global: &repos,
+                local: Discovery::default(),
+                global: &found,
             };
 
             Box::new(move |result| {
                 let entry = match result {
                     Ok(e) => e,
                     Err(e) => {
-                        warn!("Walk error: {}", e);
-                        return WalkState::Continue;
+                        // Reported together at the end of the run
+                        match e.path().filter(|_| e.is_permission_denied()) {
+                            Some(denied) => {
+                                debug!("Permission denied: {}", denied.display());
+                                collector.local.permission_denied.push(denied.to_path_buf());
+                            }
+                            None => warn!("Walk error: {}", e),
+                        }
+                        return Visit::Continue;
                     }
                 };
 
@@ -63,36 +162,129 @@ pub fn discover_repos(search_paths: &[PathBuf], ignored_paths: &[PathBuf]) -> Ve
                 // Skip configured ignored paths (linear scan; fine for typical ~6 entries)
                 if ignored_paths.iter().any(|ig| path.starts_with(ig)) {
                     debug!("Skipping ignored path: {}", path.display());
-                    return WalkState::Skip;
+                    return Visit::Skip;
                 }
 
                 // We're looking for .git entries — either a directory (regular repos)
                 // or a file (submodules and worktrees use a file pointing to the
-                // actual git dir). WalkState::Skip is a no-op for files.
+                // actual git dir). Visit::Skip is a no-op for files.
                 if entry.file_name() == ".git"
                     && let Some(repo_root) = path.parent()
                 {
                     debug!("Found repo: {}", repo_root.display());
-                    collector.local.push(repo_root.to_path_buf());
-                    return WalkState::Skip;
+                    if let Some(sink) = sink {
+                        // Only fails once the receivers are gone
+                        let _ = sink.send(repo_root.to_path_buf());
+                    }
+                    collector.local.repos.push(repo_root.to_path_buf());
+                    return Visit::Skip;
+                }
+
+                if options.detect_bare_repos && entry.is_dir() && is_bare_repo(path) {
+                    debug!("Found bare repo: {}", path.display());
+                    collector.local.bare_repos.push(path.to_path_buf());
+                    return Visit::Skip;
                 }
 
-                WalkState::Continue
+                Visit::Continue
             })
         });
     }
 
-    // Deduplicate (unlikely, but possible if search paths overlap)
-    let mut result = repos.into_inner().unwrap();
-    result.sort();
-    result.dedup();
+    // Deduplicate (unlikely, but possible if search paths overlap).  Compare
+    // canonical paths so that the same worktree reached through two spellings
+    // (e.g. `~/src` and `/Users/me/src`) is resolved only once.
+    let mut result = found.into_inner().unwrap();
+    dedup_paths(&mut result.repos);
+    dedup_paths(&mut result.bare_repos);
+    result.permission_denied.sort();
+    result.permission_denied.dedup();
     result
 }
 
+/// Expand `search_paths` for `follow_symlinked_search_paths`.
+///
+/// A search path that is itself a symlink is replaced by its target, and the
+/// target of every directory symlink directly inside a search path is added
+/// as another root.  Only that top level is looked at — the walk itself never
+/// follows links — and nothing inside a repository is followed.  A target
+/// inside another root (already scanned) or above one (a cycle back to the
+/// root) is skipped.
+fn symlinked_roots(search_paths: &[PathBuf]) -> Vec<PathBuf> {
+    let is_symlink = |p: &Path| fs::symlink_metadata(p).is_ok_and(|m| m.file_type().is_symlink());
+    let mut roots: Vec<PathBuf> = search_paths
+        .iter()
+        .map(|p| match fs::canonicalize(p) {
+            Ok(target) if is_symlink(p) => {
+                debug!(
+                    "Following search path {} → {}",
+                    p.display(),
+                    target.display()
+                );
+                target
+            }
+            _ => p.clone(),
+        })
+        .collect();
+    let mut canonical: Vec<PathBuf> = roots
+        .iter()
+        .map(|r| fs::canonicalize(r).unwrap_or_else(|_| r.clone()))
+        .collect();
+
+    for root in roots.clone() {
+        if root.join(".git").exists() {
+            continue;
+        }
+        let Ok(entries) = fs::read_dir(&root) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let link = entry.path();
+            if !entry.file_type().is_ok_and(|t| t.is_symlink()) {
+                continue;
+            }
+            let Ok(target) = fs::canonicalize(&link) else {
+                debug!("Skipping dangling symlink {}", link.display());
+                continue;
+            };
+            if !target.is_dir() {
+                continue;
+            }
+            if let Some(other) = canonical
+                .iter()
+                .find(|c| target.starts_with(c) || c.starts_with(&target))
+            {
+                debug!(
+                    "Not following {} → {}: overlaps search root {}",
+                    link.display(),
+                    target.display(),
+                    other.display()
+                );
+                continue;
+            }
+            debug!("Following {} → {}", link.display(), target.display());
+            canonical.push(target.clone());
+            roots.push(target);
+        }
+    }
+    roots
+}
+
+/// Return `true` if `dir` looks like a bare Git repository: it holds a `HEAD`
+/// file alongside `objects/` and `refs/` directories.
+pub fn is_bare_repo(dir: &Path) -> bool {
+    dir.join("HEAD").is_file() && dir.join("objects").is_dir() && dir.join("refs").is_dir()
+}
+
+fn dedup_paths(paths: &mut Vec<PathBuf>) {
+    paths.sort();
+    let mut seen = HashSet::new();
+    paths.retain(|p| seen.insert(fs::canonicalize(p).unwrap_or_else(|_| p.clone())));
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
     use tempfile::tempdir;
 
     #[test]
@@ -139,6 +331,39 @@ mod tests {
         assert!(repos.contains(&tmp.path().join("outer/sub")));
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinked_roots_follow_top_level_only() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = tempdir().unwrap();
+        let farm = tmp.path().join("farm");
+        let elsewhere = tmp.path().join("elsewhere");
+        fs::create_dir_all(farm.join("inner")).unwrap();
+        fs::create_dir_all(elsewhere.join("proj/.git")).unwrap();
+        fs::create_dir_all(elsewhere.join("deep/repo/.git")).unwrap();
+        symlink(elsewhere.join("proj"), farm.join("proj")).unwrap();
+        // Only the top level is followed
+        symlink(elsewhere.join("deep"), farm.join("inner/deep")).unwrap();
+        // Back to an ancestor: a cycle, skipped
+        symlink(tmp.path(), farm.join("up")).unwrap();
+        symlink(tmp.path().join("missing"), farm.join("dangling")).unwrap();
+
+        let options = ScanOptions {
+            follow_symlinked_search_paths: true,
+            ..ScanOptions::default()
+        };
+        let found = discover(std::slice::from_ref(&farm), &[], &options).repos;
+        let proj = fs::canonicalize(elsewhere.join("proj")).unwrap();
+        assert_eq!(found, vec![proj.clone()]);
+        assert!(discover_repos(std::slice::from_ref(&farm), &[]).is_empty());
+
+        // A search path that is itself a symlink is scanned at its target
+        let link = tmp.path().join("projects");
+        symlink(&farm, &link).unwrap();
+        assert_eq!(discover(&[link], &[], &options).repos, vec![proj]);
+    }
+
     #[test]
     fn test_discover_repos_missing_search_path() {
         // Should warn but not panic
@@ -179,6 +404,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_discover_bare_repos_opt_in() {
+        let tmp = tempdir().unwrap();
+        fs::create_dir_all(tmp.path().join("work/.git")).unwrap();
+        let bare = tmp.path().join("mirrors/project.git");
+        fs::create_dir_all(bare.join("objects/pack")).unwrap();
+        fs::create_dir_all(bare.join("refs/heads")).unwrap();
+        fs::write(bare.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        let roots = [tmp.path().to_path_buf()];
+        assert!(
+            discover(&roots, &[], &ScanOptions::default())
+                .bare_repos
+                .is_empty()
+        );
+
+        let options = ScanOptions {
+            detect_bare_repos: true,
+            ..ScanOptions::default()
+        };
+        let found = discover(&roots, &[], &options);
+        assert_eq!(found.bare_repos, vec![bare]);
+        // The regular repo's `.git` dir must not be mistaken for a bare repo
+        assert_eq!(found.repos, vec![tmp.path().join("work")]);
+    }
+
     #[cfg(unix)]
     #[test]
     fn test_discover_repos_does_not_follow_symlinks() {
@@ -193,7 +444,7 @@ mod tests {
         fs::create_dir_all(&search_dir).unwrap();
         std::os::unix::fs::symlink(&real_repo, search_dir.join("linked")).unwrap();
 
-        let repos = discover_repos(&[search_dir.clone()], &[]);
+        let repos = discover_repos(std::slice::from_ref(&search_dir), &[]);
         // The symlink must NOT be followed — no repos found
         assert!(repos.is_empty(), "symlinks should not be traversed");
     }
@@ -0,0 +1,81 @@
+use clap::ValueEnum;
+use schemars::{Schema, schema_for};
+use std::fmt;
+
+use crate::{cache::Cache, list::ListOutput, plan::Plan, report::RunReport};
+
+/// The machine-readable formats `letitgo schema` prints a JSON Schema of.
+/// The schemas are also shipped in `schemas/`, one `<name>.schema.json`
+/// each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SchemaName {
+    /// The cache file (`cache.json` and its snapshots).
+    Cache,
+    /// A plan file written by `letitgo plan`.
+    Plan,
+    /// The run report written to `report_path`.
+    Report,
+    /// The output of `letitgo list --json`.
+    List,
+}
+
+impl SchemaName {
+    pub const ALL: [SchemaName; 4] = [
+        SchemaName::Cache,
+        SchemaName::Plan,
+        SchemaName::Report,
+        SchemaName::List,
+    ];
+
+    pub fn file_name(self) -> String {
+        format!("{self}.schema.json")
+    }
+}
+
+impl fmt::Display for SchemaName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SchemaName::Cache => "cache",
+            SchemaName::Plan => "plan",
+            SchemaName::Report => "report",
+            SchemaName::List => "list",
+        })
+    }
+}
+
+/// The JSON Schema of `name`, generated from the types it is written from.
+pub fn schema(name: SchemaName) -> Schema {
+    match name {
+        SchemaName::Cache => schema_for!(Cache),
+        SchemaName::Plan => schema_for!(Plan),
+        SchemaName::Report => schema_for!(RunReport),
+        SchemaName::List => schema_for!(ListOutput),
+    }
+}
+
+/// [`schema`] as pretty-printed JSON, as shipped in `schemas/`.
+pub fn render(name: SchemaName) -> String {
+    let mut text = serde_json::to_string_pretty(&schema(name)).expect("serializing schema");
+    text.push('\n');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, path::Path};
+
+    #[test]
+    fn test_shipped_schemas_match_the_types() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("schemas");
+        for name in SchemaName::ALL {
+            let shipped = fs::read_to_string(dir.join(name.file_name())).unwrap_or_default();
+            assert!(
+                shipped == render(name),
+                "schemas/{} is out of date: run `letitgo schema {name} > schemas/{}`",
+                name.file_name(),
+                name.file_name()
+            );
+        }
+    }
+}
@@ -0,0 +1,960 @@
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Datelike, FixedOffset, Local, NaiveDateTime, TimeZone, Timelike, Weekday};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::hash_map::RandomState,
+    fs,
+    hash::{BuildHasher, Hasher},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+use tempfile::NamedTempFile;
+use tracing::{debug, info, warn};
+
+use crate::{helper::peer_uid, lock::pid_alive, permissions};
+
+/// Longest pause between runs after repeated failures (unless
+/// `service_interval` is longer).
+pub const MAX_BACKOFF: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often `sleep` and `wait` check for a stop request or a trigger.
+const STOP_POLL: Duration = Duration::from_millis(200);
+
+/// How often `wait` checks the files it watches for changes.
+const WATCH_POLL: Duration = Duration::from_secs(1);
+
+/// Shortest pause before retrying a run that found the lock held.
+pub const LOCK_RETRY: Duration = Duration::from_secs(5);
+
+/// When `service run` starts its next run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    /// Pause between successful runs.
+    pub interval: Duration,
+    /// Upper bound of the random delay added to every pause.
+    pub jitter: Duration,
+}
+
+impl Schedule {
+    /// The pause before the next run after `failures` consecutive failed
+    /// runs: the interval, doubled per failure up to [`MAX_BACKOFF`], plus a
+    /// random share of the jitter.
+    pub fn delay(&self, failures: u32) -> Duration {
+        let cap = self.interval.max(MAX_BACKOFF);
+        let backoff =
+            (0..failures).try_fold(self.interval, |d, _| d.checked_mul(2).filter(|d| *d < cap));
+        backoff.unwrap_or(cap) + self.jitter.mul_f64(random_fraction())
+    }
+
+    /// The random pause before the first run, so that machines started
+    /// together do not all scan at once.
+    pub fn initial_delay(&self) -> Duration {
+        self.jitter.mul_f64(random_fraction())
+    }
+}
+
+/// A part of the week in which `service run` starts runs of its own
+/// (`service_windows`), e.g. `Mon-Fri 12:00-14:00` or `daily 02:00-05:00`.
+///
+/// The days are `daily`, `weekdays`, `weekends`, or day names (`Mon` or
+/// `Monday`), ranges of them (`Fri-Mon` wraps) and lists (`Sat,Sun`); left
+/// out, they are `daily`.  A window closing before it opens (`22:00-06:00`)
+/// runs past midnight, into the day after each of its days.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct RunWindow {
+    /// The days it opens on, bit 0 for Monday.
+    days: u8,
+    /// Minutes after midnight it opens and closes; `close` is 1440 for
+    /// `24:00`, and before `open` past midnight.
+    open: u32,
+    close: u32,
+}
+
+impl RunWindow {
+    const EVERY_DAY: u8 = 0b111_1111;
+
+    fn on(&self, day: Weekday) -> bool {
+        self.days & (1 << day.num_days_from_monday()) != 0
+    }
+
+    /// Whether it is open at the local time `at`.
+    fn contains(&self, at: NaiveDateTime) -> bool {
+        let minute = at.hour() * 60 + at.minute();
+        let day = at.weekday();
+        if self.open < self.close {
+            self.on(day) && (self.open..self.close).contains(&minute)
+        } else {
+            (self.on(day) && minute >= self.open) || (self.on(day.pred()) && minute < self.close)
+        }
+    }
+
+    /// The first time after the local time `at` it opens.
+    fn next_open(&self, at: NaiveDateTime) -> NaiveDateTime {
+        (0..=7)
+            .map(|d| at.date() + chrono::Days::new(d))
+            .filter(|date| self.on(date.weekday()))
+            .filter_map(|date| date.and_hms_opt(self.open / 60, self.open % 60, 0))
+            .find(|open| *open > at)
+            .expect("a window opens at least once a week")
+    }
+}
+
+impl FromStr for RunWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let invalid = |why: &str| {
+            format!(
+                "invalid run window `{s}`: {why} (expected e.g. `Mon-Fri 12:00-14:00` or \
+                 `daily 02:00-05:00`)"
+            )
+        };
+        let (days, times) = match s.trim().rsplit_once(char::is_whitespace) {
+            Some((days, times)) => (parse_days(days.trim()).map_err(|e| invalid(&e))?, times),
+            None => (RunWindow::EVERY_DAY, s.trim()),
+        };
+        let (open, close) = times
+            .split_once('-')
+            .ok_or_else(|| invalid("expected a time range"))?;
+        let (open, close) = (
+            parse_clock(open).map_err(|e| invalid(&e))?,
+            parse_clock(close).map_err(|e| invalid(&e))?,
+        );
+        if open == 24 * 60 {
+            return Err(invalid("a window cannot open at 24:00"));
+        }
+        if open == close {
+            return Err(invalid("it opens and closes at the same time"));
+        }
+        Ok(RunWindow { days, open, close })
+    }
+}
+
+impl TryFrom<String> for RunWindow {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        s.parse()
+    }
+}
+
+/// The days of a [`RunWindow`], as a bit set.
+fn parse_days(text: &str) -> Result<u8, String> {
+    let day = |name: &str| {
+        Weekday::from_str(name.trim()).map_err(|_| format!("unknown day `{}`", name.trim()))
+    };
+    let mut days = 0;
+    for part in text.split(',') {
+        days |= match part.trim().to_ascii_lowercase().as_str() {
+            "daily" => RunWindow::EVERY_DAY,
+            "weekdays" => 0b001_1111,
+            "weekends" => 0b110_0000,
+            _ => {
+                let (first, last) = match part.split_once('-') {
+                    Some((first, last)) => (day(first)?, day(last)?),
+                    None => (day(part)?, day(part)?),
+                };
+                let mut bits = 1 << first.num_days_from_monday();
+                let mut d = first;
+                while d != last {
+                    d = d.succ();
+                    bits |= 1 << d.num_days_from_monday();
+                }
+                bits
+            }
+        };
+    }
+    Ok(days)
+}
+
+/// Minutes after midnight of `HH:MM`, up to `24:00`.
+fn parse_clock(text: &str) -> Result<u32, String> {
+    let text = text.trim();
+    let parsed = text
+        .split_once(':')
+        .filter(|(_, m)| m.len() == 2)
+        .and_then(|(h, m)| {
+            let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+            (m < 60 && (h < 24 || (h, m) == (24, 0))).then_some(h * 60 + m)
+        });
+    parsed.ok_or_else(|| format!("`{text}` is not a time of day like 02:00"))
+}
+
+/// When `service run` may next start a run of its own, at `now`: `None`
+/// when there are no `windows` or one of them is open, else the moment the
+/// first one opens.
+pub fn next_window(windows: &[RunWindow], now: DateTime<Local>) -> Option<DateTime<Local>> {
+    let at = now.naive_local();
+    if windows.is_empty() || windows.iter().any(|w| w.contains(at)) {
+        return None;
+    }
+    let opens = windows.iter().map(|w| w.next_open(at)).min()?;
+    // An opening skipped by a daylight saving change happens an hour later
+    [opens, opens + chrono::Duration::hours(1)]
+        .iter()
+        .find_map(|t| Local.from_local_datetime(t).earliest())
+}
+
+/// A random number in `[0, 1)`, from the standard library's per-instance
+/// hasher keys.
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_stop(_signal: libc::c_int) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+/// Make `SIGTERM` and `SIGINT` request a stop instead of killing the
+/// process, so a run in progress finishes and writes its cache.  The
+/// handler is one-shot: a second signal terminates immediately.
+pub fn install_stop_handler() -> Result<()> {
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        // SAFETY: the handler only stores to an atomic, which is
+        // async-signal-safe; `action` is fully initialized before use.
+        let installed = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = request_stop as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESETHAND;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut())
+        };
+        if installed != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("installing a handler for signal {signal}"));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a stop was requested by a signal.
+pub fn stop_requested() -> bool {
+    STOP.load(Ordering::SeqCst)
+}
+
+/// Sleep for `duration`, waking early on a stop request.  Returns `false`
+/// if it was cut short.
+pub fn sleep(duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    loop {
+        if stop_requested() {
+            return false;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return true;
+        }
+        thread::sleep(left.min(STOP_POLL));
+    }
+}
+
+/// Why [`wait`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wake {
+    /// The full duration passed.
+    Elapsed,
+    /// A run was requested through the trigger file.
+    Triggered,
+    /// One of the watched files was modified, created or removed.
+    Changed,
+    /// A stop was requested by a signal.
+    Stopped,
+}
+
+/// Sleep for `duration`, waking early on a stop request, when the trigger
+/// file at `trigger` appears, or when the modification time of a `watched`
+/// file changes.
+pub fn wait(duration: Duration, trigger: &Path, watched: &[PathBuf]) -> Wake {
+    let mtimes = || -> Vec<Option<SystemTime>> {
+        watched
+            .iter()
+            .map(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+            .collect()
+    };
+    let before = mtimes();
+    let mut next_watch = Instant::now() + WATCH_POLL;
+    let deadline = Instant::now() + duration;
+    loop {
+        if stop_requested() {
+            return Wake::Stopped;
+        }
+        if trigger.exists() {
+            return Wake::Triggered;
+        }
+        if !watched.is_empty() && Instant::now() >= next_watch {
+            if mtimes() != before {
+                return Wake::Changed;
+            }
+            next_watch = Instant::now() + WATCH_POLL;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Wake::Elapsed;
+        }
+        thread::sleep(left.min(STOP_POLL));
+    }
+}
+
+/// The file whose presence asks a running service for a run (next to the
+/// cache).  Being a single file, any number of requests made before the
+/// service gets to them amount to one run.
+pub fn trigger_path(cache_path: &Path) -> PathBuf {
+    cache_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("service-trigger")
+}
+
+/// Ask the service for a run by creating the trigger file at `path`.
+pub fn request_run(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        permissions::create_private_dir(parent)
+            .with_context(|| format!("creating trigger dir: {}", parent.display()))?;
+    }
+    permissions::write_private(path, Local::now().to_rfc3339())
+        .with_context(|| format!("writing trigger file {}", path.display()))
+}
+
+/// Consume the pending request at `path`, if any.
+pub fn take_trigger(path: &Path) {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            warn!("Could not remove trigger file {}: {e}", path.display())
+        }
+        _ => {}
+    }
+}
+
+/// How late a scheduled run may be before the service counts as unhealthy.
+pub const OVERDUE_GRACE: Duration = Duration::from_secs(10 * 60);
+
+/// What the service is doing right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ServiceState {
+    /// Pausing until `next_run`.
+    Waiting,
+    /// A run is in progress.
+    Running,
+    /// The process exited after a stop request.
+    Stopped,
+}
+
+impl std::fmt::Display for ServiceState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ServiceState::Waiting => "waiting",
+            ServiceState::Running => "running",
+            ServiceState::Stopped => "stopped",
+        })
+    }
+}
+
+/// The outcome of one run of the service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunOutcome {
+    pub finished_at: DateTime<FixedOffset>,
+    pub ok: bool,
+    /// The error of a failed run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The state `service run` publishes after every step, in the status file
+/// next to the cache and on the health endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceStatus {
+    pub pid: u32,
+    pub started_at: DateTime<FixedOffset>,
+    pub state: ServiceState,
+    #[serde(default)]
+    pub last_run: Option<RunOutcome>,
+    /// When the next run is due (while waiting).
+    #[serde(default)]
+    pub next_run: Option<DateTime<FixedOffset>>,
+    pub consecutive_failures: u32,
+}
+
+impl ServiceStatus {
+    /// The status of a service starting now in this process.
+    pub fn starting() -> Self {
+        ServiceStatus {
+            pid: std::process::id(),
+            started_at: Local::now().fixed_offset(),
+            state: ServiceState::Waiting,
+            last_run: None,
+            next_run: None,
+            consecutive_failures: 0,
+        }
+    }
+
+    /// Why the service is unhealthy at `now`, or `None` if it is fine: it
+    /// must be running, its last run must have succeeded, and a run due
+    /// more than [`OVERDUE_GRACE`] ago must have started.
+    pub fn problem(&self, now: DateTime<FixedOffset>) -> Option<String> {
+        if self.state == ServiceState::Stopped {
+            return Some("the service was stopped".into());
+        }
+        if let Some(run) = self.last_run.as_ref().filter(|run| !run.ok) {
+            return Some(format!(
+                "the last run failed ({} in a row): {}",
+                self.consecutive_failures,
+                run.error.as_deref().unwrap_or("unknown error")
+            ));
+        }
+        if self.state == ServiceState::Waiting
+            && let Some(next_run) = self.next_run
+            && now - next_run > chrono::Duration::from_std(OVERDUE_GRACE).unwrap_or_default()
+        {
+            return Some(format!(
+                "the run due at {} has not started",
+                next_run.format("%Y-%m-%d %H:%M:%S")
+            ));
+        }
+        None
+    }
+
+    /// This status as JSON, with `healthy` and, when unhealthy at `now`,
+    /// the `problem`: what `service status --json` and `/health` answer.
+    pub fn report(&self, now: DateTime<FixedOffset>) -> Result<serde_json::Value> {
+        let problem = self.problem(now);
+        let mut value = serde_json::to_value(self).context("serializing service status")?;
+        value["healthy"] = problem.is_none().into();
+        if let Some(problem) = problem {
+            value["problem"] = problem.into();
+        }
+        Ok(value)
+    }
+}
+
+/// Where `service run` writes its [`ServiceStatus`] (next to the cache).
+pub fn status_path(cache_path: &Path) -> PathBuf {
+    cache_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("service-status.json")
+}
+
+/// Write `status` to `path`, replacing it atomically.
+pub fn write_status(path: &Path, status: &ServiceStatus) -> Result<()> {
+    let parent = path.parent().unwrap_or(Path::new("."));
+    permissions::create_private_dir(parent)
+        .with_context(|| format!("creating status dir: {}", parent.display()))?;
+    let text = serde_json::to_string_pretty(status).context("serializing service status")?;
+    let mut tmp = NamedTempFile::new_in(parent)
+        .with_context(|| format!("creating temp file in {}", parent.display()))?;
+    tmp.write_all(text.as_bytes())
+        .with_context(|| format!("writing status temp file in {}", parent.display()))?;
+    tmp.persist(path)
+        .with_context(|| format!("persisting service status to {}", path.display()))?;
+    Ok(())
+}
+
+/// Read the status a service wrote to `path` (`None` if it never ran).
+///
+/// A service whose process is gone without having recorded a stop (it
+/// crashed or was killed) is reported as stopped.
+pub fn read_status(path: &Path) -> Result<Option<ServiceStatus>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    let mut status: ServiceStatus = serde_json::from_str(&text)
+        .with_context(|| format!("parsing service status {}", path.display()))?;
+    if !pid_alive(status.pid) {
+        status.state = ServiceState::Stopped;
+    }
+    Ok(Some(status))
+}
+
+/// Serve `status` as JSON on `GET /health` at `addr` from a background
+/// thread: `200` while healthy, `503` with the problem otherwise.
+///
+/// Only loopback addresses are accepted; the endpoint is for local
+/// monitoring agents, not the network.
+pub fn serve_health(addr: &str, status: Arc<Mutex<ServiceStatus>>) -> Result<SocketAddr> {
+    let addr: SocketAddr = addr
+        .parse()
+        .with_context(|| format!("parsing service_health_addr {addr:?}"))?;
+    if !addr.ip().is_loopback() {
+        bail!("service_health_addr must be a loopback address, not {addr}");
+    }
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("binding health endpoint {addr}"))?;
+    let local = listener
+        .local_addr()
+        .context("reading health endpoint address")?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let status = status.lock().expect("status lock poisoned").clone();
+            if let Err(e) = answer_health(stream, &status) {
+                debug!("Health request failed: {e:#}");
+            }
+        }
+    });
+    info!("Health endpoint listening on http://{local}/health");
+    Ok(local)
+}
+
+/// Answer one HTTP request on `stream`.
+fn answer_health(stream: TcpStream, status: &ServiceStatus) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let (code, body) = match request_line.split_whitespace().nth(1) {
+        Some("/health") => {
+            let body = status.report(Local::now().fixed_offset())?;
+            let code = if body["healthy"] == true {
+                "200 OK"
+            } else {
+                "503 Service Unavailable"
+            };
+            (code, serde_json::to_string_pretty(&body)?)
+        }
+        _ => ("404 Not Found", r#"{"error": "not found"}"#.to_string()),
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {code}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    Ok(())
+}
+
+/// Where `service run` listens for [`Command`]s with `service_socket`
+/// (next to the cache).
+pub fn socket_path(cache_path: &Path) -> PathBuf {
+    cache_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("service.sock")
+}
+
+/// A request of a companion tool on the service socket, a line of JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+pub enum Command {
+    /// What `service status --json` prints.
+    Status,
+    /// What `list --json` prints.
+    List,
+    /// The plan `letitgo plan` would write; nothing is changed.
+    Plan,
+    /// A run now, answered with its report once it is done.
+    Run {
+        #[serde(default)]
+        dry_run: bool,
+    },
+}
+
+/// The answer to a [`Command`], a line of JSON.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Reply {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    /// Why the command failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Bind the service socket at `path`, only this user may connect to it.
+/// A socket left by a service that is gone is replaced; one another
+/// service still answers on is an error.
+pub fn bind_socket(path: &Path) -> Result<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+        bail!("another service is listening on {}", path.display());
+    }
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("removing {}", path.display()));
+        }
+        _ => {}
+    }
+    if let Some(parent) = path.parent() {
+        permissions::create_private_dir(parent)
+            .with_context(|| format!("creating socket dir: {}", parent.display()))?;
+    }
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("binding {}", path.display()))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(permissions::FILE_MODE))
+        .with_context(|| format!("setting permissions of {}", path.display()))?;
+    Ok(listener)
+}
+
+/// Answer the [`Command`]s on `listener` with `handle` until a stop is
+/// requested.
+///
+/// Each connection is served on a thread of its own and may send any number
+/// of commands, one per line, each answered in turn; connections of other
+/// users are refused.  `handle` is called from several threads at once, so
+/// it serializes what must not overlap itself.
+pub fn serve_commands(
+    listener: UnixListener,
+    handle: &(dyn Fn(Command) -> Result<serde_json::Value> + Sync),
+) -> Result<()> {
+    listener
+        .set_nonblocking(true)
+        .context("configuring the service socket")?;
+    thread::scope(|s| {
+        while !stop_requested() {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    s.spawn(move || {
+                        if let Err(e) = answer_commands(stream, handle) {
+                            debug!("Service socket connection failed: {e:#}");
+                        }
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(STOP_POLL),
+                Err(e) => {
+                    warn!("Accepting a service socket connection failed: {e}");
+                    thread::sleep(STOP_POLL);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Answer the commands of one connection until the client closes it or a
+/// stop is requested.
+fn answer_commands(
+    stream: UnixStream,
+    handle: &(dyn Fn(Command) -> Result<serde_json::Value> + Sync),
+) -> Result<()> {
+    // An accepted socket may inherit the listener's non-blocking mode
+    stream.set_nonblocking(false)?;
+    let uid = peer_uid(&stream).context("reading the client's uid")?;
+    // SAFETY: getuid has no preconditions
+    if uid != unsafe { libc::getuid() } {
+        let reply = Reply {
+            result: None,
+            error: Some(format!("uid {uid} may not use this service")),
+        };
+        writeln!(&stream, "{}", serde_json::to_string(&reply)?)?;
+        return Ok(());
+    }
+    stream.set_read_timeout(Some(STOP_POLL))?;
+    let mut reader = BufReader::new(&stream);
+    let mut line = Vec::new();
+    loop {
+        match reader.read_until(b'\n', &mut line) {
+            Ok(_) => {
+                // Only the last line before the client closes lacks a newline
+                let closed = !line.ends_with(b"\n");
+                if !line.trim_ascii().is_empty() {
+                    let result = serde_json::from_slice(&line)
+                        .context("parsing the command")
+                        .and_then(handle);
+                    let reply = match result {
+                        Ok(value) => Reply {
+                            result: Some(value),
+                            error: None,
+                        },
+                        Err(e) => Reply {
+                            result: None,
+                            error: Some(format!("{e:#}")),
+                        },
+                    };
+                    writeln!(&stream, "{}", serde_json::to_string(&reply)?)?;
+                }
+                line.clear();
+                if closed {
+                    return Ok(());
+                }
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if stop_requested() {
+                    return Ok(());
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Publish `status` to the status file, logging (not failing) on error.
+pub fn publish(path: &Path, status: &ServiceStatus) {
+    if let Err(e) = write_status(path, status) {
+        warn!("Could not write the service status: {e:#}");
+    }
+}
+
+/// What `service run` shares between its schedule and its command socket:
+/// the status it publishes, and the turn every run and plan takes.
+#[derive(Debug)]
+pub struct Shared {
+    pub status: Arc<Mutex<ServiceStatus>>,
+    status_path: PathBuf,
+    turn: Mutex<()>,
+}
+
+impl Shared {
+    /// The state of a service starting with its cache at `cache_path`.
+    pub fn new(cache_path: &Path) -> Self {
+        Shared {
+            status: Arc::new(Mutex::new(ServiceStatus::starting())),
+            status_path: status_path(cache_path),
+            turn: Mutex::new(()),
+        }
+    }
+
+    /// Wait for the runs and plans in progress to finish; the next starts
+    /// once the returned guard is dropped.
+    pub fn take_turn(&self) -> MutexGuard<'_, ()> {
+        self.turn.lock().expect("service turn poisoned")
+    }
+
+    /// A copy of the status.
+    pub fn status(&self) -> ServiceStatus {
+        self.status.lock().expect("status lock poisoned").clone()
+    }
+
+    /// Edit the status and publish it.
+    pub fn update(&self, edit: impl FnOnce(&mut ServiceStatus)) {
+        let mut status = self.status.lock().expect("status lock poisoned");
+        edit(&mut status);
+        publish(&self.status_path, &status);
+    }
+
+    /// Record the outcome of a run that just finished, returning the
+    /// number of failed runs in a row since.
+    pub fn record(&self, result: &Result<()>) -> u32 {
+        let mut failures = 0;
+        self.update(|s| {
+            s.last_run = Some(RunOutcome {
+                finished_at: Local::now().fixed_offset(),
+                ok: result.is_ok(),
+                error: result.as_ref().err().map(|e| format!("{e:#}")),
+            });
+            s.consecutive_failures = match result {
+                Ok(()) => 0,
+                Err(_) => s.consecutive_failures + 1,
+            };
+            failures = s.consecutive_failures;
+        });
+        failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_wakes_when_a_watched_file_changes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (trigger, head) = (tmp.path().join("trigger"), tmp.path().join("HEAD"));
+        fs::write(&head, "ref: refs/heads/main\n").unwrap();
+        let watched = [head.clone(), tmp.path().join("absent")];
+
+        let switch = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            fs::write(&head, "ref: refs/heads/other\n").unwrap();
+        });
+        let start = Instant::now();
+        assert_eq!(
+            wait(Duration::from_secs(30), &trigger, &watched),
+            Wake::Changed
+        );
+        assert!(start.elapsed() < Duration::from_secs(10));
+        switch.join().unwrap();
+    }
+
+    #[test]
+    fn test_delay_backs_off_exponentially_up_to_cap() {
+        let hour = Duration::from_secs(3600);
+        let schedule = Schedule {
+            interval: hour,
+            jitter: Duration::ZERO,
+        };
+        assert_eq!(schedule.delay(0), hour);
+        assert_eq!(schedule.delay(1), 2 * hour);
+        assert_eq!(schedule.delay(3), 8 * hour);
+        assert_eq!(schedule.delay(5), MAX_BACKOFF);
+        assert_eq!(schedule.delay(200), MAX_BACKOFF);
+
+        // Jitter only ever lengthens the pause, by less than its bound
+        let jittered = Schedule {
+            jitter: Duration::from_secs(300),
+            ..schedule
+        };
+        for _ in 0..20 {
+            let delay = jittered.delay(0);
+            assert!(delay >= hour && delay < hour + jittered.jitter, "{delay:?}");
+        }
+
+        // An interval above the cap is never shortened
+        let weekly = Schedule {
+            interval: 7 * 24 * hour,
+            jitter: Duration::ZERO,
+        };
+        assert_eq!(weekly.delay(2), weekly.interval);
+    }
+
+    #[test]
+    fn test_run_windows_defer_to_the_next_opening() {
+        let window = |s: &str| s.parse::<RunWindow>().unwrap();
+        let at = |s: &str| {
+            let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+            Local.from_local_datetime(&naive).earliest().unwrap()
+        };
+        // 2026-10-16 is a Friday
+        let lunch = [window("Mon-Fri 12:00-14:00"), window("sat,SUN 02:00-05:00")];
+        assert_eq!(next_window(&lunch, at("2026-10-16 13:59")), None);
+        assert_eq!(
+            next_window(&lunch, at("2026-10-16 14:00")),
+            Some(at("2026-10-17 02:00"))
+        );
+        assert_eq!(
+            next_window(&lunch, at("2026-10-18 09:00")),
+            Some(at("2026-10-19 12:00"))
+        );
+        assert_eq!(next_window(&[], at("2026-10-18 09:00")), None);
+
+        // Past midnight, into the day after; ranges wrap around the week
+        let nights = [window("Fri-Mon 22:00-06:00")];
+        assert_eq!(next_window(&nights, at("2026-10-20 05:00")), None);
+        assert_eq!(
+            next_window(&nights, at("2026-10-21 05:00")),
+            Some(at("2026-10-23 22:00"))
+        );
+        assert_eq!(window("03:00-24:00"), window("daily 03:00-24:00"));
+        assert_eq!(window("weekdays 1:30-2:00"), window("Mon-Fri 01:30-02:00"));
+
+        for bad in [
+            "Mon-Fri",
+            "someday 12:00-14:00",
+            "daily 12:00-12:00",
+            "daily 24:00-02:00",
+            "daily 12:60-14:00",
+            "daily 12:5-14:00",
+        ] {
+            let err = bad.parse::<RunWindow>().unwrap_err();
+            assert!(err.contains(bad), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_health_reports_failed_and_overdue_runs() {
+        let get = |addr: SocketAddr| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"GET /health HTTP/1.1\r\n\r\n").unwrap();
+            let mut response = String::new();
+            std::io::Read::read_to_string(&mut stream, &mut response).unwrap();
+            response
+        };
+        let now = Local::now().fixed_offset();
+        let status = Arc::new(Mutex::new(ServiceStatus {
+            next_run: Some(now + chrono::Duration::minutes(5)),
+            ..ServiceStatus::starting()
+        }));
+        assert!(serve_health("0.0.0.0:0", Arc::clone(&status)).is_err());
+        let addr = serve_health("127.0.0.1:0", Arc::clone(&status)).unwrap();
+        assert!(get(addr).starts_with("HTTP/1.1 200 OK"));
+
+        // A run due long ago that never started
+        status.lock().unwrap().next_run = Some(now - chrono::Duration::hours(1));
+        let response = get(addr);
+        assert!(response.starts_with("HTTP/1.1 503"), "{response}");
+        assert!(response.contains("has not started"), "{response}");
+
+        // A failed run, until the next one succeeds
+        let failed = ServiceStatus {
+            last_run: Some(RunOutcome {
+                finished_at: now,
+                ok: false,
+                error: Some("scan failed".into()),
+            }),
+            next_run: None,
+            consecutive_failures: 2,
+            ..ServiceStatus::starting()
+        };
+        assert!(failed.problem(now).unwrap().contains("scan failed"));
+
+        // A status file left by a process that is gone reads as stopped
+        let tmp = tempfile::tempdir().unwrap();
+        let path = status_path(&tmp.path().join("cache.json"));
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let dead = ServiceStatus {
+            pid: child.id(),
+            ..ServiceStatus::starting()
+        };
+        child.wait().unwrap();
+        write_status(&path, &dead).unwrap();
+        let read = read_status(&path).unwrap().unwrap();
+        assert_eq!(read.state, ServiceState::Stopped);
+        assert!(read.problem(now).is_some());
+    }
+
+    #[test]
+    fn test_socket_answers_each_command_of_each_connection() {
+        fn handle(command: Command) -> Result<serde_json::Value> {
+            match command {
+                Command::Run { dry_run: false } => bail!("not in tests"),
+                command => Ok(serde_json::to_value(command)?),
+            }
+        }
+        let tmp = tempfile::tempdir().unwrap();
+        let path = socket_path(&tmp.path().join("cache/cache.json"));
+        // A socket left by a service that is gone is replaced
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        drop(UnixListener::bind(&path).unwrap());
+        let listener = bind_socket(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, permissions::FILE_MODE);
+        thread::spawn(move || serve_commands(listener, &handle));
+        assert!(
+            bind_socket(&path).is_err(),
+            "a second service must not bind"
+        );
+
+        let ask = |stream: &UnixStream, reader: &mut BufReader<&UnixStream>, line: &str| {
+            writeln!(&*stream, "{line}").unwrap();
+            let mut reply = String::new();
+            reader.read_line(&mut reply).unwrap();
+            serde_json::from_str::<serde_json::Value>(&reply).unwrap()
+        };
+        // A client that stays connected does not hold up the others
+        let idle = UnixStream::connect(&path).unwrap();
+        let stream = UnixStream::connect(&path).unwrap();
+        let mut reader = BufReader::new(&stream);
+        let reply = ask(&stream, &mut reader, r#"{"op": "run", "dry_run": true}"#);
+        assert_eq!(reply["result"]["op"], "run");
+        assert_eq!(reply["result"]["dry_run"], true);
+        let reply = ask(&stream, &mut reader, r#"{"op": "run"}"#);
+        assert_eq!(reply["error"], "not in tests");
+        let reply = ask(&stream, &mut reader, r#"{"op": "delete-everything"}"#);
+        assert!(
+            reply["error"]
+                .as_str()
+                .unwrap()
+                .contains("parsing the command")
+        );
+        let reply = ask(&stream, &mut reader, r#"{"op": "status"}"#);
+        assert_eq!(reply["result"]["op"], "status");
+
+        let mut reader = BufReader::new(&idle);
+        let reply = ask(&idle, &mut reader, r#"{"op": "list"}"#);
+        assert_eq!(reply["result"]["op"], "list");
+    }
+}
@@ -0,0 +1,260 @@
+use rayon::prelude::*;
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::walk::{WalkEntry, Walker};
+
+/// Hard-linked files already counted, by `(device, inode)`.
+pub type SeenInodes = Mutex<HashSet<(u64, u64)>>;
+
+/// Space allocated on disk for the files at or below `path`, in bytes.
+///
+/// Counts allocated blocks (`st_blocks`) rather than file lengths, so sparse
+/// files count only what they occupy, and a file hard-linked several times
+/// below `path` counts once.  APFS clones share blocks without saying so and
+/// are still counted per file.  Symlinks are not followed and unreadable
+/// entries are skipped, so the result is a lower bound of what a backup of
+/// `path` would contain.
+pub fn disk_size(path: &Path) -> u64 {
+    disk_size_dedup(path, &SeenInodes::default())
+}
+
+/// Like [`disk_size`], sharing `seen` between calls so that a file
+/// hard-linked into several measured paths is counted once overall.
+pub fn disk_size_dedup(path: &Path, seen: &SeenInodes) -> u64 {
+    Walker::default()
+        .entries(path)
+        .filter(WalkEntry::is_file)
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.nlink() <= 1 || seen.lock().unwrap().insert((m.dev(), m.ino())))
+        .map(|m| m.blocks() * 512)
+        .sum()
+}
+
+/// Keep only the `max` largest paths of `paths` (by [`disk_size`]) and
+/// return the dropped ones with their sizes, largest first.
+///
+/// Ties are broken by path so that the selection is stable between runs.
+pub fn keep_largest(paths: &mut HashSet<PathBuf>, max: usize) -> Vec<(PathBuf, u64)> {
+    if paths.len() <= max {
+        return Vec::new();
+    }
+    let mut sized: Vec<(PathBuf, u64)> = paths
+        .drain()
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|p| {
+            let size = disk_size(&p);
+            (p, size)
+        })
+        .collect();
+    sized.sort_by(|(pa, sa), (pb, sb)| sb.cmp(sa).then_with(|| pa.cmp(pb)));
+    let dropped = sized.split_off(max);
+    paths.extend(sized.into_iter().map(|(p, _)| p));
+    dropped
+}
+
+/// Format `bytes` with binary units, e.g. `1.5 GiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// How much the excluded paths weigh on disk and in the latest backup.
+#[derive(Debug, Clone, Serialize)]
+pub struct Impact {
+    /// Number of excluded paths.
+    pub paths: usize,
+    /// Excluded paths that still exist on disk.
+    pub existing: usize,
+    /// Their current total size on disk.
+    pub size_bytes: u64,
+    /// The same paths inside the latest Time Machine backup, if one was found.
+    pub latest_backup: Option<BackupImpact>,
+}
+
+/// The share of a Time Machine backup taken up by excluded paths.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupImpact {
+    /// The backup that was inspected (from `tmutil latestbackup`).
+    pub backup: PathBuf,
+    /// Excluded paths that are present in the backup.
+    pub paths: usize,
+    /// Their total size in the backup — what future backups stop copying.
+    pub size_bytes: u64,
+}
+
+/// Measure `paths` on disk and, when `latest_backup` is given, inside it.
+///
+/// Sizes are allocated bytes (see [`disk_size`]); a file hard-linked into
+/// several of `paths` counts once per total.
+pub fn estimate_impact(paths: &[PathBuf], latest_backup: Option<&Path>) -> Impact {
+    let seen = SeenInodes::default();
+    let on_disk: Vec<u64> = paths
+        .par_iter()
+        .filter(|p| p.exists())
+        .map(|p| disk_size_dedup(p, &seen))
+        .collect();
+    let latest_backup = latest_backup.map(|backup| {
+        let seen = SeenInodes::default();
+        let in_backup: Vec<u64> = paths
+            .par_iter()
+            .filter_map(|p| path_in_backup(backup, p))
+            .map(|p| disk_size_dedup(&p, &seen))
+            .collect();
+        BackupImpact {
+            backup: backup.to_path_buf(),
+            paths: in_backup.len(),
+            size_bytes: in_backup.iter().sum(),
+        }
+    });
+    Impact {
+        paths: paths.len(),
+        existing: on_disk.len(),
+        size_bytes: on_disk.iter().sum(),
+        latest_backup,
+    }
+}
+
+/// Locate `path` inside a Time Machine backup.
+///
+/// A backup holds one directory per backed-up volume (e.g.
+/// `Macintosh HD - Data`) mirroring that volume from its root, so each
+/// volume directory is tried in turn, after `backup` itself in case it is
+/// one of those volume directories.  The `/System/Volumes/Data` firmlink
+/// prefix is stripped first.
+pub fn path_in_backup(backup: &Path, path: &Path) -> Option<PathBuf> {
+    let rel = path
+        .strip_prefix("/System/Volumes/Data")
+        .or_else(|_| path.strip_prefix("/"))
+        .ok()?;
+    let volumes = std::fs::read_dir(backup)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|volume| volume.path());
+    std::iter::once(backup.to_path_buf())
+        .chain(volumes)
+        .map(|volume| volume.join(rel))
+        .find(|candidate| candidate.symlink_metadata().is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    /// 64 KiB: a whole number of blocks on common filesystems.
+    const CHUNK: usize = 64 * 1024;
+
+    /// Write `chunks * CHUNK` incompressible bytes, so that allocation
+    /// matches the length even on compressing filesystems.
+    fn write_chunks(path: &Path, chunks: usize) {
+        let mut x: u32 = 0x9e37_79b9 ^ chunks as u32;
+        let data: Vec<u8> = (0..chunks * CHUNK)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn test_keep_largest_drops_smallest() {
+        let tmp = tempdir().unwrap();
+        let big = tmp.path().join("big");
+        let small = tmp.path().join("small");
+        fs::create_dir_all(big.join("nested")).unwrap();
+        write_chunks(&big.join("a"), 2);
+        write_chunks(&big.join("nested/b"), 1);
+        fs::create_dir_all(&small).unwrap();
+        write_chunks(&small.join("a"), 2);
+        let file = tmp.path().join("file.log");
+        write_chunks(&file, 1);
+        assert_eq!(disk_size(&big), 3 * CHUNK as u64);
+
+        let mut paths: HashSet<PathBuf> = [big.clone(), small.clone(), file.clone()]
+            .into_iter()
+            .collect();
+        let dropped = keep_largest(&mut paths, 1);
+        assert_eq!(paths, [big].into_iter().collect());
+        assert_eq!(
+            dropped,
+            vec![(small, 2 * CHUNK as u64), (file, CHUNK as u64)]
+        );
+    }
+
+    #[test]
+    fn test_disk_size_counts_allocation_and_hard_links_once() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path().join("dir");
+        fs::create_dir_all(&dir).unwrap();
+        write_chunks(&dir.join("data"), 2);
+        fs::hard_link(dir.join("data"), dir.join("link")).unwrap();
+        assert_eq!(disk_size(&dir), 2 * CHUNK as u64);
+
+        // Linked into a second path: counted once across both
+        let other = tmp.path().join("other");
+        fs::create_dir_all(&other).unwrap();
+        fs::hard_link(dir.join("data"), other.join("link")).unwrap();
+        let impact = estimate_impact(&[dir, other], None);
+        assert_eq!(impact.size_bytes, 2 * CHUNK as u64);
+
+        // A sparse file occupies less than its length
+        let sparse = tmp.path().join("sparse");
+        fs::File::create(&sparse)
+            .unwrap()
+            .set_len(64 * CHUNK as u64)
+            .unwrap();
+        assert!(disk_size(&sparse) < 64 * CHUNK as u64);
+    }
+
+    #[test]
+    fn test_estimate_impact_finds_paths_in_backup() {
+        let tmp = tempdir().unwrap();
+        let live = tmp.path().join("live/repo/target");
+        fs::create_dir_all(&live).unwrap();
+        write_chunks(&live.join("bin"), 1);
+        let gone = tmp.path().join("live/repo/node_modules");
+
+        // The backup still holds both paths under its volume directory
+        let backup = tmp.path().join("backups/2026-01-01-120000.backup");
+        let volume = backup.join("Macintosh HD - Data");
+        for (path, chunks) in [(&live, 2), (&gone, 1)] {
+            let copy = volume.join(path.strip_prefix("/").unwrap());
+            fs::create_dir_all(&copy).unwrap();
+            write_chunks(&copy.join("blob"), chunks);
+        }
+
+        let impact = estimate_impact(&[live, gone], Some(&backup));
+        assert_eq!(
+            (impact.paths, impact.existing, impact.size_bytes),
+            (2, 1, CHUNK as u64)
+        );
+        let in_backup = impact.latest_backup.unwrap();
+        assert_eq!(
+            (in_backup.paths, in_backup.size_bytes),
+            (2, 3 * CHUNK as u64)
+        );
+        assert_eq!(format_size(5120), "5.0 KiB");
+        assert_eq!(format_size(512), "512 B");
+    }
+}
@@ -0,0 +1,268 @@
+use anyhow::{Context, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tempfile::NamedTempFile;
+use tracing::debug;
+
+use crate::{
+    tmutil::ExclusionManager,
+    warnings::{Warning, WarningKind, Warnings},
+};
+
+/// Marker Syncthing creates at the root of every synced folder.
+const SYNCTHING_MARKER: &str = ".stfolder";
+
+/// Syncthing's per-folder ignore file.
+const STIGNORE: &str = ".stignore";
+
+/// Lines delimiting the part of a `.stignore` letitgo maintains.
+const BLOCK_BEGIN: &str = "// BEGIN letitgo (managed; edits between these lines are overwritten)";
+const BLOCK_END: &str = "// END letitgo";
+
+/// The attribute the Dropbox client skips items by.
+#[cfg(target_os = "macos")]
+const DROPBOX_IGNORED_XATTR: &str = "com.dropbox.ignored";
+#[cfg(not(target_os = "macos"))]
+const DROPBOX_IGNORED_XATTR: &str = "user.com.dropbox.ignored";
+
+/// Wraps the backend so that every exclusion also stops the path from
+/// syncing (`sync_ignore`): paths inside a Syncthing folder are listed in a
+/// managed block of its `.stignore`, paths inside a Dropbox folder get the
+/// `com.dropbox.ignored` attribute.
+///
+/// Sync folders are found per path: Syncthing folders by their `.stfolder`
+/// marker, Dropbox folders from the client's `~/.dropbox/info.json`.
+/// Failing to update a sync ignore is logged; it never fails the exclusion.
+pub struct SyncIgnoreManager {
+    inner: Box<dyn ExclusionManager>,
+    dropbox_roots: Vec<PathBuf>,
+    warnings: Warnings,
+}
+
+impl SyncIgnoreManager {
+    /// Wrap `inner`; `dropbox_roots` are the Dropbox folders (see
+    /// [`dropbox_roots`]).
+    pub fn new(inner: Box<dyn ExclusionManager>, dropbox_roots: Vec<PathBuf>) -> Self {
+        SyncIgnoreManager {
+            inner,
+            dropbox_roots,
+            warnings: Warnings::default(),
+        }
+    }
+
+    /// Add (`ignore`) or remove `paths` from the sync ignores of the folders
+    /// that contain them.
+    fn update(&self, paths: &[&Path], ignore: bool) {
+        let mut by_folder: BTreeMap<&Path, Vec<&Path>> = BTreeMap::new();
+        for path in paths {
+            if let Some(folder) = path
+                .ancestors()
+                .skip(1)
+                .find(|a| a.join(SYNCTHING_MARKER).exists())
+            {
+                by_folder.entry(folder).or_default().push(path);
+            }
+            if self.dropbox_roots.iter().any(|root| path.starts_with(root))
+                && let Err(e) = set_dropbox_ignored(path, ignore)
+            {
+                self.warnings.push(
+                    WarningKind::PathFailed,
+                    Some(path),
+                    format!(
+                        "Could not update the Dropbox ignore of {}: {e:#}",
+                        path.display()
+                    ),
+                );
+            }
+        }
+        for (folder, paths) in by_folder {
+            if let Err(e) = update_stignore(folder, &paths, ignore) {
+                let file = folder.join(STIGNORE);
+                self.warnings.push(
+                    WarningKind::PathFailed,
+                    Some(&file),
+                    format!("Could not update {}: {e:#}", file.display()),
+                );
+            }
+        }
+    }
+}
+
+impl ExclusionManager for SyncIgnoreManager {
+    fn add_exclusions(&self, paths: &[&Path], fixed_path: bool) -> Result<()> {
+        self.inner.add_exclusions(paths, fixed_path)?;
+        self.update(paths, true);
+        Ok(())
+    }
+    fn remove_exclusions(&self, paths: &[&Path], fixed_path: bool) -> Result<()> {
+        self.inner.remove_exclusions(paths, fixed_path)?;
+        self.update(paths, false);
+        Ok(())
+    }
+    fn is_excluded(&self, path: &Path) -> Result<bool> {
+        self.inner.is_excluded(path)
+    }
+    fn are_excluded(&self, paths: &[&Path]) -> Vec<bool> {
+        self.inner.are_excluded(paths)
+    }
+    fn take_warnings(&self) -> Vec<Warning> {
+        let mut warnings = self.inner.take_warnings();
+        warnings.extend(self.warnings.take());
+        warnings
+    }
+}
+
+/// The Dropbox folders listed in the client's `info.json` (`personal`,
+/// `business`, … each with a `path`); none if it cannot be read.
+pub fn dropbox_roots(info: &Path) -> Vec<PathBuf> {
+    let Ok(text) = fs::read_to_string(info) else {
+        return Vec::new();
+    };
+    let accounts: BTreeMap<String, serde_json::Value> = serde_json::from_str(&text)
+        .map_err(|e| debug!("Unreadable {}: {e}", info.display()))
+        .unwrap_or_default();
+    accounts
+        .values()
+        .filter_map(|account| account.get("path")?.as_str().map(PathBuf::from))
+        .collect()
+}
+
+fn set_dropbox_ignored(path: &Path, ignore: bool) -> Result<()> {
+    if ignore {
+        xattr::set(path, DROPBOX_IGNORED_XATTR, b"1")
+            .with_context(|| format!("setting {DROPBOX_IGNORED_XATTR}"))
+    } else if xattr::get(path, DROPBOX_IGNORED_XATTR)
+        .with_context(|| format!("reading {DROPBOX_IGNORED_XATTR}"))?
+        .is_some()
+    {
+        xattr::remove(path, DROPBOX_IGNORED_XATTR)
+            .with_context(|| format!("removing {DROPBOX_IGNORED_XATTR}"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Add or remove `paths` in the managed block of `folder`'s `.stignore`,
+/// keeping every line outside the block.  The file is replaced atomically.
+fn update_stignore(folder: &Path, paths: &[&Path], ignore: bool) -> Result<()> {
+    let file = folder.join(STIGNORE);
+    let text = match fs::read_to_string(&file) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", file.display())),
+    };
+    let (before, block, after) = split_block(&text);
+    let mut patterns: BTreeSet<String> = block.into_iter().collect();
+    for path in paths {
+        let Ok(rel) = path.strip_prefix(folder) else {
+            continue;
+        };
+        let pattern = format!("/{}", escape_pattern(&rel.to_string_lossy()));
+        if ignore {
+            patterns.insert(pattern);
+        } else {
+            patterns.remove(&pattern);
+        }
+    }
+
+    let mut lines = before;
+    if !patterns.is_empty() {
+        lines.push(BLOCK_BEGIN.to_string());
+        lines.extend(patterns);
+        lines.push(BLOCK_END.to_string());
+    }
+    lines.extend(after);
+    let mut new_text = lines.join("\n");
+    if !new_text.is_empty() {
+        new_text.push('\n');
+    }
+    if new_text == text {
+        return Ok(());
+    }
+    debug!("Updating {}", file.display());
+    let mut tmp = NamedTempFile::new_in(folder)
+        .with_context(|| format!("creating temp file in {}", folder.display()))?;
+    tmp.write_all(new_text.as_bytes())
+        .with_context(|| format!("writing {} temp file", file.display()))?;
+    tmp.persist(&file)
+        .with_context(|| format!("persisting {}", file.display()))?;
+    Ok(())
+}
+
+/// The lines of a `.stignore` before, inside and after the managed block.
+fn split_block(text: &str) -> (Vec<String>, Vec<String>, Vec<String>) {
+    let (mut before, mut block, mut after) = (Vec::new(), Vec::new(), Vec::new());
+    let mut section = 0;
+    for line in text.lines() {
+        match (section, line) {
+            (0, BLOCK_BEGIN) => section = 1,
+            (1, BLOCK_END) => section = 2,
+            (0, _) => before.push(line.to_string()),
+            (1, _) => block.push(line.to_string()),
+            _ => after.push(line.to_string()),
+        }
+    }
+    (before, block, after)
+}
+
+/// Backslash-escape the characters Syncthing patterns treat specially, so a
+/// path matches only itself.
+fn escape_pattern(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '{' | '}' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tmutil::mock::MockExclusionManager;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_stignore_block_tracks_exclusions_and_keeps_user_lines() {
+        let tmp = tempdir().unwrap();
+        let folder = tmp.path().join("Sync");
+        fs::create_dir_all(folder.join(SYNCTHING_MARKER)).unwrap();
+        fs::write(folder.join(STIGNORE), "(?d).DS_Store\n").unwrap();
+        let (target, odd, outside) = (
+            folder.join("app/target"),
+            folder.join("app/[id]*.log"),
+            tmp.path().join("Code/app/target"),
+        );
+
+        let info = tmp.path().join("info.json");
+        fs::write(&info, r#"{"personal": {"path": "/Users/me/Dropbox"}}"#).unwrap();
+        assert_eq!(
+            dropbox_roots(&info),
+            vec![PathBuf::from("/Users/me/Dropbox")]
+        );
+
+        let manager = SyncIgnoreManager::new(Box::new(MockExclusionManager::new()), vec![]);
+        let paths = [target.as_path(), odd.as_path(), outside.as_path()];
+        manager.add_exclusions(&paths, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(folder.join(STIGNORE)).unwrap(),
+            format!(
+                "(?d).DS_Store\n{BLOCK_BEGIN}\n/app/\\[id\\]\\*.log\n/app/target\n{BLOCK_END}\n"
+            )
+        );
+        assert!(!tmp.path().join("Code/.stignore").exists());
+
+        // Once every path is removed, only the user's lines remain
+        manager.remove_exclusions(&paths, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(folder.join(STIGNORE)).unwrap(),
+            "(?d).DS_Store\n"
+        );
+    }
+}
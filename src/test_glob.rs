@@ -0,0 +1,167 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use globset::GlobSet;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, hash_map::Entry},
+    path::{Path, PathBuf},
+};
+
+use crate::{git, ignore_resolver::build_whitelist_globset};
+
+/// How `letitgo test-glob` reads its pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PatternKind {
+    /// A `whitelist` glob, matched against the whole absolute path.
+    Whitelist,
+    /// A `.gitignore` / `.lignore` line, matched relative to a base
+    /// directory; `!pattern` re-includes.
+    Gitignore,
+}
+
+/// What a pattern does to one path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// The pattern matches the path (or, for a gitignore line, one of its
+    /// ancestors).
+    Match,
+    /// A negated gitignore line matches: the path is re-included.
+    Reinclude,
+    /// The pattern does not match.
+    NoMatch,
+}
+
+impl std::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Verdict::Match => "match",
+            Verdict::Reinclude => "re-include",
+            Verdict::NoMatch => "no match",
+        })
+    }
+}
+
+/// One pattern compiled as the engine compiles it, for testing against
+/// sample paths without a scan.
+pub struct PatternTester {
+    kind: PatternKind,
+    pattern: String,
+    base: Option<PathBuf>,
+    whitelist: GlobSet,
+    /// Gitignore matchers by base directory.
+    matchers: RefCell<HashMap<PathBuf, Gitignore>>,
+}
+
+impl PatternTester {
+    /// Compile `pattern` as `kind`.  A gitignore line is matched relative
+    /// to `base`, or else to the Git repository holding each path (the
+    /// current directory outside any repository).
+    pub fn new(kind: PatternKind, pattern: &str, base: Option<&Path>) -> Result<Self> {
+        let whitelist = match kind {
+            PatternKind::Whitelist => build_whitelist_globset(&[pattern.to_string()])?,
+            PatternKind::Gitignore => {
+                // Compile once up front so that a bad line fails early.
+                gitignore(Path::new("/"), pattern)?;
+                GlobSet::empty()
+            }
+        };
+        Ok(PatternTester {
+            kind,
+            pattern: pattern.to_string(),
+            base: base.map(Path::to_path_buf),
+            whitelist,
+            matchers: RefCell::default(),
+        })
+    }
+
+    /// The pattern's verdict for the absolute `path`.  Whether it is a
+    /// directory is read from disk; a missing path counts as a file.
+    pub fn test(&self, path: &Path) -> Result<Verdict> {
+        if self.kind == PatternKind::Whitelist {
+            return Ok(if self.whitelist.is_match(path) {
+                Verdict::Match
+            } else {
+                Verdict::NoMatch
+            });
+        }
+        let base = match &self.base {
+            Some(base) => base.clone(),
+            None => match git::find_repo(path) {
+                Some(repo) => repo,
+                None => std::env::current_dir().context("reading the current directory")?,
+            },
+        };
+        if path == base || !path.starts_with(&base) {
+            return Ok(Verdict::NoMatch);
+        }
+        let mut matchers = self.matchers.borrow_mut();
+        let matcher = match matchers.entry(base) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let matcher = gitignore(e.key(), &self.pattern)?;
+                e.insert(matcher)
+            }
+        };
+        Ok(
+            match matcher.matched_path_or_any_parents(path, path.is_dir()) {
+                ignore::Match::Ignore(_) => Verdict::Match,
+                ignore::Match::Whitelist(_) => Verdict::Reinclude,
+                ignore::Match::None => Verdict::NoMatch,
+            },
+        )
+    }
+}
+
+fn gitignore(base: &Path, pattern: &str) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(base);
+    builder
+        .add_line(None, pattern)
+        .with_context(|| format!("invalid gitignore pattern: {pattern}"))?;
+    builder.build().context("building gitignore matcher")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_patterns_match_as_the_engine_reads_them() {
+        let tmp = tempdir().unwrap();
+        let repo = tmp.path().join("app");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        fs::create_dir_all(repo.join("target/debug")).unwrap();
+        fs::create_dir_all(repo.join("src/target")).unwrap();
+        let target = repo.join("target");
+
+        let whitelist = PatternTester::new(PatternKind::Whitelist, "**/src/**", None).unwrap();
+        assert_eq!(
+            whitelist.test(&repo.join("src/target")).unwrap(),
+            Verdict::Match
+        );
+        assert_eq!(whitelist.test(&target).unwrap(), Verdict::NoMatch);
+
+        // Anchored to the repository; contents of a match match too
+        let anchored = PatternTester::new(PatternKind::Gitignore, "/target/", None).unwrap();
+        assert_eq!(anchored.test(&target).unwrap(), Verdict::Match);
+        assert_eq!(
+            anchored.test(&repo.join("target/debug/app")).unwrap(),
+            Verdict::Match
+        );
+        assert_eq!(
+            anchored.test(&repo.join("src/target")).unwrap(),
+            Verdict::NoMatch
+        );
+
+        let negated = PatternTester::new(PatternKind::Gitignore, "!target", Some(&repo)).unwrap();
+        assert_eq!(negated.test(&target).unwrap(), Verdict::Reinclude);
+        assert_eq!(
+            negated.test(&tmp.path().join("target")).unwrap(),
+            Verdict::NoMatch
+        );
+
+        assert!(PatternTester::new(PatternKind::Whitelist, "a[", None).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+//! Fixtures for testing ignore rules against the resolver.
+//!
+//! Enabled with the `test-util` feature so that downstream users can write
+//! regression tests for their own `.gitignore` / `.lignore` setups:
+//!
+//! ```
+//! use letitgo::test_util::RepoFixture;
+//!
+//! RepoFixture::new()
+//!     .gitignore("target/")
+//!     .dir("target/debug")
+//!     .dir("target/release")
+//!     .lignore("!target/")
+//!     .assert_excluded(&[]);
+//! ```
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+use crate::{
+    config::Config,
+    ignore_resolver::{ResolveOptions, resolve_repo},
+};
+
+/// A throwaway Git working tree in a temporary directory.
+///
+/// Every builder method writes to disk immediately; the directory is removed
+/// when the fixture is dropped.  Paths are relative to the repo root.
+pub struct RepoFixture {
+    _tmp: TempDir,
+    root: PathBuf,
+    config: Config,
+}
+
+impl RepoFixture {
+    /// Create an empty repository (`.git/` only) with an empty whitelist.
+    pub fn new() -> Self {
+        let tmp = tempfile::tempdir().expect("creating fixture tempdir");
+        let root = tmp.path().join("repo");
+        fs::create_dir_all(root.join(".git")).expect("creating fixture .git");
+        RepoFixture {
+            _tmp: tmp,
+            root,
+            config: Config {
+                whitelist: Vec::new(),
+                ..Config::default()
+            },
+        }
+    }
+
+    /// The repository root.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Create directory `rel` (and its parents).
+    pub fn dir(self, rel: &str) -> Self {
+        fs::create_dir_all(self.root.join(rel)).expect("creating fixture dir");
+        self
+    }
+
+    /// Create an empty file at `rel`, creating parent directories.
+    pub fn file(self, rel: &str) -> Self {
+        let path = self.root.join(rel);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("creating fixture dir");
+        }
+        fs::write(&path, "").expect("creating fixture file");
+        self
+    }
+
+    /// Append `line` to the root `.gitignore`.
+    pub fn gitignore(self, line: &str) -> Self {
+        self.gitignore_in(".", line)
+    }
+
+    /// Append `line` to the `.gitignore` in directory `dir`.
+    pub fn gitignore_in(self, dir: &str, line: &str) -> Self {
+        self.append_line(dir, ".gitignore", line)
+    }
+
+    /// Append `line` to the root `.lignore`.
+    pub fn lignore(self, line: &str) -> Self {
+        self.lignore_in(".", line)
+    }
+
+    /// Append `line` to the `.lignore` in directory `dir`.
+    pub fn lignore_in(self, dir: &str, line: &str) -> Self {
+        self.append_line(dir, ".lignore", line)
+    }
+
+    /// Add a config whitelist glob; `**/`-style globs work as in the config.
+    pub fn whitelist(mut self, glob: &str) -> Self {
+        self.config.whitelist.push(glob.to_string());
+        self
+    }
+
+    /// Adjust the config used for resolution.
+    pub fn configure(mut self, f: impl FnOnce(&mut Config)) -> Self {
+        f(&mut self.config);
+        self
+    }
+
+    /// Resolve the repository and return the excluded paths, relative to the
+    /// root, with `/` separators.
+    pub fn resolve(&self) -> BTreeSet<String> {
+        let options = ResolveOptions::from_config(&self.config).expect("building resolve options");
+        resolve_repo(&self.root, &options)
+            .expect("resolving fixture repo")
+            .into_iter()
+            .map(|p| {
+                p.strip_prefix(&self.root)
+                    .unwrap_or(&p)
+                    .to_string_lossy()
+                    .replace(std::path::MAIN_SEPARATOR, "/")
+            })
+            .collect()
+    }
+
+    /// Assert that the resolved exclusion set is exactly `expected`.
+    ///
+    /// # Panics
+    ///
+    /// Panics with both sets when they differ.
+    #[track_caller]
+    pub fn assert_excluded(&self, expected: &[&str]) {
+        let expected: BTreeSet<String> = expected.iter().map(|s| s.to_string()).collect();
+        let actual = self.resolve();
+        assert_eq!(
+            actual, expected,
+            "resolved exclusions (left) differ from expected (right)"
+        );
+    }
+
+    fn append_line(self, dir: &str, file_name: &str, line: &str) -> Self {
+        let dir = self.root.join(dir);
+        fs::create_dir_all(&dir).expect("creating fixture dir");
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(file_name))
+            .expect("opening fixture ignore file");
+        writeln!(file, "{line}").expect("writing fixture ignore file");
+        self
+    }
+}
+
+impl Default for RepoFixture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixture_nested_rules() {
+        let fixture = RepoFixture::new()
+            .gitignore("target/")
+            .gitignore("*.log")
+            .dir("target/debug")
+            .file("build.log")
+            .gitignore_in("web", "/dist/")
+            .dir("web/dist")
+            .dir("dist")
+            .lignore("scratch/")
+            .dir("scratch")
+            .whitelist("**/build.log");
+        fixture.assert_excluded(&["target", "web/dist", "scratch"]);
+
+        let fixture = fixture.lignore("!target/");
+        fixture.assert_excluded(&["web/dist", "scratch"]);
+    }
+}
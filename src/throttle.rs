@@ -0,0 +1,364 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    sync::{Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info};
+
+/// How often a throttled run looks at the machine's load again.
+const SAMPLE_EVERY: Duration = Duration::from_secs(1);
+
+/// Longest a run pauses for a hot machine before resolving a repo at a
+/// time anyway, so that it still finishes.
+pub const MAX_PAUSE: Duration = Duration::from_secs(5 * 60);
+
+/// How much a run holds back when the machine is busy or hot (`nice_mode`).
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NiceMode {
+    /// Use every core, whatever the load.
+    #[default]
+    Off,
+    /// Back off when every core is busy or the thermal pressure is heavy.
+    Polite,
+    /// Back off when half the cores are busy or the thermal pressure is
+    /// moderate, and pause while hot.
+    Aggressive,
+}
+
+impl fmt::Display for NiceMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NiceMode::Off => write!(f, "off"),
+            NiceMode::Polite => write!(f, "polite"),
+            NiceMode::Aggressive => write!(f, "aggressive"),
+        }
+    }
+}
+
+/// The thermal pressure levels of macOS (`OSThermalPressureLevel`).
+const THERMAL_MODERATE: u64 = 1;
+const THERMAL_HEAVY: u64 = 2;
+
+/// A sample of how hard pressed the machine is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Load {
+    /// The 1-minute load average: runnable threads, on average.
+    pub average: f64,
+    /// The thermal pressure level, where the system reports one (macOS):
+    /// 0 nominal, 1 moderate, 2 heavy, 3 trapping, 4 sleeping.
+    pub thermal: Option<u64>,
+}
+
+impl Load {
+    /// The machine's load now.
+    pub fn sample() -> Self {
+        let mut average = [0.0f64; 1];
+        // SAFETY: getloadavg writes at most the one element asked for
+        let read = unsafe { libc::getloadavg(average.as_mut_ptr(), 1) };
+        Load {
+            average: if read == 1 { average[0] } else { 0.0 },
+            thermal: thermal_level(),
+        }
+    }
+
+    /// The load of everything but `own` runnable threads of ours.
+    fn without(self, own: usize) -> Self {
+        Load {
+            average: (self.average - own as f64).max(0.0),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Load {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "load {:.2}", self.average)?;
+        match self.thermal {
+            Some(level) if level > 0 => write!(f, ", thermal pressure {level}"),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn cores() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+#[cfg(target_os = "macos")]
+fn thermal_level() -> Option<u64> {
+    use std::{
+        ffi::{c_char, c_int},
+        sync::OnceLock,
+    };
+
+    unsafe extern "C" {
+        fn notify_register_check(name: *const c_char, out_token: *mut c_int) -> u32;
+        fn notify_get_state(token: c_int, state: *mut u64) -> u32;
+    }
+
+    static TOKEN: OnceLock<Option<c_int>> = OnceLock::new();
+    let token = (*TOKEN.get_or_init(|| {
+        let mut token = 0;
+        // SAFETY: the name is NUL-terminated and `token` outlives the call
+        let status = unsafe {
+            notify_register_check(
+                c"com.apple.system.thermalpressurelevel".as_ptr(),
+                &mut token,
+            )
+        };
+        (status == 0).then_some(token)
+    }))?;
+    let mut state = 0;
+    // SAFETY: `token` is registered for good; `state` outlives the call
+    (unsafe { notify_get_state(token, &mut state) } == 0).then_some(state)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn thermal_level() -> Option<u64> {
+    None
+}
+
+/// How hard pressed the machine is, for a [`NiceMode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pressure {
+    Calm,
+    Busy,
+    Hot,
+}
+
+impl NiceMode {
+    /// How hard pressed a machine with `cores` cores is, judged by this
+    /// mode.
+    pub fn pressure(self, load: Load, cores: usize) -> Pressure {
+        let (busy, hot) = match self {
+            NiceMode::Off => return Pressure::Calm,
+            NiceMode::Polite => (1.0, THERMAL_HEAVY),
+            NiceMode::Aggressive => (0.5, THERMAL_MODERATE),
+        };
+        match load {
+            Load {
+                thermal: Some(level),
+                ..
+            } if level >= hot => Pressure::Hot,
+            Load { average, .. } if average / cores as f64 >= busy => Pressure::Busy,
+            _ => Pressure::Calm,
+        }
+    }
+
+    /// How many repos are resolved at once under `pressure` on `cores`
+    /// cores: `None` for no limit, 0 for a pause.
+    pub fn slots(self, pressure: Pressure, cores: usize) -> Option<usize> {
+        match (self, pressure) {
+            (NiceMode::Off, _) | (_, Pressure::Calm) => None,
+            (NiceMode::Polite, Pressure::Busy) => Some((cores / 2).max(1)),
+            (NiceMode::Polite, Pressure::Hot) | (NiceMode::Aggressive, Pressure::Busy) => Some(1),
+            (NiceMode::Aggressive, Pressure::Hot) => Some(0),
+        }
+    }
+
+    /// Threads for a walk starting now: 0 (one per core) unless the machine
+    /// is pressed, then as many as repos resolved at once, at least one.
+    /// Walks start before the run's other work, so the load is all others'.
+    pub fn walk_threads(self) -> usize {
+        if self == NiceMode::Off {
+            return 0;
+        }
+        let load = Load::sample();
+        match self.slots(self.pressure(load, cores()), cores()) {
+            None => 0,
+            Some(slots) => {
+                debug!("Walking on {} thread(s): {load}", slots.max(1));
+                slots.max(1)
+            }
+        }
+    }
+}
+
+/// Holds back the resolution of repos while the machine is busy or hot,
+/// as its [`NiceMode`] asks: every repo is resolved inside a [`Slot`], and
+/// only as many slots are handed out at once as the current load allows.
+/// The load is sampled at most once per [`SAMPLE_EVERY`].
+///
+/// The load average counts the run's own threads too. Each repo being
+/// resolved is taken to keep one thread runnable, and that much is left out
+/// of the load, so a run never holds back for its own work.
+#[derive(Debug)]
+pub struct Throttle {
+    mode: NiceMode,
+    cores: usize,
+    sample: fn() -> Load,
+    sample_every: Duration,
+    max_pause: Duration,
+    state: Mutex<State>,
+    freed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    active: usize,
+    slots: Option<usize>,
+    sampled_at: Option<Instant>,
+    /// Since when no slot was handed out for the heat.
+    paused_since: Option<Instant>,
+}
+
+impl Throttle {
+    pub fn new(mode: NiceMode) -> Self {
+        Throttle::with_sampler(mode, cores(), Load::sample)
+    }
+
+    fn with_sampler(mode: NiceMode, cores: usize, sample: fn() -> Load) -> Self {
+        Throttle {
+            mode,
+            cores,
+            sample,
+            sample_every: SAMPLE_EVERY,
+            max_pause: MAX_PAUSE,
+            state: Mutex::default(),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Wait for a slot to resolve a repo in; it is given back on drop.
+    pub fn enter(&self) -> Slot<'_> {
+        if self.mode == NiceMode::Off {
+            return Slot { throttle: None };
+        }
+        let mut state = self.state.lock().expect("throttle poisoned");
+        loop {
+            self.resample(&mut state);
+            let slots = match (state.slots, state.paused_since) {
+                (Some(0), Some(since)) if since.elapsed() >= self.max_pause => Some(1),
+                (slots, _) => slots,
+            };
+            if slots.is_none_or(|slots| state.active < slots) {
+                state.active += 1;
+                return Slot {
+                    throttle: Some(self),
+                };
+            }
+            state = self
+                .freed
+                .wait_timeout(state, self.sample_every)
+                .expect("throttle poisoned")
+                .0;
+        }
+    }
+
+    fn resample(&self, state: &mut State) {
+        if state
+            .sampled_at
+            .is_some_and(|at| at.elapsed() < self.sample_every)
+        {
+            return;
+        }
+        state.sampled_at = Some(Instant::now());
+        let load = (self.sample)().without(state.active);
+        let slots = self
+            .mode
+            .slots(self.mode.pressure(load, self.cores), self.cores);
+        if slots != state.slots {
+            match slots {
+                None => info!("Machine calm again ({load} besides us); resolving at full speed"),
+                Some(0) => info!("Machine hot ({load} besides us); pausing between repos"),
+                Some(n) => {
+                    info!("Machine busy ({load} besides us); resolving {n} repo(s) at a time")
+                }
+            }
+        }
+        state.paused_since = match slots {
+            Some(0) => state.paused_since.or(Some(Instant::now())),
+            _ => None,
+        };
+        state.slots = slots;
+    }
+}
+
+/// A slot of a [`Throttle`], held while a repo is resolved.
+#[must_use = "the slot is given back when dropped"]
+pub struct Slot<'a> {
+    throttle: Option<&'a Throttle>,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        if let Some(throttle) = self.throttle {
+            throttle.state.lock().expect("throttle poisoned").active -= 1;
+            throttle.freed.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    };
+
+    fn throttle(mode: NiceMode, cores: usize, sample: fn() -> Load) -> Throttle {
+        Throttle {
+            sample_every: Duration::from_millis(10),
+            max_pause: Duration::from_millis(200),
+            ..Throttle::with_sampler(mode, cores, sample)
+        }
+    }
+
+    /// Whether `throttle` hands out a second slot while one is held.
+    fn second_slot_while_first_held(throttle: &Throttle) -> bool {
+        let _first = throttle.enter();
+        let (tx, rx) = mpsc::channel();
+        thread::scope(|s| {
+            s.spawn(|| {
+                let _second = throttle.enter();
+                let _ = tx.send(());
+            });
+            let granted = rx.recv_timeout(Duration::from_millis(300)).is_ok();
+            drop(_first);
+            granted
+        })
+    }
+
+    #[test]
+    fn test_throttle_leaves_its_own_load_out() {
+        // 2.5 runnable threads on 4 cores: busy for `aggressive` (from 2),
+        // but with our one repo left out the rest of the machine is calm
+        let ours = throttle(NiceMode::Aggressive, 4, || Load {
+            average: 2.5,
+            thermal: None,
+        });
+        assert!(second_slot_while_first_held(&ours));
+        // Others keep it busy still: one repo at a time
+        let others = throttle(NiceMode::Aggressive, 4, || Load {
+            average: 3.5,
+            thermal: None,
+        });
+        assert!(!second_slot_while_first_held(&others));
+    }
+
+    #[test]
+    fn test_heat_pauses_until_max_pause_then_one_repo_at_a_time() {
+        let hot = throttle(NiceMode::Aggressive, 8, || Load {
+            average: 0.0,
+            thermal: Some(THERMAL_MODERATE),
+        });
+        let start = Instant::now();
+        let (active, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let _slot = hot.enter();
+                    most.fetch_max(active.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    active.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert!(start.elapsed() >= hot.max_pause);
+        assert_eq!(most.load(Ordering::SeqCst), 1);
+    }
+}
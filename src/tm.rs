@@ -0,0 +1,180 @@
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+use tracing::debug;
+
+use crate::tmutil::tmutil_binary;
+
+/// A Time Machine backup destination, as reported by `tmutil destinationinfo`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Destination {
+    pub name: Option<String>,
+    /// `Local` or `Network`.
+    pub kind: Option<String>,
+    pub id: Option<String>,
+    /// Where the destination is mounted, if it is currently mounted.
+    pub mount_point: Option<String>,
+    /// The network location of `Network` destinations.
+    pub url: Option<String>,
+}
+
+/// List the configured backup destinations.
+pub fn list_destinations() -> Result<Vec<Destination>> {
+    Ok(parse_destination_info(&run_tmutil_capture(&[
+        "destinationinfo",
+    ])?))
+}
+
+/// List the local APFS snapshots of the volume mounted at `mount_point`.
+pub fn list_local_snapshots(mount_point: &Path) -> Result<Vec<String>> {
+    let out = run_tmutil_capture(&["listlocalsnapshots", &mount_point.to_string_lossy()])?;
+    Ok(parse_snapshot_list(&out))
+}
+
+/// List the backups on the current destination, oldest first
+/// (`tmutil listbackups`).
+pub fn list_backups() -> Result<Vec<PathBuf>> {
+    let out = run_tmutil_capture(&["listbackups"])?;
+    Ok(parse_snapshot_list(&out)
+        .into_iter()
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Delete `path`, a backup or a path inside one, from the backup destination
+/// (`tmutil delete -p`).  Needs root.
+pub fn delete_backup_path(path: &Path) -> Result<()> {
+    run_tmutil_capture(&["delete", "-p", &path.to_string_lossy()]).map(drop)
+}
+
+/// Ask Time Machine to thin the local snapshots of `mount_point`, returning
+/// the snapshots it removed.
+///
+/// `purge_amount` (bytes) and `urgency` (1–4) are passed through to
+/// `tmutil thinlocalsnapshots`; an urgency requires a purge amount.
+pub fn thin_local_snapshots(
+    mount_point: &Path,
+    purge_amount: Option<u64>,
+    urgency: Option<u8>,
+) -> Result<Vec<String>> {
+    let mut args = vec![
+        "thinlocalsnapshots".to_string(),
+        mount_point.to_string_lossy().into_owned(),
+    ];
+    match (purge_amount, urgency) {
+        (Some(amount), urgency) => {
+            args.push(amount.to_string());
+            args.extend(urgency.map(|u| u.to_string()));
+        }
+        (None, Some(_)) => bail!("an urgency requires a purge amount"),
+        (None, None) => {}
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    Ok(parse_snapshot_list(&run_tmutil_capture(&args)?))
+}
+
+/// Run `tmutil <args>` and return its stdout, failing on a non-zero exit
+/// status.
+fn run_tmutil_capture(args: &[&str]) -> Result<String> {
+    debug!("tmutil {}", args.join(" "));
+    let output = Command::new(tmutil_binary(None))
+        .args(args)
+        .output()
+        .with_context(|| format!("spawning tmutil {}", args[0]))?;
+    if !output.status.success() {
+        bail!(
+            "tmutil {} failed ({}): {}",
+            args[0],
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parse `tmutil destinationinfo` output: `Key : Value` blocks separated by
+/// lines of `=`.  Unknown keys are ignored.
+fn parse_destination_info(text: &str) -> Vec<Destination> {
+    let mut destinations = Vec::new();
+    let mut current: Option<Destination> = None;
+    for line in text.lines() {
+        // The destination in use may be marked with a leading `>`.
+        let line = line.trim().trim_start_matches('>').trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.chars().all(|c| c == '=') {
+            destinations.extend(current.take());
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = Some(value.trim().to_string());
+        let dest = current.get_or_insert_with(Destination::default);
+        match key.trim() {
+            "Name" => dest.name = value,
+            "Kind" => dest.kind = value,
+            "ID" => dest.id = value,
+            "Mount Point" => dest.mount_point = value,
+            "URL" => dest.url = value,
+            _ => {}
+        }
+    }
+    destinations.extend(current);
+    destinations
+}
+
+/// Parse a snapshot listing: every non-empty line except a trailing-colon
+/// header such as `Snapshots for disk /:`.
+fn parse_snapshot_list(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.ends_with(':'))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_destination_info_and_snapshots() {
+        let info = "\
+====================================================
+Name          : Backup Drive
+Kind          : Local
+Mount Point   : /Volumes/Backup Drive
+ID            : 6D5A1B2C-0000-4000-8000-000000000001
+====================================================
+> Name        : NAS
+Kind          : Network
+URL           : smb://user@nas.local/TimeMachine
+ID            : 6D5A1B2C-0000-4000-8000-000000000002
+";
+        let destinations = parse_destination_info(info);
+        assert_eq!(destinations.len(), 2);
+        assert_eq!(destinations[0].name.as_deref(), Some("Backup Drive"));
+        assert_eq!(
+            destinations[0].mount_point.as_deref(),
+            Some("/Volumes/Backup Drive")
+        );
+        assert_eq!(destinations[1].name.as_deref(), Some("NAS"));
+        assert_eq!(
+            destinations[1].url.as_deref(),
+            Some("smb://user@nas.local/TimeMachine")
+        );
+        assert_eq!(destinations[1].mount_point, None);
+        assert!(parse_destination_info("No destinations configured.\n").is_empty());
+
+        let thinned = "Thinned local snapshots:\n2026-10-14-101500\n2026-10-15-101500\n\n";
+        assert_eq!(
+            parse_snapshot_list(thinned),
+            vec!["2026-10-14-101500", "2026-10-15-101500"]
+        );
+    }
+}
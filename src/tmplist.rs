@@ -0,0 +1,416 @@
+use anyhow::{Context, Result, bail};
+use chrono::Local;
+use fd_lock::RwLock as FdRwLock;
+use std::{
+    fs,
+    io::Write,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use tempfile::NamedTempFile;
+use tracing::{debug, info, warn};
+
+use crate::tmutil::{ExclusionManager, TmutilManager};
+use crate::warnings::Warning;
+
+/// The Time Machine preferences file holding fixed-path exclusions.
+pub const TIME_MACHINE_PLIST: &str = "/Library/Preferences/com.apple.TimeMachine.plist";
+
+/// The key in [`TIME_MACHINE_PLIST`] listing fixed-path exclusions.
+const SKIP_PATHS_KEY: &str = "SkipPaths";
+
+/// Suffix of the copies of the plist taken before each edit, followed by
+/// the time of the edit.
+const BACKUP_SUFFIX: &str = "letitgo-backup-";
+
+/// How many of those copies are kept, the newest.
+const KEEP_BACKUPS: usize = 10;
+
+/// The `defaults` the system plist is written through, so that `cfprefsd`,
+/// which caches the domain, sees the change.
+const DEFAULTS: &str = "/usr/bin/defaults";
+
+/// Fixed-path backend that edits `SkipPaths` in the Time Machine plist
+/// directly instead of spawning one `tmutil -p` per path.
+///
+/// Sticky-mode calls (`fixed_path == false`) are delegated to
+/// [`TmutilManager`].  Writing the system plist requires root.
+pub struct PlistManager {
+    plist_path: PathBuf,
+    sticky: TmutilManager,
+    /// The `defaults` to import edits with; without one the file is
+    /// replaced directly.
+    defaults: Option<PathBuf>,
+}
+
+impl PlistManager {
+    /// Manage the system Time Machine plist, through `defaults`.
+    pub fn system() -> Self {
+        Self::new(TIME_MACHINE_PLIST).with_defaults(DEFAULTS)
+    }
+
+    /// Manage the plist at `plist_path`, replacing the file on each edit
+    /// (used under `--root` and by tests).
+    pub fn new(plist_path: impl Into<PathBuf>) -> Self {
+        PlistManager {
+            plist_path: plist_path.into(),
+            sticky: TmutilManager::default(),
+            defaults: None,
+        }
+    }
+
+    /// Write edits with `defaults import` run from `binary`.
+    pub fn with_defaults(mut self, binary: impl Into<PathBuf>) -> Self {
+        self.defaults = Some(binary.into());
+        self
+    }
+
+    /// Use `sticky` for sticky-mode calls.
+    pub fn with_sticky(mut self, sticky: TmutilManager) -> Self {
+        self.sticky = sticky;
+        self
+    }
+
+    /// Return the paths currently listed in `SkipPaths`.
+    pub fn skip_paths(&self) -> Result<Vec<String>> {
+        let file = fs::File::open(&self.plist_path)
+            .with_context(|| format!("opening {}", self.plist_path.display()))?;
+        let lock = FdRwLock::new(file);
+        let guard = lock
+            .read()
+            .with_context(|| format!("locking {}", self.plist_path.display()))?;
+        let root = parse_plist(&guard, &self.plist_path)?;
+        Ok(skip_paths_of(&root))
+    }
+
+    /// Apply `edit` to the `SkipPaths` list under an exclusive lock, writing
+    /// the plist back only if `edit` returns `true`.
+    ///
+    /// The plist is never rewritten in place: the new contents go to a
+    /// temporary file given the original's owner and mode, synced and
+    /// renamed over it — or, for the system plist, to `defaults import`, so
+    /// `cfprefsd` does not keep serving (or write back) its cached copy.
+    /// Either way a crash leaves the old or the new plist, never a torn one.
+    /// A timestamped copy is taken before every write.
+    fn edit_skip_paths(&self, edit: impl FnOnce(&mut Vec<String>) -> bool) -> Result<()> {
+        let mut edit = Some(edit);
+        loop {
+            let file = fs::File::open(&self.plist_path)
+                .with_context(|| format!("opening {}", self.plist_path.display()))?;
+            let mut lock = FdRwLock::new(file);
+            let guard = lock
+                .write()
+                .with_context(|| format!("locking {}", self.plist_path.display()))?;
+            // Replaced while we waited for the lock: lock the new file
+            if !self.is_current(&guard)? {
+                debug!("{} was replaced; locking again", self.plist_path.display());
+                continue;
+            }
+
+            let mut root = parse_plist(&guard, &self.plist_path)?;
+            let mut skip = skip_paths_of(&root);
+            let edit = edit.take().expect("edited once");
+            if !edit(&mut skip) {
+                return Ok(());
+            }
+            let Some(dict) = root.as_dictionary_mut() else {
+                bail!("{} is not a dictionary", self.plist_path.display());
+            };
+            dict.insert(
+                SKIP_PATHS_KEY.to_string(),
+                plist::Value::Array(skip.into_iter().map(plist::Value::String).collect()),
+            );
+
+            self.backup()?;
+            return match &self.defaults {
+                Some(defaults) => self.import(defaults, &root),
+                None => self.replace(&guard, &root),
+            };
+        }
+    }
+
+    /// Whether `file` is still the file at the plist path.
+    fn is_current(&self, file: &fs::File) -> Result<bool> {
+        let (held, current) = (file.metadata(), fs::metadata(&self.plist_path));
+        let (held, current) = (
+            held.with_context(|| format!("reading {}", self.plist_path.display()))?,
+            current.with_context(|| format!("reading {}", self.plist_path.display()))?,
+        );
+        Ok((held.dev(), held.ino()) == (current.dev(), current.ino()))
+    }
+
+    /// Write `root` to a temporary file next to the plist, with the owner
+    /// and mode of `original`, and rename it over the plist.
+    fn replace(&self, original: &fs::File, root: &plist::Value) -> Result<()> {
+        let path = &self.plist_path;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let meta = original
+            .metadata()
+            .with_context(|| format!("reading {}", path.display()))?;
+        let mut tmp = NamedTempFile::new_in(dir)
+            .with_context(|| format!("creating a temporary file in {}", dir.display()))?;
+        root.to_writer_binary(&mut tmp)
+            .with_context(|| format!("serializing {}", path.display()))?;
+        let file = tmp.as_file();
+        let created = file.metadata()?;
+        if (created.uid(), created.gid()) != (meta.uid(), meta.gid()) {
+            std::os::unix::fs::fchown(file, Some(meta.uid()), Some(meta.gid()))
+                .with_context(|| format!("giving {} its owner", tmp.path().display()))?;
+        }
+        file.set_permissions(meta.permissions())
+            .and_then(|_| file.sync_all())
+            .with_context(|| format!("writing {}", tmp.path().display()))?;
+        tmp.persist(path)
+            .with_context(|| format!("replacing {}", path.display()))?;
+        fs::File::open(dir)
+            .and_then(|d| d.sync_all())
+            .with_context(|| format!("syncing {}", dir.display()))
+    }
+
+    /// Replace the plist's domain with `root` through `defaults import`.
+    fn import(&self, defaults: &Path, root: &plist::Value) -> Result<()> {
+        let path = &self.plist_path;
+        let mut xml = Vec::new();
+        root.to_writer_xml(&mut xml)
+            .with_context(|| format!("serializing {}", path.display()))?;
+        let domain = path.with_extension("");
+        let mut child = Command::new(defaults)
+            .arg("import")
+            .arg(&domain)
+            .arg("-")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("running {}", defaults.display()))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(&xml)
+                .with_context(|| format!("writing {} through defaults", path.display()))?;
+        }
+        let output = child
+            .wait_with_output()
+            .with_context(|| format!("running {}", defaults.display()))?;
+        if !output.status.success() {
+            bail!(
+                "defaults import {} failed: {}",
+                domain.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
+    /// Copy the plist to `<plist>.letitgo-backup-<time>`, keeping only the
+    /// [`KEEP_BACKUPS`] newest copies.
+    fn backup(&self) -> Result<()> {
+        let path = &self.plist_path;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let prefix = format!("{name}.{BACKUP_SUFFIX}");
+        let backup = path.with_file_name(format!(
+            "{prefix}{}",
+            Local::now().format("%Y%m%dT%H%M%S%.6f")
+        ));
+        fs::copy(path, &backup)
+            .with_context(|| format!("backing up {} to {}", path.display(), backup.display()))?;
+        info!("Saved the Time Machine plist to {}", backup.display());
+
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let mut backups: Vec<PathBuf> = fs::read_dir(dir)
+            .with_context(|| format!("reading {}", dir.display()))?
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().starts_with(&prefix))
+            .map(|e| e.path())
+            .collect();
+        // The timestamps sort by name
+        backups.sort();
+        for old in &backups[..backups.len().saturating_sub(KEEP_BACKUPS)] {
+            if let Err(e) = fs::remove_file(old) {
+                warn!("Could not remove old backup {}: {e}", old.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ExclusionManager for PlistManager {
+    fn add_exclusions(&self, paths: &[&Path], fixed_path: bool) -> Result<()> {
+        if !fixed_path {
+            return self.sticky.add_exclusions(paths, fixed_path);
+        }
+        if paths.is_empty() {
+            return Ok(());
+        }
+        self.edit_skip_paths(|skip| {
+            let before = skip.len();
+            for path in paths {
+                let path = path.to_string_lossy();
+                if !skip.iter().any(|s| *s == path) {
+                    skip.push(path.into_owned());
+                }
+            }
+            info!("Added {} path(s) to SkipPaths", skip.len() - before);
+            skip.len() != before
+        })
+    }
+
+    fn remove_exclusions(&self, paths: &[&Path], fixed_path: bool) -> Result<()> {
+        if !fixed_path {
+            return self.sticky.remove_exclusions(paths, fixed_path);
+        }
+        if paths.is_empty() {
+            return Ok(());
+        }
+        self.edit_skip_paths(|skip| {
+            let before = skip.len();
+            skip.retain(|s| !paths.iter().any(|p| p.as_os_str() == s.as_str()));
+            info!("Removed {} path(s) from SkipPaths", before - skip.len());
+            skip.len() != before
+        })
+    }
+
+    fn is_excluded(&self, path: &Path) -> Result<bool> {
+        let skip = self.skip_paths()?;
+        if skip.iter().any(|s| path.as_os_str() == s.as_str()) {
+            return Ok(true);
+        }
+        self.sticky.is_excluded(path)
+    }
+
+    fn take_warnings(&self) -> Vec<Warning> {
+        self.sticky.take_warnings()
+    }
+}
+
+/// Read the plist held by `file`.
+fn parse_plist(file: &fs::File, path: &Path) -> Result<plist::Value> {
+    let root = plist::Value::from_reader(std::io::BufReader::new(file))
+        .with_context(|| format!("parsing {}", path.display()))?;
+    if root.as_dictionary().is_none() {
+        bail!("{} is not a dictionary", path.display());
+    }
+    Ok(root)
+}
+
+/// Return the string entries of `SkipPaths`, ignoring anything else.
+fn skip_paths_of(root: &plist::Value) -> Vec<String> {
+    let Some(entries) = root
+        .as_dictionary()
+        .and_then(|d| d.get(SKIP_PATHS_KEY))
+        .and_then(|v| v.as_array())
+    else {
+        return Vec::new();
+    };
+    entries
+        .iter()
+        .filter_map(|v| {
+            let s = v.as_string();
+            if s.is_none() {
+                warn!("Ignoring non-string SkipPaths entry: {:?}", v);
+            }
+            s.map(str::to_string)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    fn write_plist(path: &Path) {
+        let mut dict = plist::Dictionary::new();
+        dict.insert("AutoBackup".into(), plist::Value::Boolean(true));
+        dict.insert(
+            SKIP_PATHS_KEY.into(),
+            plist::Value::Array(vec!["/existing".into()]),
+        );
+        plist::Value::Dictionary(dict).to_file_binary(path).unwrap();
+    }
+
+    fn backups(dir: &Path) -> Vec<Vec<u8>> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+            .unwrap()
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.to_string_lossy().contains(BACKUP_SUFFIX))
+            .collect();
+        paths.sort();
+        paths.iter().map(|p| fs::read(p).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_plist_skip_paths_edit_and_backup() {
+        let tmp = tempdir().unwrap();
+        let plist_path = tmp.path().join("com.apple.TimeMachine.plist");
+        write_plist(&plist_path);
+        fs::set_permissions(&plist_path, fs::Permissions::from_mode(0o644)).unwrap();
+        let original = fs::read(&plist_path).unwrap();
+
+        let manager = PlistManager::new(&plist_path);
+        manager
+            .add_exclusions(&[Path::new("/a"), Path::new("/existing")], true)
+            .unwrap();
+        assert_eq!(manager.skip_paths().unwrap(), vec!["/existing", "/a"]);
+        assert!(manager.is_excluded(Path::new("/a")).unwrap());
+        let added = fs::read(&plist_path).unwrap();
+        assert_eq!(backups(tmp.path()), std::slice::from_ref(&original));
+
+        manager
+            .remove_exclusions(&[Path::new("/existing")], true)
+            .unwrap();
+        assert_eq!(manager.skip_paths().unwrap(), vec!["/a"]);
+        // Other keys and the mode survive the replacement
+        let root = plist::Value::from_file(&plist_path).unwrap();
+        assert_eq!(
+            root.as_dictionary().unwrap().get("AutoBackup"),
+            Some(&plist::Value::Boolean(true))
+        );
+        let mode = fs::metadata(&plist_path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o644);
+        // A copy before each edit, the newest few kept; no edit, no copy
+        assert_eq!(backups(tmp.path()), [original, added]);
+        manager.remove_exclusions(&[Path::new("/b")], true).unwrap();
+        assert_eq!(backups(tmp.path()).len(), 2);
+        for i in 0..KEEP_BACKUPS {
+            manager
+                .add_exclusions(&[Path::new(&format!("/c{i}"))], true)
+                .unwrap();
+        }
+        assert_eq!(backups(tmp.path()).len(), KEEP_BACKUPS);
+    }
+
+    #[test]
+    fn test_system_plist_is_written_through_defaults() {
+        let tmp = tempdir().unwrap();
+        let plist_path = tmp.path().join("com.apple.TimeMachine.plist");
+        write_plist(&plist_path);
+        // Stands in for `defaults import <domain> -`, as cfprefsd would
+        // store the domain
+        let defaults = tmp.path().join("defaults");
+        fs::write(
+            &defaults,
+            "#!/bin/sh\n[ \"$1\" = import ] && [ \"$3\" = - ] || exit 1\n\
+             cat > \"$2.plist.new\" && mv \"$2.plist.new\" \"$2.plist\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&defaults, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let manager = PlistManager::new(&plist_path).with_defaults(&defaults);
+        manager.add_exclusions(&[Path::new("/a")], true).unwrap();
+        assert_eq!(manager.skip_paths().unwrap(), vec!["/existing", "/a"]);
+
+        fs::write(
+            &defaults,
+            "#!/bin/sh\necho 'Rep argument is not a dictionary' >&2\nexit 1\n",
+        )
+        .unwrap();
+        let err = manager
+            .add_exclusions(&[Path::new("/b")], true)
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("not a dictionary"), "{err:#}");
+        assert_eq!(manager.skip_paths().unwrap(), vec!["/existing", "/a"]);
+    }
+}
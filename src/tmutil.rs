@@ -1,58 +1,47 @@
 use anyhow::{Context, Result};
 use std::{
-    io::Read,
-    path::Path,
-    process::{Command, Stdio},
-    time::{Duration, Instant},
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
 };
-use tracing::{debug, info, warn};
+use tracing::debug;
 
-use crate::error::is_tmutil_safe_error;
+use crate::warnings::Warning;
 
 /// The extended attribute that `tmutil addexclusion` sets in "sticky" mode.
 /// Checking for this xattr lets us skip redundant `tmutil` calls for paths
 /// that are already excluded.
 pub const BACKUP_EXCLUDE_XATTR: &str = "com.apple.metadata:com_apple_backup_excludeItem";
 
-/// Maximum number of paths per `tmutil` subprocess invocation.
+/// The string `tmutil addexclusion` stores in the backup-exclusion xattr.
+pub const BACKUP_EXCLUDE_XATTR_OWNER: &str = "com.apple.backupd";
+
+/// The value `tmutil addexclusion` writes into the backup-exclusion xattr:
+/// [`BACKUP_EXCLUDE_XATTR_OWNER`] encoded as a binary plist.
 ///
-/// Seems unbatches ones is faster.
-const TMUTIL_BATCH_SIZE: usize = 1;
-
-/// How long to wait for a batched `tmutil` subprocess before killing it.
-/// Setting xattrs is near-instant; if tmutil takes longer than this the
-/// process is likely blocked on a system lock or unresponsive filesystem.
-const TMUTIL_BATCH_TIMEOUT: Duration = Duration::from_secs(10);
-
-/// Shorter timeout used when retrying a single path after a batch timeout.
-const TMUTIL_SINGLE_TIMEOUT: Duration = Duration::from_secs(5);
-
-/// The value that `tmutil addexclusion` writes into the backup-exclusion xattr.
-/// This is a binary plist encoding of the string "com.apple.backupd", exactly
-/// matching what `tmutil` produces (verified via `xattr -px` on a real file).
-pub const BACKUP_EXCLUDE_XATTR_VALUE: &[u8] = &[
-    0x62, 0x70, 0x6C, 0x69, 0x73, 0x74, 0x30, 0x30, // bplist00
-    0x5F, 0x10, 0x11, // string type, length 17
-    0x63, 0x6F, 0x6D, 0x2E, 0x61, 0x70, 0x70, 0x6C, // com.appl
-    0x65, 0x2E, 0x62, 0x61, 0x63, 0x6B, 0x75, 0x70, // e.backup
-    0x64, // d
-    0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // plist offset table
-    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // & trailer
-    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x1C,
-];
+/// [`TmutilManager`] checks it once against what `tmutil` itself writes and
+/// uses `tmutil`'s value instead should a macOS release change it.
+pub fn backup_exclude_xattr_value() -> Vec<u8> {
+    let mut bytes = Vec::new();
+    plist::to_writer_binary(
+        &mut bytes,
+        &plist::Value::String(BACKUP_EXCLUDE_XATTR_OWNER.to_string()),
+    )
+    .expect("a string encodes as a plist");
+    bytes
+}
 
 /// When `true`, set/remove the backup-exclusion xattr directly instead of
 /// spawning `tmutil` subprocesses. This is much faster (pure syscall, no
 /// process overhead) and avoids the timeout issues seen with `tmutil`.
 /// Set to `false` to fall back to the old `tmutil`-based code path.
-const UPDATE_XATTR_DIRECTLY: bool = true;
+pub(crate) const UPDATE_XATTR_DIRECTLY: bool = true;
 
 /// Abstraction over Time Machine exclusion operations.
 ///
 /// Required to be `Send + Sync` so that `AppContext` (which owns
 /// `Box<dyn ExclusionManager>`) can be referenced across rayon scopes.
-/// The production implementation calls `/usr/bin/tmutil`; tests use
+/// The production implementation calls `tmutil` (see [`tmutil_binary`]); tests use
 /// [`MockExclusionManager`] to avoid touching the system.
 pub trait ExclusionManager: Send + Sync {
     /// Add Time Machine exclusions for each path in `paths`.
@@ -73,6 +62,36 @@ pub trait ExclusionManager: Send + Sync {
     /// Implemented by running `tmutil isexcluded` and checking for `[Excluded]`
     /// in stdout.
     fn is_excluded(&self, path: &Path) -> Result<bool>;
+
+    /// Return, for each path in `paths`, whether it is currently excluded.
+    ///
+    /// Used by `run` to find cached paths whose exclusion was lost (e.g. a
+    /// `target/` recreated by `cargo clean`).  The default implementation
+    /// calls [`is_excluded`](Self::is_excluded) per path and treats errors as
+    /// "not excluded".
+    fn are_excluded(&self, paths: &[&Path]) -> Vec<bool> {
+        paths
+            .iter()
+            .map(|p| self.is_excluded(p).unwrap_or(false))
+            .collect()
+    }
+
+    /// Take the warnings recorded since the last call: paths that timed out
+    /// or could not be changed without failing the whole call.  None by
+    /// default.
+    fn take_warnings(&self) -> Vec<Warning> {
+        Vec::new()
+    }
+
+    /// The number of paths per `tmutil` call this backend has tuned itself
+    /// to, kept in the cache for the next run; `None` when it does not tune.
+    fn batch_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// Start from `size`, a [`batch_size`](Self::batch_size) learned by an
+    /// earlier run.
+    fn resume_batch_size(&self, _size: usize) {}
 }
 
 /// Blanket impl so `Arc<T>` can be used as an `ExclusionManager` in tests.
@@ -88,267 +107,169 @@ impl<T: ExclusionManager> ExclusionManager for std::sync::Arc<T> {
     fn is_excluded(&self, path: &Path) -> Result<bool> {
         self.as_ref().is_excluded(path)
     }
-}
-
-// ─── Production implementation ───────────────────────────────────────────────
-
-/// Calls the real `/usr/bin/tmutil` binary.
-pub struct TmutilManager;
-
-impl ExclusionManager for TmutilManager {
-    fn add_exclusions(&self, paths: &[&Path], fixed_path: bool) -> Result<()> {
-        // In sticky mode (the default), tmutil sets an xattr on each path.
-        if !fixed_path {
-            // Skip paths that already carry the exclusion xattr.
-            let mut already = 0usize;
-            let filtered: Vec<&Path> = paths
-                .iter()
-                .copied()
-                .filter(|p| {
-                    if has_backup_exclusion(p) {
-                        debug!("Already excluded, skipping: {}", p.display());
-                        already += 1;
-                        false
-                    } else {
-                        true
-                    }
-                })
-                .collect();
-
-            if already > 0 {
-                info!(
-                    "Skipped {} already-excluded path(s) ({} remaining)",
-                    already,
-                    filtered.len()
-                );
-            }
-
-            if UPDATE_XATTR_DIRECTLY {
-                return set_backup_exclusion_xattr(&filtered);
-            }
-            return run_tmutil_batched("addexclusion", &filtered, false);
-        }
-
-        run_tmutil_batched("addexclusion", paths, fixed_path)
+    fn are_excluded(&self, paths: &[&Path]) -> Vec<bool> {
+        self.as_ref().are_excluded(paths)
     }
-
-    fn remove_exclusions(&self, paths: &[&Path], fixed_path: bool) -> Result<()> {
-        if UPDATE_XATTR_DIRECTLY && !fixed_path {
-            return remove_backup_exclusion_xattr(paths);
-        }
-        run_tmutil_batched("removeexclusion", paths, fixed_path)
+    fn take_warnings(&self) -> Vec<Warning> {
+        self.as_ref().take_warnings()
     }
-
-    fn is_excluded(&self, path: &Path) -> Result<bool> {
-        let output = Command::new("/usr/bin/tmutil")
-            .arg("isexcluded")
-            .arg(path)
-            .output()
-            .context("spawning tmutil isexcluded")?;
-
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        Ok(stdout.contains("[Excluded]"))
+    fn batch_size(&self) -> Option<usize> {
+        self.as_ref().batch_size()
+    }
+    fn resume_batch_size(&self, size: usize) {
+        self.as_ref().resume_batch_size(size)
     }
 }
 
-/// Check whether `path` already carries the Time Machine backup-exclusion xattr.
-///
-/// This is a lightweight syscall (no subprocess spawned) that lets us skip
-/// redundant `tmutil addexclusion` calls for paths that are already excluded.
-/// Returns `false` on any error (e.g. path no longer exists) so we fall
-/// through to tmutil which will handle the error appropriately.
-fn has_backup_exclusion(path: &Path) -> bool {
-    xattr::get(path, BACKUP_EXCLUDE_XATTR)
-        .ok()
-        .flatten()
-        .is_some()
+/// Confines another [`ExclusionManager`] to the paths under `root` (the
+/// global `--root`): changing the exclusion of any other path is an error.
+pub struct RootedManager {
+    root: PathBuf,
+    inner: Box<dyn ExclusionManager>,
 }
 
-/// Set the backup-exclusion xattr directly on each path (no tmutil subprocess).
-fn set_backup_exclusion_xattr(paths: &[&Path]) -> Result<()> {
-    if paths.is_empty() {
-        return Ok(());
-    }
-    info!("Setting exclusion xattr on {} path(s)", paths.len());
-    let mut failed = 0usize;
-    for path in paths {
-        if let Err(e) = xattr::set(path, BACKUP_EXCLUDE_XATTR, BACKUP_EXCLUDE_XATTR_VALUE) {
-            warn!("Failed to set xattr on {}: {}", path.display(), e);
-            failed += 1;
+impl RootedManager {
+    pub fn new(root: impl Into<PathBuf>, inner: Box<dyn ExclusionManager>) -> Self {
+        RootedManager {
+            root: root.into(),
+            inner,
         }
     }
-    if failed > 0 {
-        warn!("{} path(s) failed when setting exclusion xattr", failed);
+
+    fn check(&self, paths: &[&Path]) -> Result<()> {
+        if let Some(outside) = paths.iter().find(|p| !p.starts_with(&self.root)) {
+            anyhow::bail!(
+                "refusing to change the exclusion of {}: it is outside --root {}",
+                outside.display(),
+                self.root.display()
+            );
+        }
+        Ok(())
     }
-    Ok(())
 }
 
-/// Remove the backup-exclusion xattr directly from each path (no tmutil subprocess).
-fn remove_backup_exclusion_xattr(paths: &[&Path]) -> Result<()> {
-    if paths.is_empty() {
-        return Ok(());
+impl ExclusionManager for RootedManager {
+    fn add_exclusions(&self, paths: &[&Path], fixed_path: bool) -> Result<()> {
+        self.check(paths)?;
+        self.inner.add_exclusions(paths, fixed_path)
     }
-    info!("Removing exclusion xattr from {} path(s)", paths.len());
-    let mut failed = 0usize;
-    for path in paths {
-        if let Err(e) = xattr::remove(path, BACKUP_EXCLUDE_XATTR) {
-            // ENOATTR (93) is fine — the xattr was already absent.
-            if e.raw_os_error() != Some(93) {
-                warn!("Failed to remove xattr from {}: {}", path.display(), e);
-                failed += 1;
-            }
-        }
+    fn remove_exclusions(&self, paths: &[&Path], fixed_path: bool) -> Result<()> {
+        self.check(paths)?;
+        self.inner.remove_exclusions(paths, fixed_path)
     }
-    if failed > 0 {
-        warn!("{} path(s) failed when removing exclusion xattr", failed);
+    fn is_excluded(&self, path: &Path) -> Result<bool> {
+        self.inner.is_excluded(path)
     }
-    Ok(())
-}
-
-/// Process `paths` through `tmutil <verb>` in small batches with progress
-/// logging and a per-subprocess timeout.
-///
-/// If a batch times out, each path in that batch is retried individually
-/// with a shorter timeout.  Only truly problematic paths are skipped.
-fn run_tmutil_batched(verb: &str, paths: &[&Path], fixed_path: bool) -> Result<()> {
-    if paths.is_empty() {
-        return Ok(());
+    fn are_excluded(&self, paths: &[&Path]) -> Vec<bool> {
+        self.inner.are_excluded(paths)
     }
-
-    let chunks: Vec<&[&Path]> = paths.chunks(TMUTIL_BATCH_SIZE).collect();
-    let total = chunks.len();
-    let mut timed_out_count: usize = 0;
-
-    for (i, chunk) in chunks.iter().enumerate() {
-        info!(
-            "tmutil {}: batch {}/{} ({} path(s))",
-            verb,
-            i + 1,
-            total,
-            chunk.len()
-        );
-        if !run_tmutil(verb, chunk, fixed_path, TMUTIL_BATCH_TIMEOUT)? {
-            continue; // batch completed successfully
-        }
-
-        // Batch timed out — retry each path individually to isolate
-        // the problematic one(s) instead of skipping the whole batch.
-        warn!(
-            "Batch {}/{} timed out — retrying {} path(s) individually",
-            i + 1,
-            total,
-            chunk.len()
-        );
-        for path in *chunk {
-            if run_tmutil(
-                verb,
-                std::slice::from_ref(path),
-                fixed_path,
-                TMUTIL_SINGLE_TIMEOUT,
-            )? {
-                warn!("Skipping timed-out path: {}", path.display());
-                timed_out_count += 1;
-            }
-        }
+    fn take_warnings(&self) -> Vec<Warning> {
+        self.inner.take_warnings()
     }
-
-    if timed_out_count > 0 {
-        warn!(
-            "tmutil {}: {} path(s) skipped due to timeout",
-            verb, timed_out_count
-        );
+    fn batch_size(&self) -> Option<usize> {
+        self.inner.batch_size()
+    }
+    fn resume_batch_size(&self, size: usize) {
+        self.inner.resume_batch_size(size)
     }
-
-    Ok(())
 }
 
-/// Invoke `/usr/bin/tmutil <verb> [-p] <paths…>` with a timeout.
-///
-/// The subprocess is spawned and polled via `try_wait()`.
-/// Returns `Ok(true)` if the call timed out (subprocess was killed),
-/// `Ok(false)` if it completed normally.
-///
-/// Exit code 213 (path not found) is treated as non-fatal.  Any other
-/// non-zero exit code returns an error.  The `-p` flag is included only
-/// when `fixed_path` is `true`.
-fn run_tmutil(verb: &str, paths: &[&Path], fixed_path: bool, timeout: Duration) -> Result<bool> {
-    let mut cmd = Command::new("/usr/bin/tmutil");
-    cmd.arg(verb);
-    if fixed_path {
-        cmd.arg("-p");
-    }
-    for path in paths {
-        cmd.arg(path);
-    }
+// ─── Production implementation ───────────────────────────────────────────────
 
-    debug!(
-        "tmutil {} {} paths (fixed_path={}, timeout={}s)",
-        verb,
-        paths.len(),
-        fixed_path,
-        timeout.as_secs()
-    );
-
-    let mut child = cmd
-        .stdout(Stdio::null())
-        .stderr(Stdio::piped())
-        .spawn()
-        .with_context(|| format!("spawning tmutil {verb}"))?;
-
-    // Poll for completion with timeout
-    let start = Instant::now();
-    let status = loop {
-        match child.try_wait().context("checking tmutil status")? {
-            Some(status) => break status,
-            None if start.elapsed() >= timeout => {
-                let _ = child.kill();
-                let _ = child.wait(); // reap zombie
-                return Ok(true); // timed out
-            }
-            None => {
-                std::thread::sleep(Duration::from_millis(100));
-            }
-        }
-    };
-
-    // Check exit status
-    if !status.success() {
-        let code = status.code().unwrap_or(-1);
-        let stderr_output = child
-            .stderr
-            .take()
-            .map(|mut s| {
-                let mut buf = String::new();
-                let _ = s.read_to_string(&mut buf);
-                buf
-            })
-            .unwrap_or_default();
-        if is_tmutil_safe_error(code) {
-            warn!(
-                "tmutil {} exited with code {} (safe, ignored): {}",
-                verb, code, stderr_output
-            );
-        } else {
-            anyhow::bail!("tmutil {} failed (exit {}): {}", verb, code, stderr_output);
-        }
-    }
+// Built on every platform so that its subprocess handling can be tested
+// against a fake `tmutil` (see `tmutil_binary`)
+mod manager;
+pub use manager::{Batching, TmutilManager};
+
+/// The `tmutil` that ships with macOS.
+pub const TMUTIL_BINARY: &str = "/usr/bin/tmutil";
+
+/// The environment variable that points letitgo at another `tmutil`, e.g. a
+/// script implementing its CLI for tests on hosts without Time Machine.
+pub const TMUTIL_ENV: &str = "LETITGO_TMUTIL";
+
+/// The `tmutil` binary to run: `$LETITGO_TMUTIL`, else `configured` (the
+/// config's `tmutil_binary_path`), else [`TMUTIL_BINARY`].
+pub fn tmutil_binary(configured: Option<&Path>) -> PathBuf {
+    std::env::var_os(TMUTIL_ENV)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| configured.map(Path::to_path_buf))
+        .unwrap_or_else(|| PathBuf::from(TMUTIL_BINARY))
+}
 
-    Ok(false) // completed successfully
+/// Return the path of the most recent Time Machine backup, or `None` when
+/// there is none or it cannot be read (no destination, no Full Disk Access).
+pub fn latest_backup() -> Result<Option<PathBuf>> {
+    let output = Command::new(tmutil_binary(None))
+        .arg("latestbackup")
+        .output()
+        .context("spawning tmutil latestbackup")?;
+    if !output.status.success() {
+        debug!(
+            "tmutil latestbackup failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Ok(None);
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .map(PathBuf::from))
 }
 
 // ─── Mock implementation (for testing) ───────────────────────────────────────
 
 pub mod mock {
     use super::*;
+    use std::collections::{HashMap, HashSet};
     use std::path::PathBuf;
     use std::sync::Mutex;
 
+    /// Which [`ExclusionManager`] method a recorded call went to.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum MockOp {
+        Add,
+        Remove,
+        IsExcluded,
+        AreExcluded,
+    }
+
+    /// One recorded call, in the order calls were made.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct MockCall {
+        pub op: MockOp,
+        pub paths: Vec<PathBuf>,
+        /// The `fixed_path` flag (always `false` for the query operations).
+        pub fixed_path: bool,
+    }
+
+    /// A failure to inject into a call.
+    #[derive(Debug, Clone)]
+    pub enum MockFailure {
+        /// Return an error with this message.
+        Error(String),
+        /// Sleep for the duration, then return a timeout error.
+        Timeout(Duration),
+    }
+
     /// Records calls in-memory; never touches the system.
     /// Uses `Mutex` (not `RefCell`) to satisfy `Send + Sync`.
+    ///
+    /// Failures can be injected per call number ([`fail_call`](Self::fail_call))
+    /// or per path ([`fail_path`](Self::fail_path)) to exercise error handling.
+    /// Queries answer from the paths currently excluded: added and not
+    /// removed since.
     #[derive(Debug, Default)]
     pub struct MockExclusionManager {
         pub added: Mutex<Vec<PathBuf>>,
         pub removed: Mutex<Vec<PathBuf>>,
+        excluded: Mutex<HashSet<PathBuf>>,
+        calls: Mutex<Vec<MockCall>>,
+        failing_calls: HashMap<usize, MockFailure>,
+        failing_paths: HashSet<PathBuf>,
     }
 
     impl MockExclusionManager {
@@ -356,6 +277,20 @@ pub mod mock {
             Self::default()
         }
 
+        /// Make the `n`-th call (1-based, counting every method) fail.
+        pub fn fail_call(mut self, n: usize, failure: MockFailure) -> Self {
+            self.failing_calls.insert(n, failure);
+            self
+        }
+
+        /// Make adding or removing `path` fail.  The other paths of the same
+        /// call still succeed, and the call returns an error naming the
+        /// failed paths — like a partially failed batch.
+        pub fn fail_path(mut self, path: impl Into<PathBuf>) -> Self {
+            self.failing_paths.insert(path.into());
+            self
+        }
+
         /// Return a snapshot of all paths that have been passed to [`add_exclusions`](ExclusionManager::add_exclusions).
         pub fn added_paths(&self) -> Vec<PathBuf> {
             self.added.lock().unwrap().clone()
@@ -365,27 +300,111 @@ pub mod mock {
         pub fn removed_paths(&self) -> Vec<PathBuf> {
             self.removed.lock().unwrap().clone()
         }
-    }
 
-    impl ExclusionManager for MockExclusionManager {
-        fn add_exclusions(&self, paths: &[&Path], _fixed_path: bool) -> Result<()> {
-            self.added
-                .lock()
-                .unwrap()
-                .extend(paths.iter().map(|p| p.to_path_buf()));
-            Ok(())
+        /// Return every call made so far, in order (including failed ones).
+        pub fn calls(&self) -> Vec<MockCall> {
+            self.calls.lock().unwrap().clone()
         }
 
-        fn remove_exclusions(&self, paths: &[&Path], _fixed_path: bool) -> Result<()> {
-            self.removed
-                .lock()
+        /// Record a call and apply any failure injected for it.
+        fn record(&self, op: MockOp, paths: &[&Path], fixed_path: bool) -> Result<()> {
+            let n = {
+                let mut calls = self.calls.lock().unwrap();
+                calls.push(MockCall {
+                    op,
+                    paths: paths.iter().map(|p| p.to_path_buf()).collect(),
+                    fixed_path,
+                });
+                calls.len()
+            };
+            match self.failing_calls.get(&n) {
+                None => Ok(()),
+                Some(MockFailure::Error(msg)) => anyhow::bail!("{msg}"),
+                Some(MockFailure::Timeout(delay)) => {
+                    std::thread::sleep(*delay);
+                    anyhow::bail!("mock call {n} timed out after {}s", delay.as_secs_f64())
+                }
+            }
+        }
+
+        /// Store the non-failing `paths` in the log of `op` and add them to
+        /// or remove them from the excluded set, erroring if any failed.
+        fn apply(&self, op: MockOp, paths: &[&Path]) -> Result<()> {
+            let (failed, ok): (Vec<&Path>, Vec<&Path>) =
+                paths.iter().partition(|p| self.failing_paths.contains(**p));
+            let sink = if op == MockOp::Add {
+                &self.added
+            } else {
+                &self.removed
+            };
+            sink.lock()
                 .unwrap()
-                .extend(paths.iter().map(|p| p.to_path_buf()));
+                .extend(ok.iter().map(|p| p.to_path_buf()));
+            let mut excluded = self.excluded.lock().unwrap();
+            for path in ok {
+                if op == MockOp::Add {
+                    excluded.insert(path.to_path_buf());
+                } else {
+                    excluded.remove(path);
+                }
+            }
+            if !failed.is_empty() {
+                let list: Vec<String> = failed.iter().map(|p| p.display().to_string()).collect();
+                anyhow::bail!(
+                    "mock failure for {} path(s): {}",
+                    failed.len(),
+                    list.join(", ")
+                );
+            }
             Ok(())
         }
+    }
+
+    impl ExclusionManager for MockExclusionManager {
+        fn add_exclusions(&self, paths: &[&Path], fixed_path: bool) -> Result<()> {
+            self.record(MockOp::Add, paths, fixed_path)?;
+            self.apply(MockOp::Add, paths)
+        }
+
+        fn remove_exclusions(&self, paths: &[&Path], fixed_path: bool) -> Result<()> {
+            self.record(MockOp::Remove, paths, fixed_path)?;
+            self.apply(MockOp::Remove, paths)
+        }
 
         fn is_excluded(&self, path: &Path) -> Result<bool> {
-            Ok(self.added.lock().unwrap().contains(&path.to_path_buf()))
+            self.record(MockOp::IsExcluded, &[path], false)?;
+            Ok(self.excluded.lock().unwrap().contains(path))
+        }
+
+        fn are_excluded(&self, paths: &[&Path]) -> Vec<bool> {
+            if self.record(MockOp::AreExcluded, paths, false).is_err() {
+                return vec![false; paths.len()];
+            }
+            let excluded = self.excluded.lock().unwrap();
+            paths.iter().map(|p| excluded.contains(*p)).collect()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_xattr_value_matches_what_tmutil_writes() {
+        // `xattr -px com.apple.metadata:com_apple_backup_excludeItem` on a
+        // file excluded by `tmutil addexclusion`
+        let tmutil: &[u8] = &[
+            0x62, 0x70, 0x6C, 0x69, 0x73, 0x74, 0x30, 0x30, // bplist00
+            0x5F, 0x10, 0x11, // string type, length 17
+            0x63, 0x6F, 0x6D, 0x2E, 0x61, 0x70, 0x70, 0x6C, // com.appl
+            0x65, 0x2E, 0x62, 0x61, 0x63, 0x6B, 0x75, 0x70, // e.backup
+            0x64, // d
+            0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // plist offset table
+            0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // & trailer
+            0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x1C,
+        ];
+        assert_eq!(backup_exclude_xattr_value(), tmutil);
+    }
+}
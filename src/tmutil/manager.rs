@@ -0,0 +1,620 @@
+//! The Time Machine backend: sticky-mode xattrs and `tmutil`.
+
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+use wait_timeout::ChildExt;
+
+use super::{
+    BACKUP_EXCLUDE_XATTR, ExclusionManager, UPDATE_XATTR_DIRECTLY, backup_exclude_xattr_value,
+    tmutil_binary,
+};
+use crate::{
+    capabilities::Capabilities,
+    error::{TmutilError, TmutilErrorClass},
+    warnings::{Warning, WarningKind, Warnings},
+};
+
+/// How paths are passed to `tmutil` subprocesses (the config's `tmutil_*`
+/// batching settings).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Batching {
+    /// Number of paths per `tmutil` invocation; with `adaptive`, the size
+    /// to start from.
+    pub size: usize,
+    /// Tune the size to the observed latency (see [`next_batch_size`]).
+    pub adaptive: bool,
+    /// Largest size `adaptive` grows to.
+    pub max_size: usize,
+    /// How long to wait for one invocation before killing it.  Changing an
+    /// exclusion is near-instant; a slower `tmutil` is likely blocked on a
+    /// system lock or an unresponsive filesystem.
+    pub timeout: Duration,
+    /// Timeout for each path retried on its own after its batch timed out.
+    pub retry_timeout: Duration,
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Batching {
+            size: 1,
+            adaptive: true,
+            max_size: 64,
+            timeout: Duration::from_secs(10),
+            retry_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Calls the `tmutil` binary ([`tmutil_binary`], `/usr/bin/tmutil` unless
+/// overridden).
+#[derive(Debug, Clone)]
+pub struct TmutilManager {
+    /// Worker threads used to set/remove xattrs; `0` uses one per CPU.
+    pub xattr_concurrency: usize,
+    /// The `tmutil` to spawn.
+    binary: PathBuf,
+    batching: Batching,
+    /// Paths per `tmutil` invocation, as tuned so far.
+    batch_size: Arc<AtomicUsize>,
+    /// Paths that failed or timed out, until taken.
+    warnings: Arc<Warnings>,
+    /// The backup-exclusion xattr value, checked against `tmutil` on first
+    /// use (see [`exclusion_xattr_value`]).
+    xattr_value: Arc<OnceLock<Vec<u8>>>,
+    /// What `binary` is known to do on this system, probed on first use.
+    capabilities: Arc<OnceLock<Capabilities>>,
+}
+
+impl Default for TmutilManager {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl TmutilManager {
+    /// A manager that sets/removes xattrs on up to `xattr_concurrency`
+    /// threads (`0` = one per CPU).
+    pub fn new(xattr_concurrency: usize) -> Self {
+        TmutilManager {
+            xattr_concurrency,
+            binary: tmutil_binary(None),
+            batching: Batching::default(),
+            batch_size: Arc::new(AtomicUsize::new(Batching::default().size)),
+            warnings: Arc::default(),
+            xattr_value: Arc::default(),
+            capabilities: Arc::default(),
+        }
+    }
+
+    /// Spawn `binary` instead of the system `tmutil`: a script implementing
+    /// its CLI lets tests exercise batching, timeouts and exit codes.
+    pub fn with_binary(mut self, binary: impl Into<PathBuf>) -> Self {
+        self.binary = binary.into();
+        self
+    }
+
+    pub fn with_batching(mut self, batching: Batching) -> Self {
+        self.batching = batching;
+        self.batch_size = Arc::new(AtomicUsize::new(batching.size.max(1)));
+        self
+    }
+
+    /// The capabilities of `binary`, probed once per manager.
+    fn capabilities(&self) -> &Capabilities {
+        self.capabilities
+            .get_or_init(|| Capabilities::probe(&self.binary))
+    }
+
+    /// Run `tmutil <verb>` on `paths` in batches (see [`run_tmutil_batched`]).
+    fn run_batched(&self, verb: &str, paths: &[&Path], fixed_path: bool) -> Result<()> {
+        run_tmutil_batched(
+            self.capabilities(),
+            &self.batching,
+            &self.batch_size,
+            verb,
+            paths,
+            fixed_path,
+            &self.warnings,
+        )
+    }
+}
+
+impl ExclusionManager for TmutilManager {
+    fn add_exclusions(&self, paths: &[&Path], fixed_path: bool) -> Result<()> {
+        // In sticky mode (the default), tmutil sets an xattr on each path.
+        if !fixed_path {
+            // Skip paths that already carry the exclusion xattr.
+            let mut already = 0usize;
+            let filtered: Vec<&Path> = paths
+                .iter()
+                .copied()
+                .filter(|p| {
+                    if has_backup_exclusion(p) {
+                        debug!("Already excluded, skipping: {}", p.display());
+                        already += 1;
+                        false
+                    } else {
+                        true
+                    }
+                })
+                .collect();
+
+            if already > 0 {
+                info!(
+                    "Skipped {} already-excluded path(s) ({} remaining)",
+                    already,
+                    filtered.len()
+                );
+            }
+
+            if UPDATE_XATTR_DIRECTLY {
+                if filtered.is_empty() {
+                    return Ok(());
+                }
+                let value = self
+                    .xattr_value
+                    .get_or_init(|| exclusion_xattr_value(self.capabilities(), &self.batching));
+                return set_backup_exclusion_xattr(
+                    &filtered,
+                    value,
+                    self.xattr_concurrency,
+                    &self.warnings,
+                );
+            }
+            return self.run_batched("addexclusion", &filtered, false);
+        }
+
+        self.run_batched("addexclusion", paths, fixed_path)
+    }
+
+    fn remove_exclusions(&self, paths: &[&Path], fixed_path: bool) -> Result<()> {
+        if UPDATE_XATTR_DIRECTLY && !fixed_path {
+            return remove_backup_exclusion_xattr(paths, self.xattr_concurrency, &self.warnings);
+        }
+        self.run_batched("removeexclusion", paths, fixed_path)
+    }
+
+    fn is_excluded(&self, path: &Path) -> Result<bool> {
+        let output = Command::new(&self.binary)
+            .arg("isexcluded")
+            .arg(path)
+            .output()
+            .context("spawning tmutil isexcluded")?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(stdout.contains("[Excluded]"))
+    }
+
+    /// Checks the sticky-mode xattr only (one `getxattr` per path, no
+    /// subprocess), so fixed-path exclusions are reported as missing.
+    fn are_excluded(&self, paths: &[&Path]) -> Vec<bool> {
+        paths.iter().map(|p| has_backup_exclusion(p)).collect()
+    }
+
+    fn take_warnings(&self) -> Vec<Warning> {
+        self.warnings.take()
+    }
+
+    fn batch_size(&self) -> Option<usize> {
+        self.batching
+            .adaptive
+            .then(|| self.batch_size.load(Ordering::Relaxed))
+    }
+
+    fn resume_batch_size(&self, size: usize) {
+        if self.batching.adaptive {
+            let size = size.clamp(1, self.batching.max_size.max(1));
+            self.batch_size.store(size, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Check whether `path` already carries the Time Machine backup-exclusion xattr.
+///
+/// This is a lightweight syscall (no subprocess spawned) that lets us skip
+/// redundant `tmutil addexclusion` calls for paths that are already excluded.
+/// Returns `false` on any error (e.g. path no longer exists) so we fall
+/// through to tmutil which will handle the error appropriately.
+fn has_backup_exclusion(path: &Path) -> bool {
+    xattr::get(path, BACKUP_EXCLUDE_XATTR)
+        .ok()
+        .flatten()
+        .is_some()
+}
+
+/// The value to write into the backup-exclusion xattr: the generated
+/// [`backup_exclude_xattr_value`], unless `tmutil` writes something else on
+/// this system — then its value, copied from a scratch file it excluded.
+/// When `tmutil` cannot be asked, the generated value is used.
+fn exclusion_xattr_value(tmutil: &Capabilities, batching: &Batching) -> Vec<u8> {
+    let generated = backup_exclude_xattr_value();
+    match tmutil_xattr_value(tmutil, batching) {
+        Ok(value) if value != generated => {
+            warn!(
+                "tmutil writes an exclusion xattr value other than the expected one \
+                 ({:02X?}); using tmutil's",
+                value
+            );
+            value
+        }
+        Ok(_) => generated,
+        Err(e) => {
+            debug!("Could not check the exclusion xattr value against tmutil: {e:#}");
+            generated
+        }
+    }
+}
+
+/// The backup-exclusion xattr value `tmutil addexclusion` sets on a scratch
+/// file.
+fn tmutil_xattr_value(tmutil: &Capabilities, batching: &Batching) -> Result<Vec<u8>> {
+    let scratch = tempfile::NamedTempFile::new().context("creating a scratch file")?;
+    let path = scratch.path();
+    match run_tmutil(
+        tmutil,
+        "addexclusion",
+        &[path],
+        false,
+        batching.retry_timeout,
+    )? {
+        Outcome::Done => {}
+        Outcome::TimedOut => anyhow::bail!("tmutil addexclusion timed out"),
+        Outcome::Failed(e) => return Err(e.into()),
+    }
+    xattr::get(path, BACKUP_EXCLUDE_XATTR)
+        .with_context(|| format!("reading the xattrs of {}", path.display()))?
+        .with_context(|| format!("tmutil set no exclusion xattr on {}", path.display()))
+}
+
+/// Set the backup-exclusion xattr directly on each path (no tmutil subprocess).
+fn set_backup_exclusion_xattr(
+    paths: &[&Path],
+    value: &[u8],
+    concurrency: usize,
+    warnings: &Warnings,
+) -> Result<()> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+    info!("Setting exclusion xattr on {} path(s)", paths.len());
+    let failed = for_each_path_parallel("Xattr set on", paths, concurrency, |path| {
+        xattr::set(path, BACKUP_EXCLUDE_XATTR, value)
+            .map_err(|e| {
+                warnings.push(
+                    WarningKind::PathFailed,
+                    Some(path),
+                    format!("Failed to set xattr on {}: {}", path.display(), e),
+                )
+            })
+            .is_ok()
+    });
+    if failed > 0 {
+        warn!("{} path(s) failed when setting exclusion xattr", failed);
+    }
+    Ok(())
+}
+
+/// Remove the backup-exclusion xattr directly from each path (no tmutil subprocess).
+fn remove_backup_exclusion_xattr(
+    paths: &[&Path],
+    concurrency: usize,
+    warnings: &Warnings,
+) -> Result<()> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+    info!("Removing exclusion xattr from {} path(s)", paths.len());
+    let failed = for_each_path_parallel("Xattr removed from", paths, concurrency, |path| {
+        match xattr::remove(path, BACKUP_EXCLUDE_XATTR) {
+            Ok(()) => true,
+            // ENOATTR (93) is fine — the xattr was already absent.
+            Err(e) if e.raw_os_error() == Some(93) => true,
+            Err(e) => {
+                warnings.push(
+                    WarningKind::PathFailed,
+                    Some(path),
+                    format!("Failed to remove xattr from {}: {}", path.display(), e),
+                );
+                false
+            }
+        }
+    });
+    if failed > 0 {
+        warn!("{} path(s) failed when removing exclusion xattr", failed);
+    }
+    Ok(())
+}
+
+/// Run `op` on every path on a rayon pool of `concurrency` threads (`0` uses
+/// the global pool) and return how many calls returned `false`.
+///
+/// Logs the throughput at debug level (shown with `-v`), labelled with
+/// `what`.
+fn for_each_path_parallel(
+    what: &str,
+    paths: &[&Path],
+    concurrency: usize,
+    op: impl Fn(&Path) -> bool + Sync + Send,
+) -> usize {
+    let start = Instant::now();
+    let run = || paths.par_iter().filter(|p| !op(p)).count();
+    let failed = if concurrency == 0 {
+        run()
+    } else {
+        match rayon::ThreadPoolBuilder::new()
+            .num_threads(concurrency)
+            .build()
+        {
+            Ok(pool) => pool.install(run),
+            Err(e) => {
+                warn!("Could not start {concurrency} xattr thread(s), using the global pool: {e}");
+                run()
+            }
+        }
+    };
+    let secs = start.elapsed().as_secs_f64();
+    debug!(
+        "{} {} path(s) in {:.3}s ({:.0} paths/s)",
+        what,
+        paths.len(),
+        secs,
+        paths.len() as f64 / secs.max(f64::EPSILON)
+    );
+    failed
+}
+
+/// Process `paths` through `tmutil <verb>` in batches of `size` paths with
+/// progress logging and a per-subprocess timeout.  With
+/// [`Batching::adaptive`], `size` is re-tuned after every batch.
+///
+/// If a batch times out or fails with a [`TmutilErrorClass::Retryable`]
+/// error, each path in that batch is retried individually with a shorter
+/// timeout.  Only truly problematic paths are skipped.
+fn run_tmutil_batched(
+    tmutil: &Capabilities,
+    batching: &Batching,
+    size: &AtomicUsize,
+    verb: &str,
+    paths: &[&Path],
+    fixed_path: bool,
+    warnings: &Warnings,
+) -> Result<()> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let mut done = 0;
+    let mut skipped: usize = 0;
+
+    while done < paths.len() {
+        let batch_size = size.load(Ordering::Relaxed).max(1);
+        let chunk = &paths[done..paths.len().min(done + batch_size)];
+        info!(
+            "tmutil {}: {} path(s) ({}/{} done)",
+            verb,
+            chunk.len(),
+            done,
+            paths.len()
+        );
+        let start = Instant::now();
+        let outcome = run_tmutil(tmutil, verb, chunk, fixed_path, batching.timeout)?;
+        let timed_out = matches!(outcome, Outcome::TimedOut);
+        done += chunk.len();
+        if batching.adaptive {
+            let next = next_batch_size(
+                batching,
+                batch_size,
+                chunk.len(),
+                timed_out,
+                start.elapsed(),
+            );
+            if next != batch_size {
+                debug!("tmutil batch size {batch_size} → {next}");
+                size.store(next, Ordering::Relaxed);
+            }
+        }
+        match outcome {
+            Outcome::Done => continue, // batch completed successfully
+            Outcome::TimedOut => warn!(
+                "Batch of {} path(s) timed out — retrying them individually",
+                chunk.len()
+            ),
+            Outcome::Failed(e) => warn!(
+                "Batch of {} path(s) failed ({e}) — retrying them individually",
+                chunk.len()
+            ),
+        }
+
+        // Retry each path individually to isolate the problematic one(s)
+        // instead of skipping the whole batch.
+        for path in chunk {
+            let (kind, message) = match run_tmutil(
+                tmutil,
+                verb,
+                std::slice::from_ref(path),
+                fixed_path,
+                batching.retry_timeout,
+            )? {
+                Outcome::Done => continue,
+                Outcome::TimedOut => (
+                    WarningKind::TimedOut,
+                    format!("Skipping timed-out path: {}", path.display()),
+                ),
+                Outcome::Failed(e) => (
+                    WarningKind::PathFailed,
+                    format!("Skipping {} after a retry: {e}", path.display()),
+                ),
+            };
+            warnings.push(kind, Some(path), message);
+            skipped += 1;
+        }
+    }
+
+    if skipped > 0 {
+        warn!(
+            "tmutil {}: {} path(s) skipped after timeouts or transient errors",
+            verb, skipped
+        );
+    }
+
+    Ok(())
+}
+
+/// The batch size to use after a batch of `len` paths, run at `size`, took
+/// `elapsed`: half as large after a timeout, twice as large (up to
+/// `max_size`) after a full batch that finished within a quarter of the
+/// timeout, else unchanged.
+fn next_batch_size(
+    batching: &Batching,
+    size: usize,
+    len: usize,
+    timed_out: bool,
+    elapsed: Duration,
+) -> usize {
+    if timed_out {
+        (size / 2).max(1)
+    } else if len == size && elapsed < batching.timeout / 4 {
+        (size * 2).min(batching.max_size.max(size))
+    } else {
+        size
+    }
+}
+
+/// How a `tmutil` call that did not fail fatally ended.
+#[derive(Debug)]
+enum Outcome {
+    /// Exited successfully, or with an ignorable error.
+    Done,
+    /// Killed after the timeout.
+    TimedOut,
+    /// Exited with a [`TmutilErrorClass::Retryable`] error.
+    Failed(TmutilError),
+}
+
+/// Invoke `<binary> <verb> [-p] <paths…>` with a timeout.
+///
+/// The calling thread blocks until the subprocess exits or `timeout`
+/// passes, while a thread collects its stderr (so a chatty `tmutil` cannot
+/// stall on a full pipe); a killed subprocess is [`Outcome::TimedOut`].
+///
+/// A non-zero exit is classified by [`TmutilError::classify`]: ignorable
+/// errors (such as exit 213, path not found) are logged and count as done,
+/// retryable ones are returned as [`Outcome::Failed`] and fatal ones as an
+/// error.  The `-p` flag is included only when `fixed_path` is `true`.
+fn run_tmutil(
+    tmutil: &Capabilities,
+    verb: &str,
+    paths: &[&Path],
+    fixed_path: bool,
+    timeout: Duration,
+) -> Result<Outcome> {
+    let binary = &tmutil.tmutil;
+    let mut cmd = Command::new(binary);
+    cmd.arg(verb);
+    if fixed_path {
+        cmd.arg("-p");
+    }
+    for path in paths {
+        cmd.arg(path);
+    }
+
+    debug!(
+        "tmutil {} {} paths (fixed_path={}, timeout={:?})",
+        verb,
+        paths.len(),
+        fixed_path,
+        timeout
+    );
+
+    let mut child = cmd
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("spawning {} {verb}", binary.display()))?;
+    let stderr = child.stderr.take().map(|mut pipe| {
+        thread::spawn(move || {
+            let mut buf = String::new();
+            let _ = pipe.read_to_string(&mut buf);
+            buf
+        })
+    });
+
+    let Some(status) = child.wait_timeout(timeout).context("waiting for tmutil")? else {
+        let _ = child.kill();
+        let _ = child.wait(); // reap zombie
+        // The reader is left behind: a grandchild may still hold the pipe
+        return Ok(Outcome::TimedOut);
+    };
+
+    // Check exit status
+    if !status.success() {
+        let code = status.code().unwrap_or(-1);
+        let stderr_output = stderr
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default();
+        let error = TmutilError::classify(verb, code, &stderr_output, tmutil);
+        match error.class {
+            TmutilErrorClass::Ignorable => warn!(
+                "tmutil {} exited with code {} (safe, ignored): {}",
+                verb, code, error.stderr
+            ),
+            TmutilErrorClass::Retryable => return Ok(Outcome::Failed(error)),
+            TmutilErrorClass::Fatal => return Err(error.into()),
+        }
+    }
+
+    Ok(Outcome::Done)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_parallel_xattr_ops_stay_in_their_pool_and_count_failures() {
+        let paths: Vec<PathBuf> = (0..40).map(|i| PathBuf::from(format!("/p/{i}"))).collect();
+        let refs: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+        let (active, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let failed = for_each_path_parallel("Tested", &refs, 3, |path| {
+            most.fetch_max(active.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(5));
+            active.fetch_sub(1, Ordering::SeqCst);
+            !path.to_string_lossy().ends_with('7')
+        });
+        // /p/7, /p/17, /p/27 and /p/37
+        assert_eq!(failed, 4);
+        let most = most.load(Ordering::SeqCst);
+        assert!((2..=3).contains(&most), "{most} ops at once");
+    }
+
+    #[test]
+    fn test_batch_size_grows_while_fast_and_halves_on_timeouts() {
+        let batching = Batching {
+            max_size: 6,
+            ..Batching::default()
+        };
+        let fast = Duration::from_millis(100);
+        let slow = Duration::from_secs(4);
+        assert_eq!(next_batch_size(&batching, 2, 2, false, fast), 4);
+        assert_eq!(next_batch_size(&batching, 4, 4, false, fast), 6);
+        assert_eq!(next_batch_size(&batching, 6, 6, false, fast), 6);
+        // A short last batch or a slow one says nothing about larger ones
+        assert_eq!(next_batch_size(&batching, 4, 3, false, fast), 4);
+        assert_eq!(next_batch_size(&batching, 4, 4, false, slow), 4);
+        assert_eq!(next_batch_size(&batching, 6, 6, true, slow), 3);
+        assert_eq!(next_batch_size(&batching, 1, 1, true, slow), 1);
+    }
+}
@@ -0,0 +1,387 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsStr,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The library that walks directory trees (`walk_backend`).
+///
+/// Walks that prune as they go — resolution, sizes — are `walkdir`'s with
+/// either backend (the `ignore` crate's own sequential walk is built on it);
+/// the backend picks how discovery walks each search path.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WalkBackend {
+    /// The `ignore` crate, its filters off: discovery walks on all cores.
+    #[default]
+    Ignore,
+    /// The `walkdir` crate: discovery walks on one thread.
+    Walkdir,
+}
+
+impl fmt::Display for WalkBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalkBackend::Ignore => write!(f, "ignore"),
+            WalkBackend::Walkdir => write!(f, "walkdir"),
+        }
+    }
+}
+
+/// How directory trees are walked: every walk of the user's files goes
+/// through one, so discovery and resolution see the same entries.
+///
+/// Whatever the backend, a walk yields every entry (hidden ones and those
+/// ignore files name included — ignore rules are the resolver's business),
+/// never follows a symlink below its root (a symlinked root is walked at
+/// its target), and with `same_file_system` does not descend into another
+/// file system mounted inside it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Walker {
+    pub backend: WalkBackend,
+    pub same_file_system: bool,
+    /// Threads of a parallel walk (`0` = one per core).
+    pub threads: usize,
+}
+
+/// An entry of a walk.
+#[derive(Debug, Clone)]
+pub struct WalkEntry {
+    path: PathBuf,
+    file_type: fs::FileType,
+    depth: usize,
+}
+
+impl WalkEntry {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn into_path(self) -> PathBuf {
+        self.path
+    }
+
+    /// The last component of the path, or the whole path for a root like `/`.
+    pub fn file_name(&self) -> &OsStr {
+        self.path
+            .file_name()
+            .unwrap_or_else(|| self.path.as_os_str())
+    }
+
+    /// The type of the entry itself, a symlink not being followed — except
+    /// for the root, which has the type of its target.
+    pub fn file_type(&self) -> fs::FileType {
+        self.file_type
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type.is_dir()
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type.is_file()
+    }
+
+    /// 0 for the root, 1 for the entries in it, and so on.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The metadata of the entry itself, a symlink not being followed.
+    pub fn metadata(&self) -> io::Result<fs::Metadata> {
+        fs::symlink_metadata(&self.path)
+    }
+
+    /// With the root's symlink resolved, as [`Walker`] promises.
+    fn new(path: PathBuf, file_type: fs::FileType, depth: usize) -> Self {
+        let file_type = match depth {
+            0 if file_type.is_symlink() => fs::metadata(&path).map_or(file_type, |m| m.file_type()),
+            _ => file_type,
+        };
+        WalkEntry {
+            path,
+            file_type,
+            depth,
+        }
+    }
+
+    /// An entry of the `ignore` backend.  Only stdin has no file type, and
+    /// it is never walked.
+    fn from_ignore(entry: ignore::DirEntry) -> Result<Self, WalkError> {
+        let (depth, file_type) = (entry.depth(), entry.file_type());
+        let Some(file_type) = file_type else {
+            return Err(WalkError {
+                message: format!("{}: no file type", entry.path().display()),
+                path: Some(entry.into_path()),
+                kind: None,
+            });
+        };
+        Ok(WalkEntry::new(entry.into_path(), file_type, depth))
+    }
+}
+
+/// An entry a walk could not read.
+#[derive(Debug)]
+pub struct WalkError {
+    path: Option<PathBuf>,
+    kind: Option<io::ErrorKind>,
+    message: String,
+}
+
+impl WalkError {
+    /// The path that could not be read, when known.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Whether reading it was refused, e.g. by macOS privacy controls.
+    pub fn is_permission_denied(&self) -> bool {
+        self.kind == Some(io::ErrorKind::PermissionDenied)
+    }
+}
+
+impl fmt::Display for WalkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for WalkError {}
+
+impl From<walkdir::Error> for WalkError {
+    fn from(err: walkdir::Error) -> Self {
+        WalkError {
+            path: err.path().map(Path::to_path_buf),
+            kind: err.io_error().map(io::Error::kind),
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<ignore::Error> for WalkError {
+    fn from(err: ignore::Error) -> Self {
+        fn path(err: &ignore::Error) -> Option<PathBuf> {
+            match err {
+                ignore::Error::WithPath { path, .. } => Some(path.clone()),
+                ignore::Error::WithDepth { err, .. }
+                | ignore::Error::WithLineNumber { err, .. } => path(err),
+                _ => None,
+            }
+        }
+        WalkError {
+            path: path(&err),
+            kind: err.io_error().map(io::Error::kind),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// What to do after visiting an entry of a parallel walk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visit {
+    Continue,
+    /// Do not descend into this directory (a no-op for other entries).
+    Skip,
+}
+
+/// A visitor of a parallel walk, one per thread.
+pub type Visitor<'a> = Box<dyn FnMut(Result<WalkEntry, WalkError>) -> Visit + Send + 'a>;
+
+impl Walker {
+    /// Walk `root` in order, root first.
+    pub fn walk(&self, root: &Path) -> Walk {
+        Walk {
+            iter: walkdir::WalkDir::new(root)
+                .follow_links(false)
+                .same_file_system(self.same_file_system)
+                .into_iter(),
+            min_depth: 0,
+        }
+    }
+
+    /// The entries of [`walk`](Self::walk), those it could not read
+    /// skipped.
+    pub fn entries(&self, root: &Path) -> impl Iterator<Item = WalkEntry> + use<> {
+        self.walk(root).flatten()
+    }
+
+    /// Walk `root`, on all cores if the backend can: each thread visits
+    /// entries with a visitor of its own, made by `visitor`, which is
+    /// dropped once the thread is done.
+    pub fn walk_parallel<'a>(&self, root: &Path, visitor: impl Fn() -> Visitor<'a> + Sync) {
+        match self.backend {
+            WalkBackend::Ignore => self.ignore_builder(root).build_parallel().run(|| {
+                let mut visit = visitor();
+                Box::new(move |result| {
+                    match visit(
+                        result
+                            .map_err(WalkError::from)
+                            .and_then(WalkEntry::from_ignore),
+                    ) {
+                        Visit::Continue => ignore::WalkState::Continue,
+                        Visit::Skip => ignore::WalkState::Skip,
+                    }
+                })
+            }),
+            WalkBackend::Walkdir => {
+                let mut visit = visitor();
+                let mut walk = self.walk(root);
+                while let Some(result) = walk.next() {
+                    let dir = matches!(&result, Ok(entry) if entry.is_dir());
+                    if visit(result) == Visit::Skip && dir {
+                        walk.skip_current_dir();
+                    }
+                }
+            }
+        }
+    }
+
+    fn ignore_builder(&self, root: &Path) -> ignore::WalkBuilder {
+        let mut builder = ignore::WalkBuilder::new(root);
+        builder
+            .standard_filters(false)
+            .follow_links(false)
+            .same_file_system(self.same_file_system)
+            .threads(self.threads);
+        builder
+    }
+}
+
+/// A walk of a tree, from [`Walker::walk`].
+pub struct Walk {
+    iter: walkdir::IntoIter,
+    min_depth: usize,
+}
+
+impl Walk {
+    /// Leave out the entries above `depth`: 1 skips the root.
+    pub fn min_depth(self, depth: usize) -> Self {
+        Walk {
+            min_depth: depth,
+            ..self
+        }
+    }
+
+    /// Do not descend into the directory last yielded (or, after another
+    /// entry, the directory holding it).
+    pub fn skip_current_dir(&mut self) {
+        self.iter.skip_current_dir();
+    }
+}
+
+impl Iterator for Walk {
+    type Item = Result<WalkEntry, WalkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.iter.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if entry.depth() >= self.min_depth {
+                let (depth, file_type) = (entry.depth(), entry.file_type());
+                return Some(Ok(WalkEntry::new(entry.into_path(), file_type, depth)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{os::unix::fs::symlink, sync::Mutex};
+
+    #[test]
+    fn test_walks_see_the_same_entries_whatever_the_backend() {
+        let tmp = tempfile::tempdir().unwrap();
+        let real = tmp.path().join("real");
+        for dir in ["src", ".hidden", "skipped/deep"] {
+            fs::create_dir_all(real.join(dir)).unwrap();
+        }
+        // Ignore files are data, not filters
+        fs::write(real.join(".ignore"), "src\n").unwrap();
+        fs::write(real.join(".gitignore"), ".hidden\n").unwrap();
+        fs::write(real.join("src/main.rs"), "").unwrap();
+        symlink(real.join("src"), real.join("link")).unwrap();
+        symlink(&real, tmp.path().join("root")).unwrap();
+        let root = tmp.path().join("root");
+        let rel = |entry: &WalkEntry| {
+            let path = entry.path().strip_prefix(&root).unwrap();
+            let kind = match () {
+                _ if entry.file_type().is_symlink() => "@",
+                _ if entry.is_dir() => "/",
+                _ => "",
+            };
+            format!("{}{kind}", path.display())
+        };
+        // Sorted; "/" is the root
+        let expected = [
+            ".gitignore",
+            ".hidden/",
+            ".ignore",
+            "/",
+            "link@",
+            "src/",
+            "src/main.rs",
+        ];
+
+        let walker = Walker::default();
+        let mut walk = walker.walk(&root);
+        let mut seen = Vec::new();
+        while let Some(entry) = walk.next() {
+            let entry = entry.unwrap();
+            if entry.file_name() == "skipped" {
+                walk.skip_current_dir();
+                continue;
+            }
+            seen.push(rel(&entry));
+        }
+        seen.sort();
+        assert_eq!(seen, expected);
+        let below: Vec<_> = walker.walk(&root).min_depth(1).flatten().collect();
+        assert!(below.iter().all(|e| e.depth() >= 1));
+        assert_eq!(below.len(), walker.entries(&root).count() - 1);
+
+        for backend in [WalkBackend::Ignore, WalkBackend::Walkdir] {
+            let walker = Walker {
+                backend,
+                ..Walker::default()
+            };
+            let seen = Mutex::new(Vec::new());
+            walker.walk_parallel(&root, || {
+                let seen = &seen;
+                Box::new(move |entry| {
+                    let entry = entry.unwrap();
+                    if entry.file_name() == "skipped" {
+                        return Visit::Skip;
+                    }
+                    seen.lock().unwrap().push(rel(&entry));
+                    Visit::Continue
+                })
+            });
+            let mut seen = seen.into_inner().unwrap();
+            seen.sort();
+            assert_eq!(seen, expected, "{backend}");
+        }
+    }
+
+    #[test]
+    fn test_walk_errors_keep_path_and_kind() {
+        let io = |kind| ignore::Error::Io(io::Error::from(kind));
+        let denied = WalkError::from(ignore::Error::WithDepth {
+            depth: 2,
+            err: Box::new(ignore::Error::WithPath {
+                path: PathBuf::from("/Users/alice/Library/Mail"),
+                err: Box::new(io(io::ErrorKind::PermissionDenied)),
+            }),
+        });
+        assert!(denied.is_permission_denied());
+        assert_eq!(denied.path(), Some(Path::new("/Users/alice/Library/Mail")));
+        let missing = WalkError::from(ignore::Error::WithPath {
+            path: PathBuf::from("/gone"),
+            err: Box::new(io(io::ErrorKind::NotFound)),
+        });
+        assert!(!missing.is_permission_denied());
+    }
+}
@@ -0,0 +1,122 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tracing::debug;
+
+use crate::{i18n, tr};
+
+/// What a [`Warning`] is about; the end-of-run summary counts each kind.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum WarningKind {
+    /// A repository whose exclusions could not be resolved.
+    RepoSkipped,
+    /// A directory that could not be read.
+    PermissionDenied,
+    /// A `.gitignore`, exclude file or `.lignore` that could not be read.
+    UnreadableIgnoreFile,
+    /// A `.lignore` rule the engine does not apply (sub-path negation).
+    RuleNotApplied,
+    /// A path the backend gave up on after a timeout.
+    TimedOut,
+    /// A path whose exclusion (or sync ignore) could not be changed.
+    PathFailed,
+}
+
+impl WarningKind {
+    /// Catalog key of the summary count (see [`crate::i18n`]).
+    fn count_key(self) -> &'static str {
+        match self {
+            WarningKind::RepoSkipped => "warning-repo-skipped",
+            WarningKind::PermissionDenied => "warning-permission-denied",
+            WarningKind::UnreadableIgnoreFile => "warning-unreadable-ignore-file",
+            WarningKind::RuleNotApplied => "warning-rule-not-applied",
+            WarningKind::TimedOut => "warning-timed-out",
+            WarningKind::PathFailed => "warning-path-failed",
+        }
+    }
+}
+
+/// A problem a command worked around, kept for its summary and JSON output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Warning {
+    pub kind: WarningKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    pub message: String,
+}
+
+/// Collects the warnings of one command from any thread.
+///
+/// Each warning is logged at debug level when it is pushed; the command
+/// reports them as a whole with [`summary`].
+#[derive(Debug, Default)]
+pub struct Warnings(Mutex<Vec<Warning>>);
+
+impl Warnings {
+    pub fn push(&self, kind: WarningKind, path: Option<&Path>, message: impl Into<String>) {
+        let message = message.into();
+        debug!("{message}");
+        self.0.lock().expect("warnings poisoned").push(Warning {
+            kind,
+            path: path.map(Path::to_path_buf),
+            message,
+        });
+    }
+
+    /// The warnings pushed so far, leaving none.
+    pub fn take(&self) -> Vec<Warning> {
+        std::mem::take(&mut *self.0.lock().expect("warnings poisoned"))
+    }
+}
+
+/// One line counting `warnings` by kind, e.g. "3 repos skipped, 12
+/// permission errors — run with -v for details"; `None` if there are none.
+pub fn summary(warnings: &[Warning]) -> Option<String> {
+    let mut counts: BTreeMap<WarningKind, usize> = BTreeMap::new();
+    for warning in warnings {
+        *counts.entry(warning.kind).or_default() += 1;
+    }
+    if counts.is_empty() {
+        return None;
+    }
+    let parts: Vec<String> = counts
+        .into_iter()
+        .map(|(kind, n)| i18n::message(kind.count_key(), &[("n", &n)]))
+        .collect();
+    Some(tr!(
+        "warnings-summary",
+        counts = parts.join(&tr!("warnings-separator"))
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_counts_each_kind() {
+        let warnings = Warnings::default();
+        assert_eq!(summary(&warnings.take()), None);
+        for _ in 0..12 {
+            warnings.push(
+                WarningKind::PermissionDenied,
+                Some(Path::new("/x")),
+                "denied",
+            );
+        }
+        warnings.push(WarningKind::RepoSkipped, None, "bad repo");
+        let taken = warnings.take();
+        assert!(warnings.take().is_empty());
+        assert_eq!(
+            summary(&taken).unwrap(),
+            "1 repo skipped, 12 permission errors — run with -v for details"
+        );
+    }
+}
@@ -1,12 +1,34 @@
-use letitgo::cache::{Cache, load_cache, write_cache};
+use letitgo::access::{AccessGrant, DirectAccess, FileSystemAccess};
+use letitgo::artifacts::Preset;
+use letitgo::cache::{
+    Cache, KnownRepos, Origin, PathMeta, list_snapshots, load_cache, write_cache,
+};
 use letitgo::clean;
-use letitgo::config::{Config, ExclusionMode};
-use letitgo::tmutil::mock::MockExclusionManager;
-use letitgo::{AppContext, cmd_init, cmd_list, cmd_reset, cmd_run};
+use letitgo::config::{Backend, Config, ExclusionMode, ModeRule, TrashedPaths};
+use letitgo::encryption;
+use letitgo::explain;
+use letitgo::export::ExportFormat;
+use letitgo::lignore::LignoreEdit;
+use letitgo::list::{ListFormat, ListSort, PathEntry};
+use letitgo::lock::InstanceLock;
+use letitgo::output::Output;
+use letitgo::plan::{read_plan, write_plan};
+use letitgo::provenance::group_by_rule;
+use letitgo::repos::{self, NotFound, RepoKind};
+use letitgo::service::{self, ServiceStatus};
+use letitgo::tmutil::mock::{MockExclusionManager, MockFailure, MockOp};
+use letitgo::tmutil::{ExclusionManager, RootedManager};
+use letitgo::{
+    AppContext, RunOptions, answer_service_command, apply_plan, cmd_cache_gc, cmd_cache_restore,
+    cmd_clean, cmd_diff, cmd_doctor, cmd_explain, cmd_export, cmd_init, cmd_lignore, cmd_list,
+    cmd_purge_backups, cmd_query, cmd_repos, cmd_reset, cmd_run, cmd_run_with, cmd_simulate,
+    plan_run,
+};
 use std::collections::HashSet;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use tempfile::tempdir;
 
 fn make_ctx(tmp: &Path, mock: MockExclusionManager) -> AppContext {
@@ -15,6 +37,8 @@ fn make_ctx(tmp: &Path, mock: MockExclusionManager) -> AppContext {
         cache_path: tmp.join("cache.json"),
         lock_path: tmp.join("letitgo.lock"),
         exclusion_manager: Box::new(mock),
+        fs_access: Box::new(DirectAccess),
+        output: Output::default(),
     }
 }
 
@@ -27,6 +51,8 @@ fn make_ctx_with_mock(tmp: &Path) -> (AppContext, Arc<MockExclusionManager>) {
         cache_path: tmp.join("cache.json"),
         lock_path: tmp.join("letitgo.lock"),
         exclusion_manager: Box::new(Arc::clone(&mock)),
+        fs_access: Box::new(DirectAccess),
+        output: Output::default(),
     };
     (ctx, mock)
 }
@@ -46,6 +72,7 @@ fn default_config_for_test(search_root: &Path) -> Config {
         ignored_paths: vec![],
         whitelist: vec![],
         exclusion_mode: ExclusionMode::Sticky,
+        ..Config::default()
     }
 }
 
@@ -94,6 +121,1047 @@ fn test_run_diff_only_sends_new_paths() {
     assert!(paths.contains(&repo.join("dist")));
 }
 
+#[test]
+fn test_run_reapplies_lost_exclusions() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(tmp.path(), "repo-heal");
+    let config = default_config_for_test(tmp.path());
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+    cmd_run(&ctx, &config, &[], false).unwrap();
+
+    // `target/` is recreated without its xattr; nothing changed in .gitignore
+    mock.remove_exclusions(&[&repo.join("target")], false)
+        .unwrap();
+    let calls_before = mock.calls().len();
+    cmd_run(&ctx, &config, &[], false).unwrap();
+
+    let calls = &mock.calls()[calls_before..];
+    let check = calls.iter().find(|c| c.op == MockOp::AreExcluded).unwrap();
+    assert_eq!(check.paths.len(), 2);
+    let add = calls.iter().find(|c| c.op == MockOp::Add).unwrap();
+    assert_eq!(add.paths, vec![repo.join("target")]);
+
+    // With reassert off the cache is trusted and nothing is checked
+    mock.remove_exclusions(&[&repo.join("target")], false)
+        .unwrap();
+    let config = Config {
+        reassert: false,
+        ..config
+    };
+    let calls_before = mock.calls().len();
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    let calls = &mock.calls()[calls_before..];
+    assert!(calls.iter().all(|c| c.op != MockOp::AreExcluded));
+    assert!(calls.iter().all(|c| c.paths.is_empty()));
+}
+
+#[test]
+fn test_run_snapshots_cache_when_set_changes() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(tmp.path(), "repo-s");
+    let config = default_config_for_test(tmp.path());
+    let ctx = make_ctx(tmp.path(), MockExclusionManager::new());
+
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    // No cache existed before the first run
+    assert!(list_snapshots(&ctx.cache_path).unwrap().is_empty());
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    assert!(list_snapshots(&ctx.cache_path).unwrap().is_empty());
+
+    fs::write(repo.join(".gitignore"), "target/\n").unwrap();
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    let snapshots = list_snapshots(&ctx.cache_path).unwrap();
+    assert_eq!(snapshots.len(), 1);
+    assert!(
+        load_cache(&snapshots[0])
+            .unwrap()
+            .path_set()
+            .contains(&repo.join("node_modules"))
+    );
+
+    cmd_diff(&ctx, &config, false, true).unwrap();
+    cmd_diff(&ctx, &config, true, false).unwrap();
+}
+
+#[test]
+fn test_state_files_are_private_and_doctor_restores_that() {
+    use std::os::unix::fs::PermissionsExt;
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(&tmp.path().join("code"), "repo-p");
+    let ctx = make_ctx(
+        &tmp.path().join("state/letitgo"),
+        MockExclusionManager::new(),
+    );
+    let config = Config {
+        backend: Backend::CachedirTag,
+        ..default_config_for_test(&tmp.path().join("code"))
+    };
+    let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+    cmd_init(&ctx, false).unwrap();
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    fs::write(repo.join(".gitignore"), "target/\n").unwrap();
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    let snapshot = list_snapshots(&ctx.cache_path).unwrap().remove(0);
+    for file in [&ctx.config_path, &ctx.cache_path, &ctx.lock_path, &snapshot] {
+        assert_eq!(mode(file), 0o600, "{}", file.display());
+    }
+    for dir in [ctx.cache_path.parent().unwrap(), snapshot.parent().unwrap()] {
+        assert_eq!(mode(dir), 0o700, "{}", dir.display());
+    }
+
+    // Files made readable since are put back by `doctor --fix-permissions`
+    fs::set_permissions(&snapshot, fs::Permissions::from_mode(0o644)).unwrap();
+    fs::set_permissions(&ctx.config_path, fs::Permissions::from_mode(0o664)).unwrap();
+    cmd_doctor(&ctx, &config, false, false).unwrap();
+    assert_eq!(mode(&snapshot), 0o644, "checking alone changes nothing");
+    cmd_doctor(&ctx, &config, true, true).unwrap();
+    assert_eq!(mode(&snapshot), 0o600);
+    assert_eq!(mode(&ctx.config_path), 0o600);
+}
+
+#[test]
+fn test_encrypted_cache_hides_paths_and_stays_usable() {
+    let tmp = tempdir().unwrap();
+    // SAFETY: no other test in this binary reads the key variable
+    unsafe { std::env::set_var(encryption::KEY_FILE_ENV, tmp.path().join("cache.key")) };
+    let repo = make_repo(tmp.path(), "acme-secret");
+    let ctx = make_ctx(tmp.path(), MockExclusionManager::new());
+    let mut config = Config {
+        cache_encryption: true,
+        ..default_config_for_test(tmp.path())
+    };
+    let on_disk = |path: &Path| {
+        let bytes = fs::read(path).unwrap();
+        let plain = !bytes.windows(11).any(|w| w == b"acme-secret");
+        (encryption::is_encrypted(&bytes), plain)
+    };
+
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    assert_eq!(on_disk(&ctx.cache_path), (true, true));
+    let cache = load_cache(&ctx.cache_path).unwrap();
+    assert!(cache.encrypted);
+    assert!(cache.path_set().contains(&repo.join("target")));
+
+    // Snapshots are copies of the encrypted cache
+    fs::write(repo.join(".gitignore"), "target/\n").unwrap();
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    let snapshots = list_snapshots(&ctx.cache_path).unwrap();
+    assert_eq!(on_disk(&snapshots[0]), (true, true));
+    assert!(
+        load_cache(&snapshots[0])
+            .unwrap()
+            .path_set()
+            .contains(&repo.join("node_modules"))
+    );
+    cmd_list(&ctx, false, false, false, ListFormat::Table, None, true).unwrap();
+    cmd_list(&ctx, true, false, false, ListFormat::Plain, None, true).unwrap();
+
+    // Turning it off takes effect at the next write
+    config.cache_encryption = false;
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    assert_eq!(on_disk(&ctx.cache_path), (false, false));
+}
+
+/// A [`FileSystemAccess`] logging when each root's access begins and ends,
+/// and denying the roots named `denied`, as a sandboxed app without a
+/// bookmark for them would.
+struct LoggedAccess(Arc<std::sync::Mutex<Vec<String>>>);
+
+impl FileSystemAccess for LoggedAccess {
+    fn begin(&self, root: &Path) -> std::io::Result<AccessGrant> {
+        let name = root.display().to_string();
+        let mut log = self.0.lock().unwrap();
+        if root.ends_with("denied") {
+            log.push(format!("denied {name}"));
+            return Err(std::io::ErrorKind::PermissionDenied.into());
+        }
+        log.push(format!("begin {name}"));
+        let log = Arc::clone(&self.0);
+        Ok(AccessGrant::ending_with(move || {
+            log.lock().unwrap().push(format!("end {name}"))
+        }))
+    }
+}
+
+#[test]
+fn test_run_holds_access_to_its_roots_throughout() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(&tmp.path().join("work"), "repo");
+    fs::create_dir_all(tmp.path().join("denied")).unwrap();
+    let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+    let ctx = AppContext {
+        fs_access: Box::new(LoggedAccess(Arc::clone(&log))),
+        ..ctx
+    };
+    let config = Config {
+        search_paths: vec!["/work".into(), "/denied".into()],
+        exclude_system_artifacts: true,
+        system_artifacts: vec!["/Library/Caches/*".into(), "/work/.cache".into()],
+        presets: vec![Preset::Npm],
+        root: Some(tmp.path().to_path_buf()),
+        ..default_config_for_test(tmp.path())
+    };
+    let root = |p: &str| tmp.path().join(p).display().to_string();
+    let npm = config.rooted_entries(Preset::Npm.locations()).remove(0);
+
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    assert!(mock.added_paths().contains(&repo.join("target")));
+    // Artifact roots inside a search path need no access of their own; a
+    // glob needs the directory it matches in
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            format!("begin {}", root("work")),
+            format!("denied {}", root("denied")),
+            format!("begin {}", root("Library/Caches")),
+            format!("begin {npm}"),
+            format!("end {}", root("work")),
+            format!("end {}", root("Library/Caches")),
+            format!("end {npm}"),
+        ]
+    );
+
+    // Planning and applying take their own
+    log.lock().unwrap().clear();
+    let plan = plan_run(&ctx, &config, &RunOptions::default()).unwrap();
+    assert_eq!(log.lock().unwrap().len(), 7);
+    apply_plan(&ctx, &config, plan, None, false).unwrap();
+    assert_eq!(log.lock().unwrap().len(), 14);
+}
+
+#[test]
+fn test_clean_keeps_cached_paths_it_has_no_access_to() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(&tmp.path().join("work"), "repo");
+    let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+    let ctx = AppContext {
+        fs_access: Box::new(LoggedAccess(Arc::clone(&log))),
+        ..ctx
+    };
+    let config = Config {
+        search_paths: vec!["/work".into(), "/denied".into()],
+        root: Some(tmp.path().to_path_buf()),
+        cache_snapshots: 0,
+        ..default_config_for_test(tmp.path())
+    };
+    // Without a grant, a sandboxed app sees no file under `denied` at all
+    let hidden = tmp.path().join("denied/app/target");
+    let gone = tmp.path().join("work/gone/target");
+    let mut cache = Cache::empty();
+    cache.paths = vec![hidden.clone(), repo.join("target"), gone.clone()];
+    write_cache(&ctx.cache_path, &cache).unwrap();
+
+    cmd_clean(&ctx, &config, false, false, false).unwrap();
+    assert_eq!(
+        load_cache(&ctx.cache_path).unwrap().paths,
+        vec![hidden, repo.join("target")]
+    );
+    assert_eq!(mock.removed_paths(), vec![gone]);
+    let root = |p: &str| tmp.path().join(p).display().to_string();
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            format!("denied {}", root("denied")),
+            format!("begin {}", root("work")),
+            format!("end {}", root("work")),
+        ]
+    );
+}
+
+#[test]
+fn test_explain_and_why_not_look_under_root() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(&tmp.path().join("work"), "repo");
+    let (ctx, _mock) = make_ctx_with_mock(tmp.path());
+    let config = Config {
+        search_paths: vec!["/work".into()],
+        root: Some(tmp.path().to_path_buf()),
+        ..default_config_for_test(tmp.path())
+    };
+
+    let explanation = explain::explain(Path::new("/work/repo/target"), &config).unwrap();
+    assert_eq!(explanation.path, repo.join("target"));
+    assert_eq!(explanation.repo, Some(repo.clone()));
+    assert!(explanation.is_excluded());
+    assert_eq!(
+        explain::why_not(Path::new("/work/repo/target"), &config).unwrap(),
+        None
+    );
+    // Paths already under the root are left alone
+    assert_eq!(
+        explain::why_not(&repo.join("target"), &config).unwrap(),
+        None
+    );
+    assert_eq!(
+        explain::why_not(Path::new("/work/repo/src"), &config).unwrap(),
+        Some(explain::NotExcluded::NoMatchingRule)
+    );
+    cmd_explain(&ctx, &config, Path::new("/work/repo/target")).unwrap();
+}
+
+#[test]
+fn test_cache_restore_reapplies_snapshot() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(tmp.path(), "repo-r");
+    let config = default_config_for_test(tmp.path());
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    fs::write(repo.join(".gitignore"), "target/\n").unwrap();
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    let snapshot = list_snapshots(&ctx.cache_path).unwrap().pop().unwrap();
+    let name = snapshot.file_name().unwrap().to_str().unwrap().to_string();
+    assert!(cmd_cache_restore(&ctx, &config, "no-such-snapshot", false).is_err());
+
+    mock.added.lock().unwrap().clear();
+    cmd_cache_restore(&ctx, &config, &name, false).unwrap();
+    assert_eq!(*mock.added.lock().unwrap(), vec![repo.join("node_modules")]);
+    assert!(
+        load_cache(&ctx.cache_path)
+            .unwrap()
+            .path_set()
+            .contains(&repo.join("node_modules"))
+    );
+    // The replaced cache was snapshotted, so the restore can be undone
+    assert_eq!(list_snapshots(&ctx.cache_path).unwrap().len(), 2);
+}
+
+#[test]
+fn test_cache_gc_compacts_cache_and_snapshots() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(tmp.path(), "repo-gc");
+    let config = default_config_for_test(tmp.path());
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+    let nested = repo.join("target/debug");
+    let mut cache = Cache::empty();
+    cache.paths = vec![
+        repo.join("target"),
+        nested.clone(),
+        repo.join("target"),
+        repo.join("node_modules"),
+    ];
+    cache
+        .meta
+        .insert(repo.join("gone"), PathMeta::origin(Origin::Preset));
+    cache.known_repos = Some(KnownRepos {
+        discovered_at: None,
+        repos: vec![repo.clone(), tmp.path().join("deleted-repo")],
+        bare_repos: Vec::new(),
+        sparse_repos: Vec::new(),
+        ..KnownRepos::default()
+    });
+    write_cache(&ctx.cache_path, &cache).unwrap();
+
+    // An expired snapshot, and two recording the same exclusions
+    let snapshots = tmp.path().join("snapshots");
+    fs::create_dir_all(&snapshots).unwrap();
+    let mut old = Cache::empty();
+    old.paths = vec![repo.join("target")];
+    write_cache(&snapshots.join("cache-20200101T000000.000.json"), &old).unwrap();
+    let recent = chrono::Local::now() - chrono::Duration::hours(1);
+    let name = |t: chrono::DateTime<chrono::Local>| {
+        snapshots.join(format!("cache-{}.json", t.format("%Y%m%dT%H%M%S%.3f")))
+    };
+    let mut twice = Cache::empty();
+    twice.paths = vec![repo.join("target"), repo.join("target")];
+    write_cache(&name(recent), &twice).unwrap();
+    write_cache(&name(recent + chrono::Duration::minutes(1)), &twice).unwrap();
+
+    // A dry run changes nothing
+    cmd_cache_gc(&ctx, &config, Duration::from_secs(30 * 86_400), true).unwrap();
+    assert_eq!(load_cache(&ctx.cache_path).unwrap().paths.len(), 4);
+    assert_eq!(list_snapshots(&ctx.cache_path).unwrap().len(), 3);
+
+    cmd_cache_gc(&ctx, &config, Duration::from_secs(30 * 86_400), false).unwrap();
+    let cache = load_cache(&ctx.cache_path).unwrap();
+    let mut paths = cache.paths.clone();
+    paths.sort();
+    assert_eq!(paths, vec![repo.join("node_modules"), repo.join("target")]);
+    assert!(cache.meta.is_empty());
+    assert_eq!(cache.known_repos.unwrap().repos, vec![repo.clone()]);
+    assert_eq!(*mock.removed.lock().unwrap(), vec![nested]);
+
+    // The newest duplicate remains, plus the pre-gc cache
+    let remaining = list_snapshots(&ctx.cache_path).unwrap();
+    assert_eq!(remaining.len(), 2);
+    assert_eq!(remaining[0], name(recent + chrono::Duration::minutes(1)));
+    assert_eq!(load_cache(&remaining[0]).unwrap().paths.len(), 1);
+    assert_eq!(load_cache(&remaining[1]).unwrap().paths.len(), 4);
+}
+
+#[test]
+fn test_run_if_stale_skips_recent_runs() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(tmp.path(), "repo-stale");
+    let config = default_config_for_test(tmp.path());
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+
+    // Never run before: --if-stale always runs
+    let options = RunOptions {
+        if_stale: Some(Duration::from_secs(3600)),
+        ..RunOptions::default()
+    };
+    cmd_run_with(&ctx, &config, &options).unwrap();
+    assert!(mock.added_paths().contains(&repo.join("target")));
+
+    // The cache is fresh, so the new dir is not picked up
+    fs::create_dir_all(repo.join("dist")).unwrap();
+    fs::write(repo.join(".gitignore"), "target/\nnode_modules/\ndist/\n").unwrap();
+    cmd_run_with(&ctx, &config, &options).unwrap();
+    assert!(
+        !load_cache(&ctx.cache_path)
+            .unwrap()
+            .path_set()
+            .contains(&repo.join("dist"))
+    );
+
+    // A zero threshold treats any previous run as stale
+    let options = RunOptions {
+        if_stale: Some(Duration::ZERO),
+        ..RunOptions::default()
+    };
+    cmd_run_with(&ctx, &config, &options).unwrap();
+    assert!(
+        load_cache(&ctx.cache_path)
+            .unwrap()
+            .path_set()
+            .contains(&repo.join("dist"))
+    );
+}
+
+#[test]
+fn test_run_writes_report_for_every_outcome() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(tmp.path(), "repo-report");
+    let report_path = tmp.path().join("reports/last-run.json");
+    let config = Config {
+        report_path: Some(report_path.to_string_lossy().into_owned()),
+        ..default_config_for_test(tmp.path())
+    };
+    let read_report = || -> serde_json::Value {
+        serde_json::from_str(&fs::read_to_string(&report_path).unwrap()).unwrap()
+    };
+
+    // Dry runs leave no report
+    let ctx = make_ctx(tmp.path(), MockExclusionManager::new());
+    cmd_run(&ctx, &config, &[], true).unwrap();
+    assert!(!report_path.exists());
+
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    let report = read_report();
+    assert_eq!(report["outcome"], "ok");
+    assert_eq!(report["repos"], 1);
+    assert_eq!(report["added"], 2);
+    assert_eq!(report["excluded"], 2);
+    assert_eq!(report["letitgo_version"], env!("CARGO_PKG_VERSION"));
+
+    let options = RunOptions {
+        if_stale: Some(Duration::from_secs(3600)),
+        ..RunOptions::default()
+    };
+    cmd_run_with(&ctx, &config, &options).unwrap();
+    let report = read_report();
+    assert_eq!(report["outcome"], "skipped");
+    assert!(
+        report["skip_reason"]
+            .as_str()
+            .unwrap()
+            .contains("--if-stale")
+    );
+
+    fs::write(
+        repo.join(".gitignore"),
+        "target/
+",
+    )
+    .unwrap();
+    let ctx = make_ctx(
+        tmp.path(),
+        MockExclusionManager::new().fail_path(repo.join("node_modules")),
+    );
+    assert!(cmd_run(&ctx, &config, &[], false).is_err());
+    let report = read_report();
+    assert_eq!(report["outcome"], "failed");
+    assert_eq!(report["removed"], 1);
+    assert!(
+        report["errors"][0]
+            .as_str()
+            .unwrap()
+            .contains("node_modules")
+    );
+}
+
+#[test]
+fn test_run_report_lists_warnings_in_both_apply_modes() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(tmp.path(), "repo-warn");
+    fs::write(repo.join(".lignore"), "!target/debug\n").unwrap();
+    let report_path = tmp.path().join("last-run.json");
+    let config = Config {
+        report_path: Some(report_path.to_string_lossy().into_owned()),
+        ..default_config_for_test(tmp.path())
+    };
+    let ctx = make_ctx(tmp.path(), MockExclusionManager::new());
+
+    for streaming_apply in [false, true] {
+        let config = Config {
+            streaming_apply,
+            ..config.clone()
+        };
+        cmd_run(&ctx, &config, &[], false).unwrap();
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&report_path).unwrap()).unwrap();
+        assert_eq!(report["outcome"], "ok");
+        assert_eq!(
+            report["warnings"],
+            serde_json::json!([{
+                "kind": "rule-not-applied",
+                "path": repo.join(".lignore"),
+                "message": report["warnings"][0]["message"],
+            }]),
+            "streaming_apply = {streaming_apply}"
+        );
+    }
+}
+
+#[test]
+fn test_run_fast_reuses_known_repos() {
+    let tmp = tempdir().unwrap();
+    let repo_a = make_repo(tmp.path(), "repo-a");
+    let config = default_config_for_test(tmp.path());
+    let (ctx, _mock) = make_ctx_with_mock(tmp.path());
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    let known = load_cache(&ctx.cache_path).unwrap().known_repos.unwrap();
+    assert_eq!(known.repos, vec![repo_a.clone()]);
+
+    // A new repo is invisible to --fast, but changes in known repos are not
+    let repo_b = make_repo(tmp.path(), "repo-b");
+    fs::create_dir_all(repo_a.join("dist")).unwrap();
+    fs::write(repo_a.join(".gitignore"), "target/\nnode_modules/\ndist/\n").unwrap();
+    let fast = RunOptions {
+        fast: true,
+        ..RunOptions::default()
+    };
+    cmd_run_with(&ctx, &config, &fast).unwrap();
+    let paths = load_cache(&ctx.cache_path).unwrap().path_set();
+    assert!(paths.contains(&repo_a.join("dist")));
+    assert!(!paths.contains(&repo_b.join("target")));
+
+    // --search-path roots are scanned on top of the known repos
+    let fast_with_root = RunOptions {
+        fast: true,
+        search_path_overrides: vec![repo_b.clone()],
+        ..RunOptions::default()
+    };
+    cmd_run_with(&ctx, &config, &fast_with_root).unwrap();
+    let cache = load_cache(&ctx.cache_path).unwrap();
+    assert!(cache.path_set().contains(&repo_a.join("target")));
+    assert!(cache.path_set().contains(&repo_b.join("target")));
+    assert_eq!(cache.known_repos.unwrap().repos.len(), 2);
+
+    // Once the rediscovery interval has passed, --fast scans in full
+    let repo_c = make_repo(tmp.path(), "repo-c");
+    let eager = Config {
+        full_rediscovery_interval: Duration::ZERO,
+        ..default_config_for_test(tmp.path())
+    };
+    cmd_run_with(&ctx, &eager, &fast).unwrap();
+    assert!(
+        load_cache(&ctx.cache_path)
+            .unwrap()
+            .path_set()
+            .contains(&repo_c.join("target"))
+    );
+}
+
+#[test]
+fn test_run_pipeline_discovery_matches_sequential() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path().join("code");
+    for i in 0..40 {
+        let repo = make_repo(&root, &format!("repo-{i:02}"));
+        if i % 3 == 0 {
+            fs::create_dir_all(repo.join("dist")).unwrap();
+            fs::write(repo.join(".gitignore"), "dist/\n").unwrap();
+        }
+    }
+    make_repo(&root.join("repo-00/vendor"), "nested");
+    let sequential = default_config_for_test(&root);
+    let pipelined = Config {
+        pipeline_discovery: true,
+        ..default_config_for_test(&root)
+    };
+
+    let run = |state: &str, config: &Config, options: &RunOptions| {
+        let ctx = make_ctx(&tmp.path().join(state), MockExclusionManager::new());
+        cmd_run_with(&ctx, config, options).unwrap();
+        load_cache(&ctx.cache_path).unwrap()
+    };
+    let expected = run("sequential", &sequential, &RunOptions::default());
+    let actual = run("pipelined", &pipelined, &RunOptions::default());
+    assert_eq!(actual.path_set(), expected.path_set());
+    assert_eq!(actual.known_repos.unwrap().repos.len(), 41);
+
+    // A --fast run streams the known repos
+    let fast = RunOptions {
+        fast: true,
+        ..RunOptions::default()
+    };
+    assert_eq!(
+        run("pipelined", &pipelined, &fast).path_set(),
+        expected.path_set()
+    );
+}
+
+#[test]
+fn test_run_under_root_rebases_paths_and_confines_exclusions() {
+    let tmp = tempdir().unwrap();
+    let disk = tmp.path().join("disk");
+    let repo = make_repo(&disk.join("Users/me/code"), "repo-a");
+    let config = Config {
+        root: Some(disk.clone()),
+        ..default_config_for_test(Path::new("/Users/me/code"))
+    };
+    let mock = Arc::new(MockExclusionManager::new());
+    let mut ctx = make_ctx(&tmp.path().join("state"), MockExclusionManager::new());
+    ctx.rebase_under(&disk);
+    ctx.exclusion_manager = Box::new(RootedManager::new(&disk, Box::new(Arc::clone(&mock))));
+    assert!(ctx.cache_path.starts_with(&disk));
+
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    let paths = load_cache(&ctx.cache_path).unwrap().path_set();
+    assert!(paths.contains(&repo.join("target")));
+    assert!(mock.is_excluded(&repo.join("node_modules")).unwrap());
+
+    // Nothing outside the root is ever changed
+    let outside = tmp.path().join("elsewhere");
+    let err = ctx
+        .exclusion_manager
+        .add_exclusions(&[outside.as_path()], false)
+        .unwrap_err();
+    assert!(err.to_string().contains("outside --root"), "{err}");
+    assert!(!mock.is_excluded(&outside).unwrap());
+}
+
+#[test]
+fn test_run_with_excludes_file_backend_tracks_exclusion_set() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(&tmp.path().join("code"), "repo-a");
+    let excludes = tmp.path().join("state/excludes.txt");
+    let mut ctx = make_ctx(&tmp.path().join("state"), MockExclusionManager::new());
+    let config = Config {
+        backend: Backend::ExcludesFile,
+        excludes_file: excludes.to_string_lossy().into_owned(),
+        ..default_config_for_test(&tmp.path().join("code"))
+    };
+    ctx.use_backend_from(&config).unwrap();
+
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    let listed = |text: String| text.lines().skip(1).map(PathBuf::from).collect::<Vec<_>>();
+    assert_eq!(
+        listed(fs::read_to_string(&excludes).unwrap()),
+        vec![repo.join("node_modules"), repo.join("target")]
+    );
+
+    fs::write(repo.join(".gitignore"), "target/\n").unwrap();
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    assert_eq!(
+        listed(fs::read_to_string(&excludes).unwrap()),
+        vec![repo.join("target")]
+    );
+}
+
+#[test]
+fn test_export_writes_cached_paths_for_arq() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(&tmp.path().join("code"), "repo-a");
+    let ctx = make_ctx(tmp.path(), MockExclusionManager::new());
+    cmd_run(
+        &ctx,
+        &default_config_for_test(&tmp.path().join("code")),
+        &[],
+        false,
+    )
+    .unwrap();
+
+    let output = tmp.path().join("exports/arq.json");
+    let config = default_config_for_test(&tmp.path().join("code"));
+    cmd_export(&ctx, &config, ExportFormat::Arq, Some(&output), false).unwrap();
+    let fragment: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&output).unwrap()).unwrap();
+    assert_eq!(
+        fragment["excludedItemPaths"],
+        serde_json::json!([repo.join("node_modules"), repo.join("target")])
+    );
+}
+
+#[test]
+fn test_export_inventory_groups_paths_by_repo() {
+    let tmp = tempdir().unwrap();
+    let code = tmp.path().join("code");
+    let small = make_repo(&code, "small|app");
+    let big = make_repo(&code, "big-app");
+    fs::write(big.join("target/debug/app"), vec![0u8; 64 * 1024]).unwrap();
+    let artifacts = code.join("caches");
+    fs::create_dir_all(&artifacts).unwrap();
+    let ctx = make_ctx(tmp.path(), MockExclusionManager::new());
+    let config = Config {
+        exclude_system_artifacts: true,
+        system_artifacts: vec![artifacts.to_string_lossy().into_owned()],
+        ..default_config_for_test(&code)
+    };
+    cmd_run(&ctx, &config, &[], false).unwrap();
+
+    let output = tmp.path().join("inventory.md");
+    cmd_export(&ctx, &config, ExportFormat::Markdown, Some(&output), false).unwrap();
+    let markdown = fs::read_to_string(&output).unwrap();
+    assert!(
+        markdown.contains("5 path(s) in 2 repo(s) and 1 outside them"),
+        "{markdown}"
+    );
+    // Largest repo first, paths outside repos last; pipes escaped in cells
+    let headings: Vec<&str> = markdown.lines().filter(|l| l.starts_with("## ")).collect();
+    assert_eq!(headings.len(), 3, "{markdown}");
+    assert!(headings[0].starts_with(&format!("## {} (", big.display())));
+    assert!(headings[1].starts_with(&format!("## {} (", small.display())));
+    assert!(headings[2].starts_with("## Outside repositories ("));
+    assert!(markdown.contains("| `target` | 64.0 KiB | `target/` patterns | repo |"));
+    assert!(markdown.contains(&format!(
+        "| {} |",
+        small.display().to_string().replace('|', "\\|")
+    )));
+
+    cmd_export(&ctx, &config, ExportFormat::Html, Some(&output), true).unwrap();
+    let html = fs::read_to_string(&output).unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<td><code>target</code></td>"));
+    // Directories go; names inside a repo, and last components, stay
+    assert!(!html.contains("big-app"), "{html}");
+    assert!(!html.contains(&*code.to_string_lossy()), "{html}");
+    assert!(cmd_export(&ctx, &config, ExportFormat::Arq, None, true).is_err());
+}
+
+#[test]
+fn test_plan_changes_nothing_until_applied_and_goes_stale() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(&tmp.path().join("code"), "repo-a");
+    let config = default_config_for_test(&tmp.path().join("code"));
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+
+    let plan = plan_run(&ctx, &config, &RunOptions::default()).unwrap();
+    assert_eq!(
+        plan.add,
+        vec![repo.join("node_modules"), repo.join("target")]
+    );
+    assert!(mock.added_paths().is_empty());
+    assert!(!ctx.cache_path.exists());
+
+    // The plan survives a round trip through its file
+    let path = tmp.path().join("plans/plan.json");
+    write_plan(&path, &plan).unwrap();
+    apply_plan(&ctx, &config, read_plan(&path).unwrap(), None, false).unwrap();
+    assert_eq!(mock.added_paths().len(), 2);
+    assert_eq!(
+        load_cache(&ctx.cache_path).unwrap().path_set(),
+        plan.cache.path_set()
+    );
+
+    // Applied once, the cache moved on: the same plan is now stale
+    let err = apply_plan(&ctx, &config, plan, None, false).unwrap_err();
+    assert!(
+        err.to_string().contains("changed since the plan"),
+        "{err:#}"
+    );
+
+    fs::write(repo.join(".gitignore"), "target/\n").unwrap();
+    let plan = plan_run(&ctx, &config, &RunOptions::default()).unwrap();
+    assert!(plan.add.is_empty());
+    assert_eq!(plan.remove, vec![repo.join("node_modules")]);
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    assert!(apply_plan(&ctx, &config, plan, None, false).is_err());
+}
+
+#[test]
+fn test_plan_records_the_rule_behind_each_added_path() {
+    let tmp = tempdir().unwrap();
+    let code = tmp.path().join("code");
+    let (a, b) = (make_repo(&code, "repo-a"), make_repo(&code, "repo-b"));
+    fs::create_dir_all(a.join("dist")).unwrap();
+    fs::write(a.join(".lignore"), "dist/\n").unwrap();
+    let config = default_config_for_test(&code);
+    let (ctx, _mock) = make_ctx_with_mock(tmp.path());
+
+    let plan = plan_run(&ctx, &config, &RunOptions::default()).unwrap();
+    let groups = group_by_rule(plan.add.iter().map(PathBuf::as_path), &plan.rules);
+    let summary: Vec<(String, Vec<PathBuf>)> = groups
+        .into_iter()
+        .map(|g| (g.to_string(), g.paths))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (
+                "2 path(s) from `node_modules/` patterns across 2 repo(s)".to_string(),
+                vec![a.join("node_modules"), b.join("node_modules")]
+            ),
+            (
+                "2 path(s) from `target/` patterns across 2 repo(s)".to_string(),
+                vec![a.join("target"), b.join("target")]
+            ),
+            (
+                "1 path(s) from .lignore `dist/` across 1 repo(s)".to_string(),
+                vec![a.join("dist")]
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_apply_rejects_plans_whose_inputs_or_content_changed() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(&tmp.path().join("code"), "repo-a");
+    let config = default_config_for_test(&tmp.path().join("code"));
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+    let rejected = |plan, expected: Option<&str>, config: &_| {
+        apply_plan(&ctx, config, plan, expected, false)
+            .unwrap_err()
+            .to_string()
+    };
+
+    // A comment does not change the rules; a new pattern does
+    let plan = plan_run(&ctx, &config, &RunOptions::default()).unwrap();
+    assert!(plan.rule_files.contains_key(&repo.join(".gitignore")));
+    fs::write(repo.join(".gitignore"), "# deps\ntarget/\nnode_modules/\n").unwrap();
+    plan.verify(&config, None).unwrap();
+    fs::write(repo.join(".gitignore"), "node_modules/\ntarget/\ndist/\n").unwrap();
+    assert!(rejected(plan, None, &config).contains(".gitignore changed"));
+
+    let plan = plan_run(&ctx, &config, &RunOptions::default()).unwrap();
+    let mut edited = plan.clone();
+    edited.add.push(tmp.path().join("Documents"));
+    assert!(rejected(edited, None, &config).contains("edited"));
+    assert!(rejected(plan.clone(), Some("00"), &config).contains("not the expected"));
+    let mut changed = config.clone();
+    changed.whitelist.push("**/dist".into());
+    assert!(rejected(plan.clone(), None, &changed).contains("configuration changed"));
+
+    let sha256 = plan.plan_sha256.to_uppercase();
+    apply_plan(&ctx, &config, plan, Some(&sha256), false).unwrap();
+    assert_eq!(mock.added_paths().len(), 2);
+}
+
+/// What `run` logs while `f` runs on this thread.
+fn capture_logs(f: impl FnOnce()) -> String {
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<std::sync::Mutex<Vec<u8>>>);
+    impl std::io::Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .without_time()
+        .with_target(false)
+        .finish();
+    tracing::subscriber::with_default(subscriber, f);
+    let bytes = buffer.0.lock().unwrap().clone();
+    String::from_utf8(bytes).unwrap()
+}
+
+/// The `(rule, path)` pairs of the groups a dry run logged, sorted.
+fn dry_run_groups(log: &str) -> Vec<(String, String)> {
+    let mut rule = String::new();
+    let mut pairs = Vec::new();
+    for line in log.lines() {
+        if let Some(header) = line.split("[dry-run] would add ").nth(1) {
+            let from = header.split(" from ").nth(1).unwrap();
+            let from = from.trim_end_matches(':');
+            rule = from.split(" across ").next().unwrap().to_string();
+        } else if let Some(path) = line.split("[dry-run]   ").nth(1) {
+            pairs.push((rule.clone(), path.to_string()));
+        }
+    }
+    pairs.sort();
+    pairs
+}
+
+#[test]
+fn test_run_streaming_apply_matches_whole_set() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path().join("code");
+    let repo_a = make_repo(&root, "repo-a");
+    let repo_b = make_repo(&root, "repo-b");
+    let nested = make_repo(&repo_a.join("vendor"), "nested");
+    fs::write(nested.join(".gitignore"), "target/\nnode_modules/\n*.log\n").unwrap();
+    fs::write(nested.join("build.log"), "").unwrap();
+    let whole = default_config_for_test(&root);
+    let streaming = Config {
+        streaming_apply: true,
+        ..default_config_for_test(&root)
+    };
+    let whole_dir = tmp.path().join("whole");
+    let streaming_dir = tmp.path().join("streaming");
+    let whole_ctx = make_ctx(&whole_dir, MockExclusionManager::new());
+    let (streaming_ctx, mock) = make_ctx_with_mock(&streaming_dir);
+    let run_both = || {
+        cmd_run(&whole_ctx, &whole, &[], false).unwrap();
+        cmd_run(&streaming_ctx, &streaming, &[], false).unwrap();
+        let expected = load_cache(&whole_ctx.cache_path).unwrap().path_set();
+        assert_eq!(
+            load_cache(&streaming_ctx.cache_path).unwrap().path_set(),
+            expected
+        );
+        expected
+    };
+    // A dry run lists the same paths under the same rules either way
+    let dry_runs = [(&whole_ctx, &whole), (&streaming_ctx, &streaming)].map(|(ctx, config)| {
+        let log = capture_logs(|| cmd_run(ctx, config, &[], true).unwrap());
+        dry_run_groups(&log)
+    });
+    assert!(dry_runs[0].contains(&(
+        "`*.log` patterns".to_string(),
+        nested.join("build.log").display().to_string()
+    )));
+    assert_eq!(dry_runs[0], dry_runs[1]);
+
+    assert!(run_both().contains(&nested.join("build.log")));
+
+    // Each group is diffed against its own slice of the cache, and cached
+    // paths of a vanished repo are removed with the paths outside any repo
+    fs::remove_dir_all(&repo_b).unwrap();
+    fs::write(repo_a.join(".gitignore"), "target/\n").unwrap();
+    let calls_before = mock.calls().len();
+    let paths = run_both();
+    assert!(!paths.contains(&repo_a.join("node_modules")));
+    let mut removed: Vec<PathBuf> = mock.calls()[calls_before..]
+        .iter()
+        .filter(|c| c.op == MockOp::Remove)
+        .flat_map(|c| c.paths.clone())
+        .collect();
+    removed.sort();
+    assert_eq!(
+        removed,
+        vec![
+            repo_a.join("node_modules"),
+            repo_b.join("node_modules"),
+            repo_b.join("target"),
+        ]
+    );
+}
+
+#[test]
+fn test_run_failed_exclusion_keeps_old_cache() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(tmp.path(), "repo-fail");
+    let config = Config {
+        exclusion_mode: ExclusionMode::FixedPath,
+        ..default_config_for_test(tmp.path())
+    };
+
+    // One path of the batch fails: the others are applied, the cache is not
+    let mock = Arc::new(MockExclusionManager::new().fail_path(repo.join("target")));
+    let ctx = AppContext {
+        exclusion_manager: Box::new(Arc::clone(&mock)),
+        ..make_ctx(tmp.path(), MockExclusionManager::new())
+    };
+    let err = cmd_run(&ctx, &config, &[], false).unwrap_err();
+    assert!(err.to_string().contains("target"), "{err:#}");
+    assert_eq!(mock.added_paths(), vec![repo.join("node_modules")]);
+    assert!(!ctx.cache_path.exists());
+    let adds: Vec<_> = mock
+        .calls()
+        .into_iter()
+        .filter(|c| c.op == MockOp::Add)
+        .collect();
+    assert_eq!(adds.len(), 1);
+    assert!(adds[0].fixed_path);
+    assert_eq!(adds[0].paths.len(), 2);
+
+    // A timed-out call fails the whole run the same way
+    let ctx = make_ctx(
+        tmp.path(),
+        MockExclusionManager::new().fail_call(1, MockFailure::Timeout(Duration::ZERO)),
+    );
+    let err = cmd_run(&ctx, &config, &[], false).unwrap_err();
+    assert!(err.to_string().contains("timed out"), "{err:#}");
+    assert!(!ctx.cache_path.exists());
+}
+
+#[test]
+fn test_run_shallow_exclusions_need_force() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(tmp.path(), "repo-shallow");
+    let depth = repo.join("target").components().count();
+    let config = Config {
+        min_exclusion_depth: depth,
+        ..default_config_for_test(tmp.path())
+    };
+    let (ctx, _mock) = make_ctx_with_mock(tmp.path());
+
+    // `target` is one level too shallow
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    assert!(
+        !load_cache(&ctx.cache_path)
+            .unwrap()
+            .path_set()
+            .contains(&repo.join("target"))
+    );
+
+    let forced = RunOptions {
+        force: true,
+        ..RunOptions::default()
+    };
+    cmd_run_with(&ctx, &config, &forced).unwrap();
+    assert!(
+        load_cache(&ctx.cache_path)
+            .unwrap()
+            .path_set()
+            .contains(&repo.join("target"))
+    );
+}
+
+#[test]
+fn test_run_large_exclusions_need_confirmation() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(tmp.path(), "repo-large");
+    fs::write(repo.join("target/debug/app"), vec![0u8; 4096]).unwrap();
+    let config = Config {
+        large_exclusion_threshold: 1024,
+        ..default_config_for_test(tmp.path())
+    };
+    let (ctx, _mock) = make_ctx_with_mock(tmp.path());
+
+    // Non-interactive without --yes-large: the large path stays backed up
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    let paths = load_cache(&ctx.cache_path).unwrap().path_set();
+    assert!(!paths.contains(&repo.join("target")));
+    assert!(paths.contains(&repo.join("node_modules")));
+
+    let approved = RunOptions {
+        yes_large: true,
+        ..RunOptions::default()
+    };
+    cmd_run_with(&ctx, &config, &approved).unwrap();
+    let paths = load_cache(&ctx.cache_path).unwrap().path_set();
+    assert!(paths.contains(&repo.join("target")));
+
+    // Already-excluded paths are not asked about again
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    let paths = load_cache(&ctx.cache_path).unwrap().path_set();
+    assert!(paths.contains(&repo.join("target")));
+}
+
 #[test]
 fn test_run_lignore_negation_not_added() {
     let tmp = tempdir().unwrap();
@@ -113,6 +1181,57 @@ fn test_run_lignore_negation_not_added() {
     assert!(paths.contains(&repo.join("node_modules")));
 }
 
+#[test]
+fn test_lignore_negate_then_remove() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(tmp.path(), "repo-l");
+    let config = default_config_for_test(tmp.path());
+    let nested = repo.join("target").join("debug");
+    let ctx = make_ctx(tmp.path(), MockExclusionManager::new());
+
+    cmd_lignore(
+        &ctx,
+        &config,
+        LignoreEdit::Negate,
+        "target/",
+        Some(&nested),
+        false,
+    )
+    .unwrap();
+    assert_eq!(
+        fs::read_to_string(repo.join(".lignore")).unwrap(),
+        "!target/\n"
+    );
+    // Globs cannot be negated and the file is left alone
+    assert!(
+        cmd_lignore(
+            &ctx,
+            &config,
+            LignoreEdit::Negate,
+            "*.log",
+            Some(&repo),
+            false
+        )
+        .is_err()
+    );
+
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    let paths = load_cache(&ctx.cache_path).unwrap().path_set();
+    assert!(!paths.contains(&repo.join("target")));
+
+    cmd_lignore(
+        &ctx,
+        &config,
+        LignoreEdit::Remove,
+        "!target/",
+        Some(&repo),
+        false,
+    )
+    .unwrap();
+    cmd_lignore(&ctx, &config, LignoreEdit::Add, "data/", Some(&repo), true).unwrap();
+    assert_eq!(fs::read_to_string(repo.join(".lignore")).unwrap(), "");
+}
+
 #[test]
 fn test_clean_removes_stale_paths() {
     let tmp = tempdir().unwrap();
@@ -127,13 +1246,8 @@ fn test_clean_removes_stale_paths() {
     let mock = MockExclusionManager::new();
     let ctx = make_ctx(tmp.path(), mock);
 
-    let removed = clean::clean_stale(
-        &ctx.cache_path,
-        ctx.exclusion_manager.as_ref(),
-        false,
-        false,
-    )
-    .unwrap();
+    let removed =
+        clean::clean_stale(&ctx.cache_path, ctx.exclusion_manager.as_ref(), 0, false).unwrap();
     assert_eq!(removed, 1);
 
     let updated = load_cache(&ctx.cache_path).unwrap();
@@ -150,9 +1264,12 @@ fn test_reset_clears_cache() {
 
     let mock = MockExclusionManager::new();
     let ctx = make_ctx(tmp.path(), mock);
-    let config = Config::default();
+    let config = Config {
+        assume_yes: true,
+        ..Config::default()
+    };
 
-    cmd_reset(&ctx, &config, true, false).unwrap();
+    cmd_reset(&ctx, &config, false).unwrap();
 
     assert!(!ctx.cache_path.exists());
 }
@@ -296,6 +1413,7 @@ fn test_run_with_search_path_override() {
         ignored_paths: vec![],
         whitelist: vec![],
         exclusion_mode: ExclusionMode::Sticky,
+        ..Config::default()
     };
 
     // Override with the alt dir
@@ -304,117 +1422,182 @@ fn test_run_with_search_path_override() {
     assert!(mock.added_paths().contains(&repo.join("target")));
 }
 
-// ── run: mode-switch warning ────────────────────────────────────────────
+// ── run: mode switch ────────────────────────────────────────────────────
 
-#[test]
-fn test_run_mode_switch_skips_in_non_interactive() {
-    let tmp = tempdir().unwrap();
-    make_repo(tmp.path(), "repo-mode");
-    let config_sticky = default_config_for_test(tmp.path());
-
-    // First run with sticky — populates cache
+/// Run sticky, then `mode` over the same tree; returns the second mock.
+fn run_then_switch(root: &Path, mode: ExclusionMode, dry_run: bool) -> Arc<MockExclusionManager> {
+    let config = default_config_for_test(root);
     {
-        let (ctx, _mock) = make_ctx_with_mock(tmp.path());
-        cmd_run(&ctx, &config_sticky, &[], false).unwrap();
+        let (ctx, _mock) = make_ctx_with_mock(root);
+        cmd_run(&ctx, &config, &[], false).unwrap();
     }
+    let config = Config {
+        exclusion_mode: mode,
+        non_interactive: true,
+        ..config
+    };
+    let (ctx, mock) = make_ctx_with_mock(root);
+    cmd_run(&ctx, &config, &[], dry_run).unwrap();
+    mock
+}
 
-    // Second run with fixed-path (non-interactive) — should skip gracefully
-    let config_fixed = Config {
-        search_paths: vec![tmp.path().to_string_lossy().to_string()],
-        ignored_paths: vec![],
-        whitelist: vec![],
-        exclusion_mode: ExclusionMode::FixedPath,
+#[test]
+fn test_run_mode_switch_migrates_exclusions_in_place() {
+    let tmp = tempdir().unwrap();
+    let repo = make_repo(tmp.path(), "repo-mode");
+    let target = repo.join("target");
+
+    let mock = run_then_switch(tmp.path(), ExclusionMode::FixedPath, false);
+
+    // Each cached path is first added in the new mode, then removed in the
+    // old one, with no prompt even though nobody could answer it
+    let calls = mock.calls();
+    let position = |op: MockOp, fixed_path: bool| {
+        calls
+            .iter()
+            .position(|c| c.op == op && c.fixed_path == fixed_path && c.paths.contains(&target))
     };
-    {
-        let (ctx, mock) = make_ctx_with_mock(tmp.path());
-        cmd_run(&ctx, &config_fixed, &[], false).unwrap();
+    let added = position(MockOp::Add, true).expect("added with -p");
+    let removed = position(MockOp::Remove, false).expect("removed without -p");
+    assert!(added < removed);
 
-        // Run was skipped — exclusion manager must not have been called
-        assert!(mock.added_paths().is_empty());
-        assert!(mock.removed_paths().is_empty());
-    }
+    let cache = load_cache(&tmp.path().join("cache.json")).unwrap();
+    assert_eq!(cache.exclusion_mode, ExclusionMode::FixedPath);
+    assert!(cache.paths.contains(&target));
 }
 
 #[test]
-fn test_run_mode_switch_dry_run_returns_early() {
+fn test_run_mode_switch_failed_removal_keeps_old_mode_for_a_retry() {
     let tmp = tempdir().unwrap();
-    make_repo(tmp.path(), "repo-mode-dry");
-    let config_sticky = default_config_for_test(tmp.path());
-
-    // First run with sticky — populates cache
-    {
-        let (ctx, _mock) = make_ctx_with_mock(tmp.path());
-        cmd_run(&ctx, &config_sticky, &[], false).unwrap();
-    }
+    make_repo(tmp.path(), "repo-mode-retry");
+    let config = default_config_for_test(tmp.path());
+    cmd_run(
+        &make_ctx(tmp.path(), MockExclusionManager::new()),
+        &config,
+        &[],
+        false,
+    )
+    .unwrap();
 
-    // Dry-run with fixed-path — should return early without doing work
-    let config_fixed = Config {
-        search_paths: vec![tmp.path().to_string_lossy().to_string()],
-        ignored_paths: vec![],
-        whitelist: vec![],
+    // Call 1 adds in the new mode, call 2 removes in the old one
+    let mock = MockExclusionManager::new().fail_call(2, MockFailure::Error("denied".into()));
+    let config = Config {
         exclusion_mode: ExclusionMode::FixedPath,
+        ..config
     };
-    {
-        let (ctx, mock) = make_ctx_with_mock(tmp.path());
-        cmd_run(&ctx, &config_fixed, &[], true).unwrap();
+    assert!(cmd_run(&make_ctx(tmp.path(), mock), &config, &[], false).is_err());
 
-        assert!(mock.added_paths().is_empty());
-        assert!(mock.removed_paths().is_empty());
-    }
+    let cache = load_cache(&tmp.path().join("cache.json")).unwrap();
+    assert_eq!(cache.exclusion_mode, ExclusionMode::Sticky);
+    assert!(!cache.paths.is_empty());
 }
 
 #[test]
-fn test_run_mode_switch_with_empty_cache_proceeds() {
-    // When cache is empty, mode switch should NOT block the run
+fn test_run_mode_switch_dry_run_changes_nothing() {
     let tmp = tempdir().unwrap();
-    make_repo(tmp.path(), "repo-mode-empty");
+    make_repo(tmp.path(), "repo-mode-dry");
 
-    let config_fixed = Config {
-        search_paths: vec![tmp.path().to_string_lossy().to_string()],
-        ignored_paths: vec![],
-        whitelist: vec![],
-        exclusion_mode: ExclusionMode::FixedPath,
-    };
-    let (ctx, mock) = make_ctx_with_mock(tmp.path());
-    cmd_run(&ctx, &config_fixed, &[], false).unwrap();
+    let mock = run_then_switch(tmp.path(), ExclusionMode::FixedPath, true);
 
-    // Should have processed normally — exclusions were added
-    assert!(!mock.added_paths().is_empty());
+    assert!(mock.added_paths().is_empty());
+    assert!(mock.removed_paths().is_empty());
+    let cache = load_cache(&tmp.path().join("cache.json")).unwrap();
+    assert_eq!(cache.exclusion_mode, ExclusionMode::Sticky);
 }
 
 #[test]
-fn test_run_mode_switch_preserves_old_cache_on_skip() {
+fn test_run_mode_rules_pick_the_mode_per_path_and_migrate_on_change() {
     let tmp = tempdir().unwrap();
-    make_repo(tmp.path(), "repo-mode-preserve");
-    let config_sticky = default_config_for_test(tmp.path());
+    let repo = make_repo(tmp.path(), "repo-mixed");
+    let (target, node_modules) = (repo.join("target"), repo.join("node_modules"));
+    let mixed = Config {
+        mode_rules: vec![ModeRule {
+            glob: "**/node_modules".to_string(),
+            mode: ExclusionMode::FixedPath,
+        }],
+        ..default_config_for_test(tmp.path())
+    };
 
-    // First run with sticky — populates cache
-    {
-        let (ctx, _mock) = make_ctx_with_mock(tmp.path());
-        cmd_run(&ctx, &config_sticky, &[], false).unwrap();
-    }
+    // One add call per mode, and the cache records the exception
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+    cmd_run(&ctx, &mixed, &[], false).unwrap();
+    let adds: Vec<(bool, Vec<PathBuf>)> = mock
+        .calls()
+        .into_iter()
+        .filter(|c| c.op == MockOp::Add)
+        .map(|c| (c.fixed_path, c.paths))
+        .collect();
+    assert_eq!(
+        adds,
+        vec![
+            (false, vec![target.clone()]),
+            (true, vec![node_modules.clone()])
+        ]
+    );
+    let cache = load_cache(&ctx.cache_path).unwrap();
+    assert_eq!(cache.exclusion_mode, ExclusionMode::Sticky);
+    assert_eq!(cache.mode_for(&node_modules), ExclusionMode::FixedPath);
+    assert_eq!(cache.mode_for(&target), ExclusionMode::Sticky);
+
+    // Dropping the rule moves only node_modules back to sticky
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+    let sticky = Config {
+        reassert: false,
+        ..default_config_for_test(tmp.path())
+    };
+    cmd_run(&ctx, &sticky, &[], false).unwrap();
+    let changes: Vec<(MockOp, bool, Vec<PathBuf>)> = mock
+        .calls()
+        .into_iter()
+        .filter(|c| !c.paths.is_empty() && matches!(c.op, MockOp::Add | MockOp::Remove))
+        .map(|c| (c.op, c.fixed_path, c.paths))
+        .collect();
+    assert_eq!(
+        changes,
+        vec![
+            (MockOp::Add, false, vec![node_modules.clone()]),
+            (MockOp::Remove, true, vec![node_modules.clone()]),
+        ]
+    );
+    let cache = load_cache(&ctx.cache_path).unwrap();
+    assert!(cache.meta.is_empty());
+
+    // Reset removes each path in the mode it was added in
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+    cmd_run(&ctx, &mixed, &[], false).unwrap();
+    let reset = Config {
+        assume_yes: true,
+        ..mixed
+    };
+    cmd_reset(&ctx, &reset, false).unwrap();
+    let removes: Vec<(bool, Vec<PathBuf>)> = mock
+        .calls()
+        .into_iter()
+        .filter(|c| c.op == MockOp::Remove && !c.paths.is_empty())
+        .map(|c| (c.fixed_path, c.paths))
+        .collect();
+    assert_eq!(removes.last().unwrap(), &(true, vec![node_modules.clone()]));
+    assert!(removes.contains(&(false, vec![target.clone()])));
+}
 
-    // Verify cache was written with sticky mode
-    let cache_before = load_cache(&tmp.path().join("cache.json")).unwrap();
-    assert_eq!(cache_before.exclusion_mode, ExclusionMode::Sticky);
-    assert!(!cache_before.paths.is_empty());
+#[test]
+fn test_run_mode_switch_with_empty_cache_proceeds() {
+    // When cache is empty, mode switch should NOT block the run
+    let tmp = tempdir().unwrap();
+    make_repo(tmp.path(), "repo-mode-empty");
 
-    // Second run with fixed-path (non-interactive) — should skip
     let config_fixed = Config {
         search_paths: vec![tmp.path().to_string_lossy().to_string()],
         ignored_paths: vec![],
         whitelist: vec![],
         exclusion_mode: ExclusionMode::FixedPath,
+        ..Config::default()
     };
-    {
-        let (ctx, _mock) = make_ctx_with_mock(tmp.path());
-        cmd_run(&ctx, &config_fixed, &[], false).unwrap();
-    }
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+    cmd_run(&ctx, &config_fixed, &[], false).unwrap();
 
-    // Cache must be unchanged — still sticky, same paths
-    let cache_after = load_cache(&tmp.path().join("cache.json")).unwrap();
-    assert_eq!(cache_after.exclusion_mode, ExclusionMode::Sticky);
-    assert_eq!(cache_after.paths.len(), cache_before.paths.len());
+    // Should have processed normally — exclusions were added
+    assert!(!mock.added_paths().is_empty());
 }
 
 // ── run: edge cases ─────────────────────────────────────────────────────
@@ -446,6 +1629,7 @@ fn test_run_respects_config_whitelist() {
         ignored_paths: vec![],
         whitelist: vec!["**/.env".to_string()],
         exclusion_mode: ExclusionMode::Sticky,
+        ..Config::default()
     };
 
     cmd_run(&ctx, &config, &[], false).unwrap();
@@ -457,6 +1641,220 @@ fn test_run_respects_config_whitelist() {
     assert!(added.contains(&repo.join("target")));
 }
 
+#[test]
+fn test_run_excludes_bare_repo_objects_when_enabled() {
+    let tmp = tempdir().unwrap();
+    let bare = tmp.path().join("mirror.git");
+    fs::create_dir_all(bare.join("objects/pack")).unwrap();
+    fs::create_dir_all(bare.join("refs/heads")).unwrap();
+    fs::write(bare.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+    let config = Config {
+        detect_bare_repos: true,
+        ..default_config_for_test(tmp.path())
+    };
+
+    cmd_run(&ctx, &config, &[], false).unwrap();
+
+    assert_eq!(mock.added_paths(), vec![bare.join("objects")]);
+}
+
+#[test]
+fn test_run_descends_into_excluded_dirs_around_nested_repos() {
+    let tmp = tempdir().unwrap();
+    let app = make_repo(tmp.path(), "app");
+    fs::write(app.join(".gitignore"), "vendor/\n").unwrap();
+    let lib = make_repo(&app.join("vendor"), "lib");
+    fs::create_dir_all(app.join("vendor/other")).unwrap();
+    fs::write(app.join("vendor/README"), "vendored").unwrap();
+
+    // vendor/ holds a repo, so the rails keep it — and all of it — backed up
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+    let config = default_config_for_test(tmp.path());
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    let mut added = mock.added_paths();
+    added.sort();
+    assert_eq!(added, vec![lib.join("node_modules"), lib.join("target")]);
+
+    for streaming_apply in [false, true] {
+        let tmp_ctx = tempdir().unwrap();
+        let (ctx, mock) = make_ctx_with_mock(tmp_ctx.path());
+        let config = Config {
+            descend_into_excluded: true,
+            streaming_apply,
+            ..default_config_for_test(tmp.path())
+        };
+        cmd_run(&ctx, &config, &[], false).unwrap();
+        let mut added = mock.added_paths();
+        added.sort();
+        assert_eq!(
+            added,
+            vec![
+                app.join("vendor/README"),
+                lib.join("node_modules"),
+                lib.join("target"),
+                app.join("vendor/other"),
+            ]
+        );
+    }
+}
+
+#[test]
+fn test_run_leaves_repos_inside_excluded_paths_unresolved() {
+    let tmp = tempdir().unwrap();
+    let code = tmp.path().join("code");
+    let app = make_repo(&code, "app");
+    fs::write(app.join(".gitignore"), "vendor/\n").unwrap();
+    let lib = make_repo(&app.join("vendor"), "lib");
+    // A checkout inside a system artifact location is never resolved, and
+    // no longer keeps the location in backups
+    let artifacts = code.join("tools/cache");
+    let checkout = make_repo(&artifacts, "checkout");
+
+    for skip_covered_repos in [false, true] {
+        for (streaming_apply, pipeline_discovery) in [(false, false), (false, true), (true, false)]
+        {
+            let state = tempdir().unwrap();
+            let report_path = state.path().join("last-run.json");
+            let (ctx, mock) = make_ctx_with_mock(state.path());
+            let config = Config {
+                exclude_system_artifacts: true,
+                system_artifacts: vec![artifacts.to_string_lossy().into_owned()],
+                skip_covered_repos,
+                streaming_apply,
+                pipeline_discovery,
+                report_path: Some(report_path.to_string_lossy().into_owned()),
+                ..default_config_for_test(&code)
+            };
+            cmd_run(&ctx, &config, &[], false).unwrap();
+            let mut added = mock.added_paths();
+            added.sort();
+            let report: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(&report_path).unwrap()).unwrap();
+            let mut covered: Vec<PathBuf> =
+                serde_json::from_value(report["covered_repos"].clone()).unwrap();
+            covered.sort();
+
+            let case = format!(
+                "skip {skip_covered_repos}, streaming {streaming_apply}, \
+                 pipeline {pipeline_discovery}"
+            );
+            if skip_covered_repos {
+                // vendor/ goes whole; lib's own rules are never read
+                assert_eq!(added, [app.join("vendor"), artifacts.clone()], "{case}");
+                assert_eq!(covered, [lib.clone(), checkout.clone()], "{case}");
+                assert_eq!(report["repos"], 1, "{case}");
+            } else {
+                assert_eq!(
+                    added,
+                    [
+                        lib.join("node_modules"),
+                        lib.join("target"),
+                        artifacts.clone()
+                    ],
+                    "{case}"
+                );
+                assert_eq!(covered, std::slice::from_ref(&checkout), "{case}");
+                assert_eq!(report["repos"], 2, "{case}");
+            }
+        }
+    }
+}
+
+#[test]
+fn test_keep_marker_keeps_a_repo_and_removes_its_exclusions() {
+    let tmp = tempdir().unwrap();
+    let code = tmp.path().join("code");
+    let app = make_repo(&code, "app");
+    let research = make_repo(&code, "research");
+    fs::write(app.join(".gitignore"), "vendor/\n").unwrap();
+    let dataset = make_repo(&app.join("vendor"), "dataset");
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+    let config = Config {
+        skip_covered_repos: true,
+        ..default_config_for_test(&code)
+    };
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    assert!(mock.added_paths().contains(&research.join("target")));
+    assert!(mock.added_paths().contains(&app.join("vendor")));
+
+    // Exclusions made before the marker appeared are undone; a marked repo
+    // is never covered, so the rails keep the directory holding it too
+    fs::write(research.join(".letitgo-keep"), "").unwrap();
+    fs::write(dataset.join(".nolignore"), "").unwrap();
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    let mut removed = mock.removed_paths();
+    removed.sort();
+    assert_eq!(
+        removed,
+        [
+            app.join("vendor"),
+            research.join("node_modules"),
+            research.join("target")
+        ]
+    );
+    assert!(load_cache(&ctx.cache_path).unwrap().paths.is_empty());
+}
+
+// ── repos ───────────────────────────────────────────────────────────────
+
+#[test]
+fn test_repos_lists_resolved_repos_and_explains_missing_ones() {
+    for streaming_apply in [false, true] {
+        let tmp = tempdir().unwrap();
+        let code = tmp.path().join("code");
+        let app = make_repo(&code, "app");
+        let broken = code.join("broken");
+        fs::create_dir_all(&broken).unwrap();
+        fs::write(broken.join(".git"), "not a gitdir file\n").unwrap();
+        let elsewhere = make_repo(tmp.path(), "elsewhere");
+        let ctx = make_ctx(tmp.path(), MockExclusionManager::new());
+        let config = Config {
+            streaming_apply,
+            ..default_config_for_test(&code)
+        };
+        cmd_run(&ctx, &config, &[], false).unwrap();
+
+        let cache = load_cache(&ctx.cache_path).unwrap();
+        let entries = repos::entries(&cache);
+        assert_eq!(entries.len(), 2, "streaming_apply = {streaming_apply}");
+        assert_eq!(entries[0].path, app);
+        assert_eq!(entries[0].kind, RepoKind::Main);
+        assert_eq!(entries[0].exclusions, 2);
+        assert!(entries[0].last_resolved.is_some());
+        assert_eq!(entries[1].path, broken);
+        assert_eq!(entries[1].kind, RepoKind::Missing);
+        cmd_repos(&ctx, &config, None, false).unwrap();
+        cmd_repos(&ctx, &config, None, true).unwrap();
+
+        let lookup = |path: &Path| repos::lookup(path, &config, &cache);
+        assert_eq!(lookup(&app.join("src")).map(|e| e.path), Ok(app.clone()));
+        assert_eq!(
+            lookup(&elsewhere),
+            Err(NotFound::OutsideSearchPaths {
+                repo: elsewhere.clone()
+            })
+        );
+        let new = make_repo(&code, "new");
+        assert!(matches!(
+            lookup(&new),
+            Err(NotFound::NotYetDiscovered { repo, discovered_at: Some(_) }) if repo == new
+        ));
+        cmd_repos(&ctx, &config, Some(&new), false).unwrap();
+
+        // The times of repos that are gone are forgotten
+        fs::remove_dir_all(&app).unwrap();
+        cmd_run(&ctx, &config, &[], false).unwrap();
+        let known = load_cache(&ctx.cache_path).unwrap().known_repos.unwrap();
+        assert_eq!(
+            known.resolved_at.keys().collect::<Vec<_>>(),
+            [&broken, &new],
+            "streaming_apply = {streaming_apply}"
+        );
+    }
+}
+
 // ── list ────────────────────────────────────────────────────────────────
 
 #[test]
@@ -464,9 +1862,9 @@ fn test_list_empty_cache_no_error() {
     let tmp = tempdir().unwrap();
     let ctx = make_ctx(tmp.path(), MockExclusionManager::new());
     // All variants must succeed on a missing/empty cache
-    cmd_list(&ctx, false, false).unwrap();
-    cmd_list(&ctx, true, false).unwrap(); // --json
-    cmd_list(&ctx, false, true).unwrap(); // --stale
+    cmd_list(&ctx, false, false, false, ListFormat::Plain, None, false).unwrap();
+    cmd_list(&ctx, true, false, false, ListFormat::Plain, None, false).unwrap(); // --json
+    cmd_list(&ctx, false, true, false, ListFormat::Plain, None, false).unwrap(); // --stale
 }
 
 #[test]
@@ -483,9 +1881,111 @@ fn test_list_with_cached_paths_no_error() {
 
     let ctx = make_ctx(tmp.path(), MockExclusionManager::new());
 
-    cmd_list(&ctx, false, false).unwrap();
-    cmd_list(&ctx, true, false).unwrap(); // --json
-    cmd_list(&ctx, false, true).unwrap(); // --stale: should list the nonexistent path
+    cmd_list(&ctx, false, false, false, ListFormat::Plain, None, false).unwrap();
+    cmd_list(&ctx, true, false, false, ListFormat::Plain, None, false).unwrap(); // --json
+    cmd_list(&ctx, false, true, false, ListFormat::Plain, None, false).unwrap(); // --stale: should list the nonexistent path
+}
+
+#[test]
+fn test_run_records_when_each_path_was_added() {
+    let tmp = tempdir().unwrap();
+    for streaming_apply in [false, true] {
+        let root = tmp.path().join(format!("code-{streaming_apply}"));
+        let repo = make_repo(&root, "repo-a");
+        let ctx = make_ctx(&root, MockExclusionManager::new());
+        let config = Config {
+            streaming_apply,
+            ..default_config_for_test(&root)
+        };
+        cmd_run(&ctx, &config, &[], false).unwrap();
+        let first = load_cache(&ctx.cache_path).unwrap().added;
+        assert_eq!(
+            first.keys().collect::<Vec<_>>(),
+            [&repo.join("node_modules"), &repo.join("target")]
+        );
+
+        // Paths already cached keep their date; new ones get the run's
+        fs::create_dir_all(repo.join("dist")).unwrap();
+        fs::write(repo.join(".gitignore"), "target/\ndist/\n").unwrap();
+        cmd_run(&ctx, &config, &[], false).unwrap();
+        let cache = load_cache(&ctx.cache_path).unwrap();
+        let target = repo.join("target");
+        assert_eq!(cache.added[&target], first[&target]);
+        assert!(cache.added[&repo.join("dist")] >= first[&target]);
+        assert!(!cache.added.contains_key(&repo.join("node_modules")));
+
+        cmd_list(
+            &ctx,
+            false,
+            false,
+            false,
+            ListFormat::Table,
+            Some(ListSort::Added),
+            false,
+        )
+        .unwrap();
+    }
+}
+
+#[test]
+fn test_run_report_and_cache_describe_each_added_path() {
+    let tmp = tempdir().unwrap();
+    for streaming_apply in [false, true] {
+        let root = tmp.path().join(format!("code-{streaming_apply}"));
+        let repo = make_repo(&root, "repo-a");
+        fs::create_dir_all(repo.join("dist")).unwrap();
+        fs::write(repo.join(".lignore"), "dist/\n").unwrap();
+        let report_path = root.join("report.json");
+        let ctx = make_ctx(&root, MockExclusionManager::new());
+        let config = Config {
+            streaming_apply,
+            report_path: Some(report_path.to_string_lossy().into_owned()),
+            ..default_config_for_test(&root)
+        };
+        cmd_run(&ctx, &config, &[], false).unwrap();
+
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&report_path).unwrap()).unwrap();
+        let added = report["added_paths"].as_array().unwrap();
+        assert_eq!(added.len(), 3, "{report:#}");
+        assert_eq!(added[0]["path"], repo.join("dist").to_str().unwrap());
+        assert_eq!(added[0]["origin"], "repo");
+        assert_eq!(added[0]["repo"], repo.to_str().unwrap());
+        assert_eq!(
+            added[0]["rule"],
+            serde_json::json!({"kind": "lignore", "pattern": "dist/"})
+        );
+        assert!(added[0]["first_seen"].is_string());
+
+        // The cache keeps the rules for `list --json`
+        let cache = load_cache(&ctx.cache_path).unwrap();
+        let entry = PathEntry::new(&cache, &repo.join("target"));
+        assert_eq!(entry.repo.as_deref(), Some(repo.as_path()));
+        assert_eq!(entry.rule.unwrap().pattern, "target/");
+        assert_eq!(
+            entry.first_seen,
+            cache.added.get(&repo.join("target")).copied()
+        );
+    }
+}
+
+#[test]
+fn test_query_filters_cached_paths() {
+    let tmp = tempdir().unwrap();
+    let mut cache = Cache::empty();
+    cache.paths = vec![
+        tmp.path().join("app/target"),
+        PathBuf::from("/nonexistent/definitely/gone/path"),
+    ];
+    fs::create_dir_all(tmp.path().join("app/target")).unwrap();
+    write_cache(&tmp.path().join("cache.json"), &cache).unwrap();
+
+    let ctx = make_ctx(tmp.path(), MockExclusionManager::new());
+    let config = default_config_for_test(tmp.path());
+    cmd_query(&ctx, &config, r#"path ~ "app/*" and size < 1GB"#, false).unwrap();
+    cmd_query(&ctx, &config, "stale or failures > 2", true).unwrap(); // --json
+    let err = cmd_query(&ctx, &config, "size > big", false).unwrap_err();
+    assert!(err.to_string().contains("invalid size `big`"), "{err}");
 }
 
 // ── reset ───────────────────────────────────────────────────────────────
@@ -496,7 +1996,35 @@ fn test_reset_empty_cache_no_error() {
     let ctx = make_ctx(tmp.path(), MockExclusionManager::new());
     let config = Config::default();
     // Should print "Nothing to reset" and return Ok — not crash
-    cmd_reset(&ctx, &config, true, false).unwrap();
+    cmd_reset(&ctx, &config, false).unwrap();
+}
+
+#[test]
+fn test_reset_non_interactive_keeps_exclusions() {
+    let tmp = tempdir().unwrap();
+    let mut cache = Cache::empty();
+    cache.paths = vec![tmp.path().join("some-path")];
+    write_cache(&tmp.path().join("cache.json"), &cache).unwrap();
+
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+    let config = Config {
+        non_interactive: true,
+        ..Config::default()
+    };
+
+    // Nobody to confirm: answered no without reading stdin
+    cmd_reset(&ctx, &config, false).unwrap();
+    assert!(ctx.cache_path.exists());
+    assert!(mock.removed_paths().is_empty());
+
+    // `--yes` wins over `--non-interactive`
+    let config = Config {
+        assume_yes: true,
+        ..config
+    };
+    cmd_reset(&ctx, &config, false).unwrap();
+    assert!(!ctx.cache_path.exists());
+    assert_eq!(mock.removed_paths(), cache.paths);
 }
 
 #[test]
@@ -507,9 +2035,12 @@ fn test_reset_dry_run_keeps_cache() {
     write_cache(&tmp.path().join("cache.json"), &cache).unwrap();
 
     let ctx = make_ctx(tmp.path(), MockExclusionManager::new());
-    let config = Config::default();
+    let config = Config {
+        assume_yes: true,
+        ..Config::default()
+    };
 
-    cmd_reset(&ctx, &config, true, true).unwrap(); // yes + dry_run
+    cmd_reset(&ctx, &config, true).unwrap(); // dry_run
 
     assert!(
         ctx.cache_path.exists(),
@@ -525,9 +2056,12 @@ fn test_reset_dry_run_does_not_call_exclusion_manager() {
     write_cache(&tmp.path().join("cache.json"), &cache).unwrap();
 
     let (ctx, mock) = make_ctx_with_mock(tmp.path());
-    let config = Config::default();
+    let config = Config {
+        assume_yes: true,
+        ..Config::default()
+    };
 
-    cmd_reset(&ctx, &config, true, true).unwrap();
+    cmd_reset(&ctx, &config, true).unwrap();
 
     assert!(
         mock.removed_paths().is_empty(),
@@ -549,7 +2083,7 @@ fn test_clean_dry_run_does_not_modify_cache() {
     let removed = clean::clean_stale(
         &ctx.cache_path,
         ctx.exclusion_manager.as_ref(),
-        false,
+        0,
         true, // dry_run
     )
     .unwrap();
@@ -561,6 +2095,114 @@ fn test_clean_dry_run_does_not_modify_cache() {
     assert_eq!(updated.paths.len(), 1);
 }
 
+#[test]
+fn test_clean_keeps_paths_on_unmounted_volumes_unless_asked() {
+    let tmp = tempdir().unwrap();
+    let unplugged = PathBuf::from("/Volumes/letitgo-test-unplugged/app/target");
+    let mut cache = Cache::empty();
+    cache.paths = vec![PathBuf::from("/nonexistent/app/target"), unplugged.clone()];
+    write_cache(&tmp.path().join("cache.json"), &cache).unwrap();
+    let ctx = make_ctx(tmp.path(), MockExclusionManager::new());
+
+    let removed =
+        clean::clean_stale(&ctx.cache_path, ctx.exclusion_manager.as_ref(), 0, false).unwrap();
+    assert_eq!(removed, 1);
+    assert_eq!(load_cache(&ctx.cache_path).unwrap().paths, vec![unplugged]);
+
+    let config = Config {
+        cache_snapshots: 0,
+        ..Config::default()
+    };
+    cmd_clean(&ctx, &config, false, true, false).unwrap();
+    assert!(load_cache(&ctx.cache_path).unwrap().paths.is_empty());
+}
+
+#[test]
+fn test_clean_removes_or_keeps_paths_moved_to_the_trash() {
+    let tmp = tempdir().unwrap();
+    let trashed = tmp.path().join(".Trash/app/target");
+    fs::create_dir_all(&trashed).unwrap();
+    let mut cache = Cache::empty();
+    cache.paths = vec![trashed.clone()];
+    write_cache(&tmp.path().join("cache.json"), &cache).unwrap();
+    let ctx = make_ctx(tmp.path(), MockExclusionManager::new());
+
+    let config = Config {
+        trashed_paths: TrashedPaths::Keep,
+        cache_snapshots: 0,
+        ..Config::default()
+    };
+    cmd_clean(&ctx, &config, false, false, false).unwrap();
+    assert_eq!(load_cache(&ctx.cache_path).unwrap().paths, vec![trashed]);
+
+    let config = Config {
+        trashed_paths: TrashedPaths::Remove,
+        ..config
+    };
+    cmd_clean(&ctx, &config, false, false, false).unwrap();
+    assert!(load_cache(&ctx.cache_path).unwrap().paths.is_empty());
+}
+
+#[test]
+fn test_interactive_clean_keeps_the_groups_not_confirmed() {
+    let tmp = tempdir().unwrap();
+    let mut cache = Cache::empty();
+    cache.paths = vec![
+        PathBuf::from("/nonexistent/app/target"),
+        PathBuf::from("/nonexistent/api/node_modules"),
+        tmp.path().to_path_buf(),
+    ];
+    write_cache(&tmp.path().join("cache.json"), &cache).unwrap();
+    let ctx = make_ctx(tmp.path(), MockExclusionManager::new());
+
+    // No terminal to ask on: every group is kept
+    let config = Config {
+        non_interactive: true,
+        cache_snapshots: 0,
+        ..Config::default()
+    };
+    cmd_clean(&ctx, &config, true, false, false).unwrap();
+    assert_eq!(load_cache(&ctx.cache_path).unwrap().paths.len(), 3);
+
+    let config = Config {
+        assume_yes: true,
+        ..config
+    };
+    cmd_clean(&ctx, &config, true, false, false).unwrap();
+    assert_eq!(
+        load_cache(&ctx.cache_path).unwrap().paths,
+        vec![tmp.path().to_path_buf()]
+    );
+}
+
+// ── simulate ────────────────────────────────────────────────────────────
+
+#[test]
+fn test_simulate_changes_neither_the_cache_nor_exclusions() {
+    let tmp = tempdir().unwrap();
+    make_repo(tmp.path(), "repo-sim");
+    let config = default_config_for_test(tmp.path());
+    let (ctx, mock) = make_ctx_with_mock(tmp.path());
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    let (cache_before, calls_before) = (load_cache(&ctx.cache_path).unwrap(), mock.calls().len());
+
+    fs::write(
+        &ctx.config_path,
+        format!("search_paths = [{:?}]\n", tmp.path()),
+    )
+    .unwrap();
+    let overrides = [r#"whitelist=["**/node_modules"]"#.to_string()];
+    cmd_simulate(&ctx, &config, None, &overrides, true).unwrap();
+    assert_eq!(
+        load_cache(&ctx.cache_path).unwrap().paths,
+        cache_before.paths
+    );
+    assert_eq!(mock.calls().len(), calls_before);
+
+    let err = cmd_simulate(&ctx, &config, None, &["whitelst=[]".to_string()], true).unwrap_err();
+    assert!(err.to_string().contains("unknown setting"), "{err:#}");
+}
+
 // ── lockfile concurrency ────────────────────────────────────────────────
 
 #[test]
@@ -573,6 +2215,7 @@ fn test_concurrent_run_skips_when_locked() {
     let lock_path = tmp.path().join("letitgo.lock");
     let lock_file = fs::OpenOptions::new()
         .create(true)
+        .truncate(false)
         .write(true)
         .open(&lock_path)
         .unwrap();
@@ -593,5 +2236,413 @@ fn test_concurrent_run_skips_when_locked() {
         "add_exclusions must not be called when skipped"
     );
 
+    // With no_lock the held lock is ignored
+    let no_lock = Config {
+        no_lock: true,
+        ..config
+    };
+    cmd_run(&ctx, &no_lock, &[], false).unwrap();
+    assert!(ctx.cache_path.exists());
+    assert_eq!(mock.added_paths().len(), 2);
+
     drop(_guard); // release lock explicitly
 }
+
+#[test]
+fn test_locked_run_queues_one_follow_up_with_running_service() {
+    let tmp = tempdir().unwrap();
+    make_repo(tmp.path(), "repo-lock");
+    let config = default_config_for_test(tmp.path());
+    let (ctx, _mock) = make_ctx_with_mock(tmp.path());
+    let mut lock = InstanceLock::open(&ctx.lock_path).unwrap();
+    let _guard = lock.try_acquire(false).unwrap().unwrap();
+    let queued = RunOptions {
+        queue_if_locked: true,
+        ..RunOptions::default()
+    };
+    let trigger = service::trigger_path(&ctx.cache_path);
+
+    // Without a service there is nobody to queue with
+    cmd_run_with(&ctx, &config, &queued).unwrap();
+    assert!(!trigger.exists());
+
+    // A live service gets a request; runs turned away meanwhile share it
+    let status_path = service::status_path(&ctx.cache_path);
+    service::write_status(&status_path, &ServiceStatus::starting()).unwrap();
+    cmd_run_with(&ctx, &config, &queued).unwrap();
+    cmd_run_with(&ctx, &config, &queued).unwrap();
+    assert!(trigger.is_file());
+    service::take_trigger(&trigger);
+
+    // Dry runs and runs that did not ask to queue leave no request
+    cmd_run(&ctx, &config, &[], false).unwrap();
+    let dry = RunOptions {
+        dry_run: true,
+        ..queued
+    };
+    cmd_run_with(&ctx, &config, &dry).unwrap();
+    assert!(!trigger.exists());
+}
+
+#[test]
+fn test_socket_runs_are_recorded_in_the_service_status() {
+    let tmp = tempdir().unwrap();
+    make_repo(tmp.path(), "repo-socket");
+    let config = default_config_for_test(tmp.path());
+    let mock = MockExclusionManager::new().fail_call(1, MockFailure::Error("denied".into()));
+    let ctx = make_ctx(tmp.path(), mock);
+    let shared = service::Shared::new(&ctx.cache_path);
+    let ask =
+        |command| answer_service_command(&ctx, &config, &RunOptions::default(), &shared, command);
+    let run = service::Command::Run { dry_run: false };
+    let status = || {
+        let report = ask(service::Command::Status).unwrap();
+        let on_disk = service::read_status(&service::status_path(&ctx.cache_path))
+            .unwrap()
+            .and_then(|s| s.last_run);
+        assert_eq!(report["last_run"], serde_json::to_value(on_disk).unwrap());
+        report
+    };
+    assert!(status()["last_run"].is_null());
+
+    let err = ask(run.clone()).unwrap_err();
+    assert!(format!("{err:#}").contains("denied"), "{err:#}");
+    let failed = status();
+    assert_eq!(failed["last_run"]["ok"], false);
+    assert_eq!(failed["consecutive_failures"], 1);
+    assert_eq!(failed["healthy"], false);
+
+    let report = ask(run.clone()).unwrap();
+    assert_eq!(report["excluded"], 2);
+    let ok = status();
+    assert_eq!(ok["last_run"]["ok"], true);
+    assert_eq!(ok["consecutive_failures"], 0);
+    assert_eq!(ok["healthy"], true);
+
+    // A run turned away by another process's lock is not an outcome
+    let mut lock = InstanceLock::open(&ctx.lock_path).unwrap();
+    let _guard = lock.try_acquire(false).unwrap().unwrap();
+    ask(run).unwrap();
+    assert_eq!(status()["last_run"], ok["last_run"]);
+}
+
+/// A stand-in for `tmutil` that answers `version` (the capability probe)
+/// unlogged, logs its other arguments next to itself, reports
+/// paths ending in `target` as excluded, exits 213 (not found) for paths
+/// containing `missing`, 1 for paths containing `broken` (or `gone`, with
+/// "Invalid argument") and 16 ("Resource busy") for paths containing
+/// `busy`, and hangs on paths containing `slow`.
+const FAKE_TMUTIL: &str = r#"#!/bin/sh
+if [ "$1" = version ]; then echo "tmutil version 4.0.0 (fake)"; exit 0; fi
+echo "$*" >> "$(dirname "$0")/calls.log"
+case "$1" in
+listbackups) cat "$(dirname "$0")/backups.txt" ;;
+isexcluded)
+    case "$2" in
+    *target) echo "[Excluded]    $2" ;;
+    *) echo "[Included]    $2" ;;
+    esac ;;
+*)
+    case "$*" in
+    *missing*) echo "$*: not found" >&2; exit 213 ;;
+    *broken*) echo "$*: boom" >&2; exit 1 ;;
+    *gone*) echo "$*: Invalid argument (error 22)" >&2; exit 1 ;;
+    *busy*) echo "$*: Resource busy" >&2; exit 16 ;;
+    *slow*) exec sleep 30 ;;
+    esac ;;
+esac
+"#;
+
+/// Write [`FAKE_TMUTIL`] into `dir` and return its path.
+fn fake_tmutil(dir: &Path) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let binary = dir.join("tmutil");
+    fs::write(&binary, FAKE_TMUTIL).unwrap();
+    fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+    binary
+}
+
+/// The argument lines the fake `tmutil` in `dir` logged.
+fn fake_tmutil_calls(dir: &Path) -> Vec<String> {
+    fs::read_to_string(dir.join("calls.log"))
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn test_tmutil_manager_drives_a_fake_tmutil_binary() {
+    use letitgo::tmutil::TmutilManager;
+
+    let tmp = tempdir().unwrap();
+    let manager = TmutilManager::new(1).with_binary(fake_tmutil(tmp.path()));
+
+    let target = tmp.path().join("app/target");
+    let missing = tmp.path().join("app/missing");
+    // Exit 213 is a safe error: the call still succeeds
+    manager
+        .add_exclusions(&[target.as_path(), missing.as_path()], true)
+        .unwrap();
+    let broken = tmp.path().join("app/broken");
+    let err = manager
+        .remove_exclusions(&[broken.as_path()], true)
+        .unwrap_err();
+    assert!(format!("{err:#}").contains("exit 1"), "{err:#}");
+    assert!(manager.is_excluded(&target).unwrap());
+    assert!(!manager.is_excluded(&missing).unwrap());
+
+    assert_eq!(
+        fake_tmutil_calls(tmp.path()),
+        vec![
+            format!("addexclusion -p {}", target.display()),
+            format!("addexclusion -p {}", missing.display()),
+            format!("removeexclusion -p {}", broken.display()),
+            format!("isexcluded {}", target.display()),
+            format!("isexcluded {}", missing.display()),
+        ]
+    );
+}
+
+#[test]
+fn test_tmutil_timed_out_batch_is_retried_per_path() {
+    use letitgo::tmutil::{Batching, TmutilManager};
+    use letitgo::warnings::WarningKind;
+
+    let tmp = tempdir().unwrap();
+    let manager = TmutilManager::new(1)
+        .with_binary(fake_tmutil(tmp.path()))
+        .with_batching(Batching {
+            size: 2,
+            adaptive: true,
+            max_size: 8,
+            timeout: Duration::from_millis(500),
+            retry_timeout: Duration::from_millis(300),
+        });
+    let [a, slow, b] = ["a", "slow", "b"].map(|name| tmp.path().join(name));
+
+    manager
+        .add_exclusions(&[a.as_path(), slow.as_path(), b.as_path()], true)
+        .unwrap();
+    let arg = |p: &PathBuf| p.display().to_string();
+    assert_eq!(
+        fake_tmutil_calls(tmp.path()),
+        vec![
+            format!("addexclusion -p {} {}", arg(&a), arg(&slow)),
+            format!("addexclusion -p {}", arg(&a)),
+            format!("addexclusion -p {}", arg(&slow)),
+            format!("addexclusion -p {}", arg(&b)),
+        ]
+    );
+    let warnings = manager.take_warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].kind, WarningKind::TimedOut);
+    assert_eq!(warnings[0].path.as_deref(), Some(slow.as_path()));
+    // Halved by the timeout, doubled again by the fast last batch
+    assert_eq!(manager.batch_size(), Some(2));
+}
+
+#[test]
+fn test_tmutil_errors_are_ignored_or_retried_per_path_by_their_stderr() {
+    use letitgo::tmutil::{Batching, TmutilManager};
+    use letitgo::warnings::WarningKind;
+
+    let tmp = tempdir().unwrap();
+    let manager = TmutilManager::new(1)
+        .with_binary(fake_tmutil(tmp.path()))
+        .with_batching(Batching {
+            size: 2,
+            adaptive: false,
+            ..Batching::default()
+        });
+    let [a, gone, b, busy] = ["a", "gone", "b", "busy"].map(|name| tmp.path().join(name));
+
+    // Exit 1 with "Invalid argument" is ignorable; "Resource busy" is retried
+    // path by path and skipped with a warning when it persists
+    manager
+        .add_exclusions(
+            &[a.as_path(), gone.as_path(), b.as_path(), busy.as_path()],
+            true,
+        )
+        .unwrap();
+    let arg = |p: &PathBuf| p.display().to_string();
+    assert_eq!(
+        fake_tmutil_calls(tmp.path()),
+        vec![
+            format!("addexclusion -p {} {}", arg(&a), arg(&gone)),
+            format!("addexclusion -p {} {}", arg(&b), arg(&busy)),
+            format!("addexclusion -p {}", arg(&b)),
+            format!("addexclusion -p {}", arg(&busy)),
+        ]
+    );
+    let warnings = manager.take_warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].kind, WarningKind::PathFailed);
+    assert_eq!(warnings[0].path.as_deref(), Some(busy.as_path()));
+    assert!(warnings[0].message.contains("Resource busy"));
+}
+
+#[test]
+fn test_paths_failing_on_every_run_are_quarantined_and_listed() {
+    use letitgo::quarantine;
+    use letitgo::tmutil::TmutilManager;
+
+    let tmp = tempdir().unwrap();
+    let binary = fake_tmutil(tmp.path());
+    let code = tmp.path().join("code");
+    make_repo(&code, "app");
+    make_repo(&code, "busy");
+    let config = Config {
+        exclusion_mode: ExclusionMode::FixedPath,
+        quarantine_after: 2,
+        ..default_config_for_test(&code)
+    };
+    let run = || {
+        let ctx = AppContext {
+            exclusion_manager: Box::new(TmutilManager::new(1).with_binary(&binary)),
+            ..make_ctx(tmp.path(), MockExclusionManager::new())
+        };
+        cmd_run(&ctx, &config, &[], false).unwrap();
+        cmd_list(&ctx, true, false, true, ListFormat::Plain, None, false).unwrap(); // --quarantined --json
+        load_cache(&tmp.path().join("cache.json")).unwrap()
+    };
+    let calls = || fake_tmutil_calls(tmp.path()).len();
+
+    // The failing paths stay in the cache and are retried on the next run
+    let cache = run();
+    assert_eq!(cache.failures.len(), 2);
+    assert!(
+        cache
+            .failures
+            .keys()
+            .all(|p| p.starts_with(code.join("busy")))
+    );
+    assert_eq!(quarantine::quarantined(&cache.failures).count(), 0);
+    let first = calls();
+
+    let cache = run();
+    assert_eq!(quarantine::quarantined(&cache.failures).count(), 2);
+    assert!(cache.failures.values().all(|f| f.count == 2));
+    assert!(calls() > first);
+
+    // Quarantined: not tried again until the re-probe interval has passed
+    let before = calls();
+    let cache = run();
+    assert_eq!(calls(), before);
+    assert_eq!(cache.paths.len(), 4);
+}
+
+#[test]
+fn test_run_learns_the_tmutil_batch_size_and_resumes_from_the_cache() {
+    use letitgo::tmutil::TmutilManager;
+
+    let tmp = tempdir().unwrap();
+    let binary = fake_tmutil(tmp.path());
+    let code = tmp.path().join("code");
+    for name in ["a", "b", "c"] {
+        make_repo(&code, name);
+    }
+    let config = Config {
+        exclusion_mode: ExclusionMode::FixedPath,
+        tmutil_max_batch_size: 4,
+        ..default_config_for_test(&code)
+    };
+    let run = || {
+        let ctx = AppContext {
+            exclusion_manager: Box::new(TmutilManager::new(1).with_binary(&binary)),
+            ..make_ctx(tmp.path(), MockExclusionManager::new())
+        };
+        cmd_run(&ctx, &config, &[], false).unwrap();
+    };
+    let batch_lengths = || -> Vec<usize> {
+        fake_tmutil_calls(tmp.path())
+            .iter()
+            .map(|line| line.split(' ').count() - 2)
+            .collect()
+    };
+
+    // Six paths: one, then two (both fast), then the last three at four
+    run();
+    assert_eq!(batch_lengths(), vec![1, 2, 3]);
+    let cache = load_cache(&tmp.path().join("cache.json")).unwrap();
+    assert_eq!(cache.tmutil_batch_size, Some(4));
+
+    // The next run starts at the learned size
+    make_repo(&code, "d");
+    run();
+    assert_eq!(batch_lengths(), vec![1, 2, 3, 2]);
+}
+
+#[test]
+fn test_purge_backups_deletes_each_excluded_path_once_after_confirmation() {
+    use letitgo::tmutil::TMUTIL_ENV;
+
+    let tmp = tempdir().unwrap();
+    let bin = tmp.path().join("bin");
+    fs::create_dir_all(&bin).unwrap();
+    // SAFETY: no other test in this binary depends on which tmutil runs
+    unsafe { std::env::set_var(TMUTIL_ENV, fake_tmutil(&bin)) };
+    let code = tmp.path().join("code");
+    let backups = tmp.path().join("backups");
+    let in_backup = |name: &str| {
+        backups
+            .join(name)
+            .join("Data")
+            .join(code.strip_prefix("/").unwrap())
+    };
+    // The older backup is purged, the latest one kept; `broken` sorts
+    // first and holds the one path the fake fails to delete
+    for name in ["2026-01-01-000000", "2026-02-01-000000"] {
+        make_repo(&in_backup(name), "web");
+        let broken = make_repo(&in_backup(name), "broken");
+        fs::remove_dir_all(broken.join("node_modules")).unwrap();
+    }
+    fs::write(
+        bin.join("backups.txt"),
+        format!(
+            "{}\n{}\n",
+            backups.join("2026-01-01-000000").display(),
+            backups.join("2026-02-01-000000").display()
+        ),
+    )
+    .unwrap();
+    let deletes = || -> Vec<String> {
+        fake_tmutil_calls(&bin)
+            .into_iter()
+            .filter(|call| call.starts_with("delete "))
+            .collect()
+    };
+    let purge =
+        |config: &Config, dry_run| cmd_purge_backups(&Output::default(), config, &[], dry_run);
+
+    // A dry run and a declined prompt delete nothing
+    let declined = Config {
+        non_interactive: true,
+        ..default_config_for_test(&code)
+    };
+    purge(&declined, true).unwrap();
+    purge(&declined, false).unwrap();
+    assert!(deletes().is_empty(), "{:?}", deletes());
+
+    // A failing delete fails the command once the others are done
+    let confirmed = Config {
+        assume_yes: true,
+        ..default_config_for_test(&code)
+    };
+    let err = purge(&confirmed, false).unwrap_err();
+    assert!(
+        err.to_string().contains("1 path(s) could not be deleted"),
+        "{err}"
+    );
+    let old = in_backup("2026-01-01-000000");
+    let expected: Vec<String> = [
+        old.join("broken/target"),
+        old.join("web/node_modules"),
+        old.join("web/target"),
+    ]
+    .iter()
+    .map(|path| format!("delete -p {}", path.display()))
+    .collect();
+    assert_eq!(deletes(), expected);
+}
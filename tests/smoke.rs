@@ -8,9 +8,13 @@
 // CI runs them on macOS runners.  `--test-threads=1` prevents parallel tmutil
 // calls from racing.
 
+#![cfg(target_os = "macos")]
+
+use letitgo::access::DirectAccess;
 use letitgo::cache::load_cache;
 use letitgo::config::{Config, ExclusionMode};
-use letitgo::tmutil::{BACKUP_EXCLUDE_XATTR, BACKUP_EXCLUDE_XATTR_VALUE, TmutilManager};
+use letitgo::output::Output;
+use letitgo::tmutil::{BACKUP_EXCLUDE_XATTR, TmutilManager, backup_exclude_xattr_value};
 use letitgo::{AppContext, cmd_clean, cmd_init, cmd_reset, cmd_run};
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -32,7 +36,9 @@ fn make_real_ctx(tmp: &Path) -> AppContext {
         config_path: tmp.join("config.toml"),
         cache_path: tmp.join("cache.json"),
         lock_path: tmp.join("letitgo.lock"),
-        exclusion_manager: Box::new(TmutilManager),
+        exclusion_manager: Box::new(TmutilManager::default()),
+        fs_access: Box::new(DirectAccess),
+        output: Output::default(),
     }
 }
 
@@ -65,12 +71,14 @@ fn smoke_config(search: &Path, whitelist: &[&str]) -> Config {
         ignored_paths: vec![],
         whitelist: whitelist.iter().map(|s| s.to_string()).collect(),
         exclusion_mode: ExclusionMode::Sticky,
+        assume_yes: true,
+        ..Config::default()
     }
 }
 
 /// Run `cmd_reset` to clean up real exclusions (best-effort).
 fn cleanup(ctx: &AppContext, config: &Config) {
-    let _ = cmd_reset(ctx, config, true, false);
+    let _ = cmd_reset(ctx, config, false);
 }
 
 // ── Core smoke tests ─────────────────────────────────────────────────────
@@ -160,7 +168,7 @@ fn smoke_core_clean_noop() {
     cmd_run(&ctx, &config, &[], false).unwrap();
     let count_before = load_cache(&ctx.cache_path).unwrap().paths.len();
 
-    cmd_clean(&ctx, &config, false).unwrap();
+    cmd_clean(&ctx, &config, false, false, false).unwrap();
     let count_after = load_cache(&ctx.cache_path).unwrap().paths.len();
     assert_eq!(
         count_before, count_after,
@@ -210,7 +218,7 @@ fn smoke_core_reset_removes_xattrs() {
     cmd_run(&ctx, &config, &[], false).unwrap();
     assert!(has_xattr(&repo.join("target")), "precondition: xattr set");
 
-    cmd_reset(&ctx, &config, true, false).unwrap();
+    cmd_reset(&ctx, &config, false).unwrap();
 
     assert!(
         !has_xattr(&repo.join("target")),
@@ -438,7 +446,7 @@ fn smoke_advanced_stale_cleanup() {
     fs::remove_dir_all(repo.join("node_modules")).unwrap();
 
     // Clean should remove the stale entry
-    cmd_clean(&ctx, &config, false).unwrap();
+    cmd_clean(&ctx, &config, false, false, false).unwrap();
     let after = load_cache(&ctx.cache_path).unwrap().paths.len();
     assert!(
         after < before,
@@ -619,7 +627,7 @@ fn smoke_advanced_full_cycle() {
     );
 
     // Reset
-    cmd_reset(&ctx, &config, true, false).unwrap();
+    cmd_reset(&ctx, &config, false).unwrap();
     assert!(
         !has_xattr(&repo.join("target")),
         "cycle: reset clears xattr"
@@ -707,26 +715,20 @@ fn smoke_xattr_value_matches_tmutil() {
     assert!(status.success(), "tmutil addexclusion failed");
     let tmutil_value = read_xattr_value(&tmutil_file);
 
-    // 2) Verify our constant matches tmutil's output byte-for-byte
+    // 2) Verify our generated value matches tmutil's output byte-for-byte
+    let generated = backup_exclude_xattr_value();
     assert_eq!(
-        BACKUP_EXCLUDE_XATTR_VALUE,
-        tmutil_value.as_slice(),
-        "BACKUP_EXCLUDE_XATTR_VALUE does not match what tmutil writes!\n\
+        generated, tmutil_value,
+        "backup_exclude_xattr_value() does not match what tmutil writes!\n\
          expected (tmutil): {:02X?}\n\
-         got (our const):   {:02X?}",
-        tmutil_value,
-        BACKUP_EXCLUDE_XATTR_VALUE,
+         got (generated):   {:02X?}",
+        tmutil_value, generated,
     );
 
     // 3) Set via our direct xattr path and verify it also matches
     let direct_file = tmp.path().join("direct_file");
     fs::write(&direct_file, "").unwrap();
-    xattr::set(
-        &direct_file,
-        BACKUP_EXCLUDE_XATTR,
-        BACKUP_EXCLUDE_XATTR_VALUE,
-    )
-    .expect("xattr::set failed");
+    xattr::set(&direct_file, BACKUP_EXCLUDE_XATTR, &generated).expect("xattr::set failed");
     let direct_value = read_xattr_value(&direct_file);
 
     assert_eq!(